@@ -1,17 +1,22 @@
+use std::collections::{HashMap, VecDeque};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use serde_json::Value;
 use tokio::io::BufReader;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::process::Command;
 use tokio::sync::{mpsc, oneshot, watch, Mutex, Notify};
 use tokio::time;
+use tracing::Instrument;
 
 use crate::config::Config;
+#[cfg(unix)]
+use crate::daemon::{self, DaemonConnection, DaemonIdentity, DaemonPaths};
 use crate::error::{BridgeError, Error};
-use crate::jsonrpc::{self, Request, Response};
+use crate::jsonrpc::{self, Message, Request, Response, TraceFile};
 use crate::runtime::SidecarRuntime;
 
 /// Sidecar lifecycle states.
@@ -23,12 +28,126 @@ pub enum SidecarState {
     Stopped,
 }
 
+/// What the sidecar reported about itself in its `initialize` response.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SidecarInfo {
+    pub kotlin_version: Option<String>,
+    pub classpath_resolved: bool,
+    pub missing_classpath_entries: usize,
+    /// Request methods this sidecar build cannot serve.
+    pub unsupported_features: Vec<String>,
+}
+
+impl SidecarInfo {
+    /// Parses an `initialize` result. Fields older sidecars don't send
+    /// default to "everything is fine".
+    pub fn from_initialize_result(result: &Value) -> Self {
+        let missing_classpath_entries = result
+            .get("missingClasspathEntries")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0) as usize;
+        Self {
+            kotlin_version: result
+                .get("kotlinVersion")
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+            classpath_resolved: result
+                .get("classpathResolved")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(missing_classpath_entries == 0),
+            missing_classpath_entries,
+            unsupported_features: result
+                .get("unsupportedFeatures")
+                .and_then(|v| v.as_array())
+                .map(|features| {
+                    features
+                        .iter()
+                        .filter_map(|f| f.as_str().map(str::to_string))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn supports(&self, method: &str) -> bool {
+        !self.unsupported_features.iter().any(|f| f == method)
+    }
+
+    /// Describes a mismatch between the project's Kotlin version and the one
+    /// the sidecar actually analyzes with. Only the language version
+    /// (major.minor) counts; patch releases don't change what code is valid.
+    pub fn kotlin_version_mismatch(&self, project_version: Option<&str>) -> Option<String> {
+        let project_version = project_version?;
+        let sidecar_version = self.kotlin_version.as_deref()?;
+        let language_version = |version: &str| {
+            let mut parts = version.split(['.', '-']);
+            (
+                parts.next().map(str::to_string),
+                parts.next().map(str::to_string),
+            )
+        };
+        (language_version(project_version) != language_version(sidecar_version)).then(|| {
+            format!(
+                "kotlin-analyzer: project uses Kotlin {project_version}, but the sidecar analyzes with Kotlin {sidecar_version}. Diagnostics may be inaccurate: language features the two versions treat differently can be flagged or missed. Set suppressVersionMismatchWarning to hide this message."
+            )
+        })
+    }
+}
+
 /// A pending request awaiting a response from the sidecar.
 struct PendingRequest {
-    id: u64,
     response_tx: oneshot::Sender<Result<Value, Error>>,
+    /// When the caller stops waiting. Entries past their deadline are swept
+    /// even if the caller's timeout future was dropped (e.g. LSP cancellation).
+    deadline: Instant,
+    timeout: Duration,
+}
+
+/// Pending requests keyed by JSON-RPC id.
+type PendingMap = HashMap<u64, PendingRequest>;
+
+/// A request sent to the sidecar whose caller is still waiting. Dropped
+/// unanswered, because the caller was cancelled or timed out, it forgets the
+/// pending entry and sends the sidecar `$/cancelRequest` so it can stop the
+/// work.
+struct InFlight {
+    id: u64,
+    pending: Arc<Mutex<PendingMap>>,
+    request_tx: mpsc::Sender<Request>,
+    answered: bool,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.answered {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let id = self.id;
+        let pending = Arc::clone(&self.pending);
+        let request_tx = self.request_tx.clone();
+        runtime.spawn(async move {
+            pending.lock().await.remove(&id);
+            tracing::debug!("cancelling sidecar request {}", id);
+            let cancel =
+                Request::notification("$/cancelRequest", Some(serde_json::json!({ "id": id })));
+            let _ = request_tx.send(cancel).await;
+        });
+    }
 }
 
+type PartialResultMap = HashMap<String, mpsc::UnboundedSender<Value>>;
+
+/// Error code the sidecar answers with when a request names a document
+/// version it has not received yet (LSP's `ContentModified`).
+const CONTENT_MODIFIED: i32 = -32801;
+
+/// Error code the sidecar answers `initialize` with when it cannot load the
+/// project classpath.
+const CLASSPATH_REJECTED: i32 = -32002;
+
 /// Stored initialization parameters for restart.
 #[derive(Clone, Default)]
 struct InitParams {
@@ -36,10 +155,192 @@ struct InitParams {
     classpath: Vec<String>,
     compiler_flags: Vec<String>,
     source_roots: Vec<String>,
+    generated_source_roots: Vec<String>,
+    classpath_sources: Vec<String>,
+    kapt_classpath: Vec<String>,
+}
+
+impl InitParams {
+    /// Builds the `initialize` request params sent to the sidecar.
+    fn to_request_params(&self, config: &Config) -> Value {
+        serde_json::json!({
+            "projectRoot": self.project_root,
+            "classpath": self.classpath,
+            "compilerFlags": self.compiler_flags,
+            "jdkHome": config.java_home.clone().unwrap_or_default(),
+            "sourceRoots": self.source_roots,
+            "generatedSourceRoots": self.generated_source_roots,
+            "classpathSources": self.classpath_sources,
+            "kaptClasspath": self.kapt_classpath,
+            "excludePaths": config.exclude_paths,
+            "analysisScope": config.analysis_scope,
+        })
+    }
 }
 
 const MAX_RESTART_ATTEMPTS: u32 = 5;
 
+/// Number of recent ping round-trips kept for the rolling latency average.
+const PING_LATENCY_WINDOW: usize = 10;
+
+/// Rolling averages above this are worth a heap-size hint in the logs.
+const PING_LATENCY_WARN_MS: u64 = 1000;
+
+/// Round-trip latency of recent health-check pings.
+#[derive(Debug, Default)]
+struct PingLatency {
+    samples: VecDeque<u64>,
+}
+
+/// Snapshot of health-check ping latency, exposed for status reporting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PingStats {
+    pub last_ms: Option<u64>,
+    pub average_ms: Option<f64>,
+    pub samples: usize,
+}
+
+impl PingLatency {
+    fn record(&mut self, latency_ms: u64) {
+        if self.samples.len() == PING_LATENCY_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency_ms);
+    }
+
+    fn stats(&self) -> PingStats {
+        let average_ms = if self.samples.is_empty() {
+            None
+        } else {
+            Some(self.samples.iter().sum::<u64>() as f64 / self.samples.len() as f64)
+        };
+        PingStats {
+            last_ms: self.samples.back().copied(),
+            average_ms,
+            samples: self.samples.len(),
+        }
+    }
+
+    /// True when the window is full, the newer half averages at least twice
+    /// the older half, and the overall average is slow enough to matter.
+    fn is_trending_up(&self) -> bool {
+        if self.samples.len() < PING_LATENCY_WINDOW {
+            return false;
+        }
+        let half = self.samples.len() / 2;
+        let older: u64 = self.samples.iter().take(half).sum();
+        let newer: u64 = self.samples.iter().skip(half).sum();
+        let average = self.stats().average_ms.unwrap_or(0.0);
+        newer >= older.saturating_mul(2) && average >= PING_LATENCY_WARN_MS as f64
+    }
+}
+
+/// Interval between health-check pings while the sidecar is `Ready`.
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a single health-check ping may take before it counts as failed.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Consecutive ping failures before the sidecar is marked `Degraded`.
+const MAX_PING_FAILURES: u32 = 3;
+
+/// Interval between recovery probes while the sidecar is `Degraded`.
+const RECOVERY_PROBE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Consecutive failed recovery probes before the process is restarted.
+const MAX_RECOVERY_PROBES: u32 = 3;
+
+/// Result of a single health-check ping.
+#[derive(Debug)]
+enum PingOutcome {
+    Success(Duration),
+    /// The sidecar answered with an error or not in time; it may recover.
+    Failed(String),
+    /// The channel to the process is gone; only a restart helps.
+    Disconnected(&'static str),
+}
+
+/// What the health check should do after a ping.
+#[derive(Debug, PartialEq)]
+enum HealthAction {
+    Continue,
+    /// Mark the sidecar `Degraded` and start recovery probes.
+    Degrade,
+    /// A recovery probe succeeded; mark the sidecar `Ready` again.
+    Recover,
+    /// Give up on the running process and restart it.
+    Restart,
+}
+
+/// Tracks consecutive ping failures across the normal and recovery phases.
+/// Transient stalls (e.g. a long GC pause) degrade the sidecar without a
+/// JVM restart; only repeated recovery failures escalate to one.
+#[derive(Debug, Default)]
+struct HealthMonitor {
+    consecutive_failures: u32,
+    recovering: bool,
+}
+
+impl HealthMonitor {
+    fn on_ping(&mut self, outcome: &PingOutcome) -> HealthAction {
+        match outcome {
+            PingOutcome::Success(_) => {
+                self.consecutive_failures = 0;
+                if std::mem::take(&mut self.recovering) {
+                    HealthAction::Recover
+                } else {
+                    HealthAction::Continue
+                }
+            }
+            PingOutcome::Disconnected(reason) => {
+                tracing::warn!("health check: {}", reason);
+                HealthAction::Restart
+            }
+            PingOutcome::Failed(reason) => {
+                self.consecutive_failures += 1;
+                if self.recovering {
+                    tracing::warn!(
+                        "health check: recovery probe failed ({}/{}): {}",
+                        self.consecutive_failures,
+                        MAX_RECOVERY_PROBES,
+                        reason
+                    );
+                    if self.consecutive_failures >= MAX_RECOVERY_PROBES {
+                        return HealthAction::Restart;
+                    }
+                    return HealthAction::Continue;
+                }
+
+                tracing::warn!(
+                    "health check: ping failed ({}/{}): {}",
+                    self.consecutive_failures,
+                    MAX_PING_FAILURES,
+                    reason
+                );
+                if self.consecutive_failures >= MAX_PING_FAILURES {
+                    self.consecutive_failures = 0;
+                    self.recovering = true;
+                    return HealthAction::Degrade;
+                }
+                HealthAction::Continue
+            }
+        }
+    }
+}
+
+/// How long writing one message to the sidecar's stdin may take before the
+/// sidecar is considered wedged (no longer reading its input).
+const SIDECAR_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The sidecar's output, read for responses and notifications.
+type SidecarReader = Box<dyn AsyncRead + Unpin + Send>;
+
+/// The sidecar's input, where requests are written.
+type SidecarWriter = Box<dyn AsyncWrite + Unpin + Send>;
+
+/// How often expired entries are swept from the pending table.
+const PENDING_SWEEP_INTERVAL: Duration = Duration::from_secs(5);
+
 /// Manages the JVM sidecar process lifecycle and JSON-RPC communication.
 pub struct Bridge {
     state: Arc<Mutex<SidecarState>>,
@@ -47,7 +348,7 @@ pub struct Bridge {
     state_watch_tx: Arc<watch::Sender<SidecarState>>,
     state_watch_rx: watch::Receiver<SidecarState>,
     request_id: AtomicU64,
-    pending: Arc<Mutex<Vec<PendingRequest>>>,
+    pending: Arc<Mutex<PendingMap>>,
     request_tx: Mutex<mpsc::Sender<Request>>,
     runtime: SidecarRuntime,
     java_path: PathBuf,
@@ -59,6 +360,22 @@ pub struct Bridge {
     child: Mutex<Option<tokio::process::Child>>,
     /// Stored init params for automatic restart.
     init_params: Mutex<InitParams>,
+    /// Recent health-check ping round-trip times.
+    ping_latency: Arc<Mutex<PingLatency>>,
+    /// Capabilities reported by the sidecar's last `initialize` response.
+    sidecar_info: Mutex<Option<SidecarInfo>>,
+    /// Receivers for `$/partialResult` batches, keyed by the
+    /// `partialResultToken` sent with the request.
+    partial_results: Arc<Mutex<PartialResultMap>>,
+    next_partial_token: AtomicU64,
+    /// When a request or notification last went to the sidecar.
+    last_activity: Mutex<Instant>,
+    /// Set when the sidecar was stopped for being idle; the next request
+    /// starts it again instead of failing with `NotReady`.
+    suspended: AtomicBool,
+    /// How many times a sidecar process reached Ready. Each start is a
+    /// fresh session, so results cached under an older count are stale.
+    starts: AtomicU64,
 }
 
 impl Bridge {
@@ -77,7 +394,7 @@ impl Bridge {
             state_watch_tx: Arc::new(state_watch_tx),
             state_watch_rx,
             request_id: AtomicU64::new(1),
-            pending: Arc::new(Mutex::new(Vec::new())),
+            pending: Arc::new(Mutex::new(HashMap::new())),
             request_tx: Mutex::new(request_tx),
             runtime,
             java_path,
@@ -87,14 +404,42 @@ impl Bridge {
             health_check_shutdown: Arc::new(Notify::new()),
             child: Mutex::new(None),
             init_params: Mutex::new(InitParams::default()),
+            ping_latency: Arc::new(Mutex::new(PingLatency::default())),
+            sidecar_info: Mutex::new(None),
+            partial_results: Arc::new(Mutex::new(HashMap::new())),
+            next_partial_token: AtomicU64::new(1),
+            last_activity: Mutex::new(Instant::now()),
+            suspended: AtomicBool::new(false),
+            starts: AtomicU64::new(0),
         }
     }
 
+    /// Returns what the sidecar reported at its last successful `initialize`.
+    pub async fn sidecar_info(&self) -> Option<SidecarInfo> {
+        self.sidecar_info.lock().await.clone()
+    }
+
+    /// Returns the latest and rolling-average health-check ping latency.
+    pub async fn ping_stats(&self) -> PingStats {
+        self.ping_latency.lock().await.stats()
+    }
+
     /// Returns the current sidecar state.
     pub async fn state(&self) -> SidecarState {
         *self.state.lock().await
     }
 
+    /// The Kotlin version of the selected sidecar runtime, when known.
+    pub fn kotlin_version(&self) -> Option<&str> {
+        self.runtime.kotlin_version.as_deref()
+    }
+
+    /// How many times the sidecar has started, counting restarts after a
+    /// crash and wake-ups after an idle suspend.
+    pub fn start_count(&self) -> u64 {
+        self.starts.load(Ordering::SeqCst)
+    }
+
     /// Updates the state and notifies all watchers (request buffering).
     async fn set_state(
         state: &Mutex<SidecarState>,
@@ -107,18 +452,15 @@ impl Bridge {
     }
 
     /// Starts the health check heartbeat for the sidecar.
-    fn start_health_check(
-        bridge: Arc<Bridge>,
-        request_tx: mpsc::Sender<Request>,
-        request_id: &AtomicU64,
-    ) {
+    ///
+    /// Pings draw their ids from the bridge's shared counter so they can never
+    /// collide with ids of regular requests in the pending table.
+    fn start_health_check(bridge: Arc<Bridge>, request_tx: mpsc::Sender<Request>) {
         let state = Arc::clone(&bridge.state);
         let state_watch_tx = Arc::clone(&bridge.state_watch_tx);
-        let pending = Arc::clone(&bridge.pending);
         let shutdown = Arc::clone(&bridge.shutdown_notify);
         let health_shutdown = Arc::clone(&bridge.health_check_shutdown);
-        let request_id_val = request_id.load(Ordering::Relaxed);
-        let request_id_counter = Arc::new(AtomicU64::new(request_id_val));
+        let ping_latency = Arc::clone(&bridge.ping_latency);
 
         tokio::spawn(async move {
             // Wait 60s before first health check to allow initial analysis to complete.
@@ -126,67 +468,62 @@ impl Bridge {
             // is built for all modules.
             time::sleep(Duration::from_secs(60)).await;
 
-            let mut interval = time::interval(Duration::from_secs(30));
-            interval.tick().await; // Skip first immediate tick
-
-            let mut consecutive_failures: u32 = 0;
-            const MAX_FAILURES: u32 = 3;
+            let mut monitor = HealthMonitor::default();
             let mut should_restart = false;
 
             loop {
+                let period = if monitor.recovering {
+                    RECOVERY_PROBE_INTERVAL
+                } else {
+                    HEALTH_CHECK_INTERVAL
+                };
+
                 tokio::select! {
-                    _ = interval.tick() => {
-                        let current_state = *state.lock().await;
-                        if current_state != SidecarState::Ready {
+                    _ = time::sleep(period) => {
+                        let expected = if monitor.recovering {
+                            SidecarState::Degraded
+                        } else {
+                            SidecarState::Ready
+                        };
+                        if *state.lock().await != expected {
                             break;
                         }
 
-                        let id = request_id_counter.fetch_add(1, Ordering::Relaxed);
-                        let request = Request::new(id, "ping", None);
-                        let (response_tx, response_rx) = oneshot::channel();
-
-                        {
-                            let mut pending_requests = pending.lock().await;
-                            pending_requests.push(PendingRequest { id, response_tx });
-                        }
-
-                        if request_tx.send(request).await.is_err() {
-                            tracing::warn!("health check: request channel closed");
-                            Self::set_state(&state, &state_watch_tx, SidecarState::Degraded).await;
-                            should_restart = true;
-                            break;
+                        let outcome = Self::ping(&bridge, &request_tx).await;
+                        if let PingOutcome::Success(elapsed) = &outcome {
+                            let latency_ms = elapsed.as_millis() as u64;
+                            let mut latency = ping_latency.lock().await;
+                            latency.record(latency_ms);
+                            let stats = latency.stats();
+                            tracing::debug!(
+                                latency_ms,
+                                average_ms = stats.average_ms.unwrap_or_default(),
+                                "health check: ping successful"
+                            );
+                            if latency.is_trending_up() {
+                                tracing::warn!(
+                                    "health check: sidecar ping latency rising (last {}ms, average {:.0}ms); consider raising sidecarMaxMemory",
+                                    latency_ms,
+                                    stats.average_ms.unwrap_or_default()
+                                );
+                            }
                         }
 
-                        // Use a generous timeout - the sidecar may be busy analyzing
-                        match time::timeout(Duration::from_secs(30), response_rx).await {
-                            Ok(Ok(Ok(_))) => {
-                                tracing::debug!("health check: ping successful");
-                                consecutive_failures = 0;
+                        match monitor.on_ping(&outcome) {
+                            HealthAction::Continue => {}
+                            HealthAction::Degrade => {
+                                tracing::warn!("health check: sidecar unresponsive, probing for recovery");
+                                Self::set_state(&state, &state_watch_tx, SidecarState::Degraded).await;
                             }
-                            Ok(Ok(Err(e))) => {
-                                consecutive_failures += 1;
-                                tracing::warn!("health check: ping failed ({}/{}): {}", consecutive_failures, MAX_FAILURES, e);
-                                if consecutive_failures >= MAX_FAILURES {
-                                    Self::set_state(&state, &state_watch_tx, SidecarState::Degraded).await;
-                                    should_restart = true;
-                                    break;
-                                }
+                            HealthAction::Recover => {
+                                tracing::info!("health check: sidecar responsive again, resuming");
+                                Self::set_state(&state, &state_watch_tx, SidecarState::Ready).await;
                             }
-                            Ok(Err(_)) => {
-                                tracing::warn!("health check: response channel dropped");
+                            HealthAction::Restart => {
                                 Self::set_state(&state, &state_watch_tx, SidecarState::Degraded).await;
                                 should_restart = true;
                                 break;
                             }
-                            Err(_) => {
-                                consecutive_failures += 1;
-                                tracing::warn!("health check: ping timeout ({}/{})", consecutive_failures, MAX_FAILURES);
-                                if consecutive_failures >= MAX_FAILURES {
-                                    Self::set_state(&state, &state_watch_tx, SidecarState::Degraded).await;
-                                    should_restart = true;
-                                    break;
-                                }
-                            }
                         }
                     }
                     _ = shutdown.notified() => {
@@ -198,21 +535,93 @@ impl Bridge {
                 }
             }
 
-            // Trigger automatic restart if degraded
+            // Trigger automatic restart if recovery failed
             if should_restart {
                 Self::try_restart(bridge).await;
             }
         });
     }
 
+    /// Sends a single `ping` and classifies the result for the health check.
+    async fn ping(bridge: &Bridge, request_tx: &mpsc::Sender<Request>) -> PingOutcome {
+        let (id, response_rx) = bridge.register_pending(HEALTH_CHECK_TIMEOUT).await;
+        let request = Request::new(id, "ping", None);
+        let sent_at = Instant::now();
+
+        if request_tx.send(request).await.is_err() {
+            return PingOutcome::Disconnected("request channel closed");
+        }
+
+        // Use a generous timeout - the sidecar may be busy analyzing
+        match time::timeout(HEALTH_CHECK_TIMEOUT, response_rx).await {
+            Ok(Ok(Ok(_))) => PingOutcome::Success(sent_at.elapsed()),
+            Ok(Ok(Err(e))) => PingOutcome::Failed(e.to_string()),
+            Ok(Err(_)) => PingOutcome::Disconnected("response channel dropped"),
+            Err(_) => {
+                bridge.pending.lock().await.remove(&id);
+                PingOutcome::Failed("timeout".into())
+            }
+        }
+    }
+
+    /// Starts the periodic sweep that evicts pending requests whose deadline
+    /// has passed, so the table cannot grow when responses never arrive.
+    fn start_pending_sweep(&self) {
+        let pending = Arc::clone(&self.pending);
+        let shutdown = Arc::clone(&self.shutdown_notify);
+        let restart = Arc::clone(&self.health_check_shutdown);
+
+        tokio::spawn(async move {
+            let mut interval = time::interval(PENDING_SWEEP_INTERVAL);
+            interval.tick().await; // Skip first immediate tick
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        Self::sweep_expired_pending(&pending, Instant::now()).await;
+                    }
+                    _ = shutdown.notified() => break,
+                    _ = restart.notified() => break,
+                }
+            }
+        });
+    }
+
+    /// Removes pending requests whose deadline is before `now`, failing each
+    /// with `BridgeError::Timeout`. Returns the number of evicted entries.
+    async fn sweep_expired_pending(pending: &Mutex<PendingMap>, now: Instant) -> usize {
+        let mut reqs = pending.lock().await;
+        let expired: Vec<u64> = reqs
+            .iter()
+            .filter(|(_, req)| req.deadline <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in &expired {
+            if let Some(req) = reqs.remove(id) {
+                let _ = req.response_tx.send(Err(Error::Bridge(BridgeError::Timeout(
+                    req.timeout.as_millis() as u64,
+                ))));
+            }
+        }
+
+        if !expired.is_empty() {
+            tracing::warn!(
+                "evicted {} timed-out pending request(s), {} remaining",
+                expired.len(),
+                reqs.len()
+            );
+        }
+        expired.len()
+    }
+
     /// Cancel all pending requests with an error (used on sidecar crash).
-    async fn cancel_all_pending(pending: &Mutex<Vec<PendingRequest>>, reason: &str) {
+    async fn cancel_all_pending(pending: &Mutex<PendingMap>, reason: &str) {
         let mut reqs = pending.lock().await;
         let count = reqs.len();
         if count > 0 {
             tracing::warn!("cancelling {} pending request(s): {}", count, reason);
         }
-        for req in reqs.drain(..) {
+        for (_, req) in reqs.drain() {
             let _ = req
                 .response_tx
                 .send(Err(Error::Bridge(BridgeError::Crashed(reason.to_string()))));
@@ -223,23 +632,28 @@ impl Bridge {
     /// The optional project_root, classpath, and source_roots are forwarded
     /// to the sidecar's `initialize` request so the Analysis API session
     /// is configured with actual project data.
+    #[allow(clippy::too_many_arguments)]
     pub async fn start(
         self: &Arc<Self>,
         project_root: Option<&str>,
         classpath: &[String],
         compiler_flags: &[String],
         source_roots: &[String],
+        generated_source_roots: &[String],
+        classpath_sources: &[String],
+        kapt_classpath: &[String],
     ) -> Result<(), Error> {
         // Store init params for potential restart
-        {
-            let mut params = self.init_params.lock().await;
-            *params = InitParams {
-                project_root: project_root.unwrap_or("").to_string(),
-                classpath: classpath.to_vec(),
-                compiler_flags: compiler_flags.to_vec(),
-                source_roots: source_roots.to_vec(),
-            };
-        }
+        let init_params = InitParams {
+            project_root: project_root.unwrap_or("").to_string(),
+            classpath: classpath.to_vec(),
+            compiler_flags: compiler_flags.to_vec(),
+            source_roots: source_roots.to_vec(),
+            generated_source_roots: generated_source_roots.to_vec(),
+            classpath_sources: classpath_sources.to_vec(),
+            kapt_classpath: kapt_classpath.to_vec(),
+        };
+        *self.init_params.lock().await = init_params.clone();
 
         {
             Self::set_state(&self.state, &self.state_watch_tx, SidecarState::Starting).await;
@@ -247,103 +661,99 @@ impl Bridge {
         }
 
         let config = self.config.lock().await.clone();
-        let max_memory = &config.sidecar_max_memory;
         let runtime = self.runtime.clone();
-
-        let mut command = Command::new(&self.java_path);
-        command
-            .arg(format!("-Xmx{max_memory}"))
-            .arg("--add-opens")
-            .arg("java.base/java.lang=ALL-UNNAMED")
-            .arg("--add-opens")
-            .arg("java.base/java.lang.reflect=ALL-UNNAMED")
-            .arg("--add-opens")
-            .arg("java.base/java.util=ALL-UNNAMED");
-
-        match runtime.main_class.as_deref() {
-            Some(main_class) => {
-                let classpath = std::env::join_paths(&runtime.classpath)
-                    .map_err(|e| BridgeError::SpawnFailed(e.to_string()))?;
-                command.arg("-cp").arg(classpath).arg(main_class);
+        let command = self.sidecar_command(&config)?;
+
+        #[cfg(unix)]
+        let (sidecar_out, sidecar_in) = match self.daemon_paths().await {
+            Some(paths) => {
+                let identity = self.daemon_identity(&config);
+                let connection = match daemon::connect(&paths, &identity).await {
+                    Some(connection) => connection,
+                    None => daemon::spawn(&paths, identity, command).await?,
+                };
+                match connection {
+                    DaemonConnection::Ready(stream) => {
+                        let (read, write) = stream.into_split();
+                        (
+                            Box::new(read) as SidecarReader,
+                            Box::new(write) as SidecarWriter,
+                        )
+                    }
+                    DaemonConnection::Busy => {
+                        tracing::info!(
+                            "persistent sidecar is serving another server, starting a regular sidecar"
+                        );
+                        self.spawn_sidecar(self.sidecar_command(&config)?).await?
+                    }
+                }
             }
-            None => {
-                let sidecar_jar = runtime.classpath.first().ok_or_else(|| {
-                    BridgeError::SpawnFailed("sidecar runtime classpath is empty".into())
-                })?;
-                command.arg("-jar").arg(sidecar_jar);
+            None => self.spawn_sidecar(command).await?,
+        };
+        #[cfg(not(unix))]
+        let (sidecar_out, sidecar_in) = {
+            if config.persistent_sidecar {
+                tracing::warn!(
+                    "persistentSidecar needs Unix domain sockets; starting a regular sidecar"
+                );
             }
-        }
-
-        let mut child = command
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .kill_on_drop(true)
-            .spawn()
-            .map_err(|e| BridgeError::SpawnFailed(e.to_string()))?;
-
-        let stdin = child
-            .stdin
-            .take()
-            .ok_or_else(|| BridgeError::SpawnFailed("failed to capture stdin".into()))?;
-        let stdout = child
-            .stdout
-            .take()
-            .ok_or_else(|| BridgeError::SpawnFailed("failed to capture stdout".into()))?;
-        let stderr = child.stderr.take();
-
-        // Store the child process handle to prevent kill_on_drop from firing
-        {
-            let mut child_slot = self.child.lock().await;
-            *child_slot = Some(child);
-        }
+            self.spawn_sidecar(command).await?
+        };
 
-        // Forward sidecar stderr to our tracing output
-        if let Some(stderr) = stderr {
-            tokio::spawn(async move {
-                use tokio::io::AsyncBufReadExt;
-                let reader = BufReader::new(stderr);
-                let mut lines = reader.lines();
-                while let Ok(Some(line)) = lines.next_line().await {
-                    tracing::info!(target: "sidecar", "{}", line);
+        let trace = config.sidecar_trace_file.as_deref().and_then(|path| {
+            match TraceFile::open(Path::new(path)) {
+                Ok(trace) => {
+                    tracing::info!("tracing sidecar traffic to {}", path);
+                    Some(trace)
                 }
-            });
-        }
+                Err(e) => {
+                    tracing::warn!("cannot open sidecarTraceFile {}: {}", path, e);
+                    None
+                }
+            }
+        });
 
         // Spawn the reader task to process incoming responses
+        let reader_trace = trace.clone();
         let pending = Arc::clone(&self.pending);
+        let partial_results = Arc::clone(&self.partial_results);
         let state = Arc::clone(&self.state);
         let state_watch_tx = Arc::clone(&self.state_watch_tx);
         let shutdown = Arc::clone(&self.shutdown_notify);
         let reader_bridge = Arc::clone(self);
 
         tokio::spawn(async move {
-            let mut reader = BufReader::new(stdout);
+            let mut reader = BufReader::new(sidecar_out);
             tracing::debug!("sidecar reader task started");
             let mut should_restart = false;
             loop {
                 tokio::select! {
-                    result = jsonrpc::read_message(&mut reader) => {
+                    result = jsonrpc::read_message(&mut reader, reader_trace.as_ref()) => {
                         match result {
-                            Ok(Some(response)) => {
+                            Ok(Some(Message::Response(response))) => {
                                 tracing::debug!("Read message from sidecar");
                                 Self::dispatch_response(&pending, response).await;
                             }
+                            Ok(Some(Message::Notification(notification))) => {
+                                Self::dispatch_notification(&partial_results, notification).await;
+                            }
                             Ok(None) => {
-                                // EOF - sidecar exited. Cancel all pending requests immediately.
+                                // EOF - sidecar exited. Leave Ready before cancelling pending
+                                // requests, so a replay waits for the restart instead of being
+                                // sent to the dead sidecar.
                                 tracing::error!("sidecar stdout closed (process exited)");
-                                Self::cancel_all_pending(&pending, "sidecar process exited").await;
                                 let current = *state.lock().await;
                                 if current != SidecarState::Stopped {
                                     Self::set_state(&state, &state_watch_tx, SidecarState::Degraded).await;
                                     should_restart = true;
                                 }
+                                Self::cancel_all_pending(&pending, "sidecar process exited").await;
                                 break;
                             }
                             Err(e) => {
                                 tracing::error!("error reading sidecar response: {}", e);
-                                Self::cancel_all_pending(&pending, &format!("read error: {}", e)).await;
                                 Self::set_state(&state, &state_watch_tx, SidecarState::Degraded).await;
+                                Self::cancel_all_pending(&pending, &format!("read error: {}", e)).await;
                                 should_restart = true;
                                 break;
                             }
@@ -363,7 +773,7 @@ impl Bridge {
         });
 
         // Create a new request channel for this sidecar instance
-        let (tx, mut rx) = mpsc::channel::<Request>(32);
+        let (tx, rx) = mpsc::channel::<Request>(32);
 
         // Swap in the live sender so request()/notify()/shutdown() use it
         {
@@ -371,39 +781,25 @@ impl Bridge {
             *current_tx = tx.clone();
         }
 
-        let stdin = Arc::new(Mutex::new(stdin));
-        let stdin_clone = Arc::clone(&stdin);
-
-        tokio::spawn(async move {
-            while let Some(request) = rx.recv().await {
-                let mut writer = stdin_clone.lock().await;
-                if let Err(e) = jsonrpc::write_message(&mut writer, &request).await {
-                    tracing::error!("failed to write to sidecar: {}", e);
-                    break;
-                }
-            }
-        });
+        let (initialized_tx, initialized_rx) = oneshot::channel();
+        Self::spawn_writer(
+            Arc::clone(self),
+            sidecar_in,
+            rx,
+            initialized_rx,
+            trace,
+            SIDECAR_WRITE_TIMEOUT,
+        );
 
         // Send initialize request with project configuration
-        let init_params = serde_json::json!({
-            "projectRoot": project_root.unwrap_or(""),
-            "classpath": classpath,
-            "compilerFlags": compiler_flags,
-            "jdkHome": config.java_home.unwrap_or_default(),
-            "sourceRoots": source_roots,
-        });
+        let init_params = init_params.to_request_params(&config);
 
-        let id = self.next_id();
+        self.start_pending_sweep();
+
+        let (id, response_rx) = self.register_pending(Duration::from_secs(30)).await;
         let request = Request::new(id, "initialize", Some(init_params.clone()));
         tracing::debug!("sending initialize request to sidecar with id {}", id);
 
-        let (response_tx, response_rx) = oneshot::channel();
-        {
-            let mut pending = self.pending.lock().await;
-            pending.push(PendingRequest { id, response_tx });
-            tracing::debug!("Added pending request with id {}", id);
-        }
-
         tx.send(request)
             .await
             .map_err(|_| BridgeError::Crashed("request channel closed".into()))?;
@@ -412,6 +808,21 @@ impl Bridge {
         // Wait for initialize response with timeout
         match time::timeout(Duration::from_secs(30), response_rx).await {
             Ok(Ok(Ok(result))) => {
+                let info = SidecarInfo::from_initialize_result(&result);
+                if !info.classpath_resolved {
+                    tracing::warn!(
+                        "sidecar could not resolve {} classpath entries",
+                        info.missing_classpath_entries
+                    );
+                }
+                if !info.unsupported_features.is_empty() {
+                    tracing::info!(
+                        "sidecar reports unsupported features: {:?}",
+                        info.unsupported_features
+                    );
+                }
+                *self.sidecar_info.lock().await = Some(info);
+
                 if let Some(actual_version) = result.get("kotlinVersion").and_then(|v| v.as_str()) {
                     match runtime.kotlin_version.as_deref() {
                         Some(selected_version) if selected_version != actual_version => {
@@ -441,11 +852,13 @@ impl Bridge {
                     tracing::warn!("sidecar initialize response did not include kotlinVersion");
                 }
                 tracing::debug!("sidecar initialized successfully");
+                let _ = initialized_tx.send(());
+                self.starts.fetch_add(1, Ordering::SeqCst);
                 Self::set_state(&self.state, &self.state_watch_tx, SidecarState::Ready).await;
                 tracing::info!("sidecar ready");
 
                 // Start health check heartbeat
-                Self::start_health_check(Arc::clone(self), tx.clone(), &self.request_id);
+                Self::start_health_check(Arc::clone(self), tx.clone());
 
                 // Reset restart counter on successful start
                 let mut restart_count = self.restart_count.lock().await;
@@ -471,21 +884,311 @@ impl Bridge {
         Ok(())
     }
 
-    /// Waits for the sidecar to reach the `Ready` state.
-    /// Returns immediately if already Ready. Returns an error if the state
-    /// transitions to Stopped or Degraded, or if the timeout expires.
-    async fn wait_for_ready(&self, timeout: Duration) -> Result<(), Error> {
-        let mut rx = self.state_watch_rx.clone();
-
-        // Check current state first
-        let current = *rx.borrow();
-        match current {
-            SidecarState::Ready => return Ok(()),
-            SidecarState::Stopped => {
-                return Err(BridgeError::NotReady("sidecar is Stopped".into()).into());
+    /// Starts the sidecar like [`Self::start`], retrying once with an empty
+    /// classpath (stdlib-only) when the sidecar rejects the project's
+    /// classpath. Returns whether it fell back. Any other failure, such as a
+    /// missing JVM or a bad compiler flag, is returned without a retry.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn start_with_stdlib_fallback(
+        self: &Arc<Self>,
+        project_root: Option<&str>,
+        classpath: &[String],
+        compiler_flags: &[String],
+        source_roots: &[String],
+        generated_source_roots: &[String],
+        classpath_sources: &[String],
+        kapt_classpath: &[String],
+    ) -> Result<bool, Error> {
+        let result = self
+            .start(
+                project_root,
+                classpath,
+                compiler_flags,
+                source_roots,
+                generated_source_roots,
+                classpath_sources,
+                kapt_classpath,
+            )
+            .await;
+        match result {
+            Err(Error::Bridge(BridgeError::ClasspathRejected(reason))) if !classpath.is_empty() => {
+                tracing::warn!(
+                    "sidecar rejected the project classpath ({}), retrying stdlib-only",
+                    reason
+                );
+                self.stop_rejected_sidecar().await;
+                self.start(
+                    project_root,
+                    &[],
+                    compiler_flags,
+                    source_roots,
+                    generated_source_roots,
+                    &[],
+                    &[],
+                )
+                .await?;
+                Ok(true)
             }
-            SidecarState::Starting | SidecarState::Degraded => {
-                // Starting: sidecar is booting up, wait for Ready.
+            result => result.map(|()| false),
+        }
+    }
+
+    /// Stops the sidecar left running after a rejected `initialize`, without
+    /// triggering an automatic restart. A persistent sidecar stays up and is
+    /// reconnected to.
+    async fn stop_rejected_sidecar(&self) {
+        self.shutdown_notify.notify_waiters();
+        if let Some(mut child) = self.child.lock().await.take() {
+            let _ = child.kill().await;
+        }
+    }
+
+    /// The command line that runs the sidecar JVM.
+    fn sidecar_command(&self, config: &Config) -> Result<Command, Error> {
+        let max_memory = &config.sidecar_max_memory;
+        let runtime = &self.runtime;
+
+        let mut command = Command::new(&self.java_path);
+        command
+            .arg(format!("-Xmx{max_memory}"))
+            .arg("--add-opens")
+            .arg("java.base/java.lang=ALL-UNNAMED")
+            .arg("--add-opens")
+            .arg("java.base/java.lang.reflect=ALL-UNNAMED")
+            .arg("--add-opens")
+            .arg("java.base/java.util=ALL-UNNAMED");
+
+        match runtime.main_class.as_deref() {
+            Some(main_class) => {
+                let classpath = std::env::join_paths(&runtime.classpath)
+                    .map_err(|e| BridgeError::SpawnFailed(e.to_string()))?;
+                command.arg("-cp").arg(classpath).arg(main_class);
+            }
+            None => {
+                let sidecar_jar = runtime.classpath.first().ok_or_else(|| {
+                    BridgeError::SpawnFailed("sidecar runtime classpath is empty".into())
+                })?;
+                command.arg("-jar").arg(sidecar_jar);
+            }
+        }
+        Ok(command)
+    }
+
+    /// Spawns the sidecar as a child of this process, talking over its
+    /// stdin and stdout; stderr is forwarded to our log.
+    async fn spawn_sidecar(
+        &self,
+        mut command: Command,
+    ) -> Result<(SidecarReader, SidecarWriter), Error> {
+        let mut child = command
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| BridgeError::SpawnFailed(e.to_string()))?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| BridgeError::SpawnFailed("failed to capture stdin".into()))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| BridgeError::SpawnFailed("failed to capture stdout".into()))?;
+        let stderr = child.stderr.take();
+
+        // Store the child process handle to prevent kill_on_drop from firing
+        {
+            let mut child_slot = self.child.lock().await;
+            *child_slot = Some(child);
+        }
+
+        // Forward sidecar stderr to our tracing output
+        if let Some(stderr) = stderr {
+            tokio::spawn(async move {
+                use tokio::io::AsyncBufReadExt;
+                let reader = BufReader::new(stderr);
+                let mut lines = reader.lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    tracing::info!(target: "sidecar", "{}", line);
+                }
+            });
+        }
+
+        Ok((Box::new(stdout), Box::new(stdin)))
+    }
+
+    /// The daemon this bridge connects to when `persistentSidecar` is on and
+    /// the project root is known.
+    #[cfg(unix)]
+    async fn daemon_paths(&self) -> Option<DaemonPaths> {
+        let root = self.init_params.lock().await.project_root.clone();
+        if !self.config.lock().await.persistent_sidecar || root.is_empty() {
+            return None;
+        }
+        match DaemonPaths::for_project(Path::new(&root)) {
+            Ok(paths) => Some(paths),
+            Err(e) => {
+                tracing::warn!(
+                    "persistent sidecar unavailable, starting a child process: {}",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    fn daemon_identity(&self, config: &Config) -> DaemonIdentity {
+        DaemonIdentity {
+            server_version: env!("CARGO_PKG_VERSION").into(),
+            kotlin_version: self.runtime.kotlin_version.clone(),
+            classpath: self.runtime.classpath.clone(),
+            java_path: self.java_path.clone(),
+            max_memory: config.sidecar_max_memory.clone(),
+        }
+    }
+
+    /// Spawns the task writing queued requests to the sidecar's stdin. A write
+    /// that does not finish within `write_timeout` means the sidecar stopped
+    /// reading: pending requests fail, the bridge goes `Degraded` and is
+    /// restarted, rather than callers blocking on the full request channel
+    /// until their own timeouts.
+    ///
+    /// Until `initialized` fires, only `initialize` is written; anything a
+    /// racing caller queued meanwhile is held back and written in order once
+    /// it does, so the sidecar never sees a document before its session
+    /// exists. If initialization fails the held messages are dropped.
+    fn spawn_writer(
+        bridge: Arc<Self>,
+        mut stdin: SidecarWriter,
+        mut rx: mpsc::Receiver<Request>,
+        mut initialized: oneshot::Receiver<()>,
+        trace: Option<TraceFile>,
+        write_timeout: Duration,
+    ) {
+        tokio::spawn(async move {
+            let mut held = VecDeque::new();
+            let mut ready = false;
+            loop {
+                let request = if ready {
+                    match held.pop_front() {
+                        Some(request) => request,
+                        None => match rx.recv().await {
+                            Some(request) => request,
+                            None => break,
+                        },
+                    }
+                } else {
+                    tokio::select! {
+                        request = rx.recv() => match request {
+                            Some(request) if request.method == "initialize" => request,
+                            Some(request) => {
+                                tracing::debug!(
+                                    "holding '{}' until the sidecar is initialized",
+                                    request.method
+                                );
+                                held.push_back(request);
+                                continue;
+                            }
+                            None => break,
+                        },
+                        result = &mut initialized => {
+                            if result.is_err() {
+                                break;
+                            }
+                            ready = true;
+                            continue;
+                        }
+                    }
+                };
+                let write = jsonrpc::write_message(&mut stdin, &request, trace.as_ref());
+                match time::timeout(write_timeout, write).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        tracing::error!("failed to write to sidecar: {}", e);
+                        break;
+                    }
+                    Err(_) => {
+                        tracing::error!(
+                            "sidecar stopped reading requests (write blocked for {:?})",
+                            write_timeout
+                        );
+                        // Further sends fail at once instead of queueing.
+                        drop(rx);
+                        Self::set_state(
+                            &bridge.state,
+                            &bridge.state_watch_tx,
+                            SidecarState::Degraded,
+                        )
+                        .await;
+                        Self::cancel_all_pending(
+                            &bridge.pending,
+                            "sidecar stopped reading requests",
+                        )
+                        .await;
+                        Self::try_restart(bridge).await;
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Asks the sidecar to drop its analysis session and rebuild it from the
+    /// given project configuration, without restarting the JVM. The new
+    /// configuration is also kept for future restarts.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn reanalyze(
+        self: &Arc<Self>,
+        project_root: Option<&str>,
+        classpath: &[String],
+        compiler_flags: &[String],
+        source_roots: &[String],
+        generated_source_roots: &[String],
+        classpath_sources: &[String],
+        kapt_classpath: &[String],
+    ) -> Result<(), Error> {
+        let init_params = InitParams {
+            project_root: project_root.unwrap_or("").to_string(),
+            classpath: classpath.to_vec(),
+            compiler_flags: compiler_flags.to_vec(),
+            source_roots: source_roots.to_vec(),
+            generated_source_roots: generated_source_roots.to_vec(),
+            classpath_sources: classpath_sources.to_vec(),
+            kapt_classpath: kapt_classpath.to_vec(),
+        };
+        *self.init_params.lock().await = init_params.clone();
+
+        let config = self.config.lock().await.clone();
+        self.request_with_timeout(
+            "reanalyze",
+            Some(init_params.to_request_params(&config)),
+            Duration::from_secs(300),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Waits for the sidecar to reach the `Ready` state.
+    /// Returns immediately if already Ready. Returns an error if the state
+    /// transitions to Stopped or Degraded, or if the timeout expires.
+    async fn wait_for_ready(self: &Arc<Self>, timeout: Duration) -> Result<(), Error> {
+        let mut rx = self.state_watch_rx.clone();
+
+        // Check current state first
+        let current = *rx.borrow();
+        match current {
+            SidecarState::Ready => return Ok(()),
+            SidecarState::Stopped if self.resume_if_suspended().await => {
+                tracing::debug!("waiting for the suspended sidecar to start again");
+            }
+            SidecarState::Stopped => {
+                return Err(BridgeError::NotReady("sidecar is Stopped".into()).into());
+            }
+            SidecarState::Starting | SidecarState::Degraded => {
+                // Starting: sidecar is booting up, wait for Ready.
                 // Degraded: a restart may be in progress — wait to see if
                 // the state transitions to Starting/Ready before giving up.
                 tracing::debug!(
@@ -531,19 +1234,110 @@ impl Bridge {
     /// Sends a JSON-RPC request to the sidecar and waits for the response.
     /// If the sidecar is still starting, waits up to 30 seconds for it to
     /// become Ready before sending the request.
-    pub async fn request(&self, method: &str, params: Option<Value>) -> Result<Value, Error> {
+    pub async fn request(
+        self: &Arc<Self>,
+        method: &str,
+        params: Option<Value>,
+    ) -> Result<Value, Error> {
         self.request_with_timeout(method, params, Duration::from_secs(60))
             .await
     }
 
+    /// Sends a request with a `partialResultToken` so the sidecar can stream
+    /// `$/partialResult` batches, which are forwarded to `batches` as they
+    /// arrive. The returned result holds only what was not streamed. The
+    /// channel closes once the request finishes.
+    pub async fn request_streaming(
+        self: &Arc<Self>,
+        method: &str,
+        mut params: Value,
+        batches: mpsc::UnboundedSender<Value>,
+    ) -> Result<Value, Error> {
+        let token = format!(
+            "{method}-{}",
+            self.next_partial_token.fetch_add(1, Ordering::SeqCst)
+        );
+        params["partialResultToken"] = Value::String(token.clone());
+        self.partial_results
+            .lock()
+            .await
+            .insert(token.clone(), batches);
+
+        let result = self.request(method, Some(params)).await;
+        self.partial_results.lock().await.remove(&token);
+        result
+    }
+
     /// Sends a JSON-RPC request with a custom response timeout.
     /// Used for long-running operations like project-wide analysis.
+    ///
+    /// Read-only requests (see [`is_replayable`]) that fail because the
+    /// sidecar crashed mid-flight are re-sent once after it becomes Ready
+    /// again, instead of surfacing the crash to the editor. Streamed
+    /// requests fail with the crash instead.
     pub async fn request_with_timeout(
-        &self,
+        self: &Arc<Self>,
+        method: &str,
+        params: Option<Value>,
+        timeout: Duration,
+    ) -> Result<Value, Error> {
+        let span = tracing::debug_span!("sidecar_request", method);
+        let started = Instant::now();
+        let result = self
+            .request_with_replay(method, params, timeout)
+            .instrument(span.clone())
+            .await;
+        span.in_scope(|| {
+            tracing::debug!(
+                elapsed_ms = started.elapsed().as_millis() as u64,
+                ok = result.is_ok(),
+                "sidecar request finished"
+            );
+        });
+        result
+    }
+
+    async fn request_with_replay(
+        self: &Arc<Self>,
+        method: &str,
+        params: Option<Value>,
+        timeout: Duration,
+    ) -> Result<Value, Error> {
+        if let Some(info) = self.sidecar_info.lock().await.as_ref() {
+            if !info.supports(method) {
+                return Err(BridgeError::Unsupported(method.to_string()).into());
+            }
+        }
+
+        // Batches of a streamed request already reached the client, so a
+        // replay would send them again.
+        let streamed = params
+            .as_ref()
+            .is_some_and(|params| params.get("partialResultToken").is_some());
+        let replayable = is_replayable(method) && !streamed;
+        let mut replayed = false;
+
+        loop {
+            let result = self.send_request(method, params.clone(), timeout).await;
+
+            match result {
+                Err(Error::Bridge(BridgeError::Crashed(reason))) if replayable && !replayed => {
+                    tracing::info!("replaying '{}' after sidecar crash ({})", method, reason);
+                    replayed = true;
+                }
+                other => return other,
+            }
+        }
+    }
+
+    /// Waits for readiness, sends a single request, and awaits its response.
+    async fn send_request(
+        self: &Arc<Self>,
         method: &str,
         params: Option<Value>,
         timeout: Duration,
     ) -> Result<Value, Error> {
+        self.touch().await;
         self.wait_for_ready(Duration::from_secs(30)).await?;
 
         tracing::debug!(
@@ -552,33 +1346,41 @@ impl Bridge {
             timeout
         );
 
-        let id = self.next_id();
+        let (id, response_rx) = self.register_pending(timeout).await;
         let request = Request::new(id, method, params);
 
-        let (response_tx, response_rx) = oneshot::channel();
-        {
-            let mut pending = self.pending.lock().await;
-            pending.push(PendingRequest { id, response_tx });
+        let request_tx = self.request_tx.lock().await.clone();
+        if request_tx.send(request).await.is_err() {
+            self.pending.lock().await.remove(&id);
+            return Err(BridgeError::Crashed("request channel closed".into()).into());
         }
+        let mut in_flight = InFlight {
+            id,
+            pending: Arc::clone(&self.pending),
+            request_tx,
+            answered: false,
+        };
 
-        self.request_tx
-            .lock()
-            .await
-            .send(request)
-            .await
-            .map_err(|_| BridgeError::Crashed("request channel closed".into()))?;
-
-        match time::timeout(timeout, response_rx).await {
+        let result = match time::timeout(timeout, response_rx).await {
             Ok(Ok(result)) => result,
             Ok(Err(_)) => Err(BridgeError::Crashed("response channel dropped".into()).into()),
-            Err(_) => Err(BridgeError::Timeout(timeout.as_millis() as u64).into()),
-        }
+            // Still unanswered, so dropping `in_flight` cancels the request
+            // and the sidecar stops working on it.
+            Err(_) => return Err(BridgeError::Timeout(timeout.as_millis() as u64).into()),
+        };
+        in_flight.answered = true;
+        result
     }
 
     /// Sends a JSON-RPC notification (no response expected).
     /// Waits for sidecar readiness before sending.
-    pub async fn notify(&self, method: &str, params: Option<Value>) -> Result<(), Error> {
+    pub async fn notify(
+        self: &Arc<Self>,
+        method: &str,
+        params: Option<Value>,
+    ) -> Result<(), Error> {
         // Wait for sidecar to be ready for notifications too
+        self.touch().await;
         self.wait_for_ready(Duration::from_secs(30)).await?;
 
         let notification = Request::notification(method, params);
@@ -631,6 +1433,10 @@ impl Bridge {
                     let _ = proc.kill().await;
                 }
             }
+            #[cfg(unix)]
+            if let Some(paths) = bridge.daemon_paths().await {
+                daemon::kill_daemon(&paths).await;
+            }
 
             // Cancel any lingering pending requests
             Self::cancel_all_pending(&bridge.pending, "sidecar restarting").await;
@@ -648,6 +1454,9 @@ impl Bridge {
                     &params.classpath,
                     &params.compiler_flags,
                     &params.source_roots,
+                    &params.generated_source_roots,
+                    &params.classpath_sources,
+                    &params.kapt_classpath,
                 )
                 .await;
 
@@ -672,29 +1481,100 @@ impl Bridge {
 
     /// Shuts down the sidecar gracefully.
     pub async fn shutdown(&self) -> Result<(), Error> {
+        self.suspended.store(false, Ordering::SeqCst);
         let state = self.state().await;
         if state == SidecarState::Stopped {
             return Ok(());
         }
 
         tracing::info!("shutting down sidecar");
+        // A persistent sidecar keeps running for the next server to reuse.
+        #[cfg(unix)]
+        let keep_running = self.daemon_paths().await.is_some();
+        #[cfg(not(unix))]
+        let keep_running = false;
+        self.stop(keep_running, "server shutting down").await;
+        Ok(())
+    }
+
+    /// Stops the sidecar when it is `Ready`, nothing is in flight, and no
+    /// request or notification reached it for `idle_timeout`, to free the
+    /// JVM's memory. The next request starts it again. Returns whether it
+    /// was suspended.
+    pub async fn suspend_if_idle(&self, idle_timeout: Duration) -> bool {
+        if self.state().await != SidecarState::Ready
+            || self.last_activity.lock().await.elapsed() < idle_timeout
+            || !self.pending.lock().await.is_empty()
+        {
+            return false;
+        }
+        tracing::info!(
+            "sidecar idle for {:?}, stopping it until the next request",
+            idle_timeout
+        );
+        self.suspended.store(true, Ordering::SeqCst);
+        // Even a persistent sidecar exits, or no memory would be freed.
+        self.stop(false, "sidecar suspended").await;
+        true
+    }
+
+    /// Records activity, postponing an idle suspension.
+    async fn touch(&self) {
+        *self.last_activity.lock().await = Instant::now();
+    }
+
+    /// Starts a suspended sidecar again in the background with the stored
+    /// project configuration. Returns false when it was not suspended.
+    async fn resume_if_suspended(self: &Arc<Self>) -> bool {
+        if !self.suspended.swap(false, Ordering::SeqCst) {
+            return false;
+        }
+        tracing::info!("resuming suspended sidecar");
+        // Starting right away, so concurrent requests wait rather than fail.
+        Self::set_state(&self.state, &self.state_watch_tx, SidecarState::Starting).await;
+        let bridge = Arc::clone(self);
+        tokio::spawn(async move {
+            let params = bridge.init_params.lock().await.clone();
+            let result = bridge
+                .start(
+                    Some(&params.project_root),
+                    &params.classpath,
+                    &params.compiler_flags,
+                    &params.source_roots,
+                    &params.generated_source_roots,
+                    &params.classpath_sources,
+                    &params.kapt_classpath,
+                )
+                .await;
+            if let Err(e) = result {
+                tracing::error!("failed to resume suspended sidecar: {}", e);
+                Self::set_state(&bridge.state, &bridge.state_watch_tx, SidecarState::Stopped).await;
+            }
+        });
+        true
+    }
+
+    /// Stops the reader, health check and pending requests and, unless
+    /// `keep_running`, asks the sidecar to exit. Leaves the bridge `Stopped`.
+    async fn stop(&self, keep_running: bool, reason: &str) {
         self.health_check_shutdown.notify_waiters();
         self.shutdown_notify.notify_waiters();
 
         // Cancel all pending requests
-        Self::cancel_all_pending(&self.pending, "server shutting down").await;
+        Self::cancel_all_pending(&self.pending, reason).await;
 
-        // Try to send shutdown request
-        let _ = self
-            .request_tx
-            .lock()
-            .await
-            .send(Request::new(self.next_id(), "shutdown", None))
-            .await;
+        if keep_running {
+            tracing::info!("leaving persistent sidecar running");
+        } else {
+            let _ = self
+                .request_tx
+                .lock()
+                .await
+                .send(Request::new(self.next_id(), "shutdown", None))
+                .await;
+        }
 
         Self::set_state(&self.state, &self.state_watch_tx, SidecarState::Stopped).await;
-
-        Ok(())
     }
 
     /// Updates the bridge configuration.
@@ -707,7 +1587,54 @@ impl Bridge {
         self.request_id.fetch_add(1, Ordering::Relaxed)
     }
 
-    async fn dispatch_response(pending: &Mutex<Vec<PendingRequest>>, response: Response) {
+    /// Allocates a request id and registers a pending entry for its response.
+    /// Every request that expects a response must go through here so ids stay
+    /// unique across the main path, the health check, and restarts.
+    async fn register_pending(
+        &self,
+        timeout: Duration,
+    ) -> (u64, oneshot::Receiver<Result<Value, Error>>) {
+        let id = self.next_id();
+        let (response_tx, response_rx) = oneshot::channel();
+        self.pending.lock().await.insert(
+            id,
+            PendingRequest {
+                response_tx,
+                deadline: Instant::now() + timeout,
+                timeout,
+            },
+        );
+        (id, response_rx)
+    }
+
+    /// Routes a notification the sidecar sent unprompted. Only
+    /// `$/partialResult` is understood; it carries `{token, value}`, where
+    /// `value` has the shape of the final result.
+    async fn dispatch_notification(
+        partial_results: &Mutex<PartialResultMap>,
+        notification: Request,
+    ) {
+        if notification.method != "$/partialResult" {
+            tracing::debug!("ignoring sidecar notification {}", notification.method);
+            return;
+        }
+        let params = notification.params.unwrap_or(Value::Null);
+        let (Some(token), Some(value)) = (
+            params.get("token").and_then(|t| t.as_str()),
+            params.get("value"),
+        ) else {
+            tracing::warn!("malformed $/partialResult from sidecar: {}", params);
+            return;
+        };
+        match partial_results.lock().await.get(token) {
+            Some(batches) => {
+                let _ = batches.send(value.clone());
+            }
+            None => tracing::debug!("partial result for finished request {}", token),
+        }
+    }
+
+    async fn dispatch_response(pending: &Mutex<PendingMap>, response: Response) {
         tracing::debug!("Received response from sidecar: {:?}", response);
         let id = match response.id {
             Some(id) => id,
@@ -723,14 +1650,20 @@ impl Bridge {
             id,
             pending.len()
         );
-        if let Some(pos) = pending.iter().position(|p| p.id == id) {
-            let req = pending.remove(pos);
+        if let Some(req) = pending.remove(&id) {
             let result = if let Some(error) = response.error {
-                tracing::error!("Sidecar returned error for request {}: {:?}", id, error);
-                Err(Error::Bridge(BridgeError::MalformedResponse(format!(
-                    "error {}: {}",
-                    error.code, error.message
-                ))))
+                if error.code == CONTENT_MODIFIED {
+                    tracing::debug!("sidecar is behind for request {}: {}", id, error.message);
+                    Err(Error::Bridge(BridgeError::ContentModified(error.message)))
+                } else if error.code == CLASSPATH_REJECTED {
+                    Err(Error::Bridge(BridgeError::ClasspathRejected(error.message)))
+                } else {
+                    tracing::error!("Sidecar returned error for request {}: {:?}", id, error);
+                    Err(Error::Bridge(BridgeError::MalformedResponse(format!(
+                        "error {}: {}",
+                        error.code, error.message
+                    ))))
+                }
             } else {
                 tracing::debug!("sidecar returned success for request {}", id);
                 Ok(response.result.unwrap_or(Value::Null))
@@ -751,57 +1684,198 @@ impl Drop for Bridge {
     }
 }
 
-/// Discovers a suitable Java binary.
-pub fn find_java() -> Result<PathBuf, Error> {
-    // 1. KOTLIN_LS_JAVA_HOME
-    if let Ok(home) = std::env::var("KOTLIN_LS_JAVA_HOME") {
-        let java = Path::new(&home).join("bin/java");
-        if java.exists() {
-            return Ok(java);
+/// Returns true for idempotent, read-only sidecar methods that can safely be
+/// re-sent to a restarted sidecar. Mutating requests such as `rename` are
+/// never replayed.
+fn is_replayable(method: &str) -> bool {
+    matches!(
+        method,
+        "hover"
+            | "completion"
+            | "definition"
+            | "references"
+            | "pebble/definition"
+            | "pebble/references"
+    )
+}
+
+/// Discovers a suitable Java binary. Precedence: the `javaHome` setting,
+/// then `KOTLIN_LS_JAVA_HOME`, then `JAVA_HOME`, then `java` on `PATH`.
+pub fn find_java(java_home: Option<&str>) -> Result<PathBuf, Error> {
+    find_java_with(
+        java_home,
+        std::env::var("KOTLIN_LS_JAVA_HOME").ok(),
+        std::env::var("JAVA_HOME").ok(),
+        java_on_path,
+    )
+}
+
+fn find_java_with(
+    configured: Option<&str>,
+    kotlin_ls_java_home: Option<String>,
+    java_home: Option<String>,
+    path_lookup: impl FnOnce() -> Option<PathBuf>,
+) -> Result<PathBuf, Error> {
+    // 1. javaHome setting: a JDK directory (absolute, or relative to the
+    //    server binary for bundled JDKs) or a path to the java executable.
+    if let Some(configured) = configured.filter(|value| !value.is_empty()) {
+        match java_from_setting(Path::new(configured)) {
+            Some(java) => return Ok(java),
+            None => tracing::warn!(
+                "javaHome '{}' does not contain a java executable, falling back",
+                configured
+            ),
         }
     }
 
-    // 2. JAVA_HOME
-    if let Ok(home) = std::env::var("JAVA_HOME") {
-        let java = Path::new(&home).join("bin/java");
-        if java.exists() {
-            return Ok(java);
-        }
+    // 2. KOTLIN_LS_JAVA_HOME
+    if let Some(java) = kotlin_ls_java_home.and_then(|home| java_in_home(Path::new(&home))) {
+        return Ok(java);
     }
 
-    // 3. java on PATH
-    if let Ok(output) = std::process::Command::new("which").arg("java").output() {
-        if output.status.success() {
-            let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if !path.is_empty() {
-                return Ok(PathBuf::from(path));
-            }
-        }
+    // 3. JAVA_HOME
+    if let Some(java) = java_home.and_then(|home| java_in_home(Path::new(&home))) {
+        return Ok(java);
+    }
+
+    // 4. java on PATH
+    if let Some(java) = path_lookup() {
+        return Ok(java);
     }
 
     Err(crate::error::ProjectError::JvmNotFound(
-        "No JVM found. Set JAVA_HOME or KOTLIN_LS_JAVA_HOME environment variable.".into(),
+        "No JVM found. Set javaHome, JAVA_HOME, or KOTLIN_LS_JAVA_HOME.".into(),
     )
     .into())
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
+/// Resolves the `javaHome` setting, which may name the executable itself or
+/// a JDK home. Relative paths are taken relative to the server binary so a
+/// JDK can be bundled alongside it.
+fn java_from_setting(configured: &Path) -> Option<PathBuf> {
+    let configured = if configured.is_relative() {
+        let exe = std::env::current_exe().ok()?;
+        exe.parent()?.join(configured)
+    } else {
+        configured.to_path_buf()
+    };
+
+    if configured.is_file() {
+        return Some(configured);
+    }
+    java_in_home(&configured)
+}
+
+/// Returns the java executable inside a JDK home, including the macOS
+/// bundle layout (`Contents/Home`).
+fn java_in_home(home: &Path) -> Option<PathBuf> {
+    let java = java_executable_name(cfg!(windows));
+    [
+        home.join("bin").join(java),
+        home.join("Contents/Home/bin").join(java),
+    ]
+    .into_iter()
+    .find(|java| java.is_file())
+}
+
+fn java_executable_name(windows: bool) -> &'static str {
+    if windows {
+        "java.exe"
+    } else {
+        "java"
+    }
+}
+
+/// The command that prints the location of an executable on `PATH`.
+fn path_lookup_command(windows: bool) -> &'static str {
+    if windows {
+        "where"
+    } else {
+        "which"
+    }
+}
+
+fn java_on_path() -> Option<PathBuf> {
+    let output = std::process::Command::new(path_lookup_command(cfg!(windows)))
+        .arg("java")
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // `where` lists every match, one per line; take the first like `which`.
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let path = stdout.lines().next()?.trim();
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// A stopped bridge over the default bundled runtime, for tests that play
+/// the sidecar through `connect_fake_sidecar` or never start it.
+#[cfg(test)]
+pub(crate) fn test_bridge(config: Config) -> Bridge {
+    Bridge::new(
+        SidecarRuntime {
+            requested_kotlin_version: None,
+            kotlin_version: Some("2.2.21".into()),
+            classpath: vec![PathBuf::from("sidecar.jar")],
+            main_class: None,
+            selection_reason: crate::runtime::RuntimeSelectionReason::DefaultBundled,
+        },
+        PathBuf::from("/usr/bin/java"),
+        config,
+    )
+}
+
+#[cfg(test)]
+impl Bridge {
+    /// Installs a fake request channel and marks the bridge Ready so tests can
+    /// play the sidecar's side of the conversation.
+    pub(crate) async fn connect_fake_sidecar(&self) -> mpsc::Receiver<Request> {
+        let (tx, rx) = mpsc::channel(8);
+        *self.request_tx.lock().await = tx;
+        Bridge::set_state(&self.state, &self.state_watch_tx, SidecarState::Ready).await;
+        rx
+    }
+
+    /// Answers a request received through `connect_fake_sidecar`.
+    pub(crate) async fn reply_fake_sidecar(&self, request: &Request, result: Value) {
+        let response = Response {
+            jsonrpc: "2.0".into(),
+            id: request.id,
+            result: Some(result),
+            error: None,
+        };
+        Bridge::dispatch_response(&self.pending, response).await;
+    }
+
+    /// Answers a request received through `connect_fake_sidecar` with an error.
+    pub(crate) async fn reply_fake_sidecar_error(
+        &self,
+        request: &Request,
+        code: i32,
+        message: &str,
+    ) {
+        let response = Response {
+            jsonrpc: "2.0".into(),
+            id: request.id,
+            result: None,
+            error: Some(jsonrpc::ResponseError {
+                code,
+                message: message.into(),
+                data: None,
+            }),
+        };
+        Bridge::dispatch_response(&self.pending, response).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
     #[test]
     fn initial_state_is_stopped() {
-        let bridge = Bridge::new(
-            SidecarRuntime {
-                requested_kotlin_version: None,
-                kotlin_version: Some("2.2.21".into()),
-                classpath: vec![PathBuf::from("sidecar.jar")],
-                main_class: None,
-                selection_reason: crate::runtime::RuntimeSelectionReason::DefaultBundled,
-            },
-            PathBuf::from("/usr/bin/java"),
-            Config::default(),
-        );
+        let bridge = test_bridge(Config::default());
         let rt = tokio::runtime::Runtime::new().unwrap();
         let state = rt.block_on(bridge.state());
         assert_eq!(state, SidecarState::Stopped);
@@ -809,17 +1883,7 @@ mod tests {
 
     #[test]
     fn next_id_increments() {
-        let bridge = Bridge::new(
-            SidecarRuntime {
-                requested_kotlin_version: None,
-                kotlin_version: Some("2.2.21".into()),
-                classpath: vec![PathBuf::from("sidecar.jar")],
-                main_class: None,
-                selection_reason: crate::runtime::RuntimeSelectionReason::DefaultBundled,
-            },
-            PathBuf::from("/usr/bin/java"),
-            Config::default(),
-        );
+        let bridge = test_bridge(Config::default());
         let id1 = bridge.next_id();
         let id2 = bridge.next_id();
         assert_eq!(id2, id1 + 1);
@@ -827,17 +1891,7 @@ mod tests {
 
     #[tokio::test]
     async fn request_before_start_returns_not_ready() {
-        let bridge = Bridge::new(
-            SidecarRuntime {
-                requested_kotlin_version: None,
-                kotlin_version: Some("2.2.21".into()),
-                classpath: vec![PathBuf::from("sidecar.jar")],
-                main_class: None,
-                selection_reason: crate::runtime::RuntimeSelectionReason::DefaultBundled,
-            },
-            PathBuf::from("/usr/bin/java"),
-            Config::default(),
-        );
+        let bridge = Arc::new(test_bridge(Config::default()));
         // State is Stopped, so wait_for_ready should return NotReady immediately
         let result = bridge.request("hover", None).await;
         assert!(result.is_err());
@@ -851,17 +1905,7 @@ mod tests {
 
     #[tokio::test]
     async fn wait_for_ready_returns_immediately_when_ready() {
-        let bridge = Bridge::new(
-            SidecarRuntime {
-                requested_kotlin_version: None,
-                kotlin_version: Some("2.2.21".into()),
-                classpath: vec![PathBuf::from("sidecar.jar")],
-                main_class: None,
-                selection_reason: crate::runtime::RuntimeSelectionReason::DefaultBundled,
-            },
-            PathBuf::from("/usr/bin/java"),
-            Config::default(),
-        );
+        let bridge = Arc::new(test_bridge(Config::default()));
         // Manually set state to Ready
         Bridge::set_state(&bridge.state, &bridge.state_watch_tx, SidecarState::Ready).await;
 
@@ -871,17 +1915,7 @@ mod tests {
 
     #[tokio::test]
     async fn wait_for_ready_blocks_during_starting() {
-        let bridge = Bridge::new(
-            SidecarRuntime {
-                requested_kotlin_version: None,
-                kotlin_version: Some("2.2.21".into()),
-                classpath: vec![PathBuf::from("sidecar.jar")],
-                main_class: None,
-                selection_reason: crate::runtime::RuntimeSelectionReason::DefaultBundled,
-            },
-            PathBuf::from("/usr/bin/java"),
-            Config::default(),
-        );
+        let bridge = Arc::new(test_bridge(Config::default()));
         // Set state to Starting
         Bridge::set_state(
             &bridge.state,
@@ -905,17 +1939,7 @@ mod tests {
 
     #[tokio::test]
     async fn wait_for_ready_waits_then_times_out_on_degraded() {
-        let bridge = Bridge::new(
-            SidecarRuntime {
-                requested_kotlin_version: None,
-                kotlin_version: Some("2.2.21".into()),
-                classpath: vec![PathBuf::from("sidecar.jar")],
-                main_class: None,
-                selection_reason: crate::runtime::RuntimeSelectionReason::DefaultBundled,
-            },
-            PathBuf::from("/usr/bin/java"),
-            Config::default(),
-        );
+        let bridge = Arc::new(test_bridge(Config::default()));
         Bridge::set_state(
             &bridge.state,
             &bridge.state_watch_tx,
@@ -936,14 +1960,18 @@ mod tests {
 
     #[tokio::test]
     async fn cancel_all_pending_sends_errors() {
-        let pending = Arc::new(Mutex::new(Vec::new()));
+        let pending = Arc::new(Mutex::new(HashMap::new()));
         let (tx, rx) = oneshot::channel();
         {
             let mut p = pending.lock().await;
-            p.push(PendingRequest {
-                id: 1,
-                response_tx: tx,
-            });
+            p.insert(
+                1,
+                PendingRequest {
+                    response_tx: tx,
+                    deadline: Instant::now() + Duration::from_secs(60),
+                    timeout: Duration::from_secs(60),
+                },
+            );
         }
 
         Bridge::cancel_all_pending(&pending, "test crash").await;
@@ -952,6 +1980,741 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[tokio::test]
+    async fn failed_send_forgets_the_pending_request() {
+        let bridge = Arc::new(test_bridge(Config::default()));
+        drop(bridge.connect_fake_sidecar().await);
+
+        let result = bridge.request("hover", None).await;
+
+        assert!(result.is_err());
+        assert!(bridge.pending.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn sweep_evicts_requests_whose_caller_stopped_waiting() {
+        let bridge = test_bridge(Config::default());
+
+        // A response never arrives and nobody drives the timeout future.
+        let (_, expired_rx) = bridge.register_pending(Duration::from_millis(10)).await;
+        let (live_id, _live_rx) = bridge.register_pending(Duration::from_secs(60)).await;
+
+        let later = Instant::now() + Duration::from_secs(1);
+        let evicted = Bridge::sweep_expired_pending(&bridge.pending, later).await;
+        assert_eq!(evicted, 1);
+
+        let pending = bridge.pending.lock().await;
+        assert_eq!(pending.len(), 1);
+        assert!(pending.contains_key(&live_id));
+        drop(pending);
+
+        let err = expired_rx.await.unwrap().unwrap_err();
+        assert!(
+            matches!(err, Error::Bridge(BridgeError::Timeout(10))),
+            "expected Timeout, got: {:?}",
+            err
+        );
+    }
+
+    #[tokio::test]
+    async fn health_check_and_request_ids_do_not_collide() {
+        let bridge = test_bridge(Config::default());
+
+        // Interleave a health-check ping registration with a normal request.
+        let (request_id, request_rx) = bridge.register_pending(Duration::from_secs(60)).await;
+        let (ping_id, ping_rx) = bridge.register_pending(Duration::from_secs(30)).await;
+        assert_ne!(request_id, ping_id);
+
+        let response = |id: u64, result: Value| Response {
+            jsonrpc: "2.0".into(),
+            id: Some(id),
+            result: Some(result),
+            error: None,
+        };
+        Bridge::dispatch_response(
+            &bridge.pending,
+            response(ping_id, serde_json::json!({"pong": true})),
+        )
+        .await;
+        Bridge::dispatch_response(
+            &bridge.pending,
+            response(request_id, serde_json::json!({"contents": "hover"})),
+        )
+        .await;
+
+        let ping = ping_rx.await.unwrap().unwrap();
+        let request = request_rx.await.unwrap().unwrap();
+        assert_eq!(ping["pong"], true);
+        assert_eq!(request["contents"], "hover");
+    }
+
+    /// Simulates a sidecar crash followed by a successful restart.
+    async fn crash_and_recover(bridge: &Bridge) {
+        Bridge::set_state(
+            &bridge.state,
+            &bridge.state_watch_tx,
+            SidecarState::Degraded,
+        )
+        .await;
+        Bridge::cancel_all_pending(&bridge.pending, "sidecar process exited").await;
+        time::sleep(Duration::from_millis(20)).await;
+        Bridge::set_state(&bridge.state, &bridge.state_watch_tx, SidecarState::Ready).await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn writer_detects_a_sidecar_that_stops_reading_stdin() {
+        let bridge = Arc::new(test_bridge(Config::default()));
+        Bridge::set_state(&bridge.state, &bridge.state_watch_tx, SidecarState::Ready).await;
+
+        // A child that never drains its stdin: once the pipe buffer is full,
+        // every write blocks.
+        let mut child = Command::new("sleep")
+            .arg("30")
+            .stdin(std::process::Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let (tx, rx) = mpsc::channel(32);
+        let (initialized_tx, initialized_rx) = oneshot::channel();
+        initialized_tx.send(()).unwrap();
+        Bridge::spawn_writer(
+            Arc::clone(&bridge),
+            Box::new(child.stdin.take().unwrap()),
+            rx,
+            initialized_rx,
+            None,
+            Duration::from_millis(200),
+        );
+
+        let (id, response_rx) = bridge.register_pending(Duration::from_secs(60)).await;
+        let text = "x".repeat(1 << 20);
+        tx.send(Request::new(
+            id,
+            "analyze",
+            Some(serde_json::json!({ "text": text })),
+        ))
+        .await
+        .unwrap();
+
+        let response = time::timeout(Duration::from_secs(10), response_rx)
+            .await
+            .expect("stalled write was not detected")
+            .unwrap();
+        assert!(matches!(
+            response,
+            Err(Error::Bridge(BridgeError::Crashed(_)))
+        ));
+        assert_eq!(bridge.state().await, SidecarState::Degraded);
+        assert!(tx
+            .send(Request::new(bridge.next_id(), "ping", None))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn writer_holds_messages_until_initialize_completes() {
+        let bridge = Arc::new(test_bridge(Config::default()));
+        Bridge::set_state(
+            &bridge.state,
+            &bridge.state_watch_tx,
+            SidecarState::Starting,
+        )
+        .await;
+
+        let (sidecar_in, sidecar_stdin) = tokio::io::duplex(1 << 16);
+        let (tx, rx) = mpsc::channel(32);
+        let (initialized_tx, initialized_rx) = oneshot::channel();
+        Bridge::spawn_writer(
+            Arc::clone(&bridge),
+            Box::new(sidecar_stdin),
+            rx,
+            initialized_rx,
+            None,
+            Duration::from_secs(5),
+        );
+        let mut sidecar_in = BufReader::new(sidecar_in);
+        async fn next_method(
+            sidecar_in: &mut BufReader<tokio::io::DuplexStream>,
+        ) -> Option<String> {
+            match time::timeout(
+                Duration::from_millis(200),
+                jsonrpc::read_message(sidecar_in, None),
+            )
+            .await
+            {
+                Ok(Ok(Some(Message::Notification(request)))) => Some(request.method),
+                _ => None,
+            }
+        }
+
+        // A didOpen racing the startup reaches the channel before initialize.
+        tx.send(Request::notification(
+            "textDocument/didOpen",
+            Some(serde_json::json!({ "uri": "file:///A.kt", "text": "" })),
+        ))
+        .await
+        .unwrap();
+        tx.send(Request::new(1, "initialize", None)).await.unwrap();
+
+        assert_eq!(
+            next_method(&mut sidecar_in).await.as_deref(),
+            Some("initialize")
+        );
+        assert_eq!(next_method(&mut sidecar_in).await, None);
+
+        initialized_tx.send(()).unwrap();
+        assert_eq!(
+            next_method(&mut sidecar_in).await.as_deref(),
+            Some("textDocument/didOpen")
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn rejected_classpath_falls_back_to_stdlib_only() {
+        use tokio::net::UnixListener;
+
+        let project = tempfile::tempdir().unwrap();
+        let root = project.path().to_string_lossy().to_string();
+        let config = Config {
+            persistent_sidecar: true,
+            ..Config::default()
+        };
+        let bridge = Arc::new(test_bridge(config.clone()));
+
+        // A persistent sidecar that fails to build a session from any
+        // classpath entries.
+        let paths = DaemonPaths::for_project(project.path()).unwrap();
+        let listener = UnixListener::bind(&paths.socket).unwrap();
+        let record = daemon::DaemonRecord {
+            pid: 0,
+            identity: bridge.daemon_identity(&config),
+        };
+        std::fs::write(&paths.record, serde_json::to_string(&record).unwrap()).unwrap();
+        let sidecar = tokio::spawn(async move {
+            let mut classpaths = Vec::new();
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                daemon::say_hello(&mut stream, false).await;
+                let (read, mut write) = stream.into_split();
+                let mut reader = BufReader::new(read);
+                let Ok(Some(Message::Notification(request))) =
+                    jsonrpc::read_message(&mut reader, None).await
+                else {
+                    continue;
+                };
+                let classpath = request.params.unwrap()["classpath"].clone();
+                let rejected = classpath.as_array().is_some_and(|c| !c.is_empty());
+                classpaths.push(classpath);
+                let response = Response {
+                    jsonrpc: "2.0".into(),
+                    id: request.id,
+                    result: (!rejected).then(|| serde_json::json!({ "success": true })),
+                    error: rejected.then(|| jsonrpc::ResponseError {
+                        code: CLASSPATH_REJECTED,
+                        message: "ZipException: zip END header not found".into(),
+                        data: None,
+                    }),
+                };
+                jsonrpc::write_message(&mut write, &response, None)
+                    .await
+                    .unwrap();
+                if !rejected {
+                    return classpaths;
+                }
+            }
+        });
+
+        let fell_back = bridge
+            .start_with_stdlib_fallback(
+                Some(&root),
+                &["broken.jar".into()],
+                &[],
+                &[],
+                &[],
+                &[],
+                &[],
+            )
+            .await
+            .unwrap();
+        assert!(fell_back);
+        assert_eq!(bridge.state().await, SidecarState::Ready);
+        assert!(bridge.init_params.lock().await.classpath.is_empty());
+        assert_eq!(
+            sidecar.await.unwrap(),
+            vec![serde_json::json!(["broken.jar"]), serde_json::json!([])]
+        );
+        bridge.shutdown_notify.notify_waiters();
+        bridge.health_check_shutdown.notify_waiters();
+        let _ = std::fs::remove_file(&paths.socket);
+        let _ = std::fs::remove_file(&paths.record);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn other_initialize_errors_are_not_retried_stdlib_only() {
+        use tokio::net::UnixListener;
+
+        let project = tempfile::tempdir().unwrap();
+        let root = project.path().to_string_lossy().to_string();
+        let config = Config {
+            persistent_sidecar: true,
+            ..Config::default()
+        };
+        let bridge = Arc::new(test_bridge(config.clone()));
+
+        // A persistent sidecar that fails `initialize` for a reason other
+        // than the classpath.
+        let paths = DaemonPaths::for_project(project.path()).unwrap();
+        let listener = UnixListener::bind(&paths.socket).unwrap();
+        let record = daemon::DaemonRecord {
+            pid: 0,
+            identity: bridge.daemon_identity(&config),
+        };
+        std::fs::write(&paths.record, serde_json::to_string(&record).unwrap()).unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            daemon::say_hello(&mut stream, false).await;
+            let (read, mut write) = stream.into_split();
+            let mut reader = BufReader::new(read);
+            let Ok(Some(Message::Notification(request))) =
+                jsonrpc::read_message(&mut reader, None).await
+            else {
+                return;
+            };
+            let response = Response {
+                jsonrpc: "2.0".into(),
+                id: request.id,
+                result: None,
+                error: Some(jsonrpc::ResponseError {
+                    code: -32603,
+                    message: "Internal error: IllegalArgumentException: bad flag".into(),
+                    data: None,
+                }),
+            };
+            jsonrpc::write_message(&mut write, &response, None)
+                .await
+                .unwrap();
+        });
+
+        let result = bridge
+            .start_with_stdlib_fallback(Some(&root), &["app.jar".into()], &[], &[], &[], &[], &[])
+            .await;
+        assert!(
+            matches!(
+                result,
+                Err(Error::Bridge(BridgeError::MalformedResponse(_)))
+            ),
+            "{result:?}"
+        );
+        assert_eq!(bridge.state().await, SidecarState::Stopped);
+        let _ = std::fs::remove_file(&paths.socket);
+        let _ = std::fs::remove_file(&paths.record);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn idle_sidecar_is_suspended_and_resumed_on_demand() {
+        use tokio::net::UnixListener;
+
+        let project = tempfile::tempdir().unwrap();
+        let root = project.path().to_string_lossy().to_string();
+        let config = Config {
+            persistent_sidecar: true,
+            ..Config::default()
+        };
+        let bridge = Arc::new(test_bridge(config.clone()));
+
+        // A sidecar that answers every request on a connection until it is
+        // told to shut down, then waits for the next one.
+        let paths = DaemonPaths::for_project(project.path()).unwrap();
+        let listener = UnixListener::bind(&paths.socket).unwrap();
+        let record = daemon::DaemonRecord {
+            pid: 0,
+            identity: bridge.daemon_identity(&config),
+        };
+        std::fs::write(&paths.record, serde_json::to_string(&record).unwrap()).unwrap();
+        let sidecar = tokio::spawn(async move {
+            let mut methods = Vec::new();
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                daemon::say_hello(&mut stream, false).await;
+                let (read, mut write) = stream.into_split();
+                let mut reader = BufReader::new(read);
+                while let Ok(Some(Message::Notification(request))) =
+                    jsonrpc::read_message(&mut reader, None).await
+                {
+                    methods.push(request.method.clone());
+                    match request.method.as_str() {
+                        "shutdown" => break,
+                        "hover" => {
+                            let response = Response {
+                                jsonrpc: "2.0".into(),
+                                id: request.id,
+                                result: Some(serde_json::json!({ "contents": "hover" })),
+                                error: None,
+                            };
+                            jsonrpc::write_message(&mut write, &response, None)
+                                .await
+                                .unwrap();
+                            return methods;
+                        }
+                        _ => {
+                            let response = Response {
+                                jsonrpc: "2.0".into(),
+                                id: request.id,
+                                result: Some(serde_json::json!({ "success": true })),
+                                error: None,
+                            };
+                            jsonrpc::write_message(&mut write, &response, None)
+                                .await
+                                .unwrap();
+                        }
+                    }
+                }
+            }
+        });
+
+        bridge
+            .start(Some(&root), &["lib.jar".into()], &[], &[], &[], &[], &[])
+            .await
+            .unwrap();
+        assert!(!bridge.suspend_if_idle(Duration::from_secs(60)).await);
+        assert!(bridge.suspend_if_idle(Duration::ZERO).await);
+        assert_eq!(bridge.state().await, SidecarState::Stopped);
+
+        let result = bridge.request("hover", None).await.unwrap();
+        assert_eq!(result["contents"], "hover");
+        assert_eq!(
+            sidecar.await.unwrap(),
+            vec!["initialize", "shutdown", "initialize", "hover"]
+        );
+        assert_eq!(bridge.init_params.lock().await.classpath, vec!["lib.jar"]);
+        bridge.shutdown_notify.notify_waiters();
+        bridge.health_check_shutdown.notify_waiters();
+        let _ = std::fs::remove_file(&paths.socket);
+        let _ = std::fs::remove_file(&paths.record);
+    }
+
+    #[tokio::test]
+    async fn read_only_request_is_replayed_after_crash() {
+        let bridge = Arc::new(test_bridge(Config::default()));
+        let mut sidecar_rx = bridge.connect_fake_sidecar().await;
+
+        let caller = Arc::clone(&bridge);
+        let hover = tokio::spawn(async move { caller.request("hover", None).await });
+
+        let first = sidecar_rx.recv().await.unwrap();
+        assert_eq!(first.method, "hover");
+        crash_and_recover(&bridge).await;
+
+        let replay = sidecar_rx.recv().await.unwrap();
+        assert_eq!(replay.method, "hover");
+        assert_ne!(replay.id, first.id);
+        Bridge::dispatch_response(
+            &bridge.pending,
+            Response {
+                jsonrpc: "2.0".into(),
+                id: replay.id,
+                result: Some(serde_json::json!({"contents": "fun main()"})),
+                error: None,
+            },
+        )
+        .await;
+
+        let result = hover.await.unwrap().unwrap();
+        assert_eq!(result["contents"], "fun main()");
+    }
+
+    #[tokio::test]
+    async fn timed_out_request_is_cancelled_in_the_sidecar() {
+        let bridge = Arc::new(test_bridge(Config::default()));
+        let mut sidecar_rx = bridge.connect_fake_sidecar().await;
+
+        let caller = Arc::clone(&bridge);
+        let analyze = tokio::spawn(async move {
+            caller
+                .request_with_timeout("analyzeAll", None, Duration::from_millis(50))
+                .await
+        });
+
+        let request = sidecar_rx.recv().await.unwrap();
+        assert_eq!(request.method, "analyzeAll");
+        assert!(analyze.await.unwrap().is_err());
+
+        let cancel = sidecar_rx.recv().await.unwrap();
+        assert_eq!(cancel.method, "$/cancelRequest");
+        assert_eq!(cancel.params, Some(serde_json::json!({ "id": request.id })));
+        assert!(bridge.pending.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn streaming_request_forwards_partial_results_until_done() {
+        let bridge = Arc::new(test_bridge(Config::default()));
+        let mut sidecar_rx = bridge.connect_fake_sidecar().await;
+        let (batch_tx, mut batch_rx) = mpsc::unbounded_channel();
+
+        let caller = Arc::clone(&bridge);
+        let references = tokio::spawn(async move {
+            caller
+                .request_streaming(
+                    "references",
+                    serde_json::json!({"uri": "file:///a.kt"}),
+                    batch_tx,
+                )
+                .await
+        });
+
+        let request = sidecar_rx.recv().await.unwrap();
+        let params = request.params.clone().unwrap();
+        let token = params["partialResultToken"].as_str().unwrap().to_string();
+        assert_eq!(params["uri"], "file:///a.kt");
+
+        let batch = serde_json::json!({"locations": [{"uri": "file:///b.kt", "line": 3}]});
+        Bridge::dispatch_notification(
+            &bridge.partial_results,
+            Request::notification(
+                "$/partialResult",
+                Some(serde_json::json!({"token": token, "value": batch})),
+            ),
+        )
+        .await;
+        bridge
+            .reply_fake_sidecar(&request, serde_json::json!({"locations": []}))
+            .await;
+
+        let result = references.await.unwrap().unwrap();
+        assert_eq!(result, serde_json::json!({"locations": []}));
+        assert_eq!(batch_rx.recv().await, Some(batch));
+        assert_eq!(
+            batch_rx.recv().await,
+            None,
+            "channel closes with the request"
+        );
+        assert!(bridge.partial_results.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn streaming_request_is_not_replayed_after_crash() {
+        let bridge = Arc::new(test_bridge(Config::default()));
+        let mut sidecar_rx = bridge.connect_fake_sidecar().await;
+
+        let caller = Arc::clone(&bridge);
+        let (batch_tx, _batch_rx) = mpsc::unbounded_channel();
+        let references = tokio::spawn(async move {
+            caller
+                .request_streaming("references", serde_json::json!({}), batch_tx)
+                .await
+        });
+
+        assert_eq!(sidecar_rx.recv().await.unwrap().method, "references");
+        crash_and_recover(&bridge).await;
+
+        let err = references.await.unwrap().unwrap_err();
+        assert!(
+            matches!(err, Error::Bridge(BridgeError::Crashed(_))),
+            "expected Crashed, got: {:?}",
+            err
+        );
+        assert!(
+            sidecar_rx.try_recv().is_err(),
+            "references must not be re-sent"
+        );
+        assert!(bridge.partial_results.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn mutating_request_is_not_replayed_after_crash() {
+        let bridge = Arc::new(test_bridge(Config::default()));
+        let mut sidecar_rx = bridge.connect_fake_sidecar().await;
+
+        let caller = Arc::clone(&bridge);
+        let rename = tokio::spawn(async move { caller.request("rename", None).await });
+
+        assert_eq!(sidecar_rx.recv().await.unwrap().method, "rename");
+        crash_and_recover(&bridge).await;
+
+        let err = rename.await.unwrap().unwrap_err();
+        assert!(
+            matches!(err, Error::Bridge(BridgeError::Crashed(_))),
+            "expected Crashed, got: {:?}",
+            err
+        );
+        assert!(sidecar_rx.try_recv().is_err(), "rename must not be re-sent");
+    }
+
+    #[test]
+    fn initialize_params_forward_generated_source_roots() {
+        let params = InitParams {
+            project_root: "/project".into(),
+            classpath: vec!["/libs/room.jar".into()],
+            compiler_flags: Vec::new(),
+            source_roots: vec!["/project/src/main/kotlin".into()],
+            generated_source_roots: vec!["/project/build/generated/ksp/main/kotlin".into()],
+            classpath_sources: vec!["/libs/room-sources.jar".into()],
+            kapt_classpath: Vec::new(),
+        };
+
+        let json = params.to_request_params(&Config::default());
+        assert_eq!(
+            json["sourceRoots"],
+            serde_json::json!(["/project/src/main/kotlin"])
+        );
+        assert_eq!(
+            json["generatedSourceRoots"],
+            serde_json::json!(["/project/build/generated/ksp/main/kotlin"])
+        );
+        assert_eq!(
+            json["classpathSources"],
+            serde_json::json!(["/libs/room-sources.jar"])
+        );
+        assert_eq!(json["jdkHome"], "");
+        assert_eq!(
+            json["excludePaths"],
+            serde_json::json!(["**/build/**", "**/.gradle/**"])
+        );
+        assert_eq!(json["analysisScope"], "project");
+    }
+
+    #[test]
+    fn initialize_params_forward_the_kapt_classpath() {
+        let params = InitParams {
+            kapt_classpath: vec!["/project/build/tmp/kapt3/classes/main".into()],
+            ..InitParams::default()
+        };
+
+        let json = params.to_request_params(&Config::default());
+        assert_eq!(
+            json["kaptClasspath"],
+            serde_json::json!(["/project/build/tmp/kapt3/classes/main"])
+        );
+        assert_eq!(
+            InitParams::default().to_request_params(&Config::default())["kaptClasspath"],
+            serde_json::json!([])
+        );
+    }
+
+    #[test]
+    fn sidecar_info_parses_initialize_result() {
+        let info = SidecarInfo::from_initialize_result(&serde_json::json!({
+            "success": true,
+            "kotlinVersion": "2.2.21",
+            "classpathResolved": false,
+            "missingClasspathEntries": 2,
+            "unsupportedFeatures": ["typeHierarchy/prepare"],
+        }));
+        assert_eq!(info.kotlin_version.as_deref(), Some("2.2.21"));
+        assert!(!info.classpath_resolved);
+        assert_eq!(info.missing_classpath_entries, 2);
+        assert!(!info.supports("typeHierarchy/prepare"));
+        assert!(info.supports("hover"));
+
+        let legacy = SidecarInfo::from_initialize_result(&serde_json::json!({ "success": true }));
+        assert!(legacy.classpath_resolved);
+        assert!(legacy.supports("typeHierarchy/prepare"));
+    }
+
+    #[test]
+    fn sidecar_info_reports_kotlin_version_mismatch() {
+        let info = SidecarInfo::from_initialize_result(&serde_json::json!({
+            "kotlinVersion": "2.2.21",
+        }));
+        assert_eq!(info.kotlin_version_mismatch(Some("2.2.21")), None);
+        assert_eq!(info.kotlin_version_mismatch(Some("2.2.0")), None);
+        assert_eq!(info.kotlin_version_mismatch(None), None);
+        let message = info.kotlin_version_mismatch(Some("2.1.0")).unwrap();
+        assert!(message.contains("2.1.0") && message.contains("2.2.21"));
+    }
+
+    #[tokio::test]
+    async fn unsupported_methods_fail_without_reaching_sidecar() {
+        let bridge = Arc::new(test_bridge(Config::default()));
+        let mut sidecar_rx = bridge.connect_fake_sidecar().await;
+        *bridge.sidecar_info.lock().await = Some(SidecarInfo::from_initialize_result(
+            &serde_json::json!({ "unsupportedFeatures": ["codeLens"] }),
+        ));
+
+        let result = bridge.request("codeLens", None).await;
+        assert!(matches!(
+            result,
+            Err(Error::Bridge(BridgeError::Unsupported(ref method))) if method == "codeLens"
+        ));
+        assert!(sidecar_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn health_monitor_recovers_from_transient_stall() {
+        let mut monitor = HealthMonitor::default();
+        let failed = PingOutcome::Failed("timeout".into());
+
+        for _ in 1..MAX_PING_FAILURES {
+            assert_eq!(monitor.on_ping(&failed), HealthAction::Continue);
+        }
+        assert_eq!(monitor.on_ping(&failed), HealthAction::Degrade);
+        assert!(monitor.recovering);
+
+        assert_eq!(monitor.on_ping(&failed), HealthAction::Continue);
+        assert_eq!(
+            monitor.on_ping(&PingOutcome::Success(Duration::from_millis(5))),
+            HealthAction::Recover
+        );
+        assert!(!monitor.recovering);
+        assert_eq!(monitor.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn health_monitor_restarts_after_failed_recovery() {
+        let mut monitor = HealthMonitor::default();
+        let failed = PingOutcome::Failed("timeout".into());
+
+        for _ in 0..MAX_PING_FAILURES {
+            monitor.on_ping(&failed);
+        }
+        for _ in 1..MAX_RECOVERY_PROBES {
+            assert_eq!(monitor.on_ping(&failed), HealthAction::Continue);
+        }
+        assert_eq!(monitor.on_ping(&failed), HealthAction::Restart);
+    }
+
+    #[test]
+    fn health_monitor_restarts_immediately_when_disconnected() {
+        let mut monitor = HealthMonitor::default();
+        assert_eq!(
+            monitor.on_ping(&PingOutcome::Disconnected("request channel closed")),
+            HealthAction::Restart
+        );
+    }
+
+    #[test]
+    fn ping_latency_keeps_a_rolling_window() {
+        let mut latency = PingLatency::default();
+        assert_eq!(latency.stats().average_ms, None);
+
+        for ms in 1..=(PING_LATENCY_WINDOW as u64 + 2) {
+            latency.record(ms * 10);
+        }
+
+        let stats = latency.stats();
+        assert_eq!(stats.samples, PING_LATENCY_WINDOW);
+        assert_eq!(stats.last_ms, Some(120));
+        assert_eq!(stats.average_ms, Some(75.0));
+    }
+
+    #[test]
+    fn ping_latency_detects_upward_trend() {
+        let mut latency = PingLatency::default();
+        for _ in 0..PING_LATENCY_WINDOW {
+            latency.record(50);
+        }
+        assert!(!latency.is_trending_up());
+
+        for _ in 0..PING_LATENCY_WINDOW / 2 {
+            latency.record(3000);
+        }
+        assert!(latency.is_trending_up());
+    }
+
     #[test]
     fn bridge_stores_selected_runtime_for_restarts() {
         let runtime = SidecarRuntime {
@@ -969,4 +2732,76 @@ mod tests {
 
         assert_eq!(bridge.runtime, runtime);
     }
+
+    #[test]
+    fn java_lookup_is_platform_aware() {
+        assert_eq!(java_executable_name(false), "java");
+        assert_eq!(java_executable_name(true), "java.exe");
+        assert_eq!(path_lookup_command(false), "which");
+        assert_eq!(path_lookup_command(true), "where");
+    }
+
+    fn fake_jdk(root: &Path, name: &str) -> (PathBuf, PathBuf) {
+        let home = root.join(name);
+        std::fs::create_dir_all(home.join("bin")).unwrap();
+        let java = home.join("bin").join(java_executable_name(cfg!(windows)));
+        std::fs::write(&java, "").unwrap();
+        (home, java)
+    }
+
+    #[test]
+    fn find_java_prefers_configured_java_home() {
+        let dir = tempfile::tempdir().unwrap();
+        let (config_home, config_java) = fake_jdk(dir.path(), "config");
+        let (ls_home, ls_java) = fake_jdk(dir.path(), "ls");
+        let (env_home, env_java) = fake_jdk(dir.path(), "env");
+        let path_java = dir.path().join("path-java");
+
+        let resolve = |configured: Option<&str>, ls: Option<&Path>, env: Option<&Path>| {
+            find_java_with(
+                configured,
+                ls.map(|p| p.to_string_lossy().to_string()),
+                env.map(|p| p.to_string_lossy().to_string()),
+                || Some(path_java.clone()),
+            )
+            .unwrap()
+        };
+
+        let configured = config_home.to_string_lossy().to_string();
+        assert_eq!(
+            resolve(Some(&configured), Some(&ls_home), Some(&env_home)),
+            config_java
+        );
+        assert_eq!(resolve(None, Some(&ls_home), Some(&env_home)), ls_java);
+        assert_eq!(resolve(None, None, Some(&env_home)), env_java);
+        assert_eq!(resolve(None, None, None), path_java);
+    }
+
+    #[test]
+    fn find_java_accepts_executable_path_and_skips_invalid_setting() {
+        let dir = tempfile::tempdir().unwrap();
+        let (env_home, env_java) = fake_jdk(dir.path(), "env");
+        let (_, config_java) = fake_jdk(dir.path(), "config");
+
+        let java = find_java_with(
+            Some(&config_java.to_string_lossy()),
+            None,
+            Some(env_home.to_string_lossy().to_string()),
+            || None,
+        )
+        .unwrap();
+        assert_eq!(java, config_java);
+
+        let missing = dir.path().join("missing-jdk");
+        let java = find_java_with(
+            Some(&missing.to_string_lossy()),
+            None,
+            Some(env_home.to_string_lossy().to_string()),
+            || None,
+        )
+        .unwrap();
+        assert_eq!(java, env_java);
+
+        assert!(find_java_with(None, None, None, || None).is_err());
+    }
 }
@@ -1,4 +1,6 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default, rename_all = "camelCase")]
@@ -6,9 +8,90 @@ pub struct Config {
     pub java_home: Option<String>,
     pub compiler_flags: Vec<String>,
     pub formatting_tool: FormattingTool,
-    pub formatting_style: String,
+    /// ktfmt style; when unset, derived from the project's `kotlin.code.style`.
+    pub formatting_style: Option<String>,
     pub formatting_path: Option<String>,
     pub sidecar_max_memory: String,
+    /// Explicit sidecar jar, taking priority over discovered runtimes.
+    pub sidecar_jar_path: Option<String>,
+    /// Documents larger than this get lexical-only treatment.
+    pub max_file_size_bytes: u64,
+    /// Analyze files outside the resolved source roots (e.g. under `build/`).
+    pub analyze_outside_source_roots: bool,
+    /// Globs (absolute or workspace-relative) excluded from diagnostics and
+    /// symbol search.
+    pub exclude_paths: Vec<String>,
+    /// Source directories whose files are never edited, relative to the
+    /// project root unless absolute (e.g. sources of an included build).
+    /// Rename and code actions are withheld there, and their diagnostics are
+    /// shown as information.
+    pub read_only_source_roots: Vec<String>,
+    /// Extra source directories merged into the resolved project, relative to
+    /// the project root unless absolute.
+    pub additional_source_roots: Vec<String>,
+    /// Extra jars or class directories (globs allowed) appended to the
+    /// resolved classpath.
+    pub additional_classpath: Vec<String>,
+    /// The annotation processing classpath (kapt or KSP jars and class
+    /// directories, globs allowed), loaded by the sidecar next to the
+    /// project classpath so previously generated classes resolve.
+    pub kapt_classpath: Vec<String>,
+    /// Simple-name globs of classes annotation processors generate, like
+    /// `*Binding`. Unresolved references to them are warnings instead of
+    /// errors until a build generates them.
+    pub generated_class_patterns: Vec<String>,
+    /// Start the sidecar stdlib-only without waiting for project resolution,
+    /// and upgrade it to the full classpath once resolution completes.
+    pub fast_start: bool,
+    /// Whether the sidecar indexes the whole project or only open files.
+    pub analysis_scope: AnalysisScope,
+    /// Which files find references searches.
+    pub references_scope: ReferencesScope,
+    /// Stop find references after this many locations. Unlimited when unset.
+    pub references_limit: Option<usize>,
+    /// Diagnostic code to severity (`error`, `warning`, `info`, `hint`, or
+    /// `none` to suppress).
+    pub diagnostic_severity_overrides: HashMap<String, String>,
+    /// Individual LSP features that can be switched off.
+    pub features: Features,
+    /// Whether diagnostics are pushed, pulled by the client, or both. Unset
+    /// pulls when the client supports it and pushes otherwise. Read once,
+    /// in `initialize`.
+    pub diagnostics_mode: Option<DiagnosticsMode>,
+    /// How definitions into library jars are handed to the editor.
+    pub library_source_uris: LibrarySourceUris,
+    /// Have Gradle resolve the sources jars of classpath libraries for hover
+    /// documentation, downloading any it does not have yet.
+    pub download_library_sources: bool,
+    /// Complete dependency versions in `build.gradle.kts` from the local
+    /// Maven repository and Gradle module cache.
+    pub build_script_completion: bool,
+    /// Decline formatting when the client's configuration formats Kotlin
+    /// with something else, so the two do not compete over the same file.
+    pub suppress_formatting_if_other_formatter: bool,
+    /// Don't warn when the project's Kotlin version differs from the one the
+    /// sidecar analyzes with.
+    pub suppress_version_mismatch_warning: bool,
+    /// Lint open documents with ktlint and publish its findings next to the
+    /// compiler's diagnostics.
+    pub enable_lint_diagnostics: bool,
+    /// Format the document in `willSaveWaitUntil`.
+    pub format_on_save: bool,
+    /// Organize imports in `willSaveWaitUntil`, before formatting.
+    pub organize_imports_on_save: bool,
+    /// Also run the save actions for auto-saves (after a delay or on focus
+    /// loss), not just explicit saves.
+    pub save_actions_on_auto_save: bool,
+    /// Append every JSON-RPC message exchanged with the sidecar to this file
+    /// as newline-delimited JSON. A debugging aid; nothing is redacted.
+    pub sidecar_trace_file: Option<String>,
+    /// Run the sidecar as a daemon per project root that survives server
+    /// restarts, reconnecting over a Unix domain socket. Unix only.
+    pub persistent_sidecar: bool,
+    /// Stop the sidecar after this many milliseconds without requests while
+    /// no documents are open, starting it again on the next request.
+    /// Disabled when unset.
+    pub sidecar_idle_shutdown_ms: Option<u64>,
     pub trace_server: TraceLevel,
 }
 
@@ -18,42 +101,196 @@ impl Default for Config {
             java_home: None,
             compiler_flags: Vec::new(),
             formatting_tool: FormattingTool::Ktfmt,
-            formatting_style: "google".into(),
+            formatting_style: None,
             formatting_path: None,
             sidecar_max_memory: "512m".into(),
+            sidecar_jar_path: None,
+            max_file_size_bytes: 4 * 1024 * 1024,
+            analyze_outside_source_roots: false,
+            exclude_paths: vec!["**/build/**".into(), "**/.gradle/**".into()],
+            read_only_source_roots: Vec::new(),
+            additional_source_roots: Vec::new(),
+            additional_classpath: Vec::new(),
+            kapt_classpath: Vec::new(),
+            generated_class_patterns: [
+                "*Binding",
+                "Dagger*",
+                "Hilt_*",
+                "*_Factory",
+                "*_MembersInjector",
+                "*_Impl",
+                "*JsonAdapter",
+                "*Directions",
+            ]
+            .map(String::from)
+            .to_vec(),
+            fast_start: false,
+            analysis_scope: AnalysisScope::Project,
+            references_scope: ReferencesScope::All,
+            references_limit: None,
+            diagnostic_severity_overrides: HashMap::new(),
+            features: Features::default(),
+            diagnostics_mode: None,
+            library_source_uris: LibrarySourceUris::File,
+            download_library_sources: false,
+            build_script_completion: false,
+            suppress_formatting_if_other_formatter: false,
+            suppress_version_mismatch_warning: false,
+            enable_lint_diagnostics: false,
+            format_on_save: false,
+            organize_imports_on_save: false,
+            save_actions_on_auto_save: false,
+            sidecar_trace_file: None,
+            persistent_sidecar: false,
+            sidecar_idle_shutdown_ms: None,
             trace_server: TraceLevel::Off,
         }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum FormattingTool {
+    #[default]
     Ktfmt,
     Ktlint,
     None,
 }
 
-impl Default for FormattingTool {
+/// How much of the project the sidecar indexes. `OpenFiles` trades
+/// cross-file features (references, workspace symbols, project-wide
+/// diagnostics) for faster startup and lower memory in large repositories.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum AnalysisScope {
+    OpenFiles,
+    #[default]
+    Project,
+}
+
+/// Files find references reports matches in. `ExcludeTests` drops matches
+/// under test source sets; `CurrentModule` keeps only matches in the module
+/// of the file the search started from.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ReferencesScope {
+    #[default]
+    All,
+    ExcludeTests,
+    CurrentModule,
+}
+
+/// URIs used for decompiled library sources. `File` writes each class to a
+/// read-only temp file any editor can open; `KotlinAnalyzer` uses stable
+/// `kotlin-analyzer:` URIs for clients that fetch their content from the server.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LibrarySourceUris {
+    #[default]
+    File,
+    KotlinAnalyzer,
+}
+
+/// Per-feature switches; every feature is enabled unless set to `false`.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Features {
+    pub hover: bool,
+    pub completion: bool,
+    pub signature_help: bool,
+    pub code_actions: bool,
+    pub code_lens: bool,
+    pub inlay_hints: bool,
+    pub semantic_tokens: bool,
+    pub call_hierarchy: bool,
+    pub document_links: bool,
+    pub folding_range: bool,
+}
+
+impl Default for Features {
     fn default() -> Self {
-        Self::Ktfmt
+        Self {
+            hover: true,
+            completion: true,
+            signature_help: true,
+            code_actions: true,
+            code_lens: true,
+            inlay_hints: true,
+            semantic_tokens: true,
+            call_hierarchy: true,
+            document_links: true,
+            folding_range: true,
+        }
+    }
+}
+
+/// An LSP feature that [`Features`] can switch off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    Hover,
+    Completion,
+    SignatureHelp,
+    CodeActions,
+    CodeLens,
+    InlayHints,
+    SemanticTokens,
+    CallHierarchy,
+    DocumentLinks,
+    FoldingRange,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 10] = [
+        Feature::Hover,
+        Feature::Completion,
+        Feature::SignatureHelp,
+        Feature::CodeActions,
+        Feature::CodeLens,
+        Feature::InlayHints,
+        Feature::SemanticTokens,
+        Feature::CallHierarchy,
+        Feature::DocumentLinks,
+        Feature::FoldingRange,
+    ];
+}
+
+impl Features {
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::Hover => self.hover,
+            Feature::Completion => self.completion,
+            Feature::SignatureHelp => self.signature_help,
+            Feature::CodeActions => self.code_actions,
+            Feature::CodeLens => self.code_lens,
+            Feature::InlayHints => self.inlay_hints,
+            Feature::SemanticTokens => self.semantic_tokens,
+            Feature::CallHierarchy => self.call_hierarchy,
+            Feature::DocumentLinks => self.document_links,
+            Feature::FoldingRange => self.folding_range,
+        }
     }
 }
 
-#[derive(Debug, Clone, Deserialize, PartialEq)]
+/// How diagnostics reach the client: `Push` publishes them with
+/// `textDocument/publishDiagnostics`, `Pull` answers `textDocument/diagnostic`,
+/// and `Both` publishes and also answers pulls from clients that support them.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DiagnosticsMode {
+    Push,
+    Pull,
+    Both,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum TraceLevel {
+    #[default]
     Off,
     Messages,
     Verbose,
 }
 
-impl Default for TraceLevel {
-    fn default() -> Self {
-        Self::Off
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,8 +301,12 @@ mod tests {
         assert!(config.java_home.is_none());
         assert!(config.compiler_flags.is_empty());
         assert_eq!(config.formatting_tool, FormattingTool::Ktfmt);
-        assert_eq!(config.formatting_style, "google");
+        assert_eq!(config.formatting_style, None);
         assert_eq!(config.sidecar_max_memory, "512m");
+        assert_eq!(config.max_file_size_bytes, 4 * 1024 * 1024);
+        assert_eq!(config.exclude_paths, vec!["**/build/**", "**/.gradle/**"]);
+        assert_eq!(config.analysis_scope, AnalysisScope::Project);
+        assert_eq!(config.references_scope, ReferencesScope::All);
         assert_eq!(config.trace_server, TraceLevel::Off);
     }
 
@@ -83,7 +324,7 @@ mod tests {
         assert_eq!(config.java_home, Some("/usr/lib/jvm/java-17".into()));
         assert_eq!(config.compiler_flags, vec!["-Xcontext-parameters"]);
         assert_eq!(config.formatting_tool, FormattingTool::Ktlint);
-        assert_eq!(config.formatting_style, "android");
+        assert_eq!(config.formatting_style.as_deref(), Some("android"));
         assert_eq!(config.sidecar_max_memory, "1g");
         assert_eq!(config.trace_server, TraceLevel::Verbose);
     }
@@ -97,6 +338,143 @@ mod tests {
         assert_eq!(config.formatting_tool, FormattingTool::Ktfmt);
     }
 
+    #[test]
+    fn test_parse_analysis_scope() {
+        let config: Config = serde_json::from_str(r#"{"analysisScope": "openFiles"}"#).unwrap();
+        assert_eq!(config.analysis_scope, AnalysisScope::OpenFiles);
+        let config: Config = serde_json::from_str(r#"{"analysisScope": "project"}"#).unwrap();
+        assert_eq!(config.analysis_scope, AnalysisScope::Project);
+    }
+
+    #[test]
+    fn test_parse_references_scope() {
+        let config: Config =
+            serde_json::from_str(r#"{"referencesScope": "excludeTests"}"#).unwrap();
+        assert_eq!(config.references_scope, ReferencesScope::ExcludeTests);
+        let config: Config =
+            serde_json::from_str(r#"{"referencesScope": "currentModule"}"#).unwrap();
+        assert_eq!(config.references_scope, ReferencesScope::CurrentModule);
+    }
+
+    #[test]
+    fn test_parse_diagnostics_mode() {
+        let config: Config = serde_json::from_str(r#"{"diagnosticsMode": "pull"}"#).unwrap();
+        assert_eq!(config.diagnostics_mode, Some(DiagnosticsMode::Pull));
+        let config: Config = serde_json::from_str(r#"{"diagnosticsMode": "both"}"#).unwrap();
+        assert_eq!(config.diagnostics_mode, Some(DiagnosticsMode::Both));
+        assert_eq!(Config::default().diagnostics_mode, None);
+    }
+
+    #[test]
+    fn test_parse_references_limit() {
+        let config: Config = serde_json::from_str(r#"{"referencesLimit": 500}"#).unwrap();
+        assert_eq!(config.references_limit, Some(500));
+        assert_eq!(Config::default().references_limit, None);
+    }
+
+    #[test]
+    fn test_parse_diagnostic_severity_overrides() {
+        let config: Config = serde_json::from_str(
+            r#"{"diagnosticSeverityOverrides": {"UNUSED_VARIABLE": "hint", "DEPRECATION": "none"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.diagnostic_severity_overrides.get("UNUSED_VARIABLE"),
+            Some(&"hint".to_string())
+        );
+        assert_eq!(config.diagnostic_severity_overrides.len(), 2);
+        assert!(Config::default().diagnostic_severity_overrides.is_empty());
+    }
+
+    #[test]
+    fn test_parse_features_defaults_missing_switches_to_enabled() {
+        let config: Config =
+            serde_json::from_str(r#"{"features": {"codeLens": false, "semanticTokens": false}}"#)
+                .unwrap();
+        assert!(!config.features.is_enabled(Feature::CodeLens));
+        assert!(!config.features.is_enabled(Feature::SemanticTokens));
+        assert!(config.features.is_enabled(Feature::Hover));
+        assert!(Feature::ALL
+            .iter()
+            .all(|&feature| Config::default().features.is_enabled(feature)));
+    }
+
+    #[test]
+    fn test_parse_annotation_processing_fields() {
+        let config: Config = serde_json::from_str(
+            r#"{"kaptClasspath": ["build/tmp/kapt3/classes/main"], "generatedClassPatterns": ["*Proxy"]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.kapt_classpath, vec!["build/tmp/kapt3/classes/main"]);
+        assert_eq!(config.generated_class_patterns, vec!["*Proxy"]);
+        assert!(Config::default()
+            .generated_class_patterns
+            .contains(&"*Binding".to_string()));
+    }
+
+    #[test]
+    fn test_parse_library_source_uris() {
+        let config: Config =
+            serde_json::from_str(r#"{"librarySourceUris": "kotlin-analyzer"}"#).unwrap();
+        assert_eq!(
+            config.library_source_uris,
+            LibrarySourceUris::KotlinAnalyzer
+        );
+        assert_eq!(
+            Config::default().library_source_uris,
+            LibrarySourceUris::File
+        );
+    }
+
+    #[test]
+    fn test_parse_save_actions() {
+        let config: Config = serde_json::from_str(
+            r#"{"formatOnSave": true, "organizeImportsOnSave": true, "saveActionsOnAutoSave": true}"#,
+        )
+        .unwrap();
+        assert!(config.format_on_save);
+        assert!(config.organize_imports_on_save);
+        assert!(config.save_actions_on_auto_save);
+        assert!(!Config::default().format_on_save);
+    }
+
+    #[test]
+    fn test_parse_download_library_sources() {
+        let config: Config = serde_json::from_str(r#"{"downloadLibrarySources": true}"#).unwrap();
+        assert!(config.download_library_sources);
+        assert!(!Config::default().download_library_sources);
+    }
+
+    #[test]
+    fn test_parse_build_script_completion() {
+        let config: Config = serde_json::from_str(r#"{"buildScriptCompletion": true}"#).unwrap();
+        assert!(config.build_script_completion);
+        assert!(!Config::default().build_script_completion);
+    }
+
+    #[test]
+    fn test_parse_suppress_version_mismatch_warning() {
+        let config: Config =
+            serde_json::from_str(r#"{"suppressVersionMismatchWarning": true}"#).unwrap();
+        assert!(config.suppress_version_mismatch_warning);
+        assert!(!Config::default().suppress_version_mismatch_warning);
+    }
+
+    #[test]
+    fn test_parse_suppress_formatting_if_other_formatter() {
+        let config: Config =
+            serde_json::from_str(r#"{"suppressFormattingIfOtherFormatter": true}"#).unwrap();
+        assert!(config.suppress_formatting_if_other_formatter);
+        assert!(!Config::default().suppress_formatting_if_other_formatter);
+    }
+
+    #[test]
+    fn test_parse_sidecar_idle_shutdown_ms() {
+        let config: Config = serde_json::from_str(r#"{"sidecarIdleShutdownMs": 600000}"#).unwrap();
+        assert_eq!(config.sidecar_idle_shutdown_ms, Some(600_000));
+        assert_eq!(Config::default().sidecar_idle_shutdown_ms, None);
+    }
+
     #[test]
     fn test_parse_empty_config() {
         let json = "{}";
@@ -0,0 +1,88 @@
+use serde_json::Value;
+
+/// Sections pulled with `workspace/configuration` to find another Kotlin
+/// formatter: the editor's per-language settings, as VS Code names them,
+/// and the settings of fwcd/kotlin-language-server's client extension.
+pub const EDITOR_SECTION: &str = "[kotlin]";
+pub const FWCD_SECTION: &str = "kotlin.languageServer";
+
+/// Whether a client, by its `clientInfo.name`, keeps settings under
+/// [`EDITOR_SECTION`] and [`FWCD_SECTION`]: VS Code and its forks. Other
+/// clients, Zed among them, have no such sections, so asking them finds
+/// nothing.
+pub fn has_editor_sections(client: &str) -> bool {
+    ["Visual Studio Code", "VSCodium", "Code - OSS", "Cursor"]
+        .iter()
+        .any(|name| client.starts_with(name))
+}
+
+/// Names another formatter the client is configured to use for Kotlin,
+/// given its answers for [`EDITOR_SECTION`] and [`FWCD_SECTION`]. Only the
+/// keys those sections define are read, so a client that answers every
+/// section with this server's own settings finds nothing.
+pub fn other_formatter(editor: &Value, fwcd: &Value) -> Option<String> {
+    let default_formatter = editor
+        .get("editor.defaultFormatter")
+        .or_else(|| editor.get("editor")?.get("defaultFormatter"))
+        .and_then(Value::as_str)
+        .filter(|formatter| !formatter.is_empty() && !formatter.contains("kotlin-analyzer"));
+    if let Some(formatter) = default_formatter {
+        return Some(formatter.to_string());
+    }
+    fwcd.get("enabled")
+        .and_then(Value::as_bool)
+        .filter(|&enabled| enabled)
+        .map(|_| "fwcd/kotlin-language-server".to_string())
+}
+
+/// The warning shown when the client formats Kotlin with something else.
+pub fn conflict_message(formatter: &str, formatting_suppressed: bool) -> String {
+    let consequence = if formatting_suppressed {
+        "kotlin-analyzer formatting is disabled (suppressFormattingIfOtherFormatter)."
+    } else {
+        "Expect competing formatting; disable one of them for Kotlin, \
+         or set suppressFormattingIfOtherFormatter."
+    };
+    format!("kotlin-analyzer: Kotlin is also configured to use {formatter}. {consequence}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn only_vs_code_clients_have_editor_sections() {
+        assert!(has_editor_sections("Visual Studio Code"));
+        assert!(has_editor_sections("Visual Studio Code - Insiders"));
+        assert!(has_editor_sections("VSCodium"));
+        assert!(!has_editor_sections("Zed"));
+        assert!(!has_editor_sections("Neovim"));
+    }
+
+    #[test]
+    fn other_formatter_reads_only_the_formatter_settings() {
+        assert_eq!(
+            other_formatter(
+                &json!({ "editor.defaultFormatter": "fwcd.kotlin" }),
+                &Value::Null
+            ),
+            Some("fwcd.kotlin".to_string())
+        );
+        assert_eq!(
+            other_formatter(&Value::Null, &json!({ "enabled": true })),
+            Some("fwcd/kotlin-language-server".to_string())
+        );
+        assert_eq!(
+            other_formatter(
+                &json!({ "editor": { "defaultFormatter": "kouros.kotlin-analyzer" } }),
+                &json!({ "enabled": false })
+            ),
+            None
+        );
+        // Our own settings echoed back for every section.
+        let own = json!({ "formattingTool": "ktlint", "enabled": "yes" });
+        assert_eq!(other_formatter(&own, &own), None);
+        assert_eq!(other_formatter(&Value::Null, &Value::Null), None);
+    }
+}
@@ -0,0 +1,459 @@
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::process::Command;
+use tokio::time;
+
+use crate::error::BridgeError;
+use crate::jsonrpc::{self, Request};
+
+/// How long a freshly spawned daemon may take to start listening.
+const DAEMON_START_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long a replaced daemon gets to exit after `shutdown` before it is killed.
+const DAEMON_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The notification a daemon opens every connection with, saying whether it
+/// is already serving another server.
+pub const HELLO_METHOD: &str = "daemon/hello";
+
+/// How long a daemon may take to send its hello.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A connection the daemon accepted.
+#[derive(Debug)]
+pub enum DaemonConnection {
+    /// The daemon serves this server from now on.
+    Ready(UnixStream),
+    /// The daemon is serving another server for the same project and closed
+    /// the connection; a regular sidecar should be started instead.
+    Busy,
+}
+
+/// The sidecar build a daemon runs. A daemon started with a different
+/// identity (e.g. by an older server, or with another Kotlin runtime) is
+/// replaced rather than reused.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DaemonIdentity {
+    pub server_version: String,
+    pub kotlin_version: Option<String>,
+    pub classpath: Vec<PathBuf>,
+    pub java_path: PathBuf,
+    pub max_memory: String,
+}
+
+/// Written next to the socket by the server that spawned the daemon.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DaemonRecord {
+    pub pid: u32,
+    #[serde(flatten)]
+    pub identity: DaemonIdentity,
+}
+
+/// Files of the daemon serving one project root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DaemonPaths {
+    pub socket: PathBuf,
+    pub record: PathBuf,
+    /// The daemon's stderr.
+    pub log: PathBuf,
+}
+
+impl DaemonPaths {
+    /// Paths in the per-user [`runtime_dir`], named after a hash of
+    /// `project_root` so they stay well within the Unix socket path limit.
+    pub fn for_project(project_root: &Path) -> std::io::Result<Self> {
+        Ok(Self::in_dir(&runtime_dir()?, project_root))
+    }
+
+    fn in_dir(dir: &Path, project_root: &Path) -> Self {
+        let stem = format!(
+            "kotlin-analyzer-{:016x}",
+            fnv1a(project_root.as_os_str().as_bytes())
+        );
+        Self {
+            socket: dir.join(format!("{stem}.sock")),
+            record: dir.join(format!("{stem}.json")),
+            log: dir.join(format!("{stem}.log")),
+        }
+    }
+
+    /// The record, when this user wrote it.
+    fn read_record(&self) -> Option<DaemonRecord> {
+        if !owned_by_current_user(&self.record) {
+            return None;
+        }
+        let text = std::fs::read_to_string(&self.record).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    fn remove(&self) {
+        let _ = std::fs::remove_file(&self.socket);
+        let _ = std::fs::remove_file(&self.record);
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/kotlin-analyzer`, or a `kotlin-analyzer-<uid>`
+/// directory in the temp directory without one. It is created with mode
+/// 0700 and refused unless this user owns it and no one else can enter it,
+/// since the sockets and pid records inside are trusted.
+fn runtime_dir() -> std::io::Result<PathBuf> {
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("kotlin-analyzer"),
+        None => std::env::temp_dir().join(format!("kotlin-analyzer-{}", current_uid())),
+    };
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+    let metadata = std::fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir()
+        || metadata.uid() != current_uid()
+        || metadata.permissions().mode() & 0o077 != 0
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!(
+                "{} is not a private directory owned by this user",
+                dir.display()
+            ),
+        ));
+    }
+    Ok(dir)
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail.
+    unsafe { libc::getuid() }
+}
+
+fn owned_by_current_user(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.uid() == current_uid())
+}
+
+/// 64-bit FNV-1a, so daemon file names stay the same across Rust releases.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Connects to the daemon for `paths` if one is listening and runs
+/// `identity`. A daemon running anything else, or one that does not say
+/// hello, is stopped, and stale files are cleared, so the caller can spawn a
+/// fresh one. A socket another user created is never connected to.
+pub async fn connect(paths: &DaemonPaths, identity: &DaemonIdentity) -> Option<DaemonConnection> {
+    if paths.socket.exists() && !owned_by_current_user(&paths.socket) {
+        tracing::warn!(
+            "ignoring persistent sidecar socket {} owned by another user",
+            paths.socket.display()
+        );
+        return None;
+    }
+    let Ok(stream) = UnixStream::connect(&paths.socket).await else {
+        paths.remove();
+        return None;
+    };
+    let record = paths.read_record();
+    if record
+        .as_ref()
+        .is_some_and(|record| &record.identity == identity)
+    {
+        match greeted(stream).await {
+            Ok(connection) => {
+                tracing::info!(
+                    "connected to persistent sidecar at {}",
+                    paths.socket.display()
+                );
+                return Some(connection);
+            }
+            Err(e) => {
+                tracing::warn!("replacing persistent sidecar: {}", e);
+                kill_daemon(paths).await;
+                return None;
+            }
+        }
+    }
+    tracing::info!(
+        "persistent sidecar at {} runs a different build, replacing it",
+        paths.socket.display()
+    );
+    stop(stream, paths, record.map(|record| record.pid)).await;
+    paths.remove();
+    None
+}
+
+/// Reads the hello the daemon sends on a new connection.
+async fn greeted(mut stream: UnixStream) -> Result<DaemonConnection, BridgeError> {
+    // The daemon writes nothing after its hello until it receives a request,
+    // so this reader cannot buffer any later message.
+    let mut reader = BufReader::new(&mut stream);
+    let hello = time::timeout(HELLO_TIMEOUT, jsonrpc::read_message(&mut reader, None)).await;
+    let busy = match hello {
+        Ok(Ok(Some(jsonrpc::Message::Notification(request)))) if request.method == HELLO_METHOD => {
+            request
+                .params
+                .as_ref()
+                .and_then(|params| params.get("busy"))
+                .and_then(serde_json::Value::as_bool)
+                .unwrap_or(false)
+        }
+        _ => {
+            return Err(BridgeError::SpawnFailed(
+                "persistent sidecar did not say hello".into(),
+            ))
+        }
+    };
+    Ok(if busy {
+        DaemonConnection::Busy
+    } else {
+        DaemonConnection::Ready(stream)
+    })
+}
+
+/// Asks a daemon to exit and waits for it to close the connection, killing
+/// it by pid when it does not.
+async fn stop(mut stream: UnixStream, paths: &DaemonPaths, pid: Option<u32>) {
+    let shutdown = Request::new(0, "shutdown", None);
+    if jsonrpc::write_message(&mut stream, &shutdown, None)
+        .await
+        .is_ok()
+    {
+        let mut sink = Vec::new();
+        if time::timeout(DAEMON_STOP_TIMEOUT, stream.read_to_end(&mut sink))
+            .await
+            .is_ok()
+        {
+            return;
+        }
+    }
+    if let Some(pid) = pid {
+        kill(pid, paths).await;
+    }
+}
+
+/// Kills the daemon for `paths`, e.g. when it stopped responding, and
+/// clears its files.
+pub async fn kill_daemon(paths: &DaemonPaths) {
+    if let Some(record) = paths.read_record() {
+        kill(record.pid, paths).await;
+    }
+    paths.remove();
+}
+
+/// Kills `pid` only once it is confirmed to be the daemon for `paths`: a
+/// record can outlive its daemon, and the pid may since have been reused.
+/// Pids 0 and 1 (and anything `kill` would read as negative) would signal
+/// a process group or init, so they are never killed.
+async fn kill(pid: u32, paths: &DaemonPaths) {
+    if pid <= 1 || i32::try_from(pid).is_err() {
+        return;
+    }
+    if !is_daemon(pid, paths).await {
+        tracing::warn!(
+            "not killing pid {}: it is not the persistent sidecar for {}",
+            pid,
+            paths.socket.display()
+        );
+        return;
+    }
+    let _ = Command::new("kill")
+        .arg("-9")
+        .arg(pid.to_string())
+        .status()
+        .await;
+}
+
+/// Whether `pid` runs with this daemon's `--socket` on its command line.
+async fn is_daemon(pid: u32, paths: &DaemonPaths) -> bool {
+    let Ok(output) = Command::new("ps")
+        .args(["-p", &pid.to_string(), "-o", "command="])
+        .output()
+        .await
+    else {
+        return false;
+    };
+    let command = String::from_utf8_lossy(&output.stdout);
+    output.status.success() && command.contains(&*paths.socket.to_string_lossy())
+}
+
+/// Starts a daemon with `command` (the usual sidecar command line) and
+/// connects once it listens. The daemon runs in its own process group with
+/// stderr going to `paths.log`, so it outlives this server.
+pub async fn spawn(
+    paths: &DaemonPaths,
+    identity: DaemonIdentity,
+    mut command: Command,
+) -> Result<DaemonConnection, BridgeError> {
+    let spawn_failed = |e: std::io::Error| BridgeError::SpawnFailed(e.to_string());
+    let log = std::fs::File::create(&paths.log).map_err(spawn_failed)?;
+    let mut child = command
+        .arg("--socket")
+        .arg(&paths.socket)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(log)
+        .kill_on_drop(false)
+        .process_group(0)
+        .spawn()
+        .map_err(spawn_failed)?;
+
+    let Some(pid) = child.id() else {
+        paths.remove();
+        return Err(BridgeError::SpawnFailed(format!(
+            "persistent sidecar exited right away; see {}",
+            paths.log.display()
+        )));
+    };
+    let record = DaemonRecord { pid, identity };
+    let json = serde_json::to_string(&record).unwrap_or_default();
+    std::fs::write(&paths.record, json).map_err(spawn_failed)?;
+    tracing::info!(
+        "started persistent sidecar (pid {}) at {}",
+        record.pid,
+        paths.socket.display()
+    );
+
+    let deadline = Instant::now() + DAEMON_START_TIMEOUT;
+    loop {
+        if let Ok(stream) = UnixStream::connect(&paths.socket).await {
+            return greeted(stream).await;
+        }
+        if let Ok(Some(status)) = child.try_wait() {
+            paths.remove();
+            return Err(BridgeError::SpawnFailed(format!(
+                "persistent sidecar exited ({status}); see {}",
+                paths.log.display()
+            )));
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill().await;
+            paths.remove();
+            return Err(BridgeError::SpawnFailed(format!(
+                "persistent sidecar did not listen on {} within {:?}",
+                paths.socket.display(),
+                DAEMON_START_TIMEOUT
+            )));
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Writes the hello a daemon opens a connection with, for fake daemons.
+#[cfg(test)]
+pub async fn say_hello(stream: &mut UnixStream, busy: bool) {
+    let hello = Request::notification(HELLO_METHOD, Some(serde_json::json!({ "busy": busy })));
+    jsonrpc::write_message(stream, &hello, None).await.unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+    use tokio::net::UnixListener;
+
+    fn identity(kotlin_version: &str) -> DaemonIdentity {
+        DaemonIdentity {
+            server_version: "0.4.3".into(),
+            kotlin_version: Some(kotlin_version.into()),
+            classpath: vec![PathBuf::from("sidecar.jar")],
+            java_path: PathBuf::from("/usr/bin/java"),
+            max_memory: "512m".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_reuses_matching_daemon_and_replaces_others() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = DaemonPaths::in_dir(dir.path(), Path::new("/work/app"));
+        assert_eq!(
+            paths,
+            DaemonPaths::in_dir(dir.path(), Path::new("/work/app"))
+        );
+        assert_ne!(
+            paths.socket,
+            DaemonPaths::in_dir(dir.path(), Path::new("/work/lib")).socket
+        );
+
+        // Nothing listening: stale files are cleared.
+        std::fs::write(&paths.socket, "").unwrap();
+        assert!(connect(&paths, &identity("2.2.21")).await.is_none());
+        assert!(!paths.socket.exists());
+
+        let listener = UnixListener::bind(&paths.socket).unwrap();
+        let record = DaemonRecord {
+            pid: 0,
+            identity: identity("2.2.21"),
+        };
+        std::fs::write(&paths.record, serde_json::to_string(&record).unwrap()).unwrap();
+        let daemon = tokio::spawn(async move {
+            let (mut reused, _) = listener.accept().await.unwrap();
+            say_hello(&mut reused, false).await;
+            let (mut busy, _) = listener.accept().await.unwrap();
+            say_hello(&mut busy, true).await;
+            (listener, reused)
+        });
+        assert!(matches!(
+            connect(&paths, &identity("2.2.21")).await,
+            Some(DaemonConnection::Ready(_))
+        ));
+        // A daemon serving another server refuses this one.
+        assert!(matches!(
+            connect(&paths, &identity("2.2.21")).await,
+            Some(DaemonConnection::Busy)
+        ));
+        let (listener, _reused) = daemon.await.unwrap();
+
+        // A daemon built for another Kotlin runtime is told to shut down.
+        let daemon = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(stream);
+            match jsonrpc::read_message(&mut reader, None).await.unwrap() {
+                Some(jsonrpc::Message::Notification(request)) => request.method,
+                other => panic!("expected a shutdown request, got {other:?}"),
+            }
+        });
+        assert!(connect(&paths, &identity("2.1.0")).await.is_none());
+        assert_eq!(daemon.await.unwrap(), "shutdown");
+        assert!(!paths.socket.exists());
+        assert!(!paths.record.exists());
+    }
+
+    #[test]
+    fn daemon_file_names_use_a_stable_hash() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        let paths = DaemonPaths::in_dir(Path::new("/run"), Path::new("/work/app"));
+        assert_eq!(
+            paths.socket,
+            Path::new("/run").join(format!("kotlin-analyzer-{:016x}.sock", fnv1a(b"/work/app")))
+        );
+    }
+
+    #[test]
+    fn runtime_dir_is_private() {
+        let dir = runtime_dir().unwrap();
+        let metadata = std::fs::metadata(&dir).unwrap();
+        assert_eq!(metadata.uid(), current_uid());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o700);
+    }
+
+    #[tokio::test]
+    async fn kill_spares_pids_that_are_not_the_daemon() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = DaemonPaths::in_dir(dir.path(), Path::new("/work/app"));
+        // Pid 0 would signal our own process group, and this test process
+        // is no daemon: neither may be killed.
+        kill(0, &paths).await;
+        kill(std::process::id(), &paths).await;
+        assert!(!is_daemon(std::process::id(), &paths).await);
+    }
+}
@@ -0,0 +1,280 @@
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionTextEdit, Position, Range, TextEdit,
+};
+
+/// A `group:artifact:` coordinate typed inside a string literal in a
+/// `dependencies { }` block, with the part of the version typed so far.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CoordinatePrefix {
+    pub group: String,
+    pub artifact: String,
+    pub version: String,
+    /// Column (UTF-16) where the version starts; completions replace from here.
+    pub version_start: u32,
+}
+
+/// Returns the dependency coordinate being typed at `position`, when the
+/// cursor is inside a `"group:artifact:..."` string within a `dependencies`
+/// block (including `buildscript { dependencies { } }`).
+pub fn coordinate_prefix(text: &str, position: Position) -> Option<CoordinatePrefix> {
+    let line = text.lines().nth(position.line as usize)?;
+    let mut column = 0;
+    let end = line
+        .char_indices()
+        .find(|(_, c)| {
+            let past = column >= position.character;
+            column += c.len_utf16() as u32;
+            past
+        })
+        .map_or(line.len(), |(i, _)| i);
+    let before = &line[..end];
+
+    // Inside a string: an odd number of quotes before the cursor.
+    if before.matches('"').count() % 2 == 0 {
+        return None;
+    }
+    let literal = &before[before.rfind('"')? + 1..];
+    let mut parts = literal.split(':');
+    let (group, artifact, version) = (parts.next()?, parts.next()?, parts.next()?);
+    let is_name = |part: &str| {
+        !part.is_empty()
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+    };
+    let is_version = version
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+'));
+    if parts.next().is_some() || !is_name(group) || !is_name(artifact) || !is_version {
+        return None;
+    }
+
+    let preceding_lines: usize = text
+        .split_inclusive('\n')
+        .take(position.line as usize)
+        .map(str::len)
+        .sum();
+    if !in_dependencies_block(&text[..preceding_lines + end]) {
+        return None;
+    }
+
+    Some(CoordinatePrefix {
+        group: group.to_string(),
+        artifact: artifact.to_string(),
+        version: version.to_string(),
+        version_start: position.character - version.encode_utf16().count() as u32,
+    })
+}
+
+/// Whether the end of `text` is nested in a block opened by `dependencies {`.
+/// Braces inside string literals and `//` comments are ignored.
+fn in_dependencies_block(text: &str) -> bool {
+    let mut blocks: Vec<&str> = Vec::new();
+    for line in text.lines() {
+        let mut in_string = false;
+        let mut previous = None;
+        for (i, c) in line.char_indices() {
+            match c {
+                '"' if previous != Some('\\') => in_string = !in_string,
+                '/' if !in_string && previous == Some('/') => break,
+                '{' if !in_string => {
+                    let opener = line[..i].trim_end();
+                    let name_start = opener
+                        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                        .map_or(0, |j| j + 1);
+                    blocks.push(&opener[name_start..]);
+                }
+                '}' if !in_string => {
+                    blocks.pop();
+                }
+                _ => {}
+            }
+            previous = Some(c);
+        }
+    }
+    blocks.contains(&"dependencies")
+}
+
+/// The Gradle module cache (`$GRADLE_USER_HOME/caches/modules-2/files-2.1`,
+/// defaulting to `~/.gradle`).
+pub fn gradle_module_cache() -> Option<PathBuf> {
+    let gradle_home = std::env::var_os("GRADLE_USER_HOME")
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME")
+                .or_else(|| std::env::var_os("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".gradle"))
+        })?;
+    Some(
+        gradle_home
+            .join("caches")
+            .join("modules-2")
+            .join("files-2.1"),
+    )
+}
+
+/// Versions of `group:artifact` already downloaded to the local Maven
+/// repository or the Gradle module cache, newest first. Nothing is fetched
+/// from the network.
+pub fn local_versions(
+    group: &str,
+    artifact: &str,
+    maven_repository: Option<&Path>,
+    gradle_cache: Option<&Path>,
+) -> Vec<String> {
+    let directories = [
+        maven_repository.map(|repo| repo.join(group.replace('.', "/")).join(artifact)),
+        gradle_cache.map(|cache| cache.join(group).join(artifact)),
+    ];
+    let mut versions: Vec<String> = directories
+        .iter()
+        .flatten()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().is_dir())
+        .filter_map(|entry| entry.file_name().to_str().map(str::to_string))
+        .collect();
+    versions.sort_by(|a, b| compare_versions(b, a));
+    versions.dedup();
+    versions
+}
+
+/// Orders versions by their dot- or dash-separated parts, numerically where
+/// both parts are numbers, so `1.10.0` sorts after `1.9.2`.
+fn compare_versions(a: &str, b: &str) -> Ordering {
+    let parts = |v: &str| {
+        v.split(['.', '-'])
+            .map(|part| part.to_string())
+            .collect::<Vec<_>>()
+    };
+    let (a_parts, b_parts) = (parts(a), parts(b));
+    for (x, y) in a_parts.iter().zip(&b_parts) {
+        let ordering = match (x.parse::<u64>(), y.parse::<u64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            // A release (`1.0`) sorts after its qualifiers (`1.0-RC1`).
+            (Ok(_), Err(_)) => Ordering::Greater,
+            (Err(_), Ok(_)) => Ordering::Less,
+            (Err(_), Err(_)) => x.cmp(y),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    b_parts.len().cmp(&a_parts.len()).then_with(|| a.cmp(b))
+}
+
+/// Completion items for the versions matching `prefix`, keeping the newest
+/// first; each replaces the typed part of the version.
+pub fn version_completion_items(
+    prefix: &CoordinatePrefix,
+    line: u32,
+    versions: &[String],
+) -> Vec<CompletionItem> {
+    let range = Range {
+        start: Position::new(line, prefix.version_start),
+        end: Position::new(
+            line,
+            prefix.version_start + prefix.version.encode_utf16().count() as u32,
+        ),
+    };
+    versions
+        .iter()
+        .filter(|version| version.starts_with(&prefix.version))
+        .enumerate()
+        .map(|(rank, version)| CompletionItem {
+            label: version.clone(),
+            kind: Some(CompletionItemKind::VALUE),
+            detail: Some(format!("{}:{}", prefix.group, prefix.artifact)),
+            sort_text: Some(format!("{rank:04}")),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                range,
+                new_text: version.clone(),
+            })),
+            ..Default::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BUILD_SCRIPT: &str = r#"plugins {
+    kotlin("jvm") version "2.0.0"
+}
+
+dependencies {
+    implementation("com.squareup.okio:okio:3.")
+    // "ignored { braces }"
+    testImplementation("org.junit.jupiter:junit-jupiter:")
+}
+
+val notADependency = "com.squareup.okio:okio:"
+"#;
+
+    #[test]
+    fn coordinate_prefix_requires_dependencies_block_and_open_string() {
+        assert_eq!(
+            coordinate_prefix(BUILD_SCRIPT, Position::new(5, 45)),
+            Some(CoordinatePrefix {
+                group: "com.squareup.okio".to_string(),
+                artifact: "okio".to_string(),
+                version: "3.".to_string(),
+                version_start: 43,
+            })
+        );
+        assert_eq!(
+            coordinate_prefix(BUILD_SCRIPT, Position::new(7, 56))
+                .map(|prefix| (prefix.artifact, prefix.version)),
+            Some(("junit-jupiter".to_string(), String::new()))
+        );
+        // Only `group:` typed so far.
+        assert_eq!(coordinate_prefix(BUILD_SCRIPT, Position::new(5, 38)), None);
+        // Outside `dependencies { }`.
+        assert_eq!(coordinate_prefix(BUILD_SCRIPT, Position::new(10, 45)), None);
+        // Inside `plugins { }`.
+        assert_eq!(coordinate_prefix(BUILD_SCRIPT, Position::new(1, 29)), None);
+    }
+
+    #[test]
+    fn local_versions_merges_maven_and_gradle_caches_newest_first() {
+        let maven = tempfile::tempdir().unwrap();
+        let gradle = tempfile::tempdir().unwrap();
+        for version in ["3.9.0", "3.10.0-RC1"] {
+            std::fs::create_dir_all(maven.path().join("com/squareup/okio/okio").join(version))
+                .unwrap();
+        }
+        for version in ["3.10.0", "3.9.0"] {
+            std::fs::create_dir_all(gradle.path().join("com.squareup.okio/okio").join(version))
+                .unwrap();
+        }
+
+        let versions = local_versions(
+            "com.squareup.okio",
+            "okio",
+            Some(maven.path()),
+            Some(gradle.path()),
+        );
+        assert_eq!(versions, vec!["3.10.0", "3.10.0-RC1", "3.9.0"]);
+        assert!(local_versions("org.example", "missing", Some(maven.path()), None).is_empty());
+
+        let prefix = coordinate_prefix(BUILD_SCRIPT, Position::new(5, 45)).unwrap();
+        let items = version_completion_items(&prefix, 5, &versions);
+        let labels: Vec<_> = items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, vec!["3.10.0", "3.10.0-RC1", "3.9.0"]);
+        assert_eq!(
+            items[0].text_edit,
+            Some(CompletionTextEdit::Edit(TextEdit {
+                range: Range {
+                    start: Position::new(5, 43),
+                    end: Position::new(5, 45),
+                },
+                new_text: "3.10.0".to_string(),
+            }))
+        );
+    }
+}
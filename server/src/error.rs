@@ -31,6 +31,23 @@ pub enum BridgeError {
 
     #[error("spawn failed: {0}")]
     SpawnFailed(String),
+
+    /// The sidecar is running but could not load the project classpath,
+    /// e.g. because an entry is a corrupt jar.
+    #[error("sidecar rejected the classpath: {0}")]
+    ClasspathRejected(String),
+
+    #[error("sidecar does not support '{0}'")]
+    Unsupported(String),
+
+    /// The sidecar has not yet received the document version a request
+    /// refers to; re-sending the text and retrying is safe.
+    #[error("content modified: {0}")]
+    ContentModified(String),
+
+    /// The client cancelled the work the request belonged to.
+    #[error("request cancelled")]
+    Cancelled,
 }
 
 #[derive(Debug, Error)]
@@ -50,6 +67,9 @@ pub enum ProjectError {
     #[error("gradle execution failed: {0}")]
     GradleFailed(String),
 
+    #[error("maven execution failed: {0}")]
+    MavenFailed(String),
+
     #[error("classpath extraction failed: {0}")]
     ClasspathExtraction(String),
 
@@ -0,0 +1,165 @@
+use serde_json::Value;
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind};
+
+/// Computes folds for `//region Name` ... `//endregion` comment pairs. Regions
+/// may nest; an `//endregion` without an open region and regions left open at
+/// the end of the file are ignored.
+pub fn region_folding_ranges(text: &str) -> Vec<FoldingRange> {
+    let mut open: Vec<(u32, Option<String>)> = Vec::new();
+    let mut ranges = Vec::new();
+
+    for (line, content) in text.lines().enumerate() {
+        let line = line as u32;
+        let Some(marker) = line_comment(content) else {
+            continue;
+        };
+        if let Some(name) = marker.strip_prefix("endregion") {
+            if !name.is_empty() && !name.starts_with(char::is_whitespace) {
+                continue;
+            }
+            if let Some((start_line, name)) = open.pop() {
+                if line > start_line {
+                    ranges.push(FoldingRange {
+                        start_line,
+                        start_character: None,
+                        end_line: line,
+                        end_character: None,
+                        kind: Some(FoldingRangeKind::Region),
+                        collapsed_text: name,
+                    });
+                }
+            }
+        } else if let Some(name) = marker.strip_prefix("region") {
+            if !name.is_empty() && !name.starts_with(char::is_whitespace) {
+                continue;
+            }
+            let name = name.trim();
+            open.push((line, (!name.is_empty()).then(|| name.to_string())));
+        }
+    }
+
+    ranges.sort_by_key(|range| range.start_line);
+    ranges
+}
+
+/// Returns the text after `//` when the line is only a line comment.
+fn line_comment(line: &str) -> Option<&str> {
+    Some(line.trim().strip_prefix("//")?.trim_start())
+}
+
+/// Parses sidecar `foldingRange` results (1-based lines).
+pub fn parse_folding_ranges(result: &Value) -> Vec<FoldingRange> {
+    let ranges_array = match result.get("ranges").and_then(|r| r.as_array()) {
+        Some(arr) => arr,
+        None => return Vec::new(),
+    };
+
+    ranges_array
+        .iter()
+        .filter_map(|range| {
+            let start_line = range.get("startLine")?.as_u64()?.saturating_sub(1) as u32;
+            let end_line = range.get("endLine")?.as_u64()?.saturating_sub(1) as u32;
+            if end_line <= start_line {
+                return None;
+            }
+            let kind = match range.get("kind").and_then(|k| k.as_str()) {
+                Some("comment") => Some(FoldingRangeKind::Comment),
+                Some("imports") => Some(FoldingRangeKind::Imports),
+                Some("region") => Some(FoldingRangeKind::Region),
+                _ => None,
+            };
+            Some(FoldingRange {
+                start_line,
+                start_character: None,
+                end_line,
+                end_character: None,
+                kind,
+                collapsed_text: None,
+            })
+        })
+        .collect()
+}
+
+/// Combines region folds with structural folds from the sidecar. A structural
+/// fold covering the same lines as a region (or another structural fold) is
+/// dropped, since editors show overlapping folds on one line as duplicates.
+pub fn merge_folding_ranges(
+    regions: Vec<FoldingRange>,
+    structural: Vec<FoldingRange>,
+) -> Vec<FoldingRange> {
+    let mut merged = regions;
+    for range in structural {
+        let duplicate = merged
+            .iter()
+            .any(|r| r.start_line == range.start_line && r.end_line == range.end_line);
+        if !duplicate {
+            merged.push(range);
+        }
+    }
+    merged.sort_by(|a, b| {
+        a.start_line
+            .cmp(&b.start_line)
+            .then(b.end_line.cmp(&a.end_line))
+    });
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn lines(ranges: &[FoldingRange]) -> Vec<(u32, u32)> {
+        ranges.iter().map(|r| (r.start_line, r.end_line)).collect()
+    }
+
+    #[test]
+    fn region_folding_ranges_handles_nesting_and_names() {
+        let text = "\
+class Foo {
+    //region Public API
+    fun a() {}
+    // region helpers
+    fun b() {}
+    //endregion
+    //endregion
+}
+";
+        let ranges = region_folding_ranges(text);
+        assert_eq!(lines(&ranges), vec![(1, 6), (3, 5)]);
+        assert_eq!(ranges[0].collapsed_text.as_deref(), Some("Public API"));
+        assert_eq!(ranges[1].collapsed_text.as_deref(), Some("helpers"));
+        assert_eq!(ranges[0].kind, Some(FoldingRangeKind::Region));
+    }
+
+    #[test]
+    fn region_folding_ranges_ignores_unmatched_markers() {
+        let text = "\
+//endregion
+//region open forever
+fun a() {}
+//regional is not a marker
+val x = 1 //region trailing comments are not markers
+";
+        assert!(region_folding_ranges(text).is_empty());
+        assert!(region_folding_ranges("").is_empty());
+    }
+
+    #[test]
+    fn merge_folding_ranges_dedupes_overlapping_ranges() {
+        let regions = region_folding_ranges("//region A\nfun a() {\n}\n//endregion\n");
+        let structural = parse_folding_ranges(&json!({
+            "ranges": [
+                { "startLine": 1, "endLine": 4 },
+                { "startLine": 2, "endLine": 3 },
+                { "startLine": 5, "endLine": 5 },
+                { "startLine": 2, "endLine": 3, "kind": "comment" }
+            ]
+        }));
+        assert_eq!(lines(&structural), vec![(0, 3), (1, 2), (1, 2)]);
+
+        let merged = merge_folding_ranges(regions, structural);
+        assert_eq!(lines(&merged), vec![(0, 3), (1, 2)]);
+        assert_eq!(merged[0].kind, Some(FoldingRangeKind::Region));
+    }
+}
@@ -0,0 +1,358 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::config::{Config, FormattingTool};
+
+/// An external formatter binary resolved from the configured tool and path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedFormatter {
+    pub tool: FormattingTool,
+    /// The binary name or path from the config that `path` was resolved from.
+    pub binary: String,
+    pub path: PathBuf,
+    pub version: Option<String>,
+}
+
+impl ResolvedFormatter {
+    /// Whether this is still the formatter `config` asks for, i.e. neither
+    /// `formattingTool` nor `formattingPath` changed since it was resolved.
+    pub fn matches(&self, config: &Config) -> bool {
+        self.tool == config.formatting_tool
+            && configured_binary(config).as_deref() == Some(self.binary.as_str())
+    }
+}
+
+/// Returns the binary name or path the config asks for, or `None` when
+/// formatting is disabled.
+pub fn configured_binary(config: &Config) -> Option<String> {
+    let default = match config.formatting_tool {
+        FormattingTool::Ktfmt => "ktfmt",
+        FormattingTool::Ktlint => "ktlint",
+        FormattingTool::None => return None,
+    };
+    Some(
+        config
+            .formatting_path
+            .clone()
+            .unwrap_or_else(|| default.to_string()),
+    )
+}
+
+/// Picks the ktfmt style: the configured one if set, otherwise `kotlinlang`
+/// for projects declaring `kotlin.code.style=official`, otherwise `google`.
+pub fn ktfmt_style<'a>(configured: Option<&'a str>, code_style: Option<&str>) -> &'a str {
+    match (configured, code_style) {
+        (Some(style), _) => style,
+        (None, Some("official")) => "kotlinlang",
+        (None, _) => "google",
+    }
+}
+
+/// Resolves the configured formatter to an absolute path and queries its
+/// version. Returns `Ok(None)` when formatting is disabled and `Err` with a
+/// user-facing message when the binary cannot be found.
+pub async fn resolve_formatter(config: &Config) -> Result<Option<ResolvedFormatter>, String> {
+    let Some(binary) = configured_binary(config) else {
+        return Ok(None);
+    };
+
+    let path = find_executable(&binary).ok_or_else(|| {
+        format!(
+            "kotlin-analyzer: formatter '{binary}' not found. Install it or set formattingPath; formatting is unavailable."
+        )
+    })?;
+    let version = query_version(&path).await;
+
+    tracing::info!(
+        tool = ?config.formatting_tool,
+        path = %path.display(),
+        version = version.as_deref().unwrap_or("unknown"),
+        "resolved formatter binary"
+    );
+
+    Ok(Some(ResolvedFormatter {
+        tool: config.formatting_tool.clone(),
+        binary,
+        path,
+        version,
+    }))
+}
+
+/// Locates an executable either by explicit path or by searching `PATH`.
+pub fn find_executable(binary: &str) -> Option<PathBuf> {
+    let candidate = Path::new(binary);
+    if candidate.components().count() > 1 || candidate.is_absolute() {
+        return candidate.is_file().then(|| candidate.to_path_buf());
+    }
+
+    let path_var = std::env::var_os("PATH")?;
+    let names = executable_names(binary, cfg!(windows));
+    std::env::split_paths(&path_var)
+        .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+/// File names to try for a bare binary name. Windows launchers are usually
+/// `.exe`, `.cmd`, or `.bat` files that must be named explicitly.
+fn executable_names(binary: &str, windows: bool) -> Vec<String> {
+    if !windows || Path::new(binary).extension().is_some() {
+        return vec![binary.to_string()];
+    }
+    ["exe", "cmd", "bat"]
+        .iter()
+        .map(|ext| format!("{binary}.{ext}"))
+        .chain(std::iter::once(binary.to_string()))
+        .collect()
+}
+
+/// How long a single formatter run may take before it is killed.
+pub const FORMAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pipes `text` through a formatter that reads stdin and writes the
+/// formatted source to stdout. Stdin is written from its own task while the
+/// exit is awaited, so a formatter that dies before reading all of a large
+/// file fails the call instead of blocking on a full pipe. A run longer
+/// than `timeout` is killed. A non-zero exit is an error carrying stderr.
+pub async fn run_formatter(
+    binary: &Path,
+    args: &[&str],
+    text: &str,
+    timeout: Duration,
+) -> Result<String, io::Error> {
+    run_with_stdin(binary, args, text, timeout, &[0]).await
+}
+
+/// Like [`run_formatter`], for tools that report findings through their
+/// exit status: any code in `exit_codes` counts as success.
+pub async fn run_with_stdin(
+    binary: &Path,
+    args: &[&str],
+    text: &str,
+    timeout: Duration,
+    exit_codes: &[i32],
+) -> Result<String, io::Error> {
+    let mut child = Command::new(binary)
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| io::Error::other("formatter stdin was not captured"))?;
+    let input = text.as_bytes().to_vec();
+    let writer = tokio::spawn(async move {
+        stdin.write_all(&input).await?;
+        stdin.shutdown().await
+    });
+
+    // Dropping the child on timeout kills it, which also unblocks the writer.
+    let output = match tokio::time::timeout(timeout, child.wait_with_output()).await {
+        Ok(output) => output?,
+        Err(_) => {
+            writer.abort();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("did not finish within {}s", timeout.as_secs()),
+            ));
+        }
+    };
+
+    if !output
+        .status
+        .code()
+        .is_some_and(|code| exit_codes.contains(&code))
+    {
+        writer.abort();
+        return Err(io::Error::other(format!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // A formatter that exits cleanly without reading all of its input would
+    // have formatted a truncated file.
+    match writer.await {
+        Ok(Ok(())) => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+        Ok(Err(e)) => Err(e),
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+async fn query_version(path: &Path) -> Option<String> {
+    let output = tokio::time::timeout(
+        Duration::from_secs(10),
+        Command::new(path)
+            .arg("--version")
+            .stdin(std::process::Stdio::null())
+            .output(),
+    )
+    .await
+    .ok()?
+    .ok()?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configured_binary_defaults_to_tool_name() {
+        let config = Config::default();
+        assert_eq!(configured_binary(&config).as_deref(), Some("ktfmt"));
+
+        let config = Config {
+            formatting_tool: FormattingTool::Ktlint,
+            ..Config::default()
+        };
+        assert_eq!(configured_binary(&config).as_deref(), Some("ktlint"));
+    }
+
+    #[test]
+    fn configured_binary_prefers_formatting_path() {
+        let config = Config {
+            formatting_path: Some("/opt/ktfmt/bin/ktfmt".into()),
+            ..Config::default()
+        };
+        assert_eq!(
+            configured_binary(&config).as_deref(),
+            Some("/opt/ktfmt/bin/ktfmt")
+        );
+    }
+
+    #[test]
+    fn configured_binary_is_none_when_disabled() {
+        let config = Config {
+            formatting_tool: FormattingTool::None,
+            ..Config::default()
+        };
+        assert_eq!(configured_binary(&config), None);
+    }
+
+    #[test]
+    fn resolved_formatter_matches_tool_and_path() {
+        let resolved = ResolvedFormatter {
+            tool: FormattingTool::Ktfmt,
+            binary: "ktfmt".into(),
+            path: PathBuf::from("/usr/bin/ktfmt"),
+            version: None,
+        };
+        assert!(resolved.matches(&Config::default()));
+
+        let moved = Config {
+            formatting_path: Some("/opt/ktfmt/bin/ktfmt".into()),
+            ..Config::default()
+        };
+        assert!(!resolved.matches(&moved));
+
+        let ktlint = Config {
+            formatting_tool: FormattingTool::Ktlint,
+            ..Config::default()
+        };
+        assert!(!resolved.matches(&ktlint));
+    }
+
+    #[test]
+    fn ktfmt_style_falls_back_to_project_code_style() {
+        assert_eq!(ktfmt_style(None, None), "google");
+        assert_eq!(ktfmt_style(None, Some("official")), "kotlinlang");
+        assert_eq!(ktfmt_style(None, Some("obsolete")), "google");
+        assert_eq!(ktfmt_style(Some("dropbox"), Some("official")), "dropbox");
+    }
+
+    #[test]
+    fn find_executable_rejects_missing_explicit_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("ktfmt");
+        assert_eq!(find_executable(&missing.to_string_lossy()), None);
+
+        std::fs::write(&missing, "").unwrap();
+        assert_eq!(find_executable(&missing.to_string_lossy()), Some(missing));
+    }
+
+    #[test]
+    fn executable_names_add_windows_extensions() {
+        assert_eq!(executable_names("ktfmt", false), vec!["ktfmt"]);
+        assert_eq!(
+            executable_names("ktlint", true),
+            vec!["ktlint.exe", "ktlint.cmd", "ktlint.bat", "ktlint"]
+        );
+        assert_eq!(executable_names("ktlint.cmd", true), vec!["ktlint.cmd"]);
+    }
+
+    #[tokio::test]
+    async fn resolve_formatter_reports_missing_binary() {
+        let config = Config {
+            formatting_path: Some("/nonexistent/kotlin-analyzer/ktfmt".into()),
+            ..Config::default()
+        };
+        let error = resolve_formatter(&config).await.unwrap_err();
+        assert!(error.contains("not found"), "unexpected message: {error}");
+    }
+
+    /// Writes an executable shell script standing in for a formatter.
+    #[cfg(unix)]
+    fn fake_formatter(dir: &Path, body: &str) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = dir.join("formatter");
+        std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_formatter_fails_fast_when_formatter_exits_without_reading() {
+        let dir = tempfile::tempdir().unwrap();
+        let formatter = fake_formatter(dir.path(), "echo broken >&2; exit 3");
+        // Far larger than a pipe buffer, so a blocking write would never finish.
+        let text = "val x = 1\n".repeat(1_000_000);
+
+        let started = std::time::Instant::now();
+        let error = run_formatter(&formatter, &["-"], &text, FORMAT_TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(
+            error.to_string().contains("broken"),
+            "unexpected error: {error}"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_formatter_returns_stdout_and_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        let formatter = fake_formatter(dir.path(), "cat");
+        let text = "val x = 1\n".repeat(100_000);
+        assert_eq!(
+            run_formatter(&formatter, &[], &text, FORMAT_TIMEOUT)
+                .await
+                .unwrap(),
+            text
+        );
+
+        let formatter = fake_formatter(dir.path(), "sleep 30");
+        let error = run_formatter(&formatter, &[], "", Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+}
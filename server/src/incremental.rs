@@ -0,0 +1,345 @@
+use serde_json::Value;
+use tower_lsp::lsp_types::{Diagnostic, NumberOrString};
+
+/// Edits changing more lines than this are re-analyzed in full.
+const MAX_EDIT_LINES: u32 = 50;
+
+/// Diagnostic codes the sidecar always computes for the whole file, even
+/// when it re-checks only a region.
+const WHOLE_FILE_CODES: &[&str] = &["UNUSED_IMPORT"];
+
+/// Keywords starting a declaration, after any modifiers.
+const DECLARATION_KEYWORDS: &[&str] = &[
+    "fun",
+    "val",
+    "var",
+    "class",
+    "interface",
+    "object",
+    "typealias",
+    "constructor",
+    "init",
+];
+
+/// Keywords that can precede a declaration keyword.
+const MODIFIERS: &[&str] = &[
+    "public",
+    "private",
+    "protected",
+    "internal",
+    "open",
+    "abstract",
+    "final",
+    "override",
+    "sealed",
+    "data",
+    "enum",
+    "annotation",
+    "inner",
+    "value",
+    "inline",
+    "suspend",
+    "operator",
+    "infix",
+    "tailrec",
+    "external",
+    "const",
+    "lateinit",
+    "companion",
+    "expect",
+    "actual",
+];
+
+/// The lines changed since a document was last analyzed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EditRegion {
+    /// First changed line (0-based); lines before it are unchanged.
+    pub start_line: u32,
+    /// End (exclusive) of the replaced lines in the analyzed text.
+    pub old_end_line: u32,
+    /// End (exclusive) of the replacement lines in the current text.
+    pub new_end_line: u32,
+}
+
+impl EditRegion {
+    fn line_delta(&self) -> i64 {
+        i64::from(self.new_end_line) - i64::from(self.old_end_line)
+    }
+
+    /// The changed lines in the current text as the 1-based, inclusive
+    /// `range` of an `analyze` request. A deletion covers the line it
+    /// joined onto.
+    pub fn analyze_range(&self) -> Value {
+        let start_line = self.start_line + 1;
+        serde_json::json!({
+            "startLine": start_line,
+            "endLine": self.new_end_line.max(start_line),
+        })
+    }
+}
+
+/// The edit turning `analyzed` into `current`, when it is small enough to
+/// re-analyze only the declarations it touches. Returns `None` for
+/// unchanged text, large edits, and structural edits: ones that touch
+/// unindented (top-level) lines, member declaration headers, or change the
+/// brace balance, since those can add, remove, or reshape declarations
+/// other parts of the file depend on.
+pub fn edit_region(analyzed: &str, current: &str) -> Option<EditRegion> {
+    let old: Vec<&str> = analyzed.split('\n').collect();
+    let new: Vec<&str> = current.split('\n').collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    if prefix == old.len() && prefix == new.len() {
+        return None;
+    }
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_changed = &old[prefix..old.len() - suffix];
+    let new_changed = &new[prefix..new.len() - suffix];
+    if old_changed.len().max(new_changed.len()) > MAX_EDIT_LINES as usize {
+        return None;
+    }
+
+    let top_level = |lines: &[&str]| {
+        lines
+            .iter()
+            .any(|line| !line.trim().is_empty() && !line.starts_with(char::is_whitespace))
+    };
+    let brace_balance = |lines: &[&str]| {
+        lines
+            .iter()
+            .flat_map(|line| line.chars())
+            .map(|c| match c {
+                '{' => 1,
+                '}' => -1,
+                _ => 0,
+            })
+            .sum::<i64>()
+    };
+    let touches_header = |lines: &[&str], end: usize| {
+        member_headers(lines)[prefix..end]
+            .iter()
+            .any(|&header| header)
+    };
+    if top_level(old_changed)
+        || top_level(new_changed)
+        || brace_balance(old_changed) != brace_balance(new_changed)
+        || touches_header(&old, old.len() - suffix)
+        || touches_header(&new, new.len() - suffix)
+    {
+        return None;
+    }
+
+    Some(EditRegion {
+        start_line: prefix as u32,
+        old_end_line: (old.len() - suffix) as u32,
+        new_end_line: (new.len() - suffix) as u32,
+    })
+}
+
+/// For each line, whether it declares a member: a declaration nested only
+/// in class, interface, or object bodies. Braces inside strings and
+/// comments are counted too, which at worst makes an edit structural.
+fn member_headers(lines: &[&str]) -> Vec<bool> {
+    // One entry per open brace: whether it opened a class body.
+    let mut blocks: Vec<bool> = Vec::new();
+    lines
+        .iter()
+        .map(|line| {
+            let keyword = declaration_keyword(line);
+            let header = keyword.is_some() && !blocks.is_empty() && blocks.iter().all(|&b| b);
+            let class_body = matches!(keyword, Some("class" | "interface" | "object"));
+            for c in line.chars() {
+                match c {
+                    '{' => blocks.push(class_body),
+                    '}' => {
+                        blocks.pop();
+                    }
+                    _ => {}
+                }
+            }
+            header
+        })
+        .collect()
+}
+
+/// The declaration keyword `line` starts with, after annotations and
+/// modifiers.
+fn declaration_keyword(line: &str) -> Option<&str> {
+    line.split_whitespace()
+        .find(|token| !token.starts_with('@') && !MODIFIERS.contains(token))
+        .and_then(|token| {
+            let word = token.split(|c: char| !c.is_alphanumeric()).next()?;
+            DECLARATION_KEYWORDS
+                .iter()
+                .copied()
+                .find(|&keyword| keyword == word)
+        })
+}
+
+/// The lines (0-based, inclusive) an `analyze` result was limited to, or
+/// `None` when the sidecar checked the whole file.
+pub fn reanalyzed_lines(result: &Value) -> Option<(u32, u32)> {
+    let region = result.get("region")?;
+    let line = |key| {
+        let line = region.get(key)?.as_u64()?;
+        u32::try_from(line).ok()?.checked_sub(1)
+    };
+    Some((line("startLine")?, line("endLine")?))
+}
+
+/// Merges the diagnostics of re-analyzed `lines` (in the current text) into
+/// `previous`, which were published for the text before `edit`. Previous
+/// diagnostics after the edit move with their lines; those on the edited or
+/// re-analyzed lines, and whole-file ones, are replaced by `fresh`.
+pub fn merge_diagnostics(
+    previous: &[Diagnostic],
+    edit: &EditRegion,
+    lines: (u32, u32),
+    fresh: Vec<Diagnostic>,
+) -> Vec<Diagnostic> {
+    let shift = |line: u32| {
+        if line >= edit.old_end_line {
+            (i64::from(line) + edit.line_delta()) as u32
+        } else {
+            line
+        }
+    };
+    let mut merged: Vec<Diagnostic> = previous
+        .iter()
+        .filter(|diagnostic| {
+            let line = diagnostic.range.start.line;
+            !(edit.start_line..edit.old_end_line).contains(&line) && !is_whole_file(diagnostic)
+        })
+        .map(|diagnostic| {
+            let mut diagnostic = diagnostic.clone();
+            diagnostic.range.start.line = shift(diagnostic.range.start.line);
+            diagnostic.range.end.line = shift(diagnostic.range.end.line);
+            diagnostic
+        })
+        .filter(|diagnostic| !(lines.0..=lines.1).contains(&diagnostic.range.start.line))
+        .chain(fresh)
+        .collect();
+    merged.sort_by_key(|diagnostic| {
+        (
+            diagnostic.range.start.line,
+            diagnostic.range.start.character,
+        )
+    });
+    merged
+}
+
+fn is_whole_file(diagnostic: &Diagnostic) -> bool {
+    matches!(&diagnostic.code, Some(NumberOrString::String(code)) if WHOLE_FILE_CODES.contains(&code.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::{Position, Range};
+
+    const SOURCE: &str =
+        "package demo\n\nfun a() {\n    val x: Int = \"\"\n}\n\nfun b() {\n    val y = 1\n}\n";
+
+    fn diagnostic(line: u32, code: &str) -> Diagnostic {
+        Diagnostic {
+            range: Range::new(Position::new(line, 4), Position::new(line, 8)),
+            code: Some(NumberOrString::String(code.to_string())),
+            message: code.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn edit_region_rejects_structural_edits() {
+        let edited = SOURCE.replace("val y = 1", "val y = 1\n    val z = 2");
+        assert_eq!(
+            edit_region(SOURCE, &edited),
+            Some(EditRegion {
+                start_line: 8,
+                old_end_line: 8,
+                new_end_line: 9,
+            })
+        );
+        assert_eq!(edit_region(SOURCE, SOURCE), None);
+        // Renaming a top-level function.
+        assert_eq!(edit_region(SOURCE, &SOURCE.replace("fun b", "fun c")), None);
+        // Opening a block without closing it.
+        assert_eq!(
+            edit_region(SOURCE, &SOURCE.replace("val y = 1", "if (true) {")),
+            None
+        );
+    }
+
+    #[test]
+    fn edit_region_treats_member_headers_as_structural() {
+        let source = "class Cart {
+    val total: Int = 0
+
+    fun add(item: Int) {
+        val sum = item
+    }
+}
+";
+        // A member property's type and a member function's signature.
+        assert_eq!(
+            edit_region(source, &source.replace("total: Int", "total: Long")),
+            None
+        );
+        assert_eq!(
+            edit_region(source, &source.replace("add(item: Int)", "add(item: Long)")),
+            None
+        );
+        assert_eq!(
+            edit_region(
+                source,
+                &source.replace("\n\n", "\n    private fun clear() {}\n\n")
+            ),
+            None
+        );
+        // A local inside a member's body is not a header.
+        assert_eq!(
+            edit_region(
+                source,
+                &source.replace("val sum = item", "val sum = item + 1")
+            ),
+            Some(EditRegion {
+                start_line: 4,
+                old_end_line: 5,
+                new_end_line: 5,
+            })
+        );
+    }
+
+    #[test]
+    fn merge_shifts_diagnostics_after_the_edit() {
+        // `fun a` gains a line; `fun b` (lines 6-8) moves down by one.
+        let edit = EditRegion {
+            start_line: 3,
+            old_end_line: 4,
+            new_end_line: 5,
+        };
+        let previous = vec![
+            diagnostic(0, "UNUSED_IMPORT"),
+            diagnostic(3, "TYPE_MISMATCH"),
+            diagnostic(7, "UNUSED_VARIABLE"),
+        ];
+        let merged = merge_diagnostics(
+            &previous,
+            &edit,
+            (2, 5),
+            vec![diagnostic(4, "UNRESOLVED_REFERENCE")],
+        );
+        let lines: Vec<_> = merged
+            .iter()
+            .map(|d| (d.range.start.line, d.message.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![(4, "UNRESOLVED_REFERENCE"), (8, "UNUSED_VARIABLE")]
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use serde_json::Value;
+use tower_lsp::jsonrpc::{Request, Response};
+use tower_service::Service;
+use tracing::Instrument;
+
+/// Wraps the LSP service so every incoming request and notification runs in
+/// a span carrying its method and document URI, with the elapsed time logged
+/// at `debug` when the handler finishes.
+///
+/// The span is attached with [`Instrument`], so it is entered only while the
+/// handler future is polled and never leaks onto other tasks across `.await`.
+pub struct Instrumented<S> {
+    inner: S,
+}
+
+impl<S> Instrumented<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S> Service<Request> for Instrumented<S>
+where
+    S: Service<Request, Response = Option<Response>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let method = request.method().to_string();
+        let uri = document_uri(request.params());
+        let span = tracing::debug_span!(
+            "lsp_request",
+            method = %method,
+            uri = uri.as_deref().unwrap_or("")
+        );
+
+        let future = span.in_scope(|| self.inner.call(request));
+        Box::pin(
+            async move {
+                let started = Instant::now();
+                let result = future.await;
+                tracing::debug!(
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "handled {}",
+                    method
+                );
+                result
+            }
+            .instrument(span),
+        )
+    }
+}
+
+/// Extracts `textDocument.uri` from request params, when present.
+fn document_uri(params: Option<&Value>) -> Option<String> {
+    params?
+        .get("textDocument")?
+        .get("uri")?
+        .as_str()
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn document_uri_reads_text_document_param() {
+        let params = serde_json::json!({
+            "textDocument": { "uri": "file:///src/Main.kt" },
+            "position": { "line": 0, "character": 0 }
+        });
+        assert_eq!(
+            document_uri(Some(&params)).as_deref(),
+            Some("file:///src/Main.kt")
+        );
+        assert_eq!(document_uri(Some(&serde_json::json!({}))), None);
+        assert_eq!(document_uri(None), None);
+    }
+}
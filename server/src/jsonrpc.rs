@@ -1,8 +1,11 @@
-use std::io;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::process::{ChildStdin, ChildStdout};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 use crate::error::ProtocolError;
 
@@ -28,6 +31,14 @@ pub struct Response {
     pub error: Option<ResponseError>,
 }
 
+/// A message read from the sidecar: a response to one of our requests, or
+/// a notification the sidecar sent on its own (e.g. `$/partialResult`).
+#[derive(Debug)]
+pub enum Message {
+    Response(Response),
+    Notification(Request),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ResponseError {
     pub code: i32,
@@ -56,12 +67,71 @@ impl Request {
     }
 }
 
+/// Which way a traced message travelled.
+#[derive(Debug, Clone, Copy)]
+pub enum TraceDirection {
+    ToSidecar,
+    FromSidecar,
+}
+
+/// Newline-delimited JSON log of every message exchanged with the sidecar
+/// (`sidecarTraceFile`). Each line holds the direction, a millisecond Unix
+/// timestamp, and the message exactly as sent or received.
+#[derive(Debug, Clone)]
+pub struct TraceFile {
+    file: Arc<Mutex<File>>,
+}
+
+impl TraceFile {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::options().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Appends one message body. Bodies that are not valid JSON are logged
+    /// as strings so malformed traffic is still visible.
+    pub fn record(&self, direction: TraceDirection, body: &[u8]) {
+        let message = serde_json::from_slice::<serde_json::Value>(body).unwrap_or_else(|_| {
+            serde_json::Value::String(String::from_utf8_lossy(body).into_owned())
+        });
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+        let direction = match direction {
+            TraceDirection::ToSidecar => "send",
+            TraceDirection::FromSidecar => "receive",
+        };
+        let mut line = serde_json::json!({
+            "timestamp": timestamp,
+            "direction": direction,
+            "message": message,
+        })
+        .to_string();
+        line.push('\n');
+
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            tracing::warn!("failed to write sidecar trace: {}", e);
+        }
+    }
+}
+
 /// Writes a JSON-RPC message with Content-Length framing to an async writer.
 pub async fn write_message(
-    writer: &mut ChildStdin,
+    writer: &mut (impl AsyncWrite + Unpin),
     message: &impl Serialize,
+    trace: Option<&TraceFile>,
 ) -> Result<(), crate::error::Error> {
     let body = serde_json::to_string(message).map_err(ProtocolError::JsonParse)?;
+    if let Some(trace) = trace {
+        trace.record(TraceDirection::ToSidecar, body.as_bytes());
+    }
     let header = format!("Content-Length: {}\r\n\r\n", body.len());
 
     writer
@@ -80,8 +150,9 @@ pub async fn write_message(
 /// Reads a JSON-RPC message with Content-Length framing from an async reader.
 /// Returns `None` on EOF (sidecar exited).
 pub async fn read_message(
-    reader: &mut BufReader<ChildStdout>,
-) -> Result<Option<Response>, crate::error::Error> {
+    reader: &mut (impl AsyncBufRead + Unpin),
+    trace: Option<&TraceFile>,
+) -> Result<Option<Message>, crate::error::Error> {
     let content_length = match read_content_length(reader).await? {
         Some(len) => len,
         None => return Ok(None), // EOF
@@ -93,15 +164,26 @@ pub async fn read_message(
         Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
         Err(e) => return Err(crate::error::Error::Io(e)),
     }
+    if let Some(trace) = trace {
+        trace.record(TraceDirection::FromSidecar, &body);
+    }
 
-    let response: Response = serde_json::from_slice(&body).map_err(ProtocolError::JsonParse)?;
+    Ok(Some(parse_message(&body)?))
+}
 
-    Ok(Some(response))
+/// Decodes a message body; anything carrying a `method` is a notification.
+fn parse_message(body: &[u8]) -> Result<Message, ProtocolError> {
+    let value: serde_json::Value = serde_json::from_slice(body)?;
+    if value.get("method").is_some() {
+        Ok(Message::Notification(serde_json::from_value(value)?))
+    } else {
+        Ok(Message::Response(serde_json::from_value(value)?))
+    }
 }
 
 /// Reads headers until the empty line separator, extracts Content-Length.
 async fn read_content_length(
-    reader: &mut BufReader<ChildStdout>,
+    reader: &mut (impl AsyncBufRead + Unpin),
 ) -> Result<Option<usize>, crate::error::Error> {
     let mut content_length: Option<usize> = None;
 
@@ -174,6 +256,49 @@ mod tests {
         assert!(text.contains("\r\n\r\n"));
     }
 
+    #[test]
+    fn trace_file_appends_one_json_line_per_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sidecar-trace.jsonl");
+        let trace = TraceFile::open(&path).unwrap();
+
+        let request = serde_json::to_vec(&Request::new(7, "hover", None)).unwrap();
+        trace.record(TraceDirection::ToSidecar, &request);
+        trace.record(TraceDirection::FromSidecar, b"not json");
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["direction"], "send");
+        assert_eq!(lines[0]["message"]["method"], "hover");
+        assert_eq!(lines[0]["message"]["id"], 7);
+        assert!(lines[0]["timestamp"].as_u64().unwrap() > 0);
+        assert_eq!(lines[1]["direction"], "receive");
+        assert_eq!(lines[1]["message"], "not json");
+    }
+
+    #[test]
+    fn parse_message_distinguishes_notifications_from_responses() {
+        let notification = br#"{"jsonrpc":"2.0","method":"$/partialResult","params":{"token":"references-1","value":{"locations":[]}}}"#;
+        match parse_message(notification).unwrap() {
+            Message::Notification(request) => {
+                assert_eq!(request.method, "$/partialResult");
+                assert_eq!(request.id, None);
+                assert_eq!(request.params.unwrap()["token"], "references-1");
+            }
+            other => panic!("expected a notification, got {other:?}"),
+        }
+
+        let response = br#"{"jsonrpc":"2.0","id":3,"result":{"pong":true}}"#;
+        assert!(matches!(
+            parse_message(response).unwrap(),
+            Message::Response(Response { id: Some(3), .. })
+        ));
+    }
+
     #[test]
     fn deserialize_response() {
         let json = r#"{"jsonrpc":"2.0","id":1,"result":{"pong":true}}"#;
@@ -0,0 +1,401 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types::{Location, Position, Range, SymbolInformation, SymbolKind, Url};
+
+use crate::project::ExcludeMatcher;
+
+/// Modifiers that may precede a declaration keyword.
+const MODIFIERS: &[&str] = &[
+    "public",
+    "private",
+    "internal",
+    "protected",
+    "open",
+    "abstract",
+    "sealed",
+    "final",
+    "data",
+    "enum",
+    "annotation",
+    "value",
+    "inline",
+    "inner",
+    "suspend",
+    "operator",
+    "infix",
+    "tailrec",
+    "external",
+    "expect",
+    "actual",
+];
+
+/// Directories never scanned for sources.
+const SKIPPED_DIRS: &[&str] = &["build", ".gradle", ".git", "node_modules"];
+
+/// A top-level declaration found by scanning source text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LexicalSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// The name's range in the file.
+    pub range: Range,
+}
+
+/// Top-level `class`, `interface`, `object` and `fun` declarations by file,
+/// found without the compiler. Serves workspace symbols while the sidecar is
+/// starting; names are exact, but anything a scan cannot see (nested or
+/// generated declarations) is missing.
+#[derive(Debug, Default)]
+pub struct LexicalSymbolIndex {
+    files: HashMap<Url, (Option<String>, Vec<LexicalSymbol>)>,
+}
+
+impl LexicalSymbolIndex {
+    /// Indexes every `.kt` file under `roots` that `excludes` keeps.
+    pub fn scan(roots: &[PathBuf], excludes: &ExcludeMatcher) -> Self {
+        let mut index = Self::default();
+        let mut files = Vec::new();
+        for root in roots {
+            collect_kotlin_files(root, excludes, &mut files);
+        }
+        for path in files {
+            let (Ok(text), Ok(uri)) = (std::fs::read_to_string(&path), Url::from_file_path(&path))
+            else {
+                continue;
+            };
+            index.update(uri, &text);
+        }
+        index
+    }
+
+    /// Replaces the symbols of `uri` with those declared in `text`.
+    pub fn update(&mut self, uri: Url, text: &str) {
+        self.files
+            .insert(uri, (package_name(text), extract_symbols(text)));
+    }
+
+    /// Drops the symbols of a deleted file, or of every file under a
+    /// deleted directory.
+    pub fn remove(&mut self, uri: &Url) {
+        let dir = format!("{}/", uri.as_str().trim_end_matches('/'));
+        self.files
+            .retain(|file, _| file != uri && !file.as_str().starts_with(&dir));
+    }
+
+    /// Folds `other` into this index, keeping entries already present: they
+    /// come from open documents, which are newer than the files on disk.
+    pub fn merge(&mut self, other: Self) {
+        for (uri, entry) in other.files {
+            self.files.entry(uri).or_insert(entry);
+        }
+    }
+
+    /// Symbols whose name contains `query`, ignoring case, with the package
+    /// as container.
+    pub fn search(&self, query: &str) -> Vec<SymbolInformation> {
+        let query = query.to_lowercase();
+        let mut symbols: Vec<SymbolInformation> = self
+            .files
+            .iter()
+            .flat_map(|(uri, (package, symbols))| {
+                symbols
+                    .iter()
+                    .filter(|symbol| symbol.name.to_lowercase().contains(&query))
+                    .map(|symbol| {
+                        #[allow(deprecated)]
+                        SymbolInformation {
+                            name: symbol.name.clone(),
+                            kind: symbol.kind,
+                            tags: None,
+                            deprecated: None,
+                            location: Location::new(uri.clone(), symbol.range),
+                            container_name: package.clone(),
+                        }
+                    })
+            })
+            .collect();
+        symbols.sort_by(|a, b| {
+            (&a.name, a.location.uri.as_str()).cmp(&(&b.name, b.location.uri.as_str()))
+        });
+        symbols
+    }
+}
+
+/// Collects `.kt` files under `dir`, without following symlinks.
+fn collect_kotlin_files(dir: &Path, excludes: &ExcludeMatcher, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        let path = entry.path();
+        if excludes.is_excluded(&path) {
+            continue;
+        }
+        if file_type.is_dir() {
+            let skipped = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| SKIPPED_DIRS.contains(&name));
+            if !skipped {
+                collect_kotlin_files(&path, excludes, files);
+            }
+        } else if file_type.is_file() && path.extension().is_some_and(|ext| ext == "kt") {
+            files.push(path);
+        }
+    }
+}
+
+fn package_name(text: &str) -> Option<String> {
+    text.lines().find_map(|line| {
+        let name = line.trim().strip_prefix("package ")?.trim();
+        (!name.is_empty()).then(|| name.trim_end_matches(';').to_string())
+    })
+}
+
+/// The top-level declarations in `text`: unindented lines that declare a
+/// `class`, `interface`, `object` or `fun` after any modifiers and
+/// annotations.
+pub fn extract_symbols(text: &str) -> Vec<LexicalSymbol> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.starts_with(char::is_whitespace))
+        .filter_map(|(line_number, line)| {
+            let (name, kind, column) = declaration(line)?;
+            // LSP columns count UTF-16 code units, not bytes.
+            let start_column = line[..column].encode_utf16().count();
+            let end_column = start_column + name.encode_utf16().count();
+            let start = Position::new(line_number as u32, start_column as u32);
+            let end = Position::new(line_number as u32, end_column as u32);
+            Some(LexicalSymbol {
+                name: name.to_string(),
+                kind,
+                range: Range::new(start, end),
+            })
+        })
+        .collect()
+}
+
+/// The declared name, its kind, and its byte offset in `line`.
+fn declaration(line: &str) -> Option<(&str, SymbolKind, usize)> {
+    let mut rest = line;
+    let mut enum_class = false;
+    let kind = loop {
+        rest = rest.trim_start();
+        if let Some(annotated) = rest.strip_prefix('@') {
+            rest = skip_annotation(annotated);
+            continue;
+        }
+        let word_end = rest
+            .find(|c: char| !c.is_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let (word, after) = rest.split_at(word_end);
+        rest = after;
+        match word {
+            "class" if enum_class => break SymbolKind::ENUM,
+            "class" => break SymbolKind::CLASS,
+            "interface" => break SymbolKind::INTERFACE,
+            "object" => break SymbolKind::OBJECT,
+            "fun" => {
+                // `fun interface`
+                let trimmed = rest.trim_start();
+                if let Some(after) = trimmed.strip_prefix("interface") {
+                    if after.starts_with(char::is_whitespace) {
+                        rest = after;
+                        break SymbolKind::INTERFACE;
+                    }
+                }
+                break SymbolKind::FUNCTION;
+            }
+            "enum" => enum_class = true,
+            word if MODIFIERS.contains(&word) => {}
+            _ => return None,
+        }
+    };
+
+    rest = rest.trim_start();
+    if kind == SymbolKind::FUNCTION {
+        if rest.starts_with('<') {
+            rest = skip_brackets(rest, '<', '>')?.trim_start();
+        }
+        // Extension receiver: the name follows the last `.` before `(`.
+        let signature_end = rest.find('(')?;
+        let signature = &rest[..signature_end];
+        if let Some(dot) = signature.rfind('.') {
+            rest = &rest[dot + 1..];
+        }
+    }
+    let name = rest.strip_prefix('`').map_or_else(
+        || {
+            let end = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            &rest[..end]
+        },
+        |quoted| quoted.split('`').next().unwrap_or_default(),
+    );
+    if name.is_empty() {
+        return None;
+    }
+    let column = line.len() - rest.len() + usize::from(rest.starts_with('`'));
+    Some((name, kind, column))
+}
+
+/// Skips an annotation's name and arguments (without the `@`).
+fn skip_annotation(text: &str) -> &str {
+    let name_end = text
+        .find(|c: char| !c.is_alphanumeric() && c != '_' && c != '.' && c != ':')
+        .unwrap_or(text.len());
+    let rest = &text[name_end..];
+    if rest.starts_with('(') {
+        skip_brackets(rest, '(', ')').unwrap_or("")
+    } else {
+        rest
+    }
+}
+
+/// The text after the bracket that closes the one `text` starts with.
+fn skip_brackets(text: &str, open: char, close: char) -> Option<&str> {
+    let mut depth = 0;
+    for (index, c) in text.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Some(&text[index + 1..]);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extracts_top_level_declarations() {
+        let text = "\
+package demo.app
+
+import kotlin.math.max
+
+@Suppress(\"unused\")
+data class User(val name: String)
+sealed interface Shape
+enum class Color { RED }
+internal object Registry {
+    fun nested() {}
+}
+fun interface Handler { fun handle() }
+suspend fun <T : Any> load(id: T): T = id
+fun String.shout() = uppercase()
+private fun `with spaces`() {}
+val notIndexed = 1
+";
+        let symbols: Vec<_> = extract_symbols(text)
+            .into_iter()
+            .map(|symbol| (symbol.name, symbol.kind))
+            .collect();
+        assert_eq!(
+            symbols,
+            vec![
+                ("User".to_string(), SymbolKind::CLASS),
+                ("Shape".to_string(), SymbolKind::INTERFACE),
+                ("Color".to_string(), SymbolKind::ENUM),
+                ("Registry".to_string(), SymbolKind::OBJECT),
+                ("Handler".to_string(), SymbolKind::INTERFACE),
+                ("load".to_string(), SymbolKind::FUNCTION),
+                ("shout".to_string(), SymbolKind::FUNCTION),
+                ("with spaces".to_string(), SymbolKind::FUNCTION),
+            ]
+        );
+        assert_eq!(
+            extract_symbols("fun String.shout() = uppercase()")[0].range,
+            Range::new(Position::new(0, 11), Position::new(0, 16))
+        );
+    }
+
+    #[test]
+    fn ranges_count_utf16_code_units() {
+        let symbols = extract_symbols("@Named(\"Größe\") class `Über 𝒳`\nfun `naïve`() = Unit\n");
+        assert_eq!(symbols[0].name, "Über 𝒳");
+        assert_eq!(
+            symbols[0].range,
+            Range::new(Position::new(0, 23), Position::new(0, 30))
+        );
+        assert_eq!(symbols[1].name, "naïve");
+        assert_eq!(
+            symbols[1].range,
+            Range::new(Position::new(1, 5), Position::new(1, 10))
+        );
+    }
+
+    #[test]
+    fn search_matches_names_ignoring_case() {
+        let uri = Url::parse("file:///src/demo/User.kt").unwrap();
+        let mut index = LexicalSymbolIndex::default();
+        index.update(
+            uri.clone(),
+            "package demo\n\nclass UserRepository\nfun loadUser() {}\n",
+        );
+        let names: Vec<_> = index
+            .search("user")
+            .into_iter()
+            .map(|symbol| (symbol.name, symbol.container_name))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("UserRepository".to_string(), Some("demo".to_string())),
+                ("loadUser".to_string(), Some("demo".to_string())),
+            ]
+        );
+
+        index.update(uri, "package demo\n\nclass Account\n");
+        assert!(index.search("user").is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn scan_skips_excluded_paths_and_symlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let vendor = dir.path().join("vendor");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::create_dir_all(&vendor).unwrap();
+        std::fs::write(src.join("Cart.kt"), "class Cart\n").unwrap();
+        std::fs::write(vendor.join("Vendored.kt"), "class Vendored\n").unwrap();
+        // A cycle back to the root must not be followed.
+        std::os::unix::fs::symlink(dir.path(), src.join("loop")).unwrap();
+
+        let excludes = ExcludeMatcher::new(&["vendor/**".into()], Some(dir.path()), &[]);
+        let index = LexicalSymbolIndex::scan(&[dir.path().to_path_buf()], &excludes);
+        let names: Vec<_> = index.search("").into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["Cart".to_string()]);
+    }
+
+    #[test]
+    fn remove_drops_a_file_or_a_directory() {
+        let mut index = LexicalSymbolIndex::default();
+        for (path, text) in [
+            ("file:///src/demo/Account.kt", "class Account\n"),
+            ("file:///src/demo/billing/Invoice.kt", "class Invoice\n"),
+            ("file:///src/demos/Sample.kt", "class Sample\n"),
+        ] {
+            index.update(Url::parse(path).unwrap(), text);
+        }
+
+        index.remove(&Url::parse("file:///src/demo/Account.kt").unwrap());
+        assert!(index.search("Account").is_empty());
+        assert_eq!(index.search("Invoice").len(), 1);
+
+        index.remove(&Url::parse("file:///src/demo").unwrap());
+        assert!(index.search("Invoice").is_empty());
+        assert_eq!(index.search("Sample").len(), 1);
+    }
+}
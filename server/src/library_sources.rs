@@ -0,0 +1,293 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types::Url;
+
+/// URI scheme for library sources served by the server instead of temp files.
+pub const LIBRARY_SCHEME: &str = "kotlin-analyzer";
+
+/// A class file inside a library jar, as returned by definition requests that
+/// resolve into a dependency.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct JarEntry {
+    pub jar: PathBuf,
+    /// Path inside the jar, e.g. `kotlin/collections/CollectionsKt.class`.
+    pub entry: String,
+}
+
+impl JarEntry {
+    /// Parses an in-jar location: `jar:file:///lib.jar!/a/B.class`,
+    /// `jar:///lib.jar!/a/B.class`, `file:///lib.jar!/a/B.class`, or
+    /// `kotlin-analyzer:///lib.jar!/a/B.class`.
+    pub fn from_uri(uri: &Url) -> Option<Self> {
+        let path = match uri.scheme() {
+            "file" | LIBRARY_SCHEME => uri.path().to_string(),
+            "jar" => {
+                let rest = uri.as_str().strip_prefix("jar:")?;
+                let rest = rest.strip_prefix("file:").unwrap_or(rest);
+                rest.trim_start_matches('/').to_string()
+            }
+            _ => return None,
+        };
+        let (jar, entry) = path.split_once("!/")?;
+        if entry.is_empty() {
+            return None;
+        }
+        let jar = percent_decode(jar);
+        let jar = match jar.strip_prefix('/') {
+            // `file:///C:/libs/a.jar` names `C:/libs/a.jar` on Windows.
+            Some(rest) if cfg!(windows) && has_drive_letter(rest) => PathBuf::from(rest),
+            Some(_) => PathBuf::from(jar),
+            None if has_drive_letter(&jar) => PathBuf::from(jar),
+            None => PathBuf::from(format!("/{jar}")),
+        };
+        Some(Self {
+            jar,
+            entry: percent_decode(entry),
+        })
+    }
+
+    /// Stable `kotlin-analyzer:` URI for this class.
+    pub fn library_uri(&self) -> Option<Url> {
+        let jar = Url::from_file_path(&self.jar).ok()?;
+        Url::parse(&format!(
+            "{LIBRARY_SCHEME}://{}!/{}",
+            jar.path(),
+            self.entry
+        ))
+        .ok()
+    }
+
+    /// The in-jar URI the sidecar resolves library classes by, so requests
+    /// made inside a decompiled file use the original coordinates. The jar
+    /// part is a percent-encoded `file:` URI, with a drive letter on Windows.
+    pub fn sidecar_uri(&self) -> String {
+        match Url::from_file_path(&self.jar) {
+            Ok(jar) => format!("jar:{jar}!/{}", self.entry),
+            Err(()) => format!("jar:file://{}!/{}", self.jar.display(), self.entry),
+        }
+    }
+}
+
+/// Whether `path` starts with a Windows drive, e.g. `C:`.
+fn has_drive_letter(path: &str) -> bool {
+    let mut chars = path.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic()) && chars.next() == Some(':')
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| text.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Decompiled text of a library class.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecompiledClass {
+    pub text: String,
+    /// `kotlin` or `java`, as reported by the sidecar.
+    pub language: String,
+}
+
+/// Cache of decompiled library classes, shared by both ways of opening them:
+/// `kotlin-analyzer:` URIs served from memory, and read-only temp files for
+/// editors without custom scheme support. Each class is decompiled once.
+#[derive(Debug)]
+pub struct DecompiledSources {
+    dir: PathBuf,
+    classes: HashMap<JarEntry, DecompiledClass>,
+    files: HashMap<JarEntry, PathBuf>,
+}
+
+impl Default for DecompiledSources {
+    fn default() -> Self {
+        Self::new(std::env::temp_dir().join("kotlin-analyzer-decompiled"))
+    }
+}
+
+impl DecompiledSources {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            classes: HashMap::new(),
+            files: HashMap::new(),
+        }
+    }
+
+    pub fn get(&self, entry: &JarEntry) -> Option<&DecompiledClass> {
+        self.classes.get(entry)
+    }
+
+    pub fn insert(&mut self, entry: JarEntry, class: DecompiledClass) {
+        self.classes.insert(entry, class);
+    }
+
+    /// Maps a URI back to its library class: either an in-jar or
+    /// `kotlin-analyzer:` URI, or a temp file written by [`Self::file`].
+    pub fn entry_for_uri(&self, uri: &Url) -> Option<JarEntry> {
+        if let Some(entry) = JarEntry::from_uri(uri) {
+            return Some(entry);
+        }
+        let path = uri.to_file_path().ok()?;
+        self.files
+            .iter()
+            .find(|(_, file)| **file == path)
+            .map(|(entry, _)| entry.clone())
+    }
+
+    /// Returns a read-only file holding the cached class, writing it on first
+    /// use. Returns `None` when the class has not been decompiled yet.
+    pub fn file(&mut self, entry: &JarEntry) -> Option<std::io::Result<PathBuf>> {
+        if let Some(path) = self.files.get(entry).filter(|path| path.exists()) {
+            return Some(Ok(path.clone()));
+        }
+        let class = self.classes.get(entry)?;
+        let path = decompiled_path(&self.dir, entry, &class.language);
+        Some(write_read_only(&path, &class.text).map(|()| {
+            self.files.insert(entry.clone(), path.clone());
+            path
+        }))
+    }
+}
+
+fn write_read_only(path: &Path, text: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // A previous session may have left a read-only copy behind.
+    if let Ok(metadata) = std::fs::metadata(path) {
+        let mut permissions = metadata.permissions();
+        #[allow(clippy::permissions_set_readonly_false)]
+        permissions.set_readonly(false);
+        std::fs::set_permissions(path, permissions)?;
+    }
+    std::fs::write(path, text)?;
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(path, permissions)
+}
+
+/// Location of a decompiled class: one directory per jar (its file name plus a
+/// hash of its path, so jars with the same name don't collide), mirroring the
+/// package layout inside it.
+fn decompiled_path(dir: &Path, entry: &JarEntry, language: &str) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    entry.jar.hash(&mut hasher);
+    let jar_name = entry
+        .jar
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "library".to_string());
+    let extension = if language == "java" { "java" } else { "kt" };
+    let class_path = entry.entry.strip_suffix(".class").unwrap_or(&entry.entry);
+
+    dir.join(format!("{jar_name}-{:016x}", hasher.finish()))
+        .join(format!("{class_path}.{extension}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jar_entry_from_in_jar_uris() {
+        let expected = JarEntry {
+            jar: PathBuf::from("/home/me/.m2/lib 1.jar"),
+            entry: "kotlin/collections/CollectionsKt.class".to_string(),
+        };
+        for uri in [
+            "jar:file:///home/me/.m2/lib%201.jar!/kotlin/collections/CollectionsKt.class",
+            "jar:///home/me/.m2/lib%201.jar!/kotlin/collections/CollectionsKt.class",
+            "file:///home/me/.m2/lib%201.jar!/kotlin/collections/CollectionsKt.class",
+        ] {
+            assert_eq!(
+                JarEntry::from_uri(&Url::parse(uri).unwrap()),
+                Some(expected.clone()),
+                "{uri}"
+            );
+        }
+        assert_eq!(
+            JarEntry::from_uri(&Url::parse("file:///project/src/Main.kt").unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn library_uri_round_trips_to_sidecar_coordinates() {
+        let entry = JarEntry {
+            jar: PathBuf::from("/libs/okio 3.jar"),
+            entry: "okio/Buffer.class".to_string(),
+        };
+        let uri = entry.library_uri().unwrap();
+        assert_eq!(
+            uri.as_str(),
+            "kotlin-analyzer:///libs/okio%203.jar!/okio/Buffer.class"
+        );
+        assert_eq!(JarEntry::from_uri(&uri), Some(entry.clone()));
+        assert_eq!(
+            entry.sidecar_uri(),
+            "jar:file:///libs/okio%203.jar!/okio/Buffer.class"
+        );
+        assert_eq!(
+            JarEntry::from_uri(&Url::parse(&entry.sidecar_uri()).unwrap()),
+            Some(entry.clone())
+        );
+    }
+
+    #[test]
+    fn decompiled_sources_writes_read_only_files_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sources = DecompiledSources::new(dir.path().to_path_buf());
+        let entry = JarEntry {
+            jar: PathBuf::from("/libs/okio-3.9.0.jar"),
+            entry: "okio/Buffer$UnsafeCursor.class".to_string(),
+        };
+        assert!(sources.file(&entry).is_none());
+
+        let class = |text: &str| DecompiledClass {
+            text: text.to_string(),
+            language: "kotlin".to_string(),
+        };
+        sources.insert(entry.clone(), class("class UnsafeCursor"));
+        let path = sources.file(&entry).unwrap().unwrap();
+        assert!(path.starts_with(dir.path()));
+        assert!(path.ends_with("okio/Buffer$UnsafeCursor.kt"));
+        assert!(path
+            .parent()
+            .and_then(Path::parent)
+            .and_then(Path::file_name)
+            .is_some_and(|name| name.to_string_lossy().starts_with("okio-3.9.0-")));
+        assert!(std::fs::metadata(&path).unwrap().permissions().readonly());
+
+        let file_uri = Url::from_file_path(&path).unwrap();
+        assert_eq!(sources.entry_for_uri(&file_uri), Some(entry.clone()));
+
+        // Writing again (e.g. after a restart) replaces the read-only copy.
+        let mut restarted = DecompiledSources::new(dir.path().to_path_buf());
+        restarted.insert(entry.clone(), class("class UnsafeCursor2"));
+        assert_eq!(restarted.file(&entry).unwrap().unwrap(), path);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "class UnsafeCursor2"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde_json::Value;
+use tower_lsp::lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range};
+
+use crate::config::{Config, FormattingTool};
+use crate::formatter;
+
+/// How long a single ktlint run may take before it is killed.
+const LINT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The ktlint binary for lint diagnostics: `formattingPath` when ktlint is
+/// the formatter, otherwise `ktlint` from `PATH`. `None` when lint
+/// diagnostics are off or ktlint cannot be found.
+pub fn ktlint_binary(config: &Config) -> Option<PathBuf> {
+    if !config.enable_lint_diagnostics {
+        return None;
+    }
+    let binary = match config.formatting_tool {
+        FormattingTool::Ktlint => formatter::configured_binary(config)?,
+        _ => "ktlint".to_string(),
+    };
+    formatter::find_executable(&binary)
+}
+
+/// Lints `text` with ktlint's JSON reporter. `path` is passed as
+/// `--stdin-path` so ktlint applies the `.editorconfig` and file-name rules
+/// that hold for the file. ktlint exits with 1 when it finds violations, so
+/// only other exit codes are failures.
+pub async fn run_ktlint(
+    binary: &Path,
+    path: Option<&Path>,
+    text: &str,
+) -> Result<Vec<Diagnostic>, io::Error> {
+    let mut args = vec!["--stdin", "--reporter=json"];
+    let path = path.map(|path| path.to_string_lossy());
+    if let Some(path) = &path {
+        args.extend(["--stdin-path", path]);
+    }
+    let stdout = formatter::run_with_stdin(binary, &args, text, LINT_TIMEOUT, &[0, 1]).await?;
+    let report: Value = serde_json::from_str(&stdout)
+        .map_err(|e| io::Error::other(format!("unreadable ktlint report: {e}")))?;
+    Ok(parse_ktlint_report(&report))
+}
+
+/// Diagnostics for the violations in a ktlint JSON report, as warnings with
+/// the rule id as code. ktlint reports a 1-based line and column; the range
+/// covers the character there.
+fn parse_ktlint_report(report: &Value) -> Vec<Diagnostic> {
+    report
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|file| file.get("errors")?.as_array())
+        .flatten()
+        .filter_map(|error| {
+            let line = error.get("line")?.as_u64()?.saturating_sub(1) as u32;
+            let column = error.get("column")?.as_u64()?.saturating_sub(1) as u32;
+            Some(Diagnostic {
+                range: Range::new(Position::new(line, column), Position::new(line, column + 1)),
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: error
+                    .get("rule")
+                    .and_then(|rule| rule.as_str())
+                    .map(|rule| NumberOrString::String(rule.to_string())),
+                source: Some("ktlint".into()),
+                message: error.get("message")?.as_str()?.to_string(),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Compiler diagnostics followed by the lint findings that do not overlap
+/// any of them: where the compiler already reports a problem, a style
+/// warning on the same code is noise.
+pub fn merge_lint(mut compiler: Vec<Diagnostic>, lint: &[Diagnostic]) -> Vec<Diagnostic> {
+    let overlaps = |a: &Range, b: &Range| a.start <= b.end && b.start <= a.end;
+    let distinct: Vec<Diagnostic> = lint
+        .iter()
+        .filter(|finding| {
+            !compiler
+                .iter()
+                .any(|diagnostic| overlaps(&diagnostic.range, &finding.range))
+        })
+        .cloned()
+        .collect();
+    compiler.extend(distinct);
+    compiler
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn ktlint_findings_merge_around_compiler_diagnostics() {
+        let lint = parse_ktlint_report(&json!([{
+            "file": "<stdin>",
+            "errors": [
+                { "line": 1, "column": 1, "message": "Wildcard import", "rule": "standard:no-wildcard-imports" },
+                { "line": 4, "column": 9, "message": "Missing spacing", "rule": "standard:colon-spacing" }
+            ]
+        }]));
+        assert_eq!(lint[0].source.as_deref(), Some("ktlint"));
+        assert_eq!(
+            lint[1].range,
+            Range::new(Position::new(3, 8), Position::new(3, 9))
+        );
+
+        let compiler = vec![Diagnostic {
+            range: Range::new(Position::new(3, 4), Position::new(3, 12)),
+            source: Some("kotlin-analyzer".into()),
+            message: "Type mismatch".into(),
+            ..Default::default()
+        }];
+        let merged = merge_lint(compiler, &lint);
+        let messages: Vec<_> = merged.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, vec!["Type mismatch", "Wildcard import"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_ktlint_accepts_the_violations_exit_status() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let binary = dir.path().join("ktlint");
+        let report = r#"[{"file":"<stdin>","errors":[{"line":2,"column":1,"message":"Unexpected blank line","rule":"standard:no-consecutive-blank-lines"}]}]"#;
+        std::fs::write(
+            &binary,
+            format!(
+                "#!/bin/sh\ncat >/dev/null\necho \"$@\" > {}\necho '{report}'\nexit 1\n",
+                dir.path().join("args").display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path = Path::new("/project/src/Main.kt");
+        let diagnostics = run_ktlint(&binary, Some(path), "fun main() {}\n\n\n")
+            .await
+            .unwrap();
+        let args = std::fs::read_to_string(dir.path().join("args")).unwrap();
+        assert_eq!(
+            args.trim(),
+            "--stdin --reporter=json --stdin-path /project/src/Main.kt"
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].code,
+            Some(NumberOrString::String(
+                "standard:no-consecutive-blank-lines".into()
+            ))
+        );
+    }
+}
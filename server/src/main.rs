@@ -1,17 +1,35 @@
 mod bridge;
 mod config;
+mod conflicts;
+#[cfg(unix)]
+mod daemon;
+mod dependency_completion;
 mod error;
+mod folding;
+mod formatter;
+mod incremental;
+mod instrument;
 mod jsonrpc;
+mod lexical_symbols;
+mod library_sources;
+mod lint;
+mod markup;
 mod project;
+mod response_cache;
+mod routing;
 mod runtime;
+mod script;
 mod server;
 mod state;
 
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
+
 use tower_lsp::{LspService, Server};
 use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
+async fn main() -> anyhow::Result<ExitCode> {
     // Install panic hook that writes to a log file before aborting
     std::panic::set_hook(Box::new(|info| {
         let msg = format!("kotlin-analyzer PANIC: {}\n", info);
@@ -55,16 +73,41 @@ async fn main() -> anyhow::Result<()> {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::new(server::KotlinLanguageServer::new);
+    let (service, socket) = LspService::build(server::KotlinLanguageServer::new)
+        .custom_method(
+            "kotlin-analyzer/librarySource",
+            server::KotlinLanguageServer::library_source,
+        )
+        .custom_method(
+            "window/workDoneProgress/cancel",
+            server::KotlinLanguageServer::work_done_progress_cancel,
+        )
+        .custom_method(
+            routing::WORKSPACE_SYMBOL_METHOD,
+            server::KotlinLanguageServer::workspace_symbol,
+        )
+        .finish();
+    let shutdown_requested = service.inner().shutdown_requested();
 
-    Server::new(stdin, stdout, socket).serve(service).await;
+    // The loop ends on the `exit` notification (or when stdin closes).
+    Server::new(stdin, stdout, socket)
+        .serve(instrument::Instrumented::new(routing::Rerouted::new(
+            service,
+        )))
+        .await;
 
     tracing::info!(
         "kotlin-analyzer: server loop exited (pid={})",
         std::process::id()
     );
 
-    Ok(())
+    // Per the LSP spec, exiting without a prior `shutdown` is an error.
+    if shutdown_requested.load(Ordering::SeqCst) {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        tracing::warn!("kotlin-analyzer: exiting without a shutdown request");
+        Ok(ExitCode::FAILURE)
+    }
 }
 
 fn parse_log_level(args: &[String]) -> String {
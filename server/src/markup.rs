@@ -0,0 +1,121 @@
+use tower_lsp::lsp_types::{Documentation, MarkupContent, MarkupKind, SignatureInformation};
+
+/// `value` as markdown, or converted to plain text for clients that cannot
+/// render markdown.
+pub fn markup_content(value: &str, markdown: bool) -> MarkupContent {
+    if markdown {
+        MarkupContent {
+            kind: MarkupKind::Markdown,
+            value: value.to_string(),
+        }
+    } else {
+        MarkupContent {
+            kind: MarkupKind::PlainText,
+            value: markdown_to_plaintext(value),
+        }
+    }
+}
+
+/// Re-renders signature and parameter documentation as plain text.
+pub fn plaintext_signature_documentation(signature: &mut SignatureInformation) {
+    let to_plaintext = |documentation: &mut Option<Documentation>| {
+        if let Some(Documentation::MarkupContent(content)) = documentation {
+            *content = markup_content(&content.value, false);
+        }
+    };
+    to_plaintext(&mut signature.documentation);
+    for parameter in signature.parameters.iter_mut().flatten() {
+        to_plaintext(&mut parameter.documentation);
+    }
+}
+
+/// Strips the markdown the sidecar produces (code fences, headings, bold,
+/// inline code, links, rules) and keeps the text. Code block contents are
+/// kept verbatim.
+pub fn markdown_to_plaintext(markdown: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_code_block = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            in_code_block = !in_code_block;
+            continue;
+        }
+        if in_code_block {
+            lines.push(line.to_string());
+            continue;
+        }
+        if matches!(trimmed.trim_end(), "---" | "***" | "___") {
+            lines.push(String::new());
+            continue;
+        }
+        let heading = trimmed.trim_start_matches('#');
+        let text = if heading.len() < trimmed.len() && heading.starts_with(' ') {
+            heading.trim_start()
+        } else {
+            line
+        };
+        lines.push(strip_inline_markdown(text));
+    }
+    lines.join("\n").trim().to_string()
+}
+
+/// Removes inline markup from one line: `**`, backticks, backslash escapes,
+/// and link syntax, keeping link text (`[Foo](url)` and KDoc `[Foo]` become
+/// `Foo`). Brackets right after a word (`items[0]`) are kept.
+fn strip_inline_markdown(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        let after = &rest[c.len_utf8()..];
+        match c {
+            '\\' if after.starts_with(|next: char| next.is_ascii_punctuation()) => {
+                out.push_str(&after[..1]);
+                rest = &after[1..];
+            }
+            '`' => rest = after,
+            '*' if after.starts_with('*') => rest = &after[1..],
+            '[' if !out.ends_with(|prev: char| prev.is_alphanumeric() || prev == '_') => {
+                match after.find(']') {
+                    Some(close) => {
+                        out.push_str(&strip_inline_markdown(&after[..close]));
+                        rest = &after[close + 1..];
+                        if rest.starts_with('(') {
+                            if let Some(end) = rest.find(')') {
+                                rest = &rest[end + 1..];
+                            }
+                        }
+                    }
+                    None => {
+                        out.push(c);
+                        rest = after;
+                    }
+                }
+            }
+            _ => {
+                out.push(c);
+                rest = after;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn markdown_to_plaintext_keeps_code_and_link_text() {
+        let markdown = "```kotlin\nfun greet(name: String): String\n```\n\n---\n\n## Greeter\n\
+                        **Greets** `name`. See [Greeter] and [the docs](https://example.com).\n\
+                        Uses `names[0]` and 2 \\* 3.";
+        assert_eq!(
+            markdown_to_plaintext(markdown),
+            "fun greet(name: String): String\n\n\n\nGreeter\n\
+             Greets name. See Greeter and the docs.\n\
+             Uses names[0] and 2 * 3."
+        );
+        assert_eq!(markup_content("**x**", true).value, "**x**");
+    }
+}
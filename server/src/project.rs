@@ -1,8 +1,10 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::sync::mpsc;
 use std::time::Duration;
 
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use serde::{Deserialize, Serialize};
 
 use crate::config::Config;
@@ -24,6 +26,341 @@ pub struct ProjectModel {
     /// Generated source roots (KAPT, KSP).
     #[serde(default)]
     pub generated_source_roots: Vec<PathBuf>,
+    /// `kotlin.code.style` from `gradle.properties` (`official` or `obsolete`).
+    #[serde(default)]
+    pub code_style: Option<String>,
+    /// Classpath for `.gradle.kts` scripts: the Gradle API plus the buildscript classpath.
+    #[serde(default)]
+    pub script_classpath: Vec<PathBuf>,
+    /// `-sources` jars of classpath libraries, read for their documentation.
+    #[serde(default)]
+    pub classpath_sources: Vec<PathBuf>,
+    /// Annotation processing output (`kaptClasspath`), resolved against the
+    /// project root.
+    #[serde(default)]
+    pub kapt_classpath: Vec<PathBuf>,
+}
+
+impl ProjectModel {
+    /// Returns true if `path` lies under a source or generated source root.
+    /// A model without source roots (ad-hoc analysis) contains every path.
+    pub fn contains_source(&self, path: &Path) -> bool {
+        if self.source_roots.is_empty() && self.generated_source_roots.is_empty() {
+            return true;
+        }
+        self.source_roots
+            .iter()
+            .chain(self.generated_source_roots.iter())
+            .any(|root| path.starts_with(root))
+    }
+
+    /// The package a file at `path` belongs in by its directory under the
+    /// deepest source root containing it. `None` in the default package,
+    /// outside every root, or when a directory is not an identifier.
+    pub fn package_for(&self, path: &Path) -> Option<String> {
+        let root = self
+            .source_roots
+            .iter()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())?;
+        let directory = path.parent()?.strip_prefix(root).ok()?;
+        let segments = directory
+            .components()
+            .map(|component| {
+                let segment = component.as_os_str().to_str()?;
+                let valid = segment.starts_with(|c: char| c.is_alphabetic() || c == '_')
+                    && segment.chars().all(|c| c.is_alphanumeric() || c == '_');
+                valid.then_some(segment)
+            })
+            .collect::<Option<Vec<_>>>()?;
+        (!segments.is_empty()).then(|| segments.join("."))
+    }
+
+    /// Compiler flags to send to the sidecar: the resolved flags plus
+    /// `-language-version`/`-api-version` derived from `kotlin_version`,
+    /// lowered to `sidecar_version` when the sidecar's compiler is older and
+    /// would reject a newer language version. An explicit
+    /// `-language-version` suppresses both derived flags so the API version
+    /// never exceeds the language version.
+    pub fn analysis_compiler_flags(&self, sidecar_version: Option<&str>) -> Vec<String> {
+        let mut flags = self.compiler_flags.clone();
+        let Some(project) = self.kotlin_version.as_deref().and_then(language_version) else {
+            return flags;
+        };
+        if has_flag(&flags, "-language-version") {
+            return flags;
+        }
+        let (major, minor) = match sidecar_version.and_then(language_version) {
+            Some(sidecar) => project.min(sidecar),
+            None => project,
+        };
+        let version = format!("{major}.{minor}");
+        flags.push(format!("-language-version={version}"));
+        if !has_flag(&flags, "-api-version") {
+            flags.push(format!("-api-version={version}"));
+        }
+        flags
+    }
+}
+
+/// Compiled `excludePaths` globs. Workspace-relative patterns match against
+/// the path relative to the project root; absolute patterns match the full
+/// path. Generated source roots are never excluded, as in the sidecar.
+#[derive(Debug, Clone)]
+pub struct ExcludeMatcher {
+    root: Option<PathBuf>,
+    patterns: Vec<String>,
+    relative: GlobSet,
+    absolute: GlobSet,
+    /// Directories whose `.kotlin-analyzer.json` sets `exclude`, deepest
+    /// first.
+    nested: Vec<(PathBuf, bool)>,
+    generated_roots: Vec<PathBuf>,
+}
+
+impl ExcludeMatcher {
+    /// Compiles `patterns`, skipping (and logging) any that are invalid, and
+    /// reads the `exclude` of every options file below `root`. Files under
+    /// `generated_roots` are exempt.
+    pub fn new(patterns: &[String], root: Option<&Path>, generated_roots: &[PathBuf]) -> Self {
+        let mut relative = GlobSetBuilder::new();
+        let mut absolute = GlobSetBuilder::new();
+        for pattern in patterns {
+            match GlobBuilder::new(pattern).literal_separator(true).build() {
+                Ok(glob) if Path::new(pattern).is_absolute() => {
+                    absolute.add(glob);
+                }
+                Ok(glob) => {
+                    relative.add(glob);
+                }
+                Err(e) => tracing::warn!("ignoring invalid exclude pattern {:?}: {}", pattern, e),
+            }
+        }
+        let mut matcher = Self {
+            root: root.map(Path::to_path_buf),
+            patterns: patterns.to_vec(),
+            relative: relative.build().unwrap_or_else(|_| GlobSet::empty()),
+            absolute: absolute.build().unwrap_or_else(|_| GlobSet::empty()),
+            nested: Vec::new(),
+            generated_roots: generated_roots.to_vec(),
+        };
+        if let Some(root) = root {
+            matcher.nested = nested_excludes(root, |dir| matcher.matches_patterns(dir));
+        }
+        matcher
+    }
+
+    /// Whether this matcher was built from `patterns` for `root` and
+    /// `generated_roots`.
+    pub fn is_for(
+        &self,
+        patterns: &[String],
+        root: Option<&Path>,
+        generated_roots: &[PathBuf],
+    ) -> bool {
+        self.patterns == patterns
+            && self.root.as_deref() == root
+            && self.generated_roots == generated_roots
+    }
+
+    /// Returns true if `path` matches any exclude pattern or lies in a
+    /// subtree whose nested `.kotlin-analyzer.json` excludes it.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        if self
+            .generated_roots
+            .iter()
+            .any(|root| path.starts_with(root))
+        {
+            return false;
+        }
+        if self.matches_patterns(path) {
+            return true;
+        }
+        self.nested
+            .iter()
+            .find(|(dir, _)| path.starts_with(dir))
+            .is_some_and(|(_, exclude)| *exclude)
+    }
+
+    /// Whether `path` matches an absolute or workspace-relative pattern.
+    fn matches_patterns(&self, path: &Path) -> bool {
+        if self.absolute.is_match(path) {
+            return true;
+        }
+        let relative = self
+            .root
+            .as_deref()
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+        self.relative.is_match(relative)
+    }
+}
+
+/// The directories under `root` whose options file sets `exclude`, deepest
+/// first so the innermost one wins. Hidden and build output directories,
+/// directories matching `excluded` and symlinks are not searched.
+fn nested_excludes(root: &Path, excluded: impl Fn(&Path) -> bool) -> Vec<(PathBuf, bool)> {
+    let mut nested = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        if let Some(exclude) = read_options_override(&dir).and_then(|options| options.exclude) {
+            nested.push((dir.clone(), exclude));
+        }
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if !entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                continue;
+            }
+            let path = entry.path();
+            let skipped = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_none_or(|name| name.starts_with('.') || name == "build" || name == "target");
+            if !skipped && !excluded(&path) {
+                pending.push(path);
+            }
+        }
+    }
+    nested.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.components().count()));
+    nested
+}
+
+/// Resolved `readOnlySourceRoots`: directories whose files the server
+/// never edits.
+#[derive(Debug, Clone, Default)]
+pub struct ReadOnlyRoots {
+    roots: Vec<PathBuf>,
+}
+
+impl ReadOnlyRoots {
+    /// Resolves `roots` against `project_root` unless absolute.
+    pub fn new(roots: &[String], project_root: Option<&Path>) -> Self {
+        let roots = roots
+            .iter()
+            .map(|root| match project_root {
+                Some(project_root) => resolve_against(project_root, root),
+                None => PathBuf::from(root),
+            })
+            .collect();
+        Self { roots }
+    }
+
+    /// Returns true if `path` lies under one of the roots.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.roots.iter().any(|root| path.starts_with(root))
+    }
+}
+
+/// Analysis options file. At the project root it describes the project (see
+/// [`ManualProjectConfig`]); in a subdirectory it overrides options for that
+/// subtree.
+const OPTIONS_FILE: &str = ".kotlin-analyzer.json";
+
+/// Compiler flags that may be given more than once; overriding files add to
+/// them instead of replacing the inherited value.
+const REPEATABLE_FLAGS: &[&str] = &["-opt-in", "-Xopt-in", "-P", "-Xplugin"];
+
+/// The options an `.kotlin-analyzer.json` can override for its directory.
+/// Other fields (those of the root file) are ignored.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OptionsOverride {
+    #[serde(default)]
+    compiler_flags: Vec<String>,
+    #[serde(default)]
+    exclude: Option<bool>,
+}
+
+/// Analysis options in effect for one path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EffectiveConfig {
+    pub compiler_flags: Vec<String>,
+    /// Whether a nested options file excludes the path from analysis.
+    pub excluded: bool,
+}
+
+/// The analysis options for `path`: the options files from `root` down to
+/// the path's directory, innermost winning, then the LSP `compilerFlags`
+/// for any flag no file sets.
+pub fn resolve_effective_config(path: &Path, root: &Path, config: &Config) -> EffectiveConfig {
+    let mut effective = EffectiveConfig::default();
+    for options in options_overrides(path, root) {
+        for flag in options.compiler_flags {
+            override_flag(&mut effective.compiler_flags, flag);
+        }
+        if let Some(exclude) = options.exclude {
+            effective.excluded = exclude;
+        }
+    }
+    for flag in &config.compiler_flags {
+        if !effective.compiler_flags.contains(flag)
+            && (is_repeatable_flag(flag) || !has_flag(&effective.compiler_flags, flag_name(flag)))
+        {
+            effective.compiler_flags.push(flag.clone());
+        }
+    }
+    effective
+}
+
+/// The options files in effect for `path`, outermost first. Only
+/// directories between `root` and `path` are searched; unreadable files are
+/// logged and skipped.
+fn options_overrides(path: &Path, root: &Path) -> Vec<OptionsOverride> {
+    let mut overrides: Vec<OptionsOverride> = path
+        .ancestors()
+        .take_while(|dir| dir.starts_with(root))
+        .filter_map(read_options_override)
+        .collect();
+    overrides.reverse();
+    overrides
+}
+
+/// The options file in `dir`, if there is a readable one.
+fn read_options_override(dir: &Path) -> Option<OptionsOverride> {
+    let file = dir.join(OPTIONS_FILE);
+    let content = std::fs::read_to_string(&file).ok()?;
+    serde_json::from_str(&content)
+        .map_err(|e| tracing::warn!("ignoring invalid {}: {}", file.display(), e))
+        .ok()
+}
+
+/// Adds `flag`, replacing an inherited value of the same single-valued flag.
+fn override_flag(flags: &mut Vec<String>, flag: String) {
+    if !is_repeatable_flag(&flag) {
+        let name = flag_name(&flag);
+        flags.retain(|existing| flag_name(existing) != name);
+    }
+    if !flags.contains(&flag) {
+        flags.push(flag);
+    }
+}
+
+fn flag_name(flag: &str) -> &str {
+    flag.split_once('=').map_or(flag, |(name, _)| name)
+}
+
+fn is_repeatable_flag(flag: &str) -> bool {
+    REPEATABLE_FLAGS.contains(&flag_name(flag))
+}
+
+/// Reduces a Kotlin version such as `2.1.20-RC` to its `(major, minor)`.
+fn language_version(kotlin_version: &str) -> Option<(u32, u32)> {
+    let mut parts = kotlin_version.split(['.', '-']);
+    let major = parts.next()?.parse::<u32>().ok()?;
+    let minor = parts.next()?.parse::<u32>().ok()?;
+    Some((major, minor))
+}
+
+/// Matches both `-flag=value` and `-flag value` forms.
+fn has_flag(flags: &[String], name: &str) -> bool {
+    flags.iter().any(|flag| {
+        flag == name
+            || flag
+                .strip_prefix(name)
+                .is_some_and(|rest| rest.starts_with('='))
+    })
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -46,6 +383,10 @@ impl ProjectModel {
             jdk_home: None,
             has_compose: false,
             generated_source_roots: Vec::new(),
+            code_style: None,
+            script_classpath: Vec::new(),
+            classpath_sources: Vec::new(),
+            kapt_classpath: Vec::new(),
         }
     }
 }
@@ -163,25 +504,177 @@ fn collect_nested_build_roots(
 pub fn resolve_project(root: &Path, config: &Config, offline: bool) -> Result<ProjectModel, Error> {
     // Check for manual configuration first
     let manual_config = root.join(".kotlin-analyzer.json");
-    if manual_config.exists() {
+    let model = if manual_config.exists() {
         tracing::info!("using manual project configuration from .kotlin-analyzer.json");
-        return resolve_manual_config(&manual_config, root, config);
+        resolve_manual_config(&manual_config, root, config)?
+    } else {
+        match detect_build_system(root) {
+            BuildSystem::Gradle => resolve_gradle_project(root, config, offline)?,
+            BuildSystem::Maven => resolve_maven_project(root, config, offline)?,
+            BuildSystem::None => {
+                tracing::info!("no build system found, using stdlib-only analysis");
+                let mut model = ProjectModel::no_build_system(root.to_path_buf());
+                // Find .kt source files in the root
+                model.source_roots = find_kotlin_source_roots(root);
+                model.compiler_flags = config.compiler_flags.clone();
+                model
+            }
+        }
+    };
+
+    Ok(apply_config_supplements(model, config))
+}
+
+/// Merges `additionalSourceRoots` and `additionalClasspath` into an
+/// automatically resolved model, expands `kaptClasspath`, finds sources jars
+/// next to the classpath jars, then applies nested options files. Runs
+/// after every resolution, so the supplements survive re-resolution; entries
+/// already present are skipped.
+fn apply_config_supplements(mut model: ProjectModel, config: &Config) -> ProjectModel {
+    for root in &config.additional_source_roots {
+        let path = resolve_against(&model.project_root, root);
+        if !path.is_dir() {
+            tracing::warn!("additional source root {} does not exist", path.display());
+            continue;
+        }
+        if !model.source_roots.contains(&path) {
+            model.source_roots.push(path);
+        }
     }
 
-    let build_system = detect_build_system(root);
+    for entry in &config.additional_classpath {
+        let paths = expand_classpath_entry(&model.project_root, entry);
+        if paths.is_empty() {
+            tracing::warn!("additional classpath entry {:?} matched nothing", entry);
+        }
+        for path in paths {
+            if !model.classpath.contains(&path) {
+                model.classpath.push(path);
+            }
+        }
+    }
+    model.kapt_classpath = config
+        .kapt_classpath
+        .iter()
+        .flat_map(|entry| expand_classpath_entry(&model.project_root, entry))
+        .collect();
+    attach_sibling_sources(&mut model);
+    apply_options_overrides(model, config)
+}
 
-    match build_system {
-        BuildSystem::Gradle => resolve_gradle_project(root, config, offline),
-        BuildSystem::Maven => resolve_maven_project(root, config, offline),
-        BuildSystem::None => {
-            tracing::info!("no build system found, using stdlib-only analysis");
-            let mut model = ProjectModel::no_build_system(root.to_path_buf());
-            // Find .kt source files in the root
-            model.source_roots = find_kotlin_source_roots(root);
-            model.compiler_flags = config.compiler_flags.clone();
-            Ok(model)
+/// Adds the `-sources` jar lying next to a classpath jar, where Maven
+/// repositories and hand-made `libs/` directories keep them. Gradle's cache
+/// stores them apart, so the init script reports those.
+fn attach_sibling_sources(model: &mut ProjectModel) {
+    for jar in &model.classpath {
+        if jar.extension().is_none_or(|extension| extension != "jar") {
+            continue;
+        }
+        let Some(stem) = jar.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let sources = jar.with_file_name(format!("{stem}-sources.jar"));
+        if sources.is_file() && !model.classpath_sources.contains(&sources) {
+            model.classpath_sources.push(sources);
+        }
+    }
+}
+
+/// Applies nested `.kotlin-analyzer.json` files to the source roots they
+/// cover: excluded roots are dropped, and each root's compiler flags join
+/// the session's. One session serves all modules, so where two modules set
+/// different values for a flag the first one is kept.
+fn apply_options_overrides(mut model: ProjectModel, config: &Config) -> ProjectModel {
+    let root = model.project_root.clone();
+    let mut flags = model.compiler_flags.clone();
+    model.source_roots.retain(|source_root| {
+        let effective = resolve_effective_config(source_root, &root, config);
+        if effective.excluded {
+            tracing::info!("source root {} is excluded by {}", source_root.display(), OPTIONS_FILE);
+            return false;
+        }
+        for flag in effective.compiler_flags {
+            if flags.contains(&flag) {
+                continue;
+            }
+            if !is_repeatable_flag(&flag) && has_flag(&flags, flag_name(&flag)) {
+                tracing::warn!(
+                    "{} under {} sets {}, which conflicts with another module; keeping the first value",
+                    OPTIONS_FILE,
+                    source_root.display(),
+                    flag
+                );
+                continue;
+            }
+            flags.push(flag);
+        }
+        true
+    });
+    model.compiler_flags = flags;
+    model
+}
+
+/// Expands an `additionalClasspath` or `kaptClasspath` entry: a jar or
+/// directory, or a glob such as `libs/**/*.jar`, relative to the project root
+/// unless absolute.
+pub(crate) fn expand_classpath_entry(root: &Path, entry: &str) -> Vec<PathBuf> {
+    let path = resolve_against(root, entry);
+    if !entry.contains(['*', '?', '[', '{']) {
+        return if path.exists() {
+            vec![path]
+        } else {
+            Vec::new()
+        };
+    }
+
+    let glob = match GlobBuilder::new(&path.to_string_lossy())
+        .literal_separator(true)
+        .build()
+    {
+        Ok(glob) => glob.compile_matcher(),
+        Err(e) => {
+            tracing::warn!("ignoring invalid classpath pattern {:?}: {}", entry, e);
+            return Vec::new();
+        }
+    };
+    // Walk from the deepest directory without glob characters.
+    let base: PathBuf = path
+        .components()
+        .take_while(|c| {
+            !c.as_os_str()
+                .to_string_lossy()
+                .contains(['*', '?', '[', '{'])
+        })
+        .collect();
+
+    let mut matches = Vec::new();
+    let mut pending = vec![base];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if glob.is_match(&path) {
+                matches.push(path.clone());
+            }
+            if path.is_dir() {
+                pending.push(path);
+            }
         }
     }
+    matches.sort();
+    matches
+}
+
+/// Resolves a configured path against the project root unless it is absolute.
+fn resolve_against(root: &Path, path: &str) -> PathBuf {
+    let path = PathBuf::from(path);
+    if path.is_absolute() {
+        path
+    } else {
+        root.join(path)
+    }
 }
 
 /// Resolves the project model with a fallback strategy for robustness.
@@ -194,7 +687,7 @@ pub fn resolve_project(root: &Path, config: &Config, offline: bool) -> Result<Pr
 /// 5. If no cache exists, just run online with timeout
 /// 6. If everything fails, return error
 pub fn resolve_project_with_fallback(root: &Path, config: &Config) -> Result<ProjectModel, Error> {
-    let cache_dir = root.join(".kotlin-analyzer");
+    let cache_dir = cache_dir(root);
     let cache_exists = cache_dir.join("project-model.json").exists();
 
     // Fast path: try offline if cache exists
@@ -231,7 +724,7 @@ pub fn resolve_project_with_fallback(root: &Path, config: &Config) -> Result<Pro
             // Fall back to cache if available
             if let Some(cached_model) = load_cache(&cache_dir) {
                 tracing::info!("falling back to cached project model");
-                Ok(cached_model)
+                Ok(apply_config_supplements(cached_model, config))
             } else {
                 tracing::error!("no cache available, project resolution failed");
                 Err(e)
@@ -296,6 +789,41 @@ allprojects {
                 sb.append("CLASSPATH_ERROR=no resolvable compile classpaths found\n")
             }
 
+            // Sources jars of the classpath libraries, for their documentation.
+            // Opt-in: resolving them may download every library's sources.
+            if (jpe != null && project.findProperty("kotlinAnalyzerLibrarySources") == "true") {
+                try {
+                    def componentIds = new LinkedHashSet()
+                    jpe.sourceSets.each { sourceSet ->
+                        try {
+                            def configName = sourceSet.compileClasspathConfigurationName
+                            project.configurations.getByName(configName).incoming.resolutionResult.allComponents.each { component ->
+                                if (component.id instanceof org.gradle.api.artifacts.component.ModuleComponentIdentifier) {
+                                    componentIds.add(component.id)
+                                }
+                            }
+                        } catch (Exception e) {
+                            // Some source set classpaths may not be resolvable
+                        }
+                    }
+                    def seenSources = new LinkedHashSet()
+                    project.dependencies.createArtifactResolutionQuery()
+                        .forComponents(componentIds)
+                        .withArtifacts(org.gradle.jvm.JvmLibrary, org.gradle.language.base.artifact.SourcesArtifact)
+                        .execute()
+                        .resolvedComponents.each { component ->
+                            component.getArtifacts(org.gradle.language.base.artifact.SourcesArtifact).each { artifact ->
+                                if (artifact instanceof org.gradle.api.artifacts.result.ResolvedArtifactResult
+                                        && seenSources.add(artifact.file.absolutePath)) {
+                                    sb.append("CLASSPATH_SOURCES=${artifact.file.absolutePath}\n")
+                                }
+                            }
+                        }
+                } catch (Exception e) {
+                    // Sources are optional; hover just shows no documentation
+                }
+            }
+
             // Compiler flags — try multiple APIs for compatibility
             def flagsFound = new LinkedHashSet()
             // 1. New API: task.compilerOptions (Kotlin 1.8+)
@@ -355,6 +883,29 @@ allprojects {
             def kspDir = project.layout.buildDirectory.dir("generated/ksp/main/kotlin").get().asFile
             if (kspDir.exists()) sb.append("GENERATED_SOURCE_ROOT=${kspDir.absolutePath}\n")
 
+            // Build script classpath: the Gradle API jars plus the plugins on
+            // the buildscript classpath, used to analyze .gradle.kts files.
+            if (project == project.rootProject) {
+                def seenScriptJars = new LinkedHashSet()
+                def gradleLib = new File(project.gradle.gradleHomeDir, "lib")
+                [gradleLib, new File(gradleLib, "plugins")].each { dir ->
+                    dir.listFiles()?.findAll { it.name.endsWith(".jar") }?.each { jar ->
+                        if (seenScriptJars.add(jar.absolutePath)) {
+                            sb.append("SCRIPT_CLASSPATH=${jar.absolutePath}\n")
+                        }
+                    }
+                }
+                try {
+                    project.buildscript.configurations.getByName("classpath").resolve().each { file ->
+                        if (seenScriptJars.add(file.absolutePath)) {
+                            sb.append("SCRIPT_CLASSPATH=${file.absolutePath}\n")
+                        }
+                    }
+                } catch (Exception e) {
+                    // buildscript classpath may not be resolvable
+                }
+            }
+
             sb.append("---KOTLIN-ANALYZER-END---\n")
             println(sb.toString())
         }
@@ -384,6 +935,9 @@ fn resolve_gradle_project(
     if offline {
         args.push("--offline".to_string());
     }
+    if config.download_library_sources {
+        args.push("-PkotlinAnalyzerLibrarySources=true".to_string());
+    }
 
     // Execute with timeout
     let output = match execute_with_timeout(&gradlew, &args, root, Duration::from_secs(60)) {
@@ -409,7 +963,100 @@ fn resolve_gradle_project(
     }
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-    parse_gradle_output(&stdout, root, config)
+    let mut model = parse_gradle_output(&stdout, root, config)?;
+    let properties = read_gradle_properties(root, gradle_user_home().as_deref());
+    apply_gradle_properties(&mut model, &properties);
+    Ok(model)
+}
+
+/// Analysis-relevant settings read from `gradle.properties`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GradleProperties {
+    /// `kotlin.code.style`, used to pick a default formatting style.
+    pub code_style: Option<String>,
+    /// Whitespace-separated flags from `kotlin.analyzer.compilerFlags`.
+    pub compiler_flags: Vec<String>,
+}
+
+/// Reads the project's `gradle.properties` and the one in the Gradle user
+/// home. As in Gradle, the user home file takes precedence.
+pub fn read_gradle_properties(root: &Path, gradle_user_home: Option<&Path>) -> GradleProperties {
+    let mut entries = HashMap::new();
+    let files = std::iter::once(root.join("gradle.properties"))
+        .chain(gradle_user_home.map(|home| home.join("gradle.properties")));
+    for file in files {
+        if let Ok(content) = std::fs::read_to_string(&file) {
+            tracing::debug!("reading {}", file.display());
+            entries.extend(parse_properties(&content));
+        }
+    }
+
+    GradleProperties {
+        code_style: entries.remove("kotlin.code.style"),
+        compiler_flags: entries
+            .get("kotlin.analyzer.compilerFlags")
+            .map(|flags| flags.split_whitespace().map(str::to_string).collect())
+            .unwrap_or_default(),
+    }
+}
+
+/// Merges `gradle.properties` settings into the model. Flags already
+/// present (from the build or the LSP config) are not duplicated.
+fn apply_gradle_properties(model: &mut ProjectModel, properties: &GradleProperties) {
+    if properties.code_style.is_some() {
+        model.code_style = properties.code_style.clone();
+    }
+    for flag in &properties.compiler_flags {
+        if !model.compiler_flags.contains(flag) {
+            model.compiler_flags.push(flag.clone());
+        }
+    }
+}
+
+/// Returns `GRADLE_USER_HOME`, falling back to `~/.gradle`.
+fn gradle_user_home() -> Option<PathBuf> {
+    if let Some(home) = std::env::var_os("GRADLE_USER_HOME") {
+        return Some(PathBuf::from(home));
+    }
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+    Some(PathBuf::from(home).join(".gradle"))
+}
+
+/// Parses Java properties syntax: `key=value`, `key: value`, or
+/// `key value`, with `#`/`!` comments and backslash line continuations.
+fn parse_properties(content: &str) -> HashMap<String, String> {
+    let mut entries = HashMap::new();
+    let mut logical = String::new();
+
+    for line in content.lines() {
+        let line = if logical.is_empty() {
+            line.trim()
+        } else {
+            line.trim_start()
+        };
+        if logical.is_empty() && (line.is_empty() || line.starts_with('#') || line.starts_with('!'))
+        {
+            continue;
+        }
+        if let Some(continued) = line.strip_suffix('\\') {
+            logical.push_str(continued);
+            continue;
+        }
+        logical.push_str(line);
+
+        let split = logical
+            .find(|c: char| c == '=' || c == ':' || c.is_whitespace())
+            .unwrap_or(logical.len());
+        let key = &logical[..split];
+        let rest = logical[split..].trim_start();
+        let value = rest.strip_prefix(['=', ':']).unwrap_or(rest).trim();
+        if !key.is_empty() {
+            entries.insert(key.to_string(), value.to_string());
+        }
+        logical.clear();
+    }
+
+    entries
 }
 
 fn parse_gradle_output(output: &str, root: &Path, config: &Config) -> Result<ProjectModel, Error> {
@@ -423,6 +1070,10 @@ fn parse_gradle_output(output: &str, root: &Path, config: &Config) -> Result<Pro
         jdk_home: config.java_home.as_ref().map(PathBuf::from),
         has_compose: false,
         generated_source_roots: Vec::new(),
+        code_style: None,
+        script_classpath: Vec::new(),
+        classpath_sources: Vec::new(),
+        kapt_classpath: Vec::new(),
     };
 
     let mut in_section = false;
@@ -457,6 +1108,10 @@ fn parse_gradle_output(output: &str, root: &Path, config: &Config) -> Result<Pro
             model.has_compose = true;
         } else if let Some(path) = line.strip_prefix("GENERATED_SOURCE_ROOT=") {
             model.generated_source_roots.push(PathBuf::from(path));
+        } else if let Some(path) = line.strip_prefix("SCRIPT_CLASSPATH=") {
+            model.script_classpath.push(PathBuf::from(path));
+        } else if let Some(path) = line.strip_prefix("CLASSPATH_SOURCES=") {
+            model.classpath_sources.push(PathBuf::from(path));
         }
     }
 
@@ -501,28 +1156,42 @@ fn resolve_maven_project(
     config: &Config,
     offline: bool,
 ) -> Result<ProjectModel, Error> {
-    let mvn = if root.join("mvnw").exists() {
-        root.join("mvnw")
-    } else {
-        PathBuf::from("mvn")
-    };
+    let mvn = find_maven_wrapper(root);
+    if !command_available(&mvn) {
+        return Err(ProjectError::MavenFailed(format!(
+            "`{}` not found; install Maven, add a Maven wrapper (mvnw) to the project, \
+             or describe the project in .kotlin-analyzer.json",
+            mvn.display()
+        ))
+        .into());
+    }
 
-    // Build command arguments
+    // The dependency plugin writes the classpath to a file; a temp file works on
+    // every platform, unlike /dev/stdout. It is created exclusively with owner-only
+    // permissions and removed when dropped.
+    let output_file = tempfile::Builder::new()
+        .prefix("kotlin-analyzer-classpath-")
+        .suffix(".txt")
+        .tempfile()
+        .map_err(|e| ProjectError::MavenFailed(format!("cannot create temp file: {e}")))?;
     let mut args = vec![
         "dependency:build-classpath".to_string(),
         "-DincludeScope=compile".to_string(),
-        "-Dmdep.outputFile=/dev/stdout".to_string(),
+        format!("-Dmdep.outputFile={}", output_file.path().display()),
         "-q".to_string(),
     ];
     if offline {
         args.push("-o".to_string());
     }
 
-    let output = execute_with_timeout(&mvn, &args, root, Duration::from_secs(60))?;
+    let output = execute_with_timeout(&mvn, &args, root, Duration::from_secs(60));
+    let contents = std::fs::read_to_string(output_file.path());
+    drop(output_file);
+    let output = output?;
 
     if !output.status.success() {
         let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(ProjectError::ClasspathExtraction(format!(
+        return Err(ProjectError::MavenFailed(format!(
             "Maven exited with {}: {}",
             output.status,
             stderr.chars().take(500).collect::<String>()
@@ -530,12 +1199,12 @@ fn resolve_maven_project(
         .into());
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let classpath: Vec<PathBuf> = stdout
-        .lines()
-        .flat_map(|line| line.split(':'))
-        .filter(|p| !p.is_empty() && Path::new(p).exists())
-        .map(PathBuf::from)
+    let contents = contents.map_err(|e| {
+        ProjectError::ClasspathExtraction(format!("failed to read Maven classpath output: {e}"))
+    })?;
+    let classpath: Vec<PathBuf> = split_classpath(&contents, cfg!(windows))
+        .into_iter()
+        .filter(|p| p.exists())
         .collect();
 
     let mut model = ProjectModel {
@@ -548,6 +1217,10 @@ fn resolve_maven_project(
         jdk_home: config.java_home.as_ref().map(PathBuf::from),
         has_compose: false,
         generated_source_roots: Vec::new(),
+        code_style: None,
+        script_classpath: Vec::new(),
+        classpath_sources: Vec::new(),
+        kapt_classpath: Vec::new(),
     };
 
     // Filter to existing source roots
@@ -587,28 +1260,14 @@ fn resolve_manual_config(
     let source_roots: Vec<PathBuf> = manual
         .source_roots
         .iter()
-        .map(|p| {
-            let path = PathBuf::from(p);
-            if path.is_absolute() {
-                path
-            } else {
-                root.join(p)
-            }
-        })
+        .map(|p| resolve_against(root, p))
         .filter(|p| p.exists())
         .collect();
 
     let classpath: Vec<PathBuf> = manual
         .classpath
         .iter()
-        .map(|p| {
-            let path = PathBuf::from(p);
-            if path.is_absolute() {
-                path
-            } else {
-                root.join(p)
-            }
-        })
+        .map(|p| resolve_against(root, p))
         .filter(|p| p.exists())
         .collect();
 
@@ -634,23 +1293,92 @@ fn resolve_manual_config(
         jdk_home,
         has_compose: false,
         generated_source_roots: Vec::new(),
+        code_style: None,
+        script_classpath: Vec::new(),
+        classpath_sources: Vec::new(),
+        kapt_classpath: Vec::new(),
     })
 }
 
 fn find_gradle_wrapper(root: &Path) -> PathBuf {
-    let gradlew = if cfg!(target_os = "windows") {
-        root.join("gradlew.bat")
-    } else {
-        root.join("gradlew")
+    build_tool_command(root, BuildTool::Gradle, cfg!(windows))
+}
+
+fn find_maven_wrapper(root: &Path) -> PathBuf {
+    build_tool_command(root, BuildTool::Maven, cfg!(windows))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum BuildTool {
+    Gradle,
+    Maven,
+}
+
+/// Returns the project's wrapper script if present, otherwise the global
+/// launcher. On Windows both are batch scripts, which `Command` only finds
+/// with their extension.
+fn build_tool_command(root: &Path, tool: BuildTool, windows: bool) -> PathBuf {
+    let (wrapper, global) = match (tool, windows) {
+        (BuildTool::Gradle, false) => ("gradlew", "gradle"),
+        (BuildTool::Gradle, true) => ("gradlew.bat", "gradle.bat"),
+        (BuildTool::Maven, false) => ("mvnw", "mvn"),
+        (BuildTool::Maven, true) => ("mvnw.cmd", "mvn.cmd"),
     };
 
-    if gradlew.exists() {
-        gradlew
+    let wrapper = root.join(wrapper);
+    if wrapper.exists() {
+        wrapper
     } else {
-        PathBuf::from("gradle")
+        PathBuf::from(global)
     }
 }
 
+/// Splits a classpath written by Maven. Windows always uses the `;` path
+/// separator, as does any `;`-separated content; otherwise entries are split on
+/// `:`, rejoining drive letters so a lone `C:\...` entry survives.
+fn split_classpath(contents: &str, windows: bool) -> Vec<PathBuf> {
+    let contents = contents.trim();
+    if windows || contents.contains(';') {
+        return contents
+            .split(';')
+            .map(str::trim)
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .collect();
+    }
+
+    let mut entries: Vec<String> = Vec::new();
+    let mut pending_drive: Option<&str> = None;
+    for part in contents.split(':') {
+        let part = part.trim();
+        if let Some(drive) = pending_drive.take() {
+            if part.starts_with(['\\', '/']) {
+                entries.push(format!("{drive}:{part}"));
+                continue;
+            }
+            entries.push(drive.to_string());
+        }
+        if part.len() == 1 && part.chars().all(|c| c.is_ascii_alphabetic()) {
+            pending_drive = Some(part);
+        } else if !part.is_empty() {
+            entries.push(part.to_string());
+        }
+    }
+    entries.extend(pending_drive.map(str::to_string));
+    entries.into_iter().map(PathBuf::from).collect()
+}
+
+/// Returns true if `program` can be launched: an existing file when it is a
+/// path (such as a wrapper script), otherwise an entry on `PATH`.
+fn command_available(program: &Path) -> bool {
+    if program.components().count() > 1 {
+        return program.is_file();
+    }
+    std::env::var_os("PATH")
+        .map(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
 fn find_kotlin_source_roots(root: &Path) -> Vec<PathBuf> {
     let candidates = [
         root.join("src/main/kotlin"),
@@ -661,6 +1389,20 @@ fn find_kotlin_source_roots(root: &Path) -> Vec<PathBuf> {
     candidates.into_iter().filter(|p| p.exists()).collect()
 }
 
+/// Directory holding the cached project model of the project at `root`.
+pub fn cache_dir(root: &Path) -> PathBuf {
+    root.join(".kotlin-analyzer")
+}
+
+/// Deletes the cache directory, so the next resolution starts from scratch.
+/// A missing directory is not an error.
+pub fn clear_cache(cache_dir: &Path) -> Result<(), Error> {
+    match std::fs::remove_dir_all(cache_dir) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::Io(e)),
+        _ => Ok(()),
+    }
+}
+
 /// Saves the project model to a cache file.
 pub fn save_cache(model: &ProjectModel, cache_dir: &Path) -> Result<(), Error> {
     std::fs::create_dir_all(cache_dir).map_err(Error::Io)?;
@@ -753,6 +1495,155 @@ mod tests {
     use std::fs;
     use tempfile::TempDir;
 
+    #[test]
+    fn exclude_matcher_supports_relative_and_absolute_globs() {
+        let patterns = vec![
+            "**/build/**".to_string(),
+            "**/.gradle/**".to_string(),
+            "vendor/*.kt".to_string(),
+            "/opt/fixtures/**".to_string(),
+        ];
+        let excludes = ExcludeMatcher::new(&patterns, Some(Path::new("/project")), &[]);
+
+        assert!(excludes.is_excluded(Path::new("/project/build/generated/Foo.kt")));
+        assert!(excludes.is_excluded(Path::new("/project/app/build/Foo.kt")));
+        assert!(excludes.is_excluded(Path::new("/project/.gradle/kotlin/Foo.kt")));
+        assert!(excludes.is_excluded(Path::new("/project/vendor/Lib.kt")));
+        assert!(excludes.is_excluded(Path::new("/opt/fixtures/Huge.kt")));
+        assert!(!excludes.is_excluded(Path::new("/project/vendor/nested/Lib.kt")));
+        assert!(!excludes.is_excluded(Path::new("/project/src/main/kotlin/Foo.kt")));
+    }
+
+    #[test]
+    fn read_only_roots_match_whole_path_components() {
+        let roots = ReadOnlyRoots::new(
+            &["included/core".to_string(), "/opt/shared/src".to_string()],
+            Some(Path::new("/project")),
+        );
+        assert!(roots.contains(Path::new("/project/included/core/src/Foo.kt")));
+        assert!(roots.contains(Path::new("/opt/shared/src/Bar.kt")));
+        assert!(!roots.contains(Path::new("/project/included/core-extras/Foo.kt")));
+        assert!(!roots.contains(Path::new("/project/src/main/kotlin/Foo.kt")));
+    }
+
+    #[test]
+    fn exclude_matcher_skips_invalid_patterns() {
+        let patterns = vec!["[".to_string(), "**/build/**".to_string()];
+        let excludes = ExcludeMatcher::new(&patterns, None, &[]);
+        assert!(excludes.is_excluded(Path::new("/project/build/Foo.kt")));
+        assert!(!excludes.is_excluded(Path::new("/project/src/Foo.kt")));
+    }
+
+    #[test]
+    fn analysis_compiler_flags_derive_language_version() {
+        let mut model = ProjectModel::no_build_system(PathBuf::from("/project"));
+        model.compiler_flags = vec!["-opt-in=kotlin.RequiresOptIn".into()];
+        assert_eq!(model.analysis_compiler_flags(None), model.compiler_flags);
+
+        model.kotlin_version = Some("2.1".into());
+        assert_eq!(
+            model.analysis_compiler_flags(None),
+            vec![
+                "-opt-in=kotlin.RequiresOptIn",
+                "-language-version=2.1",
+                "-api-version=2.1"
+            ]
+        );
+
+        model.kotlin_version = Some("2.1.20-RC".into());
+        model.compiler_flags = vec!["-api-version=2.0".into()];
+        assert_eq!(
+            model.analysis_compiler_flags(None),
+            vec!["-api-version=2.0", "-language-version=2.1"]
+        );
+    }
+
+    #[test]
+    fn analysis_compiler_flags_clamp_to_the_sidecar_version() {
+        let mut model = ProjectModel::no_build_system(PathBuf::from("/project"));
+        model.kotlin_version = Some("2.3.0".into());
+        assert_eq!(
+            model.analysis_compiler_flags(Some("2.2.21")),
+            vec!["-language-version=2.2", "-api-version=2.2"]
+        );
+
+        model.kotlin_version = Some("2.1.0".into());
+        assert_eq!(
+            model.analysis_compiler_flags(Some("2.2.21")),
+            vec!["-language-version=2.1", "-api-version=2.1"]
+        );
+    }
+
+    #[test]
+    fn analysis_compiler_flags_keep_explicit_language_version() {
+        let mut model = ProjectModel::no_build_system(PathBuf::from("/project"));
+        model.kotlin_version = Some("2.1".into());
+        model.compiler_flags = vec!["-language-version".into(), "2.0".into()];
+        assert_eq!(
+            model.analysis_compiler_flags(None),
+            vec!["-language-version", "2.0"]
+        );
+
+        model.compiler_flags = vec!["-language-version=2.0".into()];
+        assert_eq!(
+            model.analysis_compiler_flags(None),
+            vec!["-language-version=2.0"]
+        );
+    }
+
+    #[test]
+    fn read_gradle_properties_merges_project_and_user_home() {
+        let project = TempDir::new().unwrap();
+        fs::write(
+            project.path().join("gradle.properties"),
+            r#"# Project-wide Gradle settings
+org.gradle.jvmargs=-Xmx2048m -Dfile.encoding=UTF-8
+kotlin.code.style=official
+kotlin.analyzer.compilerFlags = -Xcontext-parameters \
+    -opt-in=kotlin.RequiresOptIn
+"#,
+        )
+        .unwrap();
+
+        let properties = read_gradle_properties(project.path(), None);
+        assert_eq!(properties.code_style.as_deref(), Some("official"));
+        assert_eq!(
+            properties.compiler_flags,
+            vec!["-Xcontext-parameters", "-opt-in=kotlin.RequiresOptIn"]
+        );
+
+        let user_home = TempDir::new().unwrap();
+        fs::write(
+            user_home.path().join("gradle.properties"),
+            "kotlin.code.style: obsolete\n",
+        )
+        .unwrap();
+        let properties = read_gradle_properties(project.path(), Some(user_home.path()));
+        assert_eq!(properties.code_style.as_deref(), Some("obsolete"));
+        assert_eq!(properties.compiler_flags.len(), 2);
+    }
+
+    #[test]
+    fn apply_gradle_properties_does_not_duplicate_flags() {
+        let mut model = ProjectModel::no_build_system(PathBuf::from("/project"));
+        model.compiler_flags = vec!["-Xcontext-parameters".into()];
+        apply_gradle_properties(
+            &mut model,
+            &GradleProperties {
+                code_style: Some("official".into()),
+                compiler_flags: vec![
+                    "-Xcontext-parameters".into(),
+                    "-Xmulti-dollar-interpolation".into(),
+                ],
+            },
+        );
+        assert_eq!(model.code_style.as_deref(), Some("official"));
+        assert_eq!(
+            model.compiler_flags,
+            vec!["-Xcontext-parameters", "-Xmulti-dollar-interpolation"]
+        );
+    }
+
     #[test]
     fn detect_gradle_kts() {
         let dir = TempDir::new().unwrap();
@@ -780,6 +1671,126 @@ mod tests {
         assert_eq!(detect_build_system(dir.path()), BuildSystem::None);
     }
 
+    #[test]
+    fn contains_source_checks_source_and_generated_roots() {
+        let mut model = ProjectModel::no_build_system(PathBuf::from("/project"));
+        assert!(model.contains_source(Path::new("/anywhere/Foo.kt")));
+
+        model.source_roots = vec![PathBuf::from("/project/src/main/kotlin")];
+        model.generated_source_roots =
+            vec![PathBuf::from("/project/build/generated/ksp/main/kotlin")];
+        assert!(model.contains_source(Path::new("/project/src/main/kotlin/app/Main.kt")));
+        assert!(model.contains_source(Path::new(
+            "/project/build/generated/ksp/main/kotlin/app/Dao_Impl.kt"
+        )));
+        assert!(!model.contains_source(Path::new("/project/build/tmp/Foo.kt")));
+        assert!(!model.contains_source(Path::new("/project/src/main/kotlinx/Foo.kt")));
+    }
+
+    #[test]
+    fn package_for_follows_the_directory_under_the_source_root() {
+        let mut model = ProjectModel::no_build_system(PathBuf::from("/project"));
+        model.source_roots = vec![
+            PathBuf::from("/project/src/main/kotlin"),
+            PathBuf::from("/project/src/main/kotlin/generated"),
+        ];
+        assert_eq!(
+            model.package_for(Path::new("/project/src/main/kotlin/com/acme/app/Main.kt")),
+            Some("com.acme.app".to_string())
+        );
+        assert_eq!(
+            model.package_for(Path::new("/project/src/main/kotlin/generated/api/Dto.kt")),
+            Some("api".to_string())
+        );
+        assert_eq!(
+            model.package_for(Path::new("/project/src/main/kotlin/Main.kt")),
+            None
+        );
+        assert_eq!(
+            model.package_for(Path::new("/project/src/main/kotlin/my-dir/Main.kt")),
+            None
+        );
+        assert_eq!(model.package_for(Path::new("/elsewhere/app/Main.kt")), None);
+    }
+
+    #[test]
+    fn split_classpath_handles_windows_and_unix_separators() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("classpath.txt");
+
+        fs::write(&file, "C:\\m2\\kotlin-stdlib.jar;D:\\libs\\guava.jar;\r\n").unwrap();
+        assert_eq!(
+            split_classpath(&fs::read_to_string(&file).unwrap(), false),
+            vec![
+                PathBuf::from("C:\\m2\\kotlin-stdlib.jar"),
+                PathBuf::from("D:\\libs\\guava.jar"),
+            ]
+        );
+
+        fs::write(&file, "/m2/kotlin-stdlib.jar:/libs/guava.jar\n").unwrap();
+        assert_eq!(
+            split_classpath(&fs::read_to_string(&file).unwrap(), false),
+            vec![
+                PathBuf::from("/m2/kotlin-stdlib.jar"),
+                PathBuf::from("/libs/guava.jar"),
+            ]
+        );
+
+        assert_eq!(
+            split_classpath("C:\\m2\\kotlin-stdlib.jar", false),
+            vec![PathBuf::from("C:\\m2\\kotlin-stdlib.jar")]
+        );
+        assert!(split_classpath("", false).is_empty());
+    }
+
+    #[test]
+    fn split_classpath_keeps_windows_drive_letters_whole() {
+        let classpath = "C:\\Users\\dev\\.m2\\repository\\kotlin-stdlib-2.1.0.jar;\
+                         C:\\Users\\dev\\.m2\\repository\\annotations-13.0.jar";
+        let expected = vec![
+            PathBuf::from("C:\\Users\\dev\\.m2\\repository\\kotlin-stdlib-2.1.0.jar"),
+            PathBuf::from("C:\\Users\\dev\\.m2\\repository\\annotations-13.0.jar"),
+        ];
+        assert_eq!(split_classpath(classpath, true), expected);
+        assert_eq!(split_classpath(classpath, false), expected);
+        assert_eq!(
+            split_classpath("C:\\lib\\a.jar", true),
+            vec![PathBuf::from("C:\\lib\\a.jar")]
+        );
+    }
+
+    #[test]
+    fn build_tool_command_prefers_platform_wrapper() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(
+            build_tool_command(dir.path(), BuildTool::Maven, false),
+            PathBuf::from("mvn")
+        );
+        assert_eq!(
+            build_tool_command(dir.path(), BuildTool::Maven, true),
+            PathBuf::from("mvn.cmd")
+        );
+        assert_eq!(
+            build_tool_command(dir.path(), BuildTool::Gradle, true),
+            PathBuf::from("gradle.bat")
+        );
+
+        fs::write(dir.path().join("mvnw.cmd"), "").unwrap();
+        fs::write(dir.path().join("gradlew"), "").unwrap();
+        assert_eq!(
+            build_tool_command(dir.path(), BuildTool::Maven, true),
+            dir.path().join("mvnw.cmd")
+        );
+        assert_eq!(
+            build_tool_command(dir.path(), BuildTool::Maven, false),
+            PathBuf::from("mvn")
+        );
+        assert_eq!(
+            build_tool_command(dir.path(), BuildTool::Gradle, false),
+            dir.path().join("gradlew")
+        );
+    }
+
     #[test]
     fn parse_gradle_output_parses_all_sections() {
         let output = r#"
@@ -881,6 +1892,192 @@ COMPILER_FLAG=-Xcontext-parameters
         assert_eq!(model.compiler_flags.len(), 2);
     }
 
+    #[test]
+    fn nested_options_files_override_innermost_first() {
+        let dir = TempDir::new().unwrap();
+        let service = dir.path().join("services/billing");
+        let legacy = dir.path().join("services/legacy/src");
+        fs::create_dir_all(service.join("src")).unwrap();
+        fs::create_dir_all(&legacy).unwrap();
+        fs::write(
+            dir.path().join(".kotlin-analyzer.json"),
+            r#"{"sourceRoots": ["services/billing/src", "services/legacy/src"], "compilerFlags": ["-language-version=2.0", "-opt-in=kotlin.RequiresOptIn"]}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("services/.kotlin-analyzer.json"),
+            r#"{"compilerFlags": ["-language-version=2.1", "-Xcontext-parameters"]}"#,
+        )
+        .unwrap();
+        fs::write(
+            service.join(".kotlin-analyzer.json"),
+            r#"{"compilerFlags": ["-language-version=2.2", "-opt-in=kotlin.ExperimentalStdlibApi"]}"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("services/legacy/.kotlin-analyzer.json"),
+            r#"{"exclude": true}"#,
+        )
+        .unwrap();
+        let config = Config {
+            compiler_flags: vec!["-language-version=1.9".into(), "-Xdebug".into()],
+            ..Config::default()
+        };
+
+        let effective =
+            resolve_effective_config(&service.join("src/Invoice.kt"), dir.path(), &config);
+        assert_eq!(
+            effective.compiler_flags,
+            vec![
+                "-opt-in=kotlin.RequiresOptIn",
+                "-Xcontext-parameters",
+                "-language-version=2.2",
+                "-opt-in=kotlin.ExperimentalStdlibApi",
+                "-Xdebug",
+            ]
+        );
+        assert!(!effective.excluded);
+        assert!(resolve_effective_config(&legacy.join("Old.kt"), dir.path(), &config).excluded);
+
+        let kept = legacy.join("kept");
+        fs::create_dir_all(&kept).unwrap();
+        fs::write(kept.join(".kotlin-analyzer.json"), r#"{"exclude": false}"#).unwrap();
+        let excludes = ExcludeMatcher::new(&[], Some(dir.path()), &[]);
+        assert!(excludes.is_excluded(&legacy.join("Old.kt")));
+        assert!(!excludes.is_excluded(&kept.join("Kept.kt")));
+        assert!(!excludes.is_excluded(&service.join("src/Invoice.kt")));
+        assert!(excludes.is_for(&[], Some(dir.path()), &[]));
+
+        // The excluded module's root is dropped from the session.
+        let model = resolve_project(dir.path(), &config, false).unwrap();
+        assert_eq!(model.source_roots, vec![service.join("src")]);
+        assert!(model
+            .compiler_flags
+            .contains(&"-Xcontext-parameters".to_string()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn nested_excludes_skip_symlinks_and_excluded_subtrees() {
+        let dir = TempDir::new().unwrap();
+        let vendor = dir.path().join("vendor/lib");
+        let module = dir.path().join("module");
+        fs::create_dir_all(&vendor).unwrap();
+        fs::create_dir_all(&module).unwrap();
+        fs::write(
+            vendor.join(".kotlin-analyzer.json"),
+            r#"{"exclude": false}"#,
+        )
+        .unwrap();
+        fs::write(module.join(".kotlin-analyzer.json"), r#"{"exclude": true}"#).unwrap();
+        // A cycle back to the root must not be followed.
+        std::os::unix::fs::symlink(dir.path(), module.join("loop")).unwrap();
+
+        let excludes = ExcludeMatcher::new(&["vendor/**".into()], Some(dir.path()), &[]);
+        assert_eq!(excludes.nested, vec![(module.clone(), true)]);
+        assert!(excludes.is_excluded(&vendor.join("Lib.kt")));
+    }
+
+    #[test]
+    fn additional_source_roots_merge_into_resolved_model() {
+        let dir = TempDir::new().unwrap();
+        let generated = dir.path().join("build/generated/custom");
+        fs::create_dir_all(&generated).unwrap();
+
+        let config = Config {
+            additional_source_roots: vec![
+                "build/generated/custom".into(),
+                generated.to_string_lossy().into_owned(),
+                "missing".into(),
+            ],
+            ..Config::default()
+        };
+        let model = resolve_project(dir.path(), &config, false).unwrap();
+        assert_eq!(
+            model
+                .source_roots
+                .iter()
+                .filter(|root| **root == generated)
+                .count(),
+            1
+        );
+        assert!(!model.source_roots.contains(&dir.path().join("missing")));
+    }
+
+    #[test]
+    fn kapt_classpath_is_resolved_against_the_project_root() {
+        let dir = TempDir::new().unwrap();
+        let classes = dir.path().join("build/tmp/kapt3/classes/main");
+        fs::create_dir_all(&classes).unwrap();
+        let config = Config {
+            kapt_classpath: vec![
+                "build/tmp/kapt3/classes/main".into(),
+                "build/missing".into(),
+            ],
+            ..Config::default()
+        };
+
+        let model = ProjectModel::no_build_system(dir.path().to_path_buf());
+        let model = apply_config_supplements(model, &config);
+        assert_eq!(model.kapt_classpath, vec![classes]);
+        assert!(!model.classpath.contains(&model.kapt_classpath[0]));
+    }
+
+    #[test]
+    fn additional_classpath_survives_gradle_re_resolution() {
+        let dir = TempDir::new().unwrap();
+        let libs = dir.path().join("libs/processors");
+        fs::create_dir_all(&libs).unwrap();
+        fs::write(libs.join("a-runtime.jar"), "").unwrap();
+        fs::write(libs.join("b-runtime.jar"), "").unwrap();
+        fs::write(libs.join("notes.txt"), "").unwrap();
+        let extra = dir.path().join("extra.jar");
+        fs::write(&extra, "").unwrap();
+
+        let config = Config {
+            additional_classpath: vec![
+                "libs/**/*.jar".into(),
+                extra.to_string_lossy().into_owned(),
+                "extra.jar".into(),
+                "missing/*.jar".into(),
+            ],
+            ..Config::default()
+        };
+        let gradle_output = |classpath: &str| {
+            format!(
+                "---KOTLIN-ANALYZER-START---\nCLASSPATH={classpath}\n---KOTLIN-ANALYZER-END---\n"
+            )
+        };
+        let expected = |first: &str| {
+            vec![
+                PathBuf::from(first),
+                libs.join("a-runtime.jar"),
+                libs.join("b-runtime.jar"),
+                extra.clone(),
+            ]
+        };
+
+        let model = parse_gradle_output(
+            &gradle_output("/lib/kotlin-stdlib-2.1.20.jar"),
+            dir.path(),
+            &config,
+        )
+        .unwrap();
+        let model = apply_config_supplements(model, &config);
+        assert_eq!(model.classpath, expected("/lib/kotlin-stdlib-2.1.20.jar"));
+
+        // A re-resolve after a build file change yields a fresh Gradle model;
+        // the configured entries are applied again rather than lost.
+        let model = parse_gradle_output(
+            &gradle_output("/lib/kotlin-stdlib-2.2.0.jar"),
+            dir.path(),
+            &config,
+        )
+        .unwrap();
+        let model = apply_config_supplements(model, &config);
+        assert_eq!(model.classpath, expected("/lib/kotlin-stdlib-2.2.0.jar"));
+    }
+
     #[test]
     fn parse_gradle_output_multi_module() {
         let output = r#"
@@ -930,10 +2127,12 @@ COMPILER_FLAG=-Xcontext-parameters
 ---KOTLIN-ANALYZER-START---
 SOURCE_ROOT=/project/app/src/main/kotlin
 HAS_COMPOSE=true
+SCRIPT_CLASSPATH=/gradle/lib/gradle-api.jar
 ---KOTLIN-ANALYZER-END---
 ---KOTLIN-ANALYZER-START---
 SOURCE_ROOT=/project/lib/src/main/kotlin
 GENERATED_SOURCE_ROOT=/project/lib/build/generated/ksp/main/kotlin
+CLASSPATH_SOURCES=/caches/spring-context-6.1.0-sources.jar
 ---KOTLIN-ANALYZER-END---
 "#;
         let config = Config::default();
@@ -941,6 +2140,30 @@ GENERATED_SOURCE_ROOT=/project/lib/build/generated/ksp/main/kotlin
         assert_eq!(model.source_roots.len(), 2);
         assert!(model.has_compose);
         assert_eq!(model.generated_source_roots.len(), 1);
+        assert_eq!(
+            model.script_classpath,
+            vec![PathBuf::from("/gradle/lib/gradle-api.jar")]
+        );
+        assert_eq!(
+            model.classpath_sources,
+            vec![PathBuf::from("/caches/spring-context-6.1.0-sources.jar")]
+        );
+    }
+
+    #[test]
+    fn sources_jars_next_to_classpath_jars_are_attached() {
+        let dir = TempDir::new().unwrap();
+        let with_sources = dir.path().join("guava-33.0.jar");
+        let sources = dir.path().join("guava-33.0-sources.jar");
+        let without_sources = dir.path().join("slf4j-api-2.0.jar");
+        for jar in [&with_sources, &sources, &without_sources] {
+            fs::write(jar, "").unwrap();
+        }
+        let mut model = ProjectModel::no_build_system(dir.path().to_path_buf());
+        model.classpath = vec![with_sources, without_sources];
+
+        let model = apply_config_supplements(model, &Config::default());
+        assert_eq!(model.classpath_sources, vec![sources]);
     }
 
     #[test]
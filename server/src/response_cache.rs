@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+
+use serde_json::Value;
+use tower_lsp::lsp_types::{Position, Url};
+
+/// Entries kept before the least recently used one is evicted.
+const CAPACITY: usize = 64;
+
+/// Identifies a position request against one version of a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheKey {
+    pub uri: Url,
+    pub version: i32,
+    pub line: u32,
+    pub character: u32,
+    pub method: &'static str,
+}
+
+impl CacheKey {
+    pub fn new(uri: &Url, version: i32, position: Position, method: &'static str) -> Self {
+        Self {
+            uri: uri.clone(),
+            version,
+            line: position.line,
+            character: position.character,
+            method,
+        }
+    }
+}
+
+/// Small LRU cache of sidecar results for position requests the editor
+/// tends to repeat (hover, definition, signature help). Keys include the
+/// document version, and the cache is cleared whenever any document changes,
+/// since a result can point into another file, and when the sidecar restarts.
+#[derive(Debug, Default)]
+pub struct ResponseCache {
+    /// Most recently used last.
+    entries: VecDeque<(CacheKey, Value)>,
+    /// The sidecar start count the entries were computed under.
+    sidecar_starts: u64,
+}
+
+impl ResponseCache {
+    pub fn get(&mut self, key: &CacheKey) -> Option<Value> {
+        let index = self.entries.iter().position(|(k, _)| k == key)?;
+        let entry = self.entries.remove(index)?;
+        let value = entry.1.clone();
+        self.entries.push_back(entry);
+        Some(value)
+    }
+
+    pub fn insert(&mut self, key: CacheKey, value: Value) {
+        self.entries.retain(|(k, _)| k != &key);
+        if self.entries.len() == CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((key, value));
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Drops every entry when the sidecar has started again since they
+    /// were cached, as given by `Bridge::start_count`.
+    pub fn track_sidecar_starts(&mut self, starts: u64) {
+        if starts != self.sidecar_starts {
+            self.entries.clear();
+            self.sidecar_starts = starts;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn evicts_least_recently_used_entry() {
+        let uri = Url::parse("file:///src/Main.kt").unwrap();
+        let key = |line| CacheKey::new(&uri, 1, Position::new(line, 0), "hover");
+        let mut cache = ResponseCache::default();
+        for line in 0..CAPACITY as u32 {
+            cache.insert(key(line), json!(line));
+        }
+
+        // Touching line 0 makes line 1 the eviction candidate.
+        assert_eq!(cache.get(&key(0)), Some(json!(0)));
+        cache.insert(key(CAPACITY as u32), json!("new"));
+        assert_eq!(cache.get(&key(1)), None);
+        assert_eq!(cache.get(&key(0)), Some(json!(0)));
+
+        let newer_version = CacheKey::new(&uri, 2, Position::new(0, 0), "hover");
+        assert_eq!(cache.get(&newer_version), None);
+    }
+
+    #[test]
+    fn sidecar_restart_drops_entries() {
+        let uri = Url::parse("file:///src/Main.kt").unwrap();
+        let key = CacheKey::new(&uri, 1, Position::new(0, 0), "hover");
+        let mut cache = ResponseCache::default();
+        cache.track_sidecar_starts(1);
+        cache.insert(key.clone(), json!("before"));
+
+        cache.track_sidecar_starts(1);
+        assert_eq!(cache.get(&key), Some(json!("before")));
+        cache.track_sidecar_starts(2);
+        assert_eq!(cache.get(&key), None);
+    }
+}
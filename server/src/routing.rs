@@ -0,0 +1,75 @@
+use std::task::{Context, Poll};
+
+use tower_lsp::jsonrpc::Request;
+use tower_service::Service;
+
+/// Custom method that answers `workspace/symbol`. tower-lsp's own handler
+/// can only return `SymbolInformation`, which always carries a range, so
+/// the request is answered by [`crate::server::KotlinLanguageServer::workspace_symbol`].
+pub const WORKSPACE_SYMBOL_METHOD: &str = "kotlin-analyzer/workspaceSymbol";
+
+/// Wraps the LSP service so standard methods whose tower-lsp handler
+/// cannot express the response we need reach a custom method instead.
+pub struct Rerouted<S> {
+    inner: S,
+}
+
+impl<S> Rerouted<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+}
+
+impl<S> Service<Request> for Rerouted<S>
+where
+    S: Service<Request>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        self.inner.call(reroute(request))
+    }
+}
+
+/// Renames the method of a request that has a custom replacement.
+fn reroute(request: Request) -> Request {
+    if request.method() != "workspace/symbol" {
+        return request;
+    }
+    let (_, id, params) = request.into_parts();
+    let mut builder = Request::build(WORKSPACE_SYMBOL_METHOD);
+    if let Some(id) = id {
+        builder = builder.id(id);
+    }
+    if let Some(params) = params {
+        builder = builder.params(params);
+    }
+    builder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reroute_renames_workspace_symbol_only() {
+        let request = Request::build("workspace/symbol")
+            .id(7)
+            .params(json!({ "query": "Cart" }))
+            .finish();
+        let rerouted = reroute(request);
+        assert_eq!(rerouted.method(), WORKSPACE_SYMBOL_METHOD);
+        assert_eq!(rerouted.id(), Some(&7.into()));
+        assert_eq!(rerouted.params(), Some(&json!({ "query": "Cart" })));
+
+        let hover = Request::build("textDocument/hover").id(8).finish();
+        assert_eq!(reroute(hover).method(), "textDocument/hover");
+    }
+}
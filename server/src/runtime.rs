@@ -7,6 +7,7 @@ use serde::Deserialize;
 
 const CACHE_DIR_ENV: &str = "KOTLIN_ANALYZER_RUNTIME_CACHE_DIR";
 const PROVISION_DIRS_ENV: &str = "KOTLIN_ANALYZER_RUNTIME_SOURCE_DIRS";
+const SIDECAR_JAR_ENV: &str = "KOTLIN_ANALYZER_SIDECAR_JAR";
 
 /// A concrete sidecar runtime that can be launched by the bridge.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -25,6 +26,7 @@ pub enum RuntimeSelectionReason {
     SameMinorFallback,
     BundledFallback,
     DefaultBundled,
+    ExplicitOverride,
 }
 
 impl RuntimeSelectionReason {
@@ -34,6 +36,7 @@ impl RuntimeSelectionReason {
             RuntimeSelectionReason::SameMinorFallback => "same-minor fallback",
             RuntimeSelectionReason::BundledFallback => "bundled fallback",
             RuntimeSelectionReason::DefaultBundled => "default bundled runtime",
+            RuntimeSelectionReason::ExplicitOverride => "explicit sidecar jar override",
         }
     }
 
@@ -43,6 +46,7 @@ impl RuntimeSelectionReason {
             RuntimeSelectionReason::SameMinorFallback => "runtime_selection.same_minor_fallback",
             RuntimeSelectionReason::BundledFallback => "runtime_selection.cross_minor_fallback",
             RuntimeSelectionReason::DefaultBundled => "runtime_selection.default_bundled",
+            RuntimeSelectionReason::ExplicitOverride => "runtime_selection.explicit_override",
         }
     }
 }
@@ -53,7 +57,9 @@ impl SidecarRuntime {
         let selected = self.kotlin_version.as_deref().unwrap_or("unknown");
 
         match self.selection_reason {
-            RuntimeSelectionReason::ExactMatch | RuntimeSelectionReason::DefaultBundled => None,
+            RuntimeSelectionReason::ExactMatch
+            | RuntimeSelectionReason::DefaultBundled
+            | RuntimeSelectionReason::ExplicitOverride => None,
             RuntimeSelectionReason::SameMinorFallback => Some(format!(
                 "kotlin-analyzer: project requests Kotlin {requested}, but that exact runtime is unavailable. Using Kotlin {selected} from the same minor line instead."
             )),
@@ -87,8 +93,22 @@ struct RuntimeManifest {
     classpath: Vec<PathBuf>,
 }
 
-pub fn resolve_sidecar_runtime(requested_kotlin_version: Option<&str>) -> Option<SidecarRuntime> {
-    let context = RuntimeDiscoveryContext::for_current_process()?;
+/// Resolves the sidecar runtime to launch. An explicit jar (the
+/// `sidecarJarPath` setting, then `KOTLIN_ANALYZER_SIDECAR_JAR`) wins over
+/// discovered runtimes; otherwise runtimes next to the binary, in the cache,
+/// in the user data directory, and in a dev build are considered. The error
+/// lists every location that was searched.
+pub fn resolve_sidecar_runtime(
+    requested_kotlin_version: Option<&str>,
+    configured_jar: Option<&str>,
+) -> Result<SidecarRuntime, String> {
+    let context = RuntimeDiscoveryContext::for_current_process(configured_jar)
+        .ok_or_else(|| "kotlin-analyzer: cannot locate the server executable".to_string())?;
+
+    if let Some(runtime) = explicit_override_runtime(&context, requested_kotlin_version) {
+        return Ok(runtime);
+    }
+
     let mut available = discover_available_sidecar_runtimes(&context);
 
     if let Some(requested_version) = requested_kotlin_version {
@@ -106,25 +126,130 @@ pub fn resolve_sidecar_runtime(requested_kotlin_version: Option<&str>) -> Option
         }
     }
 
-    if available.is_empty() {
-        return None;
+    let selected = select_sidecar_runtime(requested_kotlin_version, &available)
+        .ok_or_else(|| runtime_not_found_message(&context))?;
+    tracing::info!(
+        counter = selected.selection_reason.counter_name(),
+        count = 1u64,
+        requested = selected
+            .requested_kotlin_version
+            .as_deref()
+            .unwrap_or("unknown"),
+        selected = selected.kotlin_version.as_deref().unwrap_or("unknown"),
+        reason = selected.selection_reason.description(),
+        "sidecar runtime selection counter"
+    );
+    Ok(selected)
+}
+
+/// Cheap check for whether any sidecar runtime could be launched, used to
+/// decide which capabilities to advertise before the project is resolved.
+/// The error lists every location that was searched.
+pub fn probe_sidecar_runtime(configured_jar: Option<&str>) -> Result<(), String> {
+    let context = RuntimeDiscoveryContext::for_current_process(configured_jar)
+        .ok_or_else(|| "kotlin-analyzer: cannot locate the server executable".to_string())?;
+    if has_launchable_runtime(&context) {
+        Ok(())
+    } else {
+        Err(runtime_not_found_message(&context))
     }
+}
 
-    let selected = select_sidecar_runtime(requested_kotlin_version, &available);
-    if let Some(runtime) = selected.as_ref() {
-        tracing::info!(
-            counter = runtime.selection_reason.counter_name(),
-            count = 1u64,
-            requested = runtime
-                .requested_kotlin_version
-                .as_deref()
-                .unwrap_or("unknown"),
-            selected = runtime.kotlin_version.as_deref().unwrap_or("unknown"),
-            reason = runtime.selection_reason.description(),
-            "sidecar runtime selection counter"
+/// Returns the explicit jar if one is configured and exists. A configured
+/// path that does not exist is logged and discovery continues.
+fn explicit_override_runtime(
+    context: &RuntimeDiscoveryContext,
+    requested_kotlin_version: Option<&str>,
+) -> Option<SidecarRuntime> {
+    let (jar, source) = context.explicit_jar.as_ref()?;
+    if !jar.is_file() {
+        tracing::warn!(
+            "sidecar jar from {} does not exist: {}; searching default locations",
+            source,
+            jar.display()
         );
+        return None;
     }
-    selected
+
+    tracing::info!("using sidecar jar from {}: {}", source, jar.display());
+    Some(SidecarRuntime {
+        requested_kotlin_version: requested_kotlin_version.map(str::to_string),
+        kotlin_version: None,
+        classpath: vec![jar.clone()],
+        main_class: None,
+        selection_reason: RuntimeSelectionReason::ExplicitOverride,
+    })
+}
+
+/// Picks the explicit jar by priority: config setting, then environment.
+fn explicit_sidecar_jar(
+    configured_jar: Option<&str>,
+    env_jar: Option<OsString>,
+) -> Option<(PathBuf, &'static str)> {
+    if let Some(path) = configured_jar.filter(|path| !path.is_empty()) {
+        return Some((PathBuf::from(path), "sidecarJarPath setting"));
+    }
+    env_jar
+        .filter(|path| !path.is_empty())
+        .map(|path| (PathBuf::from(path), SIDECAR_JAR_ENV))
+}
+
+/// Every location that discovery looks at, in priority order.
+fn searched_locations(context: &RuntimeDiscoveryContext) -> Vec<PathBuf> {
+    let mut locations = Vec::new();
+    if let Some((jar, _)) = &context.explicit_jar {
+        locations.push(jar.clone());
+    }
+    locations.push(context.exe_dir.join("sidecar-runtimes"));
+    locations.push(context.exe_dir.join("sidecar.jar"));
+    if let Some(cache_root) = &context.cache_root {
+        locations.push(cache_root.clone());
+    }
+    if let Some(data_dir) = &context.data_dir {
+        locations.push(data_dir.join("sidecar-runtimes"));
+        locations.push(data_dir.join("sidecar.jar"));
+    }
+    if let Some(repo_root) = &context.repo_root {
+        locations.push(repo_root.join("sidecar/build/runtime"));
+        locations.push(repo_root.join("sidecar/build/libs/sidecar-all.jar"));
+    }
+    locations
+}
+
+fn runtime_not_found_message(context: &RuntimeDiscoveryContext) -> String {
+    let searched = searched_locations(context)
+        .iter()
+        .map(|path| format!("  {}", path.display()))
+        .collect::<Vec<_>>()
+        .join("\n");
+    format!(
+        "kotlin-analyzer: sidecar runtime not found. Set sidecarJarPath or {SIDECAR_JAR_ENV}. Searched:\n{searched}"
+    )
+}
+
+fn has_launchable_runtime(context: &RuntimeDiscoveryContext) -> bool {
+    context
+        .explicit_jar
+        .as_ref()
+        .is_some_and(|(jar, _)| jar.is_file())
+        || context
+            .provision_roots
+            .iter()
+            .any(|root| has_provisionable_runtime(root))
+        || !discover_available_sidecar_runtimes(context).is_empty()
+}
+
+/// Whether a provision root holds a complete runtime, at either of the
+/// depths `find_runtime_manifest` searches.
+fn has_provisionable_runtime(root: &Path) -> bool {
+    if !discover_manifest_runtimes(root).is_empty() {
+        return true;
+    }
+    std::fs::read_dir(root).is_ok_and(|entries| {
+        entries
+            .flatten()
+            .any(|entry| !discover_manifest_runtimes(&entry.path()).is_empty())
+    })
 }
 
 pub fn select_sidecar_runtime(
@@ -231,16 +356,20 @@ struct RuntimeDiscoveryContext {
     repo_root: Option<PathBuf>,
     cache_root: Option<PathBuf>,
     provision_roots: Vec<PathBuf>,
+    data_dir: Option<PathBuf>,
+    explicit_jar: Option<(PathBuf, &'static str)>,
 }
 
 impl RuntimeDiscoveryContext {
-    fn for_current_process() -> Option<Self> {
+    fn for_current_process(configured_jar: Option<&str>) -> Option<Self> {
         let exe = std::env::current_exe().ok()?;
         let exe = std::fs::canonicalize(&exe).unwrap_or(exe);
         let exe_dir = exe.parent()?.to_path_buf();
         let repo_root = infer_repo_root(&exe);
         let cache_root = runtime_cache_root();
         let provision_roots = provision_source_roots(repo_root.as_deref());
+        let data_dir = default_data_dir();
+        let explicit_jar = explicit_sidecar_jar(configured_jar, std::env::var_os(SIDECAR_JAR_ENV));
 
         Some(Self {
             exe,
@@ -248,6 +377,8 @@ impl RuntimeDiscoveryContext {
             repo_root,
             cache_root,
             provision_roots,
+            data_dir,
+            explicit_jar,
         })
     }
 }
@@ -284,6 +415,22 @@ fn discover_available_sidecar_runtimes(
         });
     }
 
+    if let Some(data_dir) = &context.data_dir {
+        runtimes.extend(discover_manifest_runtimes(
+            &data_dir.join("sidecar-runtimes"),
+        ));
+
+        let installed = data_dir.join("sidecar.jar");
+        if installed.exists() {
+            runtimes.push(AvailableSidecarRuntime {
+                kotlin_version: None,
+                classpath: vec![installed],
+                main_class: None,
+                validated_same_minor_lines: Vec::new(),
+            });
+        }
+    }
+
     if let Some(repo_root) = &context.repo_root {
         runtimes.extend(discover_manifest_runtimes(
             &repo_root.join("sidecar/build/runtime"),
@@ -388,13 +535,48 @@ fn default_cache_base_dir() -> Option<PathBuf> {
             );
         }
         let home = std::env::var_os("HOME")?;
-        return Some(
+        Some(
             PathBuf::from(home)
                 .join(".cache")
                 .join("kotlin-analyzer")
                 .join("runtimes"),
+        )
+    }
+}
+
+/// Per-user data directory where packagers may install the sidecar, e.g.
+/// `~/.local/share/kotlin-analyzer` on Linux.
+fn default_data_dir() -> Option<PathBuf> {
+    #[cfg(target_os = "macos")]
+    {
+        let home = std::env::var_os("HOME")?;
+        return Some(
+            PathBuf::from(home)
+                .join("Library")
+                .join("Application Support")
+                .join("kotlin-analyzer"),
         );
     }
+
+    #[cfg(target_os = "windows")]
+    {
+        let app_data = std::env::var_os("APPDATA")?;
+        return Some(PathBuf::from(app_data).join("kotlin-analyzer"));
+    }
+
+    #[cfg(not(any(target_os = "macos", target_os = "windows")))]
+    {
+        if let Some(xdg_data_home) = std::env::var_os("XDG_DATA_HOME") {
+            return Some(PathBuf::from(xdg_data_home).join("kotlin-analyzer"));
+        }
+        let home = std::env::var_os("HOME")?;
+        Some(
+            PathBuf::from(home)
+                .join(".local")
+                .join("share")
+                .join("kotlin-analyzer"),
+        )
+    }
 }
 
 fn target_platform_key() -> String {
@@ -879,6 +1061,8 @@ mod tests {
             repo_root: None,
             cache_root: Some(cache_root),
             provision_roots: Vec::new(),
+            data_dir: None,
+            explicit_jar: None,
         };
 
         let runtimes = discover_available_sidecar_runtimes(&context);
@@ -886,6 +1070,125 @@ mod tests {
         assert_eq!(runtimes[0].kotlin_version.as_deref(), Some("2.2.21"));
     }
 
+    #[test]
+    fn launchable_runtime_requires_jar_manifest_or_provision_root() {
+        let dir = tempdir().unwrap();
+        let exe_dir = dir.path().join("bin");
+        std::fs::create_dir_all(&exe_dir).unwrap();
+        let context = RuntimeDiscoveryContext {
+            exe: exe_dir.join("kotlin-analyzer"),
+            exe_dir: exe_dir.clone(),
+            repo_root: None,
+            cache_root: None,
+            provision_roots: Vec::new(),
+            data_dir: None,
+            explicit_jar: None,
+        };
+        assert!(!has_launchable_runtime(&context));
+
+        std::fs::write(exe_dir.join("sidecar.jar"), b"jar").unwrap();
+        assert!(has_launchable_runtime(&context));
+    }
+
+    #[test]
+    fn provision_root_counts_only_with_a_complete_runtime() {
+        let dir = tempdir().unwrap();
+        let source_root = dir.path().join("source-runtimes");
+        let runtime_dir = source_root.join("2.2.21");
+        std::fs::create_dir_all(&runtime_dir).unwrap();
+        std::fs::write(
+            runtime_dir.join("manifest.json"),
+            r#"{"kotlinVersion": "2.2.21", "mainClass": "Main", "classpath": ["payload/sidecar-impl.jar"]}"#,
+        )
+        .unwrap();
+        let context = RuntimeDiscoveryContext {
+            exe: dir.path().join("bin/kotlin-analyzer"),
+            exe_dir: dir.path().join("bin"),
+            repo_root: None,
+            cache_root: None,
+            provision_roots: vec![source_root],
+            data_dir: None,
+            explicit_jar: None,
+        };
+        assert!(!has_launchable_runtime(&context));
+
+        std::fs::create_dir_all(runtime_dir.join("payload")).unwrap();
+        std::fs::write(runtime_dir.join("payload/sidecar-impl.jar"), b"payload").unwrap();
+        assert!(has_launchable_runtime(&context));
+    }
+
+    #[test]
+    fn explicit_jar_prefers_config_over_env() {
+        let from_config = explicit_sidecar_jar(Some("/opt/a.jar"), Some("/opt/b.jar".into()));
+        assert_eq!(
+            from_config,
+            Some((PathBuf::from("/opt/a.jar"), "sidecarJarPath setting"))
+        );
+
+        let from_env = explicit_sidecar_jar(None, Some("/opt/b.jar".into()));
+        assert_eq!(
+            from_env,
+            Some((PathBuf::from("/opt/b.jar"), SIDECAR_JAR_ENV))
+        );
+
+        assert_eq!(explicit_sidecar_jar(Some(""), Some("".into())), None);
+    }
+
+    #[test]
+    fn explicit_jar_overrides_discovered_runtimes() {
+        let dir = tempdir().unwrap();
+        let exe_dir = dir.path().join("bin");
+        std::fs::create_dir_all(&exe_dir).unwrap();
+        std::fs::write(exe_dir.join("sidecar.jar"), b"bundled").unwrap();
+        let custom = dir.path().join("custom.jar");
+        std::fs::write(&custom, b"custom").unwrap();
+
+        let context = RuntimeDiscoveryContext {
+            exe: exe_dir.join("kotlin-analyzer"),
+            exe_dir,
+            repo_root: None,
+            cache_root: None,
+            provision_roots: Vec::new(),
+            data_dir: None,
+            explicit_jar: Some((custom.clone(), SIDECAR_JAR_ENV)),
+        };
+
+        let runtime = explicit_override_runtime(&context, Some("2.2.21")).unwrap();
+        assert_eq!(runtime.classpath, vec![custom]);
+        assert_eq!(
+            runtime.selection_reason,
+            RuntimeSelectionReason::ExplicitOverride
+        );
+    }
+
+    #[test]
+    fn data_dir_runtime_is_discovered_and_reported_when_missing() {
+        let dir = tempdir().unwrap();
+        let data_dir = dir.path().join("share/kotlin-analyzer");
+        let context = RuntimeDiscoveryContext {
+            exe: dir.path().join("bin/kotlin-analyzer"),
+            exe_dir: dir.path().join("bin"),
+            repo_root: None,
+            cache_root: None,
+            provision_roots: Vec::new(),
+            data_dir: Some(data_dir.clone()),
+            explicit_jar: Some((dir.path().join("missing.jar"), "sidecarJarPath setting")),
+        };
+
+        let message = runtime_not_found_message(&context);
+        assert!(message.contains("missing.jar"), "{message}");
+        assert!(
+            message.contains(&data_dir.join("sidecar.jar").display().to_string()),
+            "{message}"
+        );
+
+        std::fs::create_dir_all(&data_dir).unwrap();
+        std::fs::write(data_dir.join("sidecar.jar"), b"jar").unwrap();
+        let runtimes = discover_available_sidecar_runtimes(&context);
+        assert_eq!(runtimes.len(), 1);
+        assert_eq!(runtimes[0].classpath, vec![data_dir.join("sidecar.jar")]);
+    }
+
     #[test]
     fn provision_exact_runtime_installs_into_cache() {
         let dir = tempdir().unwrap();
@@ -910,6 +1213,8 @@ mod tests {
             repo_root: None,
             cache_root: Some(cache_root.clone()),
             provision_roots: vec![source_root],
+            data_dir: None,
+            explicit_jar: None,
         };
 
         let runtime = provision_cached_runtime(&context, "2.2.21").unwrap();
@@ -0,0 +1,286 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::Value;
+use tower_lsp::lsp_types::Url;
+
+use crate::project::ProjectModel;
+
+/// Kind of Kotlin script, which decides the script's implicit classpath.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptKind {
+    /// `*.gradle.kts` build and settings scripts, compiled against the Gradle API.
+    GradleBuild,
+    /// `*.main.kts` standalone scripts with `@file:DependsOn`/`@file:Import`.
+    MainKts,
+    /// Any other `.kts` file.
+    Other,
+}
+
+impl ScriptKind {
+    /// Returns the script kind for a `.kts` URI, or `None` for regular sources.
+    pub fn from_uri(uri: &Url) -> Option<Self> {
+        let path = uri.path().to_ascii_lowercase();
+        if path.ends_with(".gradle.kts") {
+            Some(Self::GradleBuild)
+        } else if path.ends_with(".main.kts") {
+            Some(Self::MainKts)
+        } else if path.ends_with(".kts") {
+            Some(Self::Other)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::GradleBuild => "gradle",
+            Self::MainKts => "mainKts",
+            Self::Other => "script",
+        }
+    }
+}
+
+/// `@file:` directives declared at the top of a `.main.kts` script.
+#[derive(Debug, Default, PartialEq)]
+pub struct ScriptDirectives {
+    /// Maven coordinates (`group:artifact:version`) or jar paths.
+    pub depends_on: Vec<String>,
+    /// Paths of other scripts whose declarations are visible to this one.
+    pub imports: Vec<String>,
+}
+
+/// Parses `@file:DependsOn(...)` and `@file:Import(...)` directives. File
+/// annotations must precede the script body, so parsing stops at the first line
+/// that is not blank, a comment, a shebang, or a file annotation.
+pub fn parse_script_directives(text: &str) -> ScriptDirectives {
+    let mut directives = ScriptDirectives::default();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") || line.starts_with("#!") {
+            continue;
+        }
+        let Some(annotation) = line.strip_prefix("@file:") else {
+            break;
+        };
+        if let Some(args) = annotation_args(annotation, "DependsOn") {
+            directives.depends_on.extend(args);
+        } else if let Some(args) = annotation_args(annotation, "Import") {
+            directives.imports.extend(args);
+        }
+    }
+    directives
+}
+
+/// Returns the string literal arguments of `Name("a", "b")`.
+fn annotation_args(annotation: &str, name: &str) -> Option<Vec<String>> {
+    let args = annotation
+        .strip_prefix(name)?
+        .trim_start()
+        .strip_prefix('(')?;
+    let args = &args[..args.rfind(')')?];
+    Some(
+        args.split(',')
+            .filter_map(|arg| {
+                let arg = arg.trim().strip_prefix('"')?.strip_suffix('"')?;
+                (!arg.is_empty()).then(|| arg.to_string())
+            })
+            .collect(),
+    )
+}
+
+/// Computes the classpath a script is compiled against: the Gradle API and
+/// buildscript classpath for build scripts, and the `@file:DependsOn` jars
+/// for `.main.kts` scripts. Dependencies that cannot be found locally are
+/// skipped; the sidecar does not download artifacts.
+pub fn script_classpath(
+    kind: ScriptKind,
+    script_path: &Path,
+    text: &str,
+    model: Option<&ProjectModel>,
+    maven_repository: Option<&Path>,
+) -> Vec<PathBuf> {
+    match kind {
+        ScriptKind::GradleBuild => model
+            .map(|m| m.script_classpath.clone())
+            .unwrap_or_default(),
+        ScriptKind::MainKts => {
+            let script_dir = script_path.parent().unwrap_or(Path::new(""));
+            parse_script_directives(text)
+                .depends_on
+                .iter()
+                .filter_map(|dependency| {
+                    resolve_dependency(dependency, script_dir, maven_repository)
+                })
+                .collect()
+        }
+        ScriptKind::Other => Vec::new(),
+    }
+}
+
+/// Resolves a `@file:DependsOn` entry: a jar path relative to the script, or
+/// Maven coordinates looked up in the local repository.
+fn resolve_dependency(
+    dependency: &str,
+    script_dir: &Path,
+    maven_repository: Option<&Path>,
+) -> Option<PathBuf> {
+    if dependency.ends_with(".jar") {
+        let path = script_dir.join(dependency);
+        return path.exists().then_some(path);
+    }
+
+    let mut parts = dependency.split(':');
+    let (group, artifact, version) = (parts.next()?, parts.next()?, parts.next()?);
+    let jar = maven_repository?
+        .join(group.replace('.', "/"))
+        .join(artifact)
+        .join(version)
+        .join(format!("{artifact}-{version}.jar"));
+    jar.exists().then_some(jar)
+}
+
+/// Returns the local Maven repository (`~/.m2/repository`).
+pub fn maven_local_repository() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(".m2").join("repository"))
+}
+
+/// Builds the script fields added to `didOpen`/`didChange` for `.kts`
+/// documents: `scriptKind`, `scriptClasspath`, and resolved `scriptImports`.
+/// Returns `None` for regular `.kt` sources.
+pub fn script_sync_params(uri: &Url, text: &str, model: Option<&ProjectModel>) -> Option<Value> {
+    let kind = ScriptKind::from_uri(uri)?;
+    let path = uri.to_file_path().ok()?;
+    let classpath = script_classpath(
+        kind,
+        &path,
+        text,
+        model,
+        maven_local_repository().as_deref(),
+    );
+    let script_dir = path.parent().unwrap_or(Path::new(""));
+    let imports: Vec<PathBuf> = match kind {
+        ScriptKind::MainKts => parse_script_directives(text)
+            .imports
+            .iter()
+            .map(|import| script_dir.join(import))
+            .filter(|import| import.exists())
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    Some(serde_json::json!({
+        "scriptKind": kind.as_str(),
+        "scriptClasspath": classpath,
+        "scriptImports": imports,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../tests/fixtures/kotlin-scripts")
+    }
+
+    #[test]
+    fn script_kind_from_uri() {
+        let kind = |uri: &str| ScriptKind::from_uri(&Url::parse(uri).unwrap());
+        assert_eq!(
+            kind("file:///p/build.gradle.kts"),
+            Some(ScriptKind::GradleBuild)
+        );
+        assert_eq!(
+            kind("file:///p/settings.gradle.kts"),
+            Some(ScriptKind::GradleBuild)
+        );
+        assert_eq!(kind("file:///p/deploy.main.kts"), Some(ScriptKind::MainKts));
+        assert_eq!(kind("file:///p/notes.kts"), Some(ScriptKind::Other));
+        assert_eq!(kind("file:///p/src/Main.kt"), None);
+    }
+
+    #[test]
+    fn parse_script_directives_stops_at_script_body() {
+        let text = r#"#!/usr/bin/env kotlin
+// Fetches releases.
+@file:DependsOn("com.squareup.okhttp3:okhttp:4.12.0", "libs/extra.jar")
+@file:Import("shared.main.kts")
+@file:Suppress("UNUSED")
+
+println("hi")
+@file:DependsOn("ignored:after:body")
+"#;
+        assert_eq!(
+            parse_script_directives(text),
+            ScriptDirectives {
+                depends_on: vec![
+                    "com.squareup.okhttp3:okhttp:4.12.0".to_string(),
+                    "libs/extra.jar".to_string(),
+                ],
+                imports: vec!["shared.main.kts".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn script_classpath_resolves_main_kts_dependencies_from_fixture() {
+        let script = fixture().join("report.main.kts");
+        let text = std::fs::read_to_string(&script).unwrap();
+        let repository = fixture().join("m2");
+
+        let classpath =
+            script_classpath(ScriptKind::MainKts, &script, &text, None, Some(&repository));
+        assert_eq!(
+            classpath,
+            vec![
+                repository.join("org/example/greeter/1.0/greeter-1.0.jar"),
+                fixture().join("libs/local.jar"),
+            ]
+        );
+
+        let uri = Url::from_file_path(&script).unwrap();
+        let params = script_sync_params(&uri, &text, None).unwrap();
+        assert_eq!(params["scriptKind"], "mainKts");
+        assert_eq!(
+            params["scriptImports"][0].as_str().map(Path::new),
+            Some(fixture().join("shared.main.kts").as_path())
+        );
+    }
+
+    #[test]
+    fn script_classpath_uses_gradle_script_classpath_for_build_scripts() {
+        let model = ProjectModel {
+            project_root: PathBuf::from("/project"),
+            build_system: crate::project::BuildSystem::Gradle,
+            source_roots: Vec::new(),
+            classpath: vec![PathBuf::from("/lib/app-dep.jar")],
+            compiler_flags: Vec::new(),
+            kotlin_version: None,
+            jdk_home: None,
+            has_compose: false,
+            generated_source_roots: Vec::new(),
+            code_style: None,
+            script_classpath: vec![PathBuf::from("/gradle/lib/gradle-api.jar")],
+            classpath_sources: Vec::new(),
+            kapt_classpath: Vec::new(),
+        };
+        let classpath = script_classpath(
+            ScriptKind::GradleBuild,
+            Path::new("/project/build.gradle.kts"),
+            "",
+            Some(&model),
+            None,
+        );
+        assert_eq!(classpath, vec![PathBuf::from("/gradle/lib/gradle-api.jar")]);
+        assert!(script_classpath(
+            ScriptKind::Other,
+            Path::new("/project/notes.kts"),
+            "",
+            Some(&model),
+            None
+        )
+        .is_empty());
+    }
+}
@@ -1,25 +1,42 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::identity;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use lsp_types::notification::Notification as LspNotification;
 use lsp_types::request::Request as LspRequest;
 use lsp_types::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use tokio::io::AsyncWriteExt;
-use tokio::process::Command;
 use tokio::sync::Mutex;
 use tower_lsp::jsonrpc::{Error as JsonRpcError, ErrorCode, Result as LspResult};
 use tower_lsp::lsp_types;
 use tower_lsp::{Client, LanguageServer};
 
 use crate::bridge::{Bridge, SidecarState};
-use crate::config::{Config, FormattingTool};
-use crate::project;
+use crate::config::{
+    AnalysisScope, Config, DiagnosticsMode, Feature, Features, FormattingTool, LibrarySourceUris,
+};
+use crate::conflicts;
+use crate::dependency_completion;
+use crate::error::BridgeError;
+use crate::folding;
+use crate::formatter::{self, ResolvedFormatter};
+use crate::incremental;
+use crate::lexical_symbols::LexicalSymbolIndex;
+use crate::library_sources::{DecompiledClass, DecompiledSources, JarEntry, LIBRARY_SCHEME};
+use crate::lint;
+use crate::markup::{markup_content, plaintext_signature_documentation};
+use crate::project::{self, ExcludeMatcher, ProjectModel, ReadOnlyRoots};
+use crate::response_cache::{CacheKey, ResponseCache};
 use crate::runtime;
-use crate::state::{DocumentKind, DocumentStore};
+use crate::script::{script_sync_params, ScriptKind};
+use crate::state::{position_offset, snap_position, DocumentKind, DocumentStore};
 
 const ANALYZER_COMMAND_CONTRACT_JSON: &str = include_str!("../../protocol/analyzer-commands.json");
 
@@ -33,6 +50,15 @@ struct AnalyzerCommandContract {
 struct AnalyzerCommandEntries {
     open_test_target: AnalyzerCommandDefinition,
     create_and_open_test_target: AnalyzerCommandDefinition,
+    status: AnalyzerCommandDefinition,
+    reanalyze: AnalyzerCommandDefinition,
+    clear_cache: AnalyzerCommandDefinition,
+    next_diagnostic: AnalyzerCommandDefinition,
+    prev_diagnostic: AnalyzerCommandDefinition,
+    self_test: AnalyzerCommandDefinition,
+    change_signature: AnalyzerCommandDefinition,
+    reveal_generated: AnalyzerCommandDefinition,
+    discover_tests: AnalyzerCommandDefinition,
 }
 
 #[derive(Debug, Deserialize)]
@@ -40,6 +66,12 @@ struct AnalyzerCommandDefinition {
     id: String,
 }
 
+/// Parameters of the `kotlin-analyzer/librarySource` request.
+#[derive(Debug, Deserialize)]
+pub struct LibrarySourceParams {
+    pub uri: Url,
+}
+
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 struct CommandSelection {
@@ -74,10 +106,64 @@ struct CreateAndOpenTestTargetArgs {
     selection: Option<CommandSelection>,
 }
 
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+struct DiagnosticNavigationArgs {
+    uri: String,
+    position: Position,
+}
+
+/// Arguments of `kotlin-analyzer.changeSignature`: the function declared at
+/// `position` and its parameters, by name, in their new order. Parameters
+/// left out are removed.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+struct ChangeSignatureArgs {
+    uri: String,
+    position: Position,
+    parameters: Vec<String>,
+}
+
+/// Arguments of `kotlin-analyzer.revealGenerated`: a position on a class
+/// declaration or a reference to one.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+struct RevealGeneratedArgs {
+    uri: String,
+    position: Position,
+}
+
+/// Arguments of `kotlin-analyzer.discoverTests`: the file to search, or
+/// the whole workspace when `uri` is left out.
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+struct DiscoverTestsArgs {
+    uri: Option<String>,
+}
+
+/// A node of the tree `kotlin-analyzer.discoverTests` returns: a test
+/// class, a container of tests (a Kotest `describe`), or a test.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+struct TestItem {
+    name: String,
+    uri: Url,
+    range: Range,
+    /// `junit4`, `junit5`, `kotlin.test`, `kotest` or `unknown`.
+    framework: String,
+    /// `class`, `container` or `test`.
+    kind: String,
+    children: Vec<TestItem>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum AnalyzerCommandRequest {
     OpenTestTarget(OpenTestTargetArgs),
     CreateAndOpenTestTarget(CreateAndOpenTestTargetArgs),
+    Status,
+    Reanalyze,
+    ClearCache,
+    NextDiagnostic(DiagnosticNavigationArgs),
+    PrevDiagnostic(DiagnosticNavigationArgs),
+    SelfTest,
+    ChangeSignature(ChangeSignatureArgs),
+    RevealGenerated(RevealGeneratedArgs),
+    DiscoverTests(DiscoverTestsArgs),
 }
 
 enum CompatibleShowDocument {}
@@ -88,6 +174,21 @@ impl LspRequest for CompatibleShowDocument {
     const METHOD: &'static str = "window/showDocument";
 }
 
+/// `$/progress` carrying a batch of partial results. lsp-types' `Progress`
+/// only models work-done values, so the value is passed through as JSON.
+enum PartialResultProgress {}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PartialResultParams {
+    token: ProgressToken,
+    value: Value,
+}
+
+impl LspNotification for PartialResultProgress {
+    type Params = PartialResultParams;
+    const METHOD: &'static str = "$/progress";
+}
+
 fn analyzer_command_contract() -> &'static AnalyzerCommandContract {
     static CONTRACT: OnceLock<AnalyzerCommandContract> = OnceLock::new();
     CONTRACT.get_or_init(|| {
@@ -96,14 +197,67 @@ fn analyzer_command_contract() -> &'static AnalyzerCommandContract {
     })
 }
 
-fn supported_analyzer_command_ids() -> Vec<String> {
-    let contract = analyzer_command_contract();
+/// Validates a command's arguments and builds its request.
+type AnalyzerCommandParser = fn(Vec<Value>, &str) -> Result<AnalyzerCommandRequest, JsonRpcError>;
+
+/// Routing table for `workspace/executeCommand`. Every analyzer command is
+/// listed once with its argument parser; the same list is advertised in
+/// `executeCommandProvider`, so dispatch and advertisement cannot drift.
+fn analyzer_command_routes() -> Vec<(&'static str, AnalyzerCommandParser)> {
+    let commands = &analyzer_command_contract().commands;
     vec![
-        contract.commands.open_test_target.id.clone(),
-        contract.commands.create_and_open_test_target.id.clone(),
+        (commands.open_test_target.id.as_str(), |arguments, id| {
+            parse_command_payload(arguments, id).map(AnalyzerCommandRequest::OpenTestTarget)
+        }),
+        (
+            commands.create_and_open_test_target.id.as_str(),
+            |arguments, id| {
+                parse_command_payload(arguments, id)
+                    .map(AnalyzerCommandRequest::CreateAndOpenTestTarget)
+            },
+        ),
+        (commands.status.id.as_str(), |arguments, id| {
+            parse_no_arguments(arguments, id).map(|()| AnalyzerCommandRequest::Status)
+        }),
+        (commands.reanalyze.id.as_str(), |arguments, id| {
+            parse_no_arguments(arguments, id).map(|()| AnalyzerCommandRequest::Reanalyze)
+        }),
+        (commands.clear_cache.id.as_str(), |arguments, id| {
+            parse_no_arguments(arguments, id).map(|()| AnalyzerCommandRequest::ClearCache)
+        }),
+        (commands.next_diagnostic.id.as_str(), |arguments, id| {
+            parse_command_payload(arguments, id).map(AnalyzerCommandRequest::NextDiagnostic)
+        }),
+        (commands.prev_diagnostic.id.as_str(), |arguments, id| {
+            parse_command_payload(arguments, id).map(AnalyzerCommandRequest::PrevDiagnostic)
+        }),
+        (commands.self_test.id.as_str(), |arguments, id| {
+            parse_no_arguments(arguments, id).map(|()| AnalyzerCommandRequest::SelfTest)
+        }),
+        (commands.change_signature.id.as_str(), |arguments, id| {
+            parse_command_payload(arguments, id).map(AnalyzerCommandRequest::ChangeSignature)
+        }),
+        (commands.reveal_generated.id.as_str(), |arguments, id| {
+            parse_command_payload(arguments, id).map(AnalyzerCommandRequest::RevealGenerated)
+        }),
+        (commands.discover_tests.id.as_str(), |arguments, id| {
+            let args = if parse_no_arguments(arguments.clone(), id).is_ok() {
+                DiscoverTestsArgs::default()
+            } else {
+                parse_command_payload(arguments, id)?
+            };
+            Ok(AnalyzerCommandRequest::DiscoverTests(args))
+        }),
     ]
 }
 
+fn supported_analyzer_command_ids() -> Vec<String> {
+    analyzer_command_routes()
+        .into_iter()
+        .map(|(id, _)| id.to_string())
+        .collect()
+}
+
 fn invalid_params_error<M: Into<String>>(message: M) -> JsonRpcError {
     JsonRpcError {
         code: ErrorCode::InvalidParams,
@@ -120,6 +274,41 @@ fn request_failed_error<M: Into<String>>(message: M) -> JsonRpcError {
     }
 }
 
+/// LSP `RequestFailed`: the request was valid but the server could not
+/// serve it.
+const REQUEST_FAILED: i64 = -32803;
+
+/// `data.reason` values telling apart why the sidecar could not serve a
+/// request failing with [`REQUEST_FAILED`].
+const SIDECAR_NOT_READY: &str = "sidecarNotReady";
+const SIDECAR_CRASHED: &str = "sidecarCrashed";
+const SIDECAR_TIMEOUT: &str = "sidecarTimeout";
+
+/// The error to answer a request with when the sidecar could not serve it,
+/// so the client's log says why a feature returned nothing. `None` for
+/// failures that only mean there is no result.
+fn sidecar_unavailable_error(error: &crate::error::Error) -> Option<JsonRpcError> {
+    let (reason, message) = match error {
+        crate::error::Error::Bridge(BridgeError::NotReady(reason)) => (
+            SIDECAR_NOT_READY,
+            format!("Kotlin sidecar is not ready: {reason}"),
+        ),
+        crate::error::Error::Bridge(BridgeError::Crashed(reason)) => {
+            (SIDECAR_CRASHED, format!("Kotlin sidecar crashed: {reason}"))
+        }
+        crate::error::Error::Bridge(BridgeError::Timeout(ms)) => (
+            SIDECAR_TIMEOUT,
+            format!("Kotlin sidecar did not answer within {ms}ms"),
+        ),
+        _ => return None,
+    };
+    Some(JsonRpcError {
+        code: ErrorCode::ServerError(REQUEST_FAILED),
+        message: message.into(),
+        data: Some(serde_json::json!({ "reason": reason })),
+    })
+}
+
 fn parse_command_payload<T>(arguments: Vec<Value>, command_id: &str) -> Result<T, JsonRpcError>
 where
     T: for<'de> Deserialize<'de>,
@@ -134,26 +323,158 @@ where
         .map_err(|e| invalid_params_error(format!("invalid arguments for {command_id}: {e}")))
 }
 
+/// Commands without arguments accept an empty list or a single empty object,
+/// which some clients send by default.
+fn parse_no_arguments(arguments: Vec<Value>, command_id: &str) -> Result<(), JsonRpcError> {
+    let empty = match arguments.as_slice() {
+        [] => true,
+        [Value::Null] => true,
+        [Value::Object(object)] => object.is_empty(),
+        _ => false,
+    };
+    if empty {
+        Ok(())
+    } else {
+        Err(invalid_params_error(format!(
+            "{command_id} takes no arguments"
+        )))
+    }
+}
+
 fn parse_analyzer_command_request(
     params: ExecuteCommandParams,
 ) -> Result<AnalyzerCommandRequest, JsonRpcError> {
     let command_id = params.command;
-    let arguments = params.arguments;
-    let contract = analyzer_command_contract();
+    match analyzer_command_routes()
+        .into_iter()
+        .find(|(id, _)| *id == command_id)
+    {
+        Some((_, parse)) => parse(params.arguments, &command_id),
+        None => Err(invalid_params_error(format!(
+            "unsupported analyzer command: {command_id}"
+        ))),
+    }
+}
+
+/// Snippet analyzed by `kotlin-analyzer.selfTest`: hover and definition
+/// target the `greet` call (5:19), completion follows `message.` (6:12), and
+/// the last two statements produce errors.
+const SELF_TEST_SOURCE: &str = r#"package selftest
+
+fun greet(name: String): String = "Hello, $name"
+
+fun main() {
+    val message = greet("kotlin-analyzer")
+    message.
+    val broken: Int = "not an int"
+}
+"#;
+
+/// How long `initialized` waits for the project model before starting the
+/// sidecar stdlib-only. Longer than one Gradle or Maven run.
+const PROJECT_RESOLVE_TIMEOUT: Duration = Duration::from_secs(90);
 
-    if command_id == contract.commands.open_test_target.id {
-        let payload = parse_command_payload(arguments, &command_id)?;
-        return Ok(AnalyzerCommandRequest::OpenTestTarget(payload));
+/// How often the `sidecarIdleShutdownMs` timeout is checked.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long each self-test step may take before it counts as timed out.
+const SELF_TEST_STEP_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Result of one `kotlin-analyzer.selfTest` step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SelfTestOutcome {
+    Ok,
+    Empty,
+    Disabled,
+    Failed(String),
+    TimedOut,
+}
+
+impl SelfTestOutcome {
+    fn passed(&self) -> bool {
+        matches!(self, Self::Ok | Self::Disabled)
+    }
+}
+
+impl std::fmt::Display for SelfTestOutcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::Empty => write!(f, "no result"),
+            Self::Disabled => write!(f, "disabled"),
+            Self::Failed(message) => write!(f, "failed ({message})"),
+            Self::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+/// Awaits one self-test step under [`SELF_TEST_STEP_TIMEOUT`].
+async fn self_test_step<T>(
+    step: impl std::future::Future<Output = LspResult<Option<T>>>,
+) -> SelfTestOutcome {
+    match tokio::time::timeout(SELF_TEST_STEP_TIMEOUT, step).await {
+        Ok(Ok(Some(_))) => SelfTestOutcome::Ok,
+        Ok(Ok(None)) => SelfTestOutcome::Empty,
+        Ok(Err(e)) => SelfTestOutcome::Failed(e.message.to_string()),
+        Err(_) => SelfTestOutcome::TimedOut,
     }
+}
+
+/// A temp URI no user document can have, unique per self-test run.
+fn self_test_uri() -> Option<Url> {
+    static RUNS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let run = RUNS.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!(
+        "kotlin-analyzer-self-test-{}-{run}",
+        std::process::id()
+    ));
+    Url::from_file_path(dir.join("SelfTest.kt")).ok()
+}
 
-    if command_id == contract.commands.create_and_open_test_target.id {
-        let payload = parse_command_payload(arguments, &command_id)?;
-        return Ok(AnalyzerCommandRequest::CreateAndOpenTestTarget(payload));
+fn self_test_summary(results: &[(&str, SelfTestOutcome)]) -> (MessageType, String) {
+    let passed = results.iter().all(|(_, outcome)| outcome.passed());
+    let details = results
+        .iter()
+        .map(|(feature, outcome)| format!("{feature}: {outcome}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if passed {
+        (
+            MessageType::INFO,
+            format!("kotlin-analyzer self-test passed ({details})"),
+        )
+    } else {
+        (
+            MessageType::WARNING,
+            format!("kotlin-analyzer self-test found problems ({details})"),
+        )
     }
+}
 
-    Err(invalid_params_error(format!(
-        "unsupported analyzer command: {command_id}"
-    )))
+/// Picks the diagnostic that starts after `position` (or before it when
+/// `forward` is false), wrapping to the first (or last) diagnostic.
+fn adjacent_diagnostic(
+    diagnostics: &[Diagnostic],
+    position: Position,
+    forward: bool,
+) -> Option<Range> {
+    let mut ranges: Vec<Range> = diagnostics.iter().map(|d| d.range).collect();
+    ranges.sort_by_key(|r| (r.start.line, r.start.character));
+    ranges.dedup_by_key(|r| r.start);
+    if forward {
+        ranges
+            .iter()
+            .find(|r| r.start > position)
+            .or_else(|| ranges.first())
+            .copied()
+    } else {
+        ranges
+            .iter()
+            .rev()
+            .find(|r| r.start < position)
+            .or_else(|| ranges.last())
+            .copied()
+    }
 }
 
 fn parse_workspace_edits(result: &Value) -> HashMap<Url, Vec<TextEdit>> {
@@ -212,6 +533,113 @@ fn parse_workspace_edits(result: &Value) -> HashMap<Url, Vec<TextEdit>> {
     changes
 }
 
+/// Parses a sidecar test node and its children; its `range` has 1-based
+/// lines.
+fn parse_test_item(node: &Value) -> Option<TestItem> {
+    let range = node.get("range")?;
+    let position = |line: &str, column: &str| {
+        Some(Position::new(
+            range.get(line)?.as_u64()?.saturating_sub(1) as u32,
+            range.get(column)?.as_u64()? as u32,
+        ))
+    };
+    let text = |key: &str| node.get(key).and_then(|v| v.as_str());
+    Some(TestItem {
+        name: text("name")?.to_string(),
+        uri: Url::parse(text("uri")?).ok()?,
+        range: Range::new(
+            position("startLine", "startColumn")?,
+            position("endLine", "endColumn")?,
+        ),
+        framework: text("framework").unwrap_or("unknown").to_string(),
+        kind: text("kind").unwrap_or("test").to_string(),
+        children: node
+            .get("children")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(parse_test_item)
+            .collect(),
+    })
+}
+
+/// Replaces all of `original` with `new_text`.
+fn whole_document_edit(original: &str, new_text: String) -> TextEdit {
+    let line_count = original.lines().count() as u32;
+    TextEdit {
+        range: Range {
+            start: Position::new(0, 0),
+            end: Position::new(line_count, 0),
+        },
+        new_text,
+    }
+}
+
+/// Whether `willSaveWaitUntil` should organize imports or format for a save
+/// triggered by `reason`. Auto-saves only qualify with `saveActionsOnAutoSave`.
+fn save_actions_apply(config: &Config, reason: TextDocumentSaveReason) -> bool {
+    (config.format_on_save || config.organize_imports_on_save)
+        && (reason == TextDocumentSaveReason::MANUAL || config.save_actions_on_auto_save)
+}
+
+/// Section requested with `workspace/configuration`.
+const CONFIGURATION_SECTION: &str = "kotlin-analyzer";
+
+/// Merges settings pulled with `workspace/configuration` over the
+/// initialization options. Later results win, so the folder-scoped answer
+/// overrides the global one. Clients that ignore `section` and answer with
+/// all their settings are unwrapped to the `kotlin-analyzer` key.
+fn merged_settings(initialization: &Value, pulled: &[Value]) -> Value {
+    let mut merged = initialization.clone();
+    for settings in pulled {
+        let section = settings.get(CONFIGURATION_SECTION).unwrap_or(settings);
+        merge_settings(&mut merged, section);
+    }
+    if merged.is_null() {
+        merged = Value::Object(Default::default());
+    }
+    merged
+}
+
+/// Overlays `overlay` onto `base` key by key, recursing into objects.
+/// `null` leaves the base value in place.
+fn merge_settings(base: &mut Value, overlay: &Value) {
+    match (base, overlay) {
+        (_, Value::Null) => {}
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge_settings(base.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+/// Applies non-overlapping edits, all relative to `text`.
+fn apply_text_edits(text: &str, edits: &[TextEdit]) -> String {
+    let mut edits: Vec<_> = edits
+        .iter()
+        .map(|edit| {
+            let start = position_offset(text, edit.range.start);
+            let end = position_offset(text, edit.range.end).max(start);
+            (start, end, edit.new_text.as_str())
+        })
+        .collect();
+    edits.sort_by_key(|&(start, ..)| std::cmp::Reverse(start));
+    let mut result = text.to_string();
+    for (start, end, new_text) in edits {
+        result.replace_range(start..end, new_text);
+    }
+    result
+}
+
+/// The first of `items` that fit in `remaining`, which is reduced by as many.
+fn take_within<T>(mut items: Vec<T>, remaining: &AtomicUsize) -> Vec<T> {
+    items.truncate(remaining.load(Ordering::Relaxed));
+    remaining.fetch_sub(items.len(), Ordering::Relaxed);
+    items
+}
+
 fn response_version(result: &Value) -> Option<i32> {
     result
         .get("version")
@@ -228,6 +656,8 @@ fn analyze_edits_are_current(
     response_version == expected_version && current_version == Some(expected_version)
 }
 
+/// The command a code action runs after its edits are applied, such as a
+/// client command that organizes imports or moves the cursor.
 fn parse_code_action_command(action: &Value) -> Option<lsp_types::Command> {
     let command = action.get("command")?.clone();
     match serde_json::from_value::<lsp_types::Command>(command) {
@@ -239,7 +669,90 @@ fn parse_code_action_command(action: &Value) -> Option<lsp_types::Command> {
     }
 }
 
-fn parse_code_actions_result(result: &Value) -> CodeActionResponse {
+/// Builds the sidecar `codeActions` request. The full selection is forwarded
+/// (1-based lines) so range-based refactorings can see where it ends.
+fn code_action_request_params(uri: &Url, range: Range, diagnostics: &[Diagnostic]) -> Value {
+    serde_json::json!({
+        "uri": uri.as_str(),
+        "startLine": range.start.line + 1,
+        "startColumn": range.start.character,
+        "endLine": range.end.line + 1,
+        "endColumn": range.end.character,
+        "diagnostics": diagnostics.iter().map(|d| {
+            serde_json::json!({
+                "severity": d.severity,
+                "message": d.message,
+                "code": d.code,
+            })
+        }).collect::<Vec<_>>(),
+    })
+}
+
+/// Code of the sidecar's unused-import warning.
+const UNUSED_IMPORT_CODE: &str = "UNUSED_IMPORT";
+
+/// Returns the code to pass to `@Suppress` for a diagnostic. Only warnings
+/// (and weaker) can be suppressed; compiler errors cannot.
+fn suppressible_code(diagnostic: &Diagnostic) -> Option<&str> {
+    if diagnostic.severity == Some(DiagnosticSeverity::ERROR) {
+        return None;
+    }
+    match &diagnostic.code {
+        // The analyzer's own unused-import warning is fixed by removing the
+        // import; `@Suppress` does not apply to it.
+        Some(NumberOrString::String(code)) if code == UNUSED_IMPORT_CODE => None,
+        Some(NumberOrString::String(code)) if !code.is_empty() => Some(code),
+        _ => None,
+    }
+}
+
+/// Extract refactorings need a non-empty selection, and are skipped when the
+/// client restricts `context.only` to kinds that do not cover `refactor.extract`.
+fn wants_extract_actions(range: Range, only: Option<&[CodeActionKind]>) -> bool {
+    range.start != range.end && wants_action_kind(&CodeActionKind::REFACTOR_EXTRACT, only)
+}
+
+/// Inline refactorings act on the variable at the cursor, so they are asked
+/// for only with an empty range or one selecting a single identifier in
+/// `text`, and when `context.only` covers `refactor.inline`.
+fn wants_inline_actions(range: Range, text: &str, only: Option<&[CodeActionKind]>) -> bool {
+    let start = position_offset(text, range.start);
+    let selection = &text[start..position_offset(text, range.end).max(start)];
+    let identifier = selection
+        .trim_matches('`')
+        .chars()
+        .enumerate()
+        .all(|(i, c)| c == '_' || c.is_alphabetic() || (i > 0 && c.is_numeric()));
+    (range.start == range.end || (!selection.is_empty() && identifier))
+        && wants_action_kind(&CodeActionKind::REFACTOR_INLINE, only)
+}
+
+/// Whether `context.only` (when present) covers `wanted`, either exactly or
+/// through a parent kind such as `refactor`.
+fn wants_action_kind(wanted: &CodeActionKind, only: Option<&[CodeActionKind]>) -> bool {
+    only.is_none_or(|kinds| {
+        kinds.iter().any(|kind| {
+            wanted.as_str() == kind.as_str()
+                || wanted
+                    .as_str()
+                    .strip_prefix(kind.as_str())
+                    .is_some_and(|rest| rest.starts_with('.'))
+        })
+    })
+}
+
+/// Rank of code actions the sidecar did not rank; they follow ranked ones.
+const UNRANKED: u64 = u64::MAX;
+
+/// Parses sidecar code actions with their `rank` (lower comes first). An
+/// action naming the diagnostic it fixes (`diagnostic: { code, line }`,
+/// 1-based line) gets the matching entries of the request's `diagnostics`
+/// attached; `isPreferred` marks the fix "apply preferred action" picks,
+/// and `disabled: { reason }` an action shown grayed out with the reason.
+fn parse_ranked_code_actions(
+    result: &Value,
+    diagnostics: &[Diagnostic],
+) -> Vec<(u64, CodeActionOrCommand)> {
     let actions_array = match result.get("actions").and_then(|a| a.as_array()) {
         Some(arr) => arr,
         None => return Vec::new(),
@@ -248,6 +761,10 @@ fn parse_code_actions_result(result: &Value) -> CodeActionResponse {
     actions_array
         .iter()
         .filter_map(|action| {
+            let rank = action
+                .get("rank")
+                .and_then(|r| r.as_u64())
+                .unwrap_or(UNRANKED);
             let title = action.get("title")?.as_str()?.to_string();
             let kind = action
                 .get("kind")
@@ -265,20 +782,199 @@ fn parse_code_actions_result(result: &Value) -> CodeActionResponse {
                 })
             };
 
-            Some(CodeActionOrCommand::CodeAction(CodeAction {
+            let fixed: Vec<Diagnostic> = action
+                .get("diagnostic")
+                .map(|target| {
+                    let code = target.get("code").and_then(|c| c.as_str());
+                    let line = target.get("line").and_then(|l| l.as_u64());
+                    diagnostics
+                        .iter()
+                        .filter(|d| {
+                            matches!(&d.code, Some(NumberOrString::String(c)) if Some(c.as_str()) == code)
+                                && line == Some(u64::from(d.range.start.line) + 1)
+                        })
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            let action = CodeAction {
                 title,
                 kind,
-                diagnostics: None,
+                diagnostics: (!fixed.is_empty()).then_some(fixed),
                 edit,
                 command: parse_code_action_command(action),
-                is_preferred: None,
-                disabled: None,
+                is_preferred: action.get("isPreferred").and_then(|p| p.as_bool()),
+                disabled: action
+                    .get("disabled")
+                    .and_then(|d| d.get("reason"))
+                    .and_then(|r| r.as_str())
+                    .map(|reason| CodeActionDisabled {
+                        reason: reason.to_string(),
+                    }),
                 data: None,
-            }))
+            };
+            Some((rank, CodeActionOrCommand::CodeAction(action)))
+        })
+        .collect()
+}
+
+/// Sorts ranked code actions by rank, then by kind: quick fixes before
+/// refactors before source actions. Sorting is stable, so equal actions
+/// keep the sidecar's order.
+fn order_code_actions(mut actions: Vec<(u64, CodeActionOrCommand)>) -> CodeActionResponse {
+    actions.sort_by_key(|(rank, action)| (*rank, code_action_kind_order(action)));
+    actions.into_iter().map(|(_, action)| action).collect()
+}
+
+fn code_action_kind_order(action: &CodeActionOrCommand) -> u8 {
+    let kind = match action {
+        CodeActionOrCommand::CodeAction(action) => action.kind.as_ref(),
+        CodeActionOrCommand::Command(_) => None,
+    };
+    match kind.map(|kind| kind.as_str()) {
+        Some(kind) if kind.starts_with("quickfix") => 0,
+        Some(kind) if kind.starts_with("refactor") => 1,
+        Some(kind) if kind.starts_with("source") => 2,
+        _ => 3,
+    }
+}
+
+/// Parses sidecar `documentLink` results. Links with a literal `target` are
+/// complete; KDoc references carry their reference text in `data` and get a
+/// target in `documentLink/resolve`.
+fn parse_document_links(result: &Value, uri: &Url) -> Vec<DocumentLink> {
+    let links_array = match result.get("links").and_then(|l| l.as_array()) {
+        Some(arr) => arr,
+        None => return Vec::new(),
+    };
+
+    links_array
+        .iter()
+        .filter_map(|link| {
+            let range = link.get("range")?;
+            let start_line = range.get("startLine")?.as_u64()?.saturating_sub(1) as u32;
+            let start_column = range.get("startColumn")?.as_u64()? as u32;
+            let end_line = range.get("endLine")?.as_u64()?.saturating_sub(1) as u32;
+            let end_column = range.get("endColumn")?.as_u64()? as u32;
+            let range = Range {
+                start: Position::new(start_line, start_column),
+                end: Position::new(end_line, end_column),
+            };
+
+            if let Some(target) = link.get("target").and_then(|t| t.as_str()) {
+                return Some(DocumentLink {
+                    range,
+                    target: Some(Url::parse(target).ok()?),
+                    tooltip: None,
+                    data: None,
+                });
+            }
+
+            let reference = link.get("reference")?.as_str()?;
+            Some(DocumentLink {
+                range,
+                target: None,
+                tooltip: Some(reference.to_string()),
+                data: Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                    "reference": reference,
+                })),
+            })
         })
         .collect()
 }
 
+/// Builds the target for a resolved KDoc reference, pointing at the
+/// declaration line via a `#L<line>` fragment.
+fn parse_resolved_link_target(result: &Value) -> Option<Url> {
+    let mut target = Url::parse(result.get("uri")?.as_str()?).ok()?;
+    if let Some(line) = result.get("line").and_then(|l| l.as_u64()) {
+        target.set_fragment(Some(&format!("L{line}")));
+    }
+    Some(target)
+}
+
+/// Lists a sidecar match by URI alone, for clients that resolve the range
+/// later. The listed position (1-based line) rides along in `data`.
+#[allow(deprecated)]
+fn unresolved_workspace_symbol(symbol: SymbolInformation) -> WorkspaceSymbol {
+    let start = symbol.location.range.start;
+    WorkspaceSymbol {
+        name: symbol.name,
+        kind: symbol.kind,
+        tags: symbol.tags,
+        container_name: symbol.container_name,
+        location: OneOf::Right(WorkspaceLocation {
+            uri: symbol.location.uri,
+        }),
+        data: Some(serde_json::json!({
+            "line": start.line + 1,
+            "column": start.character,
+        })),
+    }
+}
+
+/// Lists a match with its full location, for symbols that cannot be
+/// resolved later (the lexical index answers before the sidecar is up).
+#[allow(deprecated)]
+fn located_workspace_symbol(symbol: SymbolInformation) -> WorkspaceSymbol {
+    WorkspaceSymbol {
+        name: symbol.name,
+        kind: symbol.kind,
+        tags: symbol.tags,
+        container_name: symbol.container_name,
+        location: OneOf::Left(symbol.location),
+        data: None,
+    }
+}
+
+/// Builds the sidecar `workspaceSymbol/resolve` request for a symbol picked
+/// from a `workspace/symbol` list. The listed position (1-based line), from
+/// the location or, for a URI-only symbol, its `data`, tells same-named
+/// declarations in one file apart.
+fn workspace_symbol_resolve_params(symbol: &WorkspaceSymbol) -> Value {
+    let (uri, line, column) = match &symbol.location {
+        OneOf::Left(location) => (
+            &location.uri,
+            Some(Value::from(location.range.start.line + 1)),
+            Some(Value::from(location.range.start.character)),
+        ),
+        OneOf::Right(location) => {
+            let data = symbol.data.as_ref();
+            (
+                &location.uri,
+                data.and_then(|d| d.get("line")).cloned(),
+                data.and_then(|d| d.get("column")).cloned(),
+            )
+        }
+    };
+    serde_json::json!({
+        "uri": uri.as_str(),
+        "name": symbol.name,
+        "line": line,
+        "column": column,
+    })
+}
+
+/// Parses a resolved symbol's `{ uri, range }` (1-based lines) into the
+/// `Location` of its name.
+fn parse_resolved_symbol_location(result: &Value) -> Option<Location> {
+    let uri = Url::parse(result.get("uri")?.as_str()?).ok()?;
+    let range = result.get("range")?;
+    let start_line = range.get("startLine")?.as_u64()?.saturating_sub(1) as u32;
+    let start_column = range.get("startColumn")?.as_u64()? as u32;
+    let end_line = range.get("endLine")?.as_u64()?.saturating_sub(1) as u32;
+    let end_column = range.get("endColumn")?.as_u64()? as u32;
+    Some(Location {
+        uri,
+        range: Range {
+            start: Position::new(start_line, start_column),
+            end: Position::new(end_line, end_column),
+        },
+    })
+}
+
 fn temporary_target_path(target_path: &Path) -> PathBuf {
     let file_name = target_path
         .file_name()
@@ -295,14 +991,166 @@ fn temporary_target_path(target_path: &Path) -> PathBuf {
     ))
 }
 
-/// The main language server implementation.
+/// Returns true for file URIs matching `excludePaths`.
+fn is_excluded_uri(excludes: &ExcludeMatcher, uri: &Url) -> bool {
+    uri.to_file_path()
+        .is_ok_and(|path| excludes.is_excluded(&path))
+}
+
+fn is_read_only_uri(read_only: &ReadOnlyRoots, uri: &Url) -> bool {
+    uri.to_file_path()
+        .is_ok_and(|path| read_only.contains(&path))
+}
+
+/// Shows every diagnostic of a file in a read-only source root as
+/// information: the user cannot act on it there.
+fn demote_read_only(
+    read_only: &ReadOnlyRoots,
+    uri: &Url,
+    diagnostics: Vec<Diagnostic>,
+) -> Vec<Diagnostic> {
+    if !is_read_only_uri(read_only, uri) {
+        return diagnostics;
+    }
+    diagnostics
+        .into_iter()
+        .map(|diagnostic| Diagnostic {
+            severity: Some(DiagnosticSeverity::INFORMATION),
+            ..diagnostic
+        })
+        .collect()
+}
+
+/// Picks the workspace root from `initialize`: `rootUri`, then the deprecated
+/// `rootPath`, then the first file-based workspace folder. Returns the path
+/// together with the field it came from, for logging.
+fn workspace_root(params: &InitializeParams) -> Option<(PathBuf, &'static str)> {
+    if let Some(root_uri) = &params.root_uri {
+        match root_uri.to_file_path() {
+            Ok(path) => return Some((path, "rootUri")),
+            Err(()) => tracing::info!("ignoring non-file rootUri {}", root_uri),
+        }
+    }
+
+    #[allow(deprecated)]
+    if let Some(root_path) = params.root_path.as_deref().filter(|p| !p.is_empty()) {
+        return Some((PathBuf::from(root_path), "rootPath"));
+    }
+
+    params
+        .workspace_folders
+        .iter()
+        .flatten()
+        .find_map(|folder| folder.uri.to_file_path().ok())
+        .map(|path| (path, "workspaceFolders"))
+}
+
+/// Project configuration in the string form the sidecar expects.
+#[derive(Debug, Default, PartialEq)]
+struct SidecarProjectArgs {
+    classpath: Vec<String>,
+    compiler_flags: Vec<String>,
+    source_roots: Vec<String>,
+    generated_source_roots: Vec<String>,
+    classpath_sources: Vec<String>,
+    kapt_classpath: Vec<String>,
+}
+
+impl SidecarProjectArgs {
+    fn from_model(model: Option<&ProjectModel>, sidecar_version: Option<&str>) -> Self {
+        let Some(model) = model else {
+            return Self::default();
+        };
+        let paths = |paths: &[PathBuf]| -> Vec<String> {
+            paths
+                .iter()
+                .map(|p| p.to_string_lossy().to_string())
+                .collect()
+        };
+        Self {
+            classpath: paths(&model.classpath),
+            compiler_flags: model.analysis_compiler_flags(sidecar_version),
+            source_roots: paths(&model.source_roots),
+            generated_source_roots: paths(&model.generated_source_roots),
+            classpath_sources: paths(&model.classpath_sources),
+            kapt_classpath: paths(&model.kapt_classpath),
+        }
+    }
+}
+
+/// The main language server implementation. Cloning shares all state, so a
+/// clone can be moved into background tasks.
+#[derive(Clone)]
 pub struct KotlinLanguageServer {
     client: Client,
     documents: Arc<Mutex<DocumentStore>>,
     bridge: Arc<Mutex<Option<Arc<Bridge>>>>,
     config: Arc<Mutex<Config>>,
     project_root: Arc<Mutex<Option<PathBuf>>>,
+    /// Most recently resolved project model, used to scope analysis to
+    /// source roots.
+    project_model: Arc<Mutex<Option<ProjectModel>>>,
+    /// Flips to `true` once the startup resolution of the project has
+    /// finished, successfully or not; `initialized` waits for it before
+    /// starting the sidecar.
+    project_resolved: Arc<tokio::sync::watch::Sender<bool>>,
     debounce_tx: Arc<Mutex<Option<tokio::sync::mpsc::Sender<Url>>>>,
+    /// Formatter binary resolved from the current config, cached so format
+    /// requests don't depend on PATH lookups.
+    formatter: Arc<Mutex<Option<ResolvedFormatter>>>,
+    /// Why no sidecar runtime was found at `initialize`; when set, only
+    /// lexical capabilities were advertised.
+    missing_sidecar_runtime: Arc<Mutex<Option<String>>>,
+    /// Another formatter the client's configuration uses for Kotlin, found
+    /// once the client is initialized.
+    other_formatter: Arc<Mutex<Option<String>>>,
+    /// Set once the user has been told that `analysisScope: "openFiles"`
+    /// limits cross-file results.
+    scope_warning_shown: Arc<AtomicBool>,
+    /// Set once the user has been warned that the project's Kotlin version
+    /// differs from the sidecar's.
+    version_mismatch_warned: Arc<AtomicBool>,
+    /// Which toggleable features were advertised or dynamically registered,
+    /// so `features` changes can (un)register capabilities.
+    feature_registrations: Arc<Mutex<FeatureRegistrations>>,
+    /// What the client declared it can handle in `initialize`.
+    client_support: Arc<Mutex<ClientSupport>>,
+    /// Library classes decompiled for go-to-definition into jars.
+    decompiled_sources: Arc<Mutex<DecompiledSources>>,
+    /// Recent hover, definition and signature help results.
+    response_cache: Arc<Mutex<ResponseCache>>,
+    /// The compiled `generatedClassPatterns` of the current config.
+    generated_classes: Arc<Mutex<GeneratedClassMatcher>>,
+    /// The last matcher built for `excludePaths`, reused until the patterns,
+    /// the project root, or an options file change.
+    excludes: Arc<Mutex<Option<Arc<ExcludeMatcher>>>>,
+    /// Documents whose first `analyze` is outstanding; they show a
+    /// placeholder diagnostic until real diagnostics arrive.
+    pending_first_analysis: Arc<Mutex<HashSet<Url>>>,
+    /// Latest ktlint findings per open document, published merged with the
+    /// compiler's diagnostics.
+    lint_diagnostics: LintDiagnostics,
+    /// Whether diagnostics are published, pulled, or both.
+    diagnostics_delivery: SharedDiagnosticsDelivery,
+    /// Open documents outside the project root. The sidecar analyzes them
+    /// ad hoc and keeps them out of the project's symbol index.
+    loose_files: Arc<Mutex<HashSet<Url>>>,
+    /// Top-level declarations found by scanning the sources, answering
+    /// workspace symbol searches until the sidecar is ready.
+    lexical_symbols: Arc<Mutex<LexicalSymbolIndex>>,
+    /// Work running under a progress token the client may cancel.
+    progress_cancellations: ProgressCancellations,
+    /// Set by `shutdown`; tower-lsp rejects every later request until `exit`.
+    shutting_down: Arc<AtomicBool>,
+    /// Raw `initializationOptions`, the base that pulled configuration is
+    /// merged over.
+    initialization_settings: Arc<Mutex<Value>>,
+    /// File-based workspace folders from `initialize`, each pulled with its
+    /// own `workspace/configuration` scope.
+    workspace_folders: Arc<Mutex<Vec<PathBuf>>>,
+    /// Configuration pulled for each workspace folder, applied to the
+    /// documents inside it; see [`Self::config_for`].
+    folder_configs: Arc<Mutex<Vec<(PathBuf, Config)>>>,
 }
 
 impl KotlinLanguageServer {
@@ -313,1034 +1161,3976 @@ impl KotlinLanguageServer {
             bridge: Arc::new(Mutex::new(None)),
             config: Arc::new(Mutex::new(Config::default())),
             project_root: Arc::new(Mutex::new(None)),
+            project_model: Arc::new(Mutex::new(None)),
+            project_resolved: Arc::new(tokio::sync::watch::channel(false).0),
             debounce_tx: Arc::new(Mutex::new(None)),
+            formatter: Arc::new(Mutex::new(None)),
+            missing_sidecar_runtime: Arc::new(Mutex::new(None)),
+            other_formatter: Arc::new(Mutex::new(None)),
+            scope_warning_shown: Arc::new(AtomicBool::new(false)),
+            version_mismatch_warned: Arc::new(AtomicBool::new(false)),
+            feature_registrations: Arc::new(Mutex::new(FeatureRegistrations::default())),
+            client_support: Arc::new(Mutex::new(ClientSupport::default())),
+            decompiled_sources: Arc::new(Mutex::new(DecompiledSources::default())),
+            response_cache: Arc::new(Mutex::new(ResponseCache::default())),
+            generated_classes: Arc::new(Mutex::new(GeneratedClassMatcher::new(
+                &Config::default().generated_class_patterns,
+            ))),
+            excludes: Arc::new(Mutex::new(None)),
+            pending_first_analysis: Arc::new(Mutex::new(HashSet::new())),
+            lint_diagnostics: Arc::new(Mutex::new(HashMap::new())),
+            diagnostics_delivery: Arc::new(Mutex::new(DiagnosticsDelivery::default())),
+            loose_files: Arc::new(Mutex::new(HashSet::new())),
+            lexical_symbols: Arc::new(Mutex::new(LexicalSymbolIndex::default())),
+            progress_cancellations: Arc::new(Mutex::new(HashMap::new())),
+            shutting_down: Arc::new(AtomicBool::new(false)),
+            initialization_settings: Arc::new(Mutex::new(Value::Null)),
+            workspace_folders: Arc::new(Mutex::new(Vec::new())),
+            folder_configs: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
-    /// Creates a "server not initialized" error for when the sidecar bridge is unavailable.
-    ///
-    /// Returns LSP error code -32002, signaling to clients that the server is still starting up
-    /// and they should retry the request later.
-    fn server_not_initialized_error<T>() -> LspResult<T> {
-        Err(JsonRpcError {
-            code: ErrorCode::ServerError(-32002),
-            message: "Server not initialized (sidecar still starting)".into(),
-            data: None,
-        })
-    }
+    /// Sends `method` to the sidecar as a streaming request and relays each
+    /// batch it reports to the client as a partial result on `token`.
+    /// `parse_batch` converts a batch (shaped like the final result) to LSP
+    /// items. Returns the final result and whether anything was streamed;
+    /// once something was, the caller must report the rest as a partial
+    /// result too and answer with an empty list, as LSP requires.
+    async fn request_with_partial_results<T, F>(
+        &self,
+        bridge: &Arc<Bridge>,
+        method: &str,
+        params: Value,
+        token: ProgressToken,
+        parse_batch: F,
+    ) -> Result<(Value, bool), crate::error::Error>
+    where
+        T: Serialize + Send + Sync + 'static,
+        F: Fn(&Self, &Value) -> Vec<T> + Send + 'static,
+    {
+        let (batch_tx, mut batch_rx) = tokio::sync::mpsc::unbounded_channel();
+        let server = self.clone();
+        let forwarder = tokio::spawn(async move {
+            let mut streamed = false;
+            while let Some(batch) = batch_rx.recv().await {
+                let items = parse_batch(&server, &batch);
+                if !items.is_empty() {
+                    streamed = true;
+                    server.send_partial_result(token.clone(), &items).await;
+                }
+            }
+            streamed
+        });
 
-    /// Returns a cloned Arc to the bridge, releasing the mutex immediately.
-    /// This prevents holding the bridge mutex during long-running sidecar requests,
-    /// which would block all other LSP handlers.
-    async fn get_bridge(&self) -> Option<Arc<Bridge>> {
-        let guard = self.bridge.lock().await;
-        guard.as_ref().map(Arc::clone)
+        let result = bridge.request_streaming(method, params, batch_tx).await;
+        let streamed = forwarder.await.unwrap_or(false);
+        result.map(|result| (result, streamed))
     }
 
-    /// Publishes diagnostics for a document by requesting analysis from the sidecar.
-    async fn analyze_document(&self, uri: &Url) {
-        tracing::debug!("analyze_document: {}", uri);
+    async fn send_partial_result<T: Serialize>(&self, token: ProgressToken, items: &[T]) {
+        let value = serde_json::to_value(items).unwrap_or(Value::Null);
+        self.client
+            .send_notification::<PartialResultProgress>(PartialResultParams { token, value })
+            .await;
+    }
 
-        // Skip .kts build scripts — they produce hundreds of false positives
-        // because Gradle DSL scripts need a runtime environment the sidecar doesn't replicate.
-        if is_gradle_script(uri) {
-            tracing::debug!("analyze_document: skipping build script {}", uri);
-            return;
-        }
+    /// Sends a request about `uri` tagged with the document's current
+    /// version. A sidecar that has not received that version yet answers
+    /// `ContentModified`; the current text is then re-sent and the request
+    /// retried once, closing the race with an in-flight `didChange`.
+    async fn document_request(
+        &self,
+        bridge: &Arc<Bridge>,
+        uri: &Url,
+        method: &str,
+        mut params: Value,
+    ) -> Result<Value, crate::error::Error> {
+        let document = self.documents.lock().await.get(uri).cloned();
+        let Some(document) = document else {
+            return bridge.request(method, Some(params)).await;
+        };
+        params["version"] = Value::from(document.version);
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => {
+        match bridge.request(method, Some(params.clone())).await {
+            Err(crate::error::Error::Bridge(BridgeError::ContentModified(reason))) => {
                 tracing::debug!(
-                    "analyze_document: bridge is None (sidecar still starting), skipping"
+                    "{}: re-sending {} before retrying ({})",
+                    method,
+                    uri,
+                    reason
                 );
-                return;
+                let sync_params = self
+                    .document_sync_params(uri, document.version, &document.text)
+                    .await;
+                bridge
+                    .notify(document.kind.did_change_method(), Some(sync_params))
+                    .await?;
+                bridge.request(method, Some(params)).await
             }
-        };
-
-        let state = bridge.state().await;
-        if state != SidecarState::Ready {
-            tracing::warn!("analyze_document: sidecar state is {:?}, skipping", state);
-            return;
+            other => other,
         }
+    }
 
-        let (text, version) = {
-            let documents = self.documents.lock().await;
-            match documents.get(uri) {
-                Some(d) if d.kind.supports_kotlin_analysis() => (d.text.clone(), d.version),
-                Some(_) => {
-                    tracing::debug!("analyze_document: skipping non-Kotlin document {}", uri);
-                    return;
-                }
-                None => return,
+    /// Sends a position request to the sidecar, reusing the result of an
+    /// identical request against the same document version.
+    async fn cached_position_request(
+        &self,
+        bridge: &Arc<Bridge>,
+        uri: &Url,
+        position: Position,
+        method: &'static str,
+        params: Value,
+    ) -> Result<Value, crate::error::Error> {
+        let version = self.documents.lock().await.get(uri).map(|doc| doc.version);
+        let key = version.map(|version| CacheKey::new(uri, version, position, method));
+        let starts = bridge.start_count();
+        if let Some(key) = &key {
+            let mut cache = self.response_cache.lock().await;
+            cache.track_sidecar_starts(starts);
+            if let Some(result) = cache.get(key) {
+                tracing::debug!("{}: cached result for {}:{:?}", method, uri, position);
+                return Ok(result);
             }
-        };
+        }
 
-        // Send the document content to the sidecar
-        let _ = bridge
-            .notify(
-                "textDocument/didChange",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "version": version,
-                    "text": text,
-                })),
-            )
-            .await;
+        let result = self.document_request(bridge, uri, method, params).await?;
+        // A result from a sidecar that restarted mid-request is not cached.
+        if let Some(key) = key.filter(|_| bridge.start_count() == starts) {
+            self.response_cache.lock().await.insert(key, result.clone());
+        }
+        Ok(result)
+    }
 
-        // Request analysis
-        match bridge
+    /// Returns the decompiled text of a library class, asking the sidecar on
+    /// first use.
+    async fn decompiled_class(
+        &self,
+        bridge: &Arc<Bridge>,
+        entry: &JarEntry,
+    ) -> Option<DecompiledClass> {
+        if let Some(class) = self.decompiled_sources.lock().await.get(entry) {
+            return Some(class.clone());
+        }
+
+        let result = match bridge
             .request(
-                "analyze",
+                "decompile",
                 Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "version": version,
+                    "jar": entry.jar,
+                    "entry": entry.entry,
                 })),
             )
             .await
         {
-            Ok(result) => {
-                tracing::debug!(
-                    "analyze_document: raw sidecar response for {}: {}",
-                    uri,
-                    result
-                );
-                let diagnostics = self.parse_diagnostics(&result);
-                tracing::debug!(
-                    "analyze_document: {} returned {} diagnostics",
-                    uri,
-                    diagnostics.len()
-                );
-                for d in &diagnostics {
-                    tracing::debug!(
-                        "  diagnostic: {:?} at L{}:{} - {}",
-                        d.severity,
-                        d.range.start.line,
-                        d.range.start.character,
-                        d.message
-                    );
-                }
-                // Cache diagnostics so they survive didClose/didOpen tab switches
-                {
-                    let mut documents = self.documents.lock().await;
-                    documents.set_diagnostics(uri.clone(), diagnostics.clone());
-                }
-                self.apply_analyzed_edits(uri, &result).await;
-                self.client
-                    .publish_diagnostics(uri.clone(), diagnostics, None)
-                    .await;
-            }
+            Ok(result) => result,
             Err(e) => {
-                tracing::warn!("analyze_document: analysis failed for {}: {}", uri, e);
+                tracing::warn!("decompile failed for {}: {}", entry.sidecar_uri(), e);
+                return None;
             }
-        }
+        };
+        let Some(text) = result.get("text").and_then(|t| t.as_str()) else {
+            tracing::debug!("sidecar could not decompile {}", entry.sidecar_uri());
+            return None;
+        };
+        let class = DecompiledClass {
+            text: text.to_string(),
+            language: result
+                .get("language")
+                .and_then(|l| l.as_str())
+                .unwrap_or("kotlin")
+                .to_string(),
+        };
+        self.decompiled_sources
+            .lock()
+            .await
+            .insert(entry.clone(), class.clone());
+        Some(class)
     }
 
-    async fn execute_analyzer_command(&self, request: AnalyzerCommandRequest) -> LspResult<Value> {
-        match request {
-            AnalyzerCommandRequest::OpenTestTarget(args) => {
-                let target_uri = Url::parse(&args.target_uri).map_err(|error| {
-                    invalid_params_error(format!("invalid targetUri for openTestTarget: {error}"))
-                })?;
-
-                self.show_document(target_uri, args.selection).await?;
-                Ok(serde_json::json!({ "shown": true }))
-            }
-            AnalyzerCommandRequest::CreateAndOpenTestTarget(args) => {
-                let target_uri = Url::parse(&args.target_uri).map_err(|error| {
-                    invalid_params_error(format!(
-                        "invalid targetUri for createAndOpenTestTarget: {error}"
-                    ))
-                })?;
-
-                let target_path = PathBuf::from(&args.target_path);
-                let created = !target_path.exists();
-                self.create_target_file_if_missing(&target_path, &args.initial_contents)
-                    .await?;
-                self.show_document(target_uri, args.selection).await?;
+    /// Rewrites a definition location inside a library jar, which editors
+    /// cannot open, to the decompiled class: a `kotlin-analyzer:` URI or a
+    /// read-only temp file, per `librarySourceUris`. The sidecar reports
+    /// positions against the decompiled text, so the range is kept. Locations
+    /// that cannot be decompiled are returned unchanged.
+    async fn open_library_location(&self, bridge: &Arc<Bridge>, location: Location) -> Location {
+        let Some(entry) = JarEntry::from_uri(&location.uri) else {
+            return location;
+        };
+        if location.uri.scheme() == LIBRARY_SCHEME
+            || self.decompiled_class(bridge, &entry).await.is_none()
+        {
+            return location;
+        }
 
-                Ok(serde_json::json!({
-                    "created": created,
-                    "shown": true
-                }))
-            }
+        let uri = match self.config.lock().await.library_source_uris {
+            LibrarySourceUris::KotlinAnalyzer => entry.library_uri(),
+            LibrarySourceUris::File => match self.decompiled_sources.lock().await.file(&entry) {
+                Some(Ok(path)) => Url::from_file_path(path).ok(),
+                Some(Err(e)) => {
+                    tracing::warn!("failed to write decompiled {}: {}", location.uri, e);
+                    None
+                }
+                None => None,
+            },
+        };
+        match uri {
+            Some(uri) => Location { uri, ..location },
+            None => location,
         }
     }
 
-    async fn create_target_file_if_missing(
-        &self,
-        target_path: &Path,
-        initial_contents: &str,
-    ) -> LspResult<()> {
-        if target_path.exists() {
-            return Ok(());
+    /// Tracks a `kotlin-analyzer:` document with its decompiled text, whatever
+    /// content the client sent, so hover and definition work inside it.
+    async fn open_library_source(&self, uri: Url, version: i32, kind: DocumentKind) {
+        let Some(entry) = JarEntry::from_uri(&uri) else {
+            return;
+        };
+        let Some(bridge) = self.get_bridge().await else {
+            return;
+        };
+        if let Some(class) = self.decompiled_class(&bridge, &entry).await {
+            self.documents
+                .lock()
+                .await
+                .open(uri, class.text, version, kind);
         }
+    }
 
-        if let Some(parent) = target_path.parent() {
-            tokio::fs::create_dir_all(parent).await.map_err(|error| {
-                request_failed_error(format!(
-                    "failed to create directories for {}: {}",
-                    target_path.display(),
-                    error
-                ))
-            })?;
+    /// URI to send the sidecar for `uri`: decompiled library sources are
+    /// addressed by their original in-jar location.
+    async fn sidecar_uri(&self, uri: &Url) -> String {
+        match self.decompiled_sources.lock().await.entry_for_uri(uri) {
+            Some(entry) => entry.sidecar_uri(),
+            None => uri.to_string(),
         }
+    }
 
-        let temp_path = temporary_target_path(target_path);
-        let write_result = async {
-            let mut file = tokio::fs::OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(&temp_path)
+    /// Handles `workspace/symbol`, rerouted here by [`crate::routing`] since
+    /// tower-lsp's own handler can only answer with `SymbolInformation`.
+    /// Clients that resolve `location.range` get sidecar matches by URI
+    /// alone, and `workspaceSymbol/resolve` fills in the range of the one
+    /// they pick; others get full locations up front.
+    pub async fn workspace_symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> LspResult<Option<WorkspaceSymbolResponse>> {
+        if self.client_support.lock().await.workspace_symbol_resolve {
+            let symbols = self
+                .workspace_symbols(
+                    params,
+                    unresolved_workspace_symbol,
+                    located_workspace_symbol,
+                )
                 .await?;
-            file.write_all(initial_contents.as_bytes()).await?;
-            file.flush().await?;
-            tokio::fs::rename(&temp_path, target_path).await
+            Ok(symbols.map(WorkspaceSymbolResponse::Nested))
+        } else {
+            let symbols = self.workspace_symbols(params, identity, identity).await?;
+            Ok(symbols.map(WorkspaceSymbolResponse::Flat))
         }
-        .await;
+    }
 
-        match write_result {
-            Ok(()) => Ok(()),
-            Err(error) => {
-                let _ = tokio::fs::remove_file(&temp_path).await;
-                Err(request_failed_error(format!(
-                    "failed to create {}: {}",
-                    target_path.display(),
-                    error
-                )))
+    /// Lists symbols from the sidecar's index with the position it recorded,
+    /// turning sidecar matches into `T` with `listed` and lexical fallback
+    /// matches with `lexical`.
+    async fn workspace_symbols<T>(
+        &self,
+        params: WorkspaceSymbolParams,
+        listed: fn(SymbolInformation) -> T,
+        lexical: fn(SymbolInformation) -> T,
+    ) -> LspResult<Option<Vec<T>>>
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        let query = params.query;
+        self.warn_if_scope_limited("Workspace symbol search").await;
+
+        let excludes = self.exclude_matcher().await;
+        let bridge = match self.get_bridge().await {
+            Some(b)
+                if !matches!(
+                    b.state().await,
+                    SidecarState::Starting | SidecarState::Degraded
+                ) =>
+            {
+                b
+            }
+            _ => {
+                return Ok(self
+                    .lexical_workspace_symbols(&query, &excludes)
+                    .await
+                    .map(|s| s.into_iter().map(lexical).collect()))
             }
+        };
+
+        let request_params = serde_json::json!({ "query": query });
+        if let Some(token) = params.partial_result_params.partial_result_token {
+            let batch_excludes = excludes.clone();
+            let parse_batch = move |server: &Self, batch: &Value| {
+                let mut symbols = server.parse_workspace_symbols(batch);
+                symbols.retain(|symbol| !is_excluded_uri(&batch_excludes, &symbol.location.uri));
+                symbols.into_iter().map(listed).collect::<Vec<_>>()
+            };
+            return match self
+                .request_with_partial_results(
+                    &bridge,
+                    "workspaceSymbols",
+                    request_params,
+                    token.clone(),
+                    parse_batch.clone(),
+                )
+                .await
+            {
+                Ok((result, true)) => {
+                    let rest = parse_batch(self, &result);
+                    if !rest.is_empty() {
+                        self.send_partial_result(token, &rest).await;
+                    }
+                    Ok(Some(Vec::new()))
+                }
+                Ok((result, false)) => {
+                    let symbols = parse_batch(self, &result);
+                    Ok((!symbols.is_empty()).then_some(symbols))
+                }
+                Err(e) => {
+                    tracing::warn!("workspace symbols failed: {}", e);
+                    Ok(self
+                        .lexical_workspace_symbols(&query, &excludes)
+                        .await
+                        .map(|s| s.into_iter().map(lexical).collect()))
+                }
+            };
         }
-    }
 
-    async fn show_document(&self, uri: Url, selection: Option<CommandSelection>) -> LspResult<()> {
-        let shown = self
-            .client
-            .send_request::<CompatibleShowDocument>(ShowDocumentParams {
-                uri,
-                external: Some(false),
-                take_focus: Some(true),
-                selection: selection.map(CommandSelection::into_range),
-            })
+        match bridge
+            .request("workspaceSymbols", Some(request_params))
             .await
-            .map_err(|error| {
-                request_failed_error(format!("window/showDocument failed: {error}"))
-            })?;
-
-        if show_document_acknowledged(shown) {
-            Ok(())
-        } else {
-            Err(request_failed_error(
-                "window/showDocument was not acknowledged",
-            ))
+        {
+            Ok(result) => {
+                let mut symbols = self.parse_workspace_symbols(&result);
+                symbols.retain(|symbol| !is_excluded_uri(&excludes, &symbol.location.uri));
+                if symbols.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(symbols.into_iter().map(listed).collect()))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("workspace symbols failed: {}", e);
+                Ok(self
+                    .lexical_workspace_symbols(&query, &excludes)
+                    .await
+                    .map(|s| s.into_iter().map(lexical).collect()))
+            }
         }
     }
 
-    fn parse_diagnostics(&self, result: &Value) -> Vec<Diagnostic> {
-        let diagnostics = match result.get("diagnostics").and_then(|d| d.as_array()) {
-            Some(arr) => arr,
-            None => return Vec::new(),
+    /// Handles `kotlin-analyzer/librarySource`, returning the decompiled text
+    /// behind a `kotlin-analyzer:` URI for clients that resolve custom schemes.
+    pub async fn library_source(&self, params: LibrarySourceParams) -> LspResult<Option<String>> {
+        let Some(entry) = JarEntry::from_uri(&params.uri) else {
+            return Ok(None);
         };
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+        Ok(self
+            .decompiled_class(&bridge, &entry)
+            .await
+            .map(|class| class.text))
+    }
 
-        diagnostics
-            .iter()
-            .filter_map(|d| {
-                let severity = match d.get("severity")?.as_str()? {
-                    "ERROR" => DiagnosticSeverity::ERROR,
-                    "WARNING" => DiagnosticSeverity::WARNING,
-                    "INFO" | "INFORMATION" => DiagnosticSeverity::INFORMATION,
-                    "HINT" => DiagnosticSeverity::HINT,
-                    _ => DiagnosticSeverity::ERROR,
-                };
-
-                let message = d.get("message")?.as_str()?.to_string();
-                let line = d.get("line")?.as_u64()?.saturating_sub(1) as u32;
-                let col = d.get("column").and_then(|c| c.as_u64()).unwrap_or(0);
-                let end_line = d
-                    .get("endLine")
-                    .and_then(|l| l.as_u64())
-                    .map(|l| l.saturating_sub(1) as u32)
-                    .unwrap_or(line);
-                let end_col = d
-                    .get("endColumn")
-                    .and_then(|c| c.as_u64())
-                    .unwrap_or(col + 1) as u32;
-                let col = col as u32;
-
-                Some(Diagnostic {
-                    range: Range {
-                        start: Position::new(line, col),
-                        end: Position::new(end_line, end_col),
-                    },
-                    severity: Some(severity),
-                    code: d
-                        .get("code")
-                        .and_then(|c| c.as_str())
-                        .map(|c| NumberOrString::String(c.to_string())),
-                    source: Some("kotlin-analyzer".into()),
-                    message,
-                    ..Default::default()
-                })
+    /// Reports `value` on a progress token the client supplied with a
+    /// request.
+    async fn send_work_done_progress(&self, token: &NumberOrString, value: WorkDoneProgress) {
+        self.client
+            .send_notification::<lsp_types::notification::Progress>(ProgressParams {
+                token: token.clone(),
+                value: ProgressParamsValue::WorkDone(value),
             })
-            .collect()
+            .await;
     }
 
-    fn parse_result_version(result: &Value) -> Option<i32> {
-        result
-            .get("version")
-            .and_then(|value| value.as_i64())
-            .and_then(|value| i32::try_from(value).ok())
+    /// Handles `window/workDoneProgress/cancel`: stops the work reported
+    /// under the token, cancelling its sidecar requests.
+    pub async fn work_done_progress_cancel(&self, params: WorkDoneProgressCancelParams) {
+        match self.progress_cancellations.lock().await.get(&params.token) {
+            Some(cancel) => {
+                tracing::info!("cancelling work for progress token {:?}", params.token);
+                cancel.notify_one();
+            }
+            None => tracing::debug!("no cancellable work for progress token {:?}", params.token),
+        }
     }
 
-    async fn apply_analyzed_edits(&self, uri: &Url, result: &Value) {
-        let planned_version = match Self::parse_result_version(result) {
-            Some(version) => version,
-            None => return,
-        };
-
-        let current_version = {
+    /// Completes the version of a `group:artifact:` dependency typed in a
+    /// `build.gradle.kts` `dependencies` block, from artifacts already in the
+    /// local caches. Returns `None` outside that context or when
+    /// `buildScriptCompletion` is off, leaving completion to the sidecar.
+    async fn dependency_version_completion(
+        &self,
+        uri: &Url,
+        position: Position,
+    ) -> Option<Vec<CompletionItem>> {
+        if ScriptKind::from_uri(uri) != Some(ScriptKind::GradleBuild)
+            || !self.config.lock().await.build_script_completion
+        {
+            return None;
+        }
+        let prefix = {
             let documents = self.documents.lock().await;
-            documents.get(uri).map(|doc| doc.version)
+            dependency_completion::coordinate_prefix(&documents.get(uri)?.text, position)?
         };
-        if !analyze_edits_are_current(planned_version, current_version, result) {
-            tracing::debug!(
-                "skipping analyzer edit for {} because version changed (planned={}, current={:?}, response={:?})",
-                uri,
-                planned_version,
-                current_version,
-                response_version(result),
-            );
-            return;
-        }
+        let versions = dependency_completion::local_versions(
+            &prefix.group,
+            &prefix.artifact,
+            crate::script::maven_local_repository().as_deref(),
+            dependency_completion::gradle_module_cache().as_deref(),
+        );
+        tracing::debug!(
+            "completion: {} local version(s) of {}:{}",
+            versions.len(),
+            prefix.group,
+            prefix.artifact
+        );
+        Some(dependency_completion::version_completion_items(
+            &prefix,
+            position.line,
+            &versions,
+        ))
+    }
 
-        let edits = parse_workspace_edits(result);
-        if edits.is_empty() {
-            return;
-        }
+    /// Whether a toggleable feature is enabled in the current config.
+    async fn feature_enabled(&self, feature: Feature) -> bool {
+        self.config.lock().await.features.is_enabled(feature)
+    }
 
-        let edit = WorkspaceEdit {
-            changes: Some(edits),
-            document_changes: None,
-            change_annotations: None,
-        };
+    /// Brings the client's registered features in line with `features`:
+    /// registers enabled features and unregisters disabled ones. Features the
+    /// client cannot register dynamically were fixed at `initialize`; toggling
+    /// them is logged and their handlers simply return nothing when disabled.
+    fn sync_feature_registrations(&self, features: Features) {
+        let client = self.client.clone();
+        let registrations_holder = Arc::clone(&self.feature_registrations);
+        tokio::spawn(async move {
+            let mut registrations = registrations_holder.lock().await;
+            let changes = feature_registration_changes(&registrations, &features);
 
-        match self.client.apply_edit(edit).await {
-            Ok(response) if response.applied => {}
-            Ok(response) => {
-                tracing::warn!(
-                    "client rejected analyzer edit for {}: {:?}",
-                    uri,
-                    response.failure_reason,
+            for feature in changes.static_only {
+                tracing::info!(
+                    "client does not support dynamic registration of {}; restart to apply the change",
+                    feature_method(feature)
                 );
             }
-            Err(error) => {
-                tracing::warn!("failed to apply analyzer edit for {}: {}", uri, error);
+
+            if !changes.register.is_empty() {
+                let capabilities = serde_json::to_value(server_capabilities(
+                    registrations.semantic_features,
+                    &Features::default(),
+                ))
+                .unwrap_or_default();
+                let requests = changes
+                    .register
+                    .iter()
+                    .map(|&feature| feature_registration(feature, &capabilities))
+                    .collect();
+                match tokio::time::timeout(
+                    Duration::from_secs(5),
+                    client.register_capability(requests),
+                )
+                .await
+                {
+                    Ok(Err(e)) => tracing::warn!("failed to register features: {:?}", e),
+                    Err(_) => tracing::warn!("feature registration timed out"),
+                    Ok(Ok(())) => registrations.registered.extend(changes.register),
+                }
             }
-        }
-    }
 
-    /// Starts the debounce loop for document analysis.
-    fn start_debounce_loop(&self) -> tokio::sync::mpsc::Sender<Url> {
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<Url>(64);
+            if !changes.unregister.is_empty() {
+                let requests = changes
+                    .unregister
+                    .iter()
+                    .map(|&feature| feature_unregistration(feature))
+                    .collect();
+                match tokio::time::timeout(
+                    Duration::from_secs(5),
+                    client.unregister_capability(requests),
+                )
+                .await
+                {
+                    Ok(Err(e)) => tracing::warn!("failed to unregister features: {:?}", e),
+                    Err(_) => tracing::warn!("feature unregistration timed out"),
+                    Ok(Ok(())) => {
+                        for feature in changes.unregister {
+                            registrations.registered.remove(&feature);
+                        }
+                    }
+                }
+            }
+        });
+    }
 
+    /// Resolves the configured formatter binary in the background, caching the
+    /// result and warning the user if it is missing.
+    fn refresh_formatter(&self, config: Config) {
         let client = self.client.clone();
-        let documents = Arc::clone(&self.documents);
-        let bridge = Arc::clone(&self.bridge);
-
+        let formatter_holder = Arc::clone(&self.formatter);
         tokio::spawn(async move {
-            let mut pending: Option<Url> = None;
-            let debounce_duration = Duration::from_millis(300);
+            let resolved = match formatter::resolve_formatter(&config).await {
+                Ok(resolved) => resolved,
+                Err(message) => {
+                    tracing::warn!("{}", message);
+                    client.show_message(MessageType::WARNING, message).await;
+                    None
+                }
+            };
+            *formatter_holder.lock().await = resolved;
+        });
+    }
 
-            loop {
-                tokio::select! {
-                    uri = rx.recv() => {
-                        match uri {
-                            Some(uri) => {
-                                pending = Some(uri);
-                            }
-                            None => break,
-                        }
-                    }
-                    _ = tokio::time::sleep(debounce_duration), if pending.is_some() => {
-                        if let Some(uri) = pending.take() {
-                            // Skip Gradle build scripts
-                            if is_gradle_script(&uri) {
-                                continue;
-                            }
-                            let bridge_arc = {
-                                let guard = bridge.lock().await;
-                                guard.as_ref().map(Arc::clone)
-                            };
-                            if let Some(bridge) = bridge_arc {
-                                if bridge.state().await == SidecarState::Ready {
-                                    let document_store = documents.lock().await;
-                                    if let Some(doc) = document_store.get(&uri) {
-                                        let text = doc.text.clone();
-                                        let version = doc.version;
-                                        let kind = doc.kind;
-                                        drop(document_store);
+    /// Returns the cached formatter, resolving it on first use if startup
+    /// detection has not populated the cache yet.
+    async fn resolved_formatter(&self, config: &Config) -> Option<ResolvedFormatter> {
+        let mut cached = self.formatter.lock().await;
+        if let Some(resolved) = cached.as_ref() {
+            if resolved.matches(config) {
+                return Some(resolved.clone());
+            }
+        }
 
-                                        let _ = bridge.notify(kind.did_change_method(), Some(serde_json::json!({
-                                            "uri": uri.as_str(),
-                                            "version": version,
-                                            "text": text,
-                                        }))).await;
+        match formatter::resolve_formatter(config).await {
+            Ok(resolved) => {
+                *cached = resolved.clone();
+                resolved
+            }
+            Err(message) => {
+                tracing::warn!("{}", message);
+                None
+            }
+        }
+    }
 
-                                        if !kind.supports_kotlin_analysis() {
-                                            continue;
-                                        }
+    /// `text` with the sidecar's "Organize imports" action applied, when it
+    /// offers one for `uri`.
+    async fn organized_imports(&self, uri: &Url, text: &str) -> Option<String> {
+        if self.is_lexical_only(uri).await
+            || !DocumentKind::from_uri(uri).supports_kotlin_analysis()
+        {
+            return None;
+        }
+        let bridge = self.get_bridge().await?;
+        let params = code_action_request_params(uri, Range::default(), &[]);
+        let result = match bridge.request("codeActions", Some(params)).await {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::warn!("organize imports on save failed for {}: {}", uri, e);
+                return None;
+            }
+        };
+        let action = result.get("actions")?.as_array()?.iter().find(|action| {
+            action.get("kind").and_then(|kind| kind.as_str())
+                == Some(CodeActionKind::SOURCE_ORGANIZE_IMPORTS.as_str())
+        })?;
+        let edits = parse_workspace_edits(action).remove(uri)?;
+        Some(apply_text_edits(text, &edits))
+    }
 
-                                        match bridge.request("analyze", Some(serde_json::json!({
-                                            "uri": uri.as_str(),
-                                            "version": version,
-                                        }))).await {
-                                            Ok(result) => {
-                                                if let Some(planned_version) = Self::parse_result_version(&result) {
-                                                    let current_version = {
-                                                        let document_store = documents.lock().await;
-                                                        document_store.get(&uri).map(|doc| doc.version)
-                                                    };
-                                                    if current_version == Some(planned_version) {
-                                                        let edits = parse_workspace_edits(&result);
-                                                        if !edits.is_empty() {
-                                                            let edit = WorkspaceEdit {
-                                                                changes: Some(edits),
-                                                                document_changes: None,
-                                                                change_annotations: None,
-                                                            };
-                                                            match client.apply_edit(edit).await {
-                                                                Ok(response) if response.applied => {}
-                                                                Ok(response) => tracing::warn!(
-                                                                    "client rejected analyzer edit for {}: {:?}",
-                                                                    uri,
-                                                                    response.failure_reason,
-                                                                ),
-                                                                Err(error) => tracing::warn!(
-                                                                    "failed to apply analyzer edit for {}: {}",
-                                                                    uri,
-                                                                    error,
-                                                                ),
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                                let diagnostics = parse_diagnostics_static(&result);
-                                                client.publish_diagnostics(uri, diagnostics, None).await;
-                                            }
-                                            Err(e) => {
-                                                tracing::warn!("debounced analysis failed: {}", e);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+    /// Runs the formatter configured for `uri` over `text`. `None` when
+    /// formatting is off, no formatter is available, or it failed.
+    async fn format_text(&self, uri: &Url, text: &str) -> Option<String> {
+        let config = self.config_for(uri).await;
+        if config.suppress_formatting_if_other_formatter
+            && self.other_formatter.lock().await.is_some()
+        {
+            return None;
+        }
+        let resolved = self.resolved_formatter(&config).await?;
+
+        let result = match resolved.tool {
+            FormattingTool::None => return None,
+            FormattingTool::Ktfmt => {
+                let code_style = self
+                    .project_model
+                    .lock()
+                    .await
+                    .as_ref()
+                    .and_then(|model| model.code_style.clone());
+                let style = formatter::ktfmt_style(
+                    config.formatting_style.as_deref(),
+                    code_style.as_deref(),
+                );
+                self.format_with_ktfmt(&resolved.path, text, style).await
             }
-        });
+            FormattingTool::Ktlint => self.format_with_ktlint(&resolved.path, text).await,
+        };
 
-        tx
+        match result {
+            Ok(formatted) => Some(formatted),
+            Err(e) => {
+                tracing::warn!("{} formatting failed: {}", resolved.path.display(), e);
+                None
+            }
+        }
     }
-}
 
-/// Returns true if the URI points to a Gradle build script (.gradle.kts in any
-/// location, or .kts files inside buildSrc/ or gradle/ directories).
-fn is_gradle_script(uri: &Url) -> bool {
-    if let Ok(path) = uri.to_file_path() {
-        let path_str = path.to_string_lossy();
-        path_str.ends_with(".gradle.kts")
-            || (path_str.ends_with(".kts")
-                && (path_str.contains("/buildSrc/") || path_str.contains("/gradle/")))
-    } else {
-        false
+    /// Creates a "server not initialized" error for when the sidecar bridge is unavailable.
+    ///
+    /// Returns LSP error code -32002, signaling to clients that the server is still starting up
+    /// and they should retry the request later.
+    fn server_not_initialized_error<T>() -> LspResult<T> {
+        Err(JsonRpcError {
+            code: ErrorCode::ServerError(-32002),
+            message: "Server not initialized (sidecar still starting)".into(),
+            data: None,
+        })
     }
-}
 
-fn parse_diagnostics_static(result: &Value) -> Vec<Diagnostic> {
-    let diagnostics = match result.get("diagnostics").and_then(|d| d.as_array()) {
-        Some(arr) => arr,
-        None => return Vec::new(),
-    };
+    /// Pulls the configuration and adopts it; see [`Self::fetch_configuration`].
+    /// Returns the new configuration, if one was pulled.
+    async fn pull_configuration(&self) -> Option<Config> {
+        let config = self.fetch_configuration().await?;
+        tracing::debug!("adopted pulled configuration");
+        *self.config.lock().await = config.clone();
+        *self.generated_classes.lock().await =
+            GeneratedClassMatcher::new(&config.generated_class_patterns);
+        self.refresh_formatter(config.clone());
+        self.sync_feature_registrations(config.features);
+        Some(config)
+    }
 
-    diagnostics
-        .iter()
-        .filter_map(|d| {
-            let severity = match d.get("severity")?.as_str()? {
-                "ERROR" => DiagnosticSeverity::ERROR,
-                "WARNING" => DiagnosticSeverity::WARNING,
-                "INFO" | "INFORMATION" => DiagnosticSeverity::INFORMATION,
-                "HINT" => DiagnosticSeverity::HINT,
-                _ => DiagnosticSeverity::ERROR,
+    /// Pulls the `kotlin-analyzer` section with `workspace/configuration`,
+    /// globally and scoped to each workspace folder (or the project root's
+    /// folder without any), merged over the initialization options. Each
+    /// folder's result is kept for the documents inside it; the one holding
+    /// the project root becomes the returned session-wide configuration.
+    /// Must not be awaited from a notification handler: the client's answer
+    /// is processed on the same queue.
+    async fn fetch_configuration(&self) -> Option<Config> {
+        if !self.client_support.lock().await.workspace_configuration {
+            return None;
+        }
+        let project_root = self.project_root.lock().await.clone();
+        let mut folders = self.workspace_folders.lock().await.clone();
+        if folders.is_empty() {
+            folders.extend(project_root.clone());
+        }
+        let items: Vec<ConfigurationItem> = std::iter::once(None)
+            .chain(
+                folders
+                    .iter()
+                    .map(|folder| Url::from_directory_path(folder).ok()),
+            )
+            .map(|scope_uri| ConfigurationItem {
+                scope_uri,
+                section: Some(CONFIGURATION_SECTION.into()),
+            })
+            .collect();
+        let pulled =
+            match tokio::time::timeout(Duration::from_secs(5), self.client.configuration(items))
+                .await
+            {
+                Ok(Ok(pulled)) => pulled,
+                Ok(Err(e)) => {
+                    tracing::warn!("workspace/configuration failed: {:?}", e);
+                    return None;
+                }
+                Err(_) => {
+                    tracing::warn!("workspace/configuration timed out");
+                    return None;
+                }
             };
 
-            let message = d.get("message")?.as_str()?.to_string();
-            let line = d.get("line")?.as_u64()?.saturating_sub(1) as u32;
-            let col = d.get("column").and_then(|c| c.as_u64()).unwrap_or(0);
-            let end_line = d
-                .get("endLine")
-                .and_then(|l| l.as_u64())
-                .map(|l| l.saturating_sub(1) as u32)
-                .unwrap_or(line);
-            let end_col = d
-                .get("endColumn")
-                .and_then(|c| c.as_u64())
-                .unwrap_or(col + 1) as u32;
-            let col = col as u32;
+        let initialization = self.initialization_settings.lock().await.clone();
+        let global = pulled.first().cloned().unwrap_or(Value::Null);
+        let mut folder_configs = Vec::new();
+        for (folder, scoped) in folders.iter().zip(pulled.iter().skip(1)) {
+            let merged = merged_settings(&initialization, &[global.clone(), scoped.clone()]);
+            match serde_json::from_value::<Config>(merged) {
+                Ok(config) => folder_configs.push((folder.clone(), config)),
+                Err(e) => tracing::warn!(
+                    "ignoring pulled configuration for {}: {}",
+                    folder.display(),
+                    e
+                ),
+            }
+        }
+        // Deepest first, so nested folders win in `config_for`.
+        folder_configs.sort_by_key(|(folder, _)| std::cmp::Reverse(folder.components().count()));
+
+        let session = project_root.as_deref().and_then(|root| {
+            folder_configs
+                .iter()
+                .find(|(folder, _)| root.starts_with(folder))
+                .map(|(_, config)| config.clone())
+        });
+        let config = match session {
+            Some(config) => config,
+            None => {
+                match serde_json::from_value::<Config>(merged_settings(&initialization, &[global]))
+                {
+                    Ok(config) => config,
+                    Err(e) => {
+                        tracing::warn!("ignoring pulled configuration: {}", e);
+                        return None;
+                    }
+                }
+            }
+        };
+        *self.folder_configs.lock().await = folder_configs;
+        Some(config)
+    }
 
-            Some(Diagnostic {
-                range: Range {
-                    start: Position::new(line, col),
-                    end: Position::new(end_line, end_col),
-                },
-                severity: Some(severity),
-                code: d
-                    .get("code")
-                    .and_then(|c| c.as_str())
-                    .map(|c| NumberOrString::String(c.to_string())),
-                source: Some("kotlin-analyzer".into()),
-                message,
-                ..Default::default()
-            })
-        })
-        .collect()
-}
+    /// The configuration for `uri`: what was pulled for the innermost
+    /// workspace folder holding it, otherwise the session-wide one. Settings
+    /// that shape the sidecar session (classpath, compiler flags, excludes)
+    /// are always taken from the session-wide configuration.
+    async fn config_for(&self, uri: &Url) -> Config {
+        if let Ok(path) = uri.to_file_path() {
+            let folder_configs = self.folder_configs.lock().await;
+            if let Some((_, config)) = folder_configs
+                .iter()
+                .find(|(folder, _)| path.starts_with(folder))
+            {
+                return config.clone();
+            }
+        }
+        self.config.lock().await.clone()
+    }
 
-#[tower_lsp::async_trait]
-impl LanguageServer for KotlinLanguageServer {
-    async fn initialize(&self, params: InitializeParams) -> LspResult<InitializeResult> {
-        tracing::info!("kotlin-analyzer: initializing");
+    /// Adopts a changed configuration, re-resolving the project, restarting
+    /// the formatter, re-registering features and re-analyzing open
+    /// documents as far as the change calls for.
+    async fn apply_configuration(&self, config: Config) {
+        tracing::debug!("configuration updated");
+        let (formatter_changed, overrides_changed, features_changed, supplements_changed) = {
+            let mut c = self.config.lock().await;
+            let changed = c.formatting_tool != config.formatting_tool
+                || c.formatting_path != config.formatting_path;
+            let generated_changed = c.generated_class_patterns != config.generated_class_patterns;
+            if generated_changed {
+                *self.generated_classes.lock().await =
+                    GeneratedClassMatcher::new(&config.generated_class_patterns);
+            }
+            let overrides_changed = c.diagnostic_severity_overrides
+                != config.diagnostic_severity_overrides
+                || generated_changed;
+            let features_changed = c.features != config.features;
+            let supplements_changed = c.additional_source_roots != config.additional_source_roots
+                || c.additional_classpath != config.additional_classpath
+                || c.kapt_classpath != config.kapt_classpath;
+            *c = config.clone();
+            (
+                changed,
+                overrides_changed,
+                features_changed,
+                supplements_changed,
+            )
+        };
 
-        // Store project root (project model resolution happens in initialized())
-        // Walk up from the rootUri to find the actual project root containing
-        // build system markers. Zed sometimes sets rootUri to a deep source
-        // directory (e.g. when opening a single file), so we need to find the
-        // real project root that has build.gradle.kts, pom.xml, etc.
-        if let Some(root_uri) = params.root_uri {
-            if let Ok(path) = root_uri.to_file_path() {
-                let resolved =
-                    project::prefer_nested_build_root(&project::find_project_root(&path));
-                if resolved != path {
-                    tracing::info!(
-                        "resolved project root from {} to {}",
-                        path.display(),
-                        resolved.display()
-                    );
-                }
-                let mut project_root = self.project_root.lock().await;
-                *project_root = Some(resolved);
+        if let Some(bridge) = self.get_bridge().await {
+            bridge.update_config(config.clone()).await;
+        }
+
+        // Re-resolve so removed entries disappear too, then rebuild the
+        // sidecar session with the new model.
+        if supplements_changed {
+            if let Some(root) = self.project_root.lock().await.clone() {
+                let server = self.clone();
+                let config = config.clone();
+                tokio::spawn(async move {
+                    let resolved = tokio::task::spawn_blocking(move || {
+                        project::resolve_project_with_fallback(&root, &config)
+                    })
+                    .await;
+                    match resolved {
+                        Ok(Ok(model)) => {
+                            *server.project_model.lock().await = Some(model);
+                            if let Err(e) = server.reanalyze_workspace().await {
+                                tracing::warn!(
+                                    "reanalyze after classpath change failed: {}",
+                                    e.message
+                                );
+                            }
+                        }
+                        Ok(Err(e)) => tracing::warn!("project re-resolution failed: {}", e),
+                        Err(e) => tracing::warn!("project re-resolution panicked: {}", e),
+                    }
+                });
             }
         }
 
-        // Parse initialization options as config
-        if let Some(options) = params.initialization_options {
-            if let Ok(config) = serde_json::from_value::<Config>(options) {
-                let mut c = self.config.lock().await;
-                *c = config;
+        if formatter_changed {
+            self.refresh_formatter(config.clone());
+        }
+
+        if features_changed {
+            self.sync_feature_registrations(config.features);
+        }
+
+        // Republish open documents so the new severities take effect.
+        if overrides_changed {
+            let open: Vec<Url> = {
+                let documents = self.documents.lock().await;
+                documents.all().map(|(uri, _)| uri.clone()).collect()
+            };
+            for uri in open {
+                self.analyze_document(&uri).await;
             }
         }
+    }
 
-        // Start the debounce loop
-        let tx = self.start_debounce_loop();
+    /// Asks the client's configuration whether Kotlin is formatted by
+    /// something else, and warns once if so. Only clients with the editor
+    /// settings to ask are checked. Must not be awaited from a notification
+    /// handler, like [`Self::fetch_configuration`].
+    async fn detect_other_formatter(&self) {
         {
-            let mut debounce = self.debounce_tx.lock().await;
-            *debounce = Some(tx);
+            let support = self.client_support.lock().await;
+            if !support.workspace_configuration || !support.editor_sections {
+                tracing::debug!("client has no editor settings to check for another formatter");
+                return;
+            }
         }
-
-        // File watchers for build files and .editorconfig
-        let file_watchers = vec![
-            FileSystemWatcher {
-                glob_pattern: GlobPattern::String("**/*.gradle.kts".into()),
-                kind: None,
-            },
-            FileSystemWatcher {
-                glob_pattern: GlobPattern::String("**/*.gradle".into()),
-                kind: None,
-            },
-            FileSystemWatcher {
-                glob_pattern: GlobPattern::String("**/gradle.properties".into()),
-                kind: None,
-            },
-            FileSystemWatcher {
-                glob_pattern: GlobPattern::String("**/.editorconfig".into()),
-                kind: None,
-            },
-        ];
-
-        let result = InitializeResult {
-            capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Options(
-                    TextDocumentSyncOptions {
-                        open_close: Some(true),
-                        change: Some(TextDocumentSyncKind::FULL),
-                        save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
-                            include_text: Some(false),
-                        })),
-                        ..Default::default()
-                    },
-                )),
-                completion_provider: Some(CompletionOptions {
-                    trigger_characters: Some(vec![".".into(), ":".into(), "@".into()]),
-                    resolve_provider: Some(false),
-                    ..Default::default()
-                }),
-                hover_provider: Some(HoverProviderCapability::Simple(true)),
-                signature_help_provider: Some(SignatureHelpOptions {
-                    trigger_characters: Some(vec!["(".into(), ",".into()]),
-                    ..Default::default()
-                }),
-                definition_provider: Some(OneOf::Left(true)),
-                references_provider: Some(OneOf::Left(true)),
-                document_formatting_provider: Some(OneOf::Left(true)),
-                rename_provider: Some(OneOf::Left(true)),
-                code_action_provider: Some(CodeActionProviderCapability::Options(
-                    CodeActionOptions {
-                        code_action_kinds: Some(vec![
-                            CodeActionKind::QUICKFIX,
-                            CodeActionKind::REFACTOR,
-                            CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
-                        ]),
-                        ..Default::default()
-                    },
-                )),
-                code_lens_provider: Some(CodeLensOptions {
-                    resolve_provider: Some(false),
-                }),
-                inlay_hint_provider: Some(OneOf::Right(InlayHintServerCapabilities::Options(
-                    InlayHintOptions {
-                        work_done_progress_options: WorkDoneProgressOptions {
-                            work_done_progress: Some(false),
-                        },
-                        resolve_provider: Some(false),
-                    },
-                ))),
-                workspace: Some(WorkspaceServerCapabilities {
-                    workspace_folders: None,
-                    file_operations: None,
-                }),
-                execute_command_provider: Some(ExecuteCommandOptions {
-                    commands: supported_analyzer_command_ids(),
-                    work_done_progress_options: WorkDoneProgressOptions {
-                        work_done_progress: Some(false),
-                    },
-                }),
-                workspace_symbol_provider: Some(OneOf::Left(true)),
-                semantic_tokens_provider: Some(
-                    SemanticTokensServerCapabilities::SemanticTokensOptions(
-                        SemanticTokensOptions {
-                            legend: SemanticTokensLegend {
-                                token_types: vec![
-                                    SemanticTokenType::FUNCTION,
-                                    SemanticTokenType::PARAMETER,
-                                    SemanticTokenType::VARIABLE,
-                                    SemanticTokenType::PROPERTY,
-                                    SemanticTokenType::CLASS,
-                                    SemanticTokenType::TYPE,
-                                    SemanticTokenType::STRING,
-                                    SemanticTokenType::COMMENT,
-                                    SemanticTokenType::KEYWORD,
-                                    SemanticTokenType::DECORATOR,
-                                    SemanticTokenType::NUMBER,
-                                    SemanticTokenType::ENUM_MEMBER,
-                                    SemanticTokenType::TYPE_PARAMETER,
-                                ],
-                                token_modifiers: vec![],
-                            },
-                            full: Some(SemanticTokensFullOptions::Bool(true)),
-                            range: None,
-                            work_done_progress_options: WorkDoneProgressOptions {
-                                work_done_progress: Some(false),
-                            },
-                        },
-                    ),
-                ),
-                call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
-                ..Default::default()
-            },
-            server_info: Some(ServerInfo {
-                name: "kotlin-analyzer".into(),
-                version: Some(env!("CARGO_PKG_VERSION").into()),
-            }),
-        };
-
-        // Register file watchers dynamically since they need a registered client
-        let client = self.client.clone();
-        tokio::spawn(async move {
-            tokio::time::sleep(Duration::from_millis(100)).await;
-            let registration = Registration {
-                id: "watch-files".to_string(),
-                method: "workspace/didChangeWatchedFiles".to_string(),
-                register_options: Some(
-                    serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
-                        watchers: file_watchers,
-                    })
-                    .unwrap(),
-                ),
+        let items = [conflicts::EDITOR_SECTION, conflicts::FWCD_SECTION]
+            .into_iter()
+            .map(|section| ConfigurationItem {
+                scope_uri: None,
+                section: Some(section.into()),
+            })
+            .collect();
+        let answers =
+            match tokio::time::timeout(Duration::from_secs(5), self.client.configuration(items))
+                .await
+            {
+                Ok(Ok(answers)) => answers,
+                _ => return,
             };
-
-            match tokio::time::timeout(
-                Duration::from_secs(5),
-                client.register_capability(vec![registration]),
-            )
+        let answer = |i: usize| answers.get(i).cloned().unwrap_or(Value::Null);
+        let Some(formatter) = conflicts::other_formatter(&answer(0), &answer(1)) else {
+            return;
+        };
+        let suppress = self
+            .config
+            .lock()
             .await
-            {
-                Ok(Err(e)) => tracing::warn!("failed to register file watchers: {:?}", e),
-                Err(_) => tracing::warn!("file watcher registration timed out"),
-                Ok(Ok(())) => {}
-            }
-        });
+            .suppress_formatting_if_other_formatter;
+        let message = conflicts::conflict_message(&formatter, suppress);
+        tracing::warn!("{}", message);
+        *self.other_formatter.lock().await = Some(formatter);
+        self.client
+            .show_message(MessageType::WARNING, message)
+            .await;
+    }
 
-        Ok(result)
+    /// Whether the client has sent `shutdown`. `main` reads this after the
+    /// `exit` notification ends the server loop to pick the exit code.
+    pub fn shutdown_requested(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.shutting_down)
     }
 
-    async fn initialized(&self, _: InitializedParams) {
-        tracing::info!("kotlin-analyzer: initialized");
+    /// Returns a cloned Arc to the bridge, releasing the mutex immediately.
+    /// This prevents holding the bridge mutex during long-running sidecar requests,
+    /// which would block all other LSP handlers.
+    async fn get_bridge(&self) -> Option<Arc<Bridge>> {
+        let guard = self.bridge.lock().await;
+        guard.as_ref().map(Arc::clone)
+    }
 
-        // Spawn sidecar startup in a background task. tower-lsp processes
-        // notifications sequentially, so calling send_request (which awaits
-        // the client's response) from within a notification handler deadlocks.
-        let client = self.client.clone();
-        let bridge_holder = Arc::clone(&self.bridge);
-        let documents_holder = Arc::clone(&self.documents);
-        let config = self.config.lock().await.clone();
+    /// Builds `didOpen`/`didChange` params, with script hints for `.kts`
+    /// files and a `looseFile` flag for files outside the project root.
+    async fn document_sync_params(&self, uri: &Url, version: i32, text: &str) -> Value {
+        let mut params = if ScriptKind::from_uri(uri).is_none() {
+            document_sync_params(uri, version, text, None)
+        } else {
+            let model = self.project_model.lock().await;
+            document_sync_params(uri, version, text, model.as_ref())
+        };
+        if self.loose_files.lock().await.contains(uri) {
+            params["looseFile"] = Value::Bool(true);
+        }
+        params
+    }
+
+    /// Records `uri` as a loose file when it lies outside the project root,
+    /// e.g. a single file opened next to a workspace.
+    async fn track_loose_file(&self, uri: &Url) {
         let project_root = self.project_root.lock().await.clone();
+        if !is_loose_file(project_root.as_deref(), uri) {
+            return;
+        }
+        tracing::info!(
+            "{} is outside the project root, analyzing it on its own",
+            uri
+        );
+        self.loose_files.lock().await.insert(uri.clone());
+    }
 
-        tracing::debug!("about to spawn background task for sidecar startup");
-        tokio::spawn(async move {
-            tracing::debug!("initialized: background task started");
+    /// Returns true for documents over `maxFileSizeBytes`, which get no
+    /// semantic features so a single huge file cannot stall the sidecar.
+    async fn is_lexical_only(&self, uri: &Url) -> bool {
+        self.documents.lock().await.is_lexical_only(uri)
+    }
 
-            // Create progress token
-            let token = NumberOrString::String("kotlin-analyzer-startup".to_string());
+    /// Returns true if the document should be analyzed given the resolved
+    /// source roots. Files under `build/` or decompiled dependency sources
+    /// would otherwise be analyzed against a classpath that does not apply.
+    async fn is_in_source_roots(&self, uri: &Url) -> bool {
+        if self.config.lock().await.analyze_outside_source_roots {
+            return true;
+        }
+        let Ok(path) = uri.to_file_path() else {
+            return true;
+        };
+        if self.loose_files.lock().await.contains(uri) {
+            return true;
+        }
+        match self.project_model.lock().await.as_ref() {
+            Some(model) => model.contains_source(&path),
+            None => true,
+        }
+    }
 
-            // Use a timeout: if the client doesn't support workDoneProgress
-            // or doesn't respond, we still proceed with sidecar startup.
-            match tokio::time::timeout(
-                Duration::from_secs(5),
-                client.send_request::<lsp_types::request::WorkDoneProgressCreate>(
-                    WorkDoneProgressCreateParams {
-                        token: token.clone(),
-                    },
-                ),
-            )
+    /// Compiles the configured `excludePaths` against the current workspace
+    /// root, walking it for nested options files off the async runtime. The
+    /// result is cached until the patterns or the root change.
+    async fn exclude_matcher(&self) -> Arc<ExcludeMatcher> {
+        let patterns = self.config.lock().await.exclude_paths.clone();
+        let root = self.project_root.lock().await.clone();
+        let generated_roots = self
+            .project_model
+            .lock()
             .await
-            {
-                Ok(Err(e)) => tracing::warn!("failed to create progress token: {:?}", e),
-                Err(_) => tracing::warn!(
-                    "progress token creation timed out, client may not support workDoneProgress"
-                ),
-                Ok(Ok(())) => {}
+            .as_ref()
+            .map(|model| model.generated_source_roots.clone())
+            .unwrap_or_default();
+        let mut cached = self.excludes.lock().await;
+        if let Some(excludes) = cached
+            .as_ref()
+            .filter(|excludes| excludes.is_for(&patterns, root.as_deref(), &generated_roots))
+        {
+            return Arc::clone(excludes);
+        }
+        let excludes = match tokio::task::spawn_blocking(move || {
+            ExcludeMatcher::new(&patterns, root.as_deref(), &generated_roots)
+        })
+        .await
+        {
+            Ok(excludes) => Arc::new(excludes),
+            Err(e) => {
+                tracing::warn!("building the exclude matcher failed: {}", e);
+                return Arc::new(ExcludeMatcher::new(&[], None, &[]));
             }
+        };
+        *cached = Some(Arc::clone(&excludes));
+        excludes
+    }
 
-            client
-                .send_notification::<lsp_types::notification::Progress>(ProgressParams {
-                    token: token.clone(),
-                    value: ProgressParamsValue::WorkDone(WorkDoneProgress::Begin(
-                        WorkDoneProgressBegin {
-                            title: "Starting Kotlin sidecar".to_string(),
-                            message: Some("Resolving project...".to_string()),
-                            percentage: None,
-                            cancellable: Some(false),
-                        },
-                    )),
-                })
+    /// Resolves the configured `readOnlySourceRoots` against the workspace root.
+    async fn read_only_roots(&self) -> ReadOnlyRoots {
+        let roots = self.config.lock().await.read_only_source_roots.clone();
+        let root = self.project_root.lock().await.clone();
+        ReadOnlyRoots::new(&roots, root.as_deref())
+    }
+
+    /// Whether `uri` lies in a read-only source root, where the server
+    /// offers nothing that would edit it.
+    async fn is_read_only(&self, uri: &Url) -> bool {
+        is_read_only_uri(&self.read_only_roots().await, uri)
+    }
+
+    /// Tells the user once per session that `feature` only sees open files
+    /// because `analysisScope` is `openFiles`.
+    async fn warn_if_scope_limited(&self, feature: &str) {
+        if self.config.lock().await.analysis_scope != AnalysisScope::OpenFiles {
+            return;
+        }
+        tracing::debug!("{} limited to open files by analysisScope", feature);
+        if !self.scope_warning_shown.swap(true, Ordering::Relaxed) {
+            self.client
+                .show_message(
+                    MessageType::INFO,
+                    format!(
+                        "kotlin-analyzer: {feature} only covers open files while analysisScope is \"openFiles\"."
+                    ),
+                )
                 .await;
+        }
+    }
 
-            // Resolve project model first so we can pass it to the sidecar
-            let project_model = if let Some(ref root) = project_root {
-                tracing::debug!("resolving project model for {:?}", root);
-                match project::resolve_project_with_fallback(root, &config) {
-                    Ok(model) => {
-                        tracing::debug!(
-                            "project resolved: {} source roots, {} classpath entries, {} compiler flags",
-                            model.source_roots.len(),
-                            model.classpath.len(),
-                            model.compiler_flags.len()
-                        );
+    /// Uses the nearest build marker above `uri` as the project root when
+    /// `initialize` did not supply one. Returns the root only the first time,
+    /// so the project is resolved once rather than on every open.
+    async fn derive_project_root(&self, uri: &Url) -> Option<PathBuf> {
+        let mut project_root = self.project_root.lock().await;
+        if project_root.is_some() {
+            return None;
+        }
+        let dir = uri.to_file_path().ok()?.parent()?.to_path_buf();
+        let root = project::find_project_root(&dir);
+        tracing::info!(
+            "project root {} (derived from first opened document {})",
+            root.display(),
+            uri
+        );
+        *project_root = Some(root.clone());
+        Some(root)
+    }
 
-                        Some(model)
-                    }
-                    Err(e) => {
-                        tracing::warn!("project resolution failed: {}, using stdlib-only", e);
-                        let _ = client
-                            .show_message(
-                                MessageType::WARNING,
-                                format!("kotlin-analyzer: project resolution failed: {}. Using stdlib-only analysis.", e),
-                            )
-                            .await;
-                        None
-                    }
+    /// Resolves the project at `root` when the server starts, off the async
+    /// runtime, and stores the model unless one for another root, such as a
+    /// root derived from the first opened document, got there first. Settles
+    /// `project_resolved` either way, so the sidecar is never started ahead
+    /// of the model it needs.
+    async fn resolve_startup_project(&self, root: PathBuf, config: Config) {
+        tracing::debug!("resolving project model for {:?}", root);
+        let resolved = tokio::task::spawn_blocking({
+            let root = root.clone();
+            move || project::resolve_project_with_fallback(&root, &config)
+        })
+        .await;
+        match resolved {
+            Ok(Ok(model)) => {
+                tracing::debug!(
+                    "project resolved: {} source roots, {} classpath entries, {} compiler flags",
+                    model.source_roots.len(),
+                    model.classpath.len(),
+                    model.compiler_flags.len()
+                );
+                let mut current = self.project_model.lock().await;
+                match current.as_ref() {
+                    Some(existing) if existing.project_root != root => tracing::debug!(
+                        "keeping the project model for {:?} over the startup one",
+                        existing.project_root
+                    ),
+                    _ => *current = Some(model),
                 }
-            } else {
-                tracing::debug!("no project root, using stdlib-only analysis");
-                None
-            };
+            }
+            Ok(Err(e)) => {
+                tracing::warn!("project resolution failed: {}, using stdlib-only", e);
+                self.client
+                    .show_message(
+                        MessageType::WARNING,
+                        format!("kotlin-analyzer: project resolution failed: {e}. Using stdlib-only analysis."),
+                    )
+                    .await;
+            }
+            Err(e) => tracing::warn!("project resolution task failed: {}", e),
+        }
+        self.project_resolved.send_replace(true);
+    }
 
-            client
-                .send_notification::<lsp_types::notification::Progress>(ProgressParams {
-                    token: token.clone(),
-                    value: ProgressParamsValue::WorkDone(WorkDoneProgress::Report(
-                        WorkDoneProgressReport {
-                            message: Some("Starting JVM sidecar...".to_string()),
-                            percentage: None,
-                            cancellable: Some(false),
-                        },
-                    )),
-                })
-                .await;
+    /// Waits for [`Self::resolve_startup_project`] to settle and returns the
+    /// model it left, or `Err` when it is still running after `timeout`.
+    async fn await_project_model(
+        &self,
+        timeout: Duration,
+    ) -> Result<Option<ProjectModel>, tokio::time::error::Elapsed> {
+        let mut resolved = self.project_resolved.subscribe();
+        // The sender lives as long as the server, so waiting cannot fail.
+        let _ = tokio::time::timeout(timeout, resolved.wait_for(|done| *done)).await?;
+        Ok(self.project_model.lock().await.clone())
+    }
 
-            // Try to start the sidecar
-            let java_path = match crate::bridge::find_java() {
-                Ok(p) => p,
+    /// Stops the sidecar whenever it has been idle for `sidecarIdleShutdownMs`
+    /// with no documents open; the bridge starts it again on the next
+    /// request. Runs until `shutdown`.
+    async fn suspend_idle_sidecar(&self) {
+        let mut interval = tokio::time::interval(IDLE_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            if self.shutting_down.load(Ordering::SeqCst) {
+                return;
+            }
+            let Some(idle_ms) = self.config.lock().await.sidecar_idle_shutdown_ms else {
+                continue;
+            };
+            if self.documents.lock().await.all().next().is_some() {
+                continue;
+            }
+            if let Some(bridge) = self.get_bridge().await {
+                bridge.suspend_if_idle(Duration::from_millis(idle_ms)).await;
+            }
+        }
+    }
+
+    /// Scans `roots` for top-level declarations on a blocking thread, so
+    /// workspace symbols have answers while the sidecar starts.
+    async fn build_lexical_symbol_index(&self, roots: Vec<PathBuf>) {
+        let excludes = self.exclude_matcher().await;
+        let scanned =
+            match tokio::task::spawn_blocking(move || LexicalSymbolIndex::scan(&roots, &excludes))
+                .await
+            {
+                Ok(index) => index,
                 Err(e) => {
-                    tracing::error!("JVM not found: {}", e);
-                    client
-                        .send_notification::<lsp_types::notification::Progress>(ProgressParams {
-                            token: token.clone(),
-                            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(
-                                WorkDoneProgressEnd {
-                                    message: Some(format!("Failed: {}", e)),
-                                },
-                            )),
-                        })
-                        .await;
-                    client
-                        .show_message(
-                            MessageType::ERROR,
-                            "kotlin-analyzer: JDK 17+ required but not found. Set JAVA_HOME or KOTLIN_LS_JAVA_HOME.",
-                        )
-                        .await;
+                    tracing::warn!("lexical symbol scan failed: {}", e);
                     return;
                 }
             };
+        self.lexical_symbols.lock().await.merge(scanned);
+    }
 
-            tracing::debug!("java found at {:?}", java_path);
+    /// Workspace symbols from the lexical index, for when the sidecar cannot
+    /// answer yet.
+    async fn lexical_workspace_symbols(
+        &self,
+        query: &str,
+        excludes: &ExcludeMatcher,
+    ) -> Option<Vec<SymbolInformation>> {
+        let mut symbols = self.lexical_symbols.lock().await.search(query);
+        symbols.retain(|symbol| !is_excluded_uri(excludes, &symbol.location.uri));
+        tracing::debug!(
+            "workspace symbols: {} lexical matches while the sidecar is not ready",
+            symbols.len()
+        );
+        (!symbols.is_empty()).then_some(symbols)
+    }
 
-            let requested_kotlin_version = project_model
-                .as_ref()
-                .and_then(|model| model.kotlin_version.clone());
+    /// Keeps the lexical symbol index in step with an open `.kt` document.
+    async fn update_lexical_symbols(&self, uri: &Url, kind: DocumentKind, text: &str) {
+        if kind == DocumentKind::Kotlin && uri.path().ends_with(".kt") {
+            self.lexical_symbols.lock().await.update(uri.clone(), text);
+        }
+    }
 
-            let sidecar_runtime =
-                runtime::resolve_sidecar_runtime(requested_kotlin_version.as_deref());
-            let sidecar_runtime = match sidecar_runtime {
-                Some(runtime) => runtime,
-                None => {
-                    tracing::warn!("sidecar runtime not found, semantic features unavailable");
-                    client
-                        .send_notification::<lsp_types::notification::Progress>(ProgressParams {
-                            token: token.clone(),
-                            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(
-                                WorkDoneProgressEnd {
-                                    message: Some("sidecar runtime not found".to_string()),
-                                },
-                            )),
-                        })
-                        .await;
-                    client
-                        .show_message(
-                            MessageType::WARNING,
-                            "kotlin-analyzer: sidecar runtime not found. Semantic features are unavailable.",
-                        )
-                        .await;
-                    return;
-                }
-            };
+    /// Inserts the `package` declaration matching its directory into a newly
+    /// created, empty file. Returns the inserted text once the client
+    /// applied it.
+    async fn scaffold_package(&self, uri: &Url, path: &Path) -> Option<String> {
+        let package = self
+            .project_model
+            .lock()
+            .await
+            .as_ref()?
+            .package_for(path)?;
+        let header = format!("package {package}\n\n");
+        let edit = WorkspaceEdit {
+            changes: Some(HashMap::from([(
+                uri.clone(),
+                vec![TextEdit::new(Range::default(), header.clone())],
+            )])),
+            document_changes: None,
+            change_annotations: None,
+        };
+        match self.client.apply_edit(edit).await {
+            Ok(response) if response.applied => Some(header),
+            Ok(response) => {
+                tracing::debug!(
+                    "client declined the package declaration for {}: {:?}",
+                    uri,
+                    response.failure_reason
+                );
+                None
+            }
+            Err(e) => {
+                tracing::warn!("failed to add a package declaration to {}: {}", uri, e);
+                None
+            }
+        }
+    }
 
-            tracing::info!(
-                requested = requested_kotlin_version.as_deref().unwrap_or("unknown"),
-                selected = sidecar_runtime.kotlin_version.as_deref().unwrap_or("unknown"),
-                selection_counter = sidecar_runtime.selection_reason.counter_name(),
-                reason = sidecar_runtime.selection_reason.description(),
-                classpath = ?sidecar_runtime.classpath,
-                main_class = sidecar_runtime.main_class.as_deref().unwrap_or("<jar>"),
-                "selected sidecar runtime"
+    /// Reconfigures a sidecar that was started stdlib-only because the
+    /// project was still resolving, once the model arrives.
+    async fn reanalyze_once_resolved(&self) {
+        let mut resolved = self.project_resolved.subscribe();
+        if resolved.wait_for(|done| *done).await.is_err()
+            || self.project_model.lock().await.is_none()
+        {
+            return;
+        }
+        tracing::info!("project resolved after the sidecar started, reconfiguring it");
+        if let Err(e) = self.reanalyze_workspace().await {
+            tracing::warn!(
+                "reanalyze after late project resolution failed: {}",
+                e.message
             );
+        }
+    }
 
-            if let Some(message) = sidecar_runtime.selection_warning_message() {
-                client.show_message(MessageType::WARNING, message).await;
+    /// Resolves the project at a root derived in `did_open` and rebuilds the
+    /// sidecar session for it, so opening a single file still gets its
+    /// classpath and compiler flags.
+    async fn adopt_derived_project_root(&self, root: PathBuf) {
+        let config = self.config.lock().await.clone();
+        let resolved = tokio::task::spawn_blocking(move || {
+            project::resolve_project_with_fallback(&root, &config)
+        })
+        .await;
+        match resolved {
+            Ok(Ok(model)) => {
+                tracing::info!(
+                    "resolved derived project: {} source roots, {} classpath entries",
+                    model.source_roots.len(),
+                    model.classpath.len()
+                );
+                let mut current = self.project_model.lock().await;
+                *current = Some(model);
+                // `initialized` holds the lock while it starts the sidecar,
+                // so one that never started yet will pick the model up.
+                let started = match self.get_bridge().await {
+                    Some(bridge) => bridge.start_count() > 0,
+                    None => false,
+                };
+                if !started {
+                    tracing::debug!("sidecar not started yet, it starts with the derived project");
+                    return;
+                }
             }
-
-            let bridge = Arc::new(Bridge::new(sidecar_runtime, java_path, config));
-
-            // Store the bridge BEFORE starting so LSP requests that arrive
-            // during sidecar startup can reach it and wait for Ready state
-            // (request buffering in bridge.rs handles the wait).
-            {
-                let mut b = bridge_holder.lock().await;
-                *b = Some(bridge);
+            Ok(Err(e)) => {
+                tracing::warn!("project resolution for derived root failed: {}", e);
+                self.client
+                    .show_message(
+                        MessageType::WARNING,
+                        format!("kotlin-analyzer: project resolution failed: {e}. Using stdlib-only analysis."),
+                    )
+                    .await;
+                return;
             }
-            // Lock is released here so hover/completion handlers can access
-            // the bridge while start() is running. Their requests will wait
-            // for Ready via the watch channel in bridge.rs.
+            Err(e) => {
+                tracing::warn!("project resolution task failed: {}", e);
+                return;
+            }
+        }
 
-            // Prepare project config for the sidecar
-            let project_root_str = project_root
-                .as_ref()
-                .map(|p| p.to_string_lossy().to_string())
-                .unwrap_or_default();
+        if let Err(e) = self.reanalyze_workspace().await {
+            tracing::warn!(
+                "failed to reconfigure sidecar for derived project root: {}",
+                e.message
+            );
+        }
+    }
 
-            let (classpath, compiler_flags, source_roots) = match &project_model {
-                Some(model) => {
-                    let cp: Vec<String> = model
-                        .classpath
-                        .iter()
-                        .map(|p| p.to_string_lossy().to_string())
-                        .collect();
-                    let cf: Vec<String> = model.compiler_flags.clone();
-                    let sr: Vec<String> = model
-                        .source_roots
-                        .iter()
-                        .chain(model.generated_source_roots.iter())
-                        .map(|p| p.to_string_lossy().to_string())
-                        .collect();
-                    (cp, cf, sr)
-                }
-                None => (Vec::new(), Vec::new(), Vec::new()),
-            };
+    /// Re-evaluates the size limit for a document and returns true when it is
+    /// lexical-only. The first time a document crosses the limit the user is
+    /// told why semantic features are missing.
+    async fn update_lexical_only(&self, uri: &Url, text_len: usize) -> bool {
+        let limit = self.config_for(uri).await.max_file_size_bytes;
+        let oversized = text_len as u64 > limit;
+        let newly_oversized = self.documents.lock().await.set_lexical_only(uri, oversized);
+        if newly_oversized {
+            tracing::info!(
+                "{} is {} bytes (limit {}), semantic analysis disabled",
+                uri,
+                text_len,
+                limit
+            );
+            self.client
+                .show_message(
+                    MessageType::INFO,
+                    format!(
+                        "kotlin-analyzer: {} exceeds maxFileSizeBytes ({} > {}); only formatting is available for it.",
+                        uri.path().rsplit('/').next().unwrap_or(uri.path()),
+                        text_len,
+                        limit
+                    ),
+                )
+                .await;
+        }
+        oversized
+    }
 
-            // Note: when no source roots are found (no build system), the sidecar
-            // falls back to creating ad-hoc KtFile objects from opened files via
-            // KtPsiFactory. This is faster than scanning the entire project root
-            // and works well for basic features (hover, completion, diagnostics).
-            if source_roots.is_empty() {
-                tracing::debug!("no source roots found, sidecar will use per-file fallback");
+    /// Clears the diagnostics of a document that is no longer analyzed, so
+    /// results from before it was skipped do not linger.
+    async fn clear_skipped_diagnostics(&self, uri: &Url) {
+        let had_diagnostics = {
+            let mut documents = self.documents.lock().await;
+            let had = documents
+                .get_diagnostics(uri)
+                .is_some_and(|diagnostics| !diagnostics.is_empty());
+            if had {
+                documents.set_diagnostics(uri.clone(), Vec::new());
             }
+            had
+        };
+        if had_diagnostics {
+            deliver_diagnostics(
+                &self.client,
+                &self.diagnostics_delivery,
+                uri.clone(),
+                Vec::new(),
+                None,
+            )
+            .await;
+        }
+    }
 
-            tracing::debug!(
-                "starting sidecar with project_root={}, classpath={} entries, source_roots={:?}",
-                project_root_str,
-                classpath.len(),
-                source_roots
-            );
+    /// Publishes diagnostics for a document by requesting analysis from the sidecar.
+    async fn analyze_document(&self, uri: &Url) {
+        tracing::debug!("analyze_document: {}", uri);
 
-            // Re-acquire lock briefly to call start() on the bridge
-            let start_result = {
-                let b = bridge_holder.lock().await;
-                let bridge = b.as_ref().unwrap();
-                bridge
-                    .start(
-                        Some(project_root_str.as_str()),
-                        &classpath,
-                        &compiler_flags,
-                        &source_roots,
-                    )
-                    .await
-            };
+        if skips_gradle_script_analysis(uri, self.project_model.lock().await.as_ref()) {
+            tracing::debug!("analyze_document: skipping build script {}", uri);
+            return;
+        }
 
-            match start_result {
-                Ok(()) => {
-                    tracing::info!("sidecar started successfully");
-                    client
-                        .send_notification::<lsp_types::notification::Progress>(ProgressParams {
-                            token: token.clone(),
-                            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(
-                                WorkDoneProgressEnd {
-                                    message: Some("Ready".to_string()),
-                                },
-                            )),
-                        })
-                        .await;
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => {
+                tracing::debug!(
+                    "analyze_document: bridge is None (sidecar still starting), skipping"
+                );
+                return;
+            }
+        };
 
-                    // Replay all open documents: send didOpen + analyze for each
-                    // file that was opened before the sidecar was ready.
-                    let open_docs: Vec<(Url, String, i32, DocumentKind)> = {
-                        let docs = documents_holder.lock().await;
-                        docs.all()
-                            .map(|(uri, doc)| {
-                                (uri.clone(), doc.text.clone(), doc.version, doc.kind)
-                            })
-                            .collect()
-                    };
+        let state = bridge.state().await;
+        if state != SidecarState::Ready {
+            tracing::warn!("analyze_document: sidecar state is {:?}, skipping", state);
+            return;
+        }
 
-                    if !open_docs.is_empty() {
-                        tracing::info!(
-                            "replaying {} open document(s) after sidecar startup",
-                            open_docs.len()
-                        );
-                    }
+        if !self.is_in_source_roots(uri).await {
+            tracing::info!(
+                "analyze_document: {} is outside the project's source roots, skipping (set analyzeOutsideSourceRoots to override)",
+                uri
+            );
+            self.clear_skipped_diagnostics(uri).await;
+            return;
+        }
 
-                    // Clone the Arc<Bridge> out of the mutex so the lock is released
-                    // before the replay loop. This prevents blocking hover/completion
-                    // handlers during the potentially long replay.
-                    let bridge_arc = {
-                        let guard = bridge_holder.lock().await;
-                        guard.as_ref().map(Arc::clone)
-                    };
-                    if let Some(bridge) = bridge_arc {
-                        for (uri, text, version, kind) in &open_docs {
-                            tracing::debug!("replay: sending didOpen for {}", uri);
-                            let _ = bridge
-                                .notify(
-                                    kind.did_open_method(),
-                                    Some(serde_json::json!({
-                                        "uri": uri.as_str(),
-                                        "version": version,
-                                        "text": text,
-                                    })),
-                                )
-                                .await;
+        if is_excluded_uri(self.exclude_matcher().await.as_ref(), uri) {
+            tracing::debug!("analyze_document: {} matches excludePaths, skipping", uri);
+            self.clear_skipped_diagnostics(uri).await;
+            return;
+        }
 
-                            // Send didChange + analyze
-                            let _ = bridge
-                                .notify(
-                                    kind.did_change_method(),
-                                    Some(serde_json::json!({
-                                        "uri": uri.as_str(),
-                                        "version": version,
-                                        "text": text,
-                                    })),
-                                )
-                                .await;
+        if self.is_lexical_only(uri).await {
+            tracing::debug!("analyze_document: skipping oversized document {}", uri);
+            self.clear_skipped_diagnostics(uri).await;
+            return;
+        }
 
-                            if !kind.supports_kotlin_analysis() {
-                                continue;
-                            }
+        let (text, version) = {
+            let documents = self.documents.lock().await;
+            match documents.get(uri) {
+                Some(d) if d.kind.supports_kotlin_analysis() => (d.text.clone(), d.version),
+                Some(_) => {
+                    tracing::debug!("analyze_document: skipping non-Kotlin document {}", uri);
+                    return;
+                }
+                None => return,
+            }
+        };
 
-                            match bridge
-                                .request(
-                                    "analyze",
-                                    Some(serde_json::json!({ "uri": uri.as_str() })),
-                                )
-                                .await
-                            {
-                                Ok(result) => {
-                                    let diagnostics = parse_diagnostics_static(&result);
-                                    tracing::info!(
-                                        "replay: {} returned {} diagnostics",
-                                        uri,
-                                        diagnostics.len()
-                                    );
-                                    client
-                                        .publish_diagnostics(uri.clone(), diagnostics, None)
-                                        .await;
-                                }
-                                Err(e) => {
-                                    tracing::warn!("replay: analysis failed for {}: {}", uri, e);
-                                }
-                            }
-                        }
-                    }
+        // Send the document content to the sidecar
+        let _ = bridge
+            .notify(
+                "textDocument/didChange",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                    "version": version,
+                    "text": text,
+                })),
+            )
+            .await;
 
-                    // --- Project-wide background analysis ---
-                    let bg_bridge = Arc::clone(&bridge_holder);
-                    let bg_documents = Arc::clone(&documents_holder);
-                    let bg_client = client.clone();
-                    tokio::spawn(async move {
-                        // Small delay to let open-file diagnostics settle
-                        tokio::time::sleep(Duration::from_secs(2)).await;
+        // Until a document has been analyzed once, show that analysis is
+        // running; a cold start can take most of a minute.
+        let first_analysis = {
+            let never_analyzed = self.documents.lock().await.get_diagnostics(uri).is_none();
+            never_analyzed && self.pending_first_analysis.lock().await.insert(uri.clone())
+        };
+        if first_analysis {
+            deliver_diagnostics(
+                &self.client,
+                &self.diagnostics_delivery,
+                uri.clone(),
+                vec![analysis_in_progress_diagnostic()],
+                Some(version),
+            )
+            .await;
+        }
 
-                        // Create progress token for background analysis
-                        let bg_token =
-                            NumberOrString::String("kotlin-analyzer-background".to_string());
-                        let _ = tokio::time::timeout(
-                            Duration::from_secs(5),
-                            bg_client.send_request::<lsp_types::request::WorkDoneProgressCreate>(
-                                WorkDoneProgressCreateParams {
-                                    token: bg_token.clone(),
-                                },
-                            ),
-                        )
-                        .await;
+        let lint_binary = lint::ktlint_binary(&self.config_for(uri).await);
+        if let Some(binary) = lint_binary.clone() {
+            spawn_lint(
+                self.client.clone(),
+                Arc::clone(&self.documents),
+                Arc::clone(&self.lint_diagnostics),
+                Arc::clone(&self.diagnostics_delivery),
+                binary,
+                uri.clone(),
+                version,
+                text.clone(),
+            );
+        }
 
-                        bg_client
+        // Request analysis
+        let baseline = self.documents.lock().await.incremental_baseline(uri, &text);
+        let analysis = bridge
+            .request(
+                "analyze",
+                Some(analyze_params(
+                    uri,
+                    version,
+                    baseline.as_ref().map(|(edit, _)| edit),
+                )),
+            )
+            .await;
+        if first_analysis {
+            self.pending_first_analysis.lock().await.remove(uri);
+        }
+        match analysis {
+            Ok(result) => {
+                tracing::debug!(
+                    "analyze_document: raw sidecar response for {}: {}",
+                    uri,
+                    result
+                );
+                let overrides = self
+                    .config
+                    .lock()
+                    .await
+                    .diagnostic_severity_overrides
+                    .clone();
+                let generated = self.generated_classes.lock().await.clone();
+                let fresh = apply_severity_overrides(
+                    demote_generated_references(
+                        parse_diagnostics(&result, Some(&text)),
+                        &generated,
+                    ),
+                    &overrides,
+                );
+                let fresh = demote_read_only(&self.read_only_roots().await, uri, fresh);
+                let diagnostics = merge_reanalyzed(&result, baseline, fresh);
+                tracing::debug!(
+                    "analyze_document: {} returned {} diagnostics",
+                    uri,
+                    diagnostics.len()
+                );
+                for d in &diagnostics {
+                    tracing::debug!(
+                        "  diagnostic: {:?} at L{}:{} - {}",
+                        d.severity,
+                        d.range.start.line,
+                        d.range.start.character,
+                        d.message
+                    );
+                }
+                // Cache diagnostics so they survive didClose/didOpen tab switches
+                {
+                    let mut documents = self.documents.lock().await;
+                    documents.set_analysis(uri.clone(), text, diagnostics.clone());
+                }
+                self.apply_analyzed_edits(uri, &result).await;
+                let diagnostics = with_lint(
+                    &self.lint_diagnostics,
+                    lint_binary.is_some(),
+                    uri,
+                    version,
+                    diagnostics,
+                )
+                .await;
+                deliver_diagnostics(
+                    &self.client,
+                    &self.diagnostics_delivery,
+                    uri.clone(),
+                    diagnostics,
+                    None,
+                )
+                .await;
+            }
+            Err(e) => {
+                tracing::warn!("analyze_document: analysis failed for {}: {}", uri, e);
+                if first_analysis {
+                    // Drop the placeholder rather than leave it up forever.
+                    deliver_diagnostics(
+                        &self.client,
+                        &self.diagnostics_delivery,
+                        uri.clone(),
+                        Vec::new(),
+                        None,
+                    )
+                    .await;
+                }
+            }
+        }
+    }
+
+    async fn execute_analyzer_command(&self, request: AnalyzerCommandRequest) -> LspResult<Value> {
+        match request {
+            AnalyzerCommandRequest::OpenTestTarget(args) => {
+                let target_uri = Url::parse(&args.target_uri).map_err(|error| {
+                    invalid_params_error(format!("invalid targetUri for openTestTarget: {error}"))
+                })?;
+
+                self.show_document(target_uri, args.selection).await?;
+                Ok(serde_json::json!({ "shown": true }))
+            }
+            AnalyzerCommandRequest::CreateAndOpenTestTarget(args) => {
+                let target_uri = Url::parse(&args.target_uri).map_err(|error| {
+                    invalid_params_error(format!(
+                        "invalid targetUri for createAndOpenTestTarget: {error}"
+                    ))
+                })?;
+
+                let target_path = PathBuf::from(&args.target_path);
+                let created = !target_path.exists();
+                self.create_target_file_if_missing(&target_path, &args.initial_contents)
+                    .await?;
+                self.show_document(target_uri, args.selection).await?;
+
+                Ok(serde_json::json!({
+                    "created": created,
+                    "shown": true
+                }))
+            }
+            AnalyzerCommandRequest::Status => Ok(self.status().await),
+            AnalyzerCommandRequest::Reanalyze => self.reanalyze_workspace().await,
+            AnalyzerCommandRequest::ClearCache => self.clear_project_cache().await,
+            AnalyzerCommandRequest::NextDiagnostic(args) => {
+                self.navigate_diagnostics(args, true).await
+            }
+            AnalyzerCommandRequest::PrevDiagnostic(args) => {
+                self.navigate_diagnostics(args, false).await
+            }
+            AnalyzerCommandRequest::SelfTest => self.self_test().await,
+            AnalyzerCommandRequest::ChangeSignature(args) => self.change_signature(args).await,
+            AnalyzerCommandRequest::RevealGenerated(args) => self.reveal_generated(args).await,
+            AnalyzerCommandRequest::DiscoverTests(args) => self.discover_tests(args).await,
+        }
+    }
+
+    /// Runs hover, completion, definition, and diagnostics against a small
+    /// snippet opened under a unique temp URI, then closes it again. The
+    /// snippet is sent as a loose file so it never forces a session rebuild.
+    /// Uses the regular handlers, so disabled features and lexical-only mode
+    /// show up as they would for the user. Reports a summary via
+    /// `showMessage`.
+    async fn self_test(&self) -> LspResult<Value> {
+        let Some(bridge) = self.get_bridge().await else {
+            return Self::server_not_initialized_error();
+        };
+        let uri = self_test_uri()
+            .ok_or_else(|| request_failed_error("could not build a self-test URI"))?;
+
+        if let Err(e) = bridge
+            .notify(
+                "textDocument/didOpen",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                    "version": 1,
+                    "text": SELF_TEST_SOURCE,
+                    "looseFile": true,
+                })),
+            )
+            .await
+        {
+            return Err(request_failed_error(format!(
+                "self-test could not open its snippet: {e}"
+            )));
+        }
+
+        let at = |line, character| TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            position: Position::new(line, character),
+        };
+        let mut results = Vec::new();
+
+        let outcome = if self.feature_enabled(Feature::Hover).await {
+            self_test_step(self.hover(HoverParams {
+                text_document_position_params: at(5, 19),
+                work_done_progress_params: Default::default(),
+            }))
+            .await
+        } else {
+            SelfTestOutcome::Disabled
+        };
+        results.push(("hover", outcome));
+
+        let outcome = if self.feature_enabled(Feature::Completion).await {
+            self_test_step(self.completion(CompletionParams {
+                text_document_position: at(6, 12),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            }))
+            .await
+        } else {
+            SelfTestOutcome::Disabled
+        };
+        results.push(("completion", outcome));
+
+        let outcome = self_test_step(self.goto_definition(GotoDefinitionParams {
+            text_document_position_params: at(5, 19),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        }))
+        .await;
+        results.push(("definition", outcome));
+
+        // Diagnostics go straight to the sidecar so nothing is published for
+        // the snippet.
+        let outcome = self_test_step(async {
+            let result = bridge
+                .request(
+                    "analyze",
+                    Some(serde_json::json!({ "uri": uri.as_str(), "version": 1 })),
+                )
+                .await
+                .map_err(|e| request_failed_error(e.to_string()))?;
+            let diagnostics = parse_diagnostics(&result, Some(SELF_TEST_SOURCE));
+            Ok((!diagnostics.is_empty()).then_some(diagnostics))
+        })
+        .await;
+        results.push(("diagnostics", outcome));
+
+        let _ = bridge
+            .notify(
+                "textDocument/didClose",
+                Some(serde_json::json!({ "uri": uri.as_str() })),
+            )
+            .await;
+
+        let (message_type, summary) = self_test_summary(&results);
+        self.client.show_message(message_type, summary).await;
+
+        Ok(serde_json::json!({
+            "passed": results.iter().all(|(_, outcome)| outcome.passed()),
+            "results": results
+                .iter()
+                .map(|(feature, outcome)| {
+                    serde_json::json!({ "feature": feature, "status": outcome.to_string() })
+                })
+                .collect::<Vec<_>>(),
+        }))
+    }
+
+    /// Returns the location of the cached diagnostic after (or before) the
+    /// cursor, wrapping around at the ends of the file, or `null` when the
+    /// document has no diagnostics.
+    async fn navigate_diagnostics(
+        &self,
+        args: DiagnosticNavigationArgs,
+        forward: bool,
+    ) -> LspResult<Value> {
+        let uri = Url::parse(&args.uri).map_err(|error| {
+            invalid_params_error(format!("invalid uri for diagnostic navigation: {error}"))
+        })?;
+        let range = {
+            let documents = self.documents.lock().await;
+            documents
+                .get_diagnostics(&uri)
+                .and_then(|diagnostics| adjacent_diagnostic(diagnostics, args.position, forward))
+        };
+        Ok(match range {
+            Some(range) => serde_json::to_value(Location::new(uri, range))
+                .map_err(|e| request_failed_error(e.to_string()))?,
+            None => Value::Null,
+        })
+    }
+
+    /// Asks the sidecar to rewrite a function's parameter list and every call
+    /// site, then applies the result through `workspace/applyEdit`. Changes the
+    /// sidecar refuses (e.g. removing a parameter the body still uses) fail
+    /// with its reason.
+    async fn change_signature(&self, args: ChangeSignatureArgs) -> LspResult<Value> {
+        let uri = Url::parse(&args.uri).map_err(|error| {
+            invalid_params_error(format!("invalid uri for changeSignature: {error}"))
+        })?;
+        let Some(bridge) = self.get_bridge().await else {
+            return Self::server_not_initialized_error();
+        };
+
+        let result = bridge
+            .request(
+                "changeSignature",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                    "line": args.position.line + 1,
+                    "character": args.position.character,
+                    "parameters": args.parameters,
+                })),
+            )
+            .await
+            .map_err(|e| request_failed_error(format!("changeSignature failed: {e}")))?;
+        if let Some(reason) = result.get("reason").and_then(|r| r.as_str()) {
+            return Err(request_failed_error(format!(
+                "cannot change signature: {reason}"
+            )));
+        }
+
+        let edits = parse_workspace_edits(&result);
+        if edits.is_empty() {
+            return Ok(serde_json::json!({ "applied": false }));
+        }
+        let files = edits.len();
+        let response = self
+            .client
+            .apply_edit(WorkspaceEdit {
+                changes: Some(edits),
+                document_changes: None,
+                change_annotations: None,
+            })
+            .await
+            .map_err(|e| request_failed_error(format!("changeSignature edit failed: {e}")))?;
+        if !response.applied {
+            tracing::warn!(
+                "client rejected changeSignature edit for {}: {:?}",
+                uri,
+                response.failure_reason,
+            );
+        }
+        Ok(serde_json::json!({ "applied": response.applied, "files": files }))
+    }
+
+    /// Looks up the KSP/KAPT output generated for the class at `position`
+    /// (a Room `_Impl`, a Moshi adapter, ...) under the generated source
+    /// roots, unlike go-to-definition, which stays on the source. Returns
+    /// `{ locations }` for the client to open, with a `reason` when it is
+    /// empty.
+    async fn reveal_generated(&self, args: RevealGeneratedArgs) -> LspResult<Value> {
+        let uri = Url::parse(&args.uri).map_err(|error| {
+            invalid_params_error(format!("invalid uri for revealGenerated: {error}"))
+        })?;
+        let Some(bridge) = self.get_bridge().await else {
+            return Self::server_not_initialized_error();
+        };
+
+        let result = bridge
+            .request(
+                "revealGenerated",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                    "line": args.position.line + 1,
+                    "character": args.position.character,
+                })),
+            )
+            .await
+            .map_err(|e| request_failed_error(format!("revealGenerated failed: {e}")))?;
+
+        let locations = self.parse_locations(&result);
+        if locations.is_empty() {
+            let reason = result
+                .get("reason")
+                .and_then(|r| r.as_str())
+                .unwrap_or("no generated code corresponds to this symbol");
+            return Ok(serde_json::json!({ "locations": [], "reason": reason }));
+        }
+        Ok(serde_json::json!({ "locations": locations }))
+    }
+
+    /// Asks the sidecar for the tests in a file, or in the workspace, and
+    /// returns them as `{ tests }`, a tree of `TestItem`s for a test explorer.
+    /// Files without tests, and excluded files, add nothing.
+    async fn discover_tests(&self, args: DiscoverTestsArgs) -> LspResult<Value> {
+        let uri = args
+            .uri
+            .map(|uri| Url::parse(&uri))
+            .transpose()
+            .map_err(|error| {
+                invalid_params_error(format!("invalid uri for discoverTests: {error}"))
+            })?;
+        let Some(bridge) = self.get_bridge().await else {
+            return Self::server_not_initialized_error();
+        };
+
+        let params = match &uri {
+            Some(uri) => serde_json::json!({ "uri": uri.as_str() }),
+            None => serde_json::json!({}),
+        };
+        let result = bridge
+            .request("discoverTests", Some(params))
+            .await
+            .map_err(|e| request_failed_error(format!("discoverTests failed: {e}")))?;
+
+        let excludes = self.exclude_matcher().await;
+        let tests: Vec<TestItem> = result
+            .get("tests")
+            .and_then(|t| t.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(parse_test_item)
+            .filter(|item| !is_excluded_uri(&excludes, &item.uri))
+            .collect();
+        Ok(serde_json::json!({ "tests": tests }))
+    }
+
+    /// Rebuilds the sidecar's analysis session from the current project model
+    /// and republishes diagnostics for open documents. Lighter than a JVM
+    /// restart when results go stale after a pull or branch switch.
+    async fn reanalyze_workspace(&self) -> LspResult<Value> {
+        let Some(bridge) = self.get_bridge().await else {
+            return Self::server_not_initialized_error();
+        };
+
+        let token = NumberOrString::String("kotlin-analyzer-reanalyze".to_string());
+        let progress_created = tokio::time::timeout(
+            Duration::from_secs(5),
+            self.client
+                .send_request::<lsp_types::request::WorkDoneProgressCreate>(
+                    WorkDoneProgressCreateParams {
+                        token: token.clone(),
+                    },
+                ),
+        )
+        .await
+        .is_ok_and(|result| result.is_ok());
+        let report = |value: WorkDoneProgress| {
+            let client = self.client.clone();
+            let token = token.clone();
+            async move {
+                if progress_created {
+                    client
+                        .send_notification::<lsp_types::notification::Progress>(ProgressParams {
+                            token,
+                            value: ProgressParamsValue::WorkDone(value),
+                        })
+                        .await;
+                }
+            }
+        };
+
+        report(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: "Reanalyzing workspace".to_string(),
+            message: Some("Rebuilding analysis session...".to_string()),
+            percentage: None,
+            cancellable: Some(false),
+        }))
+        .await;
+
+        let project_root = self
+            .project_root
+            .lock()
+            .await
+            .as_ref()
+            .map(|p| p.to_string_lossy().to_string());
+        let model = self.project_model.lock().await.clone();
+        let args = SidecarProjectArgs::from_model(model.as_ref(), bridge.kotlin_version());
+        self.response_cache.lock().await.clear();
+        if let Err(e) = bridge
+            .reanalyze(
+                project_root.as_deref(),
+                &args.classpath,
+                &args.compiler_flags,
+                &args.source_roots,
+                &args.generated_source_roots,
+                &args.classpath_sources,
+                &args.kapt_classpath,
+            )
+            .await
+        {
+            report(WorkDoneProgress::End(WorkDoneProgressEnd {
+                message: Some(format!("Failed: {e}")),
+            }))
+            .await;
+            return Err(request_failed_error(format!("reanalyze failed: {e}")));
+        }
+
+        let open_uris: Vec<Url> = {
+            let documents = self.documents.lock().await;
+            documents.all().map(|(uri, _)| uri.clone()).collect()
+        };
+        report(WorkDoneProgress::Report(WorkDoneProgressReport {
+            message: Some(format!(
+                "Republishing diagnostics for {} open file(s)...",
+                open_uris.len()
+            )),
+            percentage: None,
+            cancellable: Some(false),
+        }))
+        .await;
+        for uri in &open_uris {
+            self.analyze_document(uri).await;
+        }
+
+        report(WorkDoneProgress::End(WorkDoneProgressEnd {
+            message: Some("Done".to_string()),
+        }))
+        .await;
+
+        Ok(serde_json::json!({ "reanalyzed": true, "documents": open_uris.len() }))
+    }
+
+    /// Unregisters the features the started sidecar reported it cannot
+    /// serve, so the client stops offering them.
+    async fn withdraw_sidecar_unsupported_features(&self) {
+        let Some(bridge) = self.get_bridge().await else {
+            return;
+        };
+        let Some(info) = bridge.sidecar_info().await else {
+            return;
+        };
+        let unsupported: HashSet<Feature> = Feature::ALL
+            .into_iter()
+            .filter(|&feature| {
+                feature_sidecar_method(feature).is_some_and(|method| !info.supports(method))
+            })
+            .collect();
+        if unsupported.is_empty() {
+            return;
+        }
+        tracing::info!(
+            "withdrawing features the sidecar cannot serve: {:?}",
+            unsupported
+        );
+        self.feature_registrations.lock().await.sidecar_unsupported = unsupported;
+        let features = self.config.lock().await.features;
+        self.sync_feature_registrations(features);
+    }
+
+    /// Warns once when the project's Kotlin language version differs from
+    /// the sidecar's, since diagnostics may then be inaccurate. Silenced by
+    /// `suppressVersionMismatchWarning`.
+    async fn warn_kotlin_version_mismatch(&self, project_version: Option<&str>) {
+        if self.config.lock().await.suppress_version_mismatch_warning {
+            return;
+        }
+        let Some(bridge) = self.get_bridge().await else {
+            return;
+        };
+        let Some(message) = bridge
+            .sidecar_info()
+            .await
+            .and_then(|info| info.kotlin_version_mismatch(project_version))
+        else {
+            return;
+        };
+        if !self.version_mismatch_warned.swap(true, Ordering::Relaxed) {
+            tracing::warn!("{}", message);
+            self.client
+                .show_message(MessageType::WARNING, message)
+                .await;
+        }
+    }
+
+    /// Completes a `fastStart` launch: resolves the project off the async
+    /// runtime, then rebuilds the sidecar's stdlib-only session with the full
+    /// classpath and re-analyzes open documents.
+    async fn finish_fast_start(&self) {
+        let Some(root) = self.project_root.lock().await.clone() else {
+            return;
+        };
+        let config = self.config.lock().await.clone();
+        let resolved = tokio::task::spawn_blocking(move || {
+            project::resolve_project_with_fallback(&root, &config)
+        })
+        .await;
+        let model = match resolved {
+            Ok(Ok(model)) => model,
+            Ok(Err(e)) => {
+                tracing::warn!("fastStart: project resolution failed: {}", e);
+                self.client
+                    .show_message(
+                        MessageType::WARNING,
+                        format!("kotlin-analyzer: project resolution failed: {e}. Staying with stdlib-only analysis."),
+                    )
+                    .await;
+                return;
+            }
+            Err(e) => {
+                tracing::warn!("fastStart: project resolution panicked: {}", e);
+                return;
+            }
+        };
+        tracing::info!(
+            "fastStart: project resolved ({} classpath entries), upgrading the sidecar session",
+            model.classpath.len()
+        );
+        let project_version = model.kotlin_version.clone();
+        *self.project_model.lock().await = Some(model);
+
+        match self.reanalyze_workspace().await {
+            Ok(_) => {
+                self.warn_kotlin_version_mismatch(project_version.as_deref())
+                    .await;
+                self.client
+                    .show_message(
+                        MessageType::INFO,
+                        "kotlin-analyzer: project classpath loaded, full analysis is available.",
+                    )
+                    .await;
+            }
+            Err(e) => tracing::warn!(
+                "fastStart: upgrading the sidecar session failed: {}",
+                e.message
+            ),
+        }
+    }
+
+    /// Deletes the cached project model, resolves the project from scratch
+    /// and rebuilds the sidecar session with the result. The outcome is also
+    /// shown to the user, since the command is usually run from a palette.
+    async fn clear_project_cache(&self) -> LspResult<Value> {
+        let Some(root) = self.project_root.lock().await.clone() else {
+            return Err(request_failed_error("clearCache needs a project root"));
+        };
+        let config = self.config.lock().await.clone();
+        let resolved = tokio::task::spawn_blocking(move || {
+            project::clear_cache(&project::cache_dir(&root))?;
+            project::resolve_project_with_fallback(&root, &config)
+        })
+        .await;
+        let model = match resolved {
+            Ok(Ok(model)) => model,
+            Ok(Err(e)) => {
+                let message = format!("kotlin-analyzer: clearing the project cache failed: {e}");
+                tracing::warn!("{}", message);
+                self.client
+                    .show_message(MessageType::ERROR, message.clone())
+                    .await;
+                return Err(request_failed_error(message));
+            }
+            Err(e) => {
+                return Err(request_failed_error(format!(
+                    "clearing the project cache panicked: {e}"
+                )))
+            }
+        };
+        tracing::info!(
+            "project re-resolved after clearing the cache ({} classpath entries)",
+            model.classpath.len()
+        );
+        *self.project_model.lock().await = Some(model);
+
+        match self.reanalyze_workspace().await {
+            Ok(result) => {
+                self.client
+                    .show_message(
+                        MessageType::INFO,
+                        "kotlin-analyzer: project cache cleared and the project re-resolved.",
+                    )
+                    .await;
+                Ok(result)
+            }
+            Err(e) => {
+                self.client
+                    .show_message(
+                        MessageType::ERROR,
+                        format!("kotlin-analyzer: project cache cleared, but {}", e.message),
+                    )
+                    .await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Re-reads `.kotlin-analyzer.json` after it changed on disk and
+    /// reconfigures the sidecar with the new source roots, classpath and
+    /// compiler flags. An invalid file is reported to the user and the
+    /// current model is kept; a deleted file falls back to build-system
+    /// resolution.
+    async fn reload_manual_config(&self, root: PathBuf) {
+        let config = self.config.lock().await.clone();
+        let resolved = tokio::task::spawn_blocking(move || {
+            if root.join(".kotlin-analyzer.json").exists() {
+                // Manual configs never run the build tool, so skip the cache.
+                project::resolve_project(&root, &config, true)
+            } else {
+                project::resolve_project_with_fallback(&root, &config)
+            }
+        })
+        .await;
+
+        match resolved {
+            Ok(Ok(model)) => {
+                tracing::info!("reloaded project model after .kotlin-analyzer.json change");
+                *self.project_model.lock().await = Some(model);
+                if let Err(e) = self.reanalyze_workspace().await {
+                    tracing::warn!("reanalyze after manual config change failed: {}", e.message);
+                }
+            }
+            Ok(Err(e)) => {
+                tracing::warn!("manual config reload failed: {}", e);
+                self.client
+                    .show_message(
+                        MessageType::WARNING,
+                        format!("kotlin-analyzer: keeping previous project configuration: {e}"),
+                    )
+                    .await;
+            }
+            Err(e) => tracing::warn!("manual config reload panicked: {}", e),
+        }
+    }
+
+    /// Reports sidecar health, ping latency, and tool resolution for the
+    /// `status` command.
+    async fn status(&self) -> Value {
+        let (sidecar_state, ping, sidecar_info) = match self.get_bridge().await {
+            Some(bridge) => (
+                Some(format!("{:?}", bridge.state().await)),
+                Some(bridge.ping_stats().await),
+                bridge.sidecar_info().await,
+            ),
+            None => (None, None, None),
+        };
+        let formatter = self.formatter.lock().await.clone();
+        let project_root = self.project_root.lock().await.clone();
+
+        serde_json::json!({
+            "serverVersion": env!("CARGO_PKG_VERSION"),
+            "sidecarState": sidecar_state,
+            "lastPingLatencyMs": ping.and_then(|p| p.last_ms),
+            "averagePingLatencyMs": ping.and_then(|p| p.average_ms),
+            "sidecar": sidecar_info.map(|info| serde_json::json!({
+                "kotlinVersion": info.kotlin_version,
+                "classpathResolved": info.classpath_resolved,
+                "missingClasspathEntries": info.missing_classpath_entries,
+                "unsupportedFeatures": info.unsupported_features,
+            })),
+            "projectRoot": project_root.map(|p| p.to_string_lossy().to_string()),
+            "formatter": formatter.map(|f| serde_json::json!({
+                "path": f.path.to_string_lossy(),
+                "version": f.version,
+            })),
+        })
+    }
+
+    async fn create_target_file_if_missing(
+        &self,
+        target_path: &Path,
+        initial_contents: &str,
+    ) -> LspResult<()> {
+        if target_path.exists() {
+            return Ok(());
+        }
+
+        if let Some(parent) = target_path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(|error| {
+                request_failed_error(format!(
+                    "failed to create directories for {}: {}",
+                    target_path.display(),
+                    error
+                ))
+            })?;
+        }
+
+        let temp_path = temporary_target_path(target_path);
+        let write_result = async {
+            let mut file = tokio::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp_path)
+                .await?;
+            file.write_all(initial_contents.as_bytes()).await?;
+            file.flush().await?;
+            tokio::fs::rename(&temp_path, target_path).await
+        }
+        .await;
+
+        match write_result {
+            Ok(()) => Ok(()),
+            Err(error) => {
+                let _ = tokio::fs::remove_file(&temp_path).await;
+                Err(request_failed_error(format!(
+                    "failed to create {}: {}",
+                    target_path.display(),
+                    error
+                )))
+            }
+        }
+    }
+
+    async fn show_document(&self, uri: Url, selection: Option<CommandSelection>) -> LspResult<()> {
+        let shown = self
+            .client
+            .send_request::<CompatibleShowDocument>(ShowDocumentParams {
+                uri,
+                external: Some(false),
+                take_focus: Some(true),
+                selection: selection.map(CommandSelection::into_range),
+            })
+            .await
+            .map_err(|error| {
+                request_failed_error(format!("window/showDocument failed: {error}"))
+            })?;
+
+        if show_document_acknowledged(shown) {
+            Ok(())
+        } else {
+            Err(request_failed_error(
+                "window/showDocument was not acknowledged",
+            ))
+        }
+    }
+
+    fn parse_result_version(result: &Value) -> Option<i32> {
+        result
+            .get("version")
+            .and_then(|value| value.as_i64())
+            .and_then(|value| i32::try_from(value).ok())
+    }
+
+    async fn apply_analyzed_edits(&self, uri: &Url, result: &Value) {
+        let planned_version = match Self::parse_result_version(result) {
+            Some(version) => version,
+            None => return,
+        };
+
+        let current_version = {
+            let documents = self.documents.lock().await;
+            documents.get(uri).map(|doc| doc.version)
+        };
+        if !analyze_edits_are_current(planned_version, current_version, result) {
+            tracing::debug!(
+                "skipping analyzer edit for {} because version changed (planned={}, current={:?}, response={:?})",
+                uri,
+                planned_version,
+                current_version,
+                response_version(result),
+            );
+            return;
+        }
+
+        let edits = parse_workspace_edits(result);
+        if edits.is_empty() {
+            return;
+        }
+
+        let edit = WorkspaceEdit {
+            changes: Some(edits),
+            document_changes: None,
+            change_annotations: None,
+        };
+
+        match self.client.apply_edit(edit).await {
+            Ok(response) if response.applied => {}
+            Ok(response) => {
+                tracing::warn!(
+                    "client rejected analyzer edit for {}: {:?}",
+                    uri,
+                    response.failure_reason,
+                );
+            }
+            Err(error) => {
+                tracing::warn!("failed to apply analyzer edit for {}: {}", uri, error);
+            }
+        }
+    }
+
+    /// Starts the debounce loop for document analysis.
+    fn start_debounce_loop(&self) -> tokio::sync::mpsc::Sender<Url> {
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<Url>(64);
+
+        let client = self.client.clone();
+        let documents = Arc::clone(&self.documents);
+        let bridge = Arc::clone(&self.bridge);
+        let config = Arc::clone(&self.config);
+        let generated_classes = Arc::clone(&self.generated_classes);
+        let project_model = Arc::clone(&self.project_model);
+        let project_root = Arc::clone(&self.project_root);
+        let lint_diagnostics = Arc::clone(&self.lint_diagnostics);
+        let diagnostics_delivery = Arc::clone(&self.diagnostics_delivery);
+
+        tokio::spawn(async move {
+            let mut pending: Option<Url> = None;
+            let debounce_duration = Duration::from_millis(300);
+
+            loop {
+                tokio::select! {
+                    uri = rx.recv() => {
+                        match uri {
+                            Some(uri) => {
+                                pending = Some(uri);
+                            }
+                            None => break,
+                        }
+                    }
+                    _ = tokio::time::sleep(debounce_duration), if pending.is_some() => {
+                        if let Some(uri) = pending.take() {
+                            if skips_gradle_script_analysis(&uri, project_model.lock().await.as_ref()) {
+                                continue;
+                            }
+                            let bridge_arc = {
+                                let guard = bridge.lock().await;
+                                guard.as_ref().map(Arc::clone)
+                            };
+                            if let Some(bridge) = bridge_arc {
+                                if bridge.state().await == SidecarState::Ready {
+                                    let document_store = documents.lock().await;
+                                    if let Some(doc) = document_store.get(&uri) {
+                                        let text = doc.text.clone();
+                                        let version = doc.version;
+                                        let kind = doc.kind;
+                                        drop(document_store);
+
+                                        let _ = bridge.notify(kind.did_change_method(), Some(serde_json::json!({
+                                            "uri": uri.as_str(),
+                                            "version": version,
+                                            "text": &text,
+                                        }))).await;
+
+                                        if !kind.supports_kotlin_analysis() {
+                                            continue;
+                                        }
+
+                                        let lint_binary = lint::ktlint_binary(&*config.lock().await);
+                                        if let Some(binary) = lint_binary.clone() {
+                                            spawn_lint(client.clone(), Arc::clone(&documents), Arc::clone(&lint_diagnostics), Arc::clone(&diagnostics_delivery), binary, uri.clone(), version, text.clone());
+                                        }
+
+                                        let baseline = documents.lock().await.incremental_baseline(&uri, &text);
+                                        let params = analyze_params(&uri, version, baseline.as_ref().map(|(edit, _)| edit));
+                                        match bridge.request("analyze", Some(params)).await {
+                                            Ok(result) => {
+                                                if let Some(planned_version) = Self::parse_result_version(&result) {
+                                                    let current_version = {
+                                                        let document_store = documents.lock().await;
+                                                        document_store.get(&uri).map(|doc| doc.version)
+                                                    };
+                                                    if current_version == Some(planned_version) {
+                                                        let edits = parse_workspace_edits(&result);
+                                                        if !edits.is_empty() {
+                                                            let edit = WorkspaceEdit {
+                                                                changes: Some(edits),
+                                                                document_changes: None,
+                                                                change_annotations: None,
+                                                            };
+                                                            match client.apply_edit(edit).await {
+                                                                Ok(response) if response.applied => {}
+                                                                Ok(response) => tracing::warn!(
+                                                                    "client rejected analyzer edit for {}: {:?}",
+                                                                    uri,
+                                                                    response.failure_reason,
+                                                                ),
+                                                                Err(error) => tracing::warn!(
+                                                                    "failed to apply analyzer edit for {}: {}",
+                                                                    uri,
+                                                                    error,
+                                                                ),
+                                                            }
+                                                        }
+                                                    }
+                                                }
+                                                let (overrides, read_only) = {
+                                                    let config = config.lock().await;
+                                                    let read_only = ReadOnlyRoots::new(&config.read_only_source_roots, project_root.lock().await.as_deref());
+                                                    (config.diagnostic_severity_overrides.clone(), read_only)
+                                                };
+                                                let generated = generated_classes.lock().await.clone();
+                                                let fresh = apply_severity_overrides(demote_generated_references(parse_diagnostics(&result, Some(&text)), &generated), &overrides);
+                                                let fresh = demote_read_only(&read_only, &uri, fresh);
+                                                let diagnostics = merge_reanalyzed(&result, baseline, fresh);
+                                                documents.lock().await.set_analysis(uri.clone(), text, diagnostics.clone());
+                                                let diagnostics = with_lint(&lint_diagnostics, lint_binary.is_some(), &uri, version, diagnostics).await;
+                                                deliver_diagnostics(&client, &diagnostics_delivery, uri, diagnostics, None).await;
+                                            }
+                                            Err(e) => {
+                                                tracing::warn!("debounced analysis failed: {}", e);
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        tx
+    }
+}
+
+/// Latest ktlint findings per open document, with the document version they
+/// were computed for.
+type LintDiagnostics = Arc<Mutex<HashMap<Url, (i32, Vec<Diagnostic>)>>>;
+
+/// Cancellation signals by progress token, for work the client may cancel
+/// with `window/workDoneProgress/cancel`.
+type ProgressCancellations = Arc<Mutex<HashMap<NumberOrString, Arc<tokio::sync::Notify>>>>;
+
+/// Runs the sidecar `request` until it answers or the client cancels
+/// `token`. Cancelling drops the request, which sends the sidecar
+/// `$/cancelRequest`, and yields `BridgeError::Cancelled`.
+async fn cancellable_request(
+    cancellations: &ProgressCancellations,
+    token: &NumberOrString,
+    request: impl std::future::Future<Output = Result<Value, crate::error::Error>>,
+) -> Result<Value, crate::error::Error> {
+    let cancel = Arc::new(tokio::sync::Notify::new());
+    cancellations
+        .lock()
+        .await
+        .insert(token.clone(), Arc::clone(&cancel));
+    let result = tokio::select! {
+        result = request => result,
+        _ = cancel.notified() => Err(BridgeError::Cancelled.into()),
+    };
+    cancellations.lock().await.remove(token);
+    result
+}
+
+/// Lints `text` with ktlint alongside compiler analysis, so lint never
+/// delays compiler errors. On arrival the findings replace the document's
+/// previous ones and are published merged with its compiler diagnostics,
+/// unless the document changed in the meantime. Before the first compiler
+/// result they are only stored, for that result to pick up.
+#[allow(clippy::too_many_arguments)]
+fn spawn_lint(
+    client: Client,
+    documents: Arc<Mutex<DocumentStore>>,
+    lint_diagnostics: LintDiagnostics,
+    delivery: SharedDiagnosticsDelivery,
+    binary: PathBuf,
+    uri: Url,
+    version: i32,
+    text: String,
+) {
+    tokio::spawn(async move {
+        let path = uri.to_file_path().ok();
+        let findings = match lint::run_ktlint(&binary, path.as_deref(), &text).await {
+            Ok(findings) => findings,
+            Err(e) => {
+                tracing::warn!("ktlint failed for {}: {}", uri, e);
+                return;
+            }
+        };
+        let compiler = {
+            let documents = documents.lock().await;
+            if documents.get(&uri).map(|doc| doc.version) != Some(version) {
+                return;
+            }
+            documents.get_diagnostics(&uri).cloned()
+        };
+        let merged = compiler.map(|compiler| lint::merge_lint(compiler, &findings));
+        lint_diagnostics
+            .lock()
+            .await
+            .insert(uri.clone(), (version, findings));
+        if let Some(merged) = merged {
+            deliver_diagnostics(&client, &delivery, uri, merged, None).await;
+        }
+    });
+}
+
+/// Compiler diagnostics about to be published, merged with the document's
+/// ktlint findings when lint diagnostics are on. Findings for another
+/// `version` of the document are left out; a newer lint run publishes its own.
+async fn with_lint(
+    lint_diagnostics: &LintDiagnostics,
+    enabled: bool,
+    uri: &Url,
+    version: i32,
+    diagnostics: Vec<Diagnostic>,
+) -> Vec<Diagnostic> {
+    if !enabled {
+        return diagnostics;
+    }
+    match lint_diagnostics.lock().await.get(uri) {
+        Some((linted, findings)) if *linted == version => lint::merge_lint(diagnostics, findings),
+        _ => diagnostics,
+    }
+}
+
+/// How diagnostics reach the client, settled in `initialize` from
+/// `diagnosticsMode` and the client's capabilities.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct DiagnosticsDelivery {
+    /// Publish with `textDocument/publishDiagnostics`.
+    push: bool,
+    /// Advertise and answer `textDocument/diagnostic`.
+    pull: bool,
+    /// Ask the client to pull again with `workspace/diagnostic/refresh`
+    /// whenever a document's diagnostics change, since nothing is pushed.
+    refresh: bool,
+}
+
+/// Push only, as before `initialize` has seen the client.
+impl Default for DiagnosticsDelivery {
+    fn default() -> Self {
+        Self {
+            push: true,
+            pull: false,
+            refresh: false,
+        }
+    }
+}
+
+impl DiagnosticsDelivery {
+    /// Without a configured mode, pulls from clients that support it and
+    /// pushes to the rest. `Both` always pushes and also answers pulls from
+    /// clients that support them. `Pull` falls back to pushing for a client
+    /// that can neither pull nor be asked to, which would get nothing.
+    fn new(mode: Option<DiagnosticsMode>, capabilities: &ClientCapabilities) -> Self {
+        let client_pulls = capabilities
+            .text_document
+            .as_ref()
+            .is_some_and(|t| t.diagnostic.is_some());
+        let client_refreshes = capabilities
+            .workspace
+            .as_ref()
+            .and_then(|w| w.diagnostic.as_ref())
+            .and_then(|d| d.refresh_support)
+            .unwrap_or(false);
+        let (push, pull) = match mode {
+            Some(DiagnosticsMode::Push) => (true, false),
+            Some(DiagnosticsMode::Pull) if client_pulls || client_refreshes => (false, true),
+            Some(DiagnosticsMode::Pull) => (true, false),
+            Some(DiagnosticsMode::Both) => (true, client_pulls),
+            None => (!client_pulls, client_pulls),
+        };
+        Self {
+            push,
+            pull,
+            refresh: !push && client_refreshes,
+        }
+    }
+}
+
+type SharedDiagnosticsDelivery = Arc<Mutex<DiagnosticsDelivery>>;
+
+/// Hands a document's new diagnostics to the client: publishes them when
+/// pushing, otherwise asks the client to pull them. The refresh request is
+/// sent from its own task so callers do not wait on the client's answer.
+async fn deliver_diagnostics(
+    client: &Client,
+    delivery: &SharedDiagnosticsDelivery,
+    uri: Url,
+    diagnostics: Vec<Diagnostic>,
+    version: Option<i32>,
+) {
+    let delivery = *delivery.lock().await;
+    if delivery.push {
+        client.publish_diagnostics(uri, diagnostics, version).await;
+    } else if delivery.refresh {
+        let client = client.clone();
+        tokio::spawn(async move {
+            if let Err(e) = client.workspace_diagnostic_refresh().await {
+                tracing::debug!("workspace/diagnostic/refresh failed: {}", e);
+            }
+        });
+    }
+}
+
+/// Params for `analyze`. With an edit since the last analysis, the sidecar
+/// re-checks only the declarations it touches.
+fn analyze_params(uri: &Url, version: i32, edit: Option<&incremental::EditRegion>) -> Value {
+    let mut params = serde_json::json!({
+        "uri": uri.as_str(),
+        "version": version,
+    });
+    if let Some(edit) = edit {
+        params["range"] = edit.analyze_range();
+    }
+    params
+}
+
+/// The diagnostics to publish for an `analyze` result: `fresh` as is after a
+/// whole-file check, or merged into the baseline's when the sidecar limited
+/// itself to a region.
+fn merge_reanalyzed(
+    result: &Value,
+    baseline: Option<(incremental::EditRegion, Vec<Diagnostic>)>,
+    fresh: Vec<Diagnostic>,
+) -> Vec<Diagnostic> {
+    match (baseline, incremental::reanalyzed_lines(result)) {
+        (Some((edit, previous)), Some(lines)) => {
+            incremental::merge_diagnostics(&previous, &edit, lines, fresh)
+        }
+        _ => fresh,
+    }
+}
+
+/// Whether `uri` is a file opened outside `project_root`. False for files
+/// inside the project, non-file URIs, and when there is no project root yet.
+fn is_loose_file(project_root: Option<&Path>, uri: &Url) -> bool {
+    match (project_root, uri.to_file_path()) {
+        (Some(root), Ok(path)) => !path.starts_with(root),
+        _ => false,
+    }
+}
+
+/// Params for `textDocument/didOpen` and `textDocument/didChange`. `.kts`
+/// documents also carry `scriptKind`, `scriptClasspath`, and `scriptImports`.
+fn document_sync_params(
+    uri: &Url,
+    version: i32,
+    text: &str,
+    model: Option<&ProjectModel>,
+) -> Value {
+    let mut params = serde_json::json!({
+        "uri": uri.as_str(),
+        "version": version,
+        "text": text,
+    });
+    if let (Some(Value::Object(script)), Some(fields)) =
+        (script_sync_params(uri, text, model), params.as_object_mut())
+    {
+        fields.extend(script);
+    }
+    params
+}
+
+/// Returns true if the URI points to a Gradle build script (.gradle.kts in any
+/// location, or .kts files inside buildSrc/ or gradle/ directories).
+fn is_gradle_script(uri: &Url) -> bool {
+    if let Ok(path) = uri.to_file_path() {
+        let path_str = path.to_string_lossy();
+        path_str.ends_with(".gradle.kts")
+            || (path_str.ends_with(".kts")
+                && (path_str.contains("/buildSrc/") || path_str.contains("/gradle/")))
+    } else {
+        false
+    }
+}
+
+/// Whether diagnostics are skipped for a Gradle script. `.gradle.kts` files
+/// are analyzed against the Gradle API once the init script reported a
+/// script classpath; without one, and for other `.kts` files under `buildSrc/`
+/// or `gradle/`, they produce hundreds of false positives.
+fn skips_gradle_script_analysis(uri: &Url, model: Option<&ProjectModel>) -> bool {
+    let has_script_classpath = model.is_some_and(|model| !model.script_classpath.is_empty());
+    is_gradle_script(uri)
+        && !(has_script_classpath && ScriptKind::from_uri(uri) == Some(ScriptKind::GradleBuild))
+}
+
+/// `generatedClassPatterns` compiled once per configuration, since every
+/// analysis result is checked against it.
+#[derive(Debug, Clone, Default)]
+struct GeneratedClassMatcher {
+    globs: Arc<GlobSet>,
+}
+
+impl GeneratedClassMatcher {
+    /// Compiles `patterns`, warning about and skipping invalid ones.
+    fn new(patterns: &[String]) -> Self {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            match Glob::new(pattern) {
+                Ok(glob) => {
+                    builder.add(glob);
+                }
+                Err(e) => {
+                    tracing::warn!("ignoring generatedClassPatterns entry {:?}: {}", pattern, e)
+                }
+            }
+        }
+        Self {
+            globs: Arc::new(builder.build().unwrap_or_default()),
+        }
+    }
+}
+
+/// Reports unresolved references to classes matching `generatedClassPatterns`
+/// as warnings: annotation processors generate those only on a build.
+fn demote_generated_references(
+    diagnostics: Vec<Diagnostic>,
+    generated: &GeneratedClassMatcher,
+) -> Vec<Diagnostic> {
+    let generated = &generated.globs;
+    if generated.is_empty() {
+        return diagnostics;
+    }
+    diagnostics
+        .into_iter()
+        .map(|mut diagnostic| {
+            let unresolved = matches!(
+                &diagnostic.code,
+                Some(NumberOrString::String(code)) if code == "UNRESOLVED_REFERENCE"
+            );
+            if unresolved
+                && unresolved_name(&diagnostic.message).is_some_and(|name| generated.is_match(name))
+            {
+                diagnostic.severity = Some(DiagnosticSeverity::WARNING);
+            }
+            diagnostic
+        })
+        .collect()
+}
+
+/// The name in an unresolved-reference message, in either the K2
+/// (`Unresolved reference 'Name'.`) or K1 (`Unresolved reference: Name`) form.
+fn unresolved_name(message: &str) -> Option<&str> {
+    let rest = message.strip_prefix("Unresolved reference")?;
+    let name = match rest.strip_prefix(':') {
+        Some(name) => name.trim(),
+        None => rest.trim().strip_prefix('\'')?.split('\'').next()?,
+    };
+    (!name.is_empty()).then_some(name)
+}
+
+/// Remaps severities per `diagnosticSeverityOverrides` (code to `error`,
+/// `warning`, `info`, `hint`, or `none`). Diagnostics mapped to `none` are
+/// dropped; an unrecognized severity leaves the diagnostic unchanged.
+fn apply_severity_overrides(
+    diagnostics: Vec<Diagnostic>,
+    overrides: &HashMap<String, String>,
+) -> Vec<Diagnostic> {
+    if overrides.is_empty() {
+        return diagnostics;
+    }
+    diagnostics
+        .into_iter()
+        .filter_map(|mut diagnostic| {
+            let code = match &diagnostic.code {
+                Some(NumberOrString::String(code)) => code.clone(),
+                Some(NumberOrString::Number(code)) => code.to_string(),
+                None => return Some(diagnostic),
+            };
+            let Some(severity) = overrides.get(&code) else {
+                return Some(diagnostic);
+            };
+            match severity.to_ascii_lowercase().as_str() {
+                "none" => return None,
+                "error" => diagnostic.severity = Some(DiagnosticSeverity::ERROR),
+                "warning" => diagnostic.severity = Some(DiagnosticSeverity::WARNING),
+                "info" | "information" => {
+                    diagnostic.severity = Some(DiagnosticSeverity::INFORMATION)
+                }
+                "hint" => diagnostic.severity = Some(DiagnosticSeverity::HINT),
+                other => tracing::warn!(
+                    "ignoring unknown severity {:?} in diagnosticSeverityOverrides for {}",
+                    other,
+                    code
+                ),
+            }
+            Some(diagnostic)
+        })
+        .collect()
+}
+
+/// Parses the sidecar's `diagnostics`, placing their ranges on `text`, the
+/// analyzed document, when it is known.
+fn parse_diagnostics(result: &Value, text: Option<&str>) -> Vec<Diagnostic> {
+    let diagnostics = match result.get("diagnostics").and_then(|d| d.as_array()) {
+        Some(arr) => arr,
+        None => return Vec::new(),
+    };
+
+    diagnostics
+        .iter()
+        .filter_map(|d| {
+            let severity = match d.get("severity")?.as_str()? {
+                "ERROR" => DiagnosticSeverity::ERROR,
+                "WARNING" => DiagnosticSeverity::WARNING,
+                "INFO" | "INFORMATION" => DiagnosticSeverity::INFORMATION,
+                "HINT" => DiagnosticSeverity::HINT,
+                _ => DiagnosticSeverity::ERROR,
+            };
+
+            let message = d.get("message")?.as_str()?.to_string();
+
+            Some(Diagnostic {
+                range: diagnostic_range(d, text)?,
+                severity: Some(severity),
+                code: d
+                    .get("code")
+                    .and_then(|c| c.as_str())
+                    .map(|c| NumberOrString::String(c.to_string())),
+                source: Some("kotlin-analyzer".into()),
+                message,
+                tags: parse_diagnostic_tags(d),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Range of a sidecar diagnostic. The sidecar reports 1-based lines and
+/// 0-based columns counted in UTF-16 units (JVM string offsets), as LSP
+/// does by default. With the document text, columns that split a surrogate
+/// pair or pass the line end are snapped onto the text, and a missing end
+/// covers the identifier at the start (or the character, off an identifier).
+fn diagnostic_range(diagnostic: &Value, text: Option<&str>) -> Option<Range> {
+    let line = diagnostic.get("line")?.as_u64()?.saturating_sub(1) as u32;
+    let column = diagnostic
+        .get("column")
+        .and_then(|c| c.as_u64())
+        .unwrap_or(0) as u32;
+    let end_line = diagnostic
+        .get("endLine")
+        .and_then(|l| l.as_u64())
+        .map(|l| l.saturating_sub(1) as u32)
+        .unwrap_or(line);
+    let end_column = diagnostic
+        .get("endColumn")
+        .and_then(|c| c.as_u64())
+        .map(|c| c as u32);
+
+    let start = Position::new(line, column);
+    let Some(text) = text else {
+        let end = Position::new(end_line, end_column.unwrap_or(column + 1));
+        return Some(Range::new(start, end));
+    };
+    let start = snap_position(text, start);
+    let end = match end_column {
+        Some(end_column) => snap_position(text, Position::new(end_line, end_column)),
+        None => {
+            let rest = &text[position_offset(text, start)..];
+            let is_identifier = |c: &char| c.is_alphanumeric() || *c == '_';
+            let identifier: u32 = rest
+                .chars()
+                .take_while(is_identifier)
+                .map(|c| c.len_utf16() as u32)
+                .sum();
+            let width = if identifier > 0 {
+                identifier
+            } else {
+                rest.chars()
+                    .next()
+                    .filter(|c| !matches!(c, '\n' | '\r'))
+                    .map_or(0, |c| c.len_utf16() as u32)
+            };
+            Position::new(end_line, start.character + width)
+        }
+    };
+    Some(Range::new(start, end))
+}
+
+/// Parses sidecar diagnostic `tags` (`unnecessary`, `deprecated`).
+fn parse_diagnostic_tags(diagnostic: &Value) -> Option<Vec<DiagnosticTag>> {
+    let tags: Vec<DiagnosticTag> = diagnostic
+        .get("tags")?
+        .as_array()?
+        .iter()
+        .filter_map(|tag| match tag.as_str()? {
+            "unnecessary" => Some(DiagnosticTag::UNNECESSARY),
+            "deprecated" => Some(DiagnosticTag::DEPRECATED),
+            _ => None,
+        })
+        .collect();
+    (!tags.is_empty()).then_some(tags)
+}
+
+/// Tracks how each toggleable feature reached the client.
+#[derive(Debug, Default)]
+struct FeatureRegistrations {
+    /// Whether semantic capabilities were advertised (a sidecar runtime exists).
+    semantic_features: bool,
+    /// Features whose client capabilities allow dynamic registration.
+    dynamic: HashSet<Feature>,
+    /// Features included in the `initialize` result.
+    advertised: HashSet<Feature>,
+    /// Features registered later through `client/registerCapability`.
+    registered: HashSet<Feature>,
+    /// Features the client declared no capability for; never offered.
+    unsupported: HashSet<Feature>,
+    /// Features the sidecar reported it cannot serve; withdrawn once it
+    /// started.
+    sidecar_unsupported: HashSet<Feature>,
+}
+
+/// LSP request method for a toggleable feature, also used as its
+/// registration id.
+fn feature_method(feature: Feature) -> &'static str {
+    match feature {
+        Feature::Hover => "textDocument/hover",
+        Feature::Completion => "textDocument/completion",
+        Feature::SignatureHelp => "textDocument/signatureHelp",
+        Feature::CodeActions => "textDocument/codeAction",
+        Feature::CodeLens => "textDocument/codeLens",
+        Feature::InlayHints => "textDocument/inlayHint",
+        Feature::SemanticTokens => "textDocument/semanticTokens",
+        Feature::CallHierarchy => "textDocument/prepareCallHierarchy",
+        Feature::DocumentLinks => "textDocument/documentLink",
+        Feature::FoldingRange => "textDocument/foldingRange",
+    }
+}
+
+/// The sidecar request a feature cannot do without. `None` for features
+/// that still answer lexically: region folds and dependency completion.
+fn feature_sidecar_method(feature: Feature) -> Option<&'static str> {
+    match feature {
+        Feature::Hover => Some("hover"),
+        Feature::SignatureHelp => Some("signatureHelp"),
+        Feature::CodeActions => Some("codeActions"),
+        Feature::CodeLens => Some("codeLens"),
+        Feature::InlayHints => Some("inlayHints"),
+        Feature::SemanticTokens => Some("semanticTokens"),
+        Feature::CallHierarchy => Some("callHierarchy/prepare"),
+        Feature::DocumentLinks => Some("documentLink"),
+        Feature::Completion | Feature::FoldingRange => None,
+    }
+}
+
+/// Key of a feature in `ServerCapabilities` and in the client's
+/// `textDocument` capabilities.
+fn feature_capability_keys(feature: Feature) -> (&'static str, &'static str) {
+    match feature {
+        Feature::Hover => ("hoverProvider", "hover"),
+        Feature::Completion => ("completionProvider", "completion"),
+        Feature::SignatureHelp => ("signatureHelpProvider", "signatureHelp"),
+        Feature::CodeActions => ("codeActionProvider", "codeAction"),
+        Feature::CodeLens => ("codeLensProvider", "codeLens"),
+        Feature::InlayHints => ("inlayHintProvider", "inlayHint"),
+        Feature::SemanticTokens => ("semanticTokensProvider", "semanticTokens"),
+        Feature::CallHierarchy => ("callHierarchyProvider", "callHierarchy"),
+        Feature::DocumentLinks => ("documentLinkProvider", "documentLink"),
+        Feature::FoldingRange => ("foldingRangeProvider", "foldingRange"),
+    }
+}
+
+/// Features the client can register dynamically, read from
+/// `textDocument.<feature>.dynamicRegistration`.
+fn dynamic_registration_features(capabilities: &ClientCapabilities) -> HashSet<Feature> {
+    let text_document = serde_json::to_value(&capabilities.text_document).unwrap_or_default();
+    Feature::ALL
+        .into_iter()
+        .filter(|&feature| {
+            let (_, client_key) = feature_capability_keys(feature);
+            text_document
+                .get(client_key)
+                .and_then(|c| c.get("dynamicRegistration"))
+                .and_then(|d| d.as_bool())
+                .unwrap_or(false)
+        })
+        .collect()
+}
+
+/// What the client declared it can handle, negotiated in `initialize`.
+/// Features it has no capability for are not offered, and content is sent
+/// in a markup kind it can render.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ClientSupport {
+    /// Toggleable features without a `textDocument.<feature>` capability.
+    unsupported: HashSet<Feature>,
+    /// Whether hover `contentFormat` prefers markdown.
+    hover_markdown: bool,
+    /// Whether signature help `documentationFormat` prefers markdown.
+    signature_markdown: bool,
+    /// Whether the client answers `workspace/configuration`.
+    workspace_configuration: bool,
+    /// Whether the client keeps the editor settings that tell whether
+    /// another formatter handles Kotlin; see [`conflicts::has_editor_sections`].
+    editor_sections: bool,
+    /// Whether document symbols can be sent as a nested `DocumentSymbol`
+    /// tree rather than a flat `SymbolInformation` list.
+    hierarchical_document_symbols: bool,
+    /// Whether code actions may be sent `disabled` (shown grayed out with a
+    /// reason); otherwise disabled actions are left out.
+    code_action_disabled: bool,
+    /// Whether workspace symbols may be listed by URI alone, leaving the
+    /// range to `workspaceSymbol/resolve`.
+    workspace_symbol_resolve: bool,
+}
+
+impl Default for ClientSupport {
+    /// Features are assumed supported until `initialize` says otherwise;
+    /// configuration is only pulled from clients that declare it.
+    fn default() -> Self {
+        Self {
+            unsupported: HashSet::new(),
+            hover_markdown: true,
+            signature_markdown: true,
+            workspace_configuration: false,
+            editor_sections: false,
+            hierarchical_document_symbols: false,
+            code_action_disabled: false,
+            workspace_symbol_resolve: false,
+        }
+    }
+}
+
+impl ClientSupport {
+    fn from_capabilities(capabilities: &ClientCapabilities) -> Self {
+        let text_document = serde_json::to_value(&capabilities.text_document).unwrap_or_default();
+        let unsupported = Feature::ALL
+            .into_iter()
+            .filter(|&feature| {
+                let (_, client_key) = feature_capability_keys(feature);
+                text_document.get(client_key).is_none_or(Value::is_null)
+            })
+            .collect();
+        let text_document = capabilities.text_document.as_ref();
+        // The first format is the client's preference; without one the
+        // client gets markdown, as before.
+        let prefers_markdown = |formats: Option<&Vec<MarkupKind>>| {
+            formats
+                .and_then(|formats| formats.first())
+                .is_none_or(|kind| *kind == MarkupKind::Markdown)
+        };
+        Self {
+            unsupported,
+            hover_markdown: prefers_markdown(
+                text_document
+                    .and_then(|t| t.hover.as_ref())
+                    .and_then(|h| h.content_format.as_ref()),
+            ),
+            signature_markdown: prefers_markdown(
+                text_document
+                    .and_then(|t| t.signature_help.as_ref())
+                    .and_then(|s| s.signature_information.as_ref())
+                    .and_then(|i| i.documentation_format.as_ref()),
+            ),
+            workspace_configuration: capabilities
+                .workspace
+                .as_ref()
+                .and_then(|w| w.configuration)
+                .unwrap_or(false),
+            editor_sections: false,
+            hierarchical_document_symbols: text_document
+                .and_then(|t| t.document_symbol.as_ref())
+                .and_then(|d| d.hierarchical_document_symbol_support)
+                .unwrap_or(false),
+            code_action_disabled: text_document
+                .and_then(|t| t.code_action.as_ref())
+                .and_then(|c| c.disabled_support)
+                .unwrap_or(false),
+            workspace_symbol_resolve: capabilities
+                .workspace
+                .as_ref()
+                .and_then(|w| w.symbol.as_ref())
+                .and_then(|s| s.resolve_support.as_ref())
+                .is_some_and(|r| r.properties.iter().any(|p| p == "location.range")),
+        }
+    }
+}
+
+/// Features present in a set of server capabilities.
+fn advertised_features(capabilities: &ServerCapabilities) -> HashSet<Feature> {
+    let capabilities = serde_json::to_value(capabilities).unwrap_or_default();
+    Feature::ALL
+        .into_iter()
+        .filter(|&feature| {
+            capabilities
+                .get(feature_capability_keys(feature).0)
+                .is_some()
+        })
+        .collect()
+}
+
+/// Registration changes needed to apply a `features` setting.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct FeatureRegistrationChanges {
+    register: Vec<Feature>,
+    unregister: Vec<Feature>,
+    /// Toggled features the client cannot (un)register dynamically.
+    static_only: Vec<Feature>,
+}
+
+/// Computes which features to register and unregister for a new `features`
+/// setting. Only features the server can provide at all are registered.
+fn feature_registration_changes(
+    registrations: &FeatureRegistrations,
+    features: &Features,
+) -> FeatureRegistrationChanges {
+    let available = advertised_features(&server_capabilities(
+        registrations.semantic_features,
+        &Features::default(),
+    ));
+    let mut changes = FeatureRegistrationChanges::default();
+    for feature in Feature::ALL {
+        let advertised = registrations.advertised.contains(&feature);
+        let registered = registrations.registered.contains(&feature);
+        let dynamic = registrations.dynamic.contains(&feature);
+        if features.is_enabled(feature) && !registrations.sidecar_unsupported.contains(&feature) {
+            if advertised
+                || registered
+                || !available.contains(&feature)
+                || registrations.unsupported.contains(&feature)
+            {
+                continue;
+            }
+            if dynamic {
+                changes.register.push(feature);
+            } else {
+                changes.static_only.push(feature);
+            }
+        } else if registered {
+            changes.unregister.push(feature);
+        } else if advertised {
+            changes.static_only.push(feature);
+        }
+    }
+    changes
+}
+
+/// Builds a `client/registerCapability` entry from the feature's static
+/// options, applied to the client's default document selector.
+fn feature_registration(feature: Feature, capabilities: &Value) -> Registration {
+    let mut options = match capabilities.get(feature_capability_keys(feature).0) {
+        Some(Value::Object(options)) => options.clone(),
+        _ => serde_json::Map::new(),
+    };
+    options.insert("documentSelector".into(), Value::Null);
+    Registration {
+        id: feature_method(feature).to_string(),
+        method: feature_method(feature).to_string(),
+        register_options: Some(Value::Object(options)),
+    }
+}
+
+fn feature_unregistration(feature: Feature) -> Unregistration {
+    Unregistration {
+        id: feature_method(feature).to_string(),
+        method: feature_method(feature).to_string(),
+    }
+}
+
+/// Describes the client's signature help state for the sidecar: how the
+/// request was triggered and, when the popup is already open, the label of
+/// the overload it shows.
+fn signature_help_context(context: Option<&SignatureHelpContext>) -> Value {
+    let Some(context) = context else {
+        return Value::Null;
+    };
+    let trigger_kind = if context.trigger_kind == SignatureHelpTriggerKind::TRIGGER_CHARACTER {
+        "triggerCharacter"
+    } else if context.trigger_kind == SignatureHelpTriggerKind::CONTENT_CHANGE {
+        "contentChange"
+    } else {
+        "invoked"
+    };
+    let active = context.active_signature_help.as_ref();
+    serde_json::json!({
+        "triggerKind": trigger_kind,
+        "triggerCharacter": context.trigger_character,
+        "isRetrigger": context.is_retrigger,
+        "activeSignature": active.and_then(shown_signature_label),
+        "activeParameter": active.and_then(|help| help.active_parameter),
+    })
+}
+
+/// Label of the overload a signature help popup is showing.
+fn shown_signature_label(help: &SignatureHelp) -> Option<&str> {
+    let index = help.active_signature.unwrap_or(0) as usize;
+    help.signatures.get(index).map(|s| s.label.as_str())
+}
+
+/// On a retrigger (e.g. typing `,`), keeps the overload the popup already
+/// shows selected if it is still among the results, so the popup doesn't
+/// jump between overloads while arguments are typed.
+fn retriggered_active_signature(
+    signatures: &[SignatureInformation],
+    context: Option<&SignatureHelpContext>,
+) -> Option<u32> {
+    let context = context.filter(|c| c.is_retrigger)?;
+    let shown = shown_signature_label(context.active_signature_help.as_ref()?)?;
+    signatures
+        .iter()
+        .position(|s| s.label == shown)
+        .map(|index| index as u32)
+}
+
+/// Capabilities advertised in `initialize`. Without a sidecar runtime only
+/// the features that work without the JVM are advertised, so the client does
+/// not offer hover, completion, etc. that would silently return nothing.
+/// Features switched off in `features` are left out.
+fn server_capabilities(semantic_features: bool, features: &Features) -> ServerCapabilities {
+    let mut capabilities = all_server_capabilities(semantic_features);
+    for feature in Feature::ALL {
+        if !features.is_enabled(feature) {
+            remove_feature(&mut capabilities, feature);
+        }
+    }
+    capabilities
+}
+
+fn remove_feature(capabilities: &mut ServerCapabilities, feature: Feature) {
+    match feature {
+        Feature::Hover => capabilities.hover_provider = None,
+        Feature::Completion => capabilities.completion_provider = None,
+        Feature::SignatureHelp => capabilities.signature_help_provider = None,
+        Feature::CodeActions => capabilities.code_action_provider = None,
+        Feature::CodeLens => capabilities.code_lens_provider = None,
+        Feature::InlayHints => capabilities.inlay_hint_provider = None,
+        Feature::SemanticTokens => capabilities.semantic_tokens_provider = None,
+        Feature::CallHierarchy => capabilities.call_hierarchy_provider = None,
+        Feature::DocumentLinks => capabilities.document_link_provider = None,
+        Feature::FoldingRange => capabilities.folding_range_provider = None,
+    }
+}
+
+/// Placeholder published while a document's first analysis is running.
+fn analysis_in_progress_diagnostic() -> Diagnostic {
+    Diagnostic {
+        range: Range::new(Position::new(0, 0), Position::new(0, 0)),
+        severity: Some(DiagnosticSeverity::INFORMATION),
+        source: Some("kotlin-analyzer".into()),
+        message: "Kotlin analysis in progress…".into(),
+        ..Default::default()
+    }
+}
+
+fn all_server_capabilities(semantic_features: bool) -> ServerCapabilities {
+    let text_document_sync = Some(TextDocumentSyncCapability::Options(
+        TextDocumentSyncOptions {
+            open_close: Some(true),
+            change: Some(TextDocumentSyncKind::INCREMENTAL),
+            save: Some(TextDocumentSyncSaveOptions::SaveOptions(SaveOptions {
+                include_text: Some(false),
+            })),
+            will_save_wait_until: Some(true),
+            ..Default::default()
+        },
+    ));
+    let execute_command_provider = Some(ExecuteCommandOptions {
+        commands: supported_analyzer_command_ids(),
+        work_done_progress_options: WorkDoneProgressOptions {
+            work_done_progress: Some(false),
+        },
+    });
+
+    if !semantic_features {
+        return ServerCapabilities {
+            text_document_sync,
+            document_formatting_provider: Some(OneOf::Left(true)),
+            folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+            execute_command_provider,
+            ..Default::default()
+        };
+    }
+
+    ServerCapabilities {
+        text_document_sync,
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![
+                ".".into(),
+                ":".into(),
+                "@".into(),
+                "$".into(),
+                "[".into(),
+            ]),
+            resolve_provider: Some(false),
+            ..Default::default()
+        }),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        signature_help_provider: Some(SignatureHelpOptions {
+            trigger_characters: Some(vec!["(".into(), ",".into()]),
+            ..Default::default()
+        }),
+        definition_provider: Some(OneOf::Left(true)),
+        references_provider: Some(OneOf::Right(ReferencesOptions {
+            work_done_progress_options: WorkDoneProgressOptions {
+                work_done_progress: Some(true),
+            },
+        })),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        rename_provider: Some(OneOf::Left(true)),
+        code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+            code_action_kinds: Some(vec![
+                CodeActionKind::QUICKFIX,
+                CodeActionKind::REFACTOR,
+                CodeActionKind::REFACTOR_EXTRACT,
+                CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+            ]),
+            ..Default::default()
+        })),
+        code_lens_provider: Some(CodeLensOptions {
+            resolve_provider: Some(false),
+        }),
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+        document_link_provider: Some(DocumentLinkOptions {
+            resolve_provider: Some(true),
+            work_done_progress_options: WorkDoneProgressOptions {
+                work_done_progress: Some(false),
+            },
+        }),
+        inlay_hint_provider: Some(OneOf::Right(InlayHintServerCapabilities::Options(
+            InlayHintOptions {
+                work_done_progress_options: WorkDoneProgressOptions {
+                    work_done_progress: Some(false),
+                },
+                resolve_provider: Some(false),
+            },
+        ))),
+        workspace: Some(WorkspaceServerCapabilities {
+            workspace_folders: None,
+            file_operations: Some(WorkspaceFileOperationsServerCapabilities {
+                did_create: Some(FileOperationRegistrationOptions {
+                    filters: vec![FileOperationFilter {
+                        scheme: Some("file".to_string()),
+                        pattern: FileOperationPattern {
+                            glob: "**/*.kt".to_string(),
+                            matches: Some(FileOperationPatternKind::File),
+                            options: None,
+                        },
+                    }],
+                }),
+                did_delete: Some(FileOperationRegistrationOptions {
+                    filters: vec![
+                        FileOperationFilter {
+                            scheme: Some("file".to_string()),
+                            pattern: FileOperationPattern {
+                                glob: "**/*.kt".to_string(),
+                                matches: Some(FileOperationPatternKind::File),
+                                options: None,
+                            },
+                        },
+                        FileOperationFilter {
+                            scheme: Some("file".to_string()),
+                            pattern: FileOperationPattern {
+                                glob: "**".to_string(),
+                                matches: Some(FileOperationPatternKind::Folder),
+                                options: None,
+                            },
+                        },
+                    ],
+                }),
+                ..Default::default()
+            }),
+        }),
+        execute_command_provider,
+        workspace_symbol_provider: Some(OneOf::Right(WorkspaceSymbolOptions {
+            work_done_progress_options: WorkDoneProgressOptions::default(),
+            resolve_provider: Some(true),
+        })),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(
+            SemanticTokensOptions {
+                legend: SemanticTokensLegend {
+                    token_types: vec![
+                        SemanticTokenType::FUNCTION,
+                        SemanticTokenType::PARAMETER,
+                        SemanticTokenType::VARIABLE,
+                        SemanticTokenType::PROPERTY,
+                        SemanticTokenType::CLASS,
+                        SemanticTokenType::TYPE,
+                        SemanticTokenType::STRING,
+                        SemanticTokenType::COMMENT,
+                        SemanticTokenType::KEYWORD,
+                        SemanticTokenType::DECORATOR,
+                        SemanticTokenType::NUMBER,
+                        SemanticTokenType::ENUM_MEMBER,
+                        SemanticTokenType::TYPE_PARAMETER,
+                    ],
+                    token_modifiers: vec![],
+                },
+                full: Some(SemanticTokensFullOptions::Bool(true)),
+                range: None,
+                work_done_progress_options: WorkDoneProgressOptions {
+                    work_done_progress: Some(false),
+                },
+            },
+        )),
+        call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+        ..Default::default()
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for KotlinLanguageServer {
+    async fn initialize(&self, params: InitializeParams) -> LspResult<InitializeResult> {
+        tracing::info!("kotlin-analyzer: initializing");
+
+        // Store project root (project model resolution happens in initialized())
+        // Walk up from the workspace root to find the actual project root
+        // containing build system markers. Zed sometimes sets rootUri to a deep
+        // source directory (e.g. when opening a single file), so we need to find
+        // the real project root that has build.gradle.kts, pom.xml, etc.
+        match workspace_root(&params) {
+            Some((path, source)) => {
+                let resolved =
+                    project::prefer_nested_build_root(&project::find_project_root(&path));
+                tracing::info!(
+                    "project root {} (from {} {})",
+                    resolved.display(),
+                    source,
+                    path.display()
+                );
+                let mut project_root = self.project_root.lock().await;
+                *project_root = Some(resolved);
+            }
+            None => {
+                tracing::info!(
+                    "initialize supplied no file-based root; deriving the project root from the first opened document"
+                );
+            }
+        }
+
+        match &params.client_info {
+            Some(info) => tracing::info!(
+                "client: {} {}",
+                info.name,
+                info.version.as_deref().unwrap_or("(unknown version)")
+            ),
+            None => tracing::info!("client: (no clientInfo)"),
+        }
+        // Logged in full so duplicate registrations (e.g. two servers both
+        // formatting Kotlin) can be traced back to what the client supports.
+        tracing::info!(
+            "client capabilities: {}",
+            serde_json::to_string(&params.capabilities).unwrap_or_default()
+        );
+
+        *self.workspace_folders.lock().await = params
+            .workspace_folders
+            .iter()
+            .flatten()
+            .filter_map(|folder| folder.uri.to_file_path().ok())
+            .collect();
+
+        // Parse initialization options as config
+        if let Some(options) = params.initialization_options {
+            *self.initialization_settings.lock().await = options.clone();
+            if let Ok(config) = serde_json::from_value::<Config>(options) {
+                *self.generated_classes.lock().await =
+                    GeneratedClassMatcher::new(&config.generated_class_patterns);
+                let mut c = self.config.lock().await;
+                *c = config;
+            }
+        }
+
+        // Start the debounce loop
+        let tx = self.start_debounce_loop();
+        {
+            let mut debounce = self.debounce_tx.lock().await;
+            *debounce = Some(tx);
+        }
+
+        // File watchers for build files, .editorconfig and the manual project
+        // config, plus deleted sources so the lexical index drops them
+        let file_watchers = vec![
+            FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*.kt".into()),
+                kind: Some(WatchKind::Delete),
+            },
+            FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*.gradle.kts".into()),
+                kind: None,
+            },
+            FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*.gradle".into()),
+                kind: None,
+            },
+            FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/gradle.properties".into()),
+                kind: None,
+            },
+            FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/.editorconfig".into()),
+                kind: None,
+            },
+            FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/.kotlin-analyzer.json".into()),
+                kind: None,
+            },
+        ];
+
+        let configured_jar = self.config.lock().await.sidecar_jar_path.clone();
+        let semantic_features = match runtime::probe_sidecar_runtime(configured_jar.as_deref()) {
+            Ok(()) => true,
+            Err(message) => {
+                tracing::warn!("{}; advertising lexical capabilities only", message);
+                *self.missing_sidecar_runtime.lock().await = Some(message);
+                false
+            }
+        };
+
+        // Features the client can register dynamically are registered in
+        // `initialized` instead, so they can be withdrawn when disabled.
+        let features = self.config.lock().await.features;
+        let dynamic = dynamic_registration_features(&params.capabilities);
+        let mut client_support = ClientSupport::from_capabilities(&params.capabilities);
+        client_support.editor_sections = params
+            .client_info
+            .as_ref()
+            .is_some_and(|info| conflicts::has_editor_sections(&info.name));
+        let mut capabilities = server_capabilities(semantic_features, &features);
+        for &feature in dynamic.iter().chain(&client_support.unsupported) {
+            remove_feature(&mut capabilities, feature);
+        }
+        if !client_support.unsupported.is_empty() {
+            let mut skipped: Vec<_> = client_support
+                .unsupported
+                .iter()
+                .map(|&feature| feature_method(feature))
+                .collect();
+            skipped.sort();
+            tracing::info!("not advertising features the client does not declare: {skipped:?}");
+        }
+
+        *self.feature_registrations.lock().await = FeatureRegistrations {
+            semantic_features,
+            dynamic,
+            advertised: advertised_features(&capabilities),
+            registered: HashSet::new(),
+            unsupported: client_support.unsupported.clone(),
+            sidecar_unsupported: HashSet::new(),
+        };
+        *self.client_support.lock().await = client_support;
+
+        let diagnostics_mode = self.config.lock().await.diagnostics_mode;
+        let delivery = DiagnosticsDelivery::new(diagnostics_mode, &params.capabilities);
+        if delivery.pull {
+            capabilities.diagnostic_provider =
+                Some(DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                    identifier: Some("kotlin-analyzer".into()),
+                    inter_file_dependencies: true,
+                    workspace_diagnostics: false,
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                }));
+        }
+        tracing::info!("diagnostics delivery: {:?}", delivery);
+        *self.diagnostics_delivery.lock().await = delivery;
+
+        let result = InitializeResult {
+            capabilities,
+            server_info: Some(ServerInfo {
+                name: "kotlin-analyzer".into(),
+                version: Some(env!("CARGO_PKG_VERSION").into()),
+            }),
+        };
+
+        // Register file watchers dynamically since they need a registered client
+        let client = self.client.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            let registration = Registration {
+                id: "watch-files".to_string(),
+                method: "workspace/didChangeWatchedFiles".to_string(),
+                register_options: Some(
+                    serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
+                        watchers: file_watchers,
+                    })
+                    .unwrap(),
+                ),
+            };
+
+            match tokio::time::timeout(
+                Duration::from_secs(5),
+                client.register_capability(vec![registration]),
+            )
+            .await
+            {
+                Ok(Err(e)) => tracing::warn!("failed to register file watchers: {:?}", e),
+                Err(_) => tracing::warn!("file watcher registration timed out"),
+                Ok(Ok(())) => {}
+            }
+        });
+
+        Ok(result)
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        tracing::info!("kotlin-analyzer: initialized");
+
+        self.sync_feature_registrations(self.config.lock().await.features);
+
+        // Spawn sidecar startup in a background task. tower-lsp processes
+        // notifications sequentially, so calling send_request (which awaits
+        // the client's response) from within a notification handler deadlocks.
+        let client = self.client.clone();
+        let bridge_holder = Arc::clone(&self.bridge);
+        let documents_holder = Arc::clone(&self.documents);
+        let config_holder = Arc::clone(&self.config);
+        let mut config = self.config.lock().await.clone();
+        let project_root = self.project_root.lock().await.clone();
+        let server = self.clone();
+
+        self.refresh_formatter(config.clone());
+
+        tokio::spawn({
+            let server = self.clone();
+            async move { server.detect_other_formatter().await }
+        });
+
+        if let Some(message) = self.missing_sidecar_runtime.lock().await.clone() {
+            self.client
+                .show_message(
+                    MessageType::WARNING,
+                    format!("{message}\nOnly formatting is available."),
+                )
+                .await;
+            tokio::spawn(async move {
+                server.pull_configuration().await;
+            });
+            return;
+        }
+
+        tracing::debug!("about to spawn background task for sidecar startup");
+        tokio::spawn(async move {
+            tracing::debug!("initialized: background task started");
+
+            // Settings the client only hands out on request take effect
+            // before the project is resolved and the sidecar configured.
+            if let Some(pulled) = server.pull_configuration().await {
+                config = pulled;
+            }
+            let excludes = server.exclude_matcher().await;
+            let read_only =
+                ReadOnlyRoots::new(&config.read_only_source_roots, project_root.as_deref());
+            let analysis_scope = config.analysis_scope;
+
+            // Create progress token
+            let token = NumberOrString::String("kotlin-analyzer-startup".to_string());
+
+            // Use a timeout: if the client doesn't support workDoneProgress
+            // or doesn't respond, we still proceed with sidecar startup.
+            match tokio::time::timeout(
+                Duration::from_secs(5),
+                client.send_request::<lsp_types::request::WorkDoneProgressCreate>(
+                    WorkDoneProgressCreateParams {
+                        token: token.clone(),
+                    },
+                ),
+            )
+            .await
+            {
+                Ok(Err(e)) => tracing::warn!("failed to create progress token: {:?}", e),
+                Err(_) => tracing::warn!(
+                    "progress token creation timed out, client may not support workDoneProgress"
+                ),
+                Ok(Ok(())) => {}
+            }
+
+            client
+                .send_notification::<lsp_types::notification::Progress>(ProgressParams {
+                    token: token.clone(),
+                    value: ProgressParamsValue::WorkDone(WorkDoneProgress::Begin(
+                        WorkDoneProgressBegin {
+                            title: "Starting Kotlin sidecar".to_string(),
+                            message: Some("Resolving project...".to_string()),
+                            percentage: None,
+                            cancellable: Some(false),
+                        },
+                    )),
+                })
+                .await;
+
+            // With fastStart the sidecar comes up stdlib-only right away and
+            // the project is resolved once open files have diagnostics.
+            let fast_start = config.fast_start && project_root.is_some();
+
+            // Workspace symbols are served lexically while the project
+            // resolves and the sidecar starts.
+            if let Some(root) = project_root.clone() {
+                let server = server.clone();
+                tokio::spawn(async move { server.build_lexical_symbol_index(vec![root]).await });
+            }
+
+            // Resolve project model first so we can pass it to the sidecar
+            let project_model = if fast_start {
+                tracing::info!("fastStart: starting the sidecar before resolving the project");
+                None
+            } else if let Some(root) = project_root.clone() {
+                let resolver = server.clone();
+                let resolver_config = config.clone();
+                tokio::spawn(async move {
+                    resolver
+                        .resolve_startup_project(root, resolver_config)
+                        .await
+                });
+                match server.await_project_model(PROJECT_RESOLVE_TIMEOUT).await {
+                    Ok(model) => model,
+                    Err(_) => {
+                        tracing::warn!(
+                            "project still resolving after {:?}, starting the sidecar stdlib-only",
+                            PROJECT_RESOLVE_TIMEOUT
+                        );
+                        let server = server.clone();
+                        tokio::spawn(async move { server.reanalyze_once_resolved().await });
+                        None
+                    }
+                }
+            } else {
+                tracing::debug!("no project root, using stdlib-only analysis");
+                None
+            };
+
+            client
+                .send_notification::<lsp_types::notification::Progress>(ProgressParams {
+                    token: token.clone(),
+                    value: ProgressParamsValue::WorkDone(WorkDoneProgress::Report(
+                        WorkDoneProgressReport {
+                            message: Some("Starting JVM sidecar...".to_string()),
+                            percentage: None,
+                            cancellable: Some(false),
+                        },
+                    )),
+                })
+                .await;
+
+            // Try to start the sidecar
+            let java_path = match crate::bridge::find_java(config.java_home.as_deref()) {
+                Ok(p) => p,
+                Err(e) => {
+                    tracing::error!("JVM not found: {}", e);
+                    client
+                        .send_notification::<lsp_types::notification::Progress>(ProgressParams {
+                            token: token.clone(),
+                            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(
+                                WorkDoneProgressEnd {
+                                    message: Some(format!("Failed: {}", e)),
+                                },
+                            )),
+                        })
+                        .await;
+                    client
+                        .show_message(
+                            MessageType::ERROR,
+                            "kotlin-analyzer: JDK 17+ required but not found. Set javaHome, JAVA_HOME, or KOTLIN_LS_JAVA_HOME.",
+                        )
+                        .await;
+                    return;
+                }
+            };
+
+            tracing::debug!("java found at {:?}", java_path);
+
+            let requested_kotlin_version = project_model
+                .as_ref()
+                .and_then(|model| model.kotlin_version.clone());
+
+            let sidecar_runtime = runtime::resolve_sidecar_runtime(
+                requested_kotlin_version.as_deref(),
+                config.sidecar_jar_path.as_deref(),
+            );
+            let sidecar_runtime = match sidecar_runtime {
+                Ok(runtime) => runtime,
+                Err(message) => {
+                    tracing::warn!("{}", message);
+                    client
+                        .send_notification::<lsp_types::notification::Progress>(ProgressParams {
+                            token: token.clone(),
+                            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(
+                                WorkDoneProgressEnd {
+                                    message: Some("sidecar runtime not found".to_string()),
+                                },
+                            )),
+                        })
+                        .await;
+                    client
+                        .show_message(
+                            MessageType::WARNING,
+                            format!("{message}\nSemantic features are unavailable."),
+                        )
+                        .await;
+                    return;
+                }
+            };
+
+            tracing::info!(
+                requested = requested_kotlin_version.as_deref().unwrap_or("unknown"),
+                selected = sidecar_runtime.kotlin_version.as_deref().unwrap_or("unknown"),
+                selection_counter = sidecar_runtime.selection_reason.counter_name(),
+                reason = sidecar_runtime.selection_reason.description(),
+                classpath = ?sidecar_runtime.classpath,
+                main_class = sidecar_runtime.main_class.as_deref().unwrap_or("<jar>"),
+                "selected sidecar runtime"
+            );
+
+            if let Some(message) = sidecar_runtime.selection_warning_message() {
+                client.show_message(MessageType::WARNING, message).await;
+            }
+
+            let selection_warned = sidecar_runtime.selection_warning_message().is_some();
+            let sidecar_version = sidecar_runtime.kotlin_version.clone();
+            let bridge = Arc::new(Bridge::new(sidecar_runtime, java_path, config));
+
+            // Store the bridge BEFORE starting so LSP requests that arrive
+            // during sidecar startup can reach it and wait for Ready state
+            // (request buffering in bridge.rs handles the wait).
+            {
+                let mut b = bridge_holder.lock().await;
+                *b = Some(bridge);
+            }
+            // Lock is released here so hover/completion handlers can access
+            // the bridge while start() is running. Their requests will wait
+            // for Ready via the watch channel in bridge.rs.
+
+            // The model lock is held until the sidecar has started, so a
+            // root derived from an opened document meanwhile either lands in
+            // this start or reconfigures the running sidecar afterwards.
+            let model_guard = server.project_model.lock().await;
+            let project_model = model_guard.clone().or(project_model);
+
+            // Prepare project config for the sidecar
+            let project_root_str = server
+                .project_root
+                .lock()
+                .await
+                .as_ref()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+
+            let SidecarProjectArgs {
+                classpath,
+                compiler_flags,
+                source_roots,
+                generated_source_roots,
+                classpath_sources,
+                kapt_classpath,
+            } = SidecarProjectArgs::from_model(project_model.as_ref(), sidecar_version.as_deref());
+
+            // Note: when no source roots are found (no build system), the sidecar
+            // falls back to creating ad-hoc KtFile objects from opened files via
+            // KtPsiFactory. This is faster than scanning the entire project root
+            // and works well for basic features (hover, completion, diagnostics).
+            if source_roots.is_empty() {
+                tracing::debug!("no source roots found, sidecar will use per-file fallback");
+            }
+
+            tracing::info!(
+                "starting sidecar with project_root={}, classpath={} entries, source_roots={:?}",
+                project_root_str,
+                classpath.len(),
+                source_roots
+            );
+
+            // Re-acquire lock briefly to call start() on the bridge
+            let start_result = {
+                let b = bridge_holder.lock().await;
+                let bridge = b.as_ref().unwrap();
+                bridge
+                    .start_with_stdlib_fallback(
+                        Some(project_root_str.as_str()),
+                        &classpath,
+                        &compiler_flags,
+                        &source_roots,
+                        &generated_source_roots,
+                        &classpath_sources,
+                        &kapt_classpath,
+                    )
+                    .await
+            };
+            drop(model_guard);
+
+            match start_result {
+                Ok(stdlib_fallback) => {
+                    tracing::info!("sidecar started successfully");
+                    tokio::spawn({
+                        let server = server.clone();
+                        async move { server.suspend_idle_sidecar().await }
+                    });
+                    if stdlib_fallback {
+                        client
+                            .show_message(
+                                MessageType::WARNING,
+                                "kotlin-analyzer: the sidecar could not load the project classpath (a broken jar or corrupt build cache?), so analysis runs stdlib-only and references to dependencies show as unresolved. Fix the build and restart the server.",
+                            )
+                            .await;
+                    }
+                    // A fallback runtime selection has already warned about
+                    // the version; only report mismatches that slipped past it.
+                    if selection_warned {
+                        server
+                            .version_mismatch_warned
+                            .store(true, Ordering::Relaxed);
+                    }
+                    server
+                        .warn_kotlin_version_mismatch(requested_kotlin_version.as_deref())
+                        .await;
+                    server.withdraw_sidecar_unsupported_features().await;
+                    client
+                        .send_notification::<lsp_types::notification::Progress>(ProgressParams {
+                            token: token.clone(),
+                            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(
+                                WorkDoneProgressEnd {
+                                    message: Some("Ready".to_string()),
+                                },
+                            )),
+                        })
+                        .await;
+
+                    // Replay all open documents: send didOpen + analyze for each
+                    // file that was opened before the sidecar was ready.
+                    let open_docs: Vec<(Url, String, i32, DocumentKind)> = {
+                        let docs = documents_holder.lock().await;
+                        docs.all()
+                            .filter(|(uri, _)| !docs.is_lexical_only(uri))
+                            .map(|(uri, doc)| {
+                                (uri.clone(), doc.text.clone(), doc.version, doc.kind)
+                            })
+                            .collect()
+                    };
+
+                    if !open_docs.is_empty() {
+                        tracing::info!(
+                            "replaying {} open document(s) after sidecar startup",
+                            open_docs.len()
+                        );
+                    }
+
+                    // Clone the Arc<Bridge> out of the mutex so the lock is released
+                    // before the replay loop. This prevents blocking hover/completion
+                    // handlers during the potentially long replay.
+                    let bridge_arc = {
+                        let guard = bridge_holder.lock().await;
+                        guard.as_ref().map(Arc::clone)
+                    };
+                    if let Some(bridge) = bridge_arc {
+                        for (uri, text, version, kind) in &open_docs {
+                            tracing::debug!("replay: sending didOpen for {}", uri);
+                            let sync_params =
+                                server.document_sync_params(uri, *version, text).await;
+                            let _ = bridge
+                                .notify(kind.did_open_method(), Some(sync_params.clone()))
+                                .await;
+
+                            // Send didChange + analyze
+                            let _ = bridge
+                                .notify(kind.did_change_method(), Some(sync_params))
+                                .await;
+
+                            if !kind.supports_kotlin_analysis() || is_excluded_uri(&excludes, uri) {
+                                continue;
+                            }
+
+                            match bridge
+                                .request(
+                                    "analyze",
+                                    Some(serde_json::json!({ "uri": uri.as_str() })),
+                                )
+                                .await
+                            {
+                                Ok(result) => {
+                                    let overrides = config_holder
+                                        .lock()
+                                        .await
+                                        .diagnostic_severity_overrides
+                                        .clone();
+                                    let generated = server.generated_classes.lock().await.clone();
+                                    let diagnostics = demote_read_only(
+                                        &read_only,
+                                        uri,
+                                        apply_severity_overrides(
+                                            demote_generated_references(
+                                                parse_diagnostics(&result, Some(text)),
+                                                &generated,
+                                            ),
+                                            &overrides,
+                                        ),
+                                    );
+                                    tracing::info!(
+                                        "replay: {} returned {} diagnostics",
+                                        uri,
+                                        diagnostics.len()
+                                    );
+                                    deliver_diagnostics(
+                                        &client,
+                                        &server.diagnostics_delivery,
+                                        uri.clone(),
+                                        diagnostics,
+                                        None,
+                                    )
+                                    .await;
+                                }
+                                Err(e) => {
+                                    tracing::warn!("replay: analysis failed for {}: {}", uri, e);
+                                }
+                            }
+                        }
+                    }
+
+                    // Project-wide analysis needs the full classpath.
+                    if fast_start {
+                        server.finish_fast_start().await;
+                    }
+
+                    // --- Project-wide background analysis ---
+                    if analysis_scope == AnalysisScope::OpenFiles {
+                        tracing::info!(
+                            "analysisScope is openFiles, skipping project-wide analysis"
+                        );
+                        return;
+                    }
+                    let bg_bridge = Arc::clone(&bridge_holder);
+                    let bg_documents = Arc::clone(&documents_holder);
+                    let bg_config = Arc::clone(&config_holder);
+                    let bg_generated = Arc::clone(&server.generated_classes);
+                    let bg_client = client.clone();
+                    let bg_cancellations = Arc::clone(&server.progress_cancellations);
+                    let bg_delivery = Arc::clone(&server.diagnostics_delivery);
+                    tokio::spawn(async move {
+                        // Small delay to let open-file diagnostics settle
+                        tokio::time::sleep(Duration::from_secs(2)).await;
+
+                        // Create progress token for background analysis
+                        let bg_token =
+                            NumberOrString::String("kotlin-analyzer-background".to_string());
+                        let _ = tokio::time::timeout(
+                            Duration::from_secs(5),
+                            bg_client.send_request::<lsp_types::request::WorkDoneProgressCreate>(
+                                WorkDoneProgressCreateParams {
+                                    token: bg_token.clone(),
+                                },
+                            ),
+                        )
+                        .await;
+
+                        bg_client
                             .send_notification::<lsp_types::notification::Progress>(
                                 ProgressParams {
                                     token: bg_token.clone(),
@@ -1351,1870 +5141,5379 @@ impl LanguageServer for KotlinLanguageServer {
                                                 "Running diagnostics on all source files..."
                                                     .to_string(),
                                             ),
-                                            percentage: Some(0),
-                                            cancellable: Some(false),
+                                            percentage: Some(0),
+                                            cancellable: Some(true),
+                                        },
+                                    )),
+                                },
+                            )
+                            .await;
+
+                        // Call analyzeAll with a generous timeout (5 minutes)
+                        let bridge_arc = {
+                            let guard = bg_bridge.lock().await;
+                            guard.as_ref().map(Arc::clone)
+                        };
+                        let analyze_result = match bridge_arc {
+                            Some(b) => {
+                                if b.state().await != SidecarState::Ready {
+                                    tracing::warn!(
+                                        "background analysis: sidecar not ready, skipping"
+                                    );
+                                    None
+                                } else {
+                                    Some(
+                                        cancellable_request(
+                                            &bg_cancellations,
+                                            &bg_token,
+                                            b.request_with_timeout(
+                                                "analyzeAll",
+                                                None,
+                                                Duration::from_secs(300),
+                                            ),
+                                        )
+                                        .await,
+                                    )
+                                }
+                            }
+                            None => None,
+                        };
+
+                        match analyze_result {
+                            Some(Ok(result)) => {
+                                let files = result.get("files").and_then(|f| f.as_array());
+                                let total_files = result
+                                    .get("totalFiles")
+                                    .and_then(|t| t.as_u64())
+                                    .unwrap_or(0);
+                                let total_errors = result
+                                    .get("totalErrors")
+                                    .and_then(|e| e.as_u64())
+                                    .unwrap_or(0);
+                                let total_warnings = result
+                                    .get("totalWarnings")
+                                    .and_then(|w| w.as_u64())
+                                    .unwrap_or(0);
+
+                                if let Some(files) = files {
+                                    let overrides = bg_config
+                                        .lock()
+                                        .await
+                                        .diagnostic_severity_overrides
+                                        .clone();
+                                    let generated = bg_generated.lock().await.clone();
+                                    let mut processed = 0u64;
+                                    let mut _published = 0u64;
+                                    for file_entry in files {
+                                        processed += 1;
+
+                                        let uri_str =
+                                            match file_entry.get("uri").and_then(|u| u.as_str()) {
+                                                Some(u) => u,
+                                                None => continue,
+                                            };
+
+                                        let uri = match Url::parse(uri_str) {
+                                            Ok(u) => u,
+                                            Err(e) => {
+                                                tracing::warn!("background analysis: invalid URI from sidecar: {:?} ({})", uri_str, e);
+                                                continue;
+                                            }
+                                        };
+
+                                        if is_excluded_uri(&excludes, &uri) {
+                                            continue;
+                                        }
+
+                                        // Skip files that are currently open — their diagnostics
+                                        // from the replay loop are fresher
+                                        {
+                                            let docs = bg_documents.lock().await;
+                                            if docs.get(&uri).is_some() {
+                                                continue;
+                                            }
+                                        }
+
+                                        let diagnostics = demote_read_only(
+                                            &read_only,
+                                            &uri,
+                                            apply_severity_overrides(
+                                                demote_generated_references(
+                                                    parse_diagnostics(file_entry, None),
+                                                    &generated,
+                                                ),
+                                                &overrides,
+                                            ),
+                                        );
+
+                                        // Only publish and cache files with actual diagnostics
+                                        if !diagnostics.is_empty() {
+                                            {
+                                                let mut docs = bg_documents.lock().await;
+                                                docs.set_diagnostics(
+                                                    uri.clone(),
+                                                    diagnostics.clone(),
+                                                );
+                                            }
+                                            // Only open files are pulled, so
+                                            // the rest are pushed in any mode.
+                                            let push = bg_delivery.lock().await.push
+                                                || !bg_documents.lock().await.is_open(&uri);
+                                            if push {
+                                                bg_client
+                                                    .publish_diagnostics(uri, diagnostics, None)
+                                                    .await;
+                                            }
+                                            _published += 1;
+                                        }
+
+                                        // Report progress periodically
+                                        if total_files > 0 && processed.is_multiple_of(10) {
+                                            let pct = ((processed as f64 / total_files as f64)
+                                                * 100.0)
+                                                as u32;
+                                            bg_client
+                                                .send_notification::<lsp_types::notification::Progress>(ProgressParams {
+                                                    token: bg_token.clone(),
+                                                    value: ProgressParamsValue::WorkDone(WorkDoneProgress::Report(
+                                                        WorkDoneProgressReport {
+                                                            message: Some(format!("Processed {}/{} files", processed, total_files)),
+                                                            percentage: Some(pct),
+                                                            cancellable: Some(false),
+                                                        },
+                                                    )),
+                                                })
+                                                .await;
+                                        }
+                                    }
+
+                                    let summary = format!(
+                                        "Complete — {} error(s), {} warning(s) in {} file(s)",
+                                        total_errors, total_warnings, total_files
+                                    );
+                                    tracing::info!("background analysis: {}", summary);
+
+                                    bg_client
+                                        .send_notification::<lsp_types::notification::Progress>(
+                                            ProgressParams {
+                                                token: bg_token.clone(),
+                                                value: ProgressParamsValue::WorkDone(
+                                                    WorkDoneProgress::End(WorkDoneProgressEnd {
+                                                        message: Some(summary),
+                                                    }),
+                                                ),
+                                            },
+                                        )
+                                        .await;
+                                }
+                            }
+                            Some(Err(crate::error::Error::Bridge(BridgeError::Cancelled))) => {
+                                tracing::info!("background analysis cancelled by the client");
+                                bg_client
+                                    .send_notification::<lsp_types::notification::Progress>(
+                                        ProgressParams {
+                                            token: bg_token.clone(),
+                                            value: ProgressParamsValue::WorkDone(
+                                                WorkDoneProgress::End(WorkDoneProgressEnd {
+                                                    message: Some("Cancelled".to_string()),
+                                                }),
+                                            ),
                                         },
-                                    )),
+                                    )
+                                    .await;
+                            }
+                            Some(Err(e)) => {
+                                tracing::warn!("background analysis failed: {}", e);
+                                bg_client
+                                    .send_notification::<lsp_types::notification::Progress>(
+                                        ProgressParams {
+                                            token: bg_token.clone(),
+                                            value: ProgressParamsValue::WorkDone(
+                                                WorkDoneProgress::End(WorkDoneProgressEnd {
+                                                    message: Some(format!("Failed: {}", e)),
+                                                }),
+                                            ),
+                                        },
+                                    )
+                                    .await;
+                            }
+                            None => {
+                                bg_client
+                                    .send_notification::<lsp_types::notification::Progress>(
+                                        ProgressParams {
+                                            token: bg_token.clone(),
+                                            value: ProgressParamsValue::WorkDone(
+                                                WorkDoneProgress::End(WorkDoneProgressEnd {
+                                                    message: Some(
+                                                        "Skipped — sidecar not ready".to_string(),
+                                                    ),
+                                                }),
+                                            ),
+                                        },
+                                    )
+                                    .await;
+                            }
+                        }
+                    });
+                }
+                Err(e) => {
+                    tracing::error!("failed to start sidecar: {:?}", e);
+                    // Remove the bridge since startup failed
+                    {
+                        let mut b = bridge_holder.lock().await;
+                        *b = None;
+                    }
+                    client
+                        .send_notification::<lsp_types::notification::Progress>(ProgressParams {
+                            token: token.clone(),
+                            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(
+                                WorkDoneProgressEnd {
+                                    message: Some(format!("Failed: {}", e)),
                                 },
-                            )
-                            .await;
+                            )),
+                        })
+                        .await;
+                    client
+                        .show_message(
+                            MessageType::ERROR,
+                            format!("kotlin-analyzer: failed to start sidecar: {}", e),
+                        )
+                        .await;
+                }
+            }
+        });
+    }
+
+    async fn shutdown(&self) -> LspResult<()> {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        tracing::info!("kotlin-analyzer: shutting down");
+
+        if let Some(bridge) = self.get_bridge().await {
+            if let Err(e) = bridge.shutdown().await {
+                tracing::error!("error shutting down sidecar: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let uri = params.text_document.uri.clone();
+        let text = params.text_document.text.clone();
+        let version = params.text_document.version;
+        let kind = DocumentKind::from_language_id(&params.text_document.language_id, &uri);
+        // A reopened document may restart at a version seen before.
+        self.response_cache.lock().await.clear();
+
+        tracing::debug!(
+            "did_open: {} (version {}, {} bytes)",
+            uri,
+            version,
+            text.len()
+        );
+
+        // Library sources are served from the decompile cache and never sent
+        // to the sidecar as project files.
+        if uri.scheme() == LIBRARY_SCHEME {
+            self.open_library_source(uri, version, kind).await;
+            return;
+        }
+
+        // Re-publish cached diagnostics immediately so they appear instantly on tab switch
+        {
+            let mut documents = self.documents.lock().await;
+            let push = self.diagnostics_delivery.lock().await.push;
+            if let Some(cached) = documents.get_diagnostics(&uri).cloned() {
+                if push && !cached.is_empty() {
+                    tracing::debug!(
+                        "did_open: re-publishing {} cached diagnostics for {}",
+                        cached.len(),
+                        uri
+                    );
+                    self.client
+                        .publish_diagnostics(uri.clone(), cached, None)
+                        .await;
+                } else if !push && !cached.is_empty() {
+                    // Pushed by background analysis while the file was
+                    // closed; pulls report it from now on.
+                    self.client
+                        .publish_diagnostics(uri.clone(), Vec::new(), None)
+                        .await;
+                }
+            }
+            documents.open(uri.clone(), text.clone(), version, kind);
+        }
+        self.update_lexical_symbols(&uri, kind, &text).await;
+
+        if self.update_lexical_only(&uri, text.len()).await {
+            return;
+        }
+
+        if let Some(root) = self.derive_project_root(&uri).await {
+            let server = self.clone();
+            tokio::spawn(async move { server.adopt_derived_project_root(root).await });
+        }
+        self.track_loose_file(&uri).await;
+
+        // Notify sidecar
+        if let Some(bridge) = self.get_bridge().await {
+            let sync_params = self.document_sync_params(&uri, version, &text).await;
+            let _ = bridge
+                .notify(kind.did_open_method(), Some(sync_params))
+                .await;
+        }
+
+        // Trigger fresh analysis (will update the cache when complete)
+        self.analyze_document(&uri).await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        let uri = params.text_document.uri.clone();
+        let version = params.text_document.version;
+        let mut latest_doc = None;
+        self.response_cache.lock().await.clear();
+
+        {
+            let mut documents = self.documents.lock().await;
+            if documents.apply_changes(&uri, params.content_changes, version) {
+                latest_doc = documents.get(&uri).cloned();
+            }
+        }
+
+        // Keep the sidecar's virtual file state in sync immediately so
+        // completion/hover/definition requests see the latest editor buffer
+        // instead of waiting for the debounced diagnostics path.
+        if let Some(doc) = latest_doc {
+            self.update_lexical_symbols(&uri, doc.kind, &doc.text).await;
+            let was_lexical_only = self.is_lexical_only(&uri).await;
+            if self.update_lexical_only(&uri, doc.text.len()).await {
+                return;
+            }
+            // A document that shrank below the limit was never sent to the
+            // sidecar, so it needs a didOpen rather than a didChange.
+            let method = if was_lexical_only {
+                doc.kind.did_open_method()
+            } else {
+                doc.kind.did_change_method()
+            };
+            if let Some(bridge) = self.get_bridge().await {
+                let sync_params = self
+                    .document_sync_params(&uri, doc.version, &doc.text)
+                    .await;
+                let _ = bridge.notify(method, Some(sync_params)).await;
+            }
+        }
+
+        // Send to debounce loop for analysis
+        let debounce = self.debounce_tx.lock().await;
+        if let Some(tx) = debounce.as_ref() {
+            let _ = tx.send(uri).await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri.clone();
+        let kind = {
+            let documents = self.documents.lock().await;
+            documents
+                .get(&uri)
+                .map(|doc| doc.kind)
+                .unwrap_or_else(|| DocumentKind::from_uri(&uri))
+        };
+
+        {
+            let mut documents = self.documents.lock().await;
+            documents.close(&uri);
+        }
+        self.lint_diagnostics.lock().await.remove(&uri);
+        self.loose_files.lock().await.remove(&uri);
+
+        // Notify sidecar
+        if let Some(bridge) = self.get_bridge().await {
+            let _ = bridge
+                .notify(
+                    kind.did_close_method(),
+                    Some(serde_json::json!({
+                        "uri": uri.as_str(),
+                    })),
+                )
+                .await;
+        }
+
+        // Do NOT clear diagnostics on didClose — Zed sends didClose when switching
+        // tabs, and clearing diagnostics here causes them to disappear. Cached
+        // diagnostics will be re-published on the next didOpen.
+        tracing::debug!("did_close: keeping cached diagnostics for {}", uri);
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        let uri = params.text_document.uri;
+        tracing::debug!("did_save: {}", uri);
+
+        let should_analyze = {
+            let documents = self.documents.lock().await;
+            documents
+                .get(&uri)
+                .map(|doc| doc.kind.supports_kotlin_analysis())
+                .unwrap_or_else(|| DocumentKind::from_uri(&uri).supports_kotlin_analysis())
+        };
+
+        if !should_analyze {
+            return;
+        }
+
+        // Trigger fresh analysis on save (bypasses debounce for immediate feedback)
+        self.analyze_document(&uri).await;
+    }
+
+    async fn will_save_wait_until(
+        &self,
+        params: WillSaveTextDocumentParams,
+    ) -> LspResult<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+        let config = self.config_for(&uri).await;
+        if !save_actions_apply(&config, params.reason) || self.is_read_only(&uri).await {
+            return Ok(None);
+        }
+        let Some(original_text) = self
+            .documents
+            .lock()
+            .await
+            .get(&uri)
+            .map(|doc| doc.text.clone())
+        else {
+            return Ok(None);
+        };
+
+        let mut text = original_text.clone();
+        if config.organize_imports_on_save {
+            if let Some(organized) = self.organized_imports(&uri, &text).await {
+                text = organized;
+            }
+        }
+        if config.format_on_save {
+            if let Some(formatted) = self.format_text(&uri, &text).await {
+                text = formatted;
+            }
+        }
+
+        tracing::debug!("will_save_wait_until: {} ({:?})", uri, params.reason);
+        if text == original_text {
+            Ok(None)
+        } else {
+            Ok(Some(vec![whole_document_edit(&original_text, text)]))
+        }
+    }
+
+    async fn completion(&self, params: CompletionParams) -> LspResult<Option<CompletionResponse>> {
+        let uri = params.text_document_position.text_document.uri;
+        if !self.feature_enabled(Feature::Completion).await || self.is_lexical_only(&uri).await {
+            return Ok(None);
+        }
+        let position = params.text_document_position.position;
+        let trigger_character = params
+            .context
+            .as_ref()
+            .and_then(|context| context.trigger_character.clone());
+
+        if let Some(items) = self.dependency_version_completion(&uri, position).await {
+            return Ok(Some(CompletionResponse::Array(items)));
+        }
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match self
+            .document_request(
+                &bridge,
+                &uri,
+                "completion",
+                serde_json::json!({
+                    "uri": uri.as_str(),
+                    "line": position.line + 1,
+                    "character": position.character,
+                    "triggerCharacter": trigger_character,
+                }),
+            )
+            .await
+        {
+            Ok(result) => {
+                let items = self.parse_completion_items(&result);
+                if items.is_empty() {
+                    let reason = result
+                        .get("reason")
+                        .and_then(|value| value.as_str())
+                        .unwrap_or("no explicit reason");
+                    tracing::warn!(
+                        "completion returned no items for {}:{}:{} (reason={})",
+                        uri,
+                        position.line,
+                        position.character,
+                        reason
+                    );
+                }
+                Ok(Some(CompletionResponse::Array(items)))
+            }
+            Err(e) => {
+                tracing::warn!("completion failed: {}", e);
+                sidecar_unavailable_error(&e).map_or(Ok(None), Err)
+            }
+        }
+    }
+
+    async fn hover(&self, params: HoverParams) -> LspResult<Option<Hover>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        if !self.feature_enabled(Feature::Hover).await || self.is_lexical_only(&uri).await {
+            return Ok(None);
+        }
+        let position = params.text_document_position_params.position;
+
+        tracing::debug!(
+            "hover request: {}:{}:{}",
+            uri,
+            position.line,
+            position.character
+        );
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => {
+                tracing::warn!("hover: bridge is None, returning ServerNotInitialized error");
+                return Self::server_not_initialized_error();
+            }
+        };
+
+        let sidecar_state = bridge.state().await;
+        tracing::debug!("hover: sidecar state is {:?}", sidecar_state);
+
+        match self
+            .cached_position_request(
+                &bridge,
+                &uri,
+                position,
+                "hover",
+                serde_json::json!({
+                    "uri": self.sidecar_uri(&uri).await,
+                    "line": position.line + 1,
+                    "character": position.character,
+                }),
+            )
+            .await
+        {
+            Ok(result) => {
+                tracing::debug!("hover: sidecar returned: {}", result);
+                let failure_reason = result
+                    .get("reason")
+                    .and_then(|reason| reason.as_str())
+                    .unwrap_or("no explicit reason");
+
+                if let Some(contents) = result.get("contents").and_then(|c| c.as_str()) {
+                    let markdown = self.client_support.lock().await.hover_markdown;
+                    Ok(Some(Hover {
+                        contents: HoverContents::Markup(markup_content(contents, markdown)),
+                        range: None,
+                    }))
+                } else {
+                    tracing::warn!(
+                        "hover: sidecar result has no 'contents' string field (reason={})",
+                        failure_reason
+                    );
+                    Ok(None)
+                }
+            }
+            Err(e) => {
+                tracing::warn!("hover: bridge request failed: {}", e);
+                sidecar_unavailable_error(&e).map_or(Ok(None), Err)
+            }
+        }
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> LspResult<Option<GotoDefinitionResponse>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        if self.is_lexical_only(&uri).await {
+            return Ok(None);
+        }
+        let position = params.text_document_position_params.position;
+        let method = {
+            let documents = self.documents.lock().await;
+            documents
+                .get(&uri)
+                .map(|doc| doc.kind.definition_method())
+                .unwrap_or_else(|| DocumentKind::from_uri(&uri).definition_method())
+        };
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match self
+            .cached_position_request(
+                &bridge,
+                &uri,
+                position,
+                method,
+                serde_json::json!({
+                    "uri": self.sidecar_uri(&uri).await,
+                    "line": position.line + 1,
+                    "character": position.character,
+                }),
+            )
+            .await
+        {
+            Ok(result) => {
+                let mut locations = Vec::new();
+                for location in self.parse_locations(&result) {
+                    locations.push(self.open_library_location(&bridge, location).await);
+                }
+                if locations.is_empty() {
+                    Ok(None)
+                } else if locations.len() == 1 {
+                    Ok(Some(GotoDefinitionResponse::Scalar(
+                        locations.into_iter().next().unwrap(),
+                    )))
+                } else {
+                    Ok(Some(GotoDefinitionResponse::Array(locations)))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("goto_definition failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn references(&self, params: ReferenceParams) -> LspResult<Option<Vec<Location>>> {
+        let uri = params.text_document_position.text_document.uri;
+        if self.is_lexical_only(&uri).await {
+            return Ok(None);
+        }
+        self.warn_if_scope_limited("Find references").await;
+        let position = params.text_document_position.position;
+        let method = {
+            let documents = self.documents.lock().await;
+            documents
+                .get(&uri)
+                .map(|doc| doc.kind.references_method())
+                .unwrap_or_else(|| DocumentKind::from_uri(&uri).references_method())
+        };
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        let (scope, limit) = {
+            let config = self.config_for(&uri).await;
+            (config.references_scope, config.references_limit)
+        };
+        let request_params = serde_json::json!({
+            "uri": uri.as_str(),
+            "line": position.line + 1,
+            "character": position.character,
+            "includeDeclaration": params.context.include_declaration,
+            "scope": scope,
+            "limit": limit,
+        });
+        let parse_limited = |server: &Self, result: &Value| {
+            let mut locations = server.parse_locations(result);
+            locations.truncate(limit.unwrap_or(usize::MAX));
+            locations
+        };
+        if let Some(token) = params.partial_result_params.partial_result_token {
+            // Counts down across the streamed batches and the final response.
+            let remaining = Arc::new(AtomicUsize::new(limit.unwrap_or(usize::MAX)));
+            return match self
+                .request_with_partial_results(&bridge, method, request_params, token.clone(), {
+                    let remaining = Arc::clone(&remaining);
+                    move |server, batch| take_within(server.parse_locations(batch), &remaining)
+                })
+                .await
+            {
+                Ok((result, true)) => {
+                    let rest = take_within(self.parse_locations(&result), &remaining);
+                    if !rest.is_empty() {
+                        self.send_partial_result(token, &rest).await;
+                    }
+                    Ok(Some(Vec::new()))
+                }
+                Ok((result, false)) => {
+                    let locations = parse_limited(self, &result);
+                    Ok((!locations.is_empty()).then_some(locations))
+                }
+                Err(e) => {
+                    tracing::warn!("references failed: {}", e);
+                    Ok(None)
+                }
+            };
+        }
+
+        let Some(token) = params.work_done_progress_params.work_done_token else {
+            return match bridge.request(method, Some(request_params)).await {
+                Ok(result) => {
+                    let locations = parse_limited(self, &result);
+                    if locations.is_empty() {
+                        Ok(None)
+                    } else {
+                        Ok(Some(locations))
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!("references failed: {}", e);
+                    Ok(None)
+                }
+            };
+        };
+
+        self.send_work_done_progress(
+            &token,
+            WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: "Finding references".to_string(),
+                message: None,
+                percentage: None,
+                cancellable: Some(true),
+            }),
+        )
+        .await;
+        let result = cancellable_request(
+            &self.progress_cancellations,
+            &token,
+            bridge.request(method, Some(request_params)),
+        )
+        .await;
+        let (message, response) = match result {
+            Ok(result) => {
+                let locations = parse_limited(self, &result);
+                (None, Ok((!locations.is_empty()).then_some(locations)))
+            }
+            Err(crate::error::Error::Bridge(BridgeError::Cancelled)) => (
+                Some("Cancelled".to_string()),
+                Err(JsonRpcError::request_cancelled()),
+            ),
+            Err(e) => {
+                tracing::warn!("references failed: {}", e);
+                (None, Ok(None))
+            }
+        };
+        self.send_work_done_progress(
+            &token,
+            WorkDoneProgress::End(WorkDoneProgressEnd { message }),
+        )
+        .await;
+        response
+    }
+
+    async fn diagnostic(
+        &self,
+        params: DocumentDiagnosticParams,
+    ) -> LspResult<DocumentDiagnosticReportResult> {
+        let uri = params.text_document.uri;
+        let items = if self.pending_first_analysis.lock().await.contains(&uri) {
+            vec![analysis_in_progress_diagnostic()]
+        } else {
+            let (version, compiler) = {
+                let documents = self.documents.lock().await;
+                (
+                    documents.get(&uri).map_or(0, |doc| doc.version),
+                    documents.get_diagnostics(&uri).cloned().unwrap_or_default(),
+                )
+            };
+            let lint_enabled = lint::ktlint_binary(&self.config_for(&uri).await).is_some();
+            with_lint(
+                &self.lint_diagnostics,
+                lint_enabled,
+                &uri,
+                version,
+                compiler,
+            )
+            .await
+        };
+        Ok(DocumentDiagnosticReportResult::Report(
+            DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+                related_documents: None,
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id: None,
+                    items,
+                },
+            }),
+        ))
+    }
+
+    async fn formatting(
+        &self,
+        params: DocumentFormattingParams,
+    ) -> LspResult<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+
+        // Get the document text
+        let original_text = {
+            let documents = self.documents.lock().await;
+            match documents.get(&uri) {
+                Some(doc) => doc.text.clone(),
+                None => {
+                    tracing::warn!("formatting: document not found: {}", uri);
+                    return Ok(None);
+                }
+            }
+        };
+
+        match self.format_text(&uri, &original_text).await {
+            Some(formatted) if formatted != original_text => {
+                Ok(Some(vec![whole_document_edit(&original_text, formatted)]))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    async fn signature_help(
+        &self,
+        params: SignatureHelpParams,
+    ) -> LspResult<Option<SignatureHelp>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        if !self.feature_enabled(Feature::SignatureHelp).await || self.is_lexical_only(&uri).await {
+            return Ok(None);
+        }
+        let position = params.text_document_position_params.position;
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        let request_params = serde_json::json!({
+            "uri": uri.as_str(),
+            "line": position.line + 1,
+            "character": position.character,
+            "context": signature_help_context(params.context.as_ref()),
+        });
+        // A retrigger carries the previous help as context, so its result is
+        // not interchangeable with a fresh request at the same position.
+        let result = if params.context.as_ref().is_some_and(|c| c.is_retrigger) {
+            self.document_request(&bridge, &uri, "signatureHelp", request_params)
+                .await
+        } else {
+            self.cached_position_request(&bridge, &uri, position, "signatureHelp", request_params)
+                .await
+        };
+        match result {
+            Ok(result) => {
+                let mut signatures = self.parse_signatures(&result);
+                if !self.client_support.lock().await.signature_markdown {
+                    signatures
+                        .iter_mut()
+                        .for_each(plaintext_signature_documentation);
+                }
+                if signatures.is_empty() {
+                    Ok(None)
+                } else {
+                    let active_signature =
+                        retriggered_active_signature(&signatures, params.context.as_ref()).or_else(
+                            || {
+                                result
+                                    .get("activeSignature")
+                                    .and_then(|s| s.as_u64())
+                                    .map(|s| s as u32)
+                            },
+                        );
+                    let active_parameter = result
+                        .get("activeParameter")
+                        .and_then(|p| p.as_u64())
+                        .map(|p| p as u32)
+                        .or_else(|| {
+                            signatures
+                                .get(active_signature.unwrap_or(0) as usize)
+                                .and_then(|signature| signature.active_parameter)
+                        });
+
+                    Ok(Some(SignatureHelp {
+                        signatures,
+                        active_signature,
+                        active_parameter,
+                    }))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("signature_help failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        // Clients without pushed settings send none to mean "pull them"; the
+        // pull is answered on this queue, so it runs on its own task.
+        let pushed = params.settings;
+        if pushed.is_null()
+            || pushed
+                .as_object()
+                .is_some_and(|settings| settings.is_empty())
+        {
+            let server = self.clone();
+            tokio::spawn(async move {
+                if let Some(config) = server.fetch_configuration().await {
+                    server.apply_configuration(config).await;
+                }
+            });
+            return;
+        }
+        if let Ok(config) = serde_json::from_value::<Config>(pushed) {
+            // Pushed settings are not scoped to folders and replace any
+            // that were pulled.
+            self.folder_configs.lock().await.clear();
+            self.apply_configuration(config).await;
+        }
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        // A file changed on disk can change what any cached result says.
+        self.response_cache.lock().await.clear();
+        for change in params.changes {
+            let path = match change.uri.to_file_path() {
+                Ok(p) => p,
+                Err(_) => continue,
+            };
+
+            let path_str = path.to_string_lossy();
+
+            if change.typ == FileChangeType::DELETED && path_str.ends_with(".kt") {
+                self.lexical_symbols.lock().await.remove(&change.uri);
+                continue;
+            }
+
+            // Check if it's a build file (ignore our own init script to avoid loops)
+            let is_build_file = (path_str.ends_with(".gradle")
+                || path_str.ends_with(".gradle.kts")
+                || path_str.ends_with("gradle.properties"))
+                && !path_str.ends_with(".kotlin-analyzer-init.gradle");
+            if is_build_file {
+                tracing::debug!(
+                    "build file changed: {}, triggering project re-resolution",
+                    path_str
+                );
+
+                let project_root = self.project_root.lock().await.clone();
+                if let Some(root) = project_root {
+                    let config = self.config.lock().await.clone();
+                    let client = self.client.clone();
+                    let project_model_holder = Arc::clone(&self.project_model);
+
+                    tokio::spawn(async move {
+                        match project::resolve_project_with_fallback(&root, &config) {
+                            Ok(model) => {
+                                tracing::debug!("project re-resolved after build file change");
+                                *project_model_holder.lock().await = Some(model);
+                            }
+                            Err(e) => {
+                                tracing::warn!("project re-resolution failed: {}", e);
+                                let _ = client
+                                    .show_message(
+                                        MessageType::WARNING,
+                                        format!(
+                                            "kotlin-analyzer: project re-resolution failed: {}",
+                                            e
+                                        ),
+                                    )
+                                    .await;
+                            }
+                        }
+                    });
+                }
+            } else if path
+                .file_name()
+                .is_some_and(|name| name == ".kotlin-analyzer.json")
+            {
+                *self.excludes.lock().await = None;
+                let project_root = self.project_root.lock().await.clone();
+                // The root's file describes the project; nested ones override
+                // options for their source roots.
+                if let Some(root) = project_root.filter(|root| path.starts_with(root)) {
+                    tracing::debug!(".kotlin-analyzer.json changed, reloading project model");
+                    let server = self.clone();
+                    tokio::spawn(async move { server.reload_manual_config(root).await });
+                }
+            } else if path_str.ends_with(".editorconfig") {
+                tracing::debug!(".editorconfig changed: {}", path_str);
+                // External formatters pick up .editorconfig automatically, nothing to do
+            }
+        }
+    }
+
+    /// Indexes `.kt` files the client created (from a template, say) before
+    /// they are opened: they join the lexical symbol index and the sidecar
+    /// adds them to its session. An empty file first gets the `package`
+    /// declaration its directory implies.
+    async fn did_create_files(&self, params: CreateFilesParams) {
+        let excludes = self.exclude_matcher().await;
+        let mut created = Vec::new();
+        for file in params.files {
+            let Ok(uri) = Url::parse(&file.uri) else {
+                continue;
+            };
+            if !uri.path().ends_with(".kt")
+                || is_excluded_uri(&excludes, &uri)
+                || !self.is_in_source_roots(&uri).await
+            {
+                continue;
+            }
+            let Ok(path) = uri.to_file_path() else {
+                continue;
+            };
+            let mut text = tokio::fs::read_to_string(&path).await.unwrap_or_default();
+            if text.trim().is_empty() {
+                if let Some(header) = self.scaffold_package(&uri, &path).await {
+                    text = header;
+                }
+            }
+            self.update_lexical_symbols(&uri, DocumentKind::Kotlin, &text)
+                .await;
+            created.push(uri);
+        }
+        if created.is_empty() {
+            return;
+        }
+        tracing::debug!("indexing {} created file(s)", created.len());
+
+        if let Some(bridge) = self.get_bridge().await {
+            let files: Vec<&str> = created.iter().map(Url::as_str).collect();
+            if let Err(e) = bridge
+                .notify(
+                    "workspace/didCreateFiles",
+                    Some(serde_json::json!({ "files": files })),
+                )
+                .await
+            {
+                tracing::warn!("failed to notify the sidecar of created files: {}", e);
+            }
+        }
+    }
+
+    /// Drops deleted `.kt` files, or the files under a deleted folder, from
+    /// the lexical symbol index.
+    async fn did_delete_files(&self, params: DeleteFilesParams) {
+        let mut index = self.lexical_symbols.lock().await;
+        for file in params.files {
+            if let Ok(uri) = Url::parse(&file.uri) {
+                index.remove(&uri);
+            }
+        }
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> LspResult<Option<PrepareRenameResponse>> {
+        if self.is_read_only(&params.text_document.uri).await {
+            return Ok(None);
+        }
+        // Use default word-boundary behavior for all identifier positions
+        Ok(Some(PrepareRenameResponse::DefaultBehavior {
+            default_behavior: true,
+        }))
+    }
+
+    async fn rename(&self, params: RenameParams) -> LspResult<Option<WorkspaceEdit>> {
+        let uri = params.text_document_position.text_document.uri;
+        if self.is_lexical_only(&uri).await || self.is_read_only(&uri).await {
+            return Ok(None);
+        }
+        let position = params.text_document_position.position;
+        let new_name = params.new_name;
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge
+            .request(
+                "rename",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                    "line": position.line + 1,
+                    "character": position.character,
+                    "newName": new_name,
+                })),
+            )
+            .await
+        {
+            Ok(result) => {
+                let edits = parse_workspace_edits(&result);
+                let read_only = self.read_only_roots().await;
+                if let Some(blocked) = edits.keys().find(|u| is_read_only_uri(&read_only, u)) {
+                    // A partial rename would leave the read-only references
+                    // dangling, so refuse the whole edit.
+                    return Err(request_failed_error(format!(
+                        "rename would edit {blocked}, which is in a read-only source root"
+                    )));
+                }
+                if edits.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(WorkspaceEdit {
+                        changes: Some(edits),
+                        document_changes: None,
+                        change_annotations: None,
+                    }))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("rename failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> LspResult<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        if !self.feature_enabled(Feature::CodeActions).await
+            || self.is_lexical_only(&uri).await
+            || self.is_read_only(&uri).await
+        {
+            return Ok(None);
+        }
+        let range = params.range;
+        let wants_extract = wants_extract_actions(range, params.context.only.as_deref());
+        let wants_inline = {
+            let documents = self.documents.lock().await;
+            documents.get(&uri).is_some_and(|doc| {
+                wants_inline_actions(range, &doc.text, params.context.only.as_deref())
+            })
+        };
+        let diagnostics = params.context.diagnostics;
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        let mut actions = match bridge
+            .request(
+                "codeActions",
+                Some(code_action_request_params(&uri, range, &diagnostics)),
+            )
+            .await
+        {
+            Ok(result) => {
+                tracing::debug!("code_action: raw sidecar response for {}: {}", uri, result);
+                parse_ranked_code_actions(&result, &diagnostics)
+            }
+            Err(e) => {
+                tracing::warn!("code_action failed for {}: {}", uri, e);
+                Vec::new()
+            }
+        };
+
+        if wants_extract {
+            match bridge
+                .request(
+                    "extract",
+                    Some(serde_json::json!({
+                        "uri": uri.as_str(),
+                        "startLine": range.start.line + 1,
+                        "startColumn": range.start.character,
+                        "endLine": range.end.line + 1,
+                        "endColumn": range.end.character,
+                    })),
+                )
+                .await
+            {
+                Ok(result) => actions.extend(parse_ranked_code_actions(&result, &[])),
+                Err(e) => tracing::warn!("code_action: extract failed for {}: {}", uri, e),
+            }
+        }
+
+        if wants_inline {
+            match bridge
+                .request(
+                    "inline",
+                    Some(serde_json::json!({
+                        "uri": uri.as_str(),
+                        "line": range.start.line + 1,
+                        "character": range.start.character,
+                    })),
+                )
+                .await
+            {
+                Ok(result) => {
+                    if let Some(reason) = result.get("reason").and_then(|r| r.as_str()) {
+                        tracing::info!("code_action: inline not offered for {}: {}", uri, reason);
+                    }
+                    actions.extend(parse_ranked_code_actions(&result, &[]));
+                }
+                Err(e) => tracing::warn!("code_action: inline failed for {}: {}", uri, e),
+            }
+        }
+
+        for diagnostic in &diagnostics {
+            let Some(code) = suppressible_code(diagnostic) else {
+                continue;
+            };
+            match bridge
+                .request(
+                    "suppress",
+                    Some(serde_json::json!({
+                        "uri": uri.as_str(),
+                        "code": code,
+                        "line": diagnostic.range.start.line + 1,
+                        "character": diagnostic.range.start.character,
+                    })),
+                )
+                .await
+            {
+                Ok(result) => {
+                    actions.extend(parse_ranked_code_actions(&result, &[]).into_iter().map(
+                        |(rank, mut action)| {
+                            if let CodeActionOrCommand::CodeAction(action) = &mut action {
+                                action.diagnostics = Some(vec![diagnostic.clone()]);
+                            }
+                            (rank, action)
+                        },
+                    ))
+                }
+                Err(e) => tracing::warn!("code_action: suppress failed for {}: {}", uri, e),
+            }
+        }
+
+        let mut actions = order_code_actions(actions);
+        if !self.client_support.lock().await.code_action_disabled {
+            actions.retain(|action| {
+                !matches!(action, CodeActionOrCommand::CodeAction(action) if action.disabled.is_some())
+            });
+        }
+        tracing::debug!(
+            "code_action: parsed {} action(s) for {} at L{}:{}",
+            actions.len(),
+            uri,
+            range.start.line,
+            range.start.character
+        );
+        if actions.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(actions))
+        }
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> LspResult<Option<Value>> {
+        let request = parse_analyzer_command_request(params)?;
+        self.execute_analyzer_command(request).await.map(Some)
+    }
+
+    /// Lists symbols with full locations. Requests from the client reach
+    /// [`Self::workspace_symbol`] instead; see [`crate::routing`].
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> LspResult<Option<Vec<SymbolInformation>>> {
+        self.workspace_symbols(params, identity, identity).await
+    }
+
+    async fn symbol_resolve(&self, symbol: WorkspaceSymbol) -> LspResult<WorkspaceSymbol> {
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge
+            .request(
+                "workspaceSymbol/resolve",
+                Some(workspace_symbol_resolve_params(&symbol)),
+            )
+            .await
+        {
+            Ok(result) => match parse_resolved_symbol_location(&result) {
+                Some(location) => Ok(WorkspaceSymbol {
+                    location: OneOf::Left(location),
+                    ..symbol
+                }),
+                None => Ok(symbol),
+            },
+            Err(e) => {
+                tracing::warn!("workspace symbol resolve failed: {}", e);
+                Ok(symbol)
+            }
+        }
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> LspResult<Option<Vec<InlayHint>>> {
+        let uri = params.text_document.uri;
+        if !self.feature_enabled(Feature::InlayHints).await || self.is_lexical_only(&uri).await {
+            return Ok(None);
+        }
+        let range = params.range;
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge
+            .request(
+                "inlayHints",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                    "startLine": range.start.line + 1,
+                    "endLine": range.end.line + 1,
+                })),
+            )
+            .await
+        {
+            Ok(result) => {
+                let hints = self.parse_inlay_hints(&result);
+                if hints.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(hints))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("inlay_hint failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> LspResult<Option<Vec<CodeLens>>> {
+        let uri = params.text_document.uri;
+        if !self.feature_enabled(Feature::CodeLens).await || self.is_lexical_only(&uri).await {
+            return Ok(None);
+        }
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge
+            .request(
+                "codeLens",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                })),
+            )
+            .await
+        {
+            Ok(result) => {
+                let lenses = self.parse_code_lenses(&result);
+                if lenses.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(lenses))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("code_lens failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn folding_range(
+        &self,
+        params: FoldingRangeParams,
+    ) -> LspResult<Option<Vec<FoldingRange>>> {
+        if !self.feature_enabled(Feature::FoldingRange).await {
+            return Ok(None);
+        }
+        let uri = params.text_document.uri;
+        let (text, kind) = {
+            let documents = self.documents.lock().await;
+            match documents.get(&uri) {
+                Some(doc) => (doc.text.clone(), doc.kind),
+                None => return Ok(None),
+            }
+        };
+
+        // Region folds are lexical and available even without the sidecar.
+        let regions = folding::region_folding_ranges(&text);
+        let structural = if kind.supports_kotlin_analysis() && !self.is_lexical_only(&uri).await {
+            match self.get_bridge().await {
+                Some(bridge) => match bridge
+                    .request(
+                        "foldingRange",
+                        Some(serde_json::json!({
+                            "uri": uri.as_str(),
+                        })),
+                    )
+                    .await
+                {
+                    Ok(result) => folding::parse_folding_ranges(&result),
+                    Err(e) => {
+                        tracing::warn!("folding_range failed: {}", e);
+                        Vec::new()
+                    }
+                },
+                None => Vec::new(),
+            }
+        } else {
+            Vec::new()
+        };
+
+        let ranges = folding::merge_folding_ranges(regions, structural);
+        if ranges.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(ranges))
+        }
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> LspResult<Option<DocumentSymbolResponse>> {
+        let uri = params.text_document.uri;
+        if self.is_lexical_only(&uri).await {
+            return Ok(None);
+        }
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge
+            .request(
+                "documentSymbols",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                })),
+            )
+            .await
+        {
+            Ok(result) => {
+                let symbols = self.parse_document_symbols(&result);
+                if symbols.is_empty() {
+                    return Ok(None);
+                }
+                // Clients without hierarchical support show an empty outline
+                // for nested symbols, so they get the flattened list.
+                if self
+                    .client_support
+                    .lock()
+                    .await
+                    .hierarchical_document_symbols
+                {
+                    Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+                } else {
+                    Ok(Some(DocumentSymbolResponse::Flat(
+                        flatten_document_symbols(&uri, symbols),
+                    )))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("document_symbol failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn document_link(
+        &self,
+        params: DocumentLinkParams,
+    ) -> LspResult<Option<Vec<DocumentLink>>> {
+        let uri = params.text_document.uri;
+        if !self.feature_enabled(Feature::DocumentLinks).await || self.is_lexical_only(&uri).await {
+            return Ok(None);
+        }
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge
+            .request(
+                "documentLink",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                })),
+            )
+            .await
+        {
+            Ok(result) => {
+                let links = parse_document_links(&result, &uri);
+                if links.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(links))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("document_link failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn document_link_resolve(&self, mut link: DocumentLink) -> LspResult<DocumentLink> {
+        if link.target.is_some() {
+            return Ok(link);
+        }
+        let Some(data) = link.data.clone() else {
+            return Ok(link);
+        };
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge.request("documentLink/resolve", Some(data)).await {
+            Ok(result) => {
+                link.target = parse_resolved_link_target(&result);
+            }
+            Err(e) => {
+                tracing::warn!("document_link_resolve failed: {}", e);
+            }
+        }
+        Ok(link)
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> LspResult<Option<SemanticTokensResult>> {
+        let uri = params.text_document.uri;
+        if !self.feature_enabled(Feature::SemanticTokens).await || self.is_lexical_only(&uri).await
+        {
+            return Ok(None);
+        }
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge
+            .request(
+                "semanticTokens",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                })),
+            )
+            .await
+        {
+            Ok(result) => {
+                let tokens = self.parse_semantic_tokens(&result);
+                Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+                    result_id: None,
+                    data: tokens,
+                })))
+            }
+            Err(e) => {
+                tracing::warn!("semantic_tokens_full failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
 
-                        // Call analyzeAll with a generous timeout (5 minutes)
-                        let bridge_arc = {
-                            let guard = bg_bridge.lock().await;
-                            guard.as_ref().map(Arc::clone)
-                        };
-                        let analyze_result = match bridge_arc {
-                            Some(b) => {
-                                if b.state().await != SidecarState::Ready {
-                                    tracing::warn!(
-                                        "background analysis: sidecar not ready, skipping"
-                                    );
-                                    None
-                                } else {
-                                    Some(
-                                        b.request_with_timeout(
-                                            "analyzeAll",
-                                            None,
-                                            Duration::from_secs(300),
-                                        )
-                                        .await,
-                                    )
-                                }
-                            }
-                            None => None,
-                        };
+    async fn prepare_call_hierarchy(
+        &self,
+        params: CallHierarchyPrepareParams,
+    ) -> LspResult<Option<Vec<CallHierarchyItem>>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        if !self.feature_enabled(Feature::CallHierarchy).await || self.is_lexical_only(&uri).await {
+            return Ok(None);
+        }
+        let position = params.text_document_position_params.position;
 
-                        match analyze_result {
-                            Some(Ok(result)) => {
-                                let files = result.get("files").and_then(|f| f.as_array());
-                                let total_files = result
-                                    .get("totalFiles")
-                                    .and_then(|t| t.as_u64())
-                                    .unwrap_or(0);
-                                let total_errors = result
-                                    .get("totalErrors")
-                                    .and_then(|e| e.as_u64())
-                                    .unwrap_or(0);
-                                let total_warnings = result
-                                    .get("totalWarnings")
-                                    .and_then(|w| w.as_u64())
-                                    .unwrap_or(0);
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge
+            .request(
+                "callHierarchy/prepare",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                    "line": position.line + 1,
+                    "character": position.character,
+                })),
+            )
+            .await
+        {
+            Ok(result) => {
+                let items = self.parse_call_hierarchy_items(&result);
+                if items.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(items))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("prepare_call_hierarchy failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn incoming_calls(
+        &self,
+        params: CallHierarchyIncomingCallsParams,
+    ) -> LspResult<Option<Vec<CallHierarchyIncomingCall>>> {
+        let item = &params.item;
+        let uri = &item.uri;
+        let position = item.selection_range.start;
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge
+            .request(
+                "callHierarchy/incoming",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                    "line": position.line + 1,
+                    "character": position.character,
+                    "name": item.name,
+                })),
+            )
+            .await
+        {
+            Ok(result) => {
+                let calls = self.parse_incoming_calls(&result);
+                if calls.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(calls))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("incoming_calls failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn prepare_type_hierarchy(
+        &self,
+        params: TypeHierarchyPrepareParams,
+    ) -> LspResult<Option<Vec<TypeHierarchyItem>>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        if self.is_lexical_only(&uri).await {
+            return Ok(None);
+        }
+        let position = params.text_document_position_params.position;
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge
+            .request(
+                "typeHierarchy/prepare",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                    "line": position.line + 1,
+                    "character": position.character,
+                })),
+            )
+            .await
+        {
+            Ok(result) => {
+                let items = self.parse_type_hierarchy_items(&result);
+                if items.is_empty() {
+                    match result.get("reason").and_then(|r| r.as_str()) {
+                        Some("not-a-type") => tracing::debug!(
+                            "prepare_type_hierarchy: {}:{}:{} is not on a type",
+                            uri,
+                            position.line,
+                            position.character
+                        ),
+                        reason => tracing::warn!(
+                            "prepare_type_hierarchy: no items for {}:{}:{} (reason={})",
+                            uri,
+                            position.line,
+                            position.character,
+                            reason.unwrap_or("no explicit reason")
+                        ),
+                    }
+                    Ok(None)
+                } else {
+                    Ok(Some(items))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("prepare_type_hierarchy failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    async fn supertypes(
+        &self,
+        params: TypeHierarchySupertypesParams,
+    ) -> LspResult<Option<Vec<TypeHierarchyItem>>> {
+        let item = &params.item;
+        let uri = &item.uri;
+        let position = item.selection_range.start;
+
+        let bridge = match self.get_bridge().await {
+            Some(b) => b,
+            None => return Self::server_not_initialized_error(),
+        };
+
+        match bridge
+            .request(
+                "typeHierarchy/supertypes",
+                Some(serde_json::json!({
+                    "uri": uri.as_str(),
+                    "line": position.line + 1,
+                    "character": position.character,
+                    "name": item.name,
+                })),
+            )
+            .await
+        {
+            Ok(result) => {
+                let items = self.parse_type_hierarchy_items(&result);
+                if items.is_empty() {
+                    Ok(None)
+                } else {
+                    Ok(Some(items))
+                }
+            }
+            Err(e) => {
+                tracing::warn!("supertypes failed: {}", e);
+                Ok(None)
+            }
+        }
+    }
+}
+
+// Helper methods for formatting
+impl KotlinLanguageServer {
+    async fn format_with_ktfmt(
+        &self,
+        binary: &Path,
+        text: &str,
+        style: &str,
+    ) -> Result<String, std::io::Error> {
+        let style_arg = match style {
+            "google" => "--google-style",
+            "kotlinlang" => "--kotlinlang-style",
+            "dropbox" => "--dropbox-style",
+            _ => "--google-style",
+        };
+        formatter::run_formatter(binary, &[style_arg, "-"], text, formatter::FORMAT_TIMEOUT).await
+    }
+
+    async fn format_with_ktlint(
+        &self,
+        binary: &Path,
+        text: &str,
+    ) -> Result<String, std::io::Error> {
+        formatter::run_formatter(
+            binary,
+            &["--format", "--stdin"],
+            text,
+            formatter::FORMAT_TIMEOUT,
+        )
+        .await
+    }
+}
+
+// Helper methods for parsing sidecar responses
+impl KotlinLanguageServer {
+    fn parse_completion_items(&self, result: &Value) -> Vec<CompletionItem> {
+        let items = match result.get("items").and_then(|i| i.as_array()) {
+            Some(arr) => arr,
+            None => return Vec::new(),
+        };
+
+        items
+            .iter()
+            .filter_map(|item| {
+                let label = item.get("label")?.as_str()?.to_string();
+                let kind = item.get("kind").and_then(|k| k.as_str()).map(|k| match k {
+                    "function" | "method" => CompletionItemKind::FUNCTION,
+                    "property" | "field" => CompletionItemKind::FIELD,
+                    "class" => CompletionItemKind::CLASS,
+                    "interface" => CompletionItemKind::INTERFACE,
+                    "variable" | "local" => CompletionItemKind::VARIABLE,
+                    "keyword" => CompletionItemKind::KEYWORD,
+                    "snippet" => CompletionItemKind::SNIPPET,
+                    "module" | "package" => CompletionItemKind::MODULE,
+                    "enum" => CompletionItemKind::ENUM,
+                    "constant" => CompletionItemKind::CONSTANT,
+                    _ => CompletionItemKind::TEXT,
+                });
+
+                let detail = item
+                    .get("detail")
+                    .and_then(|d| d.as_str())
+                    .map(String::from);
+
+                let insert_text = item
+                    .get("insertText")
+                    .and_then(|t| t.as_str())
+                    .map(String::from);
 
-                                if let Some(files) = files {
-                                    let mut processed = 0u64;
-                                    let mut _published = 0u64;
-                                    for file_entry in files {
-                                        processed += 1;
+                let sort_text = item
+                    .get("sortText")
+                    .and_then(|s| s.as_str())
+                    .map(String::from);
 
-                                        let uri_str =
-                                            match file_entry.get("uri").and_then(|u| u.as_str()) {
-                                                Some(u) => u,
-                                                None => continue,
-                                            };
+                // Replaces the typed prefix rather than inserting after it;
+                // items without a range fall back to `insert_text`.
+                let text_edit = item.get("textEdit").and_then(|edit| {
+                    let range = edit.get("range")?;
+                    let position = |line: &str, column: &str| {
+                        Some(Position::new(
+                            range.get(line)?.as_u64()?.saturating_sub(1) as u32,
+                            range.get(column)?.as_u64()? as u32,
+                        ))
+                    };
+                    Some(CompletionTextEdit::Edit(TextEdit {
+                        range: Range {
+                            start: position("startLine", "startColumn")?,
+                            end: position("endLine", "endColumn")?,
+                        },
+                        new_text: edit.get("newText")?.as_str()?.to_string(),
+                    }))
+                });
 
-                                        let uri = match Url::parse(uri_str) {
-                                            Ok(u) => u,
-                                            Err(e) => {
-                                                tracing::warn!("background analysis: invalid URI from sidecar: {:?} ({})", uri_str, e);
-                                                continue;
-                                            }
-                                        };
+                // Auto-import edits (1-based lines). An edit without an end
+                // position is an insertion at its start.
+                let additional_text_edits = item
+                    .get("additionalTextEdits")
+                    .and_then(|a| a.as_array())
+                    .map(|edits| {
+                        edits
+                            .iter()
+                            .filter_map(|e| {
+                                let new_text = e.get("newText")?.as_str()?.to_string();
+                                let line = e.get("line")?.as_u64()?.saturating_sub(1) as u32;
+                                let col =
+                                    e.get("column").and_then(|c| c.as_u64()).unwrap_or(0) as u32;
+                                let end_line = e
+                                    .get("endLine")
+                                    .and_then(|l| l.as_u64())
+                                    .map(|l| l.saturating_sub(1) as u32)
+                                    .unwrap_or(line);
+                                let end_col = e
+                                    .get("endColumn")
+                                    .and_then(|c| c.as_u64())
+                                    .map(|c| c as u32)
+                                    .unwrap_or(col);
+                                Some(TextEdit {
+                                    range: Range {
+                                        start: Position::new(line, col),
+                                        end: Position::new(end_line, end_col),
+                                    },
+                                    new_text,
+                                })
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .filter(|edits| !edits.is_empty());
 
-                                        // Skip files that are currently open — their diagnostics
-                                        // from the replay loop are fresher
-                                        {
-                                            let docs = bg_documents.lock().await;
-                                            if docs.get(&uri).is_some() {
-                                                continue;
-                                            }
-                                        }
+                Some(CompletionItem {
+                    label,
+                    kind,
+                    detail,
+                    insert_text,
+                    sort_text,
+                    text_edit,
+                    additional_text_edits,
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
 
-                                        let diagnostics = parse_diagnostics_static(file_entry);
+    fn parse_locations(&self, result: &Value) -> Vec<Location> {
+        let locations = match result.get("locations").and_then(|l| l.as_array()) {
+            Some(arr) => arr,
+            None => return Vec::new(),
+        };
 
-                                        // Only publish and cache files with actual diagnostics
-                                        if !diagnostics.is_empty() {
-                                            {
-                                                let mut docs = bg_documents.lock().await;
-                                                docs.set_diagnostics(
-                                                    uri.clone(),
-                                                    diagnostics.clone(),
-                                                );
-                                            }
-                                            bg_client
-                                                .publish_diagnostics(uri, diagnostics, None)
-                                                .await;
-                                            _published += 1;
-                                        }
+        locations
+            .iter()
+            .filter_map(|loc| {
+                let uri_str = loc.get("uri")?.as_str()?;
+                let uri = Url::parse(uri_str).ok()?;
+                let line = loc.get("line")?.as_u64()?.saturating_sub(1) as u32;
+                let col = loc.get("column").and_then(|c| c.as_u64()).unwrap_or(0) as u32;
 
-                                        // Report progress periodically
-                                        if total_files > 0 && processed % 10 == 0 {
-                                            let pct = ((processed as f64 / total_files as f64)
-                                                * 100.0)
-                                                as u32;
-                                            bg_client
-                                                .send_notification::<lsp_types::notification::Progress>(ProgressParams {
-                                                    token: bg_token.clone(),
-                                                    value: ProgressParamsValue::WorkDone(WorkDoneProgress::Report(
-                                                        WorkDoneProgressReport {
-                                                            message: Some(format!("Processed {}/{} files", processed, total_files)),
-                                                            percentage: Some(pct),
-                                                            cancellable: Some(false),
-                                                        },
-                                                    )),
-                                                })
-                                                .await;
-                                        }
-                                    }
+                Some(Location {
+                    uri,
+                    range: Range {
+                        start: Position::new(line, col),
+                        end: Position::new(line, col),
+                    },
+                })
+            })
+            .collect()
+    }
 
-                                    let summary = format!(
-                                        "Complete — {} error(s), {} warning(s) in {} file(s)",
-                                        total_errors, total_warnings, total_files
-                                    );
-                                    tracing::info!("background analysis: {}", summary);
+    fn parse_signatures(&self, result: &Value) -> Vec<SignatureInformation> {
+        let signatures = match result.get("signatures").and_then(|s| s.as_array()) {
+            Some(arr) => arr,
+            None => return Vec::new(),
+        };
 
-                                    bg_client
-                                        .send_notification::<lsp_types::notification::Progress>(
-                                            ProgressParams {
-                                                token: bg_token.clone(),
-                                                value: ProgressParamsValue::WorkDone(
-                                                    WorkDoneProgress::End(WorkDoneProgressEnd {
-                                                        message: Some(summary),
-                                                    }),
-                                                ),
-                                            },
-                                        )
-                                        .await;
-                                }
-                            }
-                            Some(Err(e)) => {
-                                tracing::warn!("background analysis failed: {}", e);
-                                bg_client
-                                    .send_notification::<lsp_types::notification::Progress>(
-                                        ProgressParams {
-                                            token: bg_token.clone(),
-                                            value: ProgressParamsValue::WorkDone(
-                                                WorkDoneProgress::End(WorkDoneProgressEnd {
-                                                    message: Some(format!("Failed: {}", e)),
-                                                }),
-                                            ),
-                                        },
-                                    )
-                                    .await;
-                            }
-                            None => {
-                                bg_client
-                                    .send_notification::<lsp_types::notification::Progress>(
-                                        ProgressParams {
-                                            token: bg_token.clone(),
-                                            value: ProgressParamsValue::WorkDone(
-                                                WorkDoneProgress::End(WorkDoneProgressEnd {
-                                                    message: Some(
-                                                        "Skipped — sidecar not ready".to_string(),
-                                                    ),
-                                                }),
-                                            ),
-                                        },
-                                    )
-                                    .await;
-                            }
-                        }
+        signatures
+            .iter()
+            .filter_map(|sig| {
+                let label = sig.get("label")?.as_str()?.to_string();
+                let documentation = sig.get("documentation").and_then(|d| d.as_str()).map(|d| {
+                    Documentation::MarkupContent(MarkupContent {
+                        kind: MarkupKind::Markdown,
+                        value: d.to_string(),
+                    })
+                });
+
+                let parameters = sig
+                    .get("parameters")
+                    .and_then(|p| p.as_array())
+                    .map(|params| {
+                        params
+                            .iter()
+                            .filter_map(|p| {
+                                let label = p.get("label")?.as_str()?.to_string();
+                                Some(ParameterInformation {
+                                    label: ParameterLabel::Simple(label),
+                                    documentation: p
+                                        .get("documentation")
+                                        .and_then(|d| d.as_str())
+                                        .map(|d| {
+                                            Documentation::MarkupContent(MarkupContent {
+                                                kind: MarkupKind::Markdown,
+                                                value: d.to_string(),
+                                            })
+                                        }),
+                                })
+                            })
+                            .collect()
                     });
-                }
-                Err(e) => {
-                    tracing::error!("failed to start sidecar: {:?}", e);
-                    // Remove the bridge since startup failed
-                    {
-                        let mut b = bridge_holder.lock().await;
-                        *b = None;
-                    }
-                    client
-                        .send_notification::<lsp_types::notification::Progress>(ProgressParams {
-                            token: token.clone(),
-                            value: ProgressParamsValue::WorkDone(WorkDoneProgress::End(
-                                WorkDoneProgressEnd {
-                                    message: Some(format!("Failed: {}", e)),
-                                },
-                            )),
-                        })
-                        .await;
-                    client
-                        .show_message(
-                            MessageType::ERROR,
-                            format!("kotlin-analyzer: failed to start sidecar: {}", e),
-                        )
-                        .await;
-                }
-            }
-        });
+
+                // Each overload maps the argument under the cursor to its own
+                // parameter (named arguments follow the parameter name), so
+                // the index is per signature rather than per call.
+                let active_parameter = sig
+                    .get("activeParameter")
+                    .and_then(|p| p.as_u64())
+                    .map(|p| p as u32);
+
+                Some(SignatureInformation {
+                    label,
+                    documentation,
+                    parameters,
+                    active_parameter,
+                })
+            })
+            .collect()
     }
 
-    async fn shutdown(&self) -> LspResult<()> {
-        tracing::info!("kotlin-analyzer: shutting down");
+    fn parse_workspace_symbols(&self, result: &Value) -> Vec<SymbolInformation> {
+        let symbols_array = match result.get("symbols").and_then(|s| s.as_array()) {
+            Some(arr) => arr,
+            None => return Vec::new(),
+        };
+
+        symbols_array
+            .iter()
+            .filter_map(|sym| {
+                let name = sym.get("name")?.as_str()?.to_string();
+                let kind_str = sym.get("kind")?.as_str()?;
+                let kind = match kind_str {
+                    "class" => SymbolKind::CLASS,
+                    "interface" => SymbolKind::INTERFACE,
+                    "enum" => SymbolKind::ENUM,
+                    "function" | "method" => SymbolKind::FUNCTION,
+                    "property" | "field" => SymbolKind::PROPERTY,
+                    "variable" => SymbolKind::VARIABLE,
+                    "constant" => SymbolKind::CONSTANT,
+                    "module" | "package" => SymbolKind::MODULE,
+                    "constructor" => SymbolKind::CONSTRUCTOR,
+                    _ => SymbolKind::FILE,
+                };
+
+                let uri_str = sym.get("uri")?.as_str()?;
+                let uri = Url::parse(uri_str).ok()?;
+                let line = sym.get("line")?.as_u64()?.saturating_sub(1) as u32;
+                let column = sym.get("column").and_then(|c| c.as_u64()).unwrap_or(0) as u32;
 
-        if let Some(bridge) = self.get_bridge().await {
-            if let Err(e) = bridge.shutdown().await {
-                tracing::error!("error shutting down sidecar: {}", e);
-            }
-        }
+                #[allow(deprecated)]
+                Some(SymbolInformation {
+                    name,
+                    kind,
+                    tags: None,
+                    deprecated: None,
+                    location: Location {
+                        uri,
+                        range: Range {
+                            start: Position::new(line, column),
+                            end: Position::new(line, column),
+                        },
+                    },
+                    container_name: sym
+                        .get("containerName")
+                        .and_then(|c| c.as_str())
+                        .map(String::from),
+                })
+            })
+            .collect()
+    }
 
-        Ok(())
+    /// The sidecar's outline as a `DocumentSymbol` tree. Ranges use the
+    /// `{startLine,startColumn,endLine,endColumn}` format with 1-based lines.
+    fn parse_document_symbols(&self, result: &Value) -> Vec<DocumentSymbol> {
+        result
+            .get("symbols")
+            .and_then(|s| s.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(Self::parse_document_symbol)
+            .collect()
     }
 
-    async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        let uri = params.text_document.uri.clone();
-        let text = params.text_document.text.clone();
-        let version = params.text_document.version;
-        let kind = DocumentKind::from_language_id(&params.text_document.language_id, &uri);
+    fn parse_document_symbol(symbol: &Value) -> Option<DocumentSymbol> {
+        let range_at = |key: &str| {
+            let range = symbol.get(key)?;
+            let line = |key: &str| Some(range.get(key)?.as_u64()?.saturating_sub(1) as u32);
+            let column = |key: &str| Some(range.get(key)?.as_u64()? as u32);
+            Some(Range::new(
+                Position::new(line("startLine")?, column("startColumn")?),
+                Position::new(line("endLine")?, column("endColumn")?),
+            ))
+        };
+        let range = range_at("range")?;
+        let children: Vec<DocumentSymbol> = symbol
+            .get("children")
+            .and_then(|c| c.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(Self::parse_document_symbol)
+            .collect();
+
+        #[allow(deprecated)]
+        Some(DocumentSymbol {
+            name: symbol.get("name")?.as_str()?.to_string(),
+            detail: symbol
+                .get("detail")
+                .and_then(|d| d.as_str())
+                .map(str::to_string),
+            kind: Self::map_symbol_kind(symbol.get("kind")?.as_str()?),
+            tags: None,
+            deprecated: None,
+            range,
+            selection_range: range_at("selectionRange").unwrap_or(range),
+            children: (!children.is_empty()).then_some(children),
+        })
+    }
 
-        tracing::debug!(
-            "did_open: {} (version {}, {} bytes)",
-            uri,
-            version,
-            text.len()
-        );
+    fn parse_inlay_hints(&self, result: &Value) -> Vec<InlayHint> {
+        let hints_array = match result.get("hints").and_then(|h| h.as_array()) {
+            Some(arr) => arr,
+            None => return Vec::new(),
+        };
 
-        // Re-publish cached diagnostics immediately so they appear instantly on tab switch
-        {
-            let mut documents = self.documents.lock().await;
-            if let Some(cached) = documents.get_diagnostics(&uri).cloned() {
-                if !cached.is_empty() {
-                    tracing::debug!(
-                        "did_open: re-publishing {} cached diagnostics for {}",
-                        cached.len(),
-                        uri
-                    );
-                    self.client
-                        .publish_diagnostics(uri.clone(), cached, None)
-                        .await;
-                }
-            }
-            documents.open(uri.clone(), text.clone(), version, kind);
-        }
+        hints_array
+            .iter()
+            .filter_map(|hint| {
+                let line = hint.get("line")?.as_u64()?.saturating_sub(1) as u32;
+                let character = hint.get("character")?.as_u64()? as u32;
+                let label_str = hint.get("label")?.as_str()?.to_string();
 
-        // Notify sidecar
-        if let Some(bridge) = self.get_bridge().await {
-            let _ = bridge
-                .notify(
-                    kind.did_open_method(),
-                    Some(serde_json::json!({
-                        "uri": uri.as_str(),
-                        "version": version,
-                        "text": text,
-                    })),
-                )
-                .await;
-        }
+                let kind = hint
+                    .get("kind")
+                    .and_then(|k| k.as_str())
+                    .and_then(|k| match k {
+                        "type" => Some(InlayHintKind::TYPE),
+                        "parameter" => Some(InlayHintKind::PARAMETER),
+                        _ => None,
+                    });
 
-        // Trigger fresh analysis (will update the cache when complete)
-        self.analyze_document(&uri).await;
+                let padding_left = hint.get("paddingLeft").and_then(|p| p.as_bool());
+                let padding_right = hint.get("paddingRight").and_then(|p| p.as_bool());
+
+                Some(InlayHint {
+                    position: Position::new(line, character),
+                    label: InlayHintLabel::String(label_str),
+                    kind,
+                    text_edits: None,
+                    tooltip: None,
+                    padding_left,
+                    padding_right,
+                    data: None,
+                })
+            })
+            .collect()
     }
 
-    async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        let uri = params.text_document.uri.clone();
-        let version = params.text_document.version;
-        let mut latest_doc = None;
+    fn parse_code_lenses(&self, result: &Value) -> Vec<CodeLens> {
+        let lenses_array = match result.get("lenses").and_then(|l| l.as_array()) {
+            Some(arr) => arr,
+            None => return Vec::new(),
+        };
 
-        // Full sync mode — take the last content change
-        if let Some(change) = params.content_changes.into_iter().last() {
-            let mut documents = self.documents.lock().await;
-            documents.change(&uri, change.text, version);
-            latest_doc = documents.get(&uri).cloned();
-        }
+        lenses_array
+            .iter()
+            .filter_map(|lens| {
+                let line = lens.get("line")?.as_u64()?.saturating_sub(1) as u32;
+                let character = lens.get("character")?.as_u64()? as u32;
 
-        // Keep the sidecar's virtual file state in sync immediately so
-        // completion/hover/definition requests see the latest editor buffer
-        // instead of waiting for the debounced diagnostics path.
-        if let Some(doc) = latest_doc {
-            if let Some(bridge) = self.get_bridge().await {
-                let _ = bridge
-                    .notify(
-                        doc.kind.did_change_method(),
-                        Some(serde_json::json!({
-                            "uri": uri.as_str(),
-                            "version": doc.version,
-                            "text": doc.text,
-                        })),
-                    )
-                    .await;
-            }
-        }
+                let command_obj = lens.get("command")?;
+                let title = command_obj.get("title")?.as_str()?.to_string();
+                let command_name = command_obj
+                    .get("command")
+                    .and_then(|c| c.as_str())
+                    .unwrap_or("kotlin-analyzer.command")
+                    .to_string();
 
-        // Send to debounce loop for analysis
-        let debounce = self.debounce_tx.lock().await;
-        if let Some(tx) = debounce.as_ref() {
-            let _ = tx.send(uri).await;
-        }
+                Some(CodeLens {
+                    range: Range {
+                        start: Position::new(line, character),
+                        end: Position::new(line, character),
+                    },
+                    command: Some(lsp_types::Command {
+                        title,
+                        command: command_name,
+                        arguments: None,
+                    }),
+                    data: None,
+                })
+            })
+            .collect()
     }
 
-    async fn did_close(&self, params: DidCloseTextDocumentParams) {
-        let uri = params.text_document.uri.clone();
-        let kind = {
-            let documents = self.documents.lock().await;
-            documents
-                .get(&uri)
-                .map(|doc| doc.kind)
-                .unwrap_or_else(|| DocumentKind::from_uri(&uri))
+    fn parse_semantic_tokens(&self, result: &Value) -> Vec<SemanticToken> {
+        let data_array = match result.get("data").and_then(|d| d.as_array()) {
+            Some(arr) => arr,
+            None => return Vec::new(),
         };
 
-        {
-            let mut documents = self.documents.lock().await;
-            documents.close(&uri);
-        }
+        // Get the legend mapping if available
+        let legend_types = result
+            .get("legend")
+            .and_then(|l| l.get("tokenTypes"))
+            .and_then(|t| t.as_array());
 
-        // Notify sidecar
-        if let Some(bridge) = self.get_bridge().await {
-            let _ = bridge
-                .notify(
-                    kind.did_close_method(),
-                    Some(serde_json::json!({
-                        "uri": uri.as_str(),
-                    })),
-                )
-                .await;
-        }
+        // Local legend from initialize
+        let local_legend = vec![
+            "function",
+            "parameter",
+            "variable",
+            "property",
+            "class",
+            "type",
+            "string",
+            "comment",
+            "keyword",
+            "decorator",
+            "number",
+            "enumMember",
+            "typeParameter",
+        ];
 
-        // Do NOT clear diagnostics on didClose — Zed sends didClose when switching
-        // tabs, and clearing diagnostics here causes them to disappear. Cached
-        // diagnostics will be re-published on the next didOpen.
-        tracing::debug!("did_close: keeping cached diagnostics for {}", uri);
-    }
+        // Convert data array to semantic tokens (groups of 5 ints)
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i + 4 < data_array.len() {
+            let delta_line = data_array[i].as_u64().unwrap_or(0) as u32;
+            let delta_start = data_array[i + 1].as_u64().unwrap_or(0) as u32;
+            let length = data_array[i + 2].as_u64().unwrap_or(0) as u32;
+            let token_type_idx = data_array[i + 3].as_u64().unwrap_or(0) as u32;
+            let token_modifiers_bitset = data_array[i + 4].as_u64().unwrap_or(0) as u32;
 
-    async fn did_save(&self, params: DidSaveTextDocumentParams) {
-        let uri = params.text_document.uri;
-        tracing::debug!("did_save: {}", uri);
+            // Map sidecar token type to local legend index
+            let mapped_token_type = if let Some(legend) = legend_types {
+                if let Some(type_name) =
+                    legend.get(token_type_idx as usize).and_then(|t| t.as_str())
+                {
+                    // Find in local legend
+                    local_legend
+                        .iter()
+                        .position(|&t| t == type_name)
+                        .unwrap_or(0) as u32
+                } else {
+                    token_type_idx
+                }
+            } else {
+                token_type_idx
+            };
 
-        let should_analyze = {
-            let documents = self.documents.lock().await;
-            documents
-                .get(&uri)
-                .map(|doc| doc.kind.supports_kotlin_analysis())
-                .unwrap_or_else(|| DocumentKind::from_uri(&uri).supports_kotlin_analysis())
-        };
+            tokens.push(SemanticToken {
+                delta_line,
+                delta_start,
+                length,
+                token_type: mapped_token_type,
+                token_modifiers_bitset,
+            });
 
-        if !should_analyze {
-            return;
+            i += 5;
         }
 
-        // Trigger fresh analysis on save (bypasses debounce for immediate feedback)
-        self.analyze_document(&uri).await;
+        tokens
     }
 
-    async fn completion(&self, params: CompletionParams) -> LspResult<Option<CompletionResponse>> {
-        let uri = params.text_document_position.text_document.uri;
-        let position = params.text_document_position.position;
-        let trigger_character = params
-            .context
-            .as_ref()
-            .and_then(|context| context.trigger_character.clone());
-
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
+    fn parse_call_hierarchy_items(&self, result: &Value) -> Vec<CallHierarchyItem> {
+        let items_array = match result.get("items").and_then(|i| i.as_array()) {
+            Some(arr) => arr,
+            None => return Vec::new(),
         };
 
-        match bridge
-            .request(
-                "completion",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "line": position.line + 1,
-                    "character": position.character,
-                    "triggerCharacter": trigger_character,
-                })),
-            )
-            .await
-        {
-            Ok(result) => {
-                let items = self.parse_completion_items(&result);
-                if items.is_empty() {
-                    let reason = result
-                        .get("reason")
-                        .and_then(|value| value.as_str())
-                        .unwrap_or("no explicit reason");
-                    tracing::warn!(
-                        "completion returned no items for {}:{}:{} (reason={})",
-                        uri,
-                        position.line,
-                        position.character,
-                        reason
-                    );
-                }
-                Ok(Some(CompletionResponse::Array(items)))
-            }
-            Err(e) => {
-                tracing::warn!("completion failed: {}", e);
-                Ok(None)
-            }
-        }
+        items_array
+            .iter()
+            .filter_map(Self::parse_call_hierarchy_item)
+            .collect()
     }
 
-    async fn hover(&self, params: HoverParams) -> LspResult<Option<Hover>> {
-        let uri = params.text_document_position_params.text_document.uri;
-        let position = params.text_document_position_params.position;
+    /// A call hierarchy item from the sidecar. `range` spans the whole
+    /// declaration and `selectionRange` its name; older fields without end
+    /// positions fall back to empty ranges at the start.
+    fn parse_call_hierarchy_item(item: &Value) -> Option<CallHierarchyItem> {
+        let name = item.get("name")?.as_str()?.to_string();
+        let kind = Self::map_symbol_kind(item.get("kind")?.as_str()?);
+        let uri = Url::parse(item.get("uri")?.as_str()?).ok()?;
+        let line_at = |key: &str| {
+            item.get(key)
+                .and_then(|l| l.as_u64())
+                .map(|l| l.saturating_sub(1) as u32)
+        };
+        let column_at = |key: &str| item.get(key).and_then(|c| c.as_u64()).map(|c| c as u32);
 
-        tracing::debug!(
-            "hover request: {}:{}:{}",
+        let start = Position::new(line_at("line")?, column_at("column").unwrap_or(0));
+        let end = match (line_at("endLine"), column_at("endColumn")) {
+            (Some(line), Some(column)) => Position::new(line, column),
+            _ => start,
+        };
+        let selection_start = match (line_at("selectionLine"), column_at("selectionColumn")) {
+            (Some(line), Some(column)) => Position::new(line, column),
+            _ => start,
+        };
+        let selection_end = match (line_at("selectionEndLine"), column_at("selectionEndColumn")) {
+            (Some(line), Some(column)) => Position::new(line, column),
+            _ => selection_start,
+        };
+
+        Some(CallHierarchyItem {
+            name,
+            kind,
             uri,
-            position.line,
-            position.character
-        );
+            range: Range { start, end },
+            selection_range: Range {
+                start: selection_start,
+                end: selection_end,
+            },
+            detail: item
+                .get("detail")
+                .and_then(|d| d.as_str())
+                .map(str::to_string),
+            tags: None,
+            data: None,
+        })
+    }
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => {
-                tracing::warn!("hover: bridge is None, returning ServerNotInitialized error");
-                return Self::server_not_initialized_error();
-            }
+    fn parse_incoming_calls(&self, result: &Value) -> Vec<CallHierarchyIncomingCall> {
+        let calls_array = match result.get("calls").and_then(|c| c.as_array()) {
+            Some(arr) => arr,
+            None => return Vec::new(),
         };
 
-        let sidecar_state = bridge.state().await;
-        tracing::debug!("hover: sidecar state is {:?}", sidecar_state);
+        calls_array
+            .iter()
+            .filter_map(|call| {
+                let from = Self::parse_call_hierarchy_item(call.get("from")?)?;
 
-        match bridge
-            .request(
-                "hover",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "line": position.line + 1,
-                    "character": position.character,
-                })),
-            )
-            .await
-        {
-            Ok(result) => {
-                tracing::debug!("hover: sidecar returned: {}", result);
-                let failure_reason = result
-                    .get("reason")
-                    .and_then(|reason| reason.as_str())
-                    .unwrap_or("no explicit reason");
+                let from_ranges = call
+                    .get("fromRanges")
+                    .and_then(|r| r.as_array())
+                    .map(|ranges| {
+                        ranges
+                            .iter()
+                            .filter_map(|r| {
+                                let start_line =
+                                    r.get("startLine")?.as_u64()?.saturating_sub(1) as u32;
+                                let start_column = r.get("startColumn")?.as_u64()? as u32;
+                                let end_line = r.get("endLine")?.as_u64()?.saturating_sub(1) as u32;
+                                let end_column = r.get("endColumn")?.as_u64()? as u32;
 
-                if let Some(contents) = result.get("contents").and_then(|c| c.as_str()) {
-                    Ok(Some(Hover {
-                        contents: HoverContents::Markup(MarkupContent {
-                            kind: MarkupKind::Markdown,
-                            value: contents.to_string(),
-                        }),
-                        range: None,
-                    }))
-                } else {
-                    tracing::warn!(
-                        "hover: sidecar result has no 'contents' string field (reason={})",
-                        failure_reason
-                    );
-                    Ok(None)
-                }
-            }
-            Err(e) => {
-                tracing::warn!("hover: bridge request failed: {}", e);
-                Ok(None)
-            }
-        }
+                                Some(Range {
+                                    start: Position::new(start_line, start_column),
+                                    end: Position::new(end_line, end_column),
+                                })
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                Some(CallHierarchyIncomingCall { from, from_ranges })
+            })
+            .collect()
     }
 
-    async fn goto_definition(
-        &self,
-        params: GotoDefinitionParams,
-    ) -> LspResult<Option<GotoDefinitionResponse>> {
-        let uri = params.text_document_position_params.text_document.uri;
-        let position = params.text_document_position_params.position;
-        let method = {
-            let documents = self.documents.lock().await;
-            documents
-                .get(&uri)
-                .map(|doc| doc.kind.definition_method())
-                .unwrap_or_else(|| DocumentKind::from_uri(&uri).definition_method())
-        };
+    fn parse_type_hierarchy_items(&self, result: &Value) -> Vec<TypeHierarchyItem> {
+        let items_array = result
+            .get("items")
+            .or_else(|| result.get("supertypes"))
+            .and_then(|i| i.as_array());
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
+        let items_array = match items_array {
+            Some(arr) => arr,
+            None => return Vec::new(),
         };
 
-        match bridge
-            .request(
-                method,
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "line": position.line + 1,
-                    "character": position.character,
-                })),
-            )
-            .await
-        {
-            Ok(result) => {
-                let locations = self.parse_locations(&result);
-                if locations.is_empty() {
-                    Ok(None)
-                } else if locations.len() == 1 {
-                    Ok(Some(GotoDefinitionResponse::Scalar(
-                        locations.into_iter().next().unwrap(),
-                    )))
-                } else {
-                    Ok(Some(GotoDefinitionResponse::Array(locations)))
-                }
-            }
-            Err(e) => {
-                tracing::warn!("goto_definition failed: {}", e);
-                Ok(None)
+        items_array
+            .iter()
+            .filter_map(|item| {
+                let name = item.get("name")?.as_str()?.to_string();
+                let kind_str = item.get("kind")?.as_str()?;
+                let kind = Self::map_symbol_kind(kind_str);
+                let uri_str = item.get("uri")?.as_str()?;
+                let uri = Url::parse(uri_str).ok()?;
+                let line = item.get("line")?.as_u64()?.saturating_sub(1) as u32;
+                let column = item.get("column").and_then(|c| c.as_u64()).unwrap_or(0) as u32;
+
+                Some(TypeHierarchyItem {
+                    name,
+                    kind,
+                    uri,
+                    range: Range {
+                        start: Position::new(line, column),
+                        end: Position::new(line, column),
+                    },
+                    selection_range: Range {
+                        start: Position::new(line, column),
+                        end: Position::new(line, column),
+                    },
+                    detail: None,
+                    tags: None,
+                    data: None,
+                })
+            })
+            .collect()
+    }
+
+    fn map_symbol_kind(kind: &str) -> SymbolKind {
+        match kind {
+            "class" => SymbolKind::CLASS,
+            "interface" => SymbolKind::INTERFACE,
+            "function" | "method" => SymbolKind::FUNCTION,
+            "property" | "field" => SymbolKind::PROPERTY,
+            "variable" | "local" => SymbolKind::VARIABLE,
+            "enum" => SymbolKind::ENUM,
+            "enumMember" => SymbolKind::ENUM_MEMBER,
+            "module" | "package" => SymbolKind::MODULE,
+            "constructor" => SymbolKind::CONSTRUCTOR,
+            "constant" => SymbolKind::CONSTANT,
+            "object" => SymbolKind::OBJECT,
+            _ => SymbolKind::VARIABLE,
+        }
+    }
+}
+
+/// Flattens a `DocumentSymbol` tree into `SymbolInformation` in outline
+/// order, naming each symbol's parent as its `containerName`.
+fn flatten_document_symbols(uri: &Url, symbols: Vec<DocumentSymbol>) -> Vec<SymbolInformation> {
+    fn flatten(
+        uri: &Url,
+        symbols: Vec<DocumentSymbol>,
+        container: Option<&str>,
+        out: &mut Vec<SymbolInformation>,
+    ) {
+        for symbol in symbols {
+            #[allow(deprecated)]
+            out.push(SymbolInformation {
+                name: symbol.name.clone(),
+                kind: symbol.kind,
+                tags: symbol.tags,
+                deprecated: None,
+                location: Location::new(uri.clone(), symbol.range),
+                container_name: container.map(str::to_string),
+            });
+            if let Some(children) = symbol.children {
+                flatten(uri, children, Some(&symbol.name), out);
             }
         }
     }
 
-    async fn references(&self, params: ReferenceParams) -> LspResult<Option<Vec<Location>>> {
-        let uri = params.text_document_position.text_document.uri;
-        let position = params.text_document_position.position;
-        let method = {
-            let documents = self.documents.lock().await;
-            documents
-                .get(&uri)
-                .map(|doc| doc.kind.references_method())
-                .unwrap_or_else(|| DocumentKind::from_uri(&uri).references_method())
-        };
+    let mut out = Vec::new();
+    flatten(uri, symbols, None, &mut out);
+    out
+}
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
-        };
+fn show_document_acknowledged(result: Option<ShowDocumentResult>) -> bool {
+    match result {
+        Some(result) => result.success,
+        None => true,
+    }
+}
 
-        match bridge
-            .request(
-                method,
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "line": position.line + 1,
-                    "character": position.character,
-                    "includeDeclaration": params.context.include_declaration,
-                })),
-            )
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn derive_project_root_uses_nearest_build_marker_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let module = dir.path().join("app");
+        let source_dir = module.join("src/main/kotlin");
+        std::fs::create_dir_all(&source_dir).unwrap();
+        std::fs::write(module.join("build.gradle.kts"), "").unwrap();
+
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner();
+        let uri = Url::from_file_path(source_dir.join("Main.kt")).unwrap();
+
+        assert_eq!(server.derive_project_root(&uri).await, Some(module.clone()));
+        assert_eq!(server.derive_project_root(&uri).await, None);
+        assert_eq!(*server.project_root.lock().await, Some(module));
+    }
+
+    #[tokio::test]
+    async fn sidecar_start_waits_for_the_resolved_classpath() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("lib.jar"), "").unwrap();
+        std::fs::write(
+            dir.path().join(".kotlin-analyzer.json"),
+            r#"{"classpath": ["lib.jar"]}"#,
+        )
+        .unwrap();
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+
+        // Nothing resolves the project yet.
+        assert!(server
+            .await_project_model(Duration::from_millis(10))
             .await
-        {
-            Ok(result) => {
-                let locations = self.parse_locations(&result);
-                if locations.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(locations))
-                }
-            }
-            Err(e) => {
-                tracing::warn!("references failed: {}", e);
-                Ok(None)
-            }
-        }
+            .is_err());
+
+        let waiting = tokio::spawn({
+            let server = server.clone();
+            async move { server.await_project_model(Duration::from_secs(10)).await }
+        });
+        server
+            .resolve_startup_project(dir.path().to_path_buf(), Config::default())
+            .await;
+        let model = waiting.await.unwrap().unwrap();
+        let args = SidecarProjectArgs::from_model(model.as_ref(), None);
+        assert_eq!(
+            args.classpath,
+            vec![dir.path().join("lib.jar").to_string_lossy().to_string()]
+        );
     }
 
-    async fn formatting(
-        &self,
-        params: DocumentFormattingParams,
-    ) -> LspResult<Option<Vec<TextEdit>>> {
-        let uri = params.text_document.uri;
+    #[tokio::test]
+    async fn derived_root_waits_for_a_sidecar_that_has_not_started() {
+        let dir = tempfile::tempdir().unwrap();
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        assert_eq!(bridge.start_count(), 0);
 
-        // Get the document text
-        let original_text = {
-            let documents = self.documents.lock().await;
-            match documents.get(&uri) {
-                Some(doc) => doc.text.clone(),
-                None => {
-                    tracing::warn!("formatting: document not found: {}", uri);
-                    return Ok(None);
-                }
-            }
-        };
+        server
+            .adopt_derived_project_root(dir.path().to_path_buf())
+            .await;
+        let model = server.project_model.lock().await.clone().unwrap();
+        assert_eq!(model.project_root, dir.path());
+        assert!(
+            sidecar_rx.try_recv().is_err(),
+            "the pending start picks the model up"
+        );
+    }
 
-        // Get config
-        let config = self.config.lock().await.clone();
+    #[tokio::test]
+    async fn startup_resolution_keeps_a_model_for_another_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let derived = dir.path().join("module");
+        std::fs::create_dir(&derived).unwrap();
+        let derived_model =
+            project::resolve_project_with_fallback(&derived, &Config::default()).unwrap();
+        *server.project_model.lock().await = Some(derived_model);
+
+        server
+            .resolve_startup_project(dir.path().to_path_buf(), Config::default())
+            .await;
+        let model = server.project_model.lock().await.clone().unwrap();
+        assert_eq!(model.project_root, derived);
+        assert!(*server.project_resolved.borrow());
+    }
 
-        // Check formatting tool
-        match config.formatting_tool {
-            FormattingTool::None => return Ok(None),
-            FormattingTool::Ktfmt => {
-                let binary = config
-                    .formatting_path
-                    .unwrap_or_else(|| "ktfmt".to_string());
-                match self
-                    .format_with_ktfmt(&binary, &original_text, &config.formatting_style)
-                    .await
-                {
-                    Ok(Some(formatted)) => {
-                        if formatted == original_text {
-                            return Ok(None);
-                        }
-                        let line_count = original_text.lines().count() as u32;
-                        Ok(Some(vec![TextEdit {
-                            range: Range {
-                                start: Position::new(0, 0),
-                                end: Position::new(line_count, 0),
-                            },
-                            new_text: formatted,
-                        }]))
-                    }
-                    Ok(None) => Ok(None),
-                    Err(e) => {
-                        tracing::warn!("ktfmt formatting failed: {}", e);
-                        Ok(None)
-                    }
-                }
-            }
-            FormattingTool::Ktlint => {
-                let binary = config
-                    .formatting_path
-                    .unwrap_or_else(|| "ktlint".to_string());
-                match self.format_with_ktlint(&binary, &original_text).await {
-                    Ok(Some(formatted)) => {
-                        if formatted == original_text {
-                            return Ok(None);
-                        }
-                        let line_count = original_text.lines().count() as u32;
-                        Ok(Some(vec![TextEdit {
-                            range: Range {
-                                start: Position::new(0, 0),
-                                end: Position::new(line_count, 0),
-                            },
-                            new_text: formatted,
-                        }]))
-                    }
-                    Ok(None) => Ok(None),
-                    Err(e) => {
-                        tracing::warn!("ktlint formatting failed: {}", e);
-                        Ok(None)
-                    }
-                }
-            }
-        }
+    /// Installs a bridge whose sidecar side is played by the test.
+    async fn connect_fake_bridge(
+        server: &KotlinLanguageServer,
+    ) -> (
+        Arc<Bridge>,
+        tokio::sync::mpsc::Receiver<crate::jsonrpc::Request>,
+    ) {
+        let bridge = Arc::new(crate::bridge::test_bridge(Config::default()));
+        let sidecar_rx = bridge.connect_fake_sidecar().await;
+        *server.bridge.lock().await = Some(Arc::clone(&bridge));
+        (bridge, sidecar_rx)
     }
 
-    async fn signature_help(
-        &self,
-        params: SignatureHelpParams,
-    ) -> LspResult<Option<SignatureHelp>> {
-        let uri = params.text_document_position_params.text_document.uri;
-        let position = params.text_document_position_params.position;
+    #[tokio::test]
+    async fn oversized_document_drops_its_earlier_diagnostics() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (_bridge, _sidecar_rx) = connect_fake_bridge(&server).await;
+        server.config.lock().await.max_file_size_bytes = 8;
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
-        };
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        {
+            let mut documents = server.documents.lock().await;
+            documents.open(uri.clone(), "fun main() {}".into(), 2, DocumentKind::Kotlin);
+            documents.set_diagnostics(
+                uri.clone(),
+                vec![Diagnostic {
+                    message: "Unresolved reference 'greet'.".into(),
+                    ..Default::default()
+                }],
+            );
+        }
+        assert!(server.update_lexical_only(&uri, 13).await);
 
-        match bridge
-            .request(
-                "signatureHelp",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "line": position.line + 1,
-                    "character": position.character,
-                })),
-            )
+        server.analyze_document(&uri).await;
+        assert_eq!(
+            server.documents.lock().await.get_diagnostics(&uri),
+            Some(&Vec::new())
+        );
+    }
+
+    #[tokio::test]
+    async fn unavailable_sidecar_is_reported_as_an_error() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, sidecar_rx) = connect_fake_bridge(&server).await;
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "fun main() = println()\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
+        let position = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position: Position::new(0, 14),
+        };
+        let reason = |error: &JsonRpcError| error.data.as_ref().map(|data| data["reason"].clone());
+
+        // The sidecar's end of the connection is gone.
+        drop(sidecar_rx);
+        let hover = server
+            .hover(HoverParams {
+                text_document_position_params: position.clone(),
+                work_done_progress_params: Default::default(),
+            })
             .await
-        {
-            Ok(result) => {
-                let signatures = self.parse_signatures(&result);
-                if signatures.is_empty() {
-                    Ok(None)
-                } else {
-                    let active_signature = result
-                        .get("activeSignature")
-                        .and_then(|s| s.as_u64())
-                        .map(|s| s as u32);
-                    let active_parameter = result
-                        .get("activeParameter")
-                        .and_then(|p| p.as_u64())
-                        .map(|p| p as u32);
+            .unwrap_err();
+        assert_eq!(hover.code, ErrorCode::ServerError(REQUEST_FAILED));
+        assert_eq!(reason(&hover), Some(json!(SIDECAR_CRASHED)));
+        assert!(hover.message.contains("crashed"), "{}", hover.message);
+
+        bridge.shutdown().await.unwrap();
+        let completion = server
+            .completion(CompletionParams {
+                text_document_position: position,
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+                context: None,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(completion.code, ErrorCode::ServerError(REQUEST_FAILED));
+        assert_eq!(reason(&completion), Some(json!(SIDECAR_NOT_READY)));
 
-                    Ok(Some(SignatureHelp {
-                        signatures,
-                        active_signature,
-                        active_parameter,
-                    }))
-                }
+        let timeout = crate::error::Error::Bridge(BridgeError::Timeout(60_000));
+        assert_eq!(
+            sidecar_unavailable_error(&timeout).and_then(|e| reason(&e)),
+            Some(json!(SIDECAR_TIMEOUT))
+        );
+        // Nothing to show is not a failure.
+        let unsupported = crate::error::Error::Bridge(BridgeError::Unsupported("hover".into()));
+        assert!(sidecar_unavailable_error(&unsupported).is_none());
+    }
+
+    #[tokio::test]
+    async fn file_outside_the_project_root_is_analyzed_on_its_own() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = dir.path().join("app");
+        let scratch = dir.path().join("scratch");
+        std::fs::create_dir_all(app.join("src")).unwrap();
+        std::fs::create_dir_all(&scratch).unwrap();
+        std::fs::write(app.join("build.gradle.kts"), "").unwrap();
+
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        *server.project_root.lock().await = Some(app.clone());
+        let mut model = ProjectModel::no_build_system(app.clone());
+        model.source_roots = vec![app.join("src")];
+        *server.project_model.lock().await = Some(model);
+
+        let inside = Url::from_file_path(app.join("src/Main.kt")).unwrap();
+        assert!(!is_loose_file(Some(&app), &inside));
+
+        let uri = Url::from_file_path(scratch.join("Loose.kt")).unwrap();
+        let open = tokio::spawn({
+            let server = server.clone();
+            let uri = uri.clone();
+            async move {
+                server
+                    .did_open(DidOpenTextDocumentParams {
+                        text_document: TextDocumentItem::new(
+                            uri,
+                            "kotlin".into(),
+                            1,
+                            "fun main() = println()\n".into(),
+                        ),
+                    })
+                    .await
             }
-            Err(e) => {
-                tracing::warn!("signature_help failed: {}", e);
-                Ok(None)
+        });
+
+        let did_open = tokio::time::timeout(Duration::from_secs(10), sidecar_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(did_open.method, "textDocument/didOpen");
+        assert_eq!(did_open.params.unwrap()["looseFile"], json!(true));
+        assert!(server.loose_files.lock().await.contains(&uri));
+
+        // Outside the project's source roots, yet still analyzed.
+        let analyze = loop {
+            let request = tokio::time::timeout(Duration::from_secs(10), sidecar_rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            if request.method == "analyze" {
+                break request;
             }
-        }
+        };
+        bridge
+            .reply_fake_sidecar(&analyze, json!({ "diagnostics": [] }))
+            .await;
+        open.await.unwrap();
+
+        server
+            .did_close(DidCloseTextDocumentParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+            })
+            .await;
+        assert!(server.loose_files.lock().await.is_empty());
     }
 
-    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
-        if let Ok(config) = serde_json::from_value::<Config>(params.settings) {
-            tracing::debug!("configuration updated");
-            let mut c = self.config.lock().await;
-            *c = config.clone();
+    #[tokio::test]
+    async fn workspace_symbols_are_lexical_until_the_sidecar_is_ready() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let uri = Url::parse("file:///project/src/Repository.kt").unwrap();
+        server
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(
+                    uri.clone(),
+                    "kotlin".into(),
+                    1,
+                    "package demo\n\nclass UserRepository\n".into(),
+                ),
+            })
+            .await;
 
-            if let Some(bridge) = self.get_bridge().await {
-                bridge.update_config(config).await;
-            }
-        }
+        let symbols = server
+            .symbol(WorkspaceSymbolParams {
+                query: "userrepo".into(),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].name, "UserRepository");
+        assert_eq!(symbols[0].location.uri, uri);
     }
 
-    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
-        for change in params.changes {
-            let path = match change.uri.to_file_path() {
-                Ok(p) => p,
-                Err(_) => continue,
-            };
+    #[tokio::test]
+    async fn cancelling_progress_aborts_the_sidecar_request() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (_bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "fun greet() {}\nfun main() = greet()\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
+        let token = NumberOrString::String("references-1".into());
+
+        let references = tokio::spawn({
+            let server = server.clone();
+            let token = token.clone();
+            async move {
+                server
+                    .references(ReferenceParams {
+                        text_document_position: TextDocumentPositionParams {
+                            text_document: TextDocumentIdentifier { uri },
+                            position: Position::new(0, 4),
+                        },
+                        work_done_progress_params: WorkDoneProgressParams {
+                            work_done_token: Some(token),
+                        },
+                        partial_result_params: Default::default(),
+                        context: ReferenceContext {
+                            include_declaration: true,
+                        },
+                    })
+                    .await
+            }
+        });
+        let request = tokio::time::timeout(Duration::from_secs(10), sidecar_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, "references");
 
-            let path_str = path.to_string_lossy();
+        server
+            .work_done_progress_cancel(WorkDoneProgressCancelParams {
+                token: token.clone(),
+            })
+            .await;
+        let error = references.await.unwrap().unwrap_err();
+        assert_eq!(error.code, ErrorCode::RequestCancelled);
 
-            // Check if it's a build file (ignore our own init script to avoid loops)
-            let is_build_file = (path_str.ends_with(".gradle")
-                || path_str.ends_with(".gradle.kts")
-                || path_str.ends_with("gradle.properties"))
-                && !path_str.ends_with(".kotlin-analyzer-init.gradle");
-            if is_build_file {
-                tracing::debug!(
-                    "build file changed: {}, triggering project re-resolution",
-                    path_str
-                );
+        let cancel = tokio::time::timeout(Duration::from_secs(10), sidecar_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(cancel.method, "$/cancelRequest");
+        assert_eq!(cancel.params, Some(json!({ "id": request.id })));
+        assert!(
+            server.progress_cancellations.lock().await.is_empty(),
+            "progress still registered after cancel"
+        );
+    }
 
-                let project_root = self.project_root.lock().await.clone();
-                if let Some(root) = project_root {
-                    let config = self.config.lock().await.clone();
-                    let client = self.client.clone();
+    #[tokio::test]
+    async fn references_forward_the_configured_scope() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        server.config.lock().await.references_scope = crate::config::ReferencesScope::ExcludeTests;
+        let (_bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        let uri = Url::parse("file:///project/src/main/kotlin/Main.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "fun greet() {}\nfun main() = greet()\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
 
-                    tokio::spawn(async move {
-                        match project::resolve_project_with_fallback(&root, &config) {
-                            Ok(_model) => {
-                                tracing::debug!("project re-resolved after build file change");
-                            }
-                            Err(e) => {
-                                tracing::warn!("project re-resolution failed: {}", e);
-                                let _ = client
-                                    .show_message(
-                                        MessageType::WARNING,
-                                        format!(
-                                            "kotlin-analyzer: project re-resolution failed: {}",
-                                            e
-                                        ),
-                                    )
-                                    .await;
-                            }
-                        }
-                    });
-                }
-            } else if path_str.ends_with(".editorconfig") {
-                tracing::debug!(".editorconfig changed: {}", path_str);
-                // External formatters pick up .editorconfig automatically, nothing to do
+        let references = tokio::spawn({
+            let server = server.clone();
+            async move {
+                server
+                    .references(ReferenceParams {
+                        text_document_position: TextDocumentPositionParams {
+                            text_document: TextDocumentIdentifier { uri },
+                            position: Position::new(0, 4),
+                        },
+                        work_done_progress_params: Default::default(),
+                        partial_result_params: Default::default(),
+                        context: ReferenceContext {
+                            include_declaration: true,
+                        },
+                    })
+                    .await
             }
-        }
+        });
+        let request = tokio::time::timeout(Duration::from_secs(10), sidecar_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        references.abort();
+
+        assert_eq!(request.method, "references");
+        let params = request.params.unwrap();
+        assert_eq!(params["scope"], "excludeTests");
+        assert_eq!(params["includeDeclaration"], true);
     }
 
-    async fn prepare_rename(
-        &self,
-        _params: TextDocumentPositionParams,
-    ) -> LspResult<Option<PrepareRenameResponse>> {
-        // Use default word-boundary behavior for all identifier positions
-        Ok(Some(PrepareRenameResponse::DefaultBehavior {
-            default_behavior: true,
-        }))
+    #[tokio::test]
+    async fn references_are_capped_at_the_configured_limit() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        server.config.lock().await.references_limit = Some(2);
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        let uri = Url::parse("file:///project/src/main/kotlin/Main.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "fun greet() {}\nfun main() = greet()\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
+
+        let references = tokio::spawn({
+            let server = server.clone();
+            let uri = uri.clone();
+            async move {
+                server
+                    .references(ReferenceParams {
+                        text_document_position: TextDocumentPositionParams {
+                            text_document: TextDocumentIdentifier { uri },
+                            position: Position::new(0, 4),
+                        },
+                        work_done_progress_params: Default::default(),
+                        partial_result_params: Default::default(),
+                        context: ReferenceContext {
+                            include_declaration: true,
+                        },
+                    })
+                    .await
+            }
+        });
+        let request = tokio::time::timeout(Duration::from_secs(10), sidecar_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.params.as_ref().unwrap()["limit"], 2);
+        let location = |line: u32| json!({ "uri": uri.as_str(), "line": line, "column": 4 });
+        bridge
+            .reply_fake_sidecar(
+                &request,
+                json!({ "locations": [location(1), location(2), location(3)] }),
+            )
+            .await;
+
+        let locations = references.await.unwrap().unwrap().unwrap();
+        assert_eq!(locations.len(), 2);
+        assert_eq!(locations[1].range.start.line, 1);
     }
 
-    async fn rename(&self, params: RenameParams) -> LspResult<Option<WorkspaceEdit>> {
-        let uri = params.text_document_position.text_document.uri;
-        let position = params.text_document_position.position;
-        let new_name = params.new_name;
-
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
+    #[test]
+    fn diagnostics_delivery_follows_mode_and_client_support() {
+        let pulling: ClientCapabilities = serde_json::from_value(json!({
+            "textDocument": { "diagnostic": {} },
+            "workspace": { "diagnostic": { "refreshSupport": true } }
+        }))
+        .unwrap();
+        let pushing = ClientCapabilities::default();
+        let delivery = |push, pull, refresh| DiagnosticsDelivery {
+            push,
+            pull,
+            refresh,
         };
 
-        match bridge
-            .request(
-                "rename",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "line": position.line + 1,
-                    "character": position.character,
-                    "newName": new_name,
-                })),
-            )
+        let new = DiagnosticsDelivery::new;
+        assert_eq!(new(None, &pulling), delivery(false, true, true));
+        assert_eq!(new(None, &pushing), delivery(true, false, false));
+        let push = Some(DiagnosticsMode::Push);
+        assert_eq!(new(push, &pulling), delivery(true, false, false));
+        let pull = Some(DiagnosticsMode::Pull);
+        assert_eq!(new(pull, &pulling), delivery(false, true, true));
+        assert_eq!(new(pull, &pushing), delivery(true, false, false));
+        let pull_only: ClientCapabilities = serde_json::from_value(json!({
+            "textDocument": { "diagnostic": {} }
+        }))
+        .unwrap();
+        assert_eq!(new(pull, &pull_only), delivery(false, true, false));
+        let both = Some(DiagnosticsMode::Both);
+        assert_eq!(new(both, &pulling), delivery(true, true, false));
+        assert_eq!(new(both, &pushing), delivery(true, false, false));
+    }
+
+    /// Runs `initialize` for a client that supports pull diagnostics with
+    /// `diagnosticsMode` set to `mode`, returning the advertised diagnostic
+    /// provider.
+    async fn initialize_with_diagnostics_mode(
+        service: &mut tower_lsp::LspService<KotlinLanguageServer>,
+        mode: &str,
+    ) -> Value {
+        use tower_service::Service;
+
+        let request = tower_lsp::jsonrpc::Request::build("initialize")
+            .id(1)
+            .params(json!({
+                "capabilities": {
+                    "textDocument": { "diagnostic": {} },
+                    "workspace": { "diagnostic": { "refreshSupport": true } }
+                },
+                "initializationOptions": { "diagnosticsMode": mode }
+            }))
+            .finish();
+        std::future::poll_fn(|cx| service.poll_ready(cx))
             .await
-        {
-            Ok(result) => {
-                let edits = parse_workspace_edits(&result);
-                if edits.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(WorkspaceEdit {
-                        changes: Some(edits),
-                        document_changes: None,
-                        change_annotations: None,
-                    }))
-                }
-            }
-            Err(e) => {
-                tracing::warn!("rename failed: {}", e);
-                Ok(None)
+            .unwrap();
+        let response = service.call(request).await.unwrap().unwrap();
+        let (_, result) = response.into_parts();
+        result.unwrap()["capabilities"]["diagnosticProvider"].clone()
+    }
+
+    /// The next publish or pull refresh the server sends the client,
+    /// skipping unrelated messages.
+    async fn next_diagnostics_message(socket: &mut tower_lsp::ClientSocket) -> String {
+        use futures::StreamExt;
+
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(10), socket.next())
+                .await
+                .unwrap()
+                .unwrap();
+            let method = message.method();
+            if method == "textDocument/publishDiagnostics"
+                || method == "workspace/diagnostic/refresh"
+            {
+                return method.to_string();
             }
         }
     }
 
-    async fn code_action(&self, params: CodeActionParams) -> LspResult<Option<CodeActionResponse>> {
-        let uri = params.text_document.uri;
-        let range = params.range;
-        let diagnostics = params.context.diagnostics;
+    fn pull_diagnostics_params(uri: &Url) -> DocumentDiagnosticParams {
+        DocumentDiagnosticParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            identifier: None,
+            previous_result_id: None,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        }
+    }
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
+    #[tokio::test]
+    async fn push_diagnostics_mode_publishes_and_does_not_advertise_pull() {
+        let (mut service, mut socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let provider = initialize_with_diagnostics_mode(&mut service, "push").await;
+        assert_eq!(provider, Value::Null);
+
+        let server = service.inner().clone();
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        deliver_diagnostics(
+            &server.client,
+            &server.diagnostics_delivery,
+            uri,
+            vec![analysis_in_progress_diagnostic()],
+            None,
+        )
+        .await;
+        assert_eq!(
+            next_diagnostics_message(&mut socket).await,
+            "textDocument/publishDiagnostics"
+        );
+    }
+
+    #[tokio::test]
+    async fn pull_diagnostics_mode_refreshes_instead_of_publishing() {
+        let (mut service, mut socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let provider = initialize_with_diagnostics_mode(&mut service, "pull").await;
+        assert_eq!(provider["identifier"], "kotlin-analyzer");
+
+        let server = service.inner().clone();
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        let diagnostic = Diagnostic {
+            message: "Unresolved reference 'greet'.".into(),
+            ..Default::default()
         };
+        server
+            .documents
+            .lock()
+            .await
+            .set_diagnostics(uri.clone(), vec![diagnostic.clone()]);
+        // Returns without waiting for the client to answer the refresh.
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            deliver_diagnostics(
+                &server.client,
+                &server.diagnostics_delivery,
+                uri.clone(),
+                vec![diagnostic.clone()],
+                None,
+            ),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            next_diagnostics_message(&mut socket).await,
+            "workspace/diagnostic/refresh"
+        );
 
-        match bridge
-            .request(
-                "codeActions",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "line": range.start.line + 1,
-                    "character": range.start.character,
-                    "diagnostics": diagnostics.iter().map(|d| {
-                        serde_json::json!({
-                            "severity": d.severity,
-                            "message": d.message,
-                            "code": d.code,
-                        })
-                    }).collect::<Vec<_>>(),
-                })),
-            )
+        let report = server
+            .diagnostic(pull_diagnostics_params(&uri))
             .await
-        {
-            Ok(result) => {
-                tracing::debug!("code_action: raw sidecar response for {}: {}", uri, result);
-                let actions = parse_code_actions_result(&result);
-                tracing::debug!(
-                    "code_action: parsed {} action(s) for {} at L{}:{}",
-                    actions.len(),
-                    uri,
-                    range.start.line,
-                    range.start.character
-                );
-                if actions.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(actions))
-                }
-            }
-            Err(e) => {
-                tracing::warn!("code_action failed for {}: {}", uri, e);
-                Ok(None)
-            }
-        }
+            .unwrap();
+        let DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(report)) = report
+        else {
+            panic!("expected a full report, got {report:?}");
+        };
+        assert_eq!(
+            report.full_document_diagnostic_report.items,
+            vec![diagnostic]
+        );
     }
 
-    async fn execute_command(&self, params: ExecuteCommandParams) -> LspResult<Option<Value>> {
-        let request = parse_analyzer_command_request(params)?;
-        self.execute_analyzer_command(request).await.map(Some)
+    #[tokio::test]
+    async fn lint_findings_are_only_merged_for_the_version_they_were_computed_for() {
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        let finding = Diagnostic {
+            source: Some("ktlint".into()),
+            message: "Unused import".into(),
+            ..Default::default()
+        };
+        let lint_diagnostics: LintDiagnostics = Arc::new(Mutex::new(HashMap::new()));
+        lint_diagnostics
+            .lock()
+            .await
+            .insert(uri.clone(), (3, vec![finding.clone()]));
+
+        assert_eq!(
+            with_lint(&lint_diagnostics, true, &uri, 3, Vec::new()).await,
+            vec![finding]
+        );
+        assert!(with_lint(&lint_diagnostics, true, &uri, 4, Vec::new())
+            .await
+            .is_empty());
     }
 
-    async fn symbol(
-        &self,
-        params: WorkspaceSymbolParams,
-    ) -> LspResult<Option<Vec<SymbolInformation>>> {
-        let query = params.query;
+    #[tokio::test]
+    async fn both_diagnostics_mode_publishes_and_answers_pulls() {
+        let (mut service, mut socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let provider = initialize_with_diagnostics_mode(&mut service, "both").await;
+        assert_eq!(provider["identifier"], "kotlin-analyzer");
+
+        let server = service.inner().clone();
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        server
+            .pending_first_analysis
+            .lock()
+            .await
+            .insert(uri.clone());
+        deliver_diagnostics(
+            &server.client,
+            &server.diagnostics_delivery,
+            uri.clone(),
+            vec![analysis_in_progress_diagnostic()],
+            None,
+        )
+        .await;
+        assert_eq!(
+            next_diagnostics_message(&mut socket).await,
+            "textDocument/publishDiagnostics"
+        );
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
+        let report = server
+            .diagnostic(pull_diagnostics_params(&uri))
+            .await
+            .unwrap();
+        let DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(report)) = report
+        else {
+            panic!("expected a full report, got {report:?}");
         };
+        assert_eq!(
+            report.full_document_diagnostic_report.items,
+            vec![analysis_in_progress_diagnostic()]
+        );
+    }
 
-        match bridge
-            .request(
-                "workspaceSymbols",
-                Some(serde_json::json!({
-                    "query": query,
-                })),
-            )
+    #[tokio::test]
+    async fn created_file_is_searchable_before_it_is_opened() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let dir = tempfile::tempdir().unwrap();
+        let created = dir.path().join("Invoice.kt");
+        std::fs::write(&created, "package billing\n\nclass InvoiceService\n").unwrap();
+        let notes = dir.path().join("notes.txt");
+        std::fs::write(&notes, "class NotKotlin\n").unwrap();
+        let created_uri = Url::from_file_path(&created).unwrap();
+        let files = vec![
+            FileCreate {
+                uri: created_uri.to_string(),
+            },
+            FileCreate {
+                uri: Url::from_file_path(&notes).unwrap().to_string(),
+            },
+        ];
+
+        server
+            .did_create_files(CreateFilesParams {
+                files: files.clone(),
+            })
+            .await;
+        let search = |query: &str| WorkspaceSymbolParams {
+            query: query.into(),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        let symbols = server.symbol(search("invoice")).await.unwrap().unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].name, "InvoiceService");
+        assert_eq!(symbols[0].location.uri, created_uri);
+        assert_eq!(server.symbol(search("NotKotlin")).await.unwrap(), None);
+
+        let (_bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        server.did_create_files(CreateFilesParams { files }).await;
+        let notification = tokio::time::timeout(Duration::from_secs(10), sidecar_rx.recv())
             .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(notification.method, "workspace/didCreateFiles");
+        assert_eq!(
+            notification.params,
+            Some(json!({ "files": [created_uri.as_str()] }))
+        );
+    }
+
+    #[tokio::test]
+    async fn deleted_files_leave_the_lexical_index() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let invoice = Url::parse("file:///project/src/billing/Invoice.kt").unwrap();
+        let receipt = Url::parse("file:///project/src/shop/Receipt.kt").unwrap();
         {
-            Ok(result) => {
-                let symbols = self.parse_workspace_symbols(&result);
-                if symbols.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(symbols))
-                }
-            }
-            Err(e) => {
-                tracing::warn!("workspace symbols failed: {}", e);
-                Ok(None)
-            }
+            let mut index = server.lexical_symbols.lock().await;
+            index.update(invoice.clone(), "class InvoiceService\n");
+            index.update(receipt.clone(), "class ReceiptPrinter\n");
         }
-    }
+        let found = |query: &'static str| {
+            let server = server.clone();
+            async move {
+                server
+                    .symbol(WorkspaceSymbolParams {
+                        query: query.into(),
+                        work_done_progress_params: Default::default(),
+                        partial_result_params: Default::default(),
+                    })
+                    .await
+                    .unwrap()
+                    .is_some()
+            }
+        };
 
-    async fn inlay_hint(&self, params: InlayHintParams) -> LspResult<Option<Vec<InlayHint>>> {
-        let uri = params.text_document.uri;
-        let range = params.range;
+        server
+            .did_delete_files(DeleteFilesParams {
+                files: vec![FileDelete {
+                    uri: "file:///project/src/billing".into(),
+                }],
+            })
+            .await;
+        assert!(!found("Invoice").await);
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
+        // Deleted outside the editor, e.g. by a branch switch.
+        server
+            .did_change_watched_files(DidChangeWatchedFilesParams {
+                changes: vec![FileEvent::new(receipt, FileChangeType::DELETED)],
+            })
+            .await;
+        assert!(!found("Receipt").await);
+    }
+
+    #[tokio::test]
+    async fn repeated_hover_is_answered_from_cache() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "fun main() = println()\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
+        let params = HoverParams {
+            text_document_position_params: TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri },
+                position: Position::new(0, 14),
+            },
+            work_done_progress_params: Default::default(),
         };
 
-        match bridge
-            .request(
-                "inlayHints",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "startLine": range.start.line + 1,
-                    "endLine": range.end.line + 1,
-                })),
-            )
-            .await
-        {
-            Ok(result) => {
-                let hints = self.parse_inlay_hints(&result);
-                if hints.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(hints))
+        let first = tokio::spawn({
+            let server = server.clone();
+            let params = params.clone();
+            async move { server.hover(params).await }
+        });
+        let request = sidecar_rx.recv().await.unwrap();
+        assert_eq!(request.method, "hover");
+        bridge
+            .reply_fake_sidecar(&request, json!({ "contents": "fun println()" }))
+            .await;
+        let first = first.await.unwrap().unwrap();
+
+        let second = server.hover(params.clone()).await.unwrap();
+        assert_eq!(second, first);
+        assert!(second.is_some());
+        assert!(
+            sidecar_rx.try_recv().is_err(),
+            "second hover hit the bridge"
+        );
+
+        // A file changed on disk invalidates the cache.
+        server
+            .did_change_watched_files(DidChangeWatchedFilesParams {
+                changes: vec![FileEvent {
+                    uri: Url::parse("file:///project/src/Util.kt").unwrap(),
+                    typ: FileChangeType::CHANGED,
+                }],
+            })
+            .await;
+        let third = tokio::spawn({
+            let server = server.clone();
+            async move { server.hover(params).await }
+        });
+        let request = sidecar_rx.recv().await.unwrap();
+        assert_eq!(request.method, "hover");
+        bridge
+            .reply_fake_sidecar(&request, json!({ "contents": "fun println()" }))
+            .await;
+        assert_eq!(third.await.unwrap().unwrap(), first);
+    }
+
+    #[tokio::test]
+    async fn document_symbols_follow_the_negotiated_shape() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        let uri = Url::parse("file:///project/src/Greeter.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "class Greeter {\n    fun greet(): String = \"hi\"\n}\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
+        let payload = json!({
+            "symbols": [{
+                "name": "Greeter", "kind": "class",
+                "range": { "startLine": 1, "startColumn": 0, "endLine": 3, "endColumn": 1 },
+                "selectionRange": { "startLine": 1, "startColumn": 6, "endLine": 1, "endColumn": 13 },
+                "children": [{
+                    "name": "greet", "kind": "function", "detail": "(): String",
+                    "range": { "startLine": 2, "startColumn": 4, "endLine": 2, "endColumn": 30 },
+                    "selectionRange": { "startLine": 2, "startColumn": 8, "endLine": 2, "endColumn": 13 }
+                }]
+            }]
+        });
+        let mut responses = Vec::new();
+        for hierarchical in [true, false] {
+            server
+                .client_support
+                .lock()
+                .await
+                .hierarchical_document_symbols = hierarchical;
+            let response = tokio::spawn({
+                let server = server.clone();
+                let uri = uri.clone();
+                async move {
+                    server
+                        .document_symbol(DocumentSymbolParams {
+                            text_document: TextDocumentIdentifier { uri },
+                            work_done_progress_params: Default::default(),
+                            partial_result_params: Default::default(),
+                        })
+                        .await
                 }
+            });
+            let request = sidecar_rx.recv().await.unwrap();
+            assert_eq!(request.method, "documentSymbols");
+            bridge.reply_fake_sidecar(&request, payload.clone()).await;
+            responses.push(response.await.unwrap().unwrap());
+        }
+
+        let Some(DocumentSymbolResponse::Nested(nested)) = responses.remove(0) else {
+            panic!("expected nested document symbols");
+        };
+        assert_eq!(nested[0].name, "Greeter");
+        assert_eq!(
+            nested[0].selection_range,
+            Range::new(Position::new(0, 6), Position::new(0, 13))
+        );
+        let children = nested[0].children.as_ref().unwrap();
+        assert_eq!(children[0].kind, SymbolKind::FUNCTION);
+        assert_eq!(children[0].detail.as_deref(), Some("(): String"));
+
+        let Some(DocumentSymbolResponse::Flat(flat)) = responses.remove(0) else {
+            panic!("expected flat document symbols");
+        };
+        let outline: Vec<_> = flat
+            .iter()
+            .map(|s| (s.name.as_str(), s.container_name.as_deref()))
+            .collect();
+        assert_eq!(outline, vec![("Greeter", None), ("greet", Some("Greeter"))]);
+        assert_eq!(flat[1].location.uri, uri);
+        assert_eq!(
+            flat[1].location.range,
+            Range::new(Position::new(1, 4), Position::new(1, 30))
+        );
+    }
+
+    #[tokio::test]
+    async fn document_request_resends_text_when_sidecar_is_behind() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "fun main() {}\n".into(),
+            7,
+            DocumentKind::Kotlin,
+        );
+
+        let hover = tokio::spawn({
+            let server = server.clone();
+            let uri = uri.clone();
+            async move {
+                let bridge = server.get_bridge().await.unwrap();
+                server
+                    .document_request(&bridge, &uri, "hover", json!({ "uri": uri.as_str() }))
+                    .await
             }
-            Err(e) => {
-                tracing::warn!("inlay_hint failed: {}", e);
-                Ok(None)
+        });
+
+        let first = sidecar_rx.recv().await.unwrap();
+        assert_eq!(first.params.as_ref().unwrap()["version"], 7);
+        bridge
+            .reply_fake_sidecar_error(&first, -32801, "version 7 not received")
+            .await;
+
+        let resync = sidecar_rx.recv().await.unwrap();
+        assert_eq!(resync.method, "textDocument/didChange");
+        let resync_params = resync.params.unwrap();
+        assert_eq!(resync_params["version"], 7);
+        assert_eq!(resync_params["text"], "fun main() {}\n");
+
+        let retry = sidecar_rx.recv().await.unwrap();
+        assert_eq!(retry.method, "hover");
+        assert_eq!(retry.params.as_ref().unwrap()["version"], 7);
+        bridge
+            .reply_fake_sidecar(&retry, json!({ "contents": "fun main()" }))
+            .await;
+        assert_eq!(hover.await.unwrap().unwrap()["contents"], "fun main()");
+    }
+
+    #[tokio::test]
+    async fn hover_is_plain_text_for_plaintext_only_clients() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        let client: ClientCapabilities = serde_json::from_value(json!({
+            "textDocument": { "hover": { "contentFormat": ["plaintext"] } }
+        }))
+        .unwrap();
+        *server.client_support.lock().await = ClientSupport::from_capabilities(&client);
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "fun main() = greet(\"x\")\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
+
+        let hover = tokio::spawn({
+            let server = server.clone();
+            let uri = uri.clone();
+            async move {
+                server
+                    .hover(HoverParams {
+                        text_document_position_params: TextDocumentPositionParams {
+                            text_document: TextDocumentIdentifier { uri },
+                            position: Position::new(0, 14),
+                        },
+                        work_done_progress_params: Default::default(),
+                    })
+                    .await
             }
-        }
+        });
+        let request = sidecar_rx.recv().await.unwrap();
+        assert_eq!(request.method, "hover");
+        bridge
+            .reply_fake_sidecar(
+                &request,
+                json!({
+                    "contents": "```kotlin\nfun greet(name: String): String\n```\n\n**Greets** `name`."
+                }),
+            )
+            .await;
+
+        let Some(Hover {
+            contents: HoverContents::Markup(contents),
+            ..
+        }) = hover.await.unwrap().unwrap()
+        else {
+            panic!("expected markup hover contents");
+        };
+        assert_eq!(contents.kind, MarkupKind::PlainText);
+        assert!(!contents.value.contains("**"));
+        assert!(!contents.value.contains('`'));
+        assert_eq!(
+            contents.value,
+            "fun greet(name: String): String\n\nGreets name."
+        );
     }
 
-    async fn code_lens(&self, params: CodeLensParams) -> LspResult<Option<Vec<CodeLens>>> {
-        let uri = params.text_document.uri;
+    #[tokio::test]
+    async fn will_save_wait_until_organizes_imports_on_explicit_saves() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        server.config.lock().await.organize_imports_on_save = true;
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "import b.B\nimport a.A\n\nfun main() = \"é\"\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
+        let params = |reason| WillSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            reason,
+        };
+
+        // Auto-saves are left alone unless `saveActionsOnAutoSave` is set.
+        assert_eq!(
+            server
+                .will_save_wait_until(params(TextDocumentSaveReason::AFTER_DELAY))
+                .await
+                .unwrap(),
+            None
+        );
+
+        let edits = tokio::spawn({
+            let server = server.clone();
+            let params = params(TextDocumentSaveReason::MANUAL);
+            async move { server.will_save_wait_until(params).await }
+        });
+        let request = sidecar_rx.recv().await.unwrap();
+        assert_eq!(request.method, "codeActions");
+        bridge
+            .reply_fake_sidecar(
+                &request,
+                json!({
+                    "actions": [{
+                        "title": "Organize imports",
+                        "kind": "source.organizeImports",
+                        "edits": [{
+                            "uri": uri.as_str(),
+                            "range": { "startLine": 1, "startColumn": 0, "endLine": 2, "endColumn": 10 },
+                            "newText": "import a.A\nimport b.B"
+                        }]
+                    }]
+                }),
+            )
+            .await;
+
+        let edits = edits.await.unwrap().unwrap().unwrap();
+        assert_eq!(
+            edits,
+            vec![TextEdit {
+                range: Range::new(Position::new(0, 0), Position::new(4, 0)),
+                new_text: "import a.A\nimport b.B\n\nfun main() = \"é\"\n".into(),
+            }]
+        );
+    }
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
+    #[tokio::test]
+    async fn rename_is_withheld_in_read_only_source_roots() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (_bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        *server.project_root.lock().await = Some(PathBuf::from("/project"));
+        server.config.lock().await.read_only_source_roots = vec!["included/core".into()];
+        let uri = Url::parse("file:///project/included/core/src/Shared.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "fun shared() {}\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
+        let position = TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier { uri },
+            position: Position::new(0, 5),
         };
 
-        match bridge
-            .request(
-                "codeLens",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                })),
-            )
+        assert_eq!(server.prepare_rename(position.clone()).await.unwrap(), None);
+        let edit = server
+            .rename(RenameParams {
+                text_document_position: position,
+                new_name: "common".into(),
+                work_done_progress_params: Default::default(),
+            })
             .await
-        {
-            Ok(result) => {
-                let lenses = self.parse_code_lenses(&result);
-                if lenses.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(lenses))
-                }
-            }
-            Err(e) => {
-                tracing::warn!("code_lens failed: {}", e);
-                Ok(None)
-            }
-        }
+            .unwrap();
+        assert_eq!(edit, None);
+        assert!(sidecar_rx.try_recv().is_err());
     }
 
-    async fn semantic_tokens_full(
-        &self,
-        params: SemanticTokensParams,
-    ) -> LspResult<Option<SemanticTokensResult>> {
-        let uri = params.text_document.uri;
+    #[tokio::test]
+    async fn rename_reaching_a_read_only_source_root_fails() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        *server.project_root.lock().await = Some(PathBuf::from("/project"));
+        server.config.lock().await.read_only_source_roots = vec!["included/core".into()];
+        let uri = Url::parse("file:///project/app/src/Main.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "fun main() = shared()\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
+        let rename = tokio::spawn({
+            let server = server.clone();
+            async move {
+                server
+                    .rename(RenameParams {
+                        text_document_position: TextDocumentPositionParams {
+                            text_document: TextDocumentIdentifier { uri },
+                            position: Position::new(0, 14),
+                        },
+                        new_name: "common".into(),
+                        work_done_progress_params: Default::default(),
+                    })
+                    .await
+            }
+        });
+        let request = sidecar_rx.recv().await.unwrap();
+        assert_eq!(request.method, "rename");
+        let edit = |uri: &str| {
+            json!({
+                "uri": uri,
+                "range": {"startLine": 1, "startColumn": 0, "endLine": 1, "endColumn": 6},
+                "newText": "common",
+            })
         };
-
-        match bridge
-            .request(
-                "semanticTokens",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                })),
+        bridge
+            .reply_fake_sidecar(
+                &request,
+                json!({ "edits": [
+                    edit("file:///project/app/src/Main.kt"),
+                    edit("file:///project/included/core/src/Shared.kt"),
+                ] }),
             )
-            .await
-        {
-            Ok(result) => {
-                let tokens = self.parse_semantic_tokens(&result);
-                Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
-                    result_id: None,
-                    data: tokens,
-                })))
-            }
-            Err(e) => {
-                tracing::warn!("semantic_tokens_full failed: {}", e);
-                Ok(None)
-            }
-        }
+            .await;
+
+        let error = rename.await.unwrap().unwrap_err();
+        assert!(error.message.contains("read-only"), "{}", error.message);
     }
 
-    async fn prepare_call_hierarchy(
-        &self,
-        params: CallHierarchyPrepareParams,
-    ) -> LspResult<Option<Vec<CallHierarchyItem>>> {
-        let uri = params.text_document_position_params.text_document.uri;
-        let position = params.text_document_position_params.position;
+    #[tokio::test]
+    async fn hover_after_shutdown_is_rejected() {
+        use tower_lsp::jsonrpc::Request;
+        use tower_service::Service;
+
+        let (mut service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (_bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        async fn call(
+            service: &mut tower_lsp::LspService<KotlinLanguageServer>,
+            request: Request,
+        ) -> Option<tower_lsp::jsonrpc::Response> {
+            std::future::poll_fn(|cx| service.poll_ready(cx))
+                .await
+                .unwrap();
+            service.call(request).await.unwrap()
+        }
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
-        };
+        let initialize = Request::build("initialize")
+            .params(json!({ "capabilities": {} }))
+            .id(1)
+            .finish();
+        assert!(call(&mut service, initialize).await.unwrap().is_ok());
+        let shutdown = Request::build("shutdown").id(2).finish();
+        assert!(call(&mut service, shutdown).await.unwrap().is_ok());
+        assert_eq!(sidecar_rx.recv().await.unwrap().method, "shutdown");
+        assert!(server.shutdown_requested().load(Ordering::SeqCst));
+
+        let hover = Request::build("textDocument/hover")
+            .params(json!({
+                "textDocument": { "uri": "file:///project/src/Main.kt" },
+                "position": { "line": 0, "character": 4 },
+            }))
+            .id(3)
+            .finish();
+        let response = call(&mut service, hover).await.unwrap();
+        assert_eq!(response.error().unwrap().code, ErrorCode::InvalidRequest);
+        let shutdown = Request::build("shutdown").id(4).finish();
+        let response = call(&mut service, shutdown).await.unwrap();
+        assert_eq!(response.error().unwrap().code, ErrorCode::InvalidRequest);
+    }
 
-        match bridge
-            .request(
-                "callHierarchy/prepare",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "line": position.line + 1,
-                    "character": position.character,
-                })),
+    #[tokio::test]
+    async fn discover_tests_returns_a_tree_or_nothing() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        let workspace = parse_analyzer_command_request(ExecuteCommandParams {
+            command: "kotlin-analyzer.discoverTests".to_string(),
+            arguments: vec![],
+            work_done_progress_params: Default::default(),
+        })
+        .expect("discoverTests takes no arguments for the workspace");
+        assert_eq!(
+            workspace,
+            AnalyzerCommandRequest::DiscoverTests(DiscoverTestsArgs { uri: None })
+        );
+
+        let discover = tokio::spawn({
+            let server = server.clone();
+            async move { server.execute_analyzer_command(workspace).await }
+        });
+        let sent = sidecar_rx.recv().await.unwrap();
+        assert_eq!(sent.method, "discoverTests");
+        assert_eq!(sent.params, Some(json!({})));
+        let uri = "file:///project/src/test/kotlin/CalculatorTest.kt";
+        let range = |start: u64, end: u64| json!({ "startLine": start, "startColumn": 0, "endLine": end, "endColumn": 1 });
+        bridge
+            .reply_fake_sidecar(
+                &sent,
+                json!({ "tests": [{
+                    "name": "CalculatorTest", "uri": uri, "range": range(3, 12),
+                    "framework": "junit5", "kind": "class",
+                    "children": [{
+                        "name": "adds", "uri": uri, "range": range(4, 5),
+                        "framework": "junit5", "kind": "test", "children": []
+                    }]
+                }] }),
             )
-            .await
-        {
-            Ok(result) => {
-                let items = self.parse_call_hierarchy_items(&result);
-                if items.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(items))
-                }
-            }
-            Err(e) => {
-                tracing::warn!("prepare_call_hierarchy failed: {}", e);
-                Ok(None)
-            }
-        }
+            .await;
+        let result = discover.await.unwrap().unwrap();
+        let class = &result["tests"][0];
+        assert_eq!(class["name"], "CalculatorTest");
+        assert_eq!(class["range"]["start"]["line"], 2);
+        assert_eq!(class["children"][0]["name"], "adds");
+        assert_eq!(class["children"][0]["framework"], "junit5");
+        assert_eq!(class["children"][0]["range"]["end"]["line"], 4);
+
+        let file = parse_analyzer_command_request(ExecuteCommandParams {
+            command: "kotlin-analyzer.discoverTests".to_string(),
+            arguments: vec![json!({ "uri": "file:///project/src/main/kotlin/Main.kt" })],
+            work_done_progress_params: Default::default(),
+        })
+        .unwrap();
+        let discover = tokio::spawn({
+            let server = server.clone();
+            async move { server.execute_analyzer_command(file).await }
+        });
+        let sent = sidecar_rx.recv().await.unwrap();
+        assert_eq!(
+            sent.params.as_ref().unwrap()["uri"],
+            "file:///project/src/main/kotlin/Main.kt"
+        );
+        bridge
+            .reply_fake_sidecar(&sent, json!({ "tests": [] }))
+            .await;
+        assert_eq!(discover.await.unwrap().unwrap(), json!({ "tests": [] }));
     }
 
-    async fn incoming_calls(
-        &self,
-        params: CallHierarchyIncomingCallsParams,
-    ) -> LspResult<Option<Vec<CallHierarchyIncomingCall>>> {
-        let item = &params.item;
-        let uri = &item.uri;
-        let position = item.selection_range.start;
+    #[tokio::test]
+    async fn reveal_generated_returns_locations_or_a_reason() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        let request = parse_analyzer_command_request(ExecuteCommandParams {
+            command: "kotlin-analyzer.revealGenerated".to_string(),
+            arguments: vec![json!({
+                "uri": "file:///project/src/UserDao.kt",
+                "position": { "line": 4, "character": 10 }
+            })],
+            work_done_progress_params: Default::default(),
+        })
+        .expect("revealGenerated arguments should parse");
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
-        };
+        let reveal = tokio::spawn({
+            let server = server.clone();
+            let request = request.clone();
+            async move { server.execute_analyzer_command(request).await }
+        });
+        let sent = sidecar_rx.recv().await.unwrap();
+        assert_eq!(sent.method, "revealGenerated");
+        assert_eq!(sent.params.as_ref().unwrap()["line"], 5);
+        let generated = "file:///project/build/generated/ksp/main/kotlin/UserDao_Impl.kt";
+        bridge
+            .reply_fake_sidecar(
+                &sent,
+                json!({ "locations": [{ "uri": generated, "line": 3, "column": 6 }] }),
+            )
+            .await;
+        let result = reveal.await.unwrap().unwrap();
+        assert_eq!(result["locations"][0]["uri"], generated);
+        assert_eq!(result["locations"][0]["range"]["start"]["line"], 2);
 
-        match bridge
-            .request(
-                "callHierarchy/incoming",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "line": position.line + 1,
-                    "character": position.character,
-                    "name": item.name,
-                })),
+        let reveal = tokio::spawn({
+            let server = server.clone();
+            async move { server.execute_analyzer_command(request).await }
+        });
+        let sent = sidecar_rx.recv().await.unwrap();
+        bridge
+            .reply_fake_sidecar(
+                &sent,
+                json!({ "locations": [], "reason": "nothing generated for 'UserDao'" }),
             )
-            .await
-        {
-            Ok(result) => {
-                let calls = self.parse_incoming_calls(&result);
-                if calls.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(calls))
-                }
-            }
-            Err(e) => {
-                tracing::warn!("incoming_calls failed: {}", e);
-                Ok(None)
-            }
-        }
+            .await;
+        let result = reveal.await.unwrap().unwrap();
+        assert_eq!(result["locations"], json!([]));
+        assert_eq!(result["reason"], "nothing generated for 'UserDao'");
     }
 
-    async fn prepare_type_hierarchy(
-        &self,
-        params: TypeHierarchyPrepareParams,
-    ) -> LspResult<Option<Vec<TypeHierarchyItem>>> {
-        let uri = params.text_document_position_params.text_document.uri;
-        let position = params.text_document_position_params.position;
+    #[tokio::test]
+    async fn first_analysis_is_pending_until_the_sidecar_answers() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        server.documents.lock().await.open(
+            uri.clone(),
+            "fun main() {}\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
+        let analysis = tokio::spawn({
+            let server = server.clone();
+            let uri = uri.clone();
+            async move { server.analyze_document(&uri).await }
+        });
+        let request = loop {
+            let request = sidecar_rx.recv().await.unwrap();
+            if request.method == "analyze" {
+                break request;
+            }
         };
+        assert!(server.pending_first_analysis.lock().await.contains(&uri));
 
-        match bridge
-            .request(
-                "typeHierarchy/prepare",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "line": position.line + 1,
-                    "character": position.character,
-                })),
-            )
+        bridge
+            .reply_fake_sidecar(&request, json!({ "diagnostics": [] }))
+            .await;
+        analysis.await.unwrap();
+        assert!(server.pending_first_analysis.lock().await.is_empty());
+        assert!(server
+            .documents
+            .lock()
             .await
-        {
-            Ok(result) => {
-                let items = self.parse_type_hierarchy_items(&result);
-                if items.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(items))
-                }
-            }
-            Err(e) => {
-                tracing::warn!("prepare_type_hierarchy failed: {}", e);
-                Ok(None)
-            }
-        }
+            .get_diagnostics(&uri)
+            .is_some());
     }
 
-    async fn supertypes(
-        &self,
-        params: TypeHierarchySupertypesParams,
-    ) -> LspResult<Option<Vec<TypeHierarchyItem>>> {
-        let item = &params.item;
-        let uri = &item.uri;
-        let position = item.selection_range.start;
+    #[tokio::test]
+    async fn one_line_edit_preserves_unrelated_diagnostics() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        let text = "fun a() {\n    val x: Int = \"\"\n}\n\nfun b() {\n    val y = missing\n}\n";
+        server
+            .documents
+            .lock()
+            .await
+            .open(uri.clone(), text.into(), 1, DocumentKind::Kotlin);
 
-        let bridge = match self.get_bridge().await {
-            Some(b) => b,
-            None => return Self::server_not_initialized_error(),
+        let analyze = |server: KotlinLanguageServer, uri: Url| {
+            tokio::spawn(async move { server.analyze_document(&uri).await })
+        };
+        let mut next_analyze = async || loop {
+            let request = sidecar_rx.recv().await.unwrap();
+            if request.method == "analyze" {
+                break request;
+            }
         };
 
-        match bridge
-            .request(
-                "typeHierarchy/supertypes",
-                Some(serde_json::json!({
-                    "uri": uri.as_str(),
-                    "line": position.line + 1,
-                    "character": position.character,
-                    "name": item.name,
-                })),
+        let analysis = analyze(server.clone(), uri.clone());
+        let request = next_analyze().await;
+        assert_eq!(request.params.as_ref().unwrap().get("range"), None);
+        bridge
+            .reply_fake_sidecar(
+                &request,
+                json!({ "diagnostics": [
+                    { "severity": "ERROR", "message": "Type mismatch", "code": "TYPE_MISMATCH",
+                      "line": 2, "column": 17, "endLine": 2, "endColumn": 19 },
+                    { "severity": "ERROR", "message": "Unresolved reference 'missing'", "code": "UNRESOLVED_REFERENCE",
+                      "line": 6, "column": 12, "endLine": 6, "endColumn": 19 },
+                ] }),
             )
-            .await
-        {
-            Ok(result) => {
-                let items = self.parse_type_hierarchy_items(&result);
-                if items.is_empty() {
-                    Ok(None)
-                } else {
-                    Ok(Some(items))
-                }
-            }
-            Err(e) => {
-                tracing::warn!("supertypes failed: {}", e);
-                Ok(None)
-            }
-        }
+            .await;
+        analysis.await.unwrap();
+
+        // Fix the reference in `b`; only `b` (lines 5-7) is re-checked.
+        server.documents.lock().await.apply_changes(
+            &uri,
+            vec![TextDocumentContentChangeEvent {
+                range: Some(Range::new(Position::new(5, 12), Position::new(5, 19))),
+                range_length: None,
+                text: "1".into(),
+            }],
+            2,
+        );
+        let analysis = analyze(server.clone(), uri.clone());
+        let request = next_analyze().await;
+        assert_eq!(
+            request.params.as_ref().unwrap()["range"],
+            json!({ "startLine": 6, "endLine": 6 })
+        );
+        bridge
+            .reply_fake_sidecar(
+                &request,
+                json!({ "diagnostics": [], "region": { "startLine": 5, "endLine": 7 } }),
+            )
+            .await;
+        analysis.await.unwrap();
+
+        let documents = server.documents.lock().await;
+        let diagnostics = documents.get_diagnostics(&uri).unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "Type mismatch");
+        assert_eq!(diagnostics[0].range.start.line, 1);
     }
-}
 
-// Helper methods for formatting
-impl KotlinLanguageServer {
-    async fn format_with_ktfmt(
-        &self,
-        binary: &str,
-        text: &str,
-        style: &str,
-    ) -> Result<Option<String>, std::io::Error> {
-        let style_arg = match style {
-            "google" => "--google-style",
-            "kotlinlang" => "--kotlinlang-style",
-            "dropbox" => "--dropbox-style",
-            _ => "--google-style",
-        };
+    #[tokio::test]
+    async fn fast_start_upgrades_the_sidecar_once_the_project_resolves() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(".kotlin-analyzer.json"),
+            r#"{"compilerFlags": ["-Xcontext-parameters"]}"#,
+        )
+        .unwrap();
+
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        *server.project_root.lock().await = Some(dir.path().to_path_buf());
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+        assert!(server.project_model.lock().await.is_none());
+
+        let upgrade = tokio::spawn({
+            let server = server.clone();
+            async move { server.finish_fast_start().await }
+        });
+        let request = tokio::time::timeout(Duration::from_secs(10), sidecar_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, "reanalyze");
+        assert_eq!(
+            request.params.as_ref().unwrap()["compilerFlags"],
+            json!(["-Xcontext-parameters"])
+        );
+        bridge
+            .reply_fake_sidecar(&request, json!({ "success": true }))
+            .await;
+        upgrade.await.unwrap();
+        assert!(server.project_model.lock().await.is_some());
+    }
 
-        let mut child = Command::new(binary)
-            .arg(style_arg)
-            .arg("-")
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()?;
+    #[tokio::test]
+    async fn clear_cache_command_re_resolves_the_project() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(".kotlin-analyzer.json"),
+            r#"{"compilerFlags": ["-Xcontext-parameters"]}"#,
+        )
+        .unwrap();
+        let cache_dir = project::cache_dir(dir.path());
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        std::fs::write(cache_dir.join("project-model.json"), "{ corrupt").unwrap();
+
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner().clone();
+        *server.project_root.lock().await = Some(dir.path().to_path_buf());
+        let (bridge, mut sidecar_rx) = connect_fake_bridge(&server).await;
+
+        let command = tokio::spawn({
+            let server = server.clone();
+            async move {
+                server
+                    .execute_command(ExecuteCommandParams {
+                        command: analyzer_command_contract().commands.clear_cache.id.clone(),
+                        arguments: vec![],
+                        work_done_progress_params: Default::default(),
+                    })
+                    .await
+            }
+        });
+        let request = tokio::time::timeout(Duration::from_secs(10), sidecar_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, "reanalyze");
+        assert_eq!(
+            request.params.as_ref().unwrap()["compilerFlags"],
+            json!(["-Xcontext-parameters"])
+        );
+        bridge
+            .reply_fake_sidecar(&request, json!({ "success": true }))
+            .await;
+        let result = command.await.unwrap().unwrap().unwrap();
+        assert_eq!(result["reanalyzed"], true);
+        assert!(project::load_cache(&cache_dir).is_some());
+    }
 
-        let mut stdin = child.stdin.take().expect("failed to open stdin");
-        stdin.write_all(text.as_bytes()).await?;
-        drop(stdin);
+    #[tokio::test]
+    async fn manual_config_change_reconfigures_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let manual_config = dir.path().join(".kotlin-analyzer.json");
+        std::fs::write(
+            &manual_config,
+            r#"{"compilerFlags": ["-Xcontext-parameters"]}"#,
+        )
+        .unwrap();
+
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner();
+        *server.project_root.lock().await = Some(dir.path().to_path_buf());
+        let (_bridge, mut sidecar_rx) = connect_fake_bridge(server).await;
+
+        std::fs::write(
+            &manual_config,
+            r#"{"compilerFlags": ["-Xcontext-parameters", "-Xmulti-dollar-interpolation"]}"#,
+        )
+        .unwrap();
+        server
+            .did_change_watched_files(DidChangeWatchedFilesParams {
+                changes: vec![FileEvent {
+                    uri: Url::from_file_path(&manual_config).unwrap(),
+                    typ: FileChangeType::CHANGED,
+                }],
+            })
+            .await;
 
-        let output = child.wait_with_output().await?;
+        let request = tokio::time::timeout(Duration::from_secs(10), sidecar_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, "reanalyze");
+        assert_eq!(
+            request.params.unwrap()["compilerFlags"],
+            json!(["-Xcontext-parameters", "-Xmulti-dollar-interpolation"])
+        );
 
-        if output.status.success() {
-            Ok(Some(String::from_utf8_lossy(&output.stdout).to_string()))
-        } else {
-            tracing::warn!("ktfmt stderr: {}", String::from_utf8_lossy(&output.stderr));
-            Ok(None)
-        }
+        // An invalid file keeps the model that was loaded last.
+        std::fs::write(&manual_config, r#"{"compilerFlags": "#).unwrap();
+        server.reload_manual_config(dir.path().to_path_buf()).await;
+        let model = server.project_model.lock().await.clone().unwrap();
+        assert_eq!(
+            model.compiler_flags,
+            vec!["-Xcontext-parameters", "-Xmulti-dollar-interpolation"]
+        );
     }
 
-    async fn format_with_ktlint(
-        &self,
-        binary: &str,
-        text: &str,
-    ) -> Result<Option<String>, std::io::Error> {
-        let mut child = Command::new(binary)
-            .arg("--format")
-            .arg("--stdin")
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
-            .spawn()?;
-
-        let mut stdin = child.stdin.take().expect("failed to open stdin");
-        stdin.write_all(text.as_bytes()).await?;
-        drop(stdin);
-
-        let output = child.wait_with_output().await?;
-
-        if output.status.success() {
-            Ok(Some(String::from_utf8_lossy(&output.stdout).to_string()))
-        } else {
-            tracing::warn!("ktlint stderr: {}", String::from_utf8_lossy(&output.stderr));
-            Ok(None)
-        }
-    }
-}
+    #[test]
+    #[allow(deprecated)]
+    fn workspace_root_falls_back_to_root_path() {
+        let params = InitializeParams {
+            root_path: Some("/work/app".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            workspace_root(&params),
+            Some((PathBuf::from("/work/app"), "rootPath"))
+        );
 
-// Helper methods for parsing sidecar responses
-impl KotlinLanguageServer {
-    fn parse_completion_items(&self, result: &Value) -> Vec<CompletionItem> {
-        let items = match result.get("items").and_then(|i| i.as_array()) {
-            Some(arr) => arr,
-            None => return Vec::new(),
+        let params = InitializeParams {
+            root_uri: Some(Url::parse("vscode-remote://ssh/work/app").unwrap()),
+            root_path: Some("/work/app".into()),
+            ..Default::default()
         };
+        assert_eq!(
+            workspace_root(&params),
+            Some((PathBuf::from("/work/app"), "rootPath"))
+        );
+    }
 
-        items
-            .iter()
-            .filter_map(|item| {
-                let label = item.get("label")?.as_str()?.to_string();
-                let kind = item.get("kind").and_then(|k| k.as_str()).map(|k| match k {
-                    "function" | "method" => CompletionItemKind::FUNCTION,
-                    "property" | "field" => CompletionItemKind::FIELD,
-                    "class" => CompletionItemKind::CLASS,
-                    "interface" => CompletionItemKind::INTERFACE,
-                    "variable" | "local" => CompletionItemKind::VARIABLE,
-                    "keyword" => CompletionItemKind::KEYWORD,
-                    "snippet" => CompletionItemKind::SNIPPET,
-                    "module" | "package" => CompletionItemKind::MODULE,
-                    "enum" => CompletionItemKind::ENUM,
-                    "constant" => CompletionItemKind::CONSTANT,
-                    _ => CompletionItemKind::TEXT,
-                });
+    #[test]
+    fn workspace_root_falls_back_to_first_file_workspace_folder() {
+        let params = InitializeParams {
+            workspace_folders: Some(vec![
+                WorkspaceFolder {
+                    uri: Url::parse("vscode-remote://ssh/remote").unwrap(),
+                    name: "remote".into(),
+                },
+                WorkspaceFolder {
+                    uri: Url::parse("file:///work/lib").unwrap(),
+                    name: "lib".into(),
+                },
+            ]),
+            ..Default::default()
+        };
+        assert_eq!(
+            workspace_root(&params),
+            Some((PathBuf::from("/work/lib"), "workspaceFolders"))
+        );
+        assert_eq!(workspace_root(&InitializeParams::default()), None);
+    }
 
-                let detail = item
-                    .get("detail")
-                    .and_then(|d| d.as_str())
-                    .map(String::from);
+    #[test]
+    fn parse_document_links_keeps_urls_and_defers_references() {
+        let uri = Url::parse("file:///project/src/Foo.kt").unwrap();
+        let result = json!({
+            "links": [
+                {
+                    "range": { "startLine": 2, "startColumn": 8, "endLine": 2, "endColumn": 11 },
+                    "reference": "Bar"
+                },
+                {
+                    "range": { "startLine": 3, "startColumn": 7, "endLine": 3, "endColumn": 26 },
+                    "target": "https://kotlinlang.org"
+                }
+            ]
+        });
 
-                let insert_text = item
-                    .get("insertText")
-                    .and_then(|t| t.as_str())
-                    .map(String::from);
+        let links = parse_document_links(&result, &uri);
+        assert_eq!(links.len(), 2);
 
-                let sort_text = item
-                    .get("sortText")
-                    .and_then(|s| s.as_str())
-                    .map(String::from);
+        assert_eq!(links[0].range.start, Position::new(1, 8));
+        assert_eq!(links[0].target, None);
+        assert_eq!(
+            links[0].data,
+            Some(json!({ "uri": "file:///project/src/Foo.kt", "reference": "Bar" }))
+        );
 
-                let additional_text_edits = item
-                    .get("additionalTextEdits")
-                    .and_then(|a| a.as_array())
-                    .map(|edits| {
-                        edits
-                            .iter()
-                            .filter_map(|e| {
-                                let new_text = e.get("newText")?.as_str()?.to_string();
-                                let line = e.get("line")?.as_u64()?.saturating_sub(1) as u32;
-                                let col = e.get("column")?.as_u64()? as u32;
-                                let end_line = e.get("endLine")?.as_u64()?.saturating_sub(1) as u32;
-                                let end_col = e.get("endColumn")?.as_u64()? as u32;
-                                Some(TextEdit {
-                                    range: Range {
-                                        start: Position::new(line, col),
-                                        end: Position::new(end_line, end_col),
-                                    },
-                                    new_text,
-                                })
-                            })
-                            .collect()
-                    });
+        assert_eq!(
+            links[1].target.as_ref().map(Url::as_str),
+            Some("https://kotlinlang.org/")
+        );
+        assert_eq!(links[1].data, None);
+    }
 
-                Some(CompletionItem {
-                    label,
-                    kind,
-                    detail,
-                    insert_text,
-                    sort_text,
-                    additional_text_edits,
-                    ..Default::default()
-                })
-            })
-            .collect()
+    #[test]
+    fn parse_document_links_is_empty_without_kdoc() {
+        let uri = Url::parse("file:///project/src/Foo.kt").unwrap();
+        assert!(parse_document_links(&json!({ "links": [] }), &uri).is_empty());
+        assert!(parse_document_links(&json!({}), &uri).is_empty());
     }
 
-    fn parse_locations(&self, result: &Value) -> Vec<Location> {
-        let locations = match result.get("locations").and_then(|l| l.as_array()) {
-            Some(arr) => arr,
-            None => return Vec::new(),
+    #[test]
+    fn workspace_symbol_resolve_round_trips_the_listed_position() {
+        let uri = Url::parse("file:///project/src/Shapes.kt").unwrap();
+        let symbol = WorkspaceSymbol {
+            name: "Circle".to_string(),
+            kind: SymbolKind::CLASS,
+            tags: None,
+            container_name: None,
+            location: OneOf::Left(Location {
+                uri: uri.clone(),
+                range: Range {
+                    start: Position::new(11, 6),
+                    end: Position::new(11, 6),
+                },
+            }),
+            data: None,
         };
+        assert_eq!(
+            workspace_symbol_resolve_params(&symbol),
+            json!({
+                "uri": "file:///project/src/Shapes.kt",
+                "name": "Circle",
+                "line": 12,
+                "column": 6,
+            })
+        );
 
-        locations
-            .iter()
-            .filter_map(|loc| {
-                let uri_str = loc.get("uri")?.as_str()?;
-                let uri = Url::parse(uri_str).ok()?;
-                let line = loc.get("line")?.as_u64()?.saturating_sub(1) as u32;
-                let col = loc.get("column").and_then(|c| c.as_u64()).unwrap_or(0) as u32;
+        // A URI-only listing carries the position in `data` instead.
+        #[allow(deprecated)]
+        let unresolved = unresolved_workspace_symbol(SymbolInformation {
+            name: "Circle".to_string(),
+            kind: SymbolKind::CLASS,
+            tags: None,
+            deprecated: None,
+            location: match &symbol.location {
+                OneOf::Left(location) => location.clone(),
+                OneOf::Right(_) => unreachable!(),
+            },
+            container_name: None,
+        });
+        assert_eq!(
+            unresolved.location,
+            OneOf::Right(WorkspaceLocation { uri: uri.clone() })
+        );
+        assert_eq!(
+            workspace_symbol_resolve_params(&unresolved),
+            workspace_symbol_resolve_params(&symbol)
+        );
 
-                Some(Location {
-                    uri,
-                    range: Range {
-                        start: Position::new(line, col),
-                        end: Position::new(line, col),
-                    },
-                })
-            })
-            .collect()
+        let location = parse_resolved_symbol_location(&json!({
+            "uri": "file:///project/src/Shapes.kt",
+            "range": { "startLine": 14, "startColumn": 6, "endLine": 14, "endColumn": 12 }
+        }))
+        .unwrap();
+        assert_eq!(location.uri, uri);
+        assert_eq!(
+            location.range,
+            Range {
+                start: Position::new(13, 6),
+                end: Position::new(13, 12),
+            }
+        );
+        assert_eq!(parse_resolved_symbol_location(&json!({})), None);
     }
 
-    fn parse_signatures(&self, result: &Value) -> Vec<SignatureInformation> {
-        let signatures = match result.get("signatures").and_then(|s| s.as_array()) {
-            Some(arr) => arr,
-            None => return Vec::new(),
-        };
+    #[test]
+    fn parse_resolved_link_target_points_at_declaration_line() {
+        let target = parse_resolved_link_target(&json!({
+            "uri": "file:///project/src/Bar.kt",
+            "line": 12
+        }));
+        assert_eq!(
+            target.as_ref().map(Url::as_str),
+            Some("file:///project/src/Bar.kt#L12")
+        );
+        assert_eq!(parse_resolved_link_target(&json!({})), None);
+    }
 
-        signatures
-            .iter()
-            .filter_map(|sig| {
-                let label = sig.get("label")?.as_str()?.to_string();
-                let documentation = sig.get("documentation").and_then(|d| d.as_str()).map(|d| {
-                    Documentation::MarkupContent(MarkupContent {
-                        kind: MarkupKind::Markdown,
-                        value: d.to_string(),
-                    })
-                });
+    #[test]
+    fn code_action_request_params_include_selection_end() {
+        let uri = Url::parse("file:///project/src/Foo.kt").unwrap();
+        let range = Range::new(Position::new(2, 8), Position::new(4, 3));
+        let params = code_action_request_params(&uri, range, &[]);
+
+        assert_eq!(params["startLine"], 3);
+        assert_eq!(params["startColumn"], 8);
+        assert_eq!(params["endLine"], 5);
+        assert_eq!(params["endColumn"], 3);
+        assert_eq!(params["diagnostics"], json!([]));
+    }
 
-                let parameters = sig
-                    .get("parameters")
-                    .and_then(|p| p.as_array())
-                    .map(|params| {
-                        params
-                            .iter()
-                            .filter_map(|p| {
-                                let label = p.get("label")?.as_str()?.to_string();
-                                Some(ParameterInformation {
-                                    label: ParameterLabel::Simple(label),
-                                    documentation: p
-                                        .get("documentation")
-                                        .and_then(|d| d.as_str())
-                                        .map(|d| {
-                                            Documentation::MarkupContent(MarkupContent {
-                                                kind: MarkupKind::Markdown,
-                                                value: d.to_string(),
-                                            })
-                                        }),
-                                })
-                            })
-                            .collect()
-                    });
+    #[test]
+    fn suppressible_code_skips_errors_and_uncoded_diagnostics() {
+        let diagnostic = |severity, code: Option<&str>| Diagnostic {
+            severity: Some(severity),
+            code: code.map(|c| NumberOrString::String(c.to_string())),
+            ..Default::default()
+        };
 
-                Some(SignatureInformation {
-                    label,
-                    documentation,
-                    parameters,
-                    active_parameter: None,
-                })
-            })
-            .collect()
+        let warning = diagnostic(DiagnosticSeverity::WARNING, Some("UNUSED_VARIABLE"));
+        assert_eq!(suppressible_code(&warning), Some("UNUSED_VARIABLE"));
+        let hint = diagnostic(DiagnosticSeverity::HINT, Some("REDUNDANT_NULLABLE"));
+        assert_eq!(suppressible_code(&hint), Some("REDUNDANT_NULLABLE"));
+        let error = diagnostic(DiagnosticSeverity::ERROR, Some("UNRESOLVED_REFERENCE"));
+        assert_eq!(suppressible_code(&error), None);
+        assert_eq!(
+            suppressible_code(&diagnostic(DiagnosticSeverity::WARNING, None)),
+            None
+        );
     }
 
-    fn parse_workspace_symbols(&self, result: &Value) -> Vec<SymbolInformation> {
-        let symbols_array = match result.get("symbols").and_then(|s| s.as_array()) {
-            Some(arr) => arr,
-            None => return Vec::new(),
-        };
+    #[test]
+    fn wants_action_kind_matches_parent_kinds() {
+        let inline = CodeActionKind::REFACTOR_INLINE;
+        assert!(wants_action_kind(&inline, None));
+        assert!(wants_action_kind(
+            &inline,
+            Some(&[CodeActionKind::REFACTOR])
+        ));
+        assert!(wants_action_kind(
+            &inline,
+            Some(std::slice::from_ref(&inline))
+        ));
+        assert!(!wants_action_kind(
+            &inline,
+            Some(&[CodeActionKind::QUICKFIX, CodeActionKind::REFACTOR_EXTRACT])
+        ));
+    }
 
-        symbols_array
-            .iter()
-            .filter_map(|sym| {
-                let name = sym.get("name")?.as_str()?.to_string();
-                let kind_str = sym.get("kind")?.as_str()?;
-                let kind = match kind_str {
-                    "class" => SymbolKind::CLASS,
-                    "interface" => SymbolKind::INTERFACE,
-                    "enum" => SymbolKind::ENUM,
-                    "function" | "method" => SymbolKind::FUNCTION,
-                    "property" | "field" => SymbolKind::PROPERTY,
-                    "variable" => SymbolKind::VARIABLE,
-                    "constant" => SymbolKind::CONSTANT,
-                    "module" | "package" => SymbolKind::MODULE,
-                    "constructor" => SymbolKind::CONSTRUCTOR,
-                    _ => SymbolKind::FILE,
-                };
+    #[test]
+    fn wants_inline_actions_requires_a_cursor_or_identifier() {
+        let text = "val total = price * count\n";
+        let range = |start, end| Range::new(Position::new(0, start), Position::new(0, end));
+
+        assert!(wants_inline_actions(range(6, 6), text, None));
+        assert!(wants_inline_actions(range(4, 9), text, None));
+        assert!(!wants_inline_actions(range(12, 25), text, None));
+        assert!(!wants_inline_actions(range(3, 9), text, None));
+        assert!(!wants_inline_actions(
+            range(6, 6),
+            text,
+            Some(&[CodeActionKind::QUICKFIX])
+        ));
+    }
 
-                let uri_str = sym.get("uri")?.as_str()?;
-                let uri = Url::parse(uri_str).ok()?;
-                let line = sym.get("line")?.as_u64()?.saturating_sub(1) as u32;
-                let column = sym.get("column").and_then(|c| c.as_u64()).unwrap_or(0) as u32;
+    #[test]
+    fn wants_extract_actions_requires_selection_and_matching_kind() {
+        let point = Range::new(Position::new(3, 4), Position::new(3, 4));
+        let selection = Range::new(Position::new(3, 4), Position::new(3, 12));
+
+        assert!(!wants_extract_actions(point, None));
+        assert!(wants_extract_actions(selection, None));
+        assert!(wants_extract_actions(
+            selection,
+            Some(&[CodeActionKind::REFACTOR])
+        ));
+        assert!(wants_extract_actions(
+            selection,
+            Some(&[CodeActionKind::REFACTOR_EXTRACT])
+        ));
+        assert!(!wants_extract_actions(
+            selection,
+            Some(&[CodeActionKind::QUICKFIX])
+        ));
+        assert!(!wants_extract_actions(
+            selection,
+            Some(&[CodeActionKind::from("ref".to_string())])
+        ));
+    }
 
-                #[allow(deprecated)]
-                Some(SymbolInformation {
-                    name,
-                    kind,
-                    tags: None,
-                    deprecated: None,
-                    location: Location {
-                        uri,
-                        range: Range {
-                            start: Position::new(line, column),
-                            end: Position::new(line, column),
-                        },
-                    },
-                    container_name: sym
-                        .get("containerName")
-                        .and_then(|c| c.as_str())
-                        .map(String::from),
-                })
-            })
-            .collect()
+    fn parse_code_actions_result(result: &Value, diagnostics: &[Diagnostic]) -> CodeActionResponse {
+        order_code_actions(parse_ranked_code_actions(result, diagnostics))
     }
 
-    fn parse_inlay_hints(&self, result: &Value) -> Vec<InlayHint> {
-        let hints_array = match result.get("hints").and_then(|h| h.as_array()) {
-            Some(arr) => arr,
-            None => return Vec::new(),
-        };
+    #[test]
+    fn code_actions_are_ordered_by_rank_then_kind() {
+        let result = json!({
+            "actions": [
+                { "title": "Extract function", "kind": "refactor.extract" },
+                { "title": "Organize imports", "kind": "source.organizeImports" },
+                { "title": "Add import", "kind": "quickfix" },
+                { "title": "Create function 'greet'", "kind": "quickfix", "rank": 0, "isPreferred": true }
+            ]
+        });
 
-        hints_array
+        let actions = parse_code_actions_result(&result, &[]);
+        let summary: Vec<_> = actions
             .iter()
-            .filter_map(|hint| {
-                let line = hint.get("line")?.as_u64()?.saturating_sub(1) as u32;
-                let character = hint.get("character")?.as_u64()? as u32;
-                let label_str = hint.get("label")?.as_str()?.to_string();
-
-                let kind = hint
-                    .get("kind")
-                    .and_then(|k| k.as_str())
-                    .and_then(|k| match k {
-                        "type" => Some(InlayHintKind::TYPE),
-                        "parameter" => Some(InlayHintKind::PARAMETER),
-                        _ => None,
-                    });
+            .map(|action| match action {
+                CodeActionOrCommand::CodeAction(action) => {
+                    (action.title.as_str(), action.is_preferred)
+                }
+                CodeActionOrCommand::Command(command) => (command.title.as_str(), None),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Create function 'greet'", Some(true)),
+                ("Add import", None),
+                ("Extract function", None),
+                ("Organize imports", None),
+            ]
+        );
+    }
 
-                let padding_left = hint.get("paddingLeft").and_then(|p| p.as_bool());
-                let padding_right = hint.get("paddingRight").and_then(|p| p.as_bool());
+    #[test]
+    fn disabled_code_action_keeps_its_reason() {
+        let result = json!({
+            "actions": [
+                {
+                    "title": "Extract to function",
+                    "kind": "refactor.extract",
+                    "disabled": { "reason": "The selection uses local variables" }
+                }
+            ]
+        });
 
-                Some(InlayHint {
-                    position: Position::new(line, character),
-                    label: InlayHintLabel::String(label_str),
-                    kind,
-                    text_edits: None,
-                    tooltip: None,
-                    padding_left,
-                    padding_right,
-                    data: None,
-                })
+        let actions = parse_code_actions_result(&result, &[]);
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected code action");
+        };
+        assert_eq!(
+            action.disabled,
+            Some(CodeActionDisabled {
+                reason: "The selection uses local variables".into()
             })
-            .collect()
+        );
+        assert!(action.edit.is_none());
+        let sent = serde_json::to_value(action).unwrap();
+        assert_eq!(
+            sent["disabled"],
+            json!({ "reason": "The selection uses local variables" })
+        );
     }
 
-    fn parse_code_lenses(&self, result: &Value) -> Vec<CodeLens> {
-        let lenses_array = match result.get("lenses").and_then(|l| l.as_array()) {
-            Some(arr) => arr,
-            None => return Vec::new(),
+    #[test]
+    fn code_action_edits_several_files_then_runs_its_command() {
+        let edit = |uri: &str, line: u64, text: &str| {
+            json!({
+                "uri": uri,
+                "range": { "startLine": line, "startColumn": 0, "endLine": line, "endColumn": 0 },
+                "newText": text
+            })
         };
+        let result = json!({
+            "actions": [
+                {
+                    "title": "Create class 'Greeter'",
+                    "kind": "quickfix",
+                    "edits": [
+                        edit("file:///src/Main.kt", 2, "import demo.Greeter\n"),
+                        edit("file:///src/Greeter.kt", 1, "package demo\n\nclass Greeter\n"),
+                        edit("file:///src/Main.kt", 5, "    Greeter()\n")
+                    ],
+                    "command": {
+                        "title": "Organize imports",
+                        "command": "editor.action.organizeImports"
+                    }
+                }
+            ]
+        });
 
-        lenses_array
+        let actions = parse_code_actions_result(&result, &[]);
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected code action");
+        };
+        let changes = action.edit.as_ref().unwrap().changes.as_ref().unwrap();
+        let main = Url::parse("file:///src/Main.kt").unwrap();
+        let greeter = Url::parse("file:///src/Greeter.kt").unwrap();
+        assert_eq!(changes.len(), 2);
+        let main_lines: Vec<_> = changes[&main]
             .iter()
-            .filter_map(|lens| {
-                let line = lens.get("line")?.as_u64()?.saturating_sub(1) as u32;
-                let character = lens.get("character")?.as_u64()? as u32;
+            .map(|edit| edit.range.start.line)
+            .collect();
+        assert_eq!(main_lines, vec![1, 4]);
+        assert_eq!(
+            changes[&greeter][0].new_text,
+            "package demo\n\nclass Greeter\n"
+        );
+        let command = action.command.as_ref().unwrap();
+        assert_eq!(command.command, "editor.action.organizeImports");
+        assert_eq!(command.arguments, None);
+    }
 
-                let command_obj = lens.get("command")?;
-                let title = command_obj.get("title")?.as_str()?.to_string();
-                let command_name = command_obj
-                    .get("command")
-                    .and_then(|c| c.as_str())
-                    .unwrap_or("kotlin-analyzer.command")
-                    .to_string();
+    #[test]
+    fn parse_code_actions_preserves_command_payloads() {
+        let result = json!({
+            "actions": [
+                {
+                    "title": "Open existing test",
+                    "kind": "quickfix",
+                    "command": {
+                        "title": "Open existing test",
+                        "command": "kotlin-analyzer.openTestTarget",
+                        "arguments": [
+                            {
+                                "targetUri": "file:///tmp/Test.kt",
+                                "selection": {
+                                    "startLine": 3,
+                                    "startCharacter": 1,
+                                    "endLine": 3,
+                                    "endCharacter": 5
+                                }
+                            }
+                        ]
+                    }
+                }
+            ]
+        });
 
-                Some(CodeLens {
-                    range: Range {
-                        start: Position::new(line, character),
-                        end: Position::new(line, character),
-                    },
-                    command: Some(lsp_types::Command {
-                        title,
-                        command: command_name,
-                        arguments: None,
-                    }),
-                    data: None,
-                })
-            })
-            .collect()
-    }
+        let actions = parse_code_actions_result(&result, &[]);
+        assert_eq!(actions.len(), 1);
 
-    fn parse_semantic_tokens(&self, result: &Value) -> Vec<SemanticToken> {
-        let data_array = match result.get("data").and_then(|d| d.as_array()) {
-            Some(arr) => arr,
-            None => return Vec::new(),
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected code action");
         };
 
-        // Get the legend mapping if available
-        let legend_types = result
-            .get("legend")
-            .and_then(|l| l.get("tokenTypes"))
-            .and_then(|t| t.as_array());
+        let command = action
+            .command
+            .as_ref()
+            .expect("command should be preserved");
+        assert_eq!(command.command, "kotlin-analyzer.openTestTarget");
+        assert_eq!(
+            command
+                .arguments
+                .as_ref()
+                .and_then(|args| args.first())
+                .cloned(),
+            Some(json!({
+                "targetUri": "file:///tmp/Test.kt",
+                "selection": {
+                    "startLine": 3,
+                    "startCharacter": 1,
+                    "endLine": 3,
+                    "endCharacter": 5
+                }
+            }))
+        );
+        assert!(
+            action.edit.is_none(),
+            "command-only actions should not gain empty edits"
+        );
+    }
 
-        // Local legend from initialize
-        let local_legend = vec![
-            "function",
-            "parameter",
-            "variable",
-            "property",
-            "class",
-            "type",
-            "string",
-            "comment",
-            "keyword",
-            "decorator",
-            "number",
-            "enumMember",
-            "typeParameter",
-        ];
+    #[test]
+    fn parse_code_actions_keeps_edit_only_actions_compatible() {
+        let result = json!({
+            "actions": [
+                {
+                    "title": "Add import",
+                    "kind": "quickfix",
+                    "edits": [
+                        {
+                            "uri": "file:///tmp/Test.kt",
+                            "range": {
+                                "startLine": 1,
+                                "startColumn": 0,
+                                "endLine": 1,
+                                "endColumn": 0
+                            },
+                            "newText": "import kotlin.collections.List\n"
+                        }
+                    ]
+                }
+            ]
+        });
 
-        // Convert data array to semantic tokens (groups of 5 ints)
-        let mut tokens = Vec::new();
-        let mut i = 0;
-        while i + 4 < data_array.len() {
-            let delta_line = data_array[i].as_u64().unwrap_or(0) as u32;
-            let delta_start = data_array[i + 1].as_u64().unwrap_or(0) as u32;
-            let length = data_array[i + 2].as_u64().unwrap_or(0) as u32;
-            let token_type_idx = data_array[i + 3].as_u64().unwrap_or(0) as u32;
-            let token_modifiers_bitset = data_array[i + 4].as_u64().unwrap_or(0) as u32;
+        let actions = parse_code_actions_result(&result, &[]);
+        assert_eq!(actions.len(), 1);
+
+        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
+            panic!("expected code action");
+        };
+
+        let edit = action.edit.as_ref().expect("edit should be preserved");
+        let changes = edit.changes.as_ref().expect("changes should exist");
+        assert_eq!(changes.len(), 1);
+        assert!(
+            action.command.is_none(),
+            "edit-only action should remain command-free"
+        );
+    }
+
+    #[test]
+    fn remove_unused_import_fix_carries_its_diagnostic() {
+        let diagnostics = parse_diagnostics(
+            &json!({
+                "diagnostics": [
+                    {
+                        "severity": "WARNING",
+                        "message": "Unused import directive",
+                        "code": "UNUSED_IMPORT",
+                        "line": 3, "column": 0, "endLine": 3, "endColumn": 22,
+                        "tags": ["unnecessary"]
+                    },
+                    {
+                        "severity": "WARNING",
+                        "message": "Unused import directive",
+                        "code": "UNUSED_IMPORT",
+                        "line": 4, "column": 0, "endLine": 4, "endColumn": 20,
+                        "tags": ["unnecessary"]
+                    }
+                ]
+            }),
+            None,
+        );
+        assert_eq!(diagnostics[0].tags, Some(vec![DiagnosticTag::UNNECESSARY]));
+        assert_eq!(suppressible_code(&diagnostics[0]), None);
 
-            // Map sidecar token type to local legend index
-            let mapped_token_type = if let Some(legend) = legend_types {
-                if let Some(type_name) =
-                    legend.get(token_type_idx as usize).and_then(|t| t.as_str())
+        // Deleting line 4 (1-based) entirely, newline included.
+        let result = json!({
+            "actions": [
                 {
-                    // Find in local legend
-                    local_legend
-                        .iter()
-                        .position(|&t| t == type_name)
-                        .unwrap_or(0) as u32
-                } else {
-                    token_type_idx
+                    "title": "Remove unused import",
+                    "kind": "quickfix",
+                    "diagnostic": { "code": "UNUSED_IMPORT", "line": 4 },
+                    "edits": [{
+                        "uri": "file:///project/src/Main.kt",
+                        "range": { "startLine": 4, "startColumn": 0, "endLine": 5, "endColumn": 0 },
+                        "newText": ""
+                    }]
+                },
+                {
+                    "title": "Remove all unused imports",
+                    "kind": "source.removeUnusedImports",
+                    "edits": []
                 }
-            } else {
-                token_type_idx
-            };
+            ]
+        });
+        let actions = parse_code_actions_result(&result, &diagnostics);
+        let CodeActionOrCommand::CodeAction(fix) = &actions[0] else {
+            panic!("expected code action");
+        };
+        assert_eq!(fix.diagnostics.as_deref(), Some(&diagnostics[1..]));
+        let uri = Url::parse("file:///project/src/Main.kt").unwrap();
+        let edits = &fix.edit.as_ref().unwrap().changes.as_ref().unwrap()[&uri];
+        assert_eq!(
+            edits[0].range,
+            Range::new(Position::new(3, 0), Position::new(4, 0))
+        );
 
-            tokens.push(SemanticToken {
-                delta_line,
-                delta_start,
-                length,
-                token_type: mapped_token_type,
-                token_modifiers_bitset,
-            });
+        let CodeActionOrCommand::CodeAction(remove_all) = &actions[1] else {
+            panic!("expected code action");
+        };
+        assert_eq!(
+            remove_all.kind.as_ref().map(CodeActionKind::as_str),
+            Some("source.removeUnusedImports")
+        );
+        assert!(remove_all.diagnostics.is_none());
+    }
 
-            i += 5;
-        }
+    #[test]
+    fn parse_analyzer_command_rejects_unknown_command_ids() {
+        let error = parse_analyzer_command_request(ExecuteCommandParams {
+            command: "kotlin-analyzer.unsupported".to_string(),
+            arguments: vec![json!({})],
+            work_done_progress_params: Default::default(),
+        })
+        .expect_err("unsupported command should fail");
 
-        tokens
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+        assert!(error.message.contains("unsupported analyzer command"));
     }
 
-    fn parse_call_hierarchy_items(&self, result: &Value) -> Vec<CallHierarchyItem> {
-        let items_array = match result.get("items").and_then(|i| i.as_array()) {
-            Some(arr) => arr,
-            None => return Vec::new(),
-        };
+    #[test]
+    fn every_contract_command_is_routed_and_advertised() {
+        let contract: Value = serde_json::from_str(ANALYZER_COMMAND_CONTRACT_JSON).unwrap();
+        let mut contract_ids: Vec<String> = contract["commands"]
+            .as_object()
+            .unwrap()
+            .values()
+            .map(|command| command["id"].as_str().unwrap().to_string())
+            .collect();
+        contract_ids.sort();
+
+        let mut routed = supported_analyzer_command_ids();
+        routed.sort();
+        assert_eq!(routed, contract_ids);
+
+        let advertised = server_capabilities(false, &Features::default())
+            .execute_command_provider
+            .unwrap()
+            .commands;
+        assert_eq!(advertised.len(), contract_ids.len());
 
-        items_array
-            .iter()
-            .filter_map(|item| {
-                let name = item.get("name")?.as_str()?.to_string();
-                let kind_str = item.get("kind")?.as_str()?;
-                let kind = Self::map_symbol_kind(kind_str);
-                let uri_str = item.get("uri")?.as_str()?;
-                let uri = Url::parse(uri_str).ok()?;
-                let line = item.get("line")?.as_u64()?.saturating_sub(1) as u32;
-                let column = item.get("column").and_then(|c| c.as_u64()).unwrap_or(0) as u32;
+        let error = parse_analyzer_command_request(ExecuteCommandParams {
+            command: analyzer_command_contract().commands.status.id.clone(),
+            arguments: vec![json!({ "verbose": true })],
+            work_done_progress_params: Default::default(),
+        })
+        .expect_err("status takes no arguments");
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+        assert!(error.message.contains("takes no arguments"));
+    }
 
-                Some(CallHierarchyItem {
-                    name,
-                    kind,
-                    uri,
-                    range: Range {
-                        start: Position::new(line, column),
-                        end: Position::new(line, column),
-                    },
-                    selection_range: Range {
-                        start: Position::new(line, column),
-                        end: Position::new(line, column),
-                    },
-                    detail: None,
-                    tags: None,
-                    data: None,
-                })
-            })
-            .collect()
+    #[test]
+    fn parse_analyzer_command_validates_required_payload() {
+        let error = parse_analyzer_command_request(ExecuteCommandParams {
+            command: analyzer_command_contract()
+                .commands
+                .create_and_open_test_target
+                .id
+                .clone(),
+            arguments: vec![json!({
+                "targetUri": "file:///tmp/Test.kt",
+                "targetPath": "/tmp/Test.kt"
+            })],
+            work_done_progress_params: Default::default(),
+        })
+        .expect_err("missing initialContents should fail");
+
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+        assert!(error.message.contains("invalid arguments"));
     }
 
-    fn parse_incoming_calls(&self, result: &Value) -> Vec<CallHierarchyIncomingCall> {
-        let calls_array = match result.get("calls").and_then(|c| c.as_array()) {
-            Some(arr) => arr,
-            None => return Vec::new(),
+    #[test]
+    fn server_capabilities_without_sidecar_are_lexical_only() {
+        let capabilities = server_capabilities(false, &Features::default());
+        assert!(capabilities.document_formatting_provider.is_some());
+        assert!(capabilities.text_document_sync.is_some());
+        assert!(capabilities.hover_provider.is_none());
+        assert!(capabilities.completion_provider.is_none());
+        assert!(capabilities.definition_provider.is_none());
+        assert!(capabilities.folding_range_provider.is_some());
+
+        let capabilities = server_capabilities(true, &Features::default());
+        assert!(capabilities.hover_provider.is_some());
+        let triggers = capabilities
+            .completion_provider
+            .and_then(|completion| completion.trigger_characters)
+            .unwrap_or_default();
+        // `$` completes string template variables and `[` KDoc links.
+        for trigger in [".", ":", "@", "$", "["] {
+            assert!(triggers.iter().any(|t| t == trigger), "{trigger}");
+        }
+    }
+
+    #[test]
+    fn disabled_features_are_not_advertised_and_register_dynamically() {
+        let features = Features {
+            code_lens: false,
+            semantic_tokens: false,
+            ..Features::default()
+        };
+        let capabilities = server_capabilities(true, &features);
+        assert!(capabilities.code_lens_provider.is_none());
+        assert!(capabilities.semantic_tokens_provider.is_none());
+        assert!(capabilities.hover_provider.is_some());
+
+        let client: ClientCapabilities = serde_json::from_value(serde_json::json!({
+            "textDocument": {
+                "codeLens": { "dynamicRegistration": true },
+                "hover": { "dynamicRegistration": false }
+            }
+        }))
+        .unwrap();
+        let mut registrations = FeatureRegistrations {
+            semantic_features: true,
+            dynamic: dynamic_registration_features(&client),
+            advertised: advertised_features(&capabilities),
+            registered: HashSet::new(),
+            unsupported: HashSet::new(),
+            sidecar_unsupported: HashSet::new(),
         };
 
-        calls_array
-            .iter()
-            .filter_map(|call| {
-                let from_obj = call.get("from")?;
-                let name = from_obj.get("name")?.as_str()?.to_string();
-                let kind_str = from_obj.get("kind")?.as_str()?;
-                let kind = Self::map_symbol_kind(kind_str);
-                let uri_str = from_obj.get("uri")?.as_str()?;
-                let uri = Url::parse(uri_str).ok()?;
-                let line = from_obj.get("line")?.as_u64()?.saturating_sub(1) as u32;
-                let column = from_obj.get("column").and_then(|c| c.as_u64()).unwrap_or(0) as u32;
+        // Re-enabling registers only what the client can register dynamically.
+        assert_eq!(
+            feature_registration_changes(&registrations, &Features::default()),
+            FeatureRegistrationChanges {
+                register: vec![Feature::CodeLens],
+                unregister: Vec::new(),
+                static_only: vec![Feature::SemanticTokens],
+            }
+        );
 
-                let from_ranges = call
-                    .get("fromRanges")
-                    .and_then(|r| r.as_array())
-                    .map(|ranges| {
-                        ranges
-                            .iter()
-                            .filter_map(|r| {
-                                let start_line =
-                                    r.get("startLine")?.as_u64()?.saturating_sub(1) as u32;
-                                let start_column = r.get("startColumn")?.as_u64()? as u32;
-                                let end_line = r.get("endLine")?.as_u64()?.saturating_sub(1) as u32;
-                                let end_column = r.get("endColumn")?.as_u64()? as u32;
+        let registration = feature_registration(
+            Feature::CodeLens,
+            &serde_json::to_value(server_capabilities(true, &Features::default())).unwrap(),
+        );
+        assert_eq!(registration.method, "textDocument/codeLens");
+        assert_eq!(
+            registration.register_options,
+            Some(serde_json::json!({ "resolveProvider": false, "documentSelector": null }))
+        );
 
-                                Some(Range {
-                                    start: Position::new(start_line, start_column),
-                                    end: Position::new(end_line, end_column),
-                                })
-                            })
-                            .collect()
-                    })
-                    .unwrap_or_default();
+        // Disabling again unregisters it; statically advertised hover stays.
+        registrations.registered.insert(Feature::CodeLens);
+        let disabled = Features {
+            code_lens: false,
+            hover: false,
+            ..Features::default()
+        };
+        assert_eq!(
+            feature_registration_changes(&registrations, &disabled),
+            FeatureRegistrationChanges {
+                register: Vec::new(),
+                unregister: vec![Feature::CodeLens],
+                static_only: vec![Feature::Hover, Feature::SemanticTokens],
+            }
+        );
+        assert_eq!(
+            feature_unregistration(Feature::CodeLens).id,
+            registration.id
+        );
 
-                Some(CallHierarchyIncomingCall {
-                    from: CallHierarchyItem {
-                        name,
-                        kind,
-                        uri,
-                        range: Range {
-                            start: Position::new(line, column),
-                            end: Position::new(line, column),
-                        },
-                        selection_range: Range {
-                            start: Position::new(line, column),
-                            end: Position::new(line, column),
-                        },
-                        detail: None,
-                        tags: None,
-                        data: None,
-                    },
-                    from_ranges,
-                })
-            })
-            .collect()
+        // A feature the sidecar cannot serve is withdrawn though enabled.
+        registrations.sidecar_unsupported.insert(Feature::CodeLens);
+        assert_eq!(
+            feature_registration_changes(&registrations, &Features::default()),
+            FeatureRegistrationChanges {
+                register: Vec::new(),
+                unregister: vec![Feature::CodeLens],
+                static_only: vec![Feature::SemanticTokens],
+            }
+        );
     }
 
-    fn parse_type_hierarchy_items(&self, result: &Value) -> Vec<TypeHierarchyItem> {
-        let items_array = result
-            .get("items")
-            .or_else(|| result.get("supertypes"))
-            .and_then(|i| i.as_array());
+    #[test]
+    fn is_excluded_uri_matches_default_excludes() {
+        let config = Config::default();
+        let excludes = ExcludeMatcher::new(
+            &config.exclude_paths,
+            Some(Path::new("/project")),
+            &[PathBuf::from("/project/build/generated/ksp/main/kotlin")],
+        );
+        let output = Url::parse("file:///project/build/generated/Foo.kt").unwrap();
+        let generated =
+            Url::parse("file:///project/build/generated/ksp/main/kotlin/Dao_Impl.kt").unwrap();
+        let source = Url::parse("file:///project/src/main/kotlin/Foo.kt").unwrap();
+
+        assert!(is_excluded_uri(&excludes, &output));
+        assert!(!is_excluded_uri(&excludes, &generated));
+        assert!(!is_excluded_uri(&excludes, &source));
+        assert!(!is_excluded_uri(
+            &excludes,
+            &Url::parse("untitled:Untitled-1").unwrap()
+        ));
+    }
 
-        let items_array = match items_array {
-            Some(arr) => arr,
-            None => return Vec::new(),
-        };
+    #[test]
+    fn gradle_scripts_are_analyzed_once_the_script_classpath_is_known() {
+        let script = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../tests/fixtures/kotlin-scripts/gradle/build.gradle.kts");
+        let uri = Url::from_file_path(&script).unwrap();
+        let mut model = ProjectModel::no_build_system(PathBuf::from("/project"));
+        assert!(skips_gradle_script_analysis(&uri, None));
+        assert!(skips_gradle_script_analysis(&uri, Some(&model)));
+
+        model.script_classpath = vec![PathBuf::from("/gradle/lib/gradle-api.jar")];
+        assert!(!skips_gradle_script_analysis(&uri, Some(&model)));
+        let precompiled =
+            Url::parse("file:///project/buildSrc/src/main/kotlin/conventions.kts").unwrap();
+        assert!(skips_gradle_script_analysis(&precompiled, Some(&model)));
+
+        let text = std::fs::read_to_string(&script).unwrap();
+        let params = document_sync_params(&uri, 1, &text, Some(&model));
+        assert_eq!(params["scriptKind"], "gradle");
+        assert_eq!(
+            params["scriptClasspath"],
+            json!(["/gradle/lib/gradle-api.jar"])
+        );
+    }
 
-        items_array
-            .iter()
-            .filter_map(|item| {
-                let name = item.get("name")?.as_str()?.to_string();
-                let kind_str = item.get("kind")?.as_str()?;
-                let kind = Self::map_symbol_kind(kind_str);
-                let uri_str = item.get("uri")?.as_str()?;
-                let uri = Url::parse(uri_str).ok()?;
-                let line = item.get("line")?.as_u64()?.saturating_sub(1) as u32;
-                let column = item.get("column").and_then(|c| c.as_u64()).unwrap_or(0) as u32;
+    #[tokio::test]
+    async fn empty_configuration_change_pulls_settings_per_folder() {
+        use futures::{SinkExt, StreamExt};
+        use tower_service::Service;
+
+        let (mut service, mut socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let request = tower_lsp::jsonrpc::Request::build("initialize")
+            .id(1)
+            .params(json!({
+                "capabilities": { "workspace": { "configuration": true } },
+                "rootUri": "file:///work/app",
+                "workspaceFolders": [
+                    { "uri": "file:///work/app", "name": "app" },
+                    { "uri": "file:///work/lib", "name": "lib" }
+                ]
+            }))
+            .finish();
+        std::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        service.call(request).await.unwrap().unwrap();
+        let server = service.inner().clone();
 
-                Some(TypeHierarchyItem {
-                    name,
-                    kind,
-                    uri,
-                    range: Range {
-                        start: Position::new(line, column),
-                        end: Position::new(line, column),
-                    },
-                    selection_range: Range {
-                        start: Position::new(line, column),
-                        end: Position::new(line, column),
-                    },
-                    detail: None,
-                    tags: None,
-                    data: None,
-                })
+        server
+            .did_change_configuration(DidChangeConfigurationParams {
+                settings: Value::Null,
             })
-            .collect()
+            .await;
+        let pull = loop {
+            let message = tokio::time::timeout(Duration::from_secs(10), socket.next())
+                .await
+                .unwrap()
+                .unwrap();
+            if message.method() == "workspace/configuration" {
+                break message;
+            }
+        };
+        let scopes: Vec<_> = pull.params().unwrap()["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["scopeUri"].clone())
+            .collect();
+        assert_eq!(
+            scopes,
+            vec![
+                Value::Null,
+                json!("file:///work/app/"),
+                json!("file:///work/lib/")
+            ]
+        );
+        let answer = json!([
+            { "maxFileSizeBytes": 100 },
+            { "enableLintDiagnostics": true },
+            { "maxFileSizeBytes": 200 }
+        ]);
+        socket
+            .send(tower_lsp::jsonrpc::Response::from_ok(
+                pull.id().unwrap().clone(),
+                answer,
+            ))
+            .await
+            .unwrap();
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while server.config.lock().await.max_file_size_bytes != 100 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        // The project root's folder settles the session-wide configuration.
+        assert!(server.config.lock().await.enable_lint_diagnostics);
+        let app = Url::parse("file:///work/app/src/Main.kt").unwrap();
+        let lib = Url::parse("file:///work/lib/src/Lib.kt").unwrap();
+        assert_eq!(server.config_for(&app).await.max_file_size_bytes, 100);
+        assert_eq!(server.config_for(&lib).await.max_file_size_bytes, 200);
+        assert!(!server.config_for(&lib).await.enable_lint_diagnostics);
     }
 
-    fn map_symbol_kind(kind: &str) -> SymbolKind {
-        match kind {
-            "class" => SymbolKind::CLASS,
-            "interface" => SymbolKind::INTERFACE,
-            "function" | "method" => SymbolKind::FUNCTION,
-            "property" | "field" => SymbolKind::PROPERTY,
-            "variable" | "local" => SymbolKind::VARIABLE,
-            "enum" => SymbolKind::ENUM,
-            "enumMember" => SymbolKind::ENUM_MEMBER,
-            "module" | "package" => SymbolKind::MODULE,
-            "constructor" => SymbolKind::CONSTRUCTOR,
-            "constant" => SymbolKind::CONSTANT,
-            "object" => SymbolKind::OBJECT,
-            _ => SymbolKind::VARIABLE,
+    #[test]
+    fn pulled_configuration_takes_precedence_over_initialization_options() {
+        let initialization = json!({
+            "formattingTool": "ktlint",
+            "sidecarMaxMemory": "1g",
+            "features": { "hover": false, "inlayHints": false }
+        });
+        let global = json!({
+            "sidecarMaxMemory": "2g",
+            "features": { "inlayHints": true },
+            "analysisScope": "openFiles"
+        });
+        // A client answering with all its settings instead of the section.
+        let folder = json!({
+            "kotlin-analyzer": { "analysisScope": "project", "formattingTool": null }
+        });
+
+        let merged = merged_settings(&initialization, &[global, folder]);
+        let config: Config = serde_json::from_value(merged).unwrap();
+        assert_eq!(config.formatting_tool, FormattingTool::Ktlint);
+        assert_eq!(config.sidecar_max_memory, "2g");
+        assert_eq!(config.analysis_scope, AnalysisScope::Project);
+        assert!(!config.features.is_enabled(Feature::Hover));
+        assert!(config.features.is_enabled(Feature::InlayHints));
+
+        // Nothing pulled and no initialization options: defaults.
+        let config: Config =
+            serde_json::from_value(merged_settings(&Value::Null, &[Value::Null])).unwrap();
+        assert_eq!(config.sidecar_max_memory, "512m");
+
+        let client: ClientCapabilities =
+            serde_json::from_value(json!({ "workspace": { "configuration": true } })).unwrap();
+        assert!(ClientSupport::from_capabilities(&client).workspace_configuration);
+        assert!(!ClientSupport::default().workspace_configuration);
+    }
+
+    #[test]
+    fn client_support_follows_declared_capabilities() {
+        let client: ClientCapabilities = serde_json::from_value(json!({
+            "textDocument": {
+                "hover": { "contentFormat": ["plaintext"] },
+                "completion": {},
+                "signatureHelp": {
+                    "signatureInformation": { "documentationFormat": ["markdown", "plaintext"] }
+                }
+            },
+            "workspace": {
+                "symbol": { "resolveSupport": { "properties": ["location.range"] } }
+            }
+        }))
+        .unwrap();
+        let support = ClientSupport::from_capabilities(&client);
+        assert!(!support.hover_markdown);
+        assert!(support.signature_markdown);
+        assert!(support.workspace_symbol_resolve);
+        assert!(!ClientSupport::default().workspace_symbol_resolve);
+        assert!(!support.unsupported.contains(&Feature::Hover));
+        assert!(!support.unsupported.contains(&Feature::Completion));
+        assert!(support.unsupported.contains(&Feature::InlayHints));
+        assert!(support.unsupported.contains(&Feature::SemanticTokens));
+
+        let mut capabilities = server_capabilities(true, &Features::default());
+        for &feature in &support.unsupported {
+            remove_feature(&mut capabilities, feature);
         }
+        assert!(capabilities.hover_provider.is_some());
+        assert!(capabilities.inlay_hint_provider.is_none());
+
+        let hover = markup_content("```kotlin\nfun main(): Unit\n```\n\nEntry point.", false);
+        assert_eq!(hover.kind, MarkupKind::PlainText);
+        assert_eq!(hover.value, "fun main(): Unit\n\nEntry point.");
+
+        let mut signature = SignatureInformation {
+            label: "foo(a: Int)".into(),
+            documentation: Some(Documentation::MarkupContent(markup_content(
+                "```kotlin\nfoo\n```",
+                true,
+            ))),
+            parameters: None,
+            active_parameter: None,
+        };
+        plaintext_signature_documentation(&mut signature);
+        assert_eq!(
+            signature.documentation,
+            Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::PlainText,
+                value: "foo".into(),
+            }))
+        );
     }
-}
 
-fn show_document_acknowledged(result: Option<ShowDocumentResult>) -> bool {
-    match result {
-        Some(result) => result.success,
-        None => true,
-    }
-}
+    #[test]
+    fn sidecar_project_args_convert_model_paths() {
+        assert_eq!(
+            SidecarProjectArgs::from_model(None, None),
+            SidecarProjectArgs::default()
+        );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
+        let mut model = ProjectModel::no_build_system(PathBuf::from("/project"));
+        model.classpath = vec![PathBuf::from("/libs/a.jar")];
+        model.source_roots = vec![PathBuf::from("/project/src/main/kotlin")];
+        model.generated_source_roots = vec![PathBuf::from("/project/build/generated/ksp")];
+        model.classpath_sources = vec![PathBuf::from("/libs/a-sources.jar")];
+        model.kotlin_version = Some("2.1".into());
+        let args = SidecarProjectArgs::from_model(Some(&model), None);
+        assert_eq!(
+            args.compiler_flags,
+            vec!["-language-version=2.1", "-api-version=2.1"]
+        );
+        assert_eq!(args.classpath, vec!["/libs/a.jar"]);
+        assert_eq!(args.source_roots, vec!["/project/src/main/kotlin"]);
+        assert_eq!(
+            args.generated_source_roots,
+            vec!["/project/build/generated/ksp"]
+        );
+        assert_eq!(args.classpath_sources, vec!["/libs/a-sources.jar"]);
+    }
 
     #[test]
-    fn parse_code_actions_preserves_command_payloads() {
-        let result = json!({
-            "actions": [
-                {
-                    "title": "Open existing test",
-                    "kind": "quickfix",
-                    "command": {
-                        "title": "Open existing test",
-                        "command": "kotlin-analyzer.openTestTarget",
-                        "arguments": [
-                            {
-                                "targetUri": "file:///tmp/Test.kt",
-                                "selection": {
-                                    "startLine": 3,
-                                    "startCharacter": 1,
-                                    "endLine": 3,
-                                    "endCharacter": 5
-                                }
-                            }
-                        ]
-                    }
-                }
-            ]
-        });
+    fn parse_analyzer_command_accepts_reanalyze_without_arguments() {
+        let request = parse_analyzer_command_request(ExecuteCommandParams {
+            command: analyzer_command_contract().commands.reanalyze.id.clone(),
+            arguments: vec![],
+            work_done_progress_params: Default::default(),
+        })
+        .expect("reanalyze takes no arguments");
 
-        let actions = parse_code_actions_result(&result);
-        assert_eq!(actions.len(), 1);
+        assert_eq!(request, AnalyzerCommandRequest::Reanalyze);
+    }
 
-        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
-            panic!("expected code action");
+    #[test]
+    fn signature_help_retrigger_keeps_shown_overload() {
+        let signature = |label: &str| SignatureInformation {
+            label: label.to_string(),
+            documentation: None,
+            parameters: None,
+            active_parameter: None,
+        };
+        let shown = SignatureHelp {
+            signatures: vec![signature("f(a: Int)"), signature("f(a: Int, b: String)")],
+            active_signature: Some(1),
+            active_parameter: Some(0),
+        };
+        let context = SignatureHelpContext {
+            trigger_kind: SignatureHelpTriggerKind::TRIGGER_CHARACTER,
+            trigger_character: Some(",".to_string()),
+            is_retrigger: true,
+            active_signature_help: Some(shown),
         };
 
-        let command = action
-            .command
-            .as_ref()
-            .expect("command should be preserved");
-        assert_eq!(command.command, "kotlin-analyzer.openTestTarget");
+        let params = signature_help_context(Some(&context));
+        assert_eq!(params["triggerKind"], "triggerCharacter");
+        assert_eq!(params["isRetrigger"], true);
+        assert_eq!(params["activeSignature"], "f(a: Int, b: String)");
+        assert_eq!(signature_help_context(None), Value::Null);
+
+        // The sidecar reordered the candidates; the shown overload stays active.
+        let results = vec![signature("f(a: Int, b: String)"), signature("f(a: Int)")];
         assert_eq!(
-            command
-                .arguments
-                .as_ref()
-                .and_then(|args| args.first())
-                .cloned(),
-            Some(json!({
-                "targetUri": "file:///tmp/Test.kt",
-                "selection": {
-                    "startLine": 3,
-                    "startCharacter": 1,
-                    "endLine": 3,
-                    "endCharacter": 5
-                }
-            }))
+            retriggered_active_signature(&results, Some(&context)),
+            Some(0)
         );
-        assert!(
-            action.edit.is_none(),
-            "command-only actions should not gain empty edits"
+
+        let first_trigger = SignatureHelpContext {
+            is_retrigger: false,
+            ..context
+        };
+        assert_eq!(
+            retriggered_active_signature(&results, Some(&first_trigger)),
+            None
         );
     }
 
-    #[test]
-    fn parse_code_actions_keeps_edit_only_actions_compatible() {
-        let result = json!({
-            "actions": [
+    #[tokio::test]
+    async fn parse_completion_items_keeps_auto_import_edits() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner();
+
+        let items = server.parse_completion_items(&json!({
+            "items": [
                 {
-                    "title": "Add import",
-                    "kind": "quickfix",
-                    "edits": [
+                    "label": "LocalDate",
+                    "kind": "class",
+                    "detail": "java.time.LocalDate",
+                    "insertText": "LocalDate",
+                    "additionalTextEdits": [
                         {
-                            "uri": "file:///tmp/Test.kt",
-                            "range": {
-                                "startLine": 1,
-                                "startColumn": 0,
-                                "endLine": 1,
-                                "endColumn": 0
-                            },
-                            "newText": "import kotlin.collections.List\n"
-                        }
+                            "newText": "import java.time.LocalDate\n",
+                            "line": 3, "column": 0, "endLine": 3, "endColumn": 0
+                        },
+                        { "newText": "\n", "line": 4 },
+                        { "line": 5, "column": 0 }
                     ]
-                }
+                },
+                { "label": "listOf", "additionalTextEdits": [] }
             ]
-        });
+        }));
 
-        let actions = parse_code_actions_result(&result);
-        assert_eq!(actions.len(), 1);
+        let edits = items[0].additional_text_edits.as_ref().unwrap();
+        assert_eq!(
+            edits,
+            &vec![
+                TextEdit {
+                    range: Range::new(Position::new(2, 0), Position::new(2, 0)),
+                    new_text: "import java.time.LocalDate\n".to_string(),
+                },
+                TextEdit {
+                    range: Range::new(Position::new(3, 0), Position::new(3, 0)),
+                    new_text: "\n".to_string(),
+                },
+            ]
+        );
+        assert_eq!(items[1].additional_text_edits, None);
+    }
 
-        let CodeActionOrCommand::CodeAction(action) = &actions[0] else {
-            panic!("expected code action");
-        };
+    #[tokio::test]
+    async fn parse_completion_items_replaces_typed_prefix() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner();
 
-        let edit = action.edit.as_ref().expect("edit should be preserved");
-        let changes = edit.changes.as_ref().expect("changes should exist");
-        assert_eq!(changes.len(), 1);
-        assert!(
-            action.command.is_none(),
-            "edit-only action should remain command-free"
+        // `pri|` on line 3: the edit replaces columns 4..7 with `println`.
+        let items = server.parse_completion_items(&json!({
+            "items": [
+                {
+                    "label": "println",
+                    "insertText": "println(",
+                    "textEdit": {
+                        "range": { "startLine": 3, "startColumn": 4, "endLine": 3, "endColumn": 7 },
+                        "newText": "println("
+                    }
+                },
+                { "label": "print", "insertText": "print(" },
+                { "label": "private", "textEdit": { "newText": "private" } }
+            ]
+        }));
+
+        assert_eq!(
+            items[0].text_edit,
+            Some(CompletionTextEdit::Edit(TextEdit {
+                range: Range::new(Position::new(2, 4), Position::new(2, 7)),
+                new_text: "println(".to_string(),
+            }))
+        );
+        assert_eq!(items[1].text_edit, None);
+        assert_eq!(items[1].insert_text.as_deref(), Some("print("));
+        assert_eq!(items[2].text_edit, None);
+    }
+
+    #[tokio::test]
+    async fn parse_call_hierarchy_items_reads_detail_and_ranges() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner();
+
+        let items = server.parse_call_hierarchy_items(&json!({
+            "items": [
+                {
+                    "name": "greet", "kind": "function", "uri": "file:///src/Clean.kt",
+                    "detail": "Greeter.greet(): String",
+                    "line": 4, "column": 4, "endLine": 6, "endColumn": 5,
+                    "selectionLine": 4, "selectionColumn": 8,
+                    "selectionEndLine": 4, "selectionEndColumn": 13
+                },
+                { "name": "main", "kind": "function", "uri": "file:///src/Clean.kt", "line": 9 }
+            ]
+        }));
+        assert_eq!(items[0].detail.as_deref(), Some("Greeter.greet(): String"));
+        assert_eq!(
+            items[0].range,
+            Range::new(Position::new(3, 4), Position::new(5, 5))
+        );
+        assert_eq!(
+            items[0].selection_range,
+            Range::new(Position::new(3, 8), Position::new(3, 13))
+        );
+        assert_eq!(items[1].detail, None);
+        assert_eq!(
+            items[1].selection_range,
+            Range::new(Position::new(8, 0), Position::new(8, 0))
         );
     }
 
+    #[tokio::test]
+    async fn parse_signatures_keeps_per_overload_active_parameter() {
+        let (service, _socket) = tower_lsp::LspService::new(KotlinLanguageServer::new);
+        let server = service.inner();
+
+        // foo(1, |): the cursor follows the comma, on the second argument.
+        let positional = server.parse_signatures(&json!({
+            "signatures": [{
+                "label": "foo(a: Int, b: Int): Unit",
+                "parameters": [{ "label": "a: Int" }, { "label": "b: Int" }],
+                "activeParameter": 1
+            }]
+        }));
+        assert_eq!(positional[0].active_parameter, Some(1));
+
+        // foo(b = 1, a = |): the named argument picks `a` in each overload,
+        // and overloads without an `a` parameter have no active parameter.
+        let named = server.parse_signatures(&json!({
+            "signatures": [
+                {
+                    "label": "foo(a: Int, b: Int): Unit",
+                    "parameters": [{ "label": "a: Int" }, { "label": "b: Int" }],
+                    "activeParameter": 0
+                },
+                {
+                    "label": "foo(b: Int, c: Int, a: Int): Unit",
+                    "parameters": [
+                        { "label": "b: Int" },
+                        { "label": "c: Int" },
+                        { "label": "a: Int" }
+                    ],
+                    "activeParameter": 2
+                },
+                {
+                    "label": "foo(b: Int): Unit",
+                    "parameters": [{ "label": "b: Int" }]
+                }
+            ]
+        }));
+        let active: Vec<_> = named.iter().map(|sig| sig.active_parameter).collect();
+        assert_eq!(active, vec![Some(0), Some(2), None]);
+    }
+
     #[test]
-    fn parse_analyzer_command_rejects_unknown_command_ids() {
-        let error = parse_analyzer_command_request(ExecuteCommandParams {
-            command: "kotlin-analyzer.unsupported".to_string(),
-            arguments: vec![json!({})],
+    fn parse_change_signature_command_keeps_parameter_order() {
+        let request = parse_analyzer_command_request(ExecuteCommandParams {
+            command: analyzer_command_contract()
+                .commands
+                .change_signature
+                .id
+                .clone(),
+            arguments: vec![json!({
+                "uri": "file:///tmp/Main.kt",
+                "position": { "line": 2, "character": 4 },
+                "parameters": ["b", "a"]
+            })],
             work_done_progress_params: Default::default(),
         })
-        .expect_err("unsupported command should fail");
+        .expect("changeSignature arguments should parse");
+        assert_eq!(
+            request,
+            AnalyzerCommandRequest::ChangeSignature(ChangeSignatureArgs {
+                uri: "file:///tmp/Main.kt".to_string(),
+                position: Position::new(2, 4),
+                parameters: vec!["b".to_string(), "a".to_string()],
+            })
+        );
 
+        let error = parse_analyzer_command_request(ExecuteCommandParams {
+            command: "kotlin-analyzer.changeSignature".to_string(),
+            arguments: vec![json!({
+                "uri": "file:///tmp/Main.kt",
+                "position": { "line": 2, "character": 4 }
+            })],
+            work_done_progress_params: Default::default(),
+        })
+        .expect_err("parameters are required");
         assert_eq!(error.code, ErrorCode::InvalidParams);
-        assert!(error.message.contains("unsupported analyzer command"));
     }
 
     #[test]
-    fn parse_analyzer_command_validates_required_payload() {
-        let error = parse_analyzer_command_request(ExecuteCommandParams {
+    fn self_test_summary_flags_failed_and_empty_steps() {
+        let request = parse_analyzer_command_request(ExecuteCommandParams {
+            command: analyzer_command_contract().commands.self_test.id.clone(),
+            arguments: vec![],
+            work_done_progress_params: Default::default(),
+        })
+        .expect("selfTest takes no arguments");
+        assert_eq!(request, AnalyzerCommandRequest::SelfTest);
+
+        let (message_type, summary) = self_test_summary(&[
+            ("hover", SelfTestOutcome::Ok),
+            ("completion", SelfTestOutcome::Disabled),
+        ]);
+        assert_eq!(message_type, MessageType::INFO);
+        assert_eq!(
+            summary,
+            "kotlin-analyzer self-test passed (hover: ok, completion: disabled)"
+        );
+
+        let (message_type, summary) = self_test_summary(&[
+            ("hover", SelfTestOutcome::Ok),
+            ("definition", SelfTestOutcome::Empty),
+            ("diagnostics", SelfTestOutcome::TimedOut),
+            (
+                "completion",
+                SelfTestOutcome::Failed("sidecar crashed".into()),
+            ),
+        ]);
+        assert_eq!(message_type, MessageType::WARNING);
+        assert_eq!(
+            summary,
+            "kotlin-analyzer self-test found problems (hover: ok, definition: no result, \
+             diagnostics: timed out, completion: failed (sidecar crashed))"
+        );
+    }
+
+    #[test]
+    fn diagnostic_columns_count_utf16_units() {
+        // The emoji is two UTF-16 units (four bytes).
+        let text = "fun main() {\n    val s = \"😀\" + x\n}\n";
+        let diagnostics = parse_diagnostics(
+            &json!({
+                "diagnostics": [
+                    { "severity": "ERROR", "message": "unresolved", "line": 2, "column": 19, "endLine": 2, "endColumn": 20 },
+                    { "severity": "WARNING", "message": "emoji", "line": 2, "column": 13 },
+                    { "severity": "WARNING", "message": "split pair", "line": 2, "column": 14, "endLine": 2, "endColumn": 40 }
+                ]
+            }),
+            Some(text),
+        );
+        let ranges: Vec<_> = diagnostics.iter().map(|d| d.range).collect();
+        assert_eq!(
+            ranges,
+            vec![
+                Range::new(Position::new(1, 19), Position::new(1, 20)),
+                Range::new(Position::new(1, 13), Position::new(1, 15)),
+                Range::new(Position::new(1, 13), Position::new(1, 20)),
+            ]
+        );
+        let x = position_offset(text, ranges[0].start);
+        assert_eq!(&text[x..x + 1], "x");
+    }
+
+    #[test]
+    fn diagnostic_without_end_covers_the_identifier() {
+        let text = "fun main() {\n    printn(\"hi\") + 1\n}\n";
+        let result = json!({
+            "diagnostics": [
+                { "severity": "ERROR", "message": "unresolved", "line": 2, "column": 4 },
+                { "severity": "ERROR", "message": "operator", "line": 2, "column": 17 }
+            ]
+        });
+        let naive: Vec<_> = parse_diagnostics(&result, None)
+            .iter()
+            .map(|d| d.range)
+            .collect();
+        let widened: Vec<_> = parse_diagnostics(&result, Some(text))
+            .iter()
+            .map(|d| d.range)
+            .collect();
+        assert_eq!(
+            naive[0],
+            Range::new(Position::new(1, 4), Position::new(1, 5))
+        );
+        assert_eq!(
+            widened[0],
+            Range::new(Position::new(1, 4), Position::new(1, 10))
+        );
+        // Off an identifier, the range stays one character wide.
+        assert_eq!(naive[1], widened[1]);
+    }
+
+    #[test]
+    fn unresolved_generated_classes_are_warnings() {
+        let diagnostics = parse_diagnostics(
+            &json!({
+                "diagnostics": [
+                    { "severity": "ERROR", "message": "Unresolved reference 'ActivityMainBinding'.", "line": 1, "code": "UNRESOLVED_REFERENCE" },
+                    { "severity": "ERROR", "message": "Unresolved reference: DaggerAppComponent", "line": 2, "code": "UNRESOLVED_REFERENCE" },
+                    { "severity": "ERROR", "message": "Unresolved reference 'Bindings'.", "line": 3, "code": "UNRESOLVED_REFERENCE" },
+                    { "severity": "ERROR", "message": "Type mismatch: ActivityMainBinding", "line": 4, "code": "TYPE_MISMATCH" }
+                ]
+            }),
+            None,
+        );
+
+        let generated = GeneratedClassMatcher::new(&Config::default().generated_class_patterns);
+        let demoted = demote_generated_references(diagnostics, &generated);
+        let severities: Vec<_> = demoted.iter().map(|d| d.severity).collect();
+        assert_eq!(
+            severities,
+            vec![
+                Some(DiagnosticSeverity::WARNING),
+                Some(DiagnosticSeverity::WARNING),
+                Some(DiagnosticSeverity::ERROR),
+                Some(DiagnosticSeverity::ERROR),
+            ]
+        );
+        assert_eq!(
+            demote_generated_references(demoted.clone(), &GeneratedClassMatcher::new(&[])),
+            demoted,
+            "no patterns leaves diagnostics alone"
+        );
+    }
+
+    #[test]
+    fn apply_severity_overrides_remaps_and_suppresses_by_code() {
+        let diagnostics = parse_diagnostics(
+            &json!({
+                "diagnostics": [
+                    { "severity": "WARNING", "message": "unchecked cast", "line": 1, "code": "UNCHECKED_CAST" },
+                    { "severity": "WARNING", "message": "unused", "line": 2, "code": "UNUSED_VARIABLE" },
+                    { "severity": "WARNING", "message": "redundant", "line": 3, "code": "REDUNDANT_NULLABLE" },
+                    { "severity": "ERROR", "message": "unresolved", "line": 4, "code": "UNRESOLVED_REFERENCE" }
+                ]
+            }),
+            None,
+        );
+        let overrides = HashMap::from([
+            ("UNCHECKED_CAST".to_string(), "error".to_string()),
+            ("UNUSED_VARIABLE".to_string(), "Hint".to_string()),
+            ("REDUNDANT_NULLABLE".to_string(), "none".to_string()),
+            ("UNRESOLVED_REFERENCE".to_string(), "loud".to_string()),
+        ]);
+
+        let remapped = apply_severity_overrides(diagnostics, &overrides);
+        let severities: Vec<_> = remapped
+            .iter()
+            .map(|d| (d.message.as_str(), d.severity))
+            .collect();
+        assert_eq!(
+            severities,
+            vec![
+                ("unchecked cast", Some(DiagnosticSeverity::ERROR)),
+                ("unused", Some(DiagnosticSeverity::HINT)),
+                ("unresolved", Some(DiagnosticSeverity::ERROR)),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_analyzer_command_accepts_diagnostic_navigation() {
+        let request = parse_analyzer_command_request(ExecuteCommandParams {
             command: analyzer_command_contract()
                 .commands
-                .create_and_open_test_target
+                .prev_diagnostic
                 .id
                 .clone(),
             arguments: vec![json!({
-                "targetUri": "file:///tmp/Test.kt",
-                "targetPath": "/tmp/Test.kt"
+                "uri": "file:///project/src/Foo.kt",
+                "position": { "line": 4, "character": 2 }
             })],
             work_done_progress_params: Default::default(),
         })
-        .expect_err("missing initialContents should fail");
+        .expect("prevDiagnostic takes uri and position");
 
-        assert_eq!(error.code, ErrorCode::InvalidParams);
-        assert!(error.message.contains("invalid arguments"));
+        assert_eq!(
+            request,
+            AnalyzerCommandRequest::PrevDiagnostic(DiagnosticNavigationArgs {
+                uri: "file:///project/src/Foo.kt".into(),
+                position: Position::new(4, 2),
+            })
+        );
+    }
+
+    #[test]
+    fn adjacent_diagnostic_wraps_around_file_ends() {
+        let diagnostic = |line| Diagnostic {
+            range: Range::new(Position::new(line, 4), Position::new(line, 9)),
+            ..Default::default()
+        };
+        let diagnostics = vec![diagnostic(10), diagnostic(2), diagnostic(6)];
+        let start_line = |range: Option<Range>| range.map(|r| r.start.line);
+
+        let cursor = Position::new(6, 4);
+        assert_eq!(
+            start_line(adjacent_diagnostic(&diagnostics, cursor, true)),
+            Some(10)
+        );
+        assert_eq!(
+            start_line(adjacent_diagnostic(&diagnostics, cursor, false)),
+            Some(2)
+        );
+
+        let past_end = Position::new(20, 0);
+        assert_eq!(
+            start_line(adjacent_diagnostic(&diagnostics, past_end, true)),
+            Some(2)
+        );
+        let before_start = Position::new(0, 0);
+        assert_eq!(
+            start_line(adjacent_diagnostic(&diagnostics, before_start, false)),
+            Some(10)
+        );
+
+        assert_eq!(adjacent_diagnostic(&[], cursor, true), None);
+    }
+
+    #[test]
+    fn parse_analyzer_command_accepts_status_without_arguments() {
+        let request = parse_analyzer_command_request(ExecuteCommandParams {
+            command: analyzer_command_contract().commands.status.id.clone(),
+            arguments: vec![],
+            work_done_progress_params: Default::default(),
+        })
+        .expect("status takes no arguments");
+
+        assert_eq!(request, AnalyzerCommandRequest::Status);
     }
 
     #[test]
@@ -3244,6 +10543,14 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn take_within_counts_down_across_batches() {
+        let remaining = AtomicUsize::new(3);
+        assert_eq!(take_within(vec![1, 2], &remaining), vec![1, 2]);
+        assert_eq!(take_within(vec![3, 4], &remaining), vec![3]);
+        assert!(take_within(vec![5], &remaining).is_empty());
+    }
+
     #[test]
     fn response_version_handles_absent_and_non_numeric_values() {
         assert_eq!(response_version(&json!({ "version": 3 })), Some(3));
@@ -1,6 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use tower_lsp::lsp_types::{Diagnostic, Url};
+use tower_lsp::lsp_types::{Diagnostic, Position, TextDocumentContentChangeEvent, Url};
+
+use crate::incremental::{self, EditRegion};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DocumentKind {
@@ -77,6 +79,11 @@ pub struct DocumentStore {
     /// Cached diagnostics per URI — persists across didClose/didOpen cycles
     /// so that diagnostics survive tab switches in Zed.
     diagnostics: HashMap<Url, Vec<Diagnostic>>,
+    /// The text each entry of `diagnostics` was computed for, when it came
+    /// from analyzing an open document.
+    analyzed_text: HashMap<Url, String>,
+    /// Open documents too large for semantic analysis.
+    lexical_only: HashSet<Url>,
 }
 
 #[derive(Debug, Clone)]
@@ -98,17 +105,33 @@ impl DocumentStore {
         );
     }
 
-    pub fn change(&mut self, uri: &Url, text: String, version: i32) -> bool {
-        if let Some(doc) = self.documents.get_mut(uri) {
-            doc.text = text;
-            doc.version = version;
-            true
-        } else {
-            false
+    /// Applies incremental content changes in order; a change without a
+    /// range replaces the whole text.
+    pub fn apply_changes(
+        &mut self,
+        uri: &Url,
+        changes: Vec<TextDocumentContentChangeEvent>,
+        version: i32,
+    ) -> bool {
+        let Some(doc) = self.documents.get_mut(uri) else {
+            return false;
+        };
+        for change in changes {
+            match change.range {
+                Some(range) => {
+                    let start = position_offset(&doc.text, range.start);
+                    let end = position_offset(&doc.text, range.end).max(start);
+                    doc.text.replace_range(start..end, &change.text);
+                }
+                None => doc.text = change.text,
+            }
         }
+        doc.version = version;
+        true
     }
 
     pub fn close(&mut self, uri: &Url) -> bool {
+        self.lexical_only.remove(uri);
         self.documents.remove(uri).is_some()
     }
 
@@ -130,13 +153,84 @@ impl DocumentStore {
         self.documents.contains_key(uri)
     }
 
+    /// Marks whether a document is lexical-only. Returns true when it was
+    /// not lexical-only before.
+    pub fn set_lexical_only(&mut self, uri: &Url, lexical_only: bool) -> bool {
+        if lexical_only {
+            self.lexical_only.insert(uri.clone())
+        } else {
+            self.lexical_only.remove(uri);
+            false
+        }
+    }
+
+    pub fn is_lexical_only(&self, uri: &Url) -> bool {
+        self.lexical_only.contains(uri)
+    }
+
     pub fn set_diagnostics(&mut self, uri: Url, diags: Vec<Diagnostic>) {
+        self.analyzed_text.remove(&uri);
         self.diagnostics.insert(uri, diags);
     }
 
     pub fn get_diagnostics(&self, uri: &Url) -> Option<&Vec<Diagnostic>> {
         self.diagnostics.get(uri)
     }
+
+    /// Caches diagnostics from analyzing `text`, so the next analysis can
+    /// re-check only what changed since.
+    pub fn set_analysis(&mut self, uri: Url, text: String, diags: Vec<Diagnostic>) {
+        self.analyzed_text.insert(uri.clone(), text);
+        self.diagnostics.insert(uri, diags);
+    }
+
+    /// The small, non-structural edit from the last analyzed text to
+    /// `text`, with the diagnostics published for that analysis.
+    pub fn incremental_baseline(
+        &self,
+        uri: &Url,
+        text: &str,
+    ) -> Option<(EditRegion, Vec<Diagnostic>)> {
+        let edit = incremental::edit_region(self.analyzed_text.get(uri)?, text)?;
+        Some((edit, self.diagnostics.get(uri)?.clone()))
+    }
+}
+
+/// Byte offset of an LSP position (UTF-16 column), clamped to the text.
+pub fn position_offset(text: &str, position: Position) -> usize {
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(position.line as usize)
+        .map(str::len)
+        .sum();
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    let mut column = 0;
+    let within_line = line
+        .char_indices()
+        .find(|(_, c)| {
+            let past = column >= position.character;
+            column += c.len_utf16() as u32;
+            past
+        })
+        .map_or(line.len(), |(i, _)| i);
+    line_start + within_line
+}
+
+/// `position` with its column moved onto a character boundary of its line
+/// in `text`: back to the start of a surrogate pair it splits, or to the
+/// line end when past it.
+pub fn snap_position(text: &str, position: Position) -> Position {
+    let line = text.split('\n').nth(position.line as usize).unwrap_or("");
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    let mut column = 0;
+    for c in line.chars() {
+        let next = column + c.len_utf16() as u32;
+        if next > position.character {
+            break;
+        }
+        column = next;
+    }
+    Position::new(position.line, column)
 }
 
 #[cfg(test)]
@@ -147,6 +241,15 @@ mod tests {
         Url::parse(&format!("file:///{path}")).unwrap()
     }
 
+    /// A change replacing the whole document with `text`.
+    fn full_text(text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
     #[test]
     fn open_and_retrieve() {
         let mut store = DocumentStore::default();
@@ -160,22 +263,51 @@ mod tests {
     }
 
     #[test]
-    fn change_updates_content() {
+    fn apply_changes_edits_ranges_in_order() {
         let mut store = DocumentStore::default();
         let uri = test_uri("test.kt");
-        store.open(uri.clone(), "fun main() {}".into(), 1, DocumentKind::Kotlin);
+        store.open(
+            uri.clone(),
+            "fun main() {\n    val s = \"é\"\n}\n".into(),
+            1,
+            DocumentKind::Kotlin,
+        );
 
-        assert!(store.change(&uri, "fun main() { println() }".into(), 2));
+        let change = |range: Option<tower_lsp::lsp_types::Range>, text: &str| {
+            TextDocumentContentChangeEvent {
+                range,
+                range_length: None,
+                text: text.to_string(),
+            }
+        };
+        let range = |start: (u32, u32), end: (u32, u32)| {
+            Some(tower_lsp::lsp_types::Range::new(
+                Position::new(start.0, start.1),
+                Position::new(end.0, end.1),
+            ))
+        };
+        let changes = vec![
+            // Replaces `"é"`; columns count UTF-16 units.
+            change(range((1, 12), (1, 15)), "1"),
+            change(range((1, 13), (1, 13)), " + 2"),
+        ];
+        assert!(store.apply_changes(&uri, changes, 2));
+        assert_eq!(
+            store.get(&uri).unwrap().text,
+            "fun main() {\n    val s = 1 + 2\n}\n"
+        );
+
+        assert!(store.apply_changes(&uri, vec![change(None, "fun other() {}")], 3));
         let doc = store.get(&uri).unwrap();
-        assert_eq!(doc.text, "fun main() { println() }");
-        assert_eq!(doc.version, 2);
+        assert_eq!(doc.text, "fun other() {}");
+        assert_eq!(doc.version, 3);
     }
 
     #[test]
     fn change_nonexistent_returns_false() {
         let mut store = DocumentStore::default();
         let uri = test_uri("missing.kt");
-        assert!(!store.change(&uri, "text".into(), 1));
+        assert!(!store.apply_changes(&uri, vec![full_text("text")], 1));
     }
 
     #[test]
@@ -222,14 +354,28 @@ mod tests {
         let mut store = DocumentStore::default();
         let uri = test_uri("test.kt");
         store.open(uri.clone(), "v1".into(), 1, DocumentKind::Kotlin);
-        store.change(&uri, "v2".into(), 2);
-        store.change(&uri, "v3".into(), 3);
+        store.apply_changes(&uri, vec![full_text("v2")], 2);
+        store.apply_changes(&uri, vec![full_text("v3")], 3);
 
         let doc = store.get(&uri).unwrap();
         assert_eq!(doc.text, "v3");
         assert_eq!(doc.version, 3);
     }
 
+    #[test]
+    fn lexical_only_is_reported_once_and_cleared_on_close() {
+        let mut store = DocumentStore::default();
+        let uri = test_uri("Generated.kt");
+        store.open(uri.clone(), "big".into(), 1, DocumentKind::Kotlin);
+
+        assert!(store.set_lexical_only(&uri, true));
+        assert!(!store.set_lexical_only(&uri, true));
+        assert!(store.is_lexical_only(&uri));
+
+        store.close(&uri);
+        assert!(!store.is_lexical_only(&uri));
+    }
+
     #[test]
     fn pebble_language_id_takes_precedence() {
         let uri = test_uri("test.kt");
@@ -119,17 +119,111 @@ impl LspTestClient {
         params: Value,
         watched_method: Option<&str>,
     ) -> Result<(Value, Vec<Value>), Box<dyn std::error::Error>> {
-        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
-        let mut captured_requests = Vec::new();
+        let id = self.start_request(method, params)?;
+        self.await_response(id, method, watched_method)
+    }
 
+    /// Send a JSON-RPC request without waiting for its response; returns its id.
+    fn start_request(
+        &mut self,
+        method: &str,
+        params: Value,
+    ) -> Result<i64, Box<dyn std::error::Error>> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
         let request = json!({
             "jsonrpc": "2.0",
             "id": id,
             "method": method,
             "params": params
         });
-
         self.write_message(&request)?;
+        Ok(id)
+    }
+
+    /// Ask the server to cancel the request with the given id.
+    fn cancel_request(&mut self, id: i64) -> Result<(), Box<dyn std::error::Error>> {
+        self.send_notification("$/cancelRequest", json!({ "id": id }))
+    }
+
+    /// Wait for the response to a request sent with `start_request`.
+    fn wait_for_response(&mut self, id: i64) -> Result<Value, Box<dyn std::error::Error>> {
+        self.await_response(id, "request", None)
+            .map(|(response, _)| response)
+    }
+
+    /// Wait for the response to a request sent with `start_request`, also
+    /// returning the notifications with the given method that came before it.
+    fn wait_for_response_with_notifications(
+        &mut self,
+        id: i64,
+        method: &str,
+    ) -> Result<(Value, Vec<Value>), Box<dyn std::error::Error>> {
+        let mut notifications = Vec::new();
+        let deadline = std::time::Instant::now() + Duration::from_secs(30);
+        loop {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            let msg = self
+                .rx
+                .recv_timeout(remaining)
+                .map_err(|_| format!("Timeout waiting for response (id={})", id))?;
+            match (msg.get("id"), msg.get("method")) {
+                (Some(msg_id), None) if msg_id.as_i64() == Some(id) => {
+                    return Ok((msg, notifications));
+                }
+                (Some(msg_id), Some(_)) => {
+                    let reply = json!({
+                        "jsonrpc": "2.0",
+                        "id": msg_id,
+                        "result": server_request_result(&msg)
+                    });
+                    self.write_message(&reply)?;
+                }
+                (None, Some(msg_method)) if msg_method.as_str() == Some(method) => {
+                    notifications.push(msg);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Wait for the server to send a request with the given method and params
+    /// and return it unanswered, so the server-side handler stays blocked on
+    /// the reply. Other server requests are answered as usual.
+    fn wait_for_server_request(
+        &mut self,
+        method: &str,
+        params: &Value,
+        timeout: Duration,
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            let msg = self
+                .rx
+                .recv_timeout(remaining)
+                .map_err(|_| format!("Timeout waiting for server request {}", method))?;
+            let (Some(id), Some(msg_method)) = (msg.get("id"), msg.get("method")) else {
+                continue;
+            };
+            if msg_method.as_str() == Some(method) && msg.get("params") == Some(params) {
+                return Ok(msg);
+            }
+            let reply = json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "result": server_request_result(&msg)
+            });
+            self.write_message(&reply)?;
+        }
+    }
+
+    fn await_response(
+        &mut self,
+        id: i64,
+        method: &str,
+        watched_method: Option<&str>,
+    ) -> Result<(Value, Vec<Value>), Box<dyn std::error::Error>> {
+        let mut captured_requests = Vec::new();
 
         // Read messages until we find the response with our id.
         let deadline = std::time::Instant::now() + Duration::from_secs(30);
@@ -855,6 +949,113 @@ fn test_pebble_template_definition_routes_through_pebble_bridge() {
     assert_eq!(include_target, Some(include_uri.as_str()));
 }
 
+#[test]
+fn test_cancelled_request_answers_with_request_cancelled() {
+    let mut client = LspTestClient::new().expect("Failed to start LSP server");
+    client
+        .initialize()
+        .expect("Failed to initialize LSP server");
+
+    // Reanalyze first asks the client to create a progress token; leaving
+    // that unanswered keeps the request in flight until it is cancelled.
+    let id = client
+        .start_request(
+            "workspace/executeCommand",
+            json!({ "command": "kotlin-analyzer.reanalyze", "arguments": [] }),
+        )
+        .expect("executeCommand should be sent");
+    client
+        .wait_for_server_request(
+            "window/workDoneProgress/create",
+            &json!({ "token": "kotlin-analyzer-reanalyze" }),
+            Duration::from_secs(10),
+        )
+        .expect("reanalyze should ask for a progress token");
+    client.cancel_request(id).expect("cancel should be sent");
+
+    let response = client
+        .wait_for_response(id)
+        .expect("cancelled request should still be answered");
+    assert_eq!(
+        response["error"]["code"],
+        json!(-32800),
+        "expected RequestCancelled, got: {response}"
+    );
+    assert!(
+        response.get("result").is_none(),
+        "a cancelled request must not carry a result: {response}"
+    );
+
+    // The server keeps serving requests after the cancellation.
+    let response = client
+        .send_request(
+            "workspace/executeCommand",
+            json!({ "command": "kotlin-analyzer.status", "arguments": [] }),
+        )
+        .expect("status should succeed after a cancellation");
+    assert!(response.get("result").is_some(), "got: {response}");
+}
+
+#[test]
+fn test_references_stream_partial_results_before_the_response() {
+    let workspace = tempdir().expect("failed to create temporary workspace");
+    let package_dir = workspace.path().join("src/main/kotlin/shop");
+    std::fs::create_dir_all(&package_dir).expect("failed to create package dir");
+    let cart_text = "package shop\n\nclass Cart\n";
+    let cart_path = package_dir.join("Cart.kt");
+    std::fs::write(&cart_path, cart_text).expect("failed to write Cart.kt");
+    for n in 0..5 {
+        std::fs::write(
+            package_dir.join(format!("Use{n}.kt")),
+            format!("package shop\n\nfun use{n}(cart: Cart) = cart\n"),
+        )
+        .expect("failed to write usage file");
+    }
+
+    let mut client = LspTestClient::new().expect("Failed to start LSP server");
+    client
+        .initialize_with_root(&format!("file://{}", workspace.path().display()))
+        .expect("Failed to initialize LSP server");
+    let cart_uri = format!("file://{}", cart_path.display());
+    client
+        .open_document(&cart_uri, cart_text)
+        .expect("Failed to open Cart.kt");
+
+    let id = client
+        .start_request(
+            "textDocument/references",
+            json!({
+                "textDocument": { "uri": cart_uri },
+                "position": { "line": 2, "character": 6 },
+                "context": { "includeDeclaration": true },
+                "partialResultToken": "cart-references"
+            }),
+        )
+        .expect("references should be sent");
+    let (response, progress) = client
+        .wait_for_response_with_notifications(id, "$/progress")
+        .expect("references should be answered");
+
+    let streamed: Vec<&Value> = progress
+        .iter()
+        .filter(|msg| msg["params"]["token"] == json!("cart-references"))
+        .flat_map(|msg| msg["params"]["value"].as_array().into_iter().flatten())
+        .collect();
+    let uris: std::collections::HashSet<&str> = streamed
+        .iter()
+        .filter_map(|location| location["uri"].as_str())
+        .collect();
+    assert!(
+        (0..5).all(|n| uris.iter().any(|uri| uri.ends_with(&format!("Use{n}.kt")))),
+        "every usage should stream before the response, got: {uris:?}"
+    );
+    assert_eq!(
+        response["result"],
+        json!([]),
+        "streamed locations are not repeated in the response: {response}"
+    );
+}
+
 #[test]
 fn test_completion_works() {
     let mut client = LspTestClient::new().expect("Failed to start LSP server");
@@ -5,27 +5,70 @@ import com.google.gson.JsonPrimitive
 
 /**
  * JSON-RPC server that dispatches requests to the CompilerBridge.
- * Runs a single-threaded event loop reading from stdin and writing to stdout.
+ * Runs a single-threaded event loop writing to stdout; a reader thread takes
+ * requests from stdin so `$/cancelRequest` is seen while a request runs.
  */
 class AnalysisServer(
     private val transport: JsonRpcTransport,
     private val bridge: CompilerBridge = CompilerBridge(),
+    /** Set when serving one client of a [SidecarDaemon]; the session then outlives the connection. */
+    private val retainedSession: RetainedSession? = null,
 ) {
     private var running = true
 
+    /** Latest `version` received per document in didOpen/didChange. */
+    private val documentVersions = mutableMapOf<String, Int>()
+
+    /**
+     * Ids of requests read but not finished yet, mapped to whether a
+     * `$/cancelRequest` named them. A cancel for an id not in here (one that
+     * already finished) is dropped, so no id is kept once its request is done.
+     */
+    private val openRequests = java.util.concurrent.ConcurrentHashMap<Long, Boolean>()
+
+    /** Queued by the reader thread when the input ends. */
+    private val endOfInput = JsonRpcRequest(method = "")
+
     /**
      * Starts the event loop. Blocks until shutdown or EOF.
      */
     fun run() {
         System.err.println("AnalysisServer: starting event loop")
 
+        val queue = java.util.concurrent.LinkedBlockingQueue<JsonRpcRequest>()
+        Thread({
+            while (true) {
+                val request = transport.readRequest()
+                if (request == null) {
+                    queue.put(endOfInput)
+                    break
+                }
+                if (request.method == "\$/cancelRequest") {
+                    request.params?.get("id")?.asLong?.let { openRequests.computeIfPresent(it) { _, _ -> true } }
+                    continue
+                }
+                request.id?.let { openRequests[it] = false }
+                queue.put(request)
+            }
+        }, "sidecar-reader").apply {
+            isDaemon = true
+            start()
+        }
+
         while (running) {
-            val request = transport.readRequest()
-            if (request == null) {
-                System.err.println("AnalysisServer: EOF on stdin, exiting")
+            val request = queue.take()
+            if (request === endOfInput) {
+                System.err.println("AnalysisServer: EOF on input, exiting")
                 break
             }
+            val id = request.id
+            if (id != null && openRequests[id] == true) {
+                openRequests.remove(id)
+                System.err.println("AnalysisServer: skipping cancelled ${request.method} (id=$id)")
+                continue
+            }
 
+            bridge.cancellationCheck = { id != null && openRequests[id] == true }
             try {
                 handleRequest(request)
             } catch (e: Throwable) {
@@ -34,77 +77,154 @@ class AnalysisServer(
                 if (request.id != null) {
                     transport.sendError(request.id, -32603, "Internal error: ${e.javaClass.simpleName}: ${e.message}")
                 }
+            } finally {
+                bridge.cancellationCheck = { false }
+                id?.let { openRequests.remove(it) }
             }
         }
 
-        bridge.shutdown()
+        if (retainedSession == null) bridge.shutdown()
         System.err.println("AnalysisServer: event loop ended")
     }
 
+    /** Request handlers by method; feature requests missing here are reported as unsupported. */
+    private val handlers: Map<String, (JsonRpcRequest) -> Unit> = mapOf(
+        "initialize" to ::handleInitialize,
+        "shutdown" to ::handleShutdown,
+        "ping" to ::handlePing,
+        "textDocument/didOpen" to ::handleDidOpen,
+        "textDocument/didChange" to ::handleDidChange,
+        "textDocument/didClose" to ::handleDidClose,
+        "workspace/didCreateFiles" to ::handleDidCreateFiles,
+        "pebble/textDocument/didOpen" to ::handlePebbleDidOpen,
+        "pebble/textDocument/didChange" to ::handlePebbleDidChange,
+        "pebble/textDocument/didClose" to ::handlePebbleDidClose,
+        "analyze" to ::handleAnalyze,
+        "analyzeAll" to ::handleAnalyzeAll,
+        "reanalyze" to ::handleReanalyze,
+        "hover" to ::handleHover,
+        "completion" to ::handleCompletion,
+        "definition" to ::handleDefinition,
+        "decompile" to ::handleDecompile,
+        "references" to ::handleReferences,
+        "pebble/definition" to ::handlePebbleDefinition,
+        "pebble/references" to ::handlePebbleReferences,
+        "signatureHelp" to ::handleSignatureHelp,
+        "formatting" to ::handleFormatting,
+        "rename" to ::handleRename,
+        "codeActions" to ::handleCodeActions,
+        "extract" to ::handleExtract,
+        "inline" to ::handleInline,
+        "changeSignature" to ::handleChangeSignature,
+        "revealGenerated" to ::handleRevealGenerated,
+        "discoverTests" to ::handleDiscoverTests,
+        "suppress" to ::handleSuppress,
+        "workspaceSymbols" to ::handleWorkspaceSymbols,
+        "inlayHints" to ::handleInlayHints,
+        "codeLens" to ::handleCodeLens,
+        "documentLink" to ::handleDocumentLink,
+        "foldingRange" to ::handleFoldingRange,
+        "documentSymbols" to ::handleDocumentSymbols,
+        "documentLink/resolve" to ::handleDocumentLinkResolve,
+        "workspaceSymbol/resolve" to ::handleWorkspaceSymbolResolve,
+        "semanticTokens" to ::handleSemanticTokens,
+        "callHierarchy/prepare" to ::handleCallHierarchyPrepare,
+        "callHierarchy/incoming" to ::handleCallHierarchyIncoming,
+        "typeHierarchy/prepare" to ::handleTypeHierarchyPrepare,
+        "typeHierarchy/supertypes" to ::handleTypeHierarchySupertypes,
+    )
+
     private fun handleRequest(request: JsonRpcRequest) {
         System.err.println("AnalysisServer: received ${request.method} (id=${request.id})")
 
-        when (request.method) {
-            "initialize" -> handleInitialize(request)
-            "shutdown" -> handleShutdown(request)
-            "ping" -> handlePing(request)
-            "textDocument/didOpen" -> handleDidOpen(request)
-            "textDocument/didChange" -> handleDidChange(request)
-            "textDocument/didClose" -> handleDidClose(request)
-            "pebble/textDocument/didOpen" -> handlePebbleDidOpen(request)
-            "pebble/textDocument/didChange" -> handlePebbleDidChange(request)
-            "pebble/textDocument/didClose" -> handlePebbleDidClose(request)
-            "analyze" -> handleAnalyze(request)
-            "analyzeAll" -> handleAnalyzeAll(request)
-            "hover" -> handleHover(request)
-            "completion" -> handleCompletion(request)
-            "definition" -> handleDefinition(request)
-            "references" -> handleReferences(request)
-            "pebble/definition" -> handlePebbleDefinition(request)
-            "pebble/references" -> handlePebbleReferences(request)
-            "signatureHelp" -> handleSignatureHelp(request)
-            "formatting" -> handleFormatting(request)
-            "rename" -> handleRename(request)
-            "codeActions" -> handleCodeActions(request)
-            "workspaceSymbols" -> handleWorkspaceSymbols(request)
-            "inlayHints" -> handleInlayHints(request)
-            "codeLens" -> handleCodeLens(request)
-            "semanticTokens" -> handleSemanticTokens(request)
-            "callHierarchy/prepare" -> handleCallHierarchyPrepare(request)
-            "callHierarchy/incoming" -> handleCallHierarchyIncoming(request)
-            "typeHierarchy/prepare" -> handleTypeHierarchyPrepare(request)
-            "typeHierarchy/supertypes" -> handleTypeHierarchySupertypes(request)
-            "$/cancelRequest" -> {
-                // Best-effort cancellation - currently a no-op since we process sequentially
-            }
-            else -> {
-                if (request.id != null) {
-                    transport.sendError(request.id, -32601, "Method not supported: ${request.method}")
-                }
-            }
+        val handler = handlers[request.method]
+        when {
+            handler != null -> handler(request)
+            // Recorded by the reader thread in run() before it gets here
+            request.method == "\$/cancelRequest" -> Unit
+            request.id != null -> transport.sendError(request.id, -32601, "Method not supported: ${request.method}")
         }
     }
 
     private fun handleInitialize(request: JsonRpcRequest) {
         val params = request.params ?: JsonObject()
+        val retained = retainedSession
+        val classpath = if (retained != null && retained.params == params) {
+            System.err.println("AnalysisServer: reusing the retained analysis session")
+            params.get("classpath")?.asJsonArray?.map { it.asString } ?: emptyList()
+        } else {
+            try {
+                initializeSession(params)
+            } catch (e: Exception) {
+                if (!isClasspathFailure(e)) throw e
+                transport.sendError(request.id, CLASSPATH_REJECTED, "${e.javaClass.simpleName}: ${e.message}")
+                return
+            }
+        }
+
+        val missingClasspathEntries = classpath.count { !java.io.File(it).exists() }
+
+        val result = JsonObject()
+        result.addProperty("success", true)
+        result.addProperty("kotlinVersion", SidecarRuntime.kotlinVersion)
+        result.addProperty("classpathResolved", missingClasspathEntries == 0)
+        result.addProperty("missingClasspathEntries", missingClasspathEntries)
+        result.add("unsupportedFeatures", com.google.gson.JsonArray().apply {
+            unsupportedFeatures().forEach { add(it) }
+        })
+        transport.sendResult(request.id, result)
+    }
+
+    /** Whether [e] comes from reading a classpath archive, e.g. a corrupt jar. */
+    private fun isClasspathFailure(e: Throwable): Boolean =
+        generateSequence(e) { it.cause }.any { it is java.util.zip.ZipException || it is java.util.zip.ZipError }
+
+    /** The [FEATURE_METHODS] this build has no handler for. */
+    internal fun unsupportedFeatures(): List<String> = FEATURE_METHODS.filterNot { it in handlers }
+
+    /**
+     * Drops the analysis session and rebuilds it from the current project model
+     * without restarting the JVM, e.g. after a branch switch.
+     */
+    private fun handleReanalyze(request: JsonRpcRequest) {
+        initializeSession(request.params ?: JsonObject())
+
+        val result = JsonObject()
+        result.addProperty("success", true)
+        transport.sendResult(request.id, result)
+    }
 
+    /** Builds a fresh analysis session from initialize-style params; returns the classpath. */
+    private fun initializeSession(params: JsonObject): List<String> {
+        retainedSession?.params = params
         val projectRoot = params.get("projectRoot")?.asString ?: ""
-        val classpath = params.get("classpath")?.asJsonArray?.map { it.asString } ?: emptyList()
+        // The annotation processing classpath joins the session's, so classes
+        // kapt or KSP generated in an earlier build resolve.
+        val kaptClasspath = params.get("kaptClasspath")?.asJsonArray?.map { it.asString } ?: emptyList()
+        val classpath = ((params.get("classpath")?.asJsonArray?.map { it.asString } ?: emptyList()) + kaptClasspath)
+            .distinct()
         val compilerFlags = params.get("compilerFlags")?.asJsonArray?.map { it.asString } ?: emptyList()
         val jdkHome = params.get("jdkHome")?.asString ?: ""
         val sourceRoots = params.get("sourceRoots")?.asJsonArray?.map { it.asString } ?: emptyList()
+        val generatedSourceRoots = params.get("generatedSourceRoots")?.asJsonArray?.map { it.asString } ?: emptyList()
 
-        bridge.initialize(projectRoot, classpath, compilerFlags, jdkHome, sourceRoots)
+        val excludePaths = params.get("excludePaths")?.asJsonArray?.map { it.asString }
+            ?: CompilerBridge.DEFAULT_EXCLUDE_PATHS
 
-        val result = JsonObject()
-        result.addProperty("success", true)
-        result.addProperty("kotlinVersion", SidecarRuntime.kotlinVersion)
-        transport.sendResult(request.id, result)
+        val analysisScope = params.get("analysisScope")?.asString ?: "project"
+        val classpathSources = params.get("classpathSources")?.asJsonArray?.map { it.asString } ?: emptyList()
+
+        bridge.initialize(
+            projectRoot, classpath, compilerFlags, jdkHome,
+            sourceRoots, generatedSourceRoots, excludePaths, analysisScope, classpathSources,
+        )
+        return classpath
     }
 
     private fun handleShutdown(request: JsonRpcRequest) {
         bridge.shutdown()
         running = false
+        retainedSession?.shutdownRequested = true
 
         val result = JsonObject()
         result.addProperty("success", true)
@@ -121,7 +241,9 @@ class AnalysisServer(
         val params = request.params ?: return
         val uri = params.get("uri")?.asString ?: return
         val text = params.get("text")?.asString ?: return
-        bridge.updateFile(uri, text)
+        bridge.updateFile(uri, text, loose = params.get("looseFile")?.asBoolean == true)
+        recordVersion(uri, params)
+        updateScriptHint(uri, params)
         // Notifications don't get a response
     }
 
@@ -129,14 +251,54 @@ class AnalysisServer(
         val params = request.params ?: return
         val uri = params.get("uri")?.asString ?: return
         val text = params.get("text")?.asString ?: return
-        bridge.updateFile(uri, text)
+        bridge.updateFile(uri, text, loose = params.get("looseFile")?.asBoolean == true)
+        recordVersion(uri, params)
+        updateScriptHint(uri, params)
         // Notifications don't get a response
     }
 
+    private fun recordVersion(uri: String, params: JsonObject) {
+        val version = params.get("version")?.takeUnless { it.isJsonNull }?.asInt ?: return
+        documentVersions[uri] = version
+    }
+
+    /**
+     * Answers [request] with `ContentModified` when it names a version of an open
+     * document newer than the last one received, so the server re-sends the text
+     * and retries instead of getting results for stale content. Returns whether
+     * the request was rejected. Documents without a recorded version pass.
+     */
+    private fun rejectIfAhead(request: JsonRpcRequest, params: JsonObject): Boolean {
+        val uri = params.get("uri")?.asString ?: return false
+        val version = params.get("version")?.takeUnless { it.isJsonNull }?.asInt ?: return false
+        val received = documentVersions[uri] ?: return false
+        if (version <= received) return false
+        transport.sendError(request.id, CONTENT_MODIFIED, "version $version of $uri not received yet (have $received)")
+        return true
+    }
+
+    /** Forwards the `scriptKind`/`scriptClasspath`/`scriptImports` hint sent for `.kts` documents. */
+    private fun updateScriptHint(uri: String, params: JsonObject) {
+        val kind = params.get("scriptKind")?.asString ?: return
+        val classpath = params.getAsJsonArray("scriptClasspath")?.map { it.asString } ?: emptyList()
+        val imports = params.getAsJsonArray("scriptImports")?.map { it.asString } ?: emptyList()
+        bridge.updateScriptHint(uri, kind, classpath, imports)
+    }
+
     private fun handleDidClose(request: JsonRpcRequest) {
         val params = request.params ?: return
         val uri = params.get("uri")?.asString ?: return
         bridge.removeFile(uri)
+        documentVersions.remove(uri)
+        // Notifications don't get a response
+    }
+
+    private fun handleDidCreateFiles(request: JsonRpcRequest) {
+        val params = request.params ?: return
+        val files = params.getAsJsonArray("files") ?: return
+        for (file in files) {
+            bridge.addCreatedFile(file.asString)
+        }
         // Notifications don't get a response
     }
 
@@ -170,8 +332,15 @@ class AnalysisServer(
             return
         }
         val version = params.get("version")?.takeUnless { it.isJsonNull }?.asInt
+        if (rejectIfAhead(request, params)) return
+
+        val lines = params.getAsJsonObject("range")?.let { range ->
+            val startLine = range.get("startLine")?.asInt
+            val endLine = range.get("endLine")?.asInt
+            if (startLine != null && endLine != null && startLine <= endLine) startLine..endLine else null
+        }
 
-        val result = bridge.analyze(uri, version)
+        val result = bridge.analyze(uri, version, lines)
         transport.sendResult(request.id, result)
     }
 
@@ -195,6 +364,7 @@ class AnalysisServer(
         }
         val character = params.get("character")?.asInt ?: 0
 
+        if (rejectIfAhead(request, params)) return
         val result = bridge.hover(uri, line, character)
         transport.sendResult(request.id, result)
     }
@@ -215,6 +385,7 @@ class AnalysisServer(
         val character = params.get("character")?.asInt ?: 0
         val triggerCharacter = params.get("triggerCharacter")?.takeUnless { it.isJsonNull }?.asString
 
+        if (rejectIfAhead(request, params)) return
         val result = bridge.completion(uri, line, character, triggerCharacter)
         transport.sendResult(request.id, result)
     }
@@ -234,6 +405,7 @@ class AnalysisServer(
         }
         val character = params.get("character")?.asInt ?: 0
 
+        if (rejectIfAhead(request, params)) return
         val result = bridge.definition(uri, line, character)
         transport.sendResult(request.id, result)
     }
@@ -252,11 +424,32 @@ class AnalysisServer(
             return
         }
         val character = params.get("character")?.asInt ?: 0
+        val onPartial = partialResultSender(params, "locations")
+        val scope = params.get("scope")?.asString ?: CompilerBridge.REFERENCES_SCOPE_ALL
+        val limit = params.get("limit")?.takeIf { !it.isJsonNull }?.asInt ?: Int.MAX_VALUE
 
-        val result = bridge.references(uri, line, character)
+        val result = bridge.references(uri, line, character, onPartial, scope, limit)
         transport.sendResult(request.id, result)
     }
 
+    /**
+     * When the request carries a `partialResultToken`, returns a callback that
+     * streams a batch to the server as `$/partialResult`, wrapped under [key]
+     * like the final result.
+     */
+    private fun partialResultSender(params: JsonObject, key: String): ((com.google.gson.JsonArray) -> Unit)? {
+        val token = params.get("partialResultToken")?.asString ?: return null
+        return { batch ->
+            transport.sendNotification(
+                "\$/partialResult",
+                JsonObject().apply {
+                    addProperty("token", token)
+                    add("value", JsonObject().apply { add(key, batch) })
+                },
+            )
+        }
+    }
+
     private fun handlePebbleDefinition(request: JsonRpcRequest) {
         val params = request.params ?: run {
             transport.sendResult(request.id, JsonObject().apply { add("locations", com.google.gson.JsonArray()) })
@@ -307,8 +500,13 @@ class AnalysisServer(
             return
         }
         val character = params.get("character")?.asInt ?: 0
+        val previousSignature = params.getAsJsonObject("context")
+            ?.get("activeSignature")
+            ?.takeIf { it.isJsonPrimitive }
+            ?.asString
 
-        val result = bridge.signatureHelp(uri, line, character)
+        if (rejectIfAhead(request, params)) return
+        val result = bridge.signatureHelp(uri, line, character, previousSignature)
         transport.sendResult(request.id, result)
     }
 
@@ -352,13 +550,108 @@ class AnalysisServer(
             transport.sendResult(request.id, JsonObject().apply { add("actions", com.google.gson.JsonArray()) })
             return
         }
-        val line = params.get("line")?.asInt ?: run {
+        val line = (params.get("startLine") ?: params.get("line"))?.asInt ?: run {
             transport.sendResult(request.id, JsonObject().apply { add("actions", com.google.gson.JsonArray()) })
             return
         }
+        val character = (params.get("startColumn") ?: params.get("character"))?.asInt ?: 0
+        val endLine = params.get("endLine")?.asInt ?: line
+        val endCharacter = params.get("endColumn")?.asInt ?: character
+
+        val result = bridge.codeActions(uri, line, character, endLine, endCharacter)
+        transport.sendResult(request.id, result)
+    }
+
+    private fun handleDecompile(request: JsonRpcRequest) {
+        val params = request.params
+        val jar = params?.get("jar")?.asString
+        val entry = params?.get("entry")?.asString
+        if (jar == null || entry == null) {
+            transport.sendResult(request.id, JsonObject())
+            return
+        }
+
+        val result = bridge.decompile(jar, entry)
+        transport.sendResult(request.id, result)
+    }
+
+    private fun handleSuppress(request: JsonRpcRequest) {
+        val params = request.params
+        val uri = params?.get("uri")?.asString
+        val code = params?.get("code")?.asString
+        val line = params?.get("line")?.asInt
+        if (uri == null || code == null || line == null) {
+            transport.sendResult(request.id, JsonObject().apply { add("actions", com.google.gson.JsonArray()) })
+            return
+        }
+        val character = params.get("character")?.asInt ?: 0
+
+        val result = bridge.suppress(uri, code, line, character)
+        transport.sendResult(request.id, result)
+    }
+
+    private fun handleExtract(request: JsonRpcRequest) {
+        val params = request.params
+        val uri = params?.get("uri")?.asString
+        val startLine = params?.get("startLine")?.asInt
+        val endLine = params?.get("endLine")?.asInt
+        if (uri == null || startLine == null || endLine == null) {
+            transport.sendResult(request.id, JsonObject().apply { add("actions", com.google.gson.JsonArray()) })
+            return
+        }
+        val startColumn = params.get("startColumn")?.asInt ?: 0
+        val endColumn = params.get("endColumn")?.asInt ?: 0
+
+        val result = bridge.extract(uri, startLine, startColumn, endLine, endColumn)
+        transport.sendResult(request.id, result)
+    }
+
+    private fun handleInline(request: JsonRpcRequest) {
+        val params = request.params
+        val uri = params?.get("uri")?.asString
+        val line = params?.get("line")?.asInt
+        if (uri == null || line == null) {
+            transport.sendResult(request.id, JsonObject().apply { add("actions", com.google.gson.JsonArray()) })
+            return
+        }
+        val character = params.get("character")?.asInt ?: 0
+
+        val result = bridge.inline(uri, line, character)
+        transport.sendResult(request.id, result)
+    }
+
+    private fun handleRevealGenerated(request: JsonRpcRequest) {
+        val params = request.params
+        val uri = params?.get("uri")?.asString
+        val line = params?.get("line")?.asInt
+        if (uri == null || line == null) {
+            transport.sendError(request.id, -32602, "Missing uri or line")
+            return
+        }
+        val character = params.get("character")?.asInt ?: 0
+
+        val result = bridge.revealGenerated(uri, line, character)
+        transport.sendResult(request.id, result)
+    }
+
+    private fun handleDiscoverTests(request: JsonRpcRequest) {
+        val uri = request.params?.get("uri")?.takeUnless { it.isJsonNull }?.asString
+        val result = bridge.discoverTests(uri)
+        transport.sendResult(request.id, result)
+    }
+
+    private fun handleChangeSignature(request: JsonRpcRequest) {
+        val params = request.params
+        val uri = params?.get("uri")?.asString
+        val line = params?.get("line")?.asInt
+        val parameters = params?.get("parameters")?.takeIf { it.isJsonArray }?.asJsonArray
+        if (uri == null || line == null || parameters == null) {
+            transport.sendError(request.id, -32602, "Missing uri, line, or parameters")
+            return
+        }
         val character = params.get("character")?.asInt ?: 0
 
-        val result = bridge.codeActions(uri, line, character)
+        val result = bridge.changeSignature(uri, line, character, parameters.map { it.asString })
         transport.sendResult(request.id, result)
     }
 
@@ -369,7 +662,7 @@ class AnalysisServer(
         }
         val query = params.get("query")?.asString ?: ""
 
-        val result = bridge.workspaceSymbols(query)
+        val result = bridge.workspaceSymbols(query, partialResultSender(params, "symbols"))
         transport.sendResult(request.id, result)
     }
 
@@ -403,6 +696,63 @@ class AnalysisServer(
         transport.sendResult(request.id, result)
     }
 
+    private fun handleFoldingRange(request: JsonRpcRequest) {
+        val uri = request.params?.get("uri")?.asString ?: run {
+            transport.sendResult(request.id, JsonObject().apply { add("ranges", com.google.gson.JsonArray()) })
+            return
+        }
+
+        val result = bridge.foldingRanges(uri)
+        transport.sendResult(request.id, result)
+    }
+
+    private fun handleDocumentSymbols(request: JsonRpcRequest) {
+        val uri = request.params?.get("uri")?.asString ?: run {
+            transport.sendResult(request.id, JsonObject().apply { add("symbols", com.google.gson.JsonArray()) })
+            return
+        }
+
+        val result = bridge.documentSymbols(uri)
+        transport.sendResult(request.id, result)
+    }
+
+    private fun handleDocumentLink(request: JsonRpcRequest) {
+        val uri = request.params?.get("uri")?.asString ?: run {
+            transport.sendResult(request.id, JsonObject().apply { add("links", com.google.gson.JsonArray()) })
+            return
+        }
+
+        val result = bridge.documentLinks(uri)
+        transport.sendResult(request.id, result)
+    }
+
+    private fun handleDocumentLinkResolve(request: JsonRpcRequest) {
+        val params = request.params
+        val uri = params?.get("uri")?.asString
+        val reference = params?.get("reference")?.asString
+        if (uri == null || reference == null) {
+            transport.sendResult(request.id, JsonObject())
+            return
+        }
+
+        val result = bridge.resolveDocumentLink(uri, reference)
+        transport.sendResult(request.id, result)
+    }
+
+    private fun handleWorkspaceSymbolResolve(request: JsonRpcRequest) {
+        val params = request.params
+        val uri = params?.get("uri")?.asString
+        val name = params?.get("name")?.asString
+        if (uri == null || name == null) {
+            transport.sendResult(request.id, JsonObject())
+            return
+        }
+        val line = params.get("line")?.takeIf { !it.isJsonNull }?.asInt
+
+        val result = bridge.resolveWorkspaceSymbol(uri, name, line)
+        transport.sendResult(request.id, result)
+    }
+
     private fun handleSemanticTokens(request: JsonRpcRequest) {
         val params = request.params ?: run {
             transport.sendResult(request.id, JsonObject().apply {
@@ -506,4 +856,20 @@ class AnalysisServer(
         val result = bridge.typeHierarchySupertypes(uri, line, character, name)
         transport.sendResult(request.id, result)
     }
+
+    companion object {
+        /** LSP `ContentModified`: the request refers to a document version not received yet. */
+        const val CONTENT_MODIFIED = -32801
+
+        /** `initialize` failed to load the project classpath; the server retries stdlib-only. */
+        const val CLASSPATH_REJECTED = -32002
+
+        /** Feature requests the server stops offering when a sidecar build cannot serve them. */
+        val FEATURE_METHODS = listOf(
+            "hover", "completion", "signatureHelp", "codeActions", "codeLens", "inlayHints",
+            "semanticTokens", "callHierarchy/prepare", "callHierarchy/incoming",
+            "typeHierarchy/prepare", "typeHierarchy/supertypes", "documentLink", "foldingRange",
+            "documentSymbols", "workspaceSymbol/resolve",
+        )
+    }
 }
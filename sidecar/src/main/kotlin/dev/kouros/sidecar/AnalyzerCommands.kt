@@ -3,6 +3,9 @@ package dev.kouros.sidecar
 object AnalyzerCommands {
     const val OPEN_TEST_TARGET = "kotlin-analyzer.openTestTarget"
     const val CREATE_AND_OPEN_TEST_TARGET = "kotlin-analyzer.createAndOpenTestTarget"
+    const val CHANGE_SIGNATURE = "kotlin-analyzer.changeSignature"
+    const val REVEAL_GENERATED = "kotlin-analyzer.revealGenerated"
+    const val DISCOVER_TESTS = "kotlin-analyzer.discoverTests"
 
     const val ARG_TARGET_URI = "targetUri"
     const val ARG_TARGET_PATH = "targetPath"
@@ -12,4 +15,9 @@ object AnalyzerCommands {
     const val ARG_START_CHARACTER = "startCharacter"
     const val ARG_END_LINE = "endLine"
     const val ARG_END_CHARACTER = "endCharacter"
+    const val ARG_URI = "uri"
+    const val ARG_POSITION = "position"
+    const val ARG_LINE = "line"
+    const val ARG_CHARACTER = "character"
+    const val ARG_PARAMETERS = "parameters"
 }
@@ -7,6 +7,8 @@ import com.intellij.psi.PsiComment
 import com.intellij.psi.PsiElement
 import com.intellij.psi.PsiWhiteSpace
 import com.intellij.psi.util.PsiTreeUtil
+import org.jetbrains.kotlin.kdoc.psi.api.KDoc
+import org.jetbrains.kotlin.kdoc.psi.impl.KDocLink
 import org.jetbrains.kotlin.analysis.api.analyze
 import org.jetbrains.kotlin.analysis.api.components.KaDiagnosticCheckerFilter
 import org.jetbrains.kotlin.analysis.api.renderer.declarations.impl.KaDeclarationRendererForSource
@@ -35,8 +37,10 @@ import org.jetbrains.kotlin.psi.*
 import org.jetbrains.kotlin.psi.psiUtil.parents
 import org.jetbrains.kotlin.types.Variance
 import java.io.File
+import java.nio.file.FileSystems
 import java.nio.file.Files
 import java.nio.file.Path
+import java.nio.file.PathMatcher
 import java.nio.file.Paths
 
 /**
@@ -67,6 +71,20 @@ class CompilerBridge {
     private var sessionDirty = false
     // URIs of on-disk files whose content has been overridden via updateFile()
     private val dirtyOnDiskFiles = mutableSetOf<String>()
+    // URIs of files opened from outside the project root: analyzed ad hoc, never
+    // written to the temp dir, shadowed into the session, or symbol-indexed
+    private val looseFiles = mutableSetOf<String>()
+
+    /**
+     * Set by the server while a request runs; returns true once the request
+     * was cancelled. Long loops over the session's files stop early.
+     */
+    @Volatile
+    var cancellationCheck: () -> Boolean = { false }
+    // Script kind and implicit classpath of open .kts documents, keyed by URI
+    private val scriptHints = mutableMapOf<String, ScriptHint>()
+    // Scripts loaded because an open script imports them, not opened by the client
+    private val importedScripts = mutableSetOf<String>()
     // Maps shadow source tree paths back to original paths for findKtFile matching
     private val shadowPathMapping = mutableMapOf<String, String>()
     // Stored init params for session rebuilds
@@ -75,6 +93,14 @@ class CompilerBridge {
     private var initCompilerFlags = emptyList<String>()
     private var initJdkHome = ""
     private var initSourceRoots = emptyList<String>()
+    private var initGeneratedSourceRoots = emptyList<String>()
+    private var initExcludePaths = DEFAULT_EXCLUDE_PATHS
+    // excludePaths globs paired with whether they match absolute paths
+    private var excludeMatchers = emptyList<Pair<Boolean, PathMatcher>>()
+    private var initAnalysisScope = "project"
+    private var initClasspathSources = emptyList<String>()
+    // Javadoc of Java library symbols from the classpath's -sources jars
+    private var sourcesJarDocs = SourcesJarDocs(emptyList())
 
     /**
      * Initializes the Analysis API session with the given project configuration.
@@ -85,6 +111,10 @@ class CompilerBridge {
         compilerFlags: List<String>,
         jdkHome: String,
         sourceRoots: List<String>,
+        generatedSourceRoots: List<String> = emptyList(),
+        excludePaths: List<String> = DEFAULT_EXCLUDE_PATHS,
+        analysisScope: String = "project",
+        classpathSources: List<String> = initClasspathSources,
     ) {
         System.err.println("CompilerBridge: initializing session")
         val startTime = System.currentTimeMillis()
@@ -95,6 +125,14 @@ class CompilerBridge {
         initCompilerFlags = compilerFlags
         initJdkHome = jdkHome
         initSourceRoots = sourceRoots
+        initGeneratedSourceRoots = generatedSourceRoots
+        initExcludePaths = excludePaths
+        excludeMatchers = compileExcludeMatchers(excludePaths)
+        initAnalysisScope = analysisScope
+        if (classpathSources != initClasspathSources) {
+            initClasspathSources = classpathSources
+            sourcesJarDocs = SourcesJarDocs(classpathSources)
+        }
 
         // Create temp directory for virtual files
         if (virtualFileTempDir == null) {
@@ -132,13 +170,16 @@ class CompilerBridge {
             System.err.println("CompilerBridge: compilerFlags=$compilerFlags")
         }
 
-        val baseSourceRoots = if (sourceRoots.isNotEmpty()) {
+        val declaredSourceRoots = if (sourceRoots.isNotEmpty()) {
             sourceRoots.map { Paths.get(it) }.filter { it.toFile().exists() }
         } else if (projectRoot.isNotEmpty()) {
             findSourceRoots(Paths.get(projectRoot))
         } else {
             emptyList()
         }
+        // KSP/KAPT output is part of the module so navigation can reach generated symbols
+        val generatedRoots = generatedSourceRoots.map { Paths.get(it) }.filter { it.toFile().exists() }
+        val baseSourceRoots = (declaredSourceRoots + generatedRoots).distinct()
 
         // When on-disk files have virtual overrides, build a shadow source tree so the
         // session discovers the virtual content instead of stale on-disk content.
@@ -159,6 +200,7 @@ class CompilerBridge {
 
         System.err.println("CompilerBridge: projectRoot=$projectRoot")
         System.err.println("CompilerBridge: sourceRoots=$sourceRoots")
+        System.err.println("CompilerBridge: generatedSourceRoots=$generatedSourceRoots")
         System.err.println("CompilerBridge: effectiveSourceRoots=$effectiveSourceRoots")
         System.err.println("CompilerBridge: classpath=${classpath.size} entries")
 
@@ -230,6 +272,20 @@ class CompilerBridge {
                         }
                     } else null
 
+                    // Implicit classpath of open scripts (Gradle API, @file:DependsOn jars)
+                    val scriptRoots = scriptClasspath()
+                        .map { Paths.get(it) }
+                        .filter { it.toFile().exists() }
+                    val scriptModule = if (scriptRoots.isNotEmpty()) {
+                        buildKtLibraryModule {
+                            libraryName = "script-dependencies"
+                            for (jar in scriptRoots) {
+                                addBinaryRoot(jar)
+                            }
+                            platform = JvmPlatforms.defaultJvmPlatform
+                        }
+                    } else null
+
                     // Source module
                     val mainModule = buildKtSourceModule {
                         moduleName = "main"
@@ -250,6 +306,7 @@ class CompilerBridge {
                         if (jdkModule != null) addRegularDependency(jdkModule)
                         if (stdlibModule != null) addRegularDependency(stdlibModule)
                         if (classpathModule != null) addRegularDependency(classpathModule)
+                        if (scriptModule != null) addRegularDependency(scriptModule)
                     }
                     sourceModule = mainModule
                     addModule(mainModule)
@@ -290,18 +347,34 @@ class CompilerBridge {
         }
 
         // Build the symbol index from all discovered files
-        symbolIndex.rebuildFromSession(session!!)
+        // Generated roots stay indexed even when excluded so their symbols
+        // remain available as import candidates.
+        val generatedRootPaths = initGeneratedSourceRoots.map { Paths.get(it) }
+        // In openFiles scope only open files and the packages they import are indexed.
+        val scopePackages = if (initAnalysisScope == ANALYSIS_SCOPE_OPEN_FILES) {
+            openFilesScopePackages(session!!.project)
+        } else null
+        symbolIndex.rebuildFromSession(session!!) { ktFile ->
+            val path = ktFile.virtualFile.path
+            val excluded = isExcludedPath(path) && generatedRootPaths.none { Paths.get(path).startsWith(it) }
+            excluded || (scopePackages != null && ktFile.packageFqName.asString() !in scopePackages)
+        }
         System.err.println("CompilerBridge: symbol index built with ${symbolIndex.size()} declarations")
 
         pebbleSpringIndexDirty = true
     }
 
     /**
-     * Updates the content of a virtual file in the session.
+     * Updates the content of a virtual file in the session. A [loose] file lies
+     * outside the project root; it stays out of the session and the symbol
+     * index until it is removed.
      */
-    fun updateFile(uri: String, text: String) {
+    fun updateFile(uri: String, text: String, loose: Boolean = false) {
+        importedScripts.remove(uri)
         virtualFiles[uri] = text
         updateFileInSession(uri, text)
+        if (loose) looseFiles.add(uri)
+        if (uri in looseFiles) return
         pebbleSpringIndexDirty = true
 
         // Detect on-disk file edits: the session's FIR caches are baked in at creation
@@ -361,13 +434,86 @@ class CompilerBridge {
         }
     }
 
+    /**
+     * Adds a `.kt` file created on disk to analysis. Its declarations are
+     * indexed right away; FIR picks the file up from its source root at the
+     * next session rebuild, so creating a file does not force one. Files
+     * already open are tracked through [updateFile] instead.
+     */
+    fun addCreatedFile(uri: String) {
+        if (uri in virtualFiles) return
+        val path = uriToPath(uri)
+        if (isExcludedPath(path)) return
+        val text = try {
+            File(path).readText()
+        } catch (e: Exception) {
+            System.err.println("CompilerBridge: failed to read created file $uri: ${e.message}")
+            return
+        }
+        pebbleSpringIndexDirty = true
+
+        val currentSession = session ?: return
+        try {
+            val ktFile = KtPsiFactory(currentSession.project).createFile(File(path).name, text)
+            symbolIndex.indexFile(uri, ktFile)
+        } catch (e: Exception) {
+            System.err.println("CompilerBridge: failed to index created file $uri: ${e.message}")
+        }
+    }
+
+    /**
+     * Records the script kind and implicit classpath of an open `.kts` document.
+     * The session is rebuilt when the combined script classpath changes. Scripts
+     * pulled in by `@file:Import` are loaded as virtual files so their
+     * declarations resolve, and unloaded once no open script imports them.
+     */
+    fun updateScriptHint(uri: String, kind: String, classpath: List<String>, imports: List<String>) {
+        val previousClasspath = scriptClasspath()
+        val importUris = imports.map { pathToUri(it) }
+        val previous = scriptHints.put(uri, ScriptHint(kind, classpath, importUris))
+        if (scriptClasspath() != previousClasspath) {
+            System.err.println("CompilerBridge: script classpath changed for $uri ($kind), session marked dirty")
+            sessionDirty = true
+        }
+        for ((import, importUri) in imports.zip(importUris)) {
+            if (importUri in virtualFiles) continue
+            try {
+                updateFile(importUri, File(import).readText())
+                importedScripts.add(importUri)
+            } catch (e: Exception) {
+                System.err.println("CompilerBridge: failed to load imported script $import: ${e.message}")
+            }
+        }
+        previous?.let { unloadUnusedImports(it.imports) }
+    }
+
+    /** Removes the scripts among [imports] that were loaded for an import no open script still has. */
+    private fun unloadUnusedImports(imports: List<String>) {
+        val stillImported = scriptHints.values.flatMapTo(mutableSetOf()) { it.imports }
+        for (importUri in imports) {
+            if (importUri in importedScripts && importUri !in stillImported) {
+                removeFile(importUri)
+            }
+        }
+    }
+
+    /** Union of the implicit classpaths of all open scripts. */
+    private fun scriptClasspath(): Set<String> =
+        scriptHints.values.flatMapTo(linkedSetOf()) { it.classpath }
+
     /**
      * Removes a virtual file from the session.
      */
     fun removeFile(uri: String) {
         virtualFiles.remove(uri)
+        val wasLoose = looseFiles.remove(uri)
+        importedScripts.remove(uri)
+        val hint = scriptHints.remove(uri)
+        if (hint?.classpath?.isNotEmpty() == true) {
+            sessionDirty = true
+        }
         symbolIndex.removeFile(uri)
-        pebbleSpringIndexDirty = true
+        if (!wasLoose) pebbleSpringIndexDirty = true
         if (uri in dirtyOnDiskFiles) {
             dirtyOnDiskFiles.remove(uri)
             sessionDirty = true
@@ -376,6 +522,7 @@ class CompilerBridge {
             virtualFilesOnDisk.remove(uri)
             sessionDirty = true
         }
+        hint?.let { unloadUnusedImports(it.imports) }
     }
 
     fun updatePebbleFile(uri: String, text: String) {
@@ -440,7 +587,60 @@ class CompilerBridge {
     private fun ensureSessionCurrent() {
         if (!sessionDirty || session == null) return
         System.err.println("CompilerBridge: session dirty, rebuilding to pick up virtual file changes")
-        initialize(initProjectRoot, initClasspath, initCompilerFlags, initJdkHome, initSourceRoots)
+        initialize(
+            initProjectRoot, initClasspath, initCompilerFlags, initJdkHome,
+            initSourceRoots, initGeneratedSourceRoots, initExcludePaths, initAnalysisScope,
+        )
+    }
+
+    /** Packages declared or imported by the open Kotlin documents. */
+    private fun openFilesScopePackages(project: com.intellij.openapi.project.Project): Set<String> {
+        val psiFactory = KtPsiFactory(project)
+        val packages = mutableSetOf<String>()
+        for ((uri, text) in virtualFiles) {
+            if (!uri.endsWith(".kt") && !uri.endsWith(".kts")) continue
+            try {
+                val ktFile = psiFactory.createFile(uri.substringAfterLast('/'), text)
+                packages.add(ktFile.packageFqName.asString())
+                for (directive in ktFile.importDirectives) {
+                    val imported = directive.importedFqName ?: continue
+                    packages.add(if (directive.isAllUnder) imported.asString() else imported.parent().asString())
+                }
+            } catch (e: Exception) {
+                System.err.println("CompilerBridge: failed to read imports of $uri: ${e.message}")
+            }
+        }
+        return packages
+    }
+
+    /**
+     * Compiles excludePaths globs. A leading `**` + `/` also matches at the
+     * top level, as it does on the Rust side.
+     */
+    private fun compileExcludeMatchers(patterns: List<String>): List<Pair<Boolean, PathMatcher>> {
+        val fileSystem = FileSystems.getDefault()
+        return patterns.flatMap { pattern ->
+            val variants = listOf(pattern) + listOfNotNull(pattern.removePrefix("**/").takeIf { it != pattern })
+            variants.mapNotNull { glob ->
+                try {
+                    isAbsoluteGlob(pattern) to fileSystem.getPathMatcher("glob:$glob")
+                } catch (e: Exception) {
+                    System.err.println("CompilerBridge: ignoring invalid exclude pattern '$pattern': ${e.message}")
+                    null
+                }
+            }
+        }
+    }
+
+    /** Returns true if [path] matches excludePaths, relative to the project root or absolute. */
+    private fun isExcludedPath(path: String): Boolean {
+        if (excludeMatchers.isEmpty()) return false
+        val absolute = Paths.get(path)
+        val root = if (initProjectRoot.isNotEmpty()) Paths.get(initProjectRoot) else null
+        val relative = if (root != null && absolute.startsWith(root)) root.relativize(absolute) else absolute
+        return excludeMatchers.any { (isAbsolute, matcher) ->
+            matcher.matches(if (isAbsolute) absolute else relative)
+        }
     }
 
     private fun ensurePebbleSpringIndexCurrent() {
@@ -466,10 +666,17 @@ class CompilerBridge {
     }
 
     /**
-     * Analyzes a file and returns diagnostics.
+     * Analyzes a file and returns diagnostics. With [lines] (1-based,
+     * inclusive), only the top-level declarations overlapping those lines are
+     * checked and the result carries their `region`, so the server can merge
+     * it into the diagnostics it already published. Edits outside any
+     * declaration fall back to checking the whole file.
      */
-    fun analyze(uri: String, version: Int? = null): JsonObject {
+    fun analyze(uri: String, version: Int? = null, lines: IntRange? = null): JsonObject {
         ensureSessionCurrent()
+        if (scriptHints[uri]?.kind == GRADLE_SCRIPT_KIND) {
+            return analyzeGradleScript(uri, version)
+        }
 
         val result = JsonObject()
         val diagnosticsArray = JsonArray()
@@ -523,12 +730,26 @@ class CompilerBridge {
             analyze(ktFile) {
                 val analysisStart = System.currentTimeMillis()
                 val document = ktFile.viewProvider.document
-                val diagnostics = ktFile.collectDiagnostics(
-                    KaDiagnosticCheckerFilter.EXTENDED_AND_COMMON_CHECKERS
-                )
+                val region = if (lines != null && document != null) {
+                    enclosingDeclarations(ktFile, document, lines)
+                } else {
+                    null
+                }
+                val diagnostics = if (region != null) {
+                    region.declarations.flatMap { declarationDiagnostics(it) }
+                } else {
+                    ktFile.collectDiagnostics(KaDiagnosticCheckerFilter.EXTENDED_AND_COMMON_CHECKERS)
+                }
                 val analysisElapsed = System.currentTimeMillis() - analysisStart
 
-                System.err.println("CompilerBridge: analyze($uri) — collectDiagnostics took ${analysisElapsed}ms, returned ${diagnostics.size} diagnostic(s)")
+                if (region != null) {
+                    val regionJson = JsonObject()
+                    regionJson.addProperty("startLine", region.lines.first)
+                    regionJson.addProperty("endLine", region.lines.last)
+                    result.add("region", regionJson)
+                }
+                val scope = region?.let { "lines ${it.lines}" } ?: "whole file"
+                System.err.println("CompilerBridge: analyze($uri) — collecting diagnostics ($scope) took ${analysisElapsed}ms, returned ${diagnostics.size} diagnostic(s)")
                 for (d in diagnostics) {
                     System.err.println("  [${d.severity}] ${d.factoryName}: ${d.defaultMessage} at ${d.textRanges.firstOrNull()}")
                 }
@@ -602,6 +823,9 @@ class CompilerBridge {
                         }
                     }
                 }
+                if (document != null) {
+                    unusedImportDiagnostics(ktFile, document).forEach(diagnosticsArray::add)
+                }
             }
         } catch (e: Throwable) {
             System.err.println("CompilerBridge: analysis failed for $uri: ${e.javaClass.name}: ${e.message}")
@@ -616,6 +840,109 @@ class CompilerBridge {
         return result
     }
 
+    /** Top-level declarations re-checked for an incremental `analyze`, and the lines they span. */
+    private data class AnalyzedRegion(val declarations: List<KtDeclaration>, val lines: IntRange)
+
+    /**
+     * The top-level declarations overlapping [lines] (1-based), or null when
+     * some of those lines lie outside every declaration (imports, blank lines
+     * between declarations), which needs a whole-file check.
+     */
+    private fun enclosingDeclarations(
+        ktFile: KtFile,
+        document: com.intellij.openapi.editor.Document,
+        lines: IntRange,
+    ): AnalyzedRegion? {
+        val spans = ktFile.declarations.map { declaration ->
+            val range = declaration.textRange
+            val first = document.getLineNumber(range.startOffset) + 1
+            val last = document.getLineNumber(range.endOffset) + 1
+            declaration to first..last
+        }
+        val overlapping = spans.filter { (_, span) -> span.first <= lines.last && lines.first <= span.last }
+        if (overlapping.isEmpty()) return null
+        val covered = lines.all { line -> overlapping.any { (_, span) -> line in span } }
+        if (!covered) return null
+        return AnalyzedRegion(
+            declarations = overlapping.map { it.first },
+            lines = overlapping.minOf { it.second.first }..overlapping.maxOf { it.second.last },
+        )
+    }
+
+    /** Diagnostics reported on [declaration] and every element nested in it. */
+    private fun org.jetbrains.kotlin.analysis.api.KaSession.declarationDiagnostics(
+        declaration: KtDeclaration,
+    ): List<org.jetbrains.kotlin.analysis.api.diagnostics.KaDiagnosticWithPsi<*>> {
+        val diagnostics = mutableListOf<org.jetbrains.kotlin.analysis.api.diagnostics.KaDiagnosticWithPsi<*>>()
+        declaration.accept(object : KtTreeVisitorVoid() {
+            override fun visitKtElement(element: KtElement) {
+                diagnostics += element.diagnostics(KaDiagnosticCheckerFilter.EXTENDED_AND_COMMON_CHECKERS)
+                super.visitKtElement(element)
+            }
+        })
+        return diagnostics
+    }
+
+    /**
+     * Analyzes a `.gradle.kts` script. The default script definition knows
+     * nothing about Gradle, so the script is rewritten by [wrapGradleScript]
+     * and analyzed as a regular file against the script classpath (Gradle
+     * API and buildscript plugins), and diagnostics are mapped back to the
+     * script's lines. Unresolved references are dropped: type-safe accessors
+     * (`implementation`, `libs`, extensions of applied plugins) are generated
+     * per project and are not on that classpath.
+     */
+    private fun analyzeGradleScript(uri: String, version: Int?): JsonObject {
+        val result = JsonObject()
+        val diagnosticsArray = JsonArray()
+        result.add("diagnostics", diagnosticsArray)
+        result.add("edits", JsonArray())
+        if (version != null) {
+            result.addProperty("version", version)
+        }
+
+        val currentSession = session ?: return result
+        val path = uriToPath(uri)
+        val text = getVirtualFileContent(uri)
+            ?: File(path).takeIf { it.exists() }?.readText()
+            ?: return result
+        val fileName = File(path).name
+        val wrapper = wrapGradleScript(text, fileName)
+
+        try {
+            val lightVf = com.intellij.testFramework.LightVirtualFile("$fileName.kt", wrapper.text)
+            val ktFile = com.intellij.psi.PsiManager.getInstance(currentSession.project).findFile(lightVf) as? KtFile
+                ?: return result
+            analyze(ktFile) {
+                val document = ktFile.viewProvider.document ?: return@analyze
+                val diagnostics = ktFile.collectDiagnostics(KaDiagnosticCheckerFilter.EXTENDED_AND_COMMON_CHECKERS)
+                for (diagnostic in diagnostics) {
+                    if (diagnostic.factoryName == "UNRESOLVED_REFERENCE") continue
+                    val textRange = diagnostic.textRanges.firstOrNull() ?: continue
+                    val startLine = document.getLineNumber(textRange.startOffset)
+                    val endLine = document.getLineNumber(textRange.endOffset)
+                    val scriptStartLine = wrapper.scriptLine(startLine + 1) ?: continue
+                    val scriptEndLine = wrapper.scriptLine(endLine + 1) ?: scriptStartLine
+
+                    val diagObj = JsonObject()
+                    diagObj.addProperty("severity", diagnostic.severity.name)
+                    diagObj.addProperty("message", diagnostic.defaultMessage)
+                    diagObj.addProperty("code", diagnostic.factoryName)
+                    diagObj.addProperty("line", scriptStartLine)
+                    diagObj.addProperty("column", textRange.startOffset - document.getLineStartOffset(startLine))
+                    diagObj.addProperty("endLine", scriptEndLine)
+                    diagObj.addProperty("endColumn", textRange.endOffset - document.getLineStartOffset(endLine))
+                    diagnosticsArray.add(diagObj)
+                }
+            }
+        } catch (e: Throwable) {
+            System.err.println("CompilerBridge: Gradle script analysis failed for $uri: ${e.javaClass.name}: ${e.message}")
+        }
+
+        System.err.println("CompilerBridge: analyzeGradleScript($uri) — ${diagnosticsArray.size()} diagnostic(s)")
+        return result
+    }
+
     /**
      * Analyzes all source files in the session and returns diagnostics for each.
      * Used for project-wide diagnostics after initial startup.
@@ -631,6 +958,12 @@ class CompilerBridge {
             return result
         }
 
+        if (initAnalysisScope == ANALYSIS_SCOPE_OPEN_FILES) {
+            System.err.println("CompilerBridge: analyzeAll() — skipped, analysisScope is openFiles")
+            result.add("files", filesArray)
+            return result
+        }
+
         val allKtFiles = currentSession.modulesWithFiles.entries
             .flatMap { (_, files) -> files }
             .filterIsInstance<KtFile>()
@@ -642,6 +975,11 @@ class CompilerBridge {
         var warningCount = 0
 
         for (ktFile in allKtFiles) {
+            if (cancellationCheck()) {
+                System.err.println("CompilerBridge: analyzeAll() — cancelled after $analyzedCount file(s)")
+                result.addProperty("cancelled", true)
+                break
+            }
             val filePath = ktFile.virtualFile.path
 
             // Skip Gradle build scripts (.gradle.kts and .kts in buildSrc/gradle dirs)
@@ -651,8 +989,8 @@ class CompilerBridge {
                 continue
             }
 
-            // Skip files in build/output directories
-            if ("/build/" in filePath || "/.gradle/" in filePath) continue
+            // Skip excludePaths (build output and .gradle by default)
+            if (isExcludedPath(filePath)) continue
 
             val fileUri = "file://$filePath"
 
@@ -719,6 +1057,12 @@ class CompilerBridge {
                             "WARNING" -> warningCount++
                         }
                     }
+                    effectiveKtFile.viewProvider.document?.let { document ->
+                        for (diagObj in unusedImportDiagnostics(effectiveKtFile, document)) {
+                            diagnosticsArray.add(diagObj)
+                            warningCount++
+                        }
+                    }
                 }
             } catch (e: Throwable) {
                 System.err.println("CompilerBridge: analyzeAll() — error analyzing $filePath: ${e.javaClass.name}: ${e.message}")
@@ -945,6 +1289,30 @@ class CompilerBridge {
                     return@analyze
                 }
 
+                // `$name` in string templates and `[name]` in KDoc only complete
+                // identifiers; the `$`/`[` before the prefix is already typed
+                val identifierContext = findIdentifierCompletionContext(ktFile, element, offset)
+                if (identifierContext != null) {
+                    when (identifierContext) {
+                        is KtStringTemplateExpression -> collectTemplateCompletions(ktFile, identifierContext, itemsArray)
+                        is KDoc -> collectKDocLinkCompletions(ktFile, identifierContext, itemsArray)
+                    }
+                    if (itemsArray.size() == 0) {
+                        setFailureReason("no-completion-items")
+                    } else {
+                        result.remove("reason")
+                    }
+                    return@analyze
+                }
+                if (triggerCharacter == "$" || triggerCharacter == "[") {
+                    setFailureReason("trigger-outside-template-or-kdoc")
+                    return@analyze
+                }
+
+                val prefix = extractCompletionPrefix(element, offset)
+                val prefixStart = offset - prefix.length
+                val fileText = ktFile.text
+
                 // Check if we are in a dot-qualified expression (member completion)
                 val dotCompletion = findDotCompletionReceiver(element, offset)
                     ?: if (triggerCharacter == ".") {
@@ -953,14 +1321,21 @@ class CompilerBridge {
                     } else {
                         null
                     }
-                if (dotCompletion != null) {
-                    collectMemberCompletions(dotCompletion, itemsArray)
+                if (tokenTypeAt(ktFile, prefixStart - 2) == KtTokens.COLONCOLON) {
+                    // Callable reference: `String::length`, `::println`
+                    collectCallableReferenceCompletions(ktFile, element, prefixStart - 2, itemsArray)
+                } else if (dotCompletion != null) {
+                    // After `?.` the members of the non-null type apply
+                    val safeCall = fileText.startsWith("?.", prefixStart - 2)
+                    collectMemberCompletions(dotCompletion, itemsArray, nonNullReceiver = safeCall)
+                } else if (isTypeAnnotationColon(ktFile, prefixStart)) {
+                    // `val x: |`, `fun f(): |`, `class A : |` only take types
+                    collectTypeCompletions(ktFile, element, prefix, itemsArray)
                 } else {
                     // Scope-based completion: gather declarations from enclosing scopes
                     collectScopeCompletions(ktFile, element, itemsArray)
 
                     // Append unimported symbols from the index
-                    val prefix = extractCompletionPrefix(element, offset)
                     if (prefix.isNotEmpty()) {
                         appendUnimportedCompletions(ktFile, prefix, itemsArray)
                     }
@@ -978,10 +1353,42 @@ class CompilerBridge {
             System.err.println("CompilerBridge: $reason")
         }
 
+        addPrefixTextEdits(ktFile, line, character, itemsArray)
         result.add("items", itemsArray)
         return result
     }
 
+    /**
+     * Gives each item a `textEdit` replacing the identifier prefix typed
+     * before the cursor, so accepting `println` after `pri` doesn't leave
+     * `priprintln` in clients that insert at the cursor.
+     */
+    private fun addPrefixTextEdits(ktFile: KtFile, line: Int, character: Int, itemsArray: JsonArray) {
+        val document = ktFile.viewProvider.document ?: return
+        val offset = lineColToOffset(ktFile, line, character) ?: return
+        val text = ktFile.text
+        var start = offset
+        while (start > 0 && (text[start - 1].isLetterOrDigit() || text[start - 1] == '_')) {
+            start--
+        }
+        val (startLine, startCol) = offsetToLineCol(document, start)
+        val (endLine, endCol) = offsetToLineCol(document, offset)
+
+        for (element in itemsArray) {
+            val item = element.asJsonObject
+            val newText = item.get("insertText")?.asString ?: item.get("label")?.asString ?: continue
+            val range = JsonObject()
+            range.addProperty("startLine", startLine)
+            range.addProperty("startColumn", startCol)
+            range.addProperty("endLine", endLine)
+            range.addProperty("endColumn", endCol)
+            val textEdit = JsonObject()
+            textEdit.add("range", range)
+            textEdit.addProperty("newText", newText)
+            item.add("textEdit", textEdit)
+        }
+    }
+
     /**
      * Provides go-to-definition locations.
      */
@@ -1114,6 +1521,71 @@ class CompilerBridge {
         return result
     }
 
+    /**
+     * Finds the code KSP/KAPT generated for the class declared at the cursor,
+     * or named by a reference there: declarations under the generated source
+     * roots whose name extends the class name (`UserDao_Impl`,
+     * `UserJsonAdapter`) or whose header mentions it (`: UserDao`,
+     * `JsonAdapter<User>`). Generated Java is searched too. Returns
+     * `{locations[]}`, name matches first, with a `reason` when there are none.
+     */
+    fun revealGenerated(uri: String, line: Int, character: Int): JsonObject {
+        val result = JsonObject()
+        val locationsArray = JsonArray()
+        result.add("locations", locationsArray)
+        val nothing = { reason: String -> result.also { it.addProperty("reason", reason) } }
+
+        val generatedRoots = initGeneratedSourceRoots.map { Paths.get(it) }.filter { Files.isDirectory(it) }
+        if (generatedRoots.isEmpty()) return nothing("no generated source roots are configured")
+        val currentSession = session ?: return nothing("the analysis session is not ready")
+        val ktFile = findKtFile(currentSession, uri) ?: return nothing("$uri is not part of the session")
+        val offset = lineColToOffset(ktFile, line, character) ?: return nothing("position is outside $uri")
+        val className = ktFile.findElementAt(offset)?.let { generatedCodeTarget(it) }
+            ?: return nothing("no class declaration or reference at the cursor")
+
+        val mentionsClass = Regex("""\b${Regex.escape(className)}\b""")
+        val matches = mutableListOf<Pair<Boolean, JsonObject>>()
+        for (root in generatedRoots) {
+            root.toFile().walk()
+                .filter { it.isFile && (it.extension == "kt" || it.extension == "java") }
+                .forEach { file ->
+                    val text = try { file.readText() } catch (_: Exception) { return@forEach }
+                    for (match in GENERATED_DECLARATION_PATTERN.findAll(text)) {
+                        val byName = isGeneratedName(match.groupValues[1], className)
+                        if (!byName && !mentionsClass.containsMatchIn(match.groupValues[2])) continue
+                        val nameOffset = match.groups[1]!!.range.first
+                        val before = text.substring(0, nameOffset)
+                        val location = JsonObject()
+                        location.addProperty("uri", buildFileUri(file.absolutePath))
+                        location.addProperty("line", before.count { it == '\n' } + 1)
+                        location.addProperty("column", nameOffset - before.lastIndexOf('\n') - 1)
+                        matches.add(byName to location)
+                    }
+                }
+        }
+
+        matches.sortedWith(compareBy({ !it.first }, { it.second.get("uri").asString }))
+            .forEach { locationsArray.add(it.second) }
+        if (locationsArray.isEmpty) {
+            return nothing("nothing under the generated source roots was generated for '$className'")
+        }
+        return result
+    }
+
+    /** The class name at [element]: a capitalized reference, or the enclosing class. */
+    private fun generatedCodeTarget(element: PsiElement): String? {
+        val reference = PsiTreeUtil.getParentOfType(element, KtNameReferenceExpression::class.java, false)
+        reference?.getReferencedName()?.takeIf { it.firstOrNull()?.isUpperCase() == true }?.let { return it }
+        return PsiTreeUtil.getParentOfType(element, KtClassOrObject::class.java, false)?.name
+    }
+
+    private fun isGeneratedName(name: String, className: String): Boolean {
+        if (name == "Dagger$className") return true
+        if (name == className || !name.startsWith(className)) return false
+        val suffix = name.removePrefix(className)
+        return suffix.first() == '_' || suffix.first() == '$' || suffix in GENERATED_NAME_SUFFIXES
+    }
+
     /**
      * Builds a definition location from a KaDeclarationSymbol using the Analysis API.
      * Handles both source files and library symbols (inside JARs).
@@ -1257,15 +1729,64 @@ class CompilerBridge {
         }
     }
 
+    /**
+     * Returns the decompiled text of a class file inside a library jar, so the
+     * server can write it to disk for go-to-definition. Kotlin classes decompile
+     * to Kotlin stubs (`language: "kotlin"`), Java classes to Java stubs
+     * (`language: "java"`). Returns an empty object when the class is not found.
+     */
+    fun decompile(jarPath: String, entry: String): JsonObject {
+        val result = JsonObject()
+        val currentSession = session ?: return result
+
+        try {
+            val psiFile = findLibraryPsiFile(currentSession, "$jarPath!/$entry") ?: return result
+            val text = psiFile.text ?: return result
+            result.addProperty("text", text)
+            result.addProperty("language", if (psiFile is KtFile) "kotlin" else "java")
+        } catch (e: Throwable) {
+            System.err.println("CompilerBridge: decompile failed for $jarPath!/$entry: ${e.javaClass.simpleName}: ${e.message?.take(100)}")
+        }
+        return result
+    }
+
+    /**
+     * Finds the PSI file of a class inside a library jar by its `lib.jar!/a/B.class` path.
+     */
+    private fun findLibraryPsiFile(session: StandaloneAnalysisAPISession, jarEntryPath: String): com.intellij.psi.PsiFile? {
+        val virtualFile = com.intellij.openapi.vfs.StandardFileSystems.jar().findFileByPath(jarEntryPath) ?: return null
+        return com.intellij.psi.PsiManager.getInstance(session.project).findFile(virtualFile)
+    }
+
     /**
      * Provides find-references locations within the same file.
      * In standalone mode without full indexing, we search for references
      * to the target declaration by walking the PSI tree of the containing file.
+     * With [onPartial], the locations found in each file are reported as soon
+     * as the file is searched, and the result holds only the rest.
+     * [scope] limits the files searched, see [inReferencesScope]. The search
+     * stops once [limit] locations are found.
      */
-    fun references(uri: String, line: Int, character: Int): JsonObject {
+    fun references(
+        uri: String,
+        line: Int,
+        character: Int,
+        onPartial: ((JsonArray) -> Unit)? = null,
+        scope: String = REFERENCES_SCOPE_ALL,
+        limit: Int = Int.MAX_VALUE,
+    ): JsonObject {
         val result = JsonObject()
         val locationsArray = JsonArray()
         val perfStart = System.currentTimeMillis()
+        // Locations before this index were already handed to onPartial.
+        var streamed = 0
+        fun flushPartial() {
+            if (onPartial == null || locationsArray.size() == streamed) return
+            val batch = JsonArray()
+            for (i in streamed until locationsArray.size()) batch.add(locationsArray[i])
+            streamed = locationsArray.size()
+            onPartial(batch)
+        }
 
         val currentSession = session ?: run {
             result.add("locations", locationsArray)
@@ -1276,6 +1797,9 @@ class CompilerBridge {
             return result
         }
 
+        val originPath = ktFile.virtualFile?.path ?: uri.removePrefix("file://")
+        fun inScope(path: String) = inReferencesScope(path, scope, originPath)
+
         try {
             ensurePebbleSpringIndexCurrent()
 
@@ -1306,7 +1830,7 @@ class CompilerBridge {
                 // Also add the declaration itself as a reference location
                 val resolvedDeclaration = targetDeclaration ?: return@analyze
                 val declDocument = containingFile?.viewProvider?.document
-                if (declDocument != null) {
+                if (declDocument != null && inScope(containingFile.virtualFile.path)) {
                     val declLine = declDocument.getLineNumber(resolvedDeclaration.textOffset) + 1
                     val declLineStart = declDocument.getLineStartOffset(declDocument.getLineNumber(resolvedDeclaration.textOffset))
                     val declCol = resolvedDeclaration.textOffset - declLineStart
@@ -1329,6 +1853,12 @@ class CompilerBridge {
                 .filterIsInstance<KtFile>()
 
             for (sessionFile in allSessionFiles) {
+                if (cancellationCheck()) {
+                    System.err.println("CompilerBridge: references() — cancelled")
+                    break
+                }
+                if (locationsArray.size() >= limit) break
+                if (!inScope(sessionFile.virtualFile.path)) continue
                 try {
                     analyze(sessionFile) {
                         val nameExprs = PsiTreeUtil.collectElementsOfType(
@@ -1363,7 +1893,7 @@ class CompilerBridge {
                                                 existing.get("line")?.asInt == refLine &&
                                                 existing.get("column")?.asInt == refCol
                                         }
-                                        if (!isDup) locationsArray.add(loc)
+                                        if (!isDup && locationsArray.size() < limit) locationsArray.add(loc)
                                     }
                                 }
                             } catch (_: Exception) {
@@ -1374,12 +1904,15 @@ class CompilerBridge {
                 } catch (_: Exception) {
                     // Skip files that fail to analyze
                 }
+                flushPartial()
             }
 
             targetDeclaration?.let { declaration ->
                 val declarationKtFile = declaration.containingFile as? KtFile
                 val declarationUri = declarationKtFile?.virtualFile?.path?.let { "file://$it" } ?: uri
                 for (usage in pebbleSpringIndex.pebbleUsagesForDeclaration(declaration, declarationUri)) {
+                    if (locationsArray.size() >= limit) break
+                    if (!inScope(usage.uri.removePrefix("file://"))) continue
                     val isDup = (0 until locationsArray.size()).any { i ->
                         val existing = locationsArray[i].asJsonObject
                         existing.get("uri")?.asString == usage.uri &&
@@ -1400,18 +1933,43 @@ class CompilerBridge {
         }
 
         System.err.println("[PERF] method=references uri=$uri elapsed=${System.currentTimeMillis() - perfStart}ms")
-        result.add("locations", locationsArray)
+        val remaining = JsonArray()
+        for (i in streamed until locationsArray.size()) remaining.add(locationsArray[i])
+        result.add("locations", remaining)
         return result
     }
 
+    /**
+     * Whether a reference in the file at [path] belongs in the results of a
+     * search started from [originPath]. `excludeTests` drops test source sets;
+     * `currentModule` keeps the files of the origin's module, the directory
+     * holding its `src` folder.
+     */
+    private fun inReferencesScope(path: String, scope: String, originPath: String): Boolean = when (scope) {
+        REFERENCES_SCOPE_EXCLUDE_TESTS -> !isTestSourcePath(projectRelativePath(path))
+        REFERENCES_SCOPE_CURRENT_MODULE -> moduleOf(projectRelativePath(path)) == moduleOf(projectRelativePath(originPath))
+        else -> true
+    }
+
+    /** [path] relative to the project root, so directories above it don't count as source sets. */
+    private fun projectRelativePath(path: String): String {
+        val root = initProjectRoot.trimEnd('/')
+        return if (root.isNotEmpty() && path.startsWith("$root/")) path.substring(root.length) else path
+    }
+
+    /** The module directory of a project-relative [path]: everything before its first `/src/`. */
+    private fun moduleOf(path: String): String? =
+        path.indexOf("/src/").takeIf { it >= 0 }?.let { path.substring(0, it) }
+
     /**
      * Provides signature help for function calls at the given position.
      * Finds the enclosing call expression, resolves the callee, and returns
      * parameter information with the active parameter index.
      */
-    fun signatureHelp(uri: String, line: Int, character: Int): JsonObject {
+    fun signatureHelp(uri: String, line: Int, character: Int, previousSignature: String? = null): JsonObject {
         val result = JsonObject()
         val signaturesArray = JsonArray()
+        var activeSignature = 0
 
         val currentSession = session ?: run {
             result.add("signatures", signaturesArray)
@@ -1445,70 +2003,29 @@ class CompilerBridge {
                     return@analyze
                 }
 
-                // Determine the active parameter index based on cursor position
-                val activeParam = computeActiveParameter(callExpression, offset)
+                // Determine the argument under the cursor; each overload maps
+                // it to its own parameter index
+                val activeArgument = computeActiveArgument(callExpression, offset)
 
-                // Resolve the call to get signature information
-                val callInfo = callExpression.resolveToCall()
-                if (callInfo is KaSuccessCallInfo) {
-                    val call = callInfo.call
+                // Resolve the call; while arguments are incomplete resolution
+                // fails, so fall back to the candidate overloads
+                val calls = when (val callInfo = callExpression.resolveToCall()) {
+                    is KaSuccessCallInfo -> listOf(callInfo.call)
+                    is org.jetbrains.kotlin.analysis.api.resolution.KaErrorCallInfo -> callInfo.candidateCalls
+                    else -> emptyList()
+                }
+                for (call in calls) {
                     if (call is KaFunctionCall<*>) {
-                        val signature = call.partiallyAppliedSymbol.signature
-                        val functionSymbol = signature.symbol
-
-                        // Build the signature label
-                        val paramLabels = mutableListOf<String>()
-                        val paramObjects = JsonArray()
-
-                        for (paramSig in signature.valueParameters) {
-                            val paramName = paramSig.name.asString()
-                            val paramType = paramSig.returnType.render(
-                                KaTypeRendererForSource.WITH_SHORT_NAMES,
-                                Variance.INVARIANT
-                            )
-                            val paramLabel = "$paramName: $paramType"
-                            paramLabels.add(paramLabel)
-
-                            val paramObj = JsonObject()
-                            paramObj.addProperty("label", paramLabel)
-                            paramObjects.add(paramObj)
-                        }
-
-                        val functionName = when (functionSymbol) {
-                            is KaNamedFunctionSymbol -> functionSymbol.name.asString()
-                            is KaConstructorSymbol -> {
-                                val containingClass = functionSymbol.containingDeclaration
-                                if (containingClass is KaNamedSymbol) {
-                                    containingClass.name.asString()
-                                } else {
-                                    "<init>"
-                                }
-                            }
-                            else -> "invoke"
-                        }
-
-                        val returnType = signature.returnType.render(
-                            KaTypeRendererForSource.WITH_SHORT_NAMES,
-                            Variance.INVARIANT
-                        )
-                        val signatureLabel = "$functionName(${paramLabels.joinToString(", ")}): $returnType"
-
-                        val sigObj = JsonObject()
-                        sigObj.addProperty("label", signatureLabel)
-                        sigObj.add("parameters", paramObjects)
-                        sigObj.addProperty("activeParameter", activeParam)
-
-                        // Add KDoc documentation if available
-                        val psi = functionSymbol.psi
-                        if (psi is KtDeclaration) {
-                            val kdocText = extractKDocText(psi)
-                            if (kdocText != null) {
-                                sigObj.addProperty("documentation", kdocText)
-                            }
-                        }
+                        signaturesArray.add(signatureObject(call, activeArgument))
+                    }
+                }
 
-                        signaturesArray.add(sigObj)
+                // Keep the overload the editor is already showing selected
+                if (previousSignature != null) {
+                    val index = signaturesArray.indexOfFirst {
+                        it.asJsonObject.get("label")?.asString == previousSignature
                     }
+                    if (index >= 0) activeSignature = index
                 }
             }
         } catch (e: Throwable) {
@@ -1516,10 +2033,79 @@ class CompilerBridge {
         }
 
         result.add("signatures", signaturesArray)
-        result.addProperty("activeSignature", 0)
+        result.addProperty("activeSignature", activeSignature)
         return result
     }
 
+    /**
+     * Renders one resolved or candidate call as a signature-help entry.
+     * Must be called inside an `analyze` block.
+     */
+    private fun org.jetbrains.kotlin.analysis.api.KaSession.signatureObject(
+        call: KaFunctionCall<*>,
+        activeArgument: ActiveArgument,
+    ): JsonObject {
+        val signature = call.partiallyAppliedSymbol.signature
+        val functionSymbol = signature.symbol
+
+        // Build the signature label
+        val paramLabels = mutableListOf<String>()
+        val paramObjects = JsonArray()
+
+        for (paramSig in signature.valueParameters) {
+            val paramName = paramSig.name.asString()
+            val paramType = paramSig.returnType.render(
+                KaTypeRendererForSource.WITH_SHORT_NAMES,
+                Variance.INVARIANT
+            )
+            val paramLabel = "$paramName: $paramType"
+            paramLabels.add(paramLabel)
+
+            val paramObj = JsonObject()
+            paramObj.addProperty("label", paramLabel)
+            paramObjects.add(paramObj)
+        }
+
+        val functionName = when (functionSymbol) {
+            is KaNamedFunctionSymbol -> functionSymbol.name.asString()
+            is KaConstructorSymbol -> {
+                val containingClass = functionSymbol.containingDeclaration
+                if (containingClass is KaNamedSymbol) {
+                    containingClass.name.asString()
+                } else {
+                    "<init>"
+                }
+            }
+            else -> "invoke"
+        }
+
+        val returnType = signature.returnType.render(
+            KaTypeRendererForSource.WITH_SHORT_NAMES,
+            Variance.INVARIANT
+        )
+        val signatureLabel = "$functionName(${paramLabels.joinToString(", ")}): $returnType"
+
+        val sigObj = JsonObject()
+        sigObj.addProperty("label", signatureLabel)
+        sigObj.add("parameters", paramObjects)
+        val parameterNames = signature.valueParameters.map { it.name.asString() }
+        val lastIsVararg = signature.valueParameters.lastOrNull()?.symbol?.isVararg == true
+        val activeParam = parameterIndexFor(activeArgument, parameterNames, lastIsVararg)
+        if (activeParam >= 0) {
+            sigObj.addProperty("activeParameter", activeParam)
+        }
+
+        // Add KDoc documentation if available
+        val psi = functionSymbol.psi
+        if (psi is KtDeclaration) {
+            val kdocText = extractKDocText(psi)
+            if (kdocText != null) {
+                sigObj.addProperty("documentation", kdocText)
+            }
+        }
+        return sigObj
+    }
+
     /**
      * Provides rename edits for a symbol at the given position.
      * Finds the declaration and all references within the file,
@@ -1646,17 +2232,24 @@ class CompilerBridge {
     }
 
     /**
-     * Provides code actions at the given position.
+     * Provides code actions for the given range; [endLine] and [endCharacter]
+     * default to the start position for cursor-only requests.
      * Includes:
      * - Diagnostic-based quick-fixes (suppress warning, add import)
      * - Context-aware refactoring actions (add/remove explicit type, convert body style)
      */
-    fun codeActions(uri: String, line: Int, character: Int): JsonObject {
+    fun codeActions(
+        uri: String,
+        line: Int,
+        character: Int,
+        endLine: Int = line,
+        endCharacter: Int = character,
+    ): JsonObject {
         val result = JsonObject()
         val actionsArray = JsonArray()
         val perfStart = System.currentTimeMillis()
 
-        System.err.println("CompilerBridge: codeActions($uri, line=$line, char=$character)")
+        System.err.println("CompilerBridge: codeActions($uri, line=$line, char=$character, endLine=$endLine, endChar=$endCharacter)")
 
         val currentSession = session ?: run {
             System.err.println("CompilerBridge: codeActions — session is NULL")
@@ -1693,52 +2286,15 @@ class CompilerBridge {
                     for (diagnostic in diagnostics) {
                         val textRange = diagnostic.textRanges.firstOrNull() ?: continue
 
-                        // Use line-level matching: check if the cursor line overlaps the diagnostic
+                        // Use line-level matching: check if the selected lines overlap the diagnostic
                         val diagStartLine = document.getLineNumber(textRange.startOffset)
                         val diagEndLine = document.getLineNumber(textRange.endOffset)
-                        val cursorLine = line - 1 // Convert to 0-based
+                        val selectionStartLine = line - 1 // Convert to 0-based
+                        val selectionEndLine = maxOf(endLine, line) - 1
 
-                        if (cursorLine < diagStartLine || cursorLine > diagEndLine) continue
+                        if (selectionEndLine < diagStartLine || selectionStartLine > diagEndLine) continue
 
                         val factoryName = diagnostic.factoryName ?: continue
-                        val severityName = diagnostic.severity.name
-
-                        // Suppress warning action for warnings
-                        if (severityName == "WARNING") {
-                            val action = JsonObject()
-                            action.addProperty("title", "Suppress warning '$factoryName'")
-                            action.addProperty("kind", "quickfix")
-
-                            val actionEdits = JsonArray()
-
-                            val element = ktFile.findElementAt(textRange.startOffset)
-                            val enclosingDeclaration = findEnclosingDeclaration(element)
-
-                            if (enclosingDeclaration != null) {
-                                val declOffset = enclosingDeclaration.textOffset
-                                val declLine = document.getLineNumber(declOffset) + 1
-                                val declLineStart = document.getLineStartOffset(document.getLineNumber(declOffset))
-                                val declCol = declOffset - declLineStart
-
-                                val lineEndOffset = document.getLineEndOffset(document.getLineNumber(declOffset))
-                                val lineText = document.charsSequence.subSequence(declLineStart, lineEndOffset).toString()
-                                val indent = lineText.takeWhile { it == ' ' || it == '\t' }
-
-                                val suppressEdit = JsonObject()
-                                suppressEdit.addProperty("uri", fileUri)
-                                val suppressRange = JsonObject()
-                                suppressRange.addProperty("startLine", declLine)
-                                suppressRange.addProperty("startColumn", declCol)
-                                suppressRange.addProperty("endLine", declLine)
-                                suppressRange.addProperty("endColumn", declCol)
-                                suppressEdit.add("range", suppressRange)
-                                suppressEdit.addProperty("newText", "@Suppress(\"$factoryName\")\n$indent")
-                                actionEdits.add(suppressEdit)
-                            }
-
-                            action.add("edits", actionEdits)
-                            actionsArray.add(action)
-                        }
 
                         if (factoryName == "UNRESOLVED_REFERENCE") {
                             addTypeImportCodeActions(
@@ -1755,6 +2311,7 @@ class CompilerBridge {
                 } catch (e: Exception) {
                     System.err.println("CompilerBridge: diagnostic code actions failed: ${e.message}")
                 }
+                addRemoveUnusedImportActions(ktFile, document, fileUri, line - 1, maxOf(endLine, line) - 1, actionsArray)
 
                 // 2. Context-aware refactoring actions
                 val element = ktFile.findElementAt(offset)
@@ -1772,10 +2329,12 @@ class CompilerBridge {
                     addMoveFromCompanionAction(element, document, fileUri, actionsArray)
                     addMoveToTopLevelAction(element, document, fileUri, actionsArray, ktFile)
                     addConvertToExtensionFunctionAction(element, document, fileUri, actionsArray)
+                    addChangeSignatureActions(element, document, fileUri, actionsArray)
                 }
 
-                // 3. Source actions: Organize imports
+                // 3. Source actions: Organize imports, remove all unused imports
                 addOrganizeImportsAction(ktFile, document, fileUri, actionsArray)
+                addRemoveAllUnusedImportsAction(ktFile, document, fileUri, actionsArray)
             }
         } catch (e: Throwable) {
             System.err.println("CompilerBridge: codeActions failed: ${e.javaClass.name}: ${e.message}")
@@ -1886,26 +2445,85 @@ class CompilerBridge {
     }
 
     /**
-     * Adds "Add explicit type" code action for properties/variables that lack a type annotation.
+     * Adds change signature presets for the parameter at [element]: commands
+     * that remove it (when the body does not use it) or move it one place
+     * left or right. Each command carries the resulting parameter names.
      */
-    private fun org.jetbrains.kotlin.analysis.api.KaSession.addExplicitTypeAction(
+    private fun addChangeSignatureActions(
         element: PsiElement,
         document: com.intellij.openapi.editor.Document,
         fileUri: String,
         actionsArray: JsonArray,
     ) {
-        try {
-            val property = PsiTreeUtil.getParentOfType(element, KtProperty::class.java, false)
-            if (property != null && property.typeReference == null && property.initializer != null) {
-                val nameIdentifier = property.nameIdentifier ?: return
-                val symbol = property.symbol
-                val returnType = symbol.returnType
-                val rendered = returnType.render(
-                    KaTypeRendererForSource.WITH_SHORT_NAMES,
-                    Variance.INVARIANT
-                )
+        val function = PsiTreeUtil.getParentOfType(element, KtNamedFunction::class.java, false) ?: return
+        val parameterList = function.valueParameterList ?: return
+        val onHeader = element == function.nameIdentifier || element == function.funKeyword ||
+            PsiTreeUtil.isAncestor(parameterList, element, false)
+        val nameIdentifier = function.nameIdentifier
+        if (!onHeader || function.name == null || nameIdentifier == null) return
+        val parameters = function.valueParameters
+        val names = parameters.map { it.name ?: return }
+        if (names.isEmpty()) return
+        val (line, character) = offsetToLineCol(document, nameIdentifier.textRange.startOffset)
+
+        fun addAction(title: String, newNames: List<String>) {
+            val arguments = JsonObject().apply {
+                addProperty(AnalyzerCommands.ARG_URI, fileUri)
+                add(AnalyzerCommands.ARG_POSITION, JsonObject().apply {
+                    addProperty(AnalyzerCommands.ARG_LINE, line - 1)
+                    addProperty(AnalyzerCommands.ARG_CHARACTER, character)
+                })
+                add(AnalyzerCommands.ARG_PARAMETERS, JsonArray().also { array -> newNames.forEach(array::add) })
+            }
+            actionsArray.add(JsonObject().apply {
+                addProperty("title", title)
+                addProperty("kind", "refactor")
+                add("command", JsonObject().apply {
+                    addProperty("title", title)
+                    addProperty("command", AnalyzerCommands.CHANGE_SIGNATURE)
+                    add("arguments", JsonArray().also { it.add(arguments) })
+                })
+            })
+        }
 
-                if (rendered.isNotEmpty() && rendered != "Unit" && rendered != "Nothing") {
+        val parameter = PsiTreeUtil.getParentOfType(element, KtParameter::class.java, false) ?: return
+        val index = parameters.indexOf(parameter)
+        if (index < 0) return
+        val name = names[index]
+        if (!isParameterUsed(function, parameter)) {
+            addAction("Remove parameter '$name'", names.filterIndexed { i, _ -> i != index })
+        }
+        if (parameters.none { it.isVarArg }) {
+            if (index > 0) {
+                addAction("Move parameter '$name' left", names.toMutableList().apply { add(index - 1, removeAt(index)) })
+            }
+            if (index < names.lastIndex) {
+                addAction("Move parameter '$name' right", names.toMutableList().apply { add(index + 1, removeAt(index)) })
+            }
+        }
+    }
+
+    /**
+     * Adds "Add explicit type" code action for properties/variables that lack a type annotation.
+     */
+    private fun org.jetbrains.kotlin.analysis.api.KaSession.addExplicitTypeAction(
+        element: PsiElement,
+        document: com.intellij.openapi.editor.Document,
+        fileUri: String,
+        actionsArray: JsonArray,
+    ) {
+        try {
+            val property = PsiTreeUtil.getParentOfType(element, KtProperty::class.java, false)
+            if (property != null && property.typeReference == null && property.initializer != null) {
+                val nameIdentifier = property.nameIdentifier ?: return
+                val symbol = property.symbol
+                val returnType = symbol.returnType
+                val rendered = returnType.render(
+                    KaTypeRendererForSource.WITH_SHORT_NAMES,
+                    Variance.INVARIANT
+                )
+
+                if (rendered.isNotEmpty() && rendered != "Unit" && rendered != "Nothing") {
                     val action = JsonObject()
                     action.addProperty("title", "Add explicit type '$rendered'")
                     action.addProperty("kind", "refactor")
@@ -2098,6 +2716,165 @@ class CompilerBridge {
         actionsArray.add(action)
     }
 
+    /**
+     * Finds imports nothing in the file refers to: explicit imports whose name
+     * (or alias) is never referenced in code or KDoc, and repeats of an earlier
+     * import. Star imports and operator-convention names, which are used
+     * without being spelled out, are always kept.
+     */
+    private fun findUnusedImports(ktFile: KtFile): List<KtImportDirective> {
+        val imports = ktFile.importDirectives
+        if (imports.isEmpty()) return emptyList()
+
+        val referencedNames = mutableSetOf<String>()
+        for (expression in PsiTreeUtil.collectElementsOfType(ktFile, KtSimpleNameExpression::class.java)) {
+            if (PsiTreeUtil.getParentOfType(expression, KtImportList::class.java, KtPackageDirective::class.java) != null) continue
+            referencedNames.add(expression.getReferencedName())
+        }
+        for (link in PsiTreeUtil.collectElementsOfType(ktFile, KDocLink::class.java)) {
+            referencedNames.add(link.getLinkText().substringBefore('.'))
+        }
+
+        val seen = mutableSetOf<String>()
+        return imports.filter { directive ->
+            val importPath = directive.importPath ?: return@filter false
+            if (!seen.add(importPath.toString())) return@filter true
+            if (directive.isAllUnder) return@filter false
+            val name = directive.aliasName ?: directive.importedFqName?.shortName()?.asString() ?: return@filter false
+            name !in referencedNames && name !in OPERATOR_CONVENTION_NAMES && !COMPONENT_NAME.matches(name)
+        }
+    }
+
+    /** [UNUSED_IMPORT] warnings in the `analyze` result format. */
+    private fun unusedImportDiagnostics(
+        ktFile: KtFile,
+        document: com.intellij.openapi.editor.Document,
+    ): List<JsonObject> = findUnusedImports(ktFile).map { directive ->
+        val (startLine, startCol) = offsetToLineCol(document, directive.textRange.startOffset)
+        val (endLine, endCol) = offsetToLineCol(document, directive.textRange.endOffset)
+        val diagObj = JsonObject()
+        diagObj.addProperty("severity", "WARNING")
+        diagObj.addProperty("message", "Unused import directive")
+        diagObj.addProperty("code", UNUSED_IMPORT)
+        diagObj.addProperty("line", startLine)
+        diagObj.addProperty("column", startCol)
+        diagObj.addProperty("endLine", endLine)
+        diagObj.addProperty("endColumn", endCol)
+        diagObj.add("tags", JsonArray().apply { add("unnecessary") })
+        diagObj
+    }
+
+    /**
+     * Adds a "Remove unused import" quick fix for each unused import on the
+     * selected lines (0-based). Each names the diagnostic it fixes so the
+     * server can attach the client's copy.
+     */
+    private fun addRemoveUnusedImportActions(
+        ktFile: KtFile,
+        document: com.intellij.openapi.editor.Document,
+        fileUri: String,
+        selectionStartLine: Int,
+        selectionEndLine: Int,
+        actionsArray: JsonArray,
+    ) {
+        for (directive in findUnusedImports(ktFile)) {
+            val importLine = document.getLineNumber(directive.textRange.startOffset)
+            if (importLine < selectionStartLine || importLine > selectionEndLine) continue
+
+            val action = JsonObject()
+            action.addProperty("title", "Remove unused import")
+            action.addProperty("kind", "quickfix")
+            // The only fix for its diagnostic, so "apply preferred action" can pick it
+            action.addProperty("isPreferred", true)
+            action.addProperty("rank", 0)
+            action.add("edits", removeImportEdits(ktFile, document, fileUri, listOf(directive)))
+            val diagnostic = JsonObject()
+            diagnostic.addProperty("code", UNUSED_IMPORT)
+            diagnostic.addProperty("line", importLine + 1)
+            action.add("diagnostic", diagnostic)
+            actionsArray.add(action)
+        }
+    }
+
+    /** Adds a "Remove all unused imports" source action when the file has any. */
+    private fun addRemoveAllUnusedImportsAction(
+        ktFile: KtFile,
+        document: com.intellij.openapi.editor.Document,
+        fileUri: String,
+        actionsArray: JsonArray,
+    ) {
+        val unused = findUnusedImports(ktFile)
+        if (unused.isEmpty()) return
+
+        val action = JsonObject()
+        action.addProperty("title", "Remove all unused imports")
+        action.addProperty("kind", "source.removeUnusedImports")
+        action.add("edits", removeImportEdits(ktFile, document, fileUri, unused))
+        actionsArray.add(action)
+    }
+
+    /**
+     * Deletes each import's whole line, newline included. When every import
+     * goes, the blank lines that separated the import block from the code
+     * below are deleted too, so no gap is left between the package directive
+     * (or the top of the file) and the first declaration.
+     */
+    private fun removeImportEdits(
+        ktFile: KtFile,
+        document: com.intellij.openapi.editor.Document,
+        fileUri: String,
+        directives: List<KtImportDirective>,
+    ): JsonArray {
+        val sorted = directives.sortedBy { it.textRange.startOffset }
+        val removesAll = ktFile.importDirectives.all { it in directives }
+        val firstLine = document.getLineNumber(sorted.first().textRange.startOffset)
+        val separatedAbove = firstLine == 0 || isBlankLine(document, firstLine - 1)
+
+        val editsArray = JsonArray()
+        for (directive in sorted) {
+            val startOffset = document.getLineStartOffset(document.getLineNumber(directive.textRange.startOffset))
+            var nextLine = document.getLineNumber(directive.textRange.endOffset) + 1
+            if (removesAll && separatedAbove && directive == sorted.last()) {
+                while (nextLine < document.lineCount && isBlankLine(document, nextLine)) nextLine++
+            }
+            val endOffset = if (nextLine < document.lineCount) {
+                document.getLineStartOffset(nextLine)
+            } else {
+                document.textLength
+            }
+            val (startLine, startCol) = offsetToLineCol(document, startOffset)
+            val (endLine, endCol) = offsetToLineCol(document, endOffset)
+            editsArray.add(makeEdit(fileUri, startLine, startCol, endLine, endCol, ""))
+        }
+        return editsArray
+    }
+
+    private fun isBlankLine(document: com.intellij.openapi.editor.Document, line: Int): Boolean =
+        document.charsSequence
+            .subSequence(document.getLineStartOffset(line), document.getLineEndOffset(line))
+            .isBlank()
+
+    /**
+     * Kind and implicit classpath of an open `.kts` script, as sent by the
+     * server, with the URIs of the scripts it imports.
+     */
+    private data class ScriptHint(val kind: String, val classpath: List<String>, val imports: List<String>)
+
+    /**
+     * A `.gradle.kts` script rewritten by [wrapGradleScript]: the script's
+     * first [headerLines] lines are kept in place, followed by
+     * [insertedLines] lines of implicit imports and the function header.
+     */
+    data class GradleScriptWrapper(val text: String, val headerLines: Int, val insertedLines: Int, val scriptLines: Int) {
+        /** Maps a 1-based line of [text] to the script's line, or null for an inserted line. */
+        fun scriptLine(line: Int): Int? = when {
+            line <= headerLines -> line
+            line <= headerLines + insertedLines -> null
+            line - insertedLines <= scriptLines -> line - insertedLines
+            else -> null
+        }
+    }
+
     /**
      * Result of analyzing a declaration body for references to members of its containing class.
      */
@@ -2174,6 +2951,746 @@ class CompilerBridge {
         return BodyAnalysis(refsThis, accessesPrivate)
     }
 
+    /**
+     * Builds a single edit JSON object.
+     */
+    /**
+     * Offers quick fixes that suppress diagnostic [code] with `@Suppress` at
+     * the enclosing statement, function, and file. An existing `@Suppress` is
+     * extended instead of adding a second annotation, and a scope that already
+     * suppresses [code] is not offered.
+     */
+    fun suppress(uri: String, code: String, line: Int, character: Int): JsonObject {
+        val result = JsonObject()
+        val actionsArray = JsonArray()
+        result.add("actions", actionsArray)
+
+        val currentSession = session ?: return result
+        val ktFile = findKtFile(currentSession, uri) ?: return result
+        val document = ktFile.viewProvider.document ?: return result
+        val offset = lineColToOffset(ktFile, line, character) ?: return result
+
+        try {
+            val element = ktFile.findElementAt(offset) ?: return result
+            val function = PsiTreeUtil.getParentOfType(element, KtNamedFunction::class.java)
+            val statement = generateSequence(element) { it.parent }
+                .takeWhile { it !is KtFile }
+                .firstOrNull { it.parent is KtBlockExpression && it.parent.parent !is KtFile }
+                ?.takeIf { it != function }
+
+            if (statement is KtExpression) {
+                suppressStatementEdit(uri, document, statement, code)?.let {
+                    actionsArray.add(suppressAction("Suppress '$code' for statement", it))
+                }
+            }
+            if (function != null) {
+                suppressDeclarationEdit(uri, document, function, code)?.let {
+                    val name = function.name?.let { " '$it'" } ?: ""
+                    actionsArray.add(suppressAction("Suppress '$code' for function$name", it))
+                }
+            }
+            suppressFileEdit(uri, document, ktFile, code)?.let {
+                actionsArray.add(suppressAction("Suppress '$code' for file", it))
+            }
+        } catch (e: Exception) {
+            System.err.println("CompilerBridge: suppress failed for $uri: ${e.message}")
+        }
+
+        return result
+    }
+
+    private fun suppressAction(title: String, edit: JsonObject): JsonObject {
+        val action = JsonObject()
+        action.addProperty("title", title)
+        action.addProperty("kind", "quickfix")
+        action.add("edits", JsonArray().apply { add(edit) })
+        return action
+    }
+
+    /** Annotates a statement: declarations on their own line, expressions inline. */
+    private fun suppressStatementEdit(
+        uri: String,
+        document: com.intellij.openapi.editor.Document,
+        statement: KtExpression,
+        code: String,
+    ): JsonObject? {
+        if (statement is KtDeclaration) return suppressDeclarationEdit(uri, document, statement, code)
+        val existing = (statement as? KtAnnotatedExpression)?.annotationEntries.orEmpty()
+        findSuppressEntry(existing)?.let { return extendSuppressEdit(uri, document, it, code) }
+        val (insertLine, insertCol) = offsetToLineCol(document, statement.textRange.startOffset)
+        return makeEdit(uri, insertLine, insertCol, insertLine, insertCol, "@Suppress(\"$code\") ")
+    }
+
+    private fun suppressDeclarationEdit(
+        uri: String,
+        document: com.intellij.openapi.editor.Document,
+        declaration: KtDeclaration,
+        code: String,
+    ): JsonObject? {
+        findSuppressEntry(declaration.annotationEntries)?.let { return extendSuppressEdit(uri, document, it, code) }
+        val startOffset = declaration.textRange.startOffset
+        val (insertLine, insertCol) = offsetToLineCol(document, startOffset)
+        val indent = indentAt(document, startOffset)
+        return makeEdit(uri, insertLine, insertCol, insertLine, insertCol, "@Suppress(\"$code\")\n$indent")
+    }
+
+    private fun suppressFileEdit(
+        uri: String,
+        document: com.intellij.openapi.editor.Document,
+        ktFile: KtFile,
+        code: String,
+    ): JsonObject? {
+        val fileAnnotations = ktFile.fileAnnotationList
+        findSuppressEntry(fileAnnotations?.annotationEntries.orEmpty())?.let {
+            return extendSuppressEdit(uri, document, it, code)
+        }
+        // File annotations go before the package directive (or first declaration).
+        val anchor = fileAnnotations?.takeIf { it.textLength > 0 }
+            ?: ktFile.packageDirective?.takeIf { it.textLength > 0 }
+            ?: ktFile.importList?.takeIf { it.textLength > 0 }
+            ?: ktFile.declarations.firstOrNull()
+        val offset = anchor?.textRange?.startOffset ?: 0
+        val (insertLine, insertCol) = offsetToLineCol(document, offset)
+        val separator = if (fileAnnotations?.annotationEntries.isNullOrEmpty()) "\n\n" else "\n"
+        return makeEdit(uri, insertLine, insertCol, insertLine, insertCol, "@file:Suppress(\"$code\")$separator")
+    }
+
+    private fun findSuppressEntry(entries: List<KtAnnotationEntry>): KtAnnotationEntry? =
+        entries.firstOrNull { it.shortName?.asString() == "Suppress" }
+
+    /**
+     * Adds [code] to an existing `@Suppress(...)`, or returns null when it is
+     * already listed (or the annotation has no argument list to extend).
+     */
+    private fun extendSuppressEdit(
+        uri: String,
+        document: com.intellij.openapi.editor.Document,
+        entry: KtAnnotationEntry,
+        code: String,
+    ): JsonObject? {
+        val arguments = entry.valueArgumentList ?: return null
+        val listed = arguments.arguments.mapNotNull {
+            (it.getArgumentExpression() as? KtStringTemplateExpression)?.entries?.joinToString("") { e -> e.text }
+        }
+        if (code in listed) return null
+        val rightParen = arguments.rightParenthesis ?: return null
+        val (insertLine, insertCol) = offsetToLineCol(document, rightParen.textRange.startOffset)
+        val newText = if (arguments.arguments.isEmpty()) "\"$code\"" else ", \"$code\""
+        return makeEdit(uri, insertLine, insertCol, insertLine, insertCol, newText)
+    }
+
+    /**
+     * Offers "Extract to local variable" and "Extract to function" for a
+     * selection covering exactly one expression. A selection that spans a
+     * partial expression yields no actions rather than a broken edit.
+     */
+    fun extract(uri: String, startLine: Int, startColumn: Int, endLine: Int, endColumn: Int): JsonObject {
+        val result = JsonObject()
+        val actionsArray = JsonArray()
+        result.add("actions", actionsArray)
+
+        val currentSession = session ?: return result
+        val ktFile = findKtFile(currentSession, uri) ?: return result
+        val document = ktFile.viewProvider.document ?: return result
+        var start = lineColToOffset(ktFile, startLine, startColumn) ?: return result
+        var end = lineColToOffset(ktFile, endLine, endColumn) ?: return result
+        val text = document.charsSequence
+        while (start < end && text[start].isWhitespace()) start++
+        while (end > start && text[end - 1].isWhitespace()) end--
+        if (start >= end) return result
+
+        try {
+            val expression = findExtractableExpression(ktFile, start, end)
+            if (expression == null) {
+                actionsArray.add(disabledAction("Extract to local variable", "refactor.extract", "The selection is not a complete expression"))
+                return result
+            }
+            val name = uniqueIdentifier(ktFile.text, "extracted")
+            val (exprStartLine, exprStartCol) = offsetToLineCol(document, start)
+            val (exprEndLine, exprEndCol) = offsetToLineCol(document, end)
+
+            // Extract to local variable: declare before the enclosing statement.
+            val statement = generateSequence(expression as PsiElement) { it.parent }
+                .takeWhile { it !is KtFile }
+                .firstOrNull { it.parent is KtBlockExpression && it.parent.parent !is KtFile }
+            if (statement != null && statement != expression) {
+                val (statementLine, _) = offsetToLineCol(document, statement.textRange.startOffset)
+                val indent = indentAt(document, statement.textRange.startOffset)
+                val edits = JsonArray()
+                edits.add(makeEdit(uri, statementLine, 0, statementLine, 0, "${indent}val $name = ${expression.text}\n"))
+                edits.add(makeEdit(uri, exprStartLine, exprStartCol, exprEndLine, exprEndCol, name))
+                val action = JsonObject()
+                action.addProperty("title", "Extract to local variable")
+                action.addProperty("kind", "refactor.extract")
+                action.add("edits", edits)
+                actionsArray.add(action)
+            }
+
+            // Extract to function: only when the expression uses no locals,
+            // since the new function takes no parameters.
+            val container = generateSequence(expression as PsiElement) { it.parent }
+                .filterIsInstance<KtDeclaration>()
+                .firstOrNull { it.parent is KtFile || it.parent is KtClassBody }
+            if (container != null && !referencesLocals(expression, container)) {
+                val indent = indentAt(document, container.textRange.startOffset)
+                val (containerEndLine, containerEndCol) = offsetToLineCol(document, container.textRange.endOffset)
+                val edits = JsonArray()
+                edits.add(makeEdit(uri, exprStartLine, exprStartCol, exprEndLine, exprEndCol, "$name()"))
+                edits.add(
+                    makeEdit(
+                        uri, containerEndLine, containerEndCol, containerEndLine, containerEndCol,
+                        "\n\n${indent}private fun $name() = ${expression.text}",
+                    )
+                )
+                val action = JsonObject()
+                action.addProperty("title", "Extract to function")
+                action.addProperty("kind", "refactor.extract")
+                action.add("edits", edits)
+                actionsArray.add(action)
+            } else if (container != null) {
+                actionsArray.add(
+                    disabledAction(
+                        "Extract to function", "refactor.extract",
+                        "The selection uses local variables, which the extracted function cannot take as parameters",
+                    )
+                )
+            }
+        } catch (e: Exception) {
+            System.err.println("CompilerBridge: extract failed for $uri: ${e.message}")
+        }
+
+        return result
+    }
+
+    /** A code action shown grayed out, with [reason] explaining why it cannot run. */
+    private fun disabledAction(title: String, kind: String, reason: String): JsonObject {
+        val action = JsonObject()
+        action.addProperty("title", title)
+        action.addProperty("kind", kind)
+        action.add("disabled", JsonObject().apply { addProperty("reason", reason) })
+        return action
+    }
+
+    /**
+     * Offers "Inline variable" for the local or top-level `val`/`var` at or
+     * referenced at the cursor: every usage is replaced with the initializer
+     * and the declaration is removed. Usages may span files when the
+     * initializer references no names. Returns no action, with a `reason`,
+     * when the variable is reassigned, its initializer cannot be duplicated,
+     * or moving the initializer could change its value: unless it is a
+     * constant or reads only vals, the single usage must be in the statement
+     * right after the declaration.
+     */
+    fun inline(uri: String, line: Int, character: Int): JsonObject {
+        val result = JsonObject()
+        val actionsArray = JsonArray()
+        result.add("actions", actionsArray)
+        val refuse = { reason: String ->
+            System.err.println("CompilerBridge: inline($uri:$line:$character) — not offered: $reason")
+            result.addProperty("reason", reason)
+        }
+
+        val currentSession = session ?: return result
+        val ktFile = findKtFile(currentSession, uri) ?: return result
+        val offset = lineColToOffset(ktFile, line, character) ?: return result
+
+        try {
+            analyze(ktFile) {
+                val element = ktFile.findElementAt(offset) ?: return@analyze
+                val property = findTargetDeclaration(element) as? KtProperty ?: return@analyze
+                if (!property.isLocal && property.parent !is KtFile) {
+                    refuse("'${property.name}' is a member property")
+                    return@analyze
+                }
+                val initializer = property.initializer
+                if (initializer == null || property.hasDelegate() || property.getter != null) {
+                    refuse("'${property.name}' has no plain initializer")
+                    return@analyze
+                }
+                val declaringFile = property.containingKtFile
+
+                // Usages, grouped by file; only top-level, non-private
+                // properties can be used outside the declaring file
+                val candidateFiles = if (property.isLocal || property.hasModifier(KtTokens.PRIVATE_KEYWORD)) {
+                    listOf(declaringFile)
+                } else {
+                    listOf(declaringFile) + currentSession.modulesWithFiles.values.flatten()
+                        .filterIsInstance<KtFile>()
+                        .filter { it.virtualFile?.path != declaringFile.virtualFile?.path }
+                }
+                val usages = candidateFiles.associateWith { file ->
+                    PsiTreeUtil.collectElementsOfType(file, KtNameReferenceExpression::class.java).filter { nameExpr ->
+                        nameExpr.getReferencedName() == property.name &&
+                            PsiTreeUtil.getParentOfType(nameExpr, KtImportDirective::class.java) == null &&
+                            nameExpr.references.any { ref ->
+                                try { ref.resolve() == property } catch (_: Exception) { false }
+                            }
+                    }
+                }.filterValues { it.isNotEmpty() }
+                val allUsages = usages.values.flatten()
+
+                if (allUsages.any { isAssignmentTarget(it) }) {
+                    refuse("'${property.name}' is reassigned")
+                    return@analyze
+                }
+                val singleUsage = allUsages.singleOrNull()
+                if (!readsOnlyVals(initializer) && (singleUsage == null || !immediatelyFollows(property, singleUsage))) {
+                    refuse("the initializer of '${property.name}' may evaluate differently at its usages")
+                    return@analyze
+                }
+                val duplicatesEvaluation = allUsages.size != 1 ||
+                    isInsideLoopOrLambda(allUsages.single(), property.parent)
+                if (duplicatesEvaluation && !isSideEffectFree(initializer)) {
+                    refuse("the initializer of '${property.name}' may have side effects and is used ${allUsages.size} time(s)")
+                    return@analyze
+                }
+                val otherFiles = usages.keys.filter { it != declaringFile }
+                if (otherFiles.isNotEmpty() &&
+                    PsiTreeUtil.findChildrenOfType(initializer, KtNameReferenceExpression::class.java).isNotEmpty()
+                ) {
+                    refuse("the initializer of '${property.name}' references names that may not be visible in ${otherFiles.first().name}")
+                    return@analyze
+                }
+
+                val edits = JsonArray()
+                val declarationUri = if (declaringFile == ktFile) uri else pathToUri(declaringFile.virtualFile.path)
+                for ((file, fileUsages) in usages) {
+                    val document = file.viewProvider.document ?: continue
+                    val fileUri = if (file == declaringFile) declarationUri else pathToUri(file.virtualFile.path)
+                    for (usage in fileUsages) {
+                        val (target, newText) = inlinedUsage(usage, initializer)
+                        val (startLine, startCol) = offsetToLineCol(document, target.textRange.startOffset)
+                        val (endLine, endCol) = offsetToLineCol(document, target.textRange.endOffset)
+                        edits.add(makeEdit(fileUri, startLine, startCol, endLine, endCol, newText))
+                    }
+                }
+                val declarationDocument = declaringFile.viewProvider.document ?: return@analyze
+                edits.add(deleteLinesEdit(declarationDocument, declarationUri, property))
+
+                val action = JsonObject()
+                action.addProperty("title", "Inline variable '${property.name}'")
+                action.addProperty("kind", "refactor.inline")
+                action.add("edits", edits)
+                actionsArray.add(action)
+            }
+        } catch (e: Exception) {
+            System.err.println("CompilerBridge: inline failed for $uri: ${e.message}")
+        }
+
+        return result
+    }
+
+    /**
+     * Whether [usage] is written to: `x = ...`, `x += ...`, `x++`, `--x`,
+     * also as `pkg.x` or `(x)`.
+     */
+    private fun isAssignmentTarget(usage: KtExpression): Boolean {
+        var target: PsiElement = usage
+        while (true) {
+            val enclosing = target.parent
+            target = when {
+                enclosing is KtQualifiedExpression && enclosing.selectorExpression == target -> enclosing
+                enclosing is KtParenthesizedExpression -> enclosing
+                else -> break
+            }
+        }
+        val parent = target.parent
+        return when (parent) {
+            is KtBinaryExpression -> parent.left == target && parent.operationToken in KtTokens.ALL_ASSIGNMENTS
+            is KtUnaryExpression -> parent.operationToken == KtTokens.PLUSPLUS || parent.operationToken == KtTokens.MINUSMINUS
+            else -> false
+        }
+    }
+
+    /**
+     * Whether [expression] has the same value wherever it is evaluated: a
+     * side-effect-free expression whose names are all vals, parameters, or
+     * classes and objects.
+     */
+    private fun readsOnlyVals(expression: KtExpression): Boolean {
+        if (!isSideEffectFree(expression)) return false
+        val names = PsiTreeUtil.collectElementsOfType(expression, KtNameReferenceExpression::class.java) +
+            listOfNotNull(expression as? KtNameReferenceExpression)
+        return names.all { name ->
+            val target = try {
+                name.references.firstNotNullOfOrNull { it.resolve() }
+            } catch (_: Exception) {
+                null
+            }
+            when (target) {
+                is KtProperty -> !target.isVar && target.getter == null && !target.hasDelegate()
+                is KtParameter -> !target.isMutable
+                is KtClassOrObject -> true
+                else -> false
+            }
+        }
+    }
+
+    /**
+     * Whether [usage] lies in the statement right after the local [property],
+     * outside any loop or lambda, so nothing runs between the initializer's
+     * old and new place.
+     */
+    private fun immediatelyFollows(property: KtProperty, usage: KtExpression): Boolean {
+        val block = property.parent as? KtBlockExpression ?: return false
+        val statements = block.statements
+        val next = statements.getOrNull(statements.indexOf(property) + 1) ?: return false
+        return PsiTreeUtil.isAncestor(next, usage, false) && !isInsideLoopOrLambda(usage, block)
+    }
+
+    /** Whether [usage] may run more than once per evaluation of [scope]. */
+    private fun isInsideLoopOrLambda(usage: PsiElement, scope: PsiElement): Boolean =
+        generateSequence(usage.parent) { it.parent }
+            .takeWhile { it != scope && it !is KtFile }
+            .any { it is KtLoopExpression || it is KtFunctionLiteral }
+
+    /**
+     * Whether evaluating [expression] more than once is indistinguishable from
+     * evaluating it once: literals, names, `this`, property chains, and
+     * operators over those. Any call may have side effects.
+     */
+    private fun isSideEffectFree(expression: KtExpression?): Boolean = when (expression) {
+        null -> true
+        is KtConstantExpression, is KtThisExpression, is KtNameReferenceExpression -> true
+        is KtStringTemplateExpression -> expression.entries.all { isSideEffectFree(it.expression) }
+        is KtParenthesizedExpression -> isSideEffectFree(expression.expression)
+        is KtDotQualifiedExpression ->
+            expression.selectorExpression is KtNameReferenceExpression && isSideEffectFree(expression.receiverExpression)
+        is KtBinaryExpression ->
+            expression.operationToken !in KtTokens.ALL_ASSIGNMENTS &&
+                isSideEffectFree(expression.left) && isSideEffectFree(expression.right)
+        is KtPrefixExpression ->
+            expression.operationToken != KtTokens.PLUSPLUS && expression.operationToken != KtTokens.MINUSMINUS &&
+                isSideEffectFree(expression.baseExpression)
+        else -> false
+    }
+
+    /**
+     * The element a usage's replacement covers and the text to put there: a
+     * package-qualified reference is replaced whole, `$x` in a string becomes
+     * `${initializer}`, and compound initializers are parenthesized where an
+     * operator or receiver would otherwise bind to part of them.
+     */
+    private fun inlinedUsage(usage: KtNameReferenceExpression, initializer: KtExpression): Pair<PsiElement, String> {
+        val parent = usage.parent
+        if (parent is KtSimpleNameStringTemplateEntry) {
+            return parent to "\${${initializer.text}}"
+        }
+        val target = if (parent is KtDotQualifiedExpression && parent.selectorExpression == usage) parent else usage
+        val atomic = initializer is KtConstantExpression || initializer is KtStringTemplateExpression ||
+            initializer is KtNameReferenceExpression || initializer is KtQualifiedExpression ||
+            initializer is KtCallExpression || initializer is KtParenthesizedExpression ||
+            initializer is KtThisExpression || initializer is KtArrayAccessExpression
+        val context = target.parent
+        val bindsTighter = context is KtOperationExpression || context is KtArrayAccessExpression ||
+            (context is KtQualifiedExpression && context.receiverExpression == target)
+        val text = if (!atomic && bindsTighter) "(${initializer.text})" else initializer.text
+        return target to text
+    }
+
+    /**
+     * Deletes the lines [element] occupies, newline included, when nothing
+     * else shares them; otherwise just the element's text.
+     */
+    private fun deleteLinesEdit(
+        document: com.intellij.openapi.editor.Document,
+        fileUri: String,
+        element: PsiElement,
+    ): JsonObject {
+        val text = document.charsSequence
+        val startLine = document.getLineNumber(element.textRange.startOffset)
+        val endLine = document.getLineNumber(element.textRange.endOffset)
+        val lineStart = document.getLineStartOffset(startLine)
+        val lineEnd = document.getLineEndOffset(endLine)
+        val ownLines = text.subSequence(lineStart, element.textRange.startOffset).isBlank() &&
+            text.subSequence(element.textRange.endOffset, lineEnd).isBlank()
+        val (startOffset, endOffset) = when {
+            !ownLines -> element.textRange.startOffset to element.textRange.endOffset
+            endLine + 1 < document.lineCount -> lineStart to document.getLineStartOffset(endLine + 1)
+            else -> lineStart to lineEnd
+        }
+        val (fromLine, fromCol) = offsetToLineCol(document, startOffset)
+        val (toLine, toCol) = offsetToLineCol(document, endOffset)
+        return makeEdit(fileUri, fromLine, fromCol, toLine, toCol, "")
+    }
+
+    /**
+     * Changes the parameters of the function declared at the cursor:
+     * [parameters] names the parameters to keep, in their new order. Returns
+     * `{edits[]}` rewriting the declaration and the arguments of every call,
+     * or no edits and a `reason` when the change is not supported. Type
+     * changes and new parameters are out of scope.
+     */
+    fun changeSignature(uri: String, line: Int, character: Int, parameters: List<String>): JsonObject {
+        val result = JsonObject()
+        val edits = JsonArray()
+        result.add("edits", edits)
+        val refuse = { reason: String ->
+            System.err.println("CompilerBridge: changeSignature($uri:$line:$character) — refused: $reason")
+            result.addProperty("reason", reason)
+        }
+
+        val currentSession = session ?: return result.also { refuse("the analysis session is not ready") }
+        val ktFile = findKtFile(currentSession, uri) ?: return result.also { refuse("$uri is not part of the session") }
+        val offset = lineColToOffset(ktFile, line, character) ?: return result.also { refuse("position is outside $uri") }
+
+        try {
+            analyze(ktFile) {
+                val function = ktFile.findElementAt(offset)
+                    ?.let { PsiTreeUtil.getParentOfType(it, KtNamedFunction::class.java, false) }
+                if (function == null) {
+                    refuse("no function declaration at the cursor")
+                    return@analyze
+                }
+                val functionName = function.name ?: return@analyze
+                val oldParameters = function.valueParameters
+                val oldNames = oldParameters.map { it.name }
+                val newOrder = parameters.map { oldNames.indexOf(it) }
+                if (newOrder == oldNames.indices.toList()) return@analyze
+
+                val removed = oldParameters.filterIndexed { i, _ -> i !in newOrder }
+                val varargIndex = oldParameters.indexOfFirst { it.isVarArg }
+                val problem = when {
+                    parameters.any { it !in oldNames } ->
+                        "'$functionName' has no parameter '${parameters.first { it !in oldNames }}'"
+                    parameters.toSet().size != parameters.size -> "a parameter is listed more than once"
+                    function.hasModifier(KtTokens.OVERRIDE_KEYWORD) || function.hasModifier(KtTokens.OPEN_KEYWORD) ||
+                        function.hasModifier(KtTokens.ABSTRACT_KEYWORD) ||
+                        (function.parent?.parent as? KtClass)?.isInterface() == true ->
+                        "'$functionName' can be overridden; changing override hierarchies is not supported"
+                    function.hasModifier(KtTokens.OPERATOR_KEYWORD) || function.hasModifier(KtTokens.INFIX_KEYWORD) ->
+                        "operator and infix functions are not supported"
+                    varargIndex >= 0 && newOrder != newOrder.sorted() ->
+                        "parameters of a function with a vararg parameter can only be removed"
+                    else -> removed.firstOrNull { isParameterUsed(function, it) }
+                        ?.let { "parameter '${it.name}' is used in '$functionName'" }
+                }
+                if (problem != null) {
+                    refuse(problem)
+                    return@analyze
+                }
+
+                // Calls, by file; local and private functions are only
+                // visible in the declaring file
+                val declaringFile = function.containingKtFile
+                val candidateFiles = if (function.isLocal || function.hasModifier(KtTokens.PRIVATE_KEYWORD)) {
+                    listOf(declaringFile)
+                } else {
+                    listOf(declaringFile) + currentSession.modulesWithFiles.values.flatten()
+                        .filterIsInstance<KtFile>()
+                        .filter { it.virtualFile?.path != declaringFile.virtualFile?.path }
+                }
+                val calls = mutableMapOf<KtFile, MutableList<KtCallExpression>>()
+                for (file in candidateFiles) {
+                    val references = PsiTreeUtil.collectElementsOfType(file, KtNameReferenceExpression::class.java).filter { nameExpr ->
+                        nameExpr.getReferencedName() == functionName &&
+                            PsiTreeUtil.getParentOfType(nameExpr, KtImportDirective::class.java) == null &&
+                            nameExpr.references.any { ref ->
+                                try { ref.resolve() == function } catch (_: Exception) { false }
+                            }
+                    }
+                    for (reference in references) {
+                        val parent = reference.parent
+                        when {
+                            parent is KtCallExpression && parent.calleeExpression == reference ->
+                                calls.getOrPut(file) { mutableListOf() }.add(parent)
+                            parent is KtCallableReferenceExpression -> {
+                                refuse("'$functionName' is used as a callable reference in ${file.name}")
+                                return@analyze
+                            }
+                        }
+                    }
+                }
+                val allCalls = calls.values.flatten()
+                allCalls.firstOrNull { call -> allCalls.any { it != call && PsiTreeUtil.isAncestor(it, call, true) } }?.let {
+                    refuse("nested calls of '$functionName' are not supported (${it.containingKtFile.name})")
+                    return@analyze
+                }
+
+                // Collected first so a refused call leaves no partial edits
+                val pending = JsonArray()
+                val declarationUri = if (declaringFile == ktFile) uri else "file://${declaringFile.virtualFile.path}"
+                for ((file, fileCalls) in calls) {
+                    val document = file.viewProvider.document ?: continue
+                    val fileUri = if (file == declaringFile) declarationUri else "file://${file.virtualFile.path}"
+                    for (call in fileCalls) {
+                        val (range, newText) = rewrittenCallArguments(call, oldParameters, newOrder) ?: run {
+                            val callLine = document.getLineNumber(call.textRange.startOffset) + 1
+                            refuse("the arguments of the call in ${file.name}:$callLine cannot be rearranged")
+                            return@analyze
+                        }
+                        if (document.getText(range) == newText) continue
+                        val (startLine, startCol) = offsetToLineCol(document, range.startOffset)
+                        val (endLine, endCol) = offsetToLineCol(document, range.endOffset)
+                        pending.add(makeEdit(fileUri, startLine, startCol, endLine, endCol, newText))
+                    }
+                }
+
+                val parameterList = function.valueParameterList ?: return@analyze
+                val declarationDocument = declaringFile.viewProvider.document ?: return@analyze
+                val newParameters = joinLikeList(parameterList, oldParameters, newOrder.map { oldParameters[it].text })
+                val (startLine, startCol) = offsetToLineCol(declarationDocument, parameterList.textRange.startOffset)
+                val (endLine, endCol) = offsetToLineCol(declarationDocument, parameterList.textRange.endOffset)
+                pending.add(makeEdit(declarationUri, startLine, startCol, endLine, endCol, "($newParameters)"))
+                edits.addAll(pending)
+            }
+        } catch (e: Exception) {
+            System.err.println("CompilerBridge: changeSignature failed for $uri: ${e.message}")
+            refuse("analysis failed: ${e.message}")
+        }
+
+        return result
+    }
+
+    /** Whether [parameter] is referenced anywhere in [function], default values included. */
+    private fun isParameterUsed(function: KtNamedFunction, parameter: KtParameter): Boolean =
+        PsiTreeUtil.collectElementsOfType(function, KtNameReferenceExpression::class.java).any { nameExpr ->
+            nameExpr.getReferencedName() == parameter.name &&
+                nameExpr.references.any { ref ->
+                    try { ref.resolve() == parameter } catch (_: Exception) { false }
+                }
+        }
+
+    /**
+     * The range of [call]'s arguments to replace, and its new text, after
+     * reordering [oldParameters] to [newOrder]. Once a parameter is skipped
+     * or an argument is named, later arguments are passed by name; a
+     * trailing lambda stays outside the parentheses only while its parameter
+     * remains last. Returns null when an argument cannot be matched to a
+     * parameter, or when vararg values would have to be passed by name.
+     */
+    private fun rewrittenCallArguments(
+        call: KtCallExpression,
+        oldParameters: List<KtParameter>,
+        newOrder: List<Int>,
+    ): Pair<com.intellij.openapi.util.TextRange, String>? {
+        val names = oldParameters.map { it.name }
+        val varargIndex = oldParameters.indexOfFirst { it.isVarArg }
+        val arguments = mutableMapOf<Int, MutableList<KtValueArgument>>()
+        var position = 0
+        for (argument in call.valueArguments) {
+            val index = when {
+                argument is KtLambdaArgument -> oldParameters.lastIndex
+                argument.isNamed() -> names.indexOf(argument.getArgumentName()?.asName?.asString())
+                    .also { position = it + 1 }
+                varargIndex in 0..position -> varargIndex
+                else -> position++
+            }
+            if (index !in oldParameters.indices) return null
+            arguments.getOrPut(index) { mutableListOf() }.add(argument)
+        }
+
+        val lambda = call.lambdaArguments.singleOrNull()
+        if (call.lambdaArguments.size > 1) return null
+        val keepLambda = lambda != null && newOrder.lastOrNull() == oldParameters.lastIndex
+        val texts = mutableListOf<String>()
+        var byName = false
+        for (index in newOrder) {
+            val passed = arguments[index]
+            if (passed.isNullOrEmpty()) {
+                byName = true
+                continue
+            }
+            if (keepLambda && passed.singleOrNull() == lambda) continue
+            if (byName && !passed.first().isNamed()) {
+                val single = passed.singleOrNull() ?: return null
+                if (index == varargIndex) return null
+                val value = (single as? KtLambdaArgument)?.getLambdaExpression()?.text ?: single.text
+                texts.add("${names[index]} = $value")
+                continue
+            }
+            byName = byName || passed.first().isNamed()
+            passed.mapTo(texts) { (it as? KtLambdaArgument)?.getLambdaExpression()?.text ?: it.text }
+        }
+
+        val list = call.valueArgumentList
+        val inner = joinLikeList(list, list?.arguments.orEmpty(), texts)
+        val start = list?.textRange?.startOffset
+            ?: (call.typeArgumentList ?: call.calleeExpression ?: return null).textRange.endOffset
+        return if (lambda == null || keepLambda) {
+            // Without parentheses there is nothing to rewrite
+            (list?.textRange ?: com.intellij.openapi.util.TextRange(start, start)) to (if (list == null) "" else "($inner)")
+        } else {
+            com.intellij.openapi.util.TextRange(start, call.textRange.endOffset) to "($inner)"
+        }
+    }
+
+    /**
+     * Joins [items] laid out like the current [elements] of the parenthesized
+     * [list]: the text after `(`, the separator between the first two
+     * elements, and the text before `)` (which may hold a trailing comma) are
+     * kept, so one-per-line lists stay one per line.
+     */
+    private fun joinLikeList(list: PsiElement?, elements: List<PsiElement>, items: List<String>): String {
+        if (list == null || elements.isEmpty() || items.isEmpty()) return items.joinToString(", ")
+        val text = list.text
+        val base = list.textRange.startOffset
+        val prefix = text.substring(1, elements.first().textRange.startOffset - base)
+        val suffix = text.substring(elements.last().textRange.endOffset - base, text.length - 1)
+        val separator = if (elements.size > 1) {
+            text.substring(elements[0].textRange.endOffset - base, elements[1].textRange.startOffset - base)
+        } else {
+            ", "
+        }
+        return prefix + items.joinToString(separator) + suffix
+    }
+
+    /**
+     * Returns the outermost expression whose range is exactly [start, end),
+     * skipping selections that cannot stand alone as a value (assignments,
+     * declarations, call callees, and selectors of qualified expressions).
+     */
+    private fun findExtractableExpression(ktFile: KtFile, start: Int, end: Int): KtExpression? {
+        var element: PsiElement? = ktFile.findElementAt(start)
+        var match: KtExpression? = null
+        while (element != null && element !is KtFile) {
+            val range = element.textRange
+            if (range.startOffset < start || range.endOffset > end) break
+            if (range.startOffset == start && range.endOffset == end && element is KtExpression) {
+                match = element
+            }
+            element = element.parent
+        }
+        val expression = match ?: return null
+
+        if (expression is KtDeclaration || expression is KtBlockExpression) return null
+        if (expression is KtBinaryExpression && expression.operationToken in KtTokens.ALL_ASSIGNMENTS) return null
+        val parent = expression.parent
+        if (parent is KtCallExpression && parent.calleeExpression == expression) return null
+        if (parent is KtQualifiedExpression && parent.selectorExpression == expression) return null
+        return expression
+    }
+
+    /**
+     * Returns true if [expression] refers to parameters or local declarations
+     * of [container], including an implicit lambda `it`.
+     */
+    private fun referencesLocals(expression: KtExpression, container: KtDeclaration): Boolean {
+        val localNames = mutableSetOf<String>()
+        PsiTreeUtil.collectElementsOfType(container, KtParameter::class.java)
+            .mapNotNullTo(localNames) { it.name }
+        PsiTreeUtil.collectElementsOfType(container, KtProperty::class.java)
+            .filter { it.isLocal }
+            .mapNotNullTo(localNames) { it.name }
+        if (PsiTreeUtil.getParentOfType(expression, KtFunctionLiteral::class.java) != null) {
+            localNames.add("it")
+        }
+        val references = PsiTreeUtil.collectElementsOfType(expression, KtNameReferenceExpression::class.java) +
+            listOfNotNull(expression as? KtNameReferenceExpression)
+        return references.any { it.getReferencedName() in localNames }
+    }
+
+    /** Returns [base], or [base] with a numeric suffix, that does not occur as a word in [text]. */
+    private fun uniqueIdentifier(text: String, base: String): String {
+        var candidate = base
+        var suffix = 1
+        while (Regex("\\b${Regex.escape(candidate)}\\b").containsMatchIn(text)) {
+            candidate = "$base$suffix"
+            suffix++
+        }
+        return candidate
+    }
+
     /**
      * Builds a single edit JSON object.
      */
@@ -2747,11 +4264,13 @@ class CompilerBridge {
     /**
      * Provides workspace-wide symbol search.
      * Walks all KtFiles in the session and collects declarations matching the query.
-     * Results are limited to 100 symbols.
+     * Results are limited to 100 symbols. With [onPartial], full batches of
+     * [WORKSPACE_SYMBOL_BATCH_SIZE] are reported as they are built and the
+     * result holds only the last, partial batch.
      */
-    fun workspaceSymbols(query: String): JsonObject {
+    fun workspaceSymbols(query: String, onPartial: ((JsonArray) -> Unit)? = null): JsonObject {
         val result = JsonObject()
-        val symbolsArray = JsonArray()
+        var symbolsArray = JsonArray()
         val perfStart = System.currentTimeMillis()
 
         try {
@@ -2764,6 +4283,10 @@ class CompilerBridge {
                 symbolObj.addProperty("line", decl.line)
                 symbolObj.addProperty("column", decl.column)
                 symbolsArray.add(symbolObj)
+                if (onPartial != null && symbolsArray.size() == WORKSPACE_SYMBOL_BATCH_SIZE) {
+                    onPartial(symbolsArray)
+                    symbolsArray = JsonArray()
+                }
             }
         } catch (e: Throwable) {
             System.err.println("CompilerBridge: workspaceSymbols failed: ${e.javaClass.name}: ${e.message}")
@@ -2774,6 +4297,29 @@ class CompilerBridge {
         return result
     }
 
+    /**
+     * Resolves a symbol picked from a [workspaceSymbols] list to the range of
+     * its name, read from the file's current contents so edits made since it
+     * was indexed are accounted for. [line] (1-based), when given, picks among
+     * same-named declarations in the file.
+     */
+    fun resolveWorkspaceSymbol(uri: String, name: String, line: Int?): JsonObject {
+        val result = JsonObject()
+        val currentSession = session ?: return result
+        val ktFile = findKtFile(currentSession, uri) ?: return result
+        val document = ktFile.viewProvider.document ?: return result
+
+        val declaration = PsiTreeUtil.collectElementsOfType(ktFile, KtNamedDeclaration::class.java)
+            .filter { it.name == name && it.nameIdentifier != null }
+            .minByOrNull { kotlin.math.abs(document.getLineNumber(it.textOffset) + 1 - (line ?: 0)) }
+            ?: return result
+        val nameRange = declaration.nameIdentifier?.textRange ?: return result
+
+        result.addProperty("uri", uri)
+        result.add("range", offsetRangeJson(document, nameRange.startOffset, nameRange.endOffset))
+        return result
+    }
+
     /**
      * Provides inlay hints for a file within the given line range.
      * Returns type hints for declarations without explicit type annotations
@@ -2949,6 +4495,161 @@ class CompilerBridge {
         return result
     }
 
+    /**
+     * Provides structural folding ranges: the import list, class and object
+     * bodies, block bodies of functions, and multi-line comments. Region
+     * comments are folded lexically by the server.
+     */
+    fun foldingRanges(uri: String): JsonObject {
+        val result = JsonObject()
+        val rangesArray = JsonArray()
+        result.add("ranges", rangesArray)
+
+        val currentSession = session ?: return result
+        val ktFile = findKtFile(currentSession, uri) ?: return result
+        val document = ktFile.viewProvider.document ?: return result
+
+        fun addRange(startOffset: Int, endOffset: Int, kind: String?) {
+            val startLine = document.getLineNumber(startOffset) + 1
+            val endLine = document.getLineNumber(endOffset) + 1
+            if (endLine <= startLine) return
+            val range = JsonObject()
+            range.addProperty("startLine", startLine)
+            range.addProperty("endLine", endLine)
+            if (kind != null) range.addProperty("kind", kind)
+            rangesArray.add(range)
+        }
+
+        try {
+            ktFile.importList?.let { importList ->
+                if (importList.imports.size > 1) {
+                    addRange(importList.textRange.startOffset, importList.textRange.endOffset, "imports")
+                }
+            }
+            for (body in PsiTreeUtil.collectElementsOfType(ktFile, KtClassBody::class.java)) {
+                addRange(body.textRange.startOffset, body.textRange.endOffset, null)
+            }
+            for (function in PsiTreeUtil.collectElementsOfType(ktFile, KtNamedFunction::class.java)) {
+                val body = function.bodyBlockExpression ?: continue
+                addRange(body.textRange.startOffset, body.textRange.endOffset, null)
+            }
+            for (comment in PsiTreeUtil.collectElementsOfType(ktFile, PsiComment::class.java)) {
+                if (comment.tokenType == KtTokens.EOL_COMMENT) continue
+                addRange(comment.textRange.startOffset, comment.textRange.endOffset, "comment")
+            }
+        } catch (e: Exception) {
+            System.err.println("CompilerBridge: foldingRanges failed for $uri: ${e.message}")
+        }
+
+        return result
+    }
+
+    /**
+     * Provides the outline of a file: its declarations as a tree, with class
+     * and object members as children. Each symbol carries the range of the
+     * whole declaration and the `selectionRange` of its name; local
+     * declarations inside function bodies are left out.
+     */
+    fun documentSymbols(uri: String): JsonObject {
+        val result = JsonObject()
+        val symbolsArray = JsonArray()
+        result.add("symbols", symbolsArray)
+
+        val currentSession = session ?: return result
+        val ktFile = findKtFile(currentSession, uri) ?: return result
+        val document = ktFile.viewProvider.document ?: return result
+
+        try {
+            for (declaration in ktFile.declarations) {
+                buildDocumentSymbol(declaration, document)?.let(symbolsArray::add)
+            }
+        } catch (e: Exception) {
+            System.err.println("CompilerBridge: documentSymbols failed for $uri: ${e.message}")
+        }
+
+        return result
+    }
+
+    /**
+     * Provides document links for KDoc comments. `[references]` and `@see`
+     * subjects are returned with their reference text and resolved lazily by
+     * [resolveDocumentLink]; literal http(s) URLs carry their target directly.
+     */
+    fun documentLinks(uri: String): JsonObject {
+        val result = JsonObject()
+        val linksArray = JsonArray()
+        result.add("links", linksArray)
+
+        val currentSession = session ?: return result
+        val ktFile = findKtFile(currentSession, uri) ?: return result
+        val document = ktFile.viewProvider.document ?: return result
+
+        try {
+            for (kdoc in PsiTreeUtil.collectElementsOfType(ktFile, KDoc::class.java)) {
+                for (link in PsiTreeUtil.collectElementsOfType(kdoc, KDocLink::class.java)) {
+                    val reference = link.getLinkText()
+                    if (reference.isBlank()) continue
+                    val linkRange = link.getLinkTextRange().shiftRight(link.textRange.startOffset)
+
+                    val linkObj = JsonObject()
+                    linkObj.add("range", offsetRangeJson(document, linkRange.startOffset, linkRange.endOffset))
+                    linkObj.addProperty("reference", reference)
+                    linksArray.add(linkObj)
+                }
+
+                val kdocStart = kdoc.textRange.startOffset
+                for (match in KDOC_URL_PATTERN.findAll(kdoc.text)) {
+                    val url = match.value.trimEnd('.', ',', ';', ':')
+                    val start = kdocStart + match.range.first
+                    val linkObj = JsonObject()
+                    linkObj.add("range", offsetRangeJson(document, start, start + url.length))
+                    linkObj.addProperty("target", url)
+                    linksArray.add(linkObj)
+                }
+            }
+        } catch (e: Exception) {
+            System.err.println("CompilerBridge: documentLinks failed for $uri: ${e.message}")
+        }
+
+        return result
+    }
+
+    /**
+     * Resolves a KDoc reference from [documentLinks] to the declaration it
+     * names, preferring a fully qualified match. Returns `{ uri, line }`, or an
+     * empty object when the reference does not resolve to a project source.
+     */
+    fun resolveDocumentLink(uri: String, reference: String): JsonObject {
+        val result = JsonObject()
+        val shortName = reference.substringAfterLast('.')
+        val candidates = symbolIndex.findByShortName(shortName)
+        val packageName = session?.let { findKtFile(it, uri) }?.packageFqName?.asString()
+        val match = candidates.firstOrNull { it.fqn == reference }
+            ?: candidates.firstOrNull { packageName != null && it.fqn == "$packageName.$reference" }
+            ?: candidates.firstOrNull { it.fqn?.endsWith(".$reference") == true }
+            ?: candidates.firstOrNull().takeIf { '.' !in reference }
+            ?: return result
+
+        result.addProperty("uri", match.uri)
+        result.addProperty("line", match.line)
+        return result
+    }
+
+    private fun offsetRangeJson(
+        document: com.intellij.openapi.editor.Document,
+        startOffset: Int,
+        endOffset: Int,
+    ): JsonObject {
+        val startLine = document.getLineNumber(startOffset)
+        val endLine = document.getLineNumber(endOffset)
+        val range = JsonObject()
+        range.addProperty("startLine", startLine + 1)
+        range.addProperty("startColumn", startOffset - document.getLineStartOffset(startLine))
+        range.addProperty("endLine", endLine + 1)
+        range.addProperty("endColumn", endOffset - document.getLineStartOffset(endLine))
+        return range
+    }
+
     /**
      * Provides code lenses for a file.
      * Returns reference counts for classes/interfaces/objects,
@@ -3052,12 +4753,179 @@ class CompilerBridge {
                     }
                 }
             }
-        } catch (e: Throwable) {
-            System.err.println("CompilerBridge: codeLens failed: ${e.javaClass.name}: ${e.message}")
+        } catch (e: Throwable) {
+            System.err.println("CompilerBridge: codeLens failed: ${e.javaClass.name}: ${e.message}")
+        }
+
+        result.add("lenses", lensesArray)
+        return result
+    }
+
+    /**
+     * Finds the tests in [uri], or in every session file when [uri] is null, as a
+     * tree: test classes (nested classes inside) holding their test functions, and
+     * Kotest specs holding their test blocks. Each node has `name`, `uri`, `range`,
+     * `framework` (`junit4`, `junit5`, `kotlin.test`, `kotest`, or `unknown` for a
+     * `@Test` whose import is not recognized), `kind` (`class`, `container` or
+     * `test`) and `children`. Files without tests add nothing.
+     */
+    fun discoverTests(uri: String?): JsonObject {
+        val result = JsonObject()
+        val testsArray = JsonArray()
+        result.add("tests", testsArray)
+
+        val currentSession = session ?: return result
+        val files = if (uri != null) {
+            listOfNotNull(findKtFile(currentSession, uri)?.let { uri to it })
+        } else {
+            currentSession.modulesWithFiles.entries
+                .flatMap { (_, files) -> files }
+                .filterIsInstance<KtFile>()
+                .map { "file://${it.virtualFile.path}" to it }
+        }
+
+        for ((fileUri, ktFile) in files) {
+            if (cancellationCheck()) break
+            try {
+                val document = ktFile.viewProvider.document ?: continue
+                for (declaration in ktFile.declarations.filterIsInstance<KtClassOrObject>()) {
+                    testClassNode(ktFile, document, fileUri, declaration)?.let { testsArray.add(it) }
+                }
+            } catch (e: Exception) {
+                System.err.println("CompilerBridge: discoverTests failed for $fileUri: ${e.message}")
+            }
+        }
+        return result
+    }
+
+    /** The test tree of [declaration], or null when it holds no tests. */
+    private fun testClassNode(
+        ktFile: KtFile,
+        document: com.intellij.openapi.editor.Document,
+        uri: String,
+        declaration: KtClassOrObject,
+    ): JsonObject? {
+        val name = declaration.name ?: return null
+        val children = JsonArray()
+        val kotestSpec = declaration.superTypeListEntries.any { entry ->
+            entry.typeReference?.text?.substringBefore('<')?.substringAfterLast('.') in KOTEST_SPECS
+        }
+        if (kotestSpec) {
+            collectKotestBlocks(declaration, document, uri, children)
+        }
+        for (member in declaration.declarations) {
+            when (member) {
+                is KtNamedFunction -> {
+                    val framework = testFramework(ktFile, member) ?: continue
+                    val testName = member.name ?: continue
+                    children.add(testNode(testName, uri, document, member, if (kotestSpec) "kotest" else framework, "test"))
+                }
+                is KtClassOrObject -> testClassNode(ktFile, document, uri, member)?.let { children.add(it) }
+                else -> {}
+            }
+        }
+        if (children.isEmpty) return null
+
+        val framework = if (kotestSpec) "kotest" else children.first().asJsonObject.get("framework").asString
+        return testNode(name, uri, document, declaration, framework, "class", children)
+    }
+
+    /**
+     * The framework of [function]'s test annotation, from the annotation's
+     * qualified name, its import, or a star import of a test package.
+     */
+    private fun testFramework(ktFile: KtFile, function: KtNamedFunction): String? {
+        val annotation = function.annotationEntries.firstOrNull {
+            it.shortName?.asString() in TEST_ANNOTATIONS
+        } ?: return null
+        val shortName = annotation.shortName?.asString() ?: return null
+        val written = annotation.typeReference?.text ?: shortName
+        val fqn = if ('.' in written) {
+            written
+        } else {
+            ktFile.importDirectives.firstOrNull { import ->
+                (import.aliasName ?: import.importedFqName?.shortName()?.asString()) == shortName
+            }?.importedFqName?.asString()
+        }
+        val testPackage = fqn?.substringBeforeLast('.')
+            ?: ktFile.importDirectives
+                .filter { it.isAllUnder }
+                .mapNotNull { it.importedFqName?.asString() }
+                .firstOrNull { it in TEST_FRAMEWORKS }
+        return TEST_FRAMEWORKS[testPackage] ?: "unknown"
+    }
+
+    /** Adds the Kotest blocks found under [element] to [into], nested blocks as children. */
+    private fun collectKotestBlocks(
+        element: PsiElement,
+        document: com.intellij.openapi.editor.Document,
+        uri: String,
+        into: JsonArray,
+    ) {
+        for (child in generateSequence(element.firstChild) { it.nextSibling }) {
+            val block = kotestBlock(child)
+            if (block == null) {
+                if (child !is KtNamedFunction && child !is KtClassOrObject) {
+                    collectKotestBlocks(child, document, uri, into)
+                }
+                continue
+            }
+            val (name, body) = block
+            val children = JsonArray()
+            collectKotestBlocks(body, document, uri, children)
+            val kind = if (children.isEmpty) "test" else "container"
+            into.add(testNode(name, uri, document, child, "kotest", kind, children))
         }
+    }
 
-        result.add("lenses", lensesArray)
-        return result
+    /**
+     * The name and body of a Kotest block: `test("name") { }` and the other
+     * spec styles' functions, `"name" { }`, and infix forms such as
+     * `"name" should { }` or `"name" - { }`.
+     */
+    private fun kotestBlock(element: PsiElement): Pair<String, KtLambdaExpression>? {
+        val (name, body) = when (element) {
+            is KtCallExpression -> {
+                val body = element.lambdaArguments.firstOrNull()?.getLambdaExpression() ?: return null
+                val name = when (val callee = element.calleeExpression) {
+                    is KtStringTemplateExpression -> callee
+                    is KtNameReferenceExpression ->
+                        if (callee.getReferencedName() in KOTEST_BLOCKS) {
+                            element.valueArguments.firstOrNull()?.getArgumentExpression() as? KtStringTemplateExpression
+                        } else {
+                            null
+                        }
+                    else -> null
+                } ?: return null
+                name to body
+            }
+            is KtBinaryExpression -> {
+                val name = element.left as? KtStringTemplateExpression ?: return null
+                val body = element.right as? KtLambdaExpression ?: return null
+                name to body
+            }
+            else -> return null
+        }
+        return name.text.removeSurrounding("\"\"\"").removeSurrounding("\"") to body
+    }
+
+    private fun testNode(
+        name: String,
+        uri: String,
+        document: com.intellij.openapi.editor.Document,
+        element: PsiElement,
+        framework: String,
+        kind: String,
+        children: JsonArray = JsonArray(),
+    ): JsonObject {
+        val node = JsonObject()
+        node.addProperty("name", name)
+        node.addProperty("uri", uri)
+        node.add("range", offsetRangeJson(document, element.textRange.startOffset, element.textRange.endOffset))
+        node.addProperty("framework", framework)
+        node.addProperty("kind", kind)
+        node.add("children", children)
+        return node
     }
 
     /**
@@ -3230,52 +5098,69 @@ class CompilerBridge {
     }
 
     /**
-     * Prepares call hierarchy items at the given cursor position.
-     * Finds the function/method declaration at the cursor and returns its info.
+     * Prepares call hierarchy items at the given cursor position: the
+     * function whose name or call is under the cursor. Anywhere else (e.g.
+     * inside a function body) there are no items, with a `reason`.
      */
     fun callHierarchyPrepare(uri: String, line: Int, character: Int): JsonObject {
         val result = JsonObject()
         val itemsArray = JsonArray()
+        result.add("items", itemsArray)
 
         val currentSession = session ?: run {
-            result.add("items", itemsArray)
+            result.addProperty("reason", "no-active-session")
             return result
         }
         val ktFile = findKtFile(currentSession, uri) ?: run {
-            result.add("items", itemsArray)
+            result.addProperty("reason", "file-not-found")
             return result
         }
 
         try {
             val offset = lineColToOffset(ktFile, line, character) ?: run {
-                result.add("items", itemsArray)
+                result.addProperty("reason", "line-col-offset-null")
                 return result
             }
 
-            val element = ktFile.findElementAt(offset)
-            if (element != null) {
-                // Find the enclosing function declaration
-                val function = PsiTreeUtil.getParentOfType(
-                    element, KtNamedFunction::class.java, false
-                )
-                if (function != null) {
-                    val document = ktFile.viewProvider.document
-                    if (document != null) {
-                        val item = buildCallHierarchyItem(function, ktFile, document)
-                        if (item != null) {
-                            itemsArray.add(item)
-                        }
-                    }
-                }
+            // A cursor right after a name (`foo|(`) still names it.
+            val function = ktFile.findElementAt(offset)?.let { callableAtElement(ktFile, it) }
+                ?: ktFile.findElementAt(offset - 1)?.let { callableAtElement(ktFile, it) }
+            val declarationFile = function?.containingFile as? KtFile
+            val document = declarationFile?.viewProvider?.document
+            val item = if (function != null && document != null) {
+                buildCallHierarchyItem(function, declarationFile, document)
+            } else null
+
+            if (item != null) {
+                itemsArray.add(item)
+            } else {
+                result.addProperty("reason", "not-a-callable")
             }
         } catch (e: Throwable) {
             System.err.println("CompilerBridge: callHierarchyPrepare failed: ${e.javaClass.name}: ${e.message}")
+            result.addProperty("reason", "analysis-failed: ${e.javaClass.simpleName}: ${e.message}")
         }
 
-        result.add("items", itemsArray)
         return result
     }
 
+    /**
+     * Returns the function named at [element]: its declaration name, or the
+     * callee of a call resolving to a source declaration.
+     */
+    private fun callableAtElement(ktFile: KtFile, element: PsiElement): KtNamedFunction? {
+        val declaration = element.parent as? KtNamedFunction
+        if (declaration != null && declaration.nameIdentifier == element) return declaration
+
+        val reference = PsiTreeUtil.getParentOfType(element, KtNameReferenceExpression::class.java, false)
+            ?: return null
+        val call = reference.parent as? KtCallExpression
+        if (call?.calleeExpression != reference) return null
+        return analyze(ktFile) {
+            reference.references.firstNotNullOfOrNull { ref -> ref.resolve() as? KtNamedFunction }
+        }
+    }
+
     /**
      * Finds incoming calls to the function at the given position.
      * Searches all KtFiles in the session for call expressions that reference the target function.
@@ -3410,50 +5295,73 @@ class CompilerBridge {
 
     /**
      * Prepares type hierarchy items at the given cursor position.
-     * Finds the class/interface declaration at the cursor and returns its info.
+     * Only a class/interface name, or a reference resolving to one in source,
+     * yields an item. Otherwise `items` is empty and `reason` says whether the
+     * position is simply not a type or analysis failed.
      */
     fun typeHierarchyPrepare(uri: String, line: Int, character: Int): JsonObject {
         val result = JsonObject()
         val itemsArray = JsonArray()
+        result.add("items", itemsArray)
 
         val currentSession = session ?: run {
-            result.add("items", itemsArray)
+            result.addProperty("reason", "no-active-session")
             return result
         }
         val ktFile = findKtFile(currentSession, uri) ?: run {
-            result.add("items", itemsArray)
+            result.addProperty("reason", "file-not-found")
             return result
         }
 
         try {
             val offset = lineColToOffset(ktFile, line, character) ?: run {
-                result.add("items", itemsArray)
+                result.addProperty("reason", "line-col-offset-null")
                 return result
             }
 
             val element = ktFile.findElementAt(offset)
-            if (element != null) {
-                val classOrObject = PsiTreeUtil.getParentOfType(
-                    element, KtClassOrObject::class.java, false
-                )
-                if (classOrObject != null) {
-                    val document = ktFile.viewProvider.document
-                    if (document != null) {
-                        val item = buildTypeHierarchyItem(classOrObject, ktFile, document)
-                        if (item != null) {
-                            itemsArray.add(item)
-                        }
-                    }
-                }
+            val classOrObject = element?.let { typeAtElement(ktFile, it) }
+            val declarationFile = classOrObject?.containingFile as? KtFile
+            val document = declarationFile?.viewProvider?.document
+            val item = if (classOrObject != null && document != null) {
+                buildTypeHierarchyItem(classOrObject, declarationFile, document)
+            } else null
+
+            if (item != null) {
+                itemsArray.add(item)
+            } else {
+                result.addProperty("reason", "not-a-type")
             }
         } catch (e: Throwable) {
             System.err.println("CompilerBridge: typeHierarchyPrepare failed: ${e.javaClass.name}: ${e.message}")
+            result.addProperty("reason", "analysis-failed: ${e.javaClass.simpleName}: ${e.message}")
         }
 
-        result.add("items", itemsArray)
         return result
     }
 
+    /**
+     * Returns the class/interface named at [element]: its declaration name, or
+     * a type reference resolving to a source declaration.
+     */
+    private fun typeAtElement(ktFile: KtFile, element: PsiElement): KtClassOrObject? {
+        val declaration = element.parent as? KtClassOrObject
+        if (declaration != null && declaration.nameIdentifier == element) return declaration
+
+        val reference = PsiTreeUtil.getParentOfType(element, KtNameReferenceExpression::class.java, false)
+            ?: return null
+        return analyze(ktFile) {
+            reference.references.firstNotNullOfOrNull { ref ->
+                when (val target = ref.resolve()) {
+                    is KtClassOrObject -> target
+                    // Constructor calls resolve to the class's primary constructor
+                    is KtPrimaryConstructor -> target.getContainingClassOrObject()
+                    else -> null
+                }
+            }
+        }
+    }
+
     /**
      * Finds supertypes for the class/interface at the given position.
      * Uses the Analysis API to enumerate the superTypes of the class symbol.
@@ -3675,6 +5583,22 @@ class CompilerBridge {
         }
     }
 
+    /**
+     * The function a call resolves to when [refExpr] is its callee. Resolving
+     * the whole call lets its arguments pick among overloads, where the bare
+     * reference may resolve to any of them. Null when [refExpr] is not a
+     * callee or the call does not resolve.
+     */
+    private fun org.jetbrains.kotlin.analysis.api.KaSession.calledDeclarationSymbol(
+        refExpr: KtReferenceExpression,
+    ): KaDeclarationSymbol? {
+        val callExpression = refExpr.parent as? KtCallExpression ?: return null
+        if (callExpression.calleeExpression != refExpr) return null
+        val callInfo = callExpression.resolveToCall() as? KaSuccessCallInfo ?: return null
+        val call = callInfo.call as? KaFunctionCall<*> ?: return null
+        return call.partiallyAppliedSymbol.signature.symbol
+    }
+
     /**
      * Builds hover text for a reference expression by resolving it to the target declaration.
      * Must be called inside an `analyze` block.
@@ -3685,7 +5609,7 @@ class CompilerBridge {
         // Try to resolve via references
         for (ref in refExpr.references) {
             if (ref is KtReference) {
-                var symbol = ref.resolveToSymbol()
+                var symbol = calledDeclarationSymbol(refExpr) ?: ref.resolveToSymbol()
                 // For constructor calls, show the containing class instead of the constructor
                 if (symbol is KaConstructorSymbol) {
                     val containingClass = symbol.containingDeclaration
@@ -4104,6 +6028,7 @@ class CompilerBridge {
      * 1. KDoc from the PSI (works for source declarations)
      * 2. KDoc from decompiled stubs (works for Kotlin library declarations)
      * 3. Javadoc-style comments from Java stubs
+     * 4. Javadoc from the `-sources` jar of a compiled Java library class
      * Must be called inside an `analyze` block.
      */
     private fun org.jetbrains.kotlin.analysis.api.KaSession.extractSymbolDocumentation(
@@ -4131,6 +6056,23 @@ class CompilerBridge {
             if (javadoc != null) return javadoc
         }
 
+        // Compiled Java library classes: the Javadoc of an attached -sources jar
+        if (symbol.origin == KaSymbolOrigin.JAVA_LIBRARY) {
+            return when (symbol) {
+                is KaClassLikeSymbol -> symbol.classId?.let {
+                    sourcesJarDocs.documentation(it.asSingleFqName().asString())
+                }
+                is KaCallableSymbol -> symbol.callableId?.let { callableId ->
+                    val classId = callableId.classId ?: return null
+                    sourcesJarDocs.documentation(
+                        classId.asSingleFqName().asString(),
+                        callableId.callableName.asString(),
+                    )
+                }
+                else -> null
+            }
+        }
+
         return null
     }
 
@@ -4234,11 +6176,12 @@ class CompilerBridge {
             return null
         }
 
-        // Find the expression just before the dot using PSI from the raw file text.
-        val beforeDot = containingFile.findElementAt((checkOffset - 1).coerceAtLeast(0)) ?: return null
+        // Find the expression just before the dot (or `?.`) using PSI from the raw file text.
+        val receiverEnd = if (fileText.getOrNull(checkOffset - 1) == '?') checkOffset - 1 else checkOffset
+        val beforeDot = containingFile.findElementAt((receiverEnd - 1).coerceAtLeast(0)) ?: return null
         var expr: PsiElement? = beforeDot
         while (expr != null && expr !is KtFile) {
-            if (expr is KtExpression && expr.textRange.endOffset == checkOffset) {
+            if (expr is KtExpression && expr.textRange.endOffset == receiverEnd) {
                 return expr
             }
             expr = expr.parent
@@ -4263,6 +6206,115 @@ class CompilerBridge {
         return null
     }
 
+    /**
+     * Whether the identifier starting at [prefixStart] follows a single `:`
+     * token on the same line, as in a type annotation or supertype list. A
+     * colon inside a string or comment is a different token and never counts.
+     */
+    private fun isTypeAnnotationColon(ktFile: KtFile, prefixStart: Int): Boolean {
+        val fileText = ktFile.text
+        var i = prefixStart - 1
+        while (i >= 0 && (fileText[i] == ' ' || fileText[i] == '\t')) i--
+        return i >= 0 && tokenTypeAt(ktFile, i) == KtTokens.COLON
+    }
+
+    /** The lexer token at [offset], or null outside the file. */
+    private fun tokenTypeAt(ktFile: KtFile, offset: Int) =
+        if (offset < 0) null else ktFile.findElementAt(offset)?.node?.elementType
+
+    /**
+     * Collects completions after `::`. With a class receiver (`String::`) these
+     * are its members plus `class`; with a value receiver (`text::`) the
+     * members of its type; without a receiver the functions and properties in
+     * scope. Items insert bare names since references are not called.
+     * Must be called inside an `analyze` block.
+     */
+    private fun org.jetbrains.kotlin.analysis.api.KaSession.collectCallableReferenceCompletions(
+        ktFile: KtFile,
+        element: PsiElement,
+        colonOffset: Int,
+        itemsArray: JsonArray,
+    ) {
+        val fileText = ktFile.text
+        val receiver = if (colonOffset > 0 && !fileText[colonOffset - 1].isWhitespace()) {
+            findReceiverExpressionEndingAtOffset(ktFile, colonOffset)
+        } else {
+            null
+        }
+        val receiverName = (receiver as? KtDotQualifiedExpression)?.selectorExpression ?: receiver
+        val classSymbol = receiverName?.references
+            ?.filterIsInstance<KtReference>()
+            ?.firstNotNullOfOrNull { reference ->
+                try { reference.resolveToSymbol() } catch (_: Exception) { null }
+            } as? KaClassSymbol
+
+        val seen = mutableSetOf<String>()
+        val addCallable = { callable: KaCallableSymbol ->
+            val name = (callable as? KaNamedSymbol)?.name?.asString()
+            if (name != null && !name.startsWith("<") && seen.add(name)) {
+                val item = JsonObject()
+                item.addProperty("label", name)
+                item.addProperty("kind", symbolToCompletionKind(callable))
+                item.addProperty("detail", try {
+                    callable.returnType.render(KaTypeRendererForSource.WITH_SHORT_NAMES, Variance.INVARIANT)
+                } catch (_: Exception) { "" })
+                item.addProperty("insertText", name)
+                itemsArray.add(item)
+            }
+        }
+
+        when {
+            classSymbol != null -> {
+                val item = JsonObject()
+                item.addProperty("label", "class")
+                item.addProperty("kind", "keyword")
+                item.addProperty("detail", "KClass")
+                item.addProperty("insertText", "class")
+                itemsArray.add(item)
+                classSymbol.memberScope.callables.forEach(addCallable)
+            }
+            receiver != null -> {
+                collectMemberCompletions(receiver, itemsArray)
+                for (member in itemsArray) {
+                    val item = member.asJsonObject
+                    item.addProperty("insertText", item.get("label").asString)
+                }
+            }
+            else -> {
+                val ktElement = PsiTreeUtil.getParentOfType(element, KtElement::class.java, false) ?: ktFile
+                ktFile.scopeContext(ktElement).compositeScope { true }.callables.forEach(addCallable)
+            }
+        }
+    }
+
+    /**
+     * Collects types for a position after `:`: classifiers in scope and, for a
+     * non-empty [prefix], unimported classes from the index.
+     * Must be called inside an `analyze` block.
+     */
+    private fun org.jetbrains.kotlin.analysis.api.KaSession.collectTypeCompletions(
+        ktFile: KtFile,
+        element: PsiElement,
+        prefix: String,
+        itemsArray: JsonArray,
+    ) {
+        val ktElement = PsiTreeUtil.getParentOfType(element, KtElement::class.java, false) ?: ktFile
+        val seen = mutableSetOf<String>()
+        for (classifier in ktFile.scopeContext(ktElement).compositeScope { true }.classifiers) {
+            val name = (classifier as? KaNamedSymbol)?.name?.asString() ?: continue
+            if (name.startsWith("<") || !seen.add(name)) continue
+            val item = JsonObject()
+            item.addProperty("label", name)
+            item.addProperty("kind", if (classifier is KaTypeParameterSymbol) "typeParameter" else "class")
+            item.addProperty("detail", "")
+            item.addProperty("insertText", name)
+            itemsArray.add(item)
+        }
+        if (prefix.isNotEmpty()) {
+            appendUnimportedCompletions(ktFile, prefix, itemsArray, kinds = TYPE_DECLARATION_KINDS)
+        }
+    }
+
     /**
      * Collects member completions for dot-qualified expressions.
      * Gets the type of the receiver and enumerates its members.
@@ -4271,8 +6323,10 @@ class CompilerBridge {
     private fun org.jetbrains.kotlin.analysis.api.KaSession.collectMemberCompletions(
         receiverExpr: KtExpression,
         itemsArray: JsonArray,
+        nonNullReceiver: Boolean = false,
     ) {
-        val receiverType = receiverExpr.expressionType ?: return
+        val expressionType = receiverExpr.expressionType ?: return
+        val receiverType = if (nonNullReceiver) expressionType.withNullability(false) else expressionType
 
         // Use KaTypeScope to enumerate members with proper type substitution
         val typeScope = receiverType.scope
@@ -4473,6 +6527,89 @@ class CompilerBridge {
         addKeywordCompletions(itemsArray)
     }
 
+    /**
+     * Returns the string template or KDoc comment when the identifier being
+     * typed follows an unescaped `$` in a string or a `[` in KDoc.
+     */
+    private fun findIdentifierCompletionContext(ktFile: KtFile, element: PsiElement, offset: Int): PsiElement? {
+        val text = ktFile.text
+        val markerOffset = offset - extractCompletionPrefix(element, offset).length - 1
+        return when (text.getOrNull(markerOffset)) {
+            '$' -> PsiTreeUtil.getParentOfType(element, KtStringTemplateExpression::class.java, false)
+                ?.takeIf { text.getOrNull(markerOffset - 1) != '\\' }
+            '[' -> PsiTreeUtil.getParentOfType(element, KDoc::class.java, false)
+            else -> null
+        }
+    }
+
+    /**
+     * Collects variables visible in a string template. A bare `$name` entry
+     * can only reference a value, so functions and classes are left out.
+     * Must be called inside an `analyze` block.
+     */
+    private fun org.jetbrains.kotlin.analysis.api.KaSession.collectTemplateCompletions(
+        ktFile: KtFile,
+        template: KtStringTemplateExpression,
+        itemsArray: JsonArray,
+    ) {
+        val scope = ktFile.scopeContext(template).compositeScope { true }
+        val seen = mutableSetOf<String>()
+        for (callable in scope.callables) {
+            if (callable !is KaVariableSymbol) continue
+            val name = callable.name.asString()
+            if (name.startsWith("<") || !seen.add(name)) continue
+
+            val item = JsonObject()
+            item.addProperty("label", name)
+            item.addProperty("kind", symbolToCompletionKind(callable))
+            item.addProperty("detail", callable.returnType.render(
+                KaTypeRendererForSource.WITH_SHORT_NAMES,
+                Variance.INVARIANT
+            ))
+            item.addProperty("insertText", name)
+            itemsArray.add(item)
+        }
+    }
+
+    /**
+     * Collects names a KDoc `[link]` can reference: the documented
+     * declaration's parameters, type parameters and members, then everything
+     * in scope at the declaration. Items insert the bare name.
+     * Must be called inside an `analyze` block.
+     */
+    private fun org.jetbrains.kotlin.analysis.api.KaSession.collectKDocLinkCompletions(
+        ktFile: KtFile,
+        kdoc: KDoc,
+        itemsArray: JsonArray,
+    ) {
+        val owner = kdoc.parent as? KtDeclaration
+        val seen = mutableSetOf<String>()
+        val addName = { name: String?, kind: String ->
+            if (name != null && !name.startsWith("<") && seen.add(name)) {
+                val item = JsonObject()
+                item.addProperty("label", name)
+                item.addProperty("kind", kind)
+                item.addProperty("detail", "")
+                item.addProperty("insertText", name)
+                itemsArray.add(item)
+            }
+        }
+
+        (owner as? KtCallableDeclaration)?.valueParameters?.forEach { addName(it.name, "variable") }
+        (owner as? KtClass)?.primaryConstructorParameters?.forEach { addName(it.name, "property") }
+        (owner as? KtTypeParameterListOwner)?.typeParameters?.forEach { addName(it.name, "typeParameter") }
+        (owner as? KtClassOrObject)?.declarations?.filterIsInstance<KtNamedDeclaration>()
+            ?.forEach { addName(it.name, psiToCompletionKind(it)) }
+
+        val scope = ktFile.scopeContext(owner ?: ktFile).compositeScope { true }
+        for (callable in scope.callables) {
+            if (callable is KaNamedSymbol) addName(callable.name.asString(), symbolToCompletionKind(callable))
+        }
+        for (classifier in scope.classifiers) {
+            if (classifier is KaNamedSymbol) addName(classifier.name.asString(), "class")
+        }
+    }
+
     /**
      * Extracts the identifier prefix being typed at the cursor position.
      * Looks at the raw file text before the cursor to find the identifier being typed.
@@ -4492,7 +6629,12 @@ class CompilerBridge {
      * Appends completion items for unimported symbols that match the prefix.
      * Each item includes additionalTextEdits to auto-insert the import statement.
      */
-    private fun appendUnimportedCompletions(ktFile: KtFile, prefix: String, itemsArray: JsonArray) {
+    private fun appendUnimportedCompletions(
+        ktFile: KtFile,
+        prefix: String,
+        itemsArray: JsonArray,
+        kinds: Set<String>? = null,
+    ) {
         val existingLabels = (0 until itemsArray.size())
             .map { itemsArray[it].asJsonObject.get("label")?.asString ?: "" }
             .toSet()
@@ -4503,6 +6645,7 @@ class CompilerBridge {
 
         val candidates = symbolIndex.searchSymbols(prefix, limit = 20)
             .filter { it.fqn != null && it.fqn !in existingImports && it.shortName !in existingLabels }
+            .filter { kinds == null || it.kind in kinds }
 
         if (candidates.isEmpty()) return
 
@@ -4618,15 +6761,21 @@ class CompilerBridge {
     // --- Private helpers: signature help ---
 
     /**
-     * Computes the active parameter index based on cursor position within
-     * the argument list of a call expression.
+     * The argument under the cursor: its position in the argument list and,
+     * for a named argument (`a = ...`), its name.
+     */
+    private data class ActiveArgument(val index: Int, val name: String?)
+
+    /**
+     * Finds the argument under the cursor within the argument list of a call
+     * expression. A trailing comma starts the next argument.
      */
-    private fun computeActiveParameter(callExpression: KtCallExpression, offset: Int): Int {
-        val argList = callExpression.valueArgumentList ?: return 0
-        val leftParen = argList.leftParenthesis ?: return 0
+    private fun computeActiveArgument(callExpression: KtCallExpression, offset: Int): ActiveArgument {
+        val argList = callExpression.valueArgumentList ?: return ActiveArgument(0, null)
+        val leftParen = argList.leftParenthesis ?: return ActiveArgument(0, null)
 
         // If cursor is before or at the left parenthesis, active param is 0
-        if (offset <= leftParen.textRange.endOffset) return 0
+        if (offset <= leftParen.textRange.endOffset) return ActiveArgument(0, null)
 
         // Count commas before the cursor position
         var commaCount = 0
@@ -4636,7 +6785,26 @@ class CompilerBridge {
             if (child.text == ",") commaCount++
         }
 
-        return commaCount
+        // After a trailing comma there is no argument yet, so no name either
+        val argument = argList.arguments.getOrNull(commaCount)
+            ?.takeIf { it.textRange.startOffset < offset }
+        val name = argument?.getArgumentName()?.asName?.asString()
+        return ActiveArgument(commaCount, name)
+    }
+
+    /**
+     * Maps the argument under the cursor to a parameter index of one overload:
+     * named arguments follow the parameter name, positional arguments past
+     * the end stay on a trailing vararg. Returns -1 when no parameter matches.
+     */
+    private fun parameterIndexFor(
+        argument: ActiveArgument,
+        parameterNames: List<String>,
+        lastIsVararg: Boolean,
+    ): Int {
+        if (argument.name != null) return parameterNames.indexOf(argument.name)
+        if (argument.index >= parameterNames.size && lastIsVararg) return parameterNames.size - 1
+        return argument.index
     }
 
     // --- Private helpers: references ---
@@ -4716,21 +6884,6 @@ class CompilerBridge {
 
     // --- Private helpers: code actions ---
 
-    /**
-     * Finds the nearest enclosing declaration for a given PSI element.
-     * Used to determine where to insert @Suppress annotations.
-     */
-    private fun findEnclosingDeclaration(element: PsiElement?): KtDeclaration? {
-        var current: PsiElement? = element
-        while (current != null && current !is KtFile) {
-            if (current is KtDeclaration && current !is KtParameter) {
-                return current
-            }
-            current = current.parent
-        }
-        return null
-    }
-
     /**
      * Finds the line number where a new import statement should be inserted.
      * Returns a 1-based line number after the package statement and existing imports.
@@ -4869,7 +7022,9 @@ class CompilerBridge {
     // --- Private helpers: call hierarchy ---
 
     /**
-     * Builds a call hierarchy item JSON object for a function declaration.
+     * Builds a call hierarchy item JSON object for a function declaration:
+     * its whole declaration as the range, its name as the selection, and
+     * `Container.name(params): Type` as the detail.
      */
     private fun buildCallHierarchyItem(
         function: KtNamedFunction,
@@ -4879,27 +7034,109 @@ class CompilerBridge {
         val name = function.name ?: return null
         val nameIdentifier = function.nameIdentifier ?: return null
 
-        val declOffset = function.textOffset
-        val declLine = document.getLineNumber(declOffset) + 1
-        val declLineStart = document.getLineStartOffset(document.getLineNumber(declOffset))
-        val declCol = declOffset - declLineStart
+        fun position(offset: Int): Pair<Int, Int> {
+            val line = document.getLineNumber(offset)
+            return (line + 1) to (offset - document.getLineStartOffset(line))
+        }
+        val (declLine, declCol) = position(function.textRange.startOffset)
+        val (declEndLine, declEndCol) = position(function.textRange.endOffset)
+        val (selLine, selCol) = position(nameIdentifier.textRange.startOffset)
+        val (selEndLine, selEndCol) = position(nameIdentifier.textRange.endOffset)
 
-        val selOffset = nameIdentifier.textOffset
-        val selLine = document.getLineNumber(selOffset) + 1
-        val selLineStart = document.getLineStartOffset(document.getLineNumber(selOffset))
-        val selCol = selOffset - selLineStart
+        val container = PsiTreeUtil.getParentOfType(function, KtClassOrObject::class.java)?.name
+        val parameters = function.valueParameters.joinToString(", ") { parameter ->
+            "${parameter.name}: ${parameter.typeReference?.text ?: "?"}"
+        }
+        val returnType = function.typeReference?.text?.let { ": $it" } ?: ""
+        val signature = "$name($parameters)$returnType"
 
         val item = JsonObject()
         item.addProperty("name", name)
         item.addProperty("kind", "function")
         item.addProperty("uri", "file://${ktFile.virtualFile.path}")
+        item.addProperty("detail", if (container != null) "$container.$signature" else signature)
         item.addProperty("line", declLine)
         item.addProperty("column", declCol)
+        item.addProperty("endLine", declEndLine)
+        item.addProperty("endColumn", declEndCol)
         item.addProperty("selectionLine", selLine)
         item.addProperty("selectionColumn", selCol)
+        item.addProperty("selectionEndLine", selEndLine)
+        item.addProperty("selectionEndColumn", selEndCol)
         return item
     }
 
+    // --- Private helpers: document symbols ---
+
+    /**
+     * Builds a document symbol for [declaration] and, for classes and
+     * objects, its members. Returns null for declarations without a name
+     * (e.g. initializer blocks).
+     */
+    private fun buildDocumentSymbol(
+        declaration: KtDeclaration,
+        document: com.intellij.openapi.editor.Document,
+    ): JsonObject? {
+        val nameIdentifier = (declaration as? KtNamedDeclaration)?.nameIdentifier
+        val name = declaration.name ?: return null
+        val kind = when (declaration) {
+            is KtEnumEntry -> "enumMember"
+            is KtClass -> when {
+                declaration.isInterface() -> "interface"
+                declaration.isEnum() -> "enum"
+                else -> "class"
+            }
+            is KtObjectDeclaration -> "object"
+            is KtNamedFunction -> "function"
+            is KtSecondaryConstructor -> "constructor"
+            is KtProperty -> if (declaration.hasModifier(KtTokens.CONST_KEYWORD)) "constant" else "property"
+            is KtTypeAlias -> "class"
+            else -> return null
+        }
+        val detail = when (declaration) {
+            is KtNamedFunction -> {
+                val parameters = declaration.valueParameters.joinToString(", ") { parameter ->
+                    "${parameter.name}: ${parameter.typeReference?.text ?: "?"}"
+                }
+                "($parameters)" + (declaration.typeReference?.text?.let { ": $it" } ?: "")
+            }
+            is KtProperty -> declaration.typeReference?.text
+            else -> null
+        }
+
+        val range = declaration.textRange
+        val selection = (nameIdentifier ?: declaration).textRange
+        val symbol = JsonObject()
+        symbol.addProperty("name", name)
+        symbol.addProperty("kind", kind)
+        detail?.let { symbol.addProperty("detail", it) }
+        symbol.add("range", offsetRangeJson(document, range.startOffset, range.endOffset))
+        symbol.add("selectionRange", offsetRangeJson(document, selection.startOffset, selection.endOffset))
+
+        val children = JsonArray()
+        if (declaration is KtClassOrObject) {
+            for (parameter in declaration.primaryConstructorParameters) {
+                if (!parameter.hasValOrVar()) continue
+                val parameterName = parameter.nameIdentifier ?: continue
+                val child = JsonObject()
+                child.addProperty("name", parameter.name)
+                child.addProperty("kind", "property")
+                parameter.typeReference?.text?.let { child.addProperty("detail", it) }
+                child.add("range", offsetRangeJson(document, parameter.textRange.startOffset, parameter.textRange.endOffset))
+                child.add(
+                    "selectionRange",
+                    offsetRangeJson(document, parameterName.textRange.startOffset, parameterName.textRange.endOffset),
+                )
+                children.add(child)
+            }
+            for (member in declaration.declarations) {
+                buildDocumentSymbol(member, document)?.let(children::add)
+            }
+        }
+        if (children.size() > 0) symbol.add("children", children)
+        return symbol
+    }
+
     // --- Private helpers: type hierarchy ---
 
     /**
@@ -5299,6 +7536,10 @@ class CompilerBridge {
     private val lightFileCache = mutableMapOf<String, Pair<String, KtFile>>() // uri -> (content, ktFile)
 
     private fun findKtFile(session: StandaloneAnalysisAPISession, uri: String): KtFile? {
+        // Decompiled library classes are addressed by their in-jar location
+        if (uri.startsWith("jar:")) {
+            return findLibraryPsiFile(session, jarUriToPath(uri)) as? KtFile
+        }
         val filePath = uriToPath(uri)
 
         val allSessionFiles = session.modulesWithFiles.entries
@@ -5430,6 +7671,24 @@ class CompilerBridge {
         return names
     }
 
+    /** The `file:` URI of [path], percent-encoded like the URIs clients send. */
+    private fun pathToUri(path: String): String = Paths.get(path).toUri().toString()
+
+    /**
+     * Turns an in-jar URI (`jar:file:///C:/libs/a%20b.jar!/a/B.class`) into the
+     * jar file system path [findLibraryPsiFile] expects (`C:/libs/a b.jar!/a/B.class`).
+     */
+    private fun jarUriToPath(uri: String): String {
+        val jar = uri.removePrefix("jar:").substringBefore("!/")
+        val entry = uri.substringAfter("!/", "")
+        val jarPath = try {
+            Paths.get(java.net.URI(jar)).toString()
+        } catch (e: Exception) {
+            uriToPath(jar)
+        }
+        return "${jarPath.replace('\\', '/')}!/$entry"
+    }
+
     private fun uriToPath(uri: String): String {
         return if (uri.startsWith("file://")) {
             uri.removePrefix("file://")
@@ -5599,6 +7858,115 @@ class CompilerBridge {
     }
 
     companion object {
+        /** Annotations marking a test function. */
+        private val TEST_ANNOTATIONS = setOf("Test", "ParameterizedTest", "RepeatedTest", "TestFactory", "TestTemplate")
+
+        /** Test framework by the package its test annotation comes from. */
+        private val TEST_FRAMEWORKS = mapOf(
+            "org.junit" to "junit4",
+            "org.junit.jupiter.api" to "junit5",
+            "org.junit.jupiter.params" to "junit5",
+            "kotlin.test" to "kotlin.test",
+        )
+
+        /** Kotest spec base classes. */
+        private val KOTEST_SPECS = setOf(
+            "FunSpec", "StringSpec", "ShouldSpec", "DescribeSpec", "BehaviorSpec",
+            "WordSpec", "FreeSpec", "FeatureSpec", "ExpectSpec", "AnnotationSpec",
+        )
+
+        /** Kotest functions that declare a test or a container of tests. */
+        private val KOTEST_BLOCKS = setOf(
+            "test", "context", "describe", "it", "should", "given", "Given", "when", "When",
+            "then", "Then", "and", "And", "feature", "scenario", "expect",
+            "xtest", "xcontext", "xdescribe", "xit", "xshould", "xgiven", "xwhen", "xthen",
+            "xfeature", "xscenario", "xexpect",
+        )
+
+        /** Mirrors the server's default `excludePaths` setting. */
+        val DEFAULT_EXCLUDE_PATHS = listOf("**/build/**", "**/.gradle/**")
+
+        /** Drive-letter prefix of a Windows absolute path, e.g. `C:\` or `c:/`. */
+        private val WINDOWS_DRIVE_PREFIX = Regex("""^[A-Za-z]:[/\\]""")
+
+        /**
+         * Whether an exclude [pattern] is an absolute path glob. Decided from its
+         * prefix, since `Paths.get` rejects glob characters such as `*` on Windows.
+         */
+        fun isAbsoluteGlob(pattern: String): Boolean =
+            pattern.startsWith("/") || pattern.startsWith("\\") || WINDOWS_DRIVE_PREFIX.containsMatchIn(pattern)
+
+        /** `analysisScope` value that limits indexing to open files and their imports. */
+        const val ANALYSIS_SCOPE_OPEN_FILES = "openFiles"
+
+        /** `scope` values of a `references` request; anything else searches all files. */
+        const val REFERENCES_SCOPE_ALL = "all"
+        const val REFERENCES_SCOPE_EXCLUDE_TESTS = "excludeTests"
+        const val REFERENCES_SCOPE_CURRENT_MODULE = "currentModule"
+
+        /** Source sets of tests: `src/test`, `src/androidTest`, `src/jvmTest`, `src/testFixtures`. */
+        private val TEST_SOURCE_SET = Regex("""/src/(test\w*|\w+Test)/""")
+
+        /** Whether [path] lies in a test source set. */
+        fun isTestSourcePath(path: String): Boolean = TEST_SOURCE_SET.containsMatchIn(path)
+
+        /** `scriptKind` the server sends for `.gradle.kts` documents. */
+        const val GRADLE_SCRIPT_KIND = "gradle"
+
+        /** Imports Gradle adds to every Kotlin build script. */
+        private val GRADLE_IMPLICIT_IMPORTS = listOf(
+            "org.gradle.kotlin.dsl.*",
+            "org.gradle.api.*",
+            "org.gradle.api.artifacts.*",
+            "org.gradle.api.artifacts.dsl.*",
+            "org.gradle.api.file.*",
+            "org.gradle.api.plugins.*",
+            "org.gradle.api.provider.*",
+            "org.gradle.api.publish.*",
+            "org.gradle.api.publish.maven.*",
+            "org.gradle.api.tasks.*",
+            "org.gradle.api.tasks.bundling.*",
+            "org.gradle.api.tasks.testing.*",
+            "org.gradle.plugin.use.*",
+            "java.io.File",
+        )
+
+        /** Symbols per `$/partialResult` batch of a streamed workspace symbol search. */
+        const val WORKSPACE_SYMBOL_BATCH_SIZE = 25
+
+        /**
+         * Suffixes annotation processors append to the name of the class they
+         * generate code for (Room, Moshi, Dagger, kotlinx.serialization, ...).
+         */
+        private val GENERATED_NAME_SUFFIXES = setOf(
+            "Impl", "JsonAdapter", "Factory", "MembersInjector", "Builder", "Serializer",
+        )
+
+        private val GENERATED_DECLARATION_PATTERN =
+            Regex("""\b(?:class|interface|object)\s+([A-Za-z_][\w$]*)([^{;]*)""")
+
+        private val KDOC_URL_PATTERN = Regex("""https?://[^\s<>()\[\]]+""")
+
+        /** Code of the analyzer's own unused-import warning (not a compiler diagnostic). */
+        const val UNUSED_IMPORT = "UNUSED_IMPORT"
+
+        /**
+         * Operator-convention names that are used without being spelled out
+         * (`a + b`, `val x by lazy`, `for (x in xs)`), so imports of them are kept.
+         */
+        private val OPERATOR_CONVENTION_NAMES = setOf(
+            "plus", "minus", "times", "div", "rem", "mod", "rangeTo", "rangeUntil",
+            "unaryPlus", "unaryMinus", "not", "inc", "dec",
+            "plusAssign", "minusAssign", "timesAssign", "divAssign", "remAssign",
+            "get", "set", "invoke", "contains", "compareTo", "equals",
+            "iterator", "next", "hasNext",
+            "getValue", "setValue", "provideDelegate",
+        )
+        private val COMPONENT_NAME = Regex("component\\d+")
+
+        /** [SymbolIndex] kinds that can appear in a type position. */
+        private val TYPE_DECLARATION_KINDS = setOf("class", "interface", "enum", "object", "typeAlias")
+
         /**
          * Set of Kotlin keyword token types for semantic token classification.
          */
@@ -5700,6 +8068,32 @@ class CompilerBridge {
                 ?.toPath()
         }
 
+        /**
+         * Rewrites a `.gradle.kts` script as a regular Kotlin file. Leading
+         * comments, `@file:` annotations, and imports stay in place; Gradle's
+         * implicit imports follow, and the rest of the script becomes the body
+         * of an extension function on the script template, so `plugins { }`,
+         * `dependencies { }`, and `Project` members resolve as they do in Gradle.
+         */
+        fun wrapGradleScript(text: String, fileName: String): GradleScriptWrapper {
+            val template = when {
+                fileName.endsWith("settings.gradle.kts") -> "org.gradle.kotlin.dsl.KotlinSettingsScript"
+                fileName.endsWith("init.gradle.kts") -> "org.gradle.kotlin.dsl.KotlinInitScript"
+                else -> "org.gradle.kotlin.dsl.KotlinBuildScript"
+            }
+            val lines = text.lines()
+            val headerLines = lines.takeWhile { line ->
+                val trimmed = line.trim()
+                trimmed.isEmpty() || trimmed.startsWith("//") || trimmed.startsWith("@file:") ||
+                    trimmed.startsWith("import ")
+            }.size
+            val inserted = GRADLE_IMPLICIT_IMPORTS.map { "import $it" } +
+                "fun $template.__gradleScriptBody() {"
+            val wrapped = (lines.take(headerLines) + inserted + lines.drop(headerLines) + "}")
+                .joinToString("\n")
+            return GradleScriptWrapper(wrapped, headerLines, inserted.size, lines.size)
+        }
+
         fun mapCompilerFlag(flag: String): LanguageFeature? {
             return when (flag) {
                 "-Xcontext-parameters" -> LanguageFeature.ContextParameters
@@ -87,6 +87,25 @@ class JsonRpcTransport(
         }
     }
 
+    /**
+     * Sends a notification (no id) to the Rust server, e.g. `$/partialResult`.
+     */
+    fun sendNotification(method: String, params: JsonElement) {
+        val message = JsonObject().apply {
+            addProperty("jsonrpc", "2.0")
+            addProperty("method", method)
+            add("params", params)
+        }
+        val bytes = gson.toJson(message).toByteArray(Charsets.UTF_8)
+        val header = "Content-Length: ${bytes.size}\r\n\r\n".toByteArray(Charsets.UTF_8)
+
+        synchronized(bufferedOutput) {
+            bufferedOutput.write(header)
+            bufferedOutput.write(bytes)
+            bufferedOutput.flush()
+        }
+    }
+
     /**
      * Sends a successful response.
      */
@@ -1,14 +1,26 @@
 package dev.kouros.sidecar
 
+import java.nio.file.Path
+
 /**
  * Entry point for the kotlin-analyzer JVM sidecar.
  *
  * Reads JSON-RPC requests from stdin, processes them using the Kotlin Analysis API,
  * and writes JSON-RPC responses to stdout. All logging goes to stderr.
+ *
+ * With `--socket <path>` the sidecar instead runs as a [SidecarDaemon] listening on
+ * that Unix domain socket (the server's `persistentSidecar` mode).
  */
 fun main(args: Array<String>) {
     System.err.println("kotlin-analyzer sidecar v0.4.3 starting (Kotlin ${SidecarRuntime.kotlinVersion})")
 
+    val socketIndex = args.indexOf("--socket")
+    if (socketIndex >= 0 && socketIndex + 1 < args.size) {
+        SidecarDaemon(Path.of(args[socketIndex + 1])).run()
+        System.err.println("kotlin-analyzer sidecar exiting")
+        return
+    }
+
     val transport = JsonRpcTransport(
         input = System.`in`,
         output = System.out,
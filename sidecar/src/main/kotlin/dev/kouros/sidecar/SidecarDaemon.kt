@@ -0,0 +1,103 @@
+package dev.kouros.sidecar
+
+import com.google.gson.JsonObject
+import java.net.StandardProtocolFamily
+import java.net.UnixDomainSocketAddress
+import java.nio.channels.Channels
+import java.nio.channels.SelectionKey
+import java.nio.channels.Selector
+import java.nio.channels.ServerSocketChannel
+import java.nio.file.Files
+import java.nio.file.Path
+import java.util.concurrent.atomic.AtomicBoolean
+import kotlin.concurrent.thread
+
+/**
+ * Analysis session a [SidecarDaemon] keeps between clients, with the
+ * initialize params it was built from.
+ */
+class RetainedSession {
+    var params: JsonObject? = null
+    var shutdownRequested = false
+}
+
+/**
+ * Runs the sidecar as a daemon on a Unix domain socket (the server's
+ * `persistentSidecar` mode), so the JVM and its analysis session survive the
+ * language server being restarted, e.g. on an editor reload.
+ *
+ * Every connection opens with a [HELLO_METHOD] notification. Clients are
+ * served one at a time: while one is connected, the hello tells any other
+ * that the daemon is busy and the connection is closed, so that server
+ * starts a regular sidecar instead of waiting. A client that initializes
+ * with the same params as the previous one reuses the session instead of
+ * rebuilding it. The daemon exits on `shutdown` or after [IDLE_TIMEOUT_MS]
+ * without a client.
+ */
+class SidecarDaemon(private val socketPath: Path) {
+    private val bridge = CompilerBridge()
+    private val session = RetainedSession()
+    private val busy = AtomicBoolean(false)
+    @Volatile private var idleSince = System.currentTimeMillis()
+
+    fun run() {
+        Files.deleteIfExists(socketPath)
+        Selector.open().use { selector ->
+            ServerSocketChannel.open(StandardProtocolFamily.UNIX).use { server ->
+                server.bind(UnixDomainSocketAddress.of(socketPath))
+                server.configureBlocking(false)
+                server.register(selector, SelectionKey.OP_ACCEPT)
+                System.err.println("SidecarDaemon: listening on $socketPath")
+
+                try {
+                    while (!session.shutdownRequested) {
+                        val idleFor = if (busy.get()) 0 else System.currentTimeMillis() - idleSince
+                        if (idleFor >= IDLE_TIMEOUT_MS) {
+                            System.err.println("SidecarDaemon: idle for ${IDLE_TIMEOUT_MS / 60_000} minutes, exiting")
+                            break
+                        }
+                        // Also woken when a client disconnects.
+                        selector.select(IDLE_TIMEOUT_MS - idleFor)
+                        selector.selectedKeys().clear()
+                        val client = server.accept() ?: continue
+                        client.configureBlocking(true)
+                        val transport = JsonRpcTransport(
+                            input = Channels.newInputStream(client),
+                            output = Channels.newOutputStream(client),
+                        )
+                        if (!busy.compareAndSet(false, true)) {
+                            client.use { transport.sendNotification(HELLO_METHOD, hello(busy = true)) }
+                            System.err.println("SidecarDaemon: refused a client while serving another")
+                            continue
+                        }
+                        transport.sendNotification(HELLO_METHOD, hello(busy = false))
+                        System.err.println("SidecarDaemon: client connected")
+                        thread(name = "sidecar-daemon-client") {
+                            try {
+                                client.use { AnalysisServer(transport, bridge, session).run() }
+                            } finally {
+                                System.err.println("SidecarDaemon: client disconnected")
+                                idleSince = System.currentTimeMillis()
+                                busy.set(false)
+                                selector.wakeup()
+                            }
+                        }
+                    }
+                } finally {
+                    if (!session.shutdownRequested) bridge.shutdown()
+                    Files.deleteIfExists(socketPath)
+                }
+            }
+        }
+    }
+
+    private fun hello(busy: Boolean) = JsonObject().apply { addProperty("busy", busy) }
+
+    companion object {
+        /** How long the daemon waits for a client before exiting. */
+        const val IDLE_TIMEOUT_MS = 30 * 60 * 1000L
+
+        /** The notification every connection opens with; `busy` refuses it. */
+        const val HELLO_METHOD = "daemon/hello"
+    }
+}
@@ -0,0 +1,113 @@
+package dev.kouros.sidecar
+
+import java.util.zip.ZipFile
+
+/**
+ * Javadoc of Java library declarations, read from the `-sources` jars the
+ * build attached to the classpath. Class files carry no documentation, so
+ * hover over a library symbol falls back to its source here.
+ */
+internal class SourcesJarDocs(private val sourcesJars: List<String>) {
+    /** Maps each `.java` entry, like `org/example/Service.java`, to its jar. */
+    private val entries: Map<String, String> by lazy {
+        val entries = mutableMapOf<String, String>()
+        for (jar in sourcesJars) {
+            try {
+                ZipFile(jar).use { zip ->
+                    for (entry in zip.entries()) {
+                        if (entry.name.endsWith(".java")) entries.putIfAbsent(entry.name, jar)
+                    }
+                }
+            } catch (e: Exception) {
+                System.err.println("SourcesJarDocs: cannot read $jar: ${e.message}")
+            }
+        }
+        entries
+    }
+    private val sources = mutableMapOf<String, String?>()
+
+    /**
+     * The Javadoc of the class [classFqName], or of its member [memberName],
+     * as plain text with inline tags resolved. Null without sources or docs.
+     */
+    fun documentation(classFqName: String, memberName: String? = null): String? {
+        if (sourcesJars.isEmpty()) return null
+        val text = sourceOf(classFqName) ?: return null
+        val simpleName = Regex.escape(classFqName.substringAfterLast('.'))
+        val classDeclaration = Regex("""\b(?:class|interface|enum|record|@interface)\s+$simpleName\b""")
+            .find(text) ?: return null
+        if (memberName == null) return docCommentBefore(text, classDeclaration.range.first)
+
+        val member = memberDeclaration(memberName).find(text, classDeclaration.range.last) ?: return null
+        return docCommentBefore(text, member.range.first)
+    }
+
+    /** The source of the file declaring [classFqName], which may be nested. */
+    private fun sourceOf(classFqName: String): String? {
+        val segments = classFqName.split('.')
+        for (count in segments.size downTo 1) {
+            val path = segments.take(count).joinToString("/") + ".java"
+            val jar = entries[path] ?: continue
+            return sources.getOrPut(path) {
+                try {
+                    ZipFile(jar).use { zip -> zip.getInputStream(zip.getEntry(path)).bufferedReader().readText() }
+                } catch (e: Exception) {
+                    System.err.println("SourcesJarDocs: cannot read $path from $jar: ${e.message}")
+                    null
+                }
+            }
+        }
+        return null
+    }
+
+    /**
+     * The doc comment ending right before the line at [index], skipping the
+     * annotation lines between them.
+     */
+    private fun docCommentBefore(text: String, index: Int): String? {
+        var lineStart = text.lastIndexOf('\n', index - 1) + 1
+        while (true) {
+            val before = text.substring(0, lineStart).trimEnd()
+            if (before.endsWith("*/")) {
+                val start = before.lastIndexOf("/**")
+                if (start < 0) return null
+                return parseJavadoc(before.substring(start))
+            }
+            val previousStart = before.lastIndexOf('\n') + 1
+            if (previousStart == 0 || !before.substring(previousStart).trim().startsWith("@")) return null
+            lineStart = previousStart
+        }
+    }
+
+    companion object {
+        /** Statements that can precede a call the way a type precedes a declared name. */
+        private const val NOT_A_TYPE = "return|new|throw|else|case|yield|assert"
+
+        /**
+         * A field or method declaration of [name]: a line of annotations,
+         * modifiers and a type followed by the name, so calls like
+         * `return name(x)` in an earlier method body do not match.
+         */
+        fun memberDeclaration(name: String): Regex = Regex(
+            """^[ \t]*(?:@[\w.]+(?:\([^)]*\))?\s+)*(?!(?:$NOT_A_TYPE)\b)[\w$][\w$.<>\[\],? \t]*[\w$>\]]\s+${Regex.escape(name)}\s*(?:\(|=|;)""",
+            RegexOption.MULTILINE,
+        )
+
+        /** Strips comment markers and HTML, and turns inline tags into Markdown. */
+        fun parseJavadoc(comment: String): String? {
+            val text = comment.removePrefix("/**").removeSuffix("*/")
+                .lines()
+                .joinToString("\n") { it.trim().removePrefix("*").removePrefix(" ") }
+                .replace(Regex("""\{@(?:code|literal)\s+([^}]*)}""")) { "`${it.groupValues[1].trim()}`" }
+                .replace(Regex("""\{@(?:link|linkplain)\s+([^}\s]+)[^}]*}""")) {
+                    val target = it.groupValues[1].substringBefore('(').substringAfterLast('.')
+                    "`${target.replace('#', '.').trimStart('.')}`"
+                }
+                .replace(Regex("(?i)</?p>"), "\n")
+                .replace(Regex("<[^>]+>"), "")
+                .replace(Regex("\n{3,}"), "\n\n")
+                .trim()
+            return text.ifEmpty { null }
+        }
+    }
+}
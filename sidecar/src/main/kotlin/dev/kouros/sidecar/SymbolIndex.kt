@@ -74,10 +74,14 @@ class SymbolIndex {
     fun allDeclarationsInFile(uri: String): List<IndexedDeclaration> =
         index[uri] ?: emptyList()
 
-    fun rebuildFromSession(session: StandaloneAnalysisAPISession) {
+    fun rebuildFromSession(
+        session: StandaloneAnalysisAPISession,
+        isExcluded: (KtFile) -> Boolean = { false },
+    ) {
         index.clear()
         for ((_, ktFiles) in session.modulesWithFiles) {
             for (ktFile in ktFiles.filterIsInstance<KtFile>()) {
+                if (isExcluded(ktFile)) continue
                 val path = ktFile.virtualFile.path
                 indexFile("file://$path", ktFile)
             }
@@ -6,6 +6,7 @@ import org.junit.jupiter.api.Test
 import java.io.ByteArrayInputStream
 import java.io.ByteArrayOutputStream
 import kotlin.test.assertEquals
+import kotlin.test.assertFalse
 import kotlin.test.assertTrue
 
 class AnalysisServerTest {
@@ -70,6 +71,43 @@ class AnalysisServerTest {
             initializeResult.get("kotlinVersion").asString,
         )
         assertTrue(initializeResult.get("success").asBoolean)
+        // This build handles every feature request.
+        assertEquals(0, initializeResult.getAsJsonArray("unsupportedFeatures").size())
+        assertTrue(server.unsupportedFeatures().isEmpty())
+    }
+
+    @Test
+    fun `retained session survives a disconnect and ends on shutdown`() {
+        val session = RetainedSession()
+        val bridge = CompilerBridge()
+        val params = JsonObject().apply { addProperty("projectRoot", "/workspace") }
+        val connect = { requests: String ->
+            val output = ByteArrayOutputStream()
+            AnalysisServer(
+                transport = JsonRpcTransport(
+                    input = ByteArrayInputStream(requests.toByteArray()),
+                    output = output,
+                ),
+                bridge = bridge,
+                retainedSession = session,
+            ).run()
+            parseResponses(output.toString(Charsets.UTF_8))
+        }
+
+        // The first client disconnects without shutdown; the session is kept.
+        connect(buildString { appendRpcRequest(id = 1, method = "initialize", params = params) })
+        assertEquals(params, session.params)
+        assertFalse(session.shutdownRequested)
+
+        val responses = connect(
+            buildString {
+                appendRpcRequest(id = 1, method = "initialize", params = params)
+                appendRpcRequest(id = 2, method = "shutdown", params = JsonObject())
+            },
+        )
+        val initializeResult = responses.first { it.get("id").asLong == 1L }.getAsJsonObject("result")
+        assertTrue(initializeResult.get("success").asBoolean)
+        assertTrue(session.shutdownRequested)
     }
 
     @Test
@@ -169,6 +207,52 @@ class AnalysisServerTest {
         assertTrue(shutdownResult.get("success").asBoolean)
     }
 
+    @Test
+    fun `requests for a document version not yet received get ContentModified`() {
+        val uri = "file:///workspace/src/main/kotlin/Main.kt"
+        fun hoverAt(version: Int) = JsonObject().apply {
+            addProperty("uri", uri)
+            addProperty("line", 1)
+            addProperty("character", 4)
+            addProperty("version", version)
+        }
+        val input = buildString {
+            appendRpcNotification(
+                method = "textDocument/didOpen",
+                params = JsonObject().apply {
+                    addProperty("uri", uri)
+                    addProperty("version", 1)
+                    addProperty("text", "fun main() {}")
+                },
+            )
+            appendRpcRequest(id = 1, method = "hover", params = hoverAt(2))
+            appendRpcNotification(
+                method = "textDocument/didChange",
+                params = JsonObject().apply {
+                    addProperty("uri", uri)
+                    addProperty("version", 2)
+                    addProperty("text", "fun main() { }")
+                },
+            )
+            appendRpcRequest(id = 2, method = "hover", params = hoverAt(2))
+            appendRpcRequest(id = 3, method = "shutdown", params = JsonObject())
+        }.toByteArray()
+
+        val output = ByteArrayOutputStream()
+        AnalysisServer(
+            transport = JsonRpcTransport(
+                input = ByteArrayInputStream(input),
+                output = output,
+            ),
+        ).run()
+
+        val responses = parseResponses(output.toString(Charsets.UTF_8))
+        val stale = responses.first { it.get("id").asLong == 1L }
+        assertEquals(AnalysisServer.CONTENT_MODIFIED, stale.getAsJsonObject("error").get("code").asInt)
+        val current = responses.first { it.get("id").asLong == 2L }
+        assertTrue(current.get("error") == null, "current version should be served: $current")
+    }
+
     private fun StringBuilder.appendRpcRequest(id: Long, method: String, params: JsonObject) {
         val body = JsonObject().apply {
             addProperty("jsonrpc", "2.0")
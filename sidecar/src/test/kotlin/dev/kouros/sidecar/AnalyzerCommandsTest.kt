@@ -47,6 +47,29 @@ class AnalyzerCommandsTest {
             createArgs,
         )
 
+        assertEquals(AnalyzerCommands.CHANGE_SIGNATURE, commands.getAsJsonObject("changeSignature").get("id").asString)
+        val changeSignatureArgs = commands
+            .getAsJsonObject("changeSignature")
+            .getAsJsonObject("arguments")
+        assertEquals(
+            setOf(AnalyzerCommands.ARG_URI, AnalyzerCommands.ARG_POSITION, AnalyzerCommands.ARG_PARAMETERS),
+            changeSignatureArgs.keySet(),
+        )
+        val position = changeSignatureArgs.getAsJsonObject(AnalyzerCommands.ARG_POSITION).getAsJsonObject("properties")
+        assertEquals(setOf(AnalyzerCommands.ARG_LINE, AnalyzerCommands.ARG_CHARACTER), position.keySet())
+
+        assertEquals(AnalyzerCommands.REVEAL_GENERATED, commands.getAsJsonObject("revealGenerated").get("id").asString)
+        assertEquals(
+            setOf(AnalyzerCommands.ARG_URI, AnalyzerCommands.ARG_POSITION),
+            commands.getAsJsonObject("revealGenerated").getAsJsonObject("arguments").keySet(),
+        )
+
+        assertEquals(AnalyzerCommands.DISCOVER_TESTS, commands.getAsJsonObject("discoverTests").get("id").asString)
+        assertEquals(
+            setOf(AnalyzerCommands.ARG_URI),
+            commands.getAsJsonObject("discoverTests").getAsJsonObject("arguments").keySet(),
+        )
+
         val selection = commands
             .getAsJsonObject("openTestTarget")
             .getAsJsonObject("arguments")
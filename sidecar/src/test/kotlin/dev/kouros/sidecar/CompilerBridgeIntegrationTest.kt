@@ -6,7 +6,9 @@ import org.junit.jupiter.api.AfterEach
 import org.junit.jupiter.api.BeforeEach
 import org.junit.jupiter.api.Test
 import kotlin.test.assertEquals
+import kotlin.test.assertFalse
 import kotlin.test.assertNotNull
+import kotlin.test.assertNull
 import kotlin.test.assertTrue
 
 /**
@@ -287,6 +289,32 @@ class CompilerBridgeIntegrationTest {
         )
     }
 
+    @Test
+    fun `hover - call shows the overload its arguments select`() {
+        val uri = "file://$testSourceDir/HoverOverloads.kt"
+        val content = """
+            package scratch
+
+            fun f(x: Int) = x
+            fun f(x: String) = x.length
+
+            fun main() {
+                f("a")
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+
+        // line 7: "    f("a")" — ^4 = "f"
+        val result = bridge.hover(uri, line = 7, character = 4)
+
+        val contents = result.get("contents")?.asString
+        assertNotNull(contents, "hover should return contents for the call, got: $result")
+        assertTrue(
+            contents.contains("x: String") && !contents.contains("x: Int"),
+            "hover should show the String overload, got: $contents"
+        )
+    }
+
     @Test
     fun `hover - accepts end-of-line character by falling back to previous token`() {
         // Clean.kt line 1: "class Greeter("
@@ -313,6 +341,69 @@ class CompilerBridgeIntegrationTest {
         )
     }
 
+    // --- Call hierarchy ---
+
+    @Test
+    fun `callHierarchyPrepare - function name and call carry signature and ranges`() {
+        val uri = "file://$testSourceDir/Clean.kt"
+        // Clean.kt line 4: "    fun greet(): String {" — ^8 = "greet"
+        val declaration = bridge.callHierarchyPrepare(uri, line = 4, character = 8)
+        val item = declaration.getAsJsonArray("items")[0].asJsonObject
+        assertEquals("Greeter.greet(): String", item.get("detail")?.asString)
+        assertEquals(4, item.get("line")?.asInt)
+        assertEquals(6, item.get("endLine")?.asInt)
+        assertEquals(8, item.get("selectionColumn")?.asInt)
+        assertEquals(13, item.get("selectionEndColumn")?.asInt)
+
+        // Clean.kt line 11: "    println(greeter.greet())" — ^20 = "greet" call
+        val call = bridge.callHierarchyPrepare(uri, line = 11, character = 20)
+        assertEquals("greet", call.getAsJsonArray("items")[0].asJsonObject.get("name")?.asString)
+    }
+
+    @Test
+    fun `callHierarchyPrepare - function body is not a callable`() {
+        // Clean.kt line 5: "        return "Hello, $name!""
+        val uri = "file://$testSourceDir/Clean.kt"
+        val result = bridge.callHierarchyPrepare(uri, line = 5, character = 8)
+
+        assertEquals(0, result.getAsJsonArray("items").size(), "enclosing function should not be offered, got: $result")
+        assertEquals("not-a-callable", result.get("reason")?.asString)
+    }
+
+    // --- Type hierarchy ---
+
+    @Test
+    fun `typeHierarchyPrepare - local variable is not a type`() {
+        // Clean.kt line 10: "    val greeter = Greeter("World")"
+        //                        ^8 (0-based) = "greeter"
+        val uri = "file://$testSourceDir/Clean.kt"
+        val result = bridge.typeHierarchyPrepare(uri, line = 10, character = 8)
+
+        assertEquals(0, result.getAsJsonArray("items").size(), "local variable has no type hierarchy")
+        assertEquals("not-a-type", result.get("reason")?.asString)
+    }
+
+    @Test
+    fun `typeHierarchyPrepare - member body inside a class is not a type`() {
+        // Clean.kt line 5: "        return "Hello, $name!""
+        val uri = "file://$testSourceDir/Clean.kt"
+        val result = bridge.typeHierarchyPrepare(uri, line = 5, character = 8)
+
+        assertEquals(0, result.getAsJsonArray("items").size(), "enclosing class should not be offered, got: $result")
+    }
+
+    @Test
+    fun `typeHierarchyPrepare - class name and type reference`() {
+        val uri = "file://$testSourceDir/Clean.kt"
+        // Clean.kt line 1: "class Greeter(" — ^6 = "Greeter"
+        val declaration = bridge.typeHierarchyPrepare(uri, line = 1, character = 6)
+        assertEquals("Greeter", declaration.getAsJsonArray("items")[0].asJsonObject.get("name")?.asString)
+
+        // Clean.kt line 10: ^18 = "Greeter" constructor call
+        val reference = bridge.typeHierarchyPrepare(uri, line = 10, character = 18)
+        assertEquals("Greeter", reference.getAsJsonArray("items")[0].asJsonObject.get("name")?.asString)
+    }
+
     // --- Definition ---
 
     @Test
@@ -405,6 +496,21 @@ class CompilerBridgeIntegrationTest {
         )
     }
 
+    @Test
+    fun `completion - items replace the typed prefix`() {
+        // Completion.kt line 10: "    my" — the prefix spans columns 4..6
+        val uri = "file://$testSourceDir/Completion.kt"
+        val result = bridge.completion(uri, line = 10, character = 6)
+
+        val item = result.getAsJsonArray("items").map { it.asJsonObject }
+            .first { it.get("label")?.asString == "myFunction" }
+        val textEdit = item.getAsJsonObject("textEdit")
+        assertNotNull(textEdit, "items should carry a textEdit")
+        val range = textEdit.getAsJsonObject("range")
+        assertEquals(listOf(10, 4, 10, 6), listOf("startLine", "startColumn", "endLine", "endColumn").map { range.get(it).asInt })
+        assertEquals(item.get("insertText")?.asString, textEdit.get("newText")?.asString)
+    }
+
     @Test
     fun `completion - dirty on-disk local member completion retries after rebuild`() {
         val uri = "file://$testSourceDir/Completion.kt"
@@ -429,6 +535,132 @@ class CompilerBridgeIntegrationTest {
         )
     }
 
+    @Test
+    fun `completion - safe call offers members of the non-null type`() {
+        val uri = "file://$testSourceDir/SafeCallCompletion.kt"
+        val content = """
+            fun test(name: String?) {
+                name?.
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+        // After `name?.` (line 2, col 10)
+        val result = bridge.completion(uri, line = 2, character = 10, triggerCharacter = ".")
+
+        val labels = result.getAsJsonArray("items").map { it.asJsonObject.get("label")?.asString }
+        assertTrue(
+            "length" in labels && "uppercase" in labels,
+            "safe call on String? should offer String members, got: ${labels.take(20)}"
+        )
+    }
+
+    @Test
+    fun `completion - callable reference after double colon`() {
+        val uri = "file://$testSourceDir/CallableReferenceCompletion.kt"
+        val content = """
+            fun test() {
+                val lengths = listOf("a").map(String::)
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+        // After `String::` (line 2, col 42)
+        val result = bridge.completion(uri, line = 2, character = 42, triggerCharacter = ":")
+
+        val items = result.getAsJsonArray("items").map { it.asJsonObject }
+        val length = items.firstOrNull { it.get("label")?.asString == "length" }
+        assertNotNull(length, "String:: should offer 'length', got: ${items.map { it.get("label") }.take(20)}")
+        assertTrue(items.any { it.get("label")?.asString == "class" }, "String:: should offer 'class'")
+        val uppercase = items.first { it.get("label")?.asString == "uppercase" }
+        assertEquals("uppercase", uppercase.get("insertText")?.asString, "references insert the bare name")
+    }
+
+    @Test
+    fun `completion - single colon offers types only`() {
+        val uri = "file://$testSourceDir/TypeAnnotationCompletion.kt"
+        val content = """
+            class Money
+            fun amount() = 1
+            val price: 
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+        // After `val price: ` (line 3, col 11)
+        val result = bridge.completion(uri, line = 3, character = 11, triggerCharacter = ":")
+
+        val labels = result.getAsJsonArray("items").map { it.asJsonObject.get("label")?.asString }
+        assertTrue("Money" in labels, "type position should offer 'Money', got: ${labels.take(20)}")
+        assertTrue("amount" !in labels, "type position should not offer functions")
+    }
+
+    @Test
+    fun `completion - colon inside a string is not a type position`() {
+        val uri = "file://$testSourceDir/StringColonCompletion.kt"
+        val content = """
+            class Money
+            fun amount() = 1
+            val label = "total:am"
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+        // After `"total:am` (line 3, col 21)
+        val result = bridge.completion(uri, line = 3, character = 21)
+
+        val labels = result.getAsJsonArray("items").map { it.asJsonObject.get("label")?.asString }
+        assertTrue("amount" in labels, "a string colon should not limit items to types, got: ${labels.take(20)}")
+    }
+
+    @Test
+    fun `completion - string template variable after dollar`() {
+        val uri = "file://$testSourceDir/TemplateCompletion.kt"
+        val content = """
+            fun greet(userName: String) {
+                val greeting = "Hello, ${'$'}"
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+        // After the `$` inside the string (line 2, col 28)
+        val result = bridge.completion(uri, line = 2, character = 28, triggerCharacter = "$")
+
+        val items = result.getAsJsonArray("items").map { it.asJsonObject }
+        val userName = items.firstOrNull { it.get("label")?.asString == "userName" }
+        assertNotNull(userName, "template completion should offer 'userName', got: ${items.map { it.get("label") }}")
+        assertEquals("userName", userName.get("insertText")?.asString, "the typed '$' must not be inserted again")
+        assertTrue(items.none { it.get("label")?.asString == "greet" }, "functions are not offered after a bare '$'")
+    }
+
+    @Test
+    fun `completion - KDoc link after bracket`() {
+        val uri = "file://$testSourceDir/KDocCompletion.kt"
+        val content = """
+            /**
+             * Formats the [
+             */
+            fun format(amount: Int, currency: String): String = "${'$'}amount ${'$'}currency"
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+        // After the `[` in the KDoc (line 2, col 16)
+        val result = bridge.completion(uri, line = 2, character = 16, triggerCharacter = "[")
+
+        val items = result.getAsJsonArray("items").map { it.asJsonObject }
+        val amount = items.firstOrNull { it.get("label")?.asString == "amount" }
+        assertNotNull(amount, "KDoc completion should offer parameter 'amount', got: ${items.map { it.get("label") }}")
+        assertEquals("amount", amount.get("insertText")?.asString, "the typed '[' must not be inserted again")
+        assertTrue(items.any { it.get("label")?.asString == "format" }, "KDoc completion should offer 'format'")
+    }
+
+    @Test
+    fun `completion - bracket outside KDoc returns nothing`() {
+        val uri = "file://$testSourceDir/BracketOutsideKDoc.kt"
+        val content = """
+            fun test() {
+                val list = listOf(1)[
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+        val result = bridge.completion(uri, line = 2, character = 25, triggerCharacter = "[")
+
+        assertEquals(0, result.getAsJsonArray("items").size())
+        assertEquals("trigger-outside-template-or-kdoc", result.get("reason")?.asString)
+    }
+
     // --- Signature Help ---
 
     @Test
@@ -456,6 +688,40 @@ class CompilerBridgeIntegrationTest {
         }
     }
 
+    @Test
+    fun `signatureHelp - trailing comma moves to next parameter`() {
+        val uri = "file://$testSourceDir/SignatureHelpTrailing.kt"
+        val content = """
+            fun foo(a: Int, b: Int): Int = a + b
+            fun test() {
+                foo(1, )
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+        // foo(1, |) — after the comma (line 3, col 11)
+        val result = bridge.signatureHelp(uri, line = 3, character = 11)
+
+        val sig = result.getAsJsonArray("signatures")[0].asJsonObject
+        assertEquals(1, sig.get("activeParameter")?.asInt, "cursor after the comma is on 'b'")
+    }
+
+    @Test
+    fun `signatureHelp - named argument selects parameter by name`() {
+        val uri = "file://$testSourceDir/SignatureHelpNamed.kt"
+        val content = """
+            fun foo(a: Int, b: Int): Int = a + b
+            fun test() {
+                foo(b = 1, a = )
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+        // foo(b = 1, a = |) — second argument, but named 'a' (line 3, col 19)
+        val result = bridge.signatureHelp(uri, line = 3, character = 19)
+
+        val sig = result.getAsJsonArray("signatures")[0].asJsonObject
+        assertEquals(0, sig.get("activeParameter")?.asInt, "named argument 'a' is parameter 0")
+    }
+
     // --- Virtual file content injection ---
 
     @Test
@@ -625,6 +891,32 @@ class CompilerBridgeIntegrationTest {
         )
     }
 
+    @Test
+    fun `references - partial results stream per file and are not repeated`() {
+        val uri = "file://$testSourceDir/User.kt"
+        val batches = mutableListOf<com.google.gson.JsonArray>()
+        val result = bridge.references(uri, line = 1, character = 11) { batches.add(it) }
+
+        assertTrue(batches.isNotEmpty(), "locations should be streamed")
+        val streamed = batches.flatMap { batch -> batch.map { it.toString() } }
+        val remaining = result.getAsJsonArray("locations").map { it.toString() }
+        assertTrue(streamed.none { it in remaining }, "final result should hold only unstreamed locations")
+
+        val all = bridge.references(uri, line = 1, character = 11)
+            .getAsJsonArray("locations").map { it.toString() }
+        assertEquals(all.sorted(), (streamed + remaining).sorted())
+    }
+
+    @Test
+    fun `references - stop at the limit`() {
+        val uri = "file://$testSourceDir/User.kt"
+        val all = bridge.references(uri, line = 1, character = 11).getAsJsonArray("locations")
+        assertTrue(all.size() > 2, "User should have more than two references, got: $all")
+
+        val limited = bridge.references(uri, line = 1, character = 11, limit = 2).getAsJsonArray("locations")
+        assertEquals(2, limited.size())
+    }
+
     // --- Workspace symbols ---
 
     @Test
@@ -657,6 +949,22 @@ class CompilerBridgeIntegrationTest {
         )
     }
 
+    @Test
+    fun `addCreatedFile - created file becomes searchable`() {
+        val created = java.io.File("$testSourceDir/CreatedLater.kt")
+        created.writeText("class CreatedLaterService\n")
+        try {
+            bridge.addCreatedFile("file://${created.path}")
+
+            val symbols = bridge.workspaceSymbols("CreatedLater").getAsJsonArray("symbols")
+            val names = symbols.map { it.asJsonObject.get("name")?.asString ?: "" }
+            assertTrue(names.contains("CreatedLaterService"), "created file should be indexed, got: $names")
+        } finally {
+            created.delete()
+            bridge.removeFile("file://${created.path}")
+        }
+    }
+
     @Test
     fun `workspaceSymbols - specific query filters results`() {
         val result = bridge.workspaceSymbols("User")
@@ -670,6 +978,31 @@ class CompilerBridgeIntegrationTest {
         assertTrue(names.none { it == "Greeter" }, "should NOT find Greeter when searching 'User', got: $names")
     }
 
+    @Test
+    fun `resolveWorkspaceSymbol - picks the same-named declaration nearest the listed line`() {
+        val uri = "file://$testSourceDir/ResolveSymbol.kt"
+        val content = """
+            class Square {
+                fun area() = 4
+            }
+
+            class Circle {
+                fun area() = 3
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+
+        val result = bridge.resolveWorkspaceSymbol(uri, "area", line = 6)
+
+        assertEquals(uri, result.get("uri")?.asString)
+        val range = result.getAsJsonObject("range")
+        assertEquals(
+            listOf(6, 8, 6, 12),
+            listOf("startLine", "startColumn", "endLine", "endColumn").map { range.get(it).asInt },
+        )
+        assertEquals(0, bridge.resolveWorkspaceSymbol(uri, "perimeter", line = 6).size())
+    }
+
     // --- Completion with auto-import ---
 
     @Test
@@ -762,6 +1095,202 @@ class CompilerBridgeIntegrationTest {
         )
     }
 
+    // --- Unused imports ---
+
+    @Test
+    fun `codeActions - remove unused import deletes the whole line`() {
+        val uri = "file://$testSourceDir/UnusedImports.kt"
+        val content = """
+            package sample
+
+            import java.io.File
+            import kotlin.math.sqrt
+            import kotlin.math.sqrt
+
+            fun root(x: Double) = sqrt(x)
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+
+        val unused = bridge.analyze(uri).getAsJsonArray("diagnostics")
+            .map { it.asJsonObject }
+            .filter { it.get("code")?.asString == "UNUSED_IMPORT" }
+        assertEquals(listOf(3, 5), unused.map { it.get("line").asInt }, "File and the repeated sqrt import are unused")
+
+        val actions = bridge.codeActions(uri, line = 3, character = 0).getAsJsonArray("actions")
+            .map { it.asJsonObject }
+        val fix = actions.single { it.get("title")?.asString == "Remove unused import" }
+        assertEquals("quickfix", fix.get("kind")?.asString)
+        assertEquals(3, fix.getAsJsonObject("diagnostic").get("line").asInt)
+        val range = fix.getAsJsonArray("edits")[0].asJsonObject.getAsJsonObject("range")
+        assertEquals(listOf(3, 0, 4, 0), listOf("startLine", "startColumn", "endLine", "endColumn").map { range.get(it).asInt })
+
+        val removeAll = actions.single { it.get("title")?.asString == "Remove all unused imports" }
+        assertEquals("source.removeUnusedImports", removeAll.get("kind")?.asString)
+        assertEquals(2, removeAll.getAsJsonArray("edits").size())
+    }
+
+    @Test
+    fun `codeActions - removing every import leaves no blank import block`() {
+        val uri = "file://$testSourceDir/OnlyUnusedImports.kt"
+        val content = """
+            package sample
+
+            import java.io.File
+            import java.io.Reader
+
+            fun answer() = 42
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+
+        val removeAll = bridge.codeActions(uri, line = 6, character = 0).getAsJsonArray("actions")
+            .map { it.asJsonObject }
+            .single { it.get("title")?.asString == "Remove all unused imports" }
+
+        // Apply the edits bottom-up and check the result
+        val lines = content.lines().toMutableList()
+        val edits = removeAll.getAsJsonArray("edits").map { it.asJsonObject.getAsJsonObject("range") }
+        for (range in edits.sortedByDescending { it.get("startLine").asInt }) {
+            val start = range.get("startLine").asInt - 1
+            val end = range.get("endLine").asInt - 1
+            repeat(end - start) { lines.removeAt(start) }
+        }
+        assertEquals("package sample\n\nfun answer() = 42", lines.joinToString("\n"))
+    }
+
+    // --- Inline variable ---
+
+    @Test
+    fun `inline - simple local is replaced at its usages and removed`() {
+        val uri = "file://$testSourceDir/InlineLocal.kt"
+        val content = """
+            fun total(price: Int): Int {
+                val tax = price / 10
+                println("tax: ${'$'}tax")
+                return tax * 2
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+        // On the `tax` declaration (line 2, col 8)
+        val result = bridge.inline(uri, line = 2, character = 8)
+
+        val action = result.getAsJsonArray("actions").single().asJsonObject
+        assertEquals("Inline variable 'tax'", action.get("title")?.asString)
+        assertEquals("refactor.inline", action.get("kind")?.asString)
+        val newTexts = action.getAsJsonArray("edits").map { it.asJsonObject.get("newText").asString }
+        assertEquals(listOf("\${price / 10}", "(price / 10)", ""), newTexts)
+
+        val deletion = action.getAsJsonArray("edits").last().asJsonObject.getAsJsonObject("range")
+        assertEquals(listOf(2, 0, 3, 0), listOf("startLine", "startColumn", "endLine", "endColumn").map { deletion.get(it).asInt })
+    }
+
+    @Test
+    fun `inline - reassigned var is refused`() {
+        val uri = "file://$testSourceDir/InlineReassigned.kt"
+        val content = """
+            fun count(): Int {
+                var n = 0
+                n += 1
+                return n
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+        val result = bridge.inline(uri, line = 2, character = 8)
+
+        assertEquals(0, result.getAsJsonArray("actions").size())
+        assertTrue(result.get("reason")?.asString?.contains("reassigned") == true, "got: ${result.get("reason")}")
+    }
+
+    @Test
+    fun `inline - initializer reading a var is refused unless used right away`() {
+        val uri = "file://$testSourceDir/InlineVarRead.kt"
+        val content = """
+            fun prices(): Int {
+                var rate = 2
+                val cost = rate * 10
+                rate = 3
+                val now = System.nanoTime()
+                println(now)
+                return cost + rate
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+
+        // `cost` would read `rate` after it changed
+        val moved = bridge.inline(uri, line = 3, character = 8)
+        assertEquals(0, moved.getAsJsonArray("actions").size())
+        assertTrue(moved.get("reason")?.asString?.contains("evaluate differently") == true, "got: ${moved.get("reason")}")
+
+        // `now` is used in the very next statement
+        val adjacent = bridge.inline(uri, line = 5, character = 8)
+        assertEquals(1, adjacent.getAsJsonArray("actions").size(), "got: ${adjacent.get("reason")}")
+    }
+
+    // --- Change signature ---
+
+    @Test
+    fun `changeSignature - reorders and removes arguments at call sites`() {
+        val uri = "file://$testSourceDir/ChangeSignature.kt"
+        val content = """
+            fun send(to: String, subject: String, retries: Int = 3, body: () -> String) {
+                println(to + subject + body())
+            }
+
+            fun main() {
+                send("a@b.c", "hi", 1) { "text" }
+                send(to = "x@y.z", subject = "re", retries = 2, body = { "more" })
+            }
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+
+        // Move `subject` first and drop `retries`, which the body does not use
+        val result = bridge.changeSignature(uri, line = 1, character = 4, parameters = listOf("subject", "to", "body"))
+
+        assertNull(result.get("reason"), "got: ${result.get("reason")}")
+        val newTexts = result.getAsJsonArray("edits").map { it.asJsonObject.get("newText").asString }
+        assertEquals(
+            listOf(
+                "(\"hi\", \"a@b.c\")",
+                "(subject = \"re\", to = \"x@y.z\", body = { \"more\" })",
+                "(subject: String, to: String, body: () -> String)",
+            ),
+            newTexts,
+        )
+    }
+
+    @Test
+    fun `changeSignature - refuses removing a used parameter`() {
+        val uri = "file://$testSourceDir/ChangeSignatureUsed.kt"
+        val content = """
+            fun greet(name: String, punctuation: String) = "Hello, " + name + punctuation
+
+            val greeting = greet("Ada", "!")
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+
+        val result = bridge.changeSignature(uri, line = 1, character = 4, parameters = listOf("name"))
+
+        assertEquals(0, result.getAsJsonArray("edits").size())
+        assertEquals("parameter 'punctuation' is used in 'greet'", result.get("reason")?.asString)
+    }
+
+    @Test
+    fun `codeActions - offers change signature presets on a parameter`() {
+        val uri = "file://$testSourceDir/ChangeSignatureActions.kt"
+        val content = """
+            fun area(width: Int, height: Int, unit: String) = width * height
+        """.trimIndent()
+        bridge.updateFile(uri, content)
+
+        // On `unit` (line 1, col 34)
+        val result = bridge.codeActions(uri, line = 1, character = 34)
+        val titles = result.getAsJsonArray("actions").map { it.asJsonObject.get("title").asString }
+
+        assertFalse(titles.any { it.startsWith("Change signature") }, "no no-op action, got: $titles")
+        assertTrue("Remove parameter 'unit'" in titles, "got: $titles")
+        assertTrue("Move parameter 'unit' left" in titles, "got: $titles")
+        assertFalse("Move parameter 'unit' right" in titles, "got: $titles")
+    }
+
     // --- Compiler flags: context parameters ---
 
     @Test
@@ -1753,6 +2282,67 @@ class CompilerBridgeIntegrationTest {
         }
     }
 
+    // --- Test discovery ---
+
+    @Test
+    fun `discoverTests - builds a tree across frameworks`() {
+        val junitUri = "file://$testSourceDir/DiscoveredJUnitTest.kt"
+        bridge.updateFile(
+            junitUri,
+            """
+            import org.junit.jupiter.api.Nested
+            import org.junit.jupiter.api.Test
+
+            class CalculatorTest {
+                @Test
+                fun adds() {}
+
+                fun helper() {}
+
+                @Nested
+                inner class Division {
+                    @Test
+                    fun byZero() {}
+                }
+            }
+            """.trimIndent(),
+        )
+        val kotestUri = "file://$testSourceDir/DiscoveredKotestSpec.kt"
+        bridge.updateFile(
+            kotestUri,
+            """
+            import io.kotest.core.spec.style.DescribeSpec
+
+            class StackSpec : DescribeSpec({
+                describe("push") {
+                    it("grows the stack") {}
+                }
+            })
+            """.trimIndent(),
+        )
+
+        val junit = bridge.discoverTests(junitUri).getAsJsonArray("tests")
+        assertEquals(1, junit.size())
+        val calculator = junit[0].asJsonObject
+        assertEquals("CalculatorTest", calculator.get("name").asString)
+        assertEquals("junit5", calculator.get("framework").asString)
+        val members = calculator.getAsJsonArray("children").map { it.asJsonObject.get("name").asString }
+        assertEquals(listOf("adds", "Division"), members)
+        val division = calculator.getAsJsonArray("children")[1].asJsonObject
+        assertEquals("byZero", division.getAsJsonArray("children")[0].asJsonObject.get("name").asString)
+
+        val spec = bridge.discoverTests(kotestUri).getAsJsonArray("tests")[0].asJsonObject
+        assertEquals("kotest", spec.get("framework").asString)
+        val push = spec.getAsJsonArray("children")[0].asJsonObject
+        assertEquals("push", push.get("name").asString)
+        assertEquals("container", push.get("kind").asString)
+        assertEquals("grows the stack", push.getAsJsonArray("children")[0].asJsonObject.get("name").asString)
+
+        val plainUri = "file://$testSourceDir/NoTests.kt"
+        bridge.updateFile(plainUri, "class Plain { fun run() {} }")
+        assertEquals(0, bridge.discoverTests(plainUri).getAsJsonArray("tests").size())
+    }
+
     private fun JsonArray.titles(): List<String> =
         (0 until size()).map { get(it).asJsonObject.get("title")?.asString ?: "" }
 
@@ -40,6 +40,32 @@ class CompilerBridgeTest {
         assertNull(CompilerBridge.mapCompilerFlag("-Xnonexistent-flag-12345"))
     }
 
+    @Test
+    fun `wrapGradleScript keeps imports in place and maps body lines back`() {
+        var dir = java.io.File(System.getProperty("user.dir"))
+        while (!java.io.File(dir, "tests/fixtures/kotlin-scripts").exists()) dir = dir.parentFile
+        val script = java.io.File(dir, "tests/fixtures/kotlin-scripts/gradle/build.gradle.kts").readText()
+
+        val wrapper = CompilerBridge.wrapGradleScript(script, "build.gradle.kts")
+        val lines = wrapper.text.lines()
+        assertEquals(3, wrapper.headerLines)
+        assertEquals("import org.gradle.api.tasks.testing.logging.TestExceptionFormat", lines[1])
+        assertTrue(lines.contains("import org.gradle.kotlin.dsl.*"))
+        assertEquals("fun org.gradle.kotlin.dsl.KotlinBuildScript.__gradleScriptBody() {", lines[wrapper.headerLines + wrapper.insertedLines - 1])
+        assertEquals("}", lines.last())
+
+        // "plugins {" is line 4 of the script.
+        val pluginsLine = lines.indexOf("plugins {") + 1
+        assertEquals(4, wrapper.scriptLine(pluginsLine))
+        assertEquals(2, wrapper.scriptLine(2))
+        assertNull(wrapper.scriptLine(wrapper.headerLines + 1))
+        assertNull(wrapper.scriptLine(lines.size))
+
+        val settings = CompilerBridge.wrapGradleScript("rootProject.name = \"app\"\n", "settings.gradle.kts")
+        assertEquals(0, settings.headerLines)
+        assertTrue(settings.text.contains("fun org.gradle.kotlin.dsl.KotlinSettingsScript.__gradleScriptBody() {"))
+    }
+
     @Test
     fun `findStdlibJarsInRepository prefers bundled Kotlin version`() {
         val repositoryRoot = Files.createTempDirectory("stdlib-repo")
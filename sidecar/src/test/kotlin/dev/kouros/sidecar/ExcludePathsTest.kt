@@ -0,0 +1,81 @@
+package dev.kouros.sidecar
+
+import org.junit.jupiter.api.AfterEach
+import org.junit.jupiter.api.Test
+import java.nio.file.Files
+import java.nio.file.Path
+import kotlin.test.assertFalse
+import kotlin.test.assertTrue
+
+/**
+ * Files matching excludePaths must not show up in project-wide diagnostics
+ * or workspace symbol search, while the rest of the project still does.
+ */
+class ExcludePathsTest {
+    private lateinit var bridge: CompilerBridge
+
+    @AfterEach
+    fun tearDown() {
+        bridge.shutdown()
+    }
+
+    @Test
+    fun `excluded file produces no diagnostics and no symbol hits`() {
+        val workspace = Files.createTempDirectory("exclude-paths")
+        val sourceRoot = workspace.resolve("src/main/kotlin")
+        write(
+            sourceRoot.resolve("app/Kept.kt"),
+            """
+            package app
+
+            class KeptFixture {
+                val broken: Int = "not an int"
+            }
+            """.trimIndent(),
+        )
+        write(
+            sourceRoot.resolve("vendor/Vendored.kt"),
+            """
+            package vendor
+
+            class VendoredFixture {
+                val broken: Int = "not an int"
+            }
+            """.trimIndent(),
+        )
+
+        bridge = CompilerBridge()
+        bridge.initialize(
+            projectRoot = workspace.toString(),
+            classpath = emptyList(),
+            compilerFlags = emptyList(),
+            jdkHome = "",
+            sourceRoots = listOf(sourceRoot.toString()),
+            excludePaths = listOf("**/vendor/**"),
+        )
+
+        val analyzedUris = bridge.analyzeAll().getAsJsonArray("files")
+            .map { it.asJsonObject.get("uri").asString }
+        assertTrue(analyzedUris.any { it.endsWith("app/Kept.kt") }, "Kept.kt should be analyzed: $analyzedUris")
+        assertFalse(analyzedUris.any { it.endsWith("vendor/Vendored.kt") }, "Vendored.kt is excluded: $analyzedUris")
+
+        val symbolNames = bridge.workspaceSymbols("Fixture").getAsJsonArray("symbols")
+            .map { it.asJsonObject.get("name").asString }
+        assertTrue("KeptFixture" in symbolNames, "KeptFixture should be indexed: $symbolNames")
+        assertFalse("VendoredFixture" in symbolNames, "VendoredFixture is excluded: $symbolNames")
+    }
+
+    @Test
+    fun `isAbsoluteGlob - decides from the prefix without parsing the glob`() {
+        assertTrue(CompilerBridge.isAbsoluteGlob("/opt/vendor/**"))
+        assertTrue(CompilerBridge.isAbsoluteGlob("C:\\work\\vendor\\**"))
+        assertTrue(CompilerBridge.isAbsoluteGlob("c:/work/*/generated/**"))
+        assertFalse(CompilerBridge.isAbsoluteGlob("**/vendor/**"))
+        assertFalse(CompilerBridge.isAbsoluteGlob("vendor/*.kt"))
+    }
+
+    private fun write(path: Path, content: String) {
+        Files.createDirectories(path.parent)
+        Files.writeString(path, content)
+    }
+}
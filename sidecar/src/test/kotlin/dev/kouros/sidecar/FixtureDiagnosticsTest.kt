@@ -256,15 +256,19 @@ class FixtureDiagnosticsTest {
     }
 
     @Test
-    fun `warnings - UnusedImport does not crash analyzer`() {
-        // Note: UNUSED_IMPORT is an IDE-level inspection, not a compiler diagnostic.
-        // The standalone Analysis API does not produce this warning.
-        // This test verifies the file can be analyzed without errors.
+    fun `warnings - UnusedImport produces unused import warning`() {
+        // UNUSED_IMPORT is an IDE-level inspection, not a compiler diagnostic;
+        // the analyzer reports it from its own import usage check.
         val uri = "file://${fixtureDir()}/warnings/UnusedImport.kt"
         val result = bridge.analyze(uri)
         val errors = extractErrors(result)
+        val warnings = extractWarnings(result)
 
         assertEquals(0, errors.size, "UnusedImport.kt should produce no errors, got: ${errors.map { "${it.code}: ${it.message}" }}")
+        assertTrue(
+            warnings.any { it.code == "UNUSED_IMPORT" && it.line == 6 },
+            "should contain UNUSED_IMPORT on the sqrt import, got: ${warnings.map { "${it.code}@${it.line}: ${it.message}" }}"
+        )
     }
 
     @Test
@@ -0,0 +1,129 @@
+package dev.kouros.sidecar
+
+import org.junit.jupiter.api.AfterEach
+import org.junit.jupiter.api.Test
+import java.nio.file.Files
+import java.nio.file.Path
+import kotlin.test.assertNotNull
+import kotlin.test.assertTrue
+
+/**
+ * Regression test: symbols produced by KSP/KAPT live in generated source roots
+ * that are sent separately from the declared source roots, and go-to-definition
+ * must be able to navigate into them.
+ */
+class GeneratedSourceRootsTest {
+    private lateinit var bridge: CompilerBridge
+
+    @AfterEach
+    fun tearDown() {
+        bridge.shutdown()
+    }
+
+    @Test
+    fun `definition - resolves symbol declared in generated source root`() {
+        val workspace = Files.createTempDirectory("generated-source-roots")
+        val sourceRoot = workspace.resolve("src/main/kotlin")
+        val generatedRoot = workspace.resolve("build/generated/ksp/main/kotlin")
+        write(
+            generatedRoot.resolve("app/UserDao_Impl.kt"),
+            """
+            package app
+
+            class UserDao_Impl {
+                fun findAll(): List<String> = emptyList()
+            }
+            """.trimIndent(),
+        )
+        write(
+            sourceRoot.resolve("app/Repository.kt"),
+            """
+            package app
+
+            fun load(): List<String> = UserDao_Impl().findAll()
+            """.trimIndent(),
+        )
+
+        bridge = CompilerBridge()
+        bridge.initialize(
+            projectRoot = workspace.toString(),
+            classpath = emptyList(),
+            compilerFlags = emptyList(),
+            jdkHome = "",
+            sourceRoots = listOf(sourceRoot.toString()),
+            generatedSourceRoots = listOf(generatedRoot.toString()),
+        )
+
+        // Repository.kt line 3: "fun load(): List<String> = UserDao_Impl().findAll()"
+        //                                                     ^27 (0-based) = "UserDao_Impl"
+        val uri = "file://${sourceRoot.resolve("app/Repository.kt")}"
+        val result = bridge.definition(uri, line = 3, character = 27)
+
+        val locations = result.getAsJsonArray("locations")
+        assertNotNull(locations, "locations array should be present")
+        assertTrue(locations.size() > 0, "should resolve UserDao_Impl, got: $result")
+        val targetUri = locations[0].asJsonObject.get("uri")?.asString ?: ""
+        assertTrue(
+            targetUri.contains("build/generated/ksp/main/kotlin"),
+            "definition should point into the generated root, got: $targetUri",
+        )
+    }
+
+    @Test
+    fun `revealGenerated - finds the implementation generated for a DAO`() {
+        val workspace = Files.createTempDirectory("reveal-generated")
+        val sourceRoot = workspace.resolve("src/main/kotlin")
+        val generatedRoot = workspace.resolve("build/generated/ksp/main/kotlin")
+        write(
+            sourceRoot.resolve("app/UserDao.kt"),
+            """
+            package app
+
+            interface UserDao {
+                fun findAll(): List<String>
+            }
+
+            class User(val name: String)
+            """.trimIndent(),
+        )
+        write(
+            generatedRoot.resolve("app/UserDao_Impl.kt"),
+            """
+            package app
+
+            class UserDao_Impl : UserDao {
+                override fun findAll(): List<String> = emptyList()
+            }
+            """.trimIndent(),
+        )
+
+        bridge = CompilerBridge()
+        bridge.initialize(
+            projectRoot = workspace.toString(),
+            classpath = emptyList(),
+            compilerFlags = emptyList(),
+            jdkHome = "",
+            sourceRoots = listOf(sourceRoot.toString()),
+            generatedSourceRoots = listOf(generatedRoot.toString()),
+        )
+
+        // UserDao.kt line 3: "interface UserDao {"
+        val uri = "file://${sourceRoot.resolve("app/UserDao.kt")}"
+        val result = bridge.revealGenerated(uri, line = 3, character = 12)
+        val locations = result.getAsJsonArray("locations")
+        assertTrue(locations.size() == 1, "should find UserDao_Impl, got: $result")
+        val location = locations[0].asJsonObject
+        assertTrue(location.get("uri").asString.endsWith("build/generated/ksp/main/kotlin/app/UserDao_Impl.kt"))
+        assertTrue(location.get("line").asInt == 3 && location.get("column").asInt == 6, "got: $location")
+
+        // UserDao.kt line 7: "class User(val name: String)"
+        val none = bridge.revealGenerated(uri, line = 7, character = 7)
+        assertTrue(none.getAsJsonArray("locations").isEmpty, "User has no generated code, got: $none")
+        assertNotNull(none.get("reason"), "an empty result should say why")
+    }
+
+    private fun write(path: Path, content: String) {
+        Files.createDirectories(path.parent)
+        Files.writeString(path, content)
+    }
+}
@@ -0,0 +1,104 @@
+package dev.kouros.sidecar
+
+import org.junit.jupiter.api.AfterEach
+import org.junit.jupiter.api.Test
+import java.nio.file.Files
+import java.nio.file.Path
+import kotlin.test.assertEquals
+import kotlin.test.assertFalse
+import kotlin.test.assertTrue
+
+/**
+ * The `scope` of a references request drops matches in test source sets,
+ * or keeps only those in the module the search started from.
+ */
+class ReferencesScopeTest {
+    private lateinit var bridge: CompilerBridge
+
+    @AfterEach
+    fun tearDown() {
+        bridge.shutdown()
+    }
+
+    @Test
+    fun `references - scope filters test sources and other modules`() {
+        val workspace = Files.createTempDirectory("references-scope")
+        val appMain = workspace.resolve("app/src/main/kotlin")
+        val appTest = workspace.resolve("app/src/test/kotlin")
+        val webMain = workspace.resolve("web/src/main/kotlin")
+        write(
+            appMain.resolve("shop/Cart.kt"),
+            """
+            package shop
+
+            class Cart
+            """.trimIndent(),
+        )
+        write(
+            appMain.resolve("shop/Checkout.kt"),
+            """
+            package shop
+
+            fun checkout(cart: Cart) = cart
+            """.trimIndent(),
+        )
+        write(
+            appTest.resolve("shop/CartTest.kt"),
+            """
+            package shop
+
+            fun cartFixture(): Cart = Cart()
+            """.trimIndent(),
+        )
+        write(
+            webMain.resolve("web/Page.kt"),
+            """
+            package web
+
+            fun render(cart: shop.Cart) = cart
+            """.trimIndent(),
+        )
+
+        bridge = CompilerBridge()
+        bridge.initialize(
+            projectRoot = workspace.toString(),
+            classpath = emptyList(),
+            compilerFlags = emptyList(),
+            jdkHome = "",
+            sourceRoots = listOf(appMain, appTest, webMain).map { it.toString() },
+        )
+
+        // Cart.kt line 3: "class Cart"
+        //                        ^6
+        val uri = "file://${appMain.resolve("shop/Cart.kt")}"
+        fun files(scope: String) = bridge.references(uri, line = 3, character = 6, scope = scope)
+            .getAsJsonArray("locations")
+            .map { it.asJsonObject.get("uri").asString.substringAfterLast('/') }
+            .toSet()
+
+        val all = files(CompilerBridge.REFERENCES_SCOPE_ALL)
+        assertEquals(setOf("Cart.kt", "Checkout.kt", "CartTest.kt", "Page.kt"), all)
+
+        val withoutTests = files(CompilerBridge.REFERENCES_SCOPE_EXCLUDE_TESTS)
+        assertFalse("CartTest.kt" in withoutTests, "test sources are excluded: $withoutTests")
+        assertTrue("Checkout.kt" in withoutTests && "Page.kt" in withoutTests, "main sources stay: $withoutTests")
+
+        val module = files(CompilerBridge.REFERENCES_SCOPE_CURRENT_MODULE)
+        assertEquals(setOf("Cart.kt", "Checkout.kt", "CartTest.kt"), module)
+    }
+
+    @Test
+    fun `isTestSourcePath - recognizes test source sets`() {
+        assertTrue(CompilerBridge.isTestSourcePath("/app/src/test/kotlin/A.kt"))
+        assertTrue(CompilerBridge.isTestSourcePath("/app/src/androidTest/kotlin/A.kt"))
+        assertTrue(CompilerBridge.isTestSourcePath("/lib/src/jvmTest/kotlin/A.kt"))
+        assertTrue(CompilerBridge.isTestSourcePath("/lib/src/testFixtures/kotlin/A.kt"))
+        assertFalse(CompilerBridge.isTestSourcePath("/app/src/main/kotlin/test/A.kt"))
+        assertFalse(CompilerBridge.isTestSourcePath("/app/src/commonMain/kotlin/A.kt"))
+    }
+
+    private fun write(path: Path, content: String) {
+        Files.createDirectories(path.parent)
+        Files.writeString(path, content)
+    }
+}
@@ -0,0 +1,132 @@
+package dev.kouros.sidecar
+
+import org.junit.jupiter.api.AfterEach
+import org.junit.jupiter.api.Test
+import java.nio.file.Files
+import java.nio.file.Path
+import java.util.zip.ZipEntry
+import java.util.zip.ZipOutputStream
+import javax.tools.ToolProvider
+import kotlin.test.assertEquals
+import kotlin.test.assertNull
+import kotlin.test.assertTrue
+
+/**
+ * Hover over a compiled Java library symbol reads its Javadoc from the
+ * `-sources` jar sent as `classpathSources`.
+ */
+class SourcesJarDocsTest {
+    private var bridge: CompilerBridge? = null
+
+    @AfterEach
+    fun tearDown() {
+        bridge?.shutdown()
+    }
+
+    private val greeterSource = """
+        package lib;
+
+        /**
+         * Greets people by name.
+         * <p>Use {@link #greet(String)} for a single person.
+         */
+        @Deprecated
+        public class Greeter {
+            /** Greets both people. */
+            public String greetBoth(String first, String second) {
+                return greet(first) + " " + greet(second);
+            }
+
+            /** Returns a greeting for {@code name}. */
+            public String greet(String name) {
+                return "Hello, " + name;
+            }
+        }
+    """.trimIndent()
+
+    @Test
+    fun `documentation - reads class and member javadoc from a sources jar`() {
+        val workspace = Files.createTempDirectory("sources-jar-docs")
+        val sourcesJar = jar(workspace.resolve("lib-sources.jar"), mapOf("lib/Greeter.java" to greeterSource))
+        val docs = SourcesJarDocs(listOf(sourcesJar.toString()))
+
+        assertEquals(
+            "Greets people by name.\n\nUse `greet` for a single person.",
+            docs.documentation("lib.Greeter"),
+        )
+        assertEquals("Returns a greeting for `name`.", docs.documentation("lib.Greeter", "greet"))
+        assertNull(docs.documentation("lib.Missing"))
+        assertNull(SourcesJarDocs(emptyList()).documentation("lib.Greeter"))
+    }
+
+    @Test
+    fun `memberDeclaration - matches declarations but not calls`() {
+        val greet = SourcesJarDocs.memberDeclaration("greet")
+        assertTrue(greet.containsMatchIn("    public String greet(String name) {"))
+        assertTrue(greet.containsMatchIn("    static final Map<String, List<String>> greet = new HashMap<>();"))
+        assertTrue(greet.containsMatchIn("    String[] greet;"))
+        assertTrue(greet.containsMatchIn("    @Override public String greet(String name) {"))
+        assertNull(greet.find("        return greet(first);"))
+        assertNull(greet.find("        String line = greet(name);"))
+        assertNull(greet.find("        other.greet(name);"))
+    }
+
+    @Test
+    fun `hover - shows javadoc of a classpath java symbol with sources`() {
+        val workspace = Files.createTempDirectory("sources-jar-hover")
+        val javaSource = workspace.resolve("java/lib/Greeter.java")
+        Files.createDirectories(javaSource.parent)
+        Files.writeString(javaSource, greeterSource)
+        val classes = workspace.resolve("classes")
+        Files.createDirectories(classes)
+        val compiled = ToolProvider.getSystemJavaCompiler()
+            .run(null, null, null, "-d", classes.toString(), javaSource.toString())
+        assertEquals(0, compiled, "javac should compile the library")
+        val classesJar = jar(
+            workspace.resolve("lib.jar"),
+            mapOf("lib/Greeter.class" to null),
+            classes,
+        )
+        val sourcesJar = jar(workspace.resolve("lib-sources.jar"), mapOf("lib/Greeter.java" to greeterSource))
+
+        val sourceRoot = workspace.resolve("src/main/kotlin")
+        val app = sourceRoot.resolve("app/App.kt")
+        Files.createDirectories(app.parent)
+        Files.writeString(
+            app,
+            """
+            package app
+
+            fun hello() = lib.Greeter().greet("Ada")
+            """.trimIndent(),
+        )
+
+        val bridge = CompilerBridge().also { bridge = it }
+        bridge.initialize(
+            projectRoot = workspace.toString(),
+            classpath = listOf(classesJar.toString()),
+            compilerFlags = emptyList(),
+            jdkHome = "",
+            sourceRoots = listOf(sourceRoot.toString()),
+            classpathSources = listOf(sourcesJar.toString()),
+        )
+
+        // App.kt line 3: "fun hello() = lib.Greeter().greet("Ada")"
+        //                                ^28 = "greet"
+        val result = bridge.hover("file://$app", line = 3, character = 28)
+        val contents = result.get("contents")?.asString ?: ""
+        assertTrue(contents.contains("Returns a greeting for"), "hover should show the Javadoc, got: $contents")
+    }
+
+    /** Writes a jar of [entries]: given text, or the file of that name under [classes]. */
+    private fun jar(path: Path, entries: Map<String, String?>, classes: Path? = null): Path {
+        ZipOutputStream(Files.newOutputStream(path)).use { zip ->
+            for ((name, text) in entries) {
+                zip.putNextEntry(ZipEntry(name))
+                zip.write(text?.toByteArray() ?: Files.readAllBytes(classes!!.resolve(name)))
+                zip.closeEntry()
+            }
+        }
+        return path
+    }
+}
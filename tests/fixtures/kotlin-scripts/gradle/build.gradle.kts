@@ -0,0 +1,14 @@
+// Build script analyzed against the Gradle API script classpath.
+import org.gradle.api.tasks.testing.logging.TestExceptionFormat
+
+plugins {
+    kotlin("jvm") version "2.1.20"
+}
+
+repositories {
+    mavenCentral()
+}
+
+tasks.withType<Test>().configureEach {
+    testLogging.exceptionFormat = TestExceptionFormat.FULL
+}
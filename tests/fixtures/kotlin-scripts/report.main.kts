@@ -0,0 +1,8 @@
+#!/usr/bin/env kotlin
+
+@file:DependsOn("org.example:greeter:1.0")
+@file:DependsOn("libs/local.jar")
+@file:DependsOn("org.example:missing:2.0")
+@file:Import("shared.main.kts")
+
+println(greeting("report"))
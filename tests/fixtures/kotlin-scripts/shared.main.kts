@@ -0,0 +1 @@
+fun greeting(name: String): String = "Hello, $name"